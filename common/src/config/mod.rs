@@ -1,24 +1,62 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{RwLock, RwLockReadGuard};
+
+const MODEL_TO_ID_FILE: &str = "model_to_id.json";
+const ID_TO_TFLOPS_FILE: &str = "id_to_tflops.json";
+
+const EMBEDDED_MODEL_TO_ID: &str = include_str!("model_to_id.json");
+const EMBEDDED_ID_TO_TFLOPS: &str = include_str!("id_to_tflops.json");
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GpuModelConfig {
     pub model_to_id: HashMap<String, u16>,
     pub id_to_tflops: HashMap<u16, f32>,
+    // Reverse of `model_to_id`, built on load
+    #[serde(skip)]
+    id_to_model: HashMap<u16, String>,
 }
 
 impl GpuModelConfig {
     pub fn load() -> Result<Self> {
-        let model_to_id = include_str!("model_to_id.json");
-        let id_to_tflops = include_str!("id_to_tflops.json");
+        Self::parse(EMBEDDED_MODEL_TO_ID, EMBEDDED_ID_TO_TFLOPS)
+    }
 
+    /// Reads `model_to_id.json` and `id_to_tflops.json` from `dir`, so new GPU
+    /// models can be added without rebuilding. A missing file falls back to
+    /// the table built into the binary; a malformed one is an error.
+    pub fn load_from_path(dir: &Path) -> Result<Self> {
+        let model_to_id = read_or_embedded(&dir.join(MODEL_TO_ID_FILE), EMBEDDED_MODEL_TO_ID)?;
+        let id_to_tflops = read_or_embedded(&dir.join(ID_TO_TFLOPS_FILE), EMBEDDED_ID_TO_TFLOPS)?;
+        Self::parse(&model_to_id, &id_to_tflops)
+            .with_context(|| format!("Invalid GPU model config in {}", dir.display()))
+    }
+
+    fn parse(model_to_id: &str, id_to_tflops: &str) -> Result<Self> {
         let model_to_id: HashMap<String, u16> = serde_json::from_str(model_to_id)?;
         let id_to_tflops: HashMap<u16, f32> = serde_json::from_str(id_to_tflops)?;
 
+        // Should two names share an id, the first in sort order wins so the
+        // lookup doesn't depend on map order
+        let mut id_to_model: HashMap<u16, String> = HashMap::with_capacity(model_to_id.len());
+        for (model, &id) in &model_to_id {
+            id_to_model
+                .entry(id)
+                .and_modify(|name| {
+                    if model < name {
+                        *name = model.clone();
+                    }
+                })
+                .or_insert_with(|| model.clone());
+        }
+
         Ok(Self {
             model_to_id,
             id_to_tflops,
+            id_to_model,
         })
     }
 
@@ -29,4 +67,58 @@ impl GpuModelConfig {
     pub fn get_tflops(&self, id: u16) -> Option<f32> {
         self.id_to_tflops.get(&id).copied()
     }
+
+    /// GPU model name for `id`, the reverse of `get_id`.
+    pub fn get_model(&self, id: u16) -> Option<&str> {
+        self.id_to_model.get(&id).map(String::as_str)
+    }
+}
+
+fn read_or_embedded(path: &Path, embedded: &'static str) -> Result<Cow<'static, str>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Cow::Owned(contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Cow::Borrowed(embedded)),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// A `GpuModelConfig` shared by the whole process that can be swapped at
+/// runtime with `reload`.
+#[derive(Debug)]
+pub struct SharedGpuModelConfig {
+    inner: RwLock<GpuModelConfig>,
+}
+
+impl SharedGpuModelConfig {
+    pub fn new(config: GpuModelConfig) -> Self {
+        Self {
+            inner: RwLock::new(config),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, GpuModelConfig> {
+        self.inner
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Replaces the config with the files in `dir`, see
+    /// `GpuModelConfig::load_from_path`. The current config stays in place if
+    /// they cannot be loaded.
+    pub fn reload(&self, dir: &Path) -> Result<()> {
+        let config = GpuModelConfig::load_from_path(dir)?;
+        *self
+            .inner
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = config;
+        Ok(())
+    }
+
+    pub fn get_id(&self, model: &str) -> Option<u16> {
+        self.read().get_id(model)
+    }
+
+    pub fn get_tflops(&self, id: u16) -> Option<f32> {
+        self.read().get_tflops(id)
+    }
 }
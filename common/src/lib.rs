@@ -2,10 +2,11 @@ use anyhow::{anyhow, Result};
 use bincode::{self as bincode, config as bincode_config, Decode, Encode};
 use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Encoder};
 use tracing::warn;
 pub mod config;
-use bytes::BytesMut;
-use config::GpuModelConfig;
+use bytes::{Buf, BufMut, BytesMut};
+use config::{GpuModelConfig, SharedGpuModelConfig};
 use std::fmt;
 use zeroize::Zeroize;
 
@@ -17,8 +18,113 @@ pub struct Model {
     pub owned_by: String,
 }
 
+/// GGUF weight quantization of a model file.
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QuantType {
+    F32,
+    F16,
+    BF16,
+    Q8_0,
+    Q6K,
+    Q5KM,
+    Q5KS,
+    Q5_1,
+    Q5_0,
+    Q4KM,
+    Q4KS,
+    Q4_1,
+    Q4_0,
+    Q3KL,
+    Q3KM,
+    Q3KS,
+    Q2K,
+    IQ4NL,
+    IQ4XS,
+    IQ3XXS,
+    IQ2XXS,
+}
+
+impl QuantType {
+    pub const ALL: &'static [QuantType] = &[
+        QuantType::F32,
+        QuantType::F16,
+        QuantType::BF16,
+        QuantType::Q8_0,
+        QuantType::Q6K,
+        QuantType::Q5KM,
+        QuantType::Q5KS,
+        QuantType::Q5_1,
+        QuantType::Q5_0,
+        QuantType::Q4KM,
+        QuantType::Q4KS,
+        QuantType::Q4_1,
+        QuantType::Q4_0,
+        QuantType::Q3KL,
+        QuantType::Q3KM,
+        QuantType::Q3KS,
+        QuantType::Q2K,
+        QuantType::IQ4NL,
+        QuantType::IQ4XS,
+        QuantType::IQ3XXS,
+        QuantType::IQ2XXS,
+    ];
+
+    /// Name as it appears in GGUF file names, e.g. "Q4_K_M".
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QuantType::F32 => "F32",
+            QuantType::F16 => "F16",
+            QuantType::BF16 => "BF16",
+            QuantType::Q8_0 => "Q8_0",
+            QuantType::Q6K => "Q6_K",
+            QuantType::Q5KM => "Q5_K_M",
+            QuantType::Q5KS => "Q5_K_S",
+            QuantType::Q5_1 => "Q5_1",
+            QuantType::Q5_0 => "Q5_0",
+            QuantType::Q4KM => "Q4_K_M",
+            QuantType::Q4KS => "Q4_K_S",
+            QuantType::Q4_1 => "Q4_1",
+            QuantType::Q4_0 => "Q4_0",
+            QuantType::Q3KL => "Q3_K_L",
+            QuantType::Q3KM => "Q3_K_M",
+            QuantType::Q3KS => "Q3_K_S",
+            QuantType::Q2K => "Q2_K",
+            QuantType::IQ4NL => "IQ4_NL",
+            QuantType::IQ4XS => "IQ4_XS",
+            QuantType::IQ3XXS => "IQ3_XXS",
+            QuantType::IQ2XXS => "IQ2_XXS",
+        }
+    }
+
+    /// Quantization named in a model id or file name such as
+    /// "qwen2.5-0.5b-instruct-q4_k_m.gguf". The last match wins.
+    pub fn detect(name: &str) -> Option<QuantType> {
+        name.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .rev()
+            .find_map(|part| part.parse().ok())
+    }
+}
+
+impl std::str::FromStr for QuantType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        QuantType::ALL
+            .iter()
+            .copied()
+            .find(|q| q.as_str().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| anyhow!("Unknown quantization type: {}", s))
+    }
+}
+
+impl fmt::Display for QuantType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 // Device information from client to server
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, Default, PartialEq, Eq)]
 pub struct DeviceInfo {
     pub index: u8,
     pub usage: u8,
@@ -27,6 +133,8 @@ pub struct DeviceInfo {
     pub vendor_id: u16,
     pub device_id: u16,
     pub temp: u32,
+    pub memsize_gb: u16,
+    pub powerlimit_w: u16,
 }
 
 // Device information from client to server (max num: 8)
@@ -142,6 +250,133 @@ pub fn set_u8_to_u64(value: &mut u64, index: usize, val: u8) {
     *value |= (val as u64) << shift;
 }
 
+/// Usage readings of one `DevicesInfo` entry, sent by `CommandV1::HeartbeatLite`.
+/// `index` points into the `devices_info` of the last full heartbeat.
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceUsage {
+    pub index: u16,
+    pub usage: u64,
+    pub mem_usage: u64,
+    pub power_usage: u64,
+    pub temp: u64,
+}
+
+impl DevicesInfo {
+    pub fn usage_at(&self, index: u16) -> DeviceUsage {
+        DeviceUsage {
+            index,
+            usage: self.usage,
+            mem_usage: self.mem_usage,
+            power_usage: self.power_usage,
+            temp: self.temp,
+        }
+    }
+
+    pub fn apply_usage(&mut self, usage: &DeviceUsage) {
+        self.usage = usage.usage;
+        self.mem_usage = usage.mem_usage;
+        self.power_usage = usage.power_usage;
+        self.temp = usage.temp;
+    }
+
+    /// Number of device slots packed into one `DevicesInfo`.
+    pub const MAX_DEVICES: usize = 8;
+
+    /// Packs `dev` into `slot` of every per-device field and grows `num` to
+    /// cover it. `temp` is stored as a byte, so readings above 255 saturate.
+    pub fn set_device(&mut self, slot: usize, dev: &DeviceInfo) -> Result<()> {
+        if slot >= Self::MAX_DEVICES {
+            return Err(anyhow!(
+                "Device slot {} out of range (max {})",
+                slot,
+                Self::MAX_DEVICES
+            ));
+        }
+        set_u8_to_u64(&mut self.usage, slot, dev.usage);
+        set_u8_to_u64(&mut self.mem_usage, slot, dev.mem_usage);
+        set_u8_to_u64(&mut self.power_usage, slot, dev.power_usage);
+        set_u8_to_u64(&mut self.temp, slot, dev.temp.min(u8::MAX as u32) as u8);
+        set_u16_to_u128(&mut self.vendor_id, slot, dev.vendor_id);
+        set_u16_to_u128(&mut self.device_id, slot, dev.device_id);
+        set_u16_to_u128(&mut self.memsize_gb, slot, dev.memsize_gb);
+        set_u16_to_u128(&mut self.powerlimit_w, slot, dev.powerlimit_w);
+        self.num = self.num.max(slot as u16 + 1);
+        Ok(())
+    }
+
+    /// Unpacks the device in `slot`, or `None` if the slot is past `num`.
+    pub fn get_device(&self, slot: usize) -> Option<DeviceInfo> {
+        if slot >= Self::MAX_DEVICES || slot >= self.num as usize {
+            return None;
+        }
+        Some(DeviceInfo {
+            index: slot as u8,
+            usage: get_u8_from_u64(self.usage, slot),
+            mem_usage: get_u8_from_u64(self.mem_usage, slot),
+            power_usage: get_u8_from_u64(self.power_usage, slot),
+            vendor_id: get_u16_from_u128(self.vendor_id, slot),
+            device_id: get_u16_from_u128(self.device_id, slot),
+            temp: get_u8_from_u64(self.temp, slot) as u32,
+            memsize_gb: get_u16_from_u128(self.memsize_gb, slot),
+            powerlimit_w: get_u16_from_u128(self.powerlimit_w, slot),
+        })
+    }
+
+    /// Whether both entries describe the same pod and hardware, ignoring usage readings.
+    pub fn same_static_fields(&self, other: &DevicesInfo) -> bool {
+        self.num == other.num
+            && self.pod_id == other.pod_id
+            && self.total_tflops == other.total_tflops
+            && self.memtotal_gb == other.memtotal_gb
+            && self.port == other.port
+            && self.ip == other.ip
+            && self.os_type == other.os_type
+            && self.engine_type == other.engine_type
+            && self.vendor_id == other.vendor_id
+            && self.device_id == other.device_id
+            && self.memsize_gb == other.memsize_gb
+            && self.powerlimit_w == other.powerlimit_w
+    }
+}
+
+/// Usage entries for the devices whose readings changed since `previous`.
+/// Returns `None` when the device list or any static field changed, in which
+/// case a full heartbeat has to be sent.
+pub fn device_usage_delta(
+    previous: &[DevicesInfo],
+    current: &[DevicesInfo],
+) -> Option<Vec<DeviceUsage>> {
+    if previous.len() != current.len()
+        || previous
+            .iter()
+            .zip(current)
+            .any(|(prev, cur)| !prev.same_static_fields(cur))
+    {
+        return None;
+    }
+
+    Some(
+        current
+            .iter()
+            .zip(previous)
+            .enumerate()
+            .map(|(i, (cur, prev))| (cur.usage_at(i as u16), prev.usage_at(i as u16)))
+            .filter(|(cur, prev)| cur != prev)
+            .map(|(cur, _)| cur)
+            .collect(),
+    )
+}
+
+/// Applies the entries of a lite heartbeat to the devices of the last full one.
+/// Entries pointing past the known devices are ignored.
+pub fn apply_device_usage(devices: &mut [DevicesInfo], usage: &[DeviceUsage]) {
+    for entry in usage {
+        if let Some(device) = devices.get_mut(entry.index as usize) {
+            device.apply_usage(entry);
+        }
+    }
+}
+
 /// System information from client to server
 #[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Default)]
 pub struct SystemInfo {
@@ -152,6 +387,26 @@ pub struct SystemInfo {
     pub network_tx: u64,
 }
 
+/// A worker's own account of what it runs, sent in reply to
+/// `CommandV1::DescribeWorker`.
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Default, PartialEq)]
+pub struct WorkerDescription {
+    /// Version of the worker build.
+    pub version: String,
+    /// `PROTOCOL_REVISION` of the worker build.
+    pub protocol_revision: u32,
+    pub os: String,
+    pub engine: String,
+    /// Model currently loaded or served, if any.
+    pub model: Option<String>,
+    pub n_ctx: u32,
+    pub n_batch: u32,
+    /// `None` where the worker cannot tell, e.g. layers chosen by the backend.
+    pub n_gpu_layers: Option<u32>,
+    /// Short hardware summary, e.g. "linux x86_64, 16 CPUs, 2 GPUs, 48 GB".
+    pub device_summary: String,
+}
+
 /// Commands exchanged between client and server.
 #[derive(Encode, Decode, Debug, Clone)]
 pub enum Command {
@@ -276,6 +531,61 @@ pub enum CommandV1 {
         status: DownloadStatus,
         error: Option<String>,
     },
+
+    // Trimmed heartbeat for bandwidth-sensitive clients: static device fields are
+    // taken from the last full `Heartbeat`, only changed usage readings are sent.
+    HeartbeatLite {
+        client_id: [u8; 16],
+        system_info: SystemInfo,
+        devices_usage: Vec<DeviceUsage>,
+    },
+
+    // Quantizations the worker's backend runs well, sent after login. Workers
+    // that never send it are treated as handling every quantization.
+    Capabilities {
+        client_id: [u8; 16],
+        quant_types: Vec<QuantType>,
+    },
+
+    // Server asks a worker to describe itself; answered with `WorkerDescription`
+    DescribeWorker {
+        request_id: String,
+    },
+
+    // Worker's reply to `DescribeWorker` with the same request id
+    WorkerDescription {
+        request_id: String,
+        description: WorkerDescription,
+    },
+
+    // Context window of the worker's loaded model in tokens, sent once known.
+    // The server keeps prompts plus generation that would not fit away from it.
+    ContextWindow {
+        client_id: [u8; 16],
+        n_ctx: u32,
+    },
+
+    // InferenceTask for a named model, so the worker can load it on demand.
+    // Workers before revision 5 get InferenceTask instead.
+    ModelInferenceTask {
+        task_id: String,
+        model: String,
+        prompt: String,
+        max_tokens: u32,
+        temperature: f32,
+        top_k: u32,
+        top_p: f32,
+        repeat_penalty: f32,
+        repeat_last_n: i32,
+        min_keep: u32,
+    },
+
+    // Server's PROTOCOL_REVISION, sent right after a successful LoginResult to
+    // workers that know it. Workers hold back commands newer than the server
+    // advertised and assume revision 1 until this arrives.
+    ServerRevision {
+        protocol_revision: u32,
+    },
 }
 
 #[derive(Encode, Decode, Debug, Clone)]
@@ -378,6 +688,58 @@ pub enum CommandV2 {
     },
 }
 
+// Whether logs may contain full prompt text; off unless an operator opts in
+static LOG_PROMPTS: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Allows `prompt_log_label` to log full prompt text (`--log-prompts`).
+pub fn set_log_prompts(enabled: bool) {
+    LOG_PROMPTS.store(enabled, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Stable 64-bit FNV-1a hash, used to correlate redacted values across logs.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Client id for logs: the first 6 and last 4 hex digits.
+pub fn client_id_log_label(client_id: &[u8]) -> String {
+    let encoded = hex::encode(client_id);
+    if encoded.len() <= 10 {
+        return encoded;
+    }
+    format!("{}...{}", &encoded[..6], &encoded[encoded.len() - 4..])
+}
+
+/// Network address for logs with the host masked: IPv4 keeps the first three
+/// octets, IPv6 the first three groups.
+pub fn addr_log_label(addr: &std::net::SocketAddr) -> String {
+    match addr.ip() {
+        std::net::IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.x:{}", a, b, c, addr.port())
+        }
+        std::net::IpAddr::V6(ip) => {
+            let s = ip.segments();
+            format!("[{:x}:{:x}:{:x}::x]:{}", s[0], s[1], s[2], addr.port())
+        }
+    }
+}
+
+/// Prompt for logs: its length and hash, or the full text once
+/// `set_log_prompts(true)` has been called.
+pub fn prompt_log_label(prompt: &str) -> String {
+    if LOG_PROMPTS.load(std::sync::atomic::Ordering::Relaxed) {
+        return format!("{:?}", prompt);
+    }
+    format!(
+        "<prompt {} bytes, fnv {:08x}>",
+        prompt.len(),
+        fnv1a(prompt.as_bytes()) as u32
+    )
+}
+
 #[derive(Encode, Decode, Clone, PartialEq, Eq)]
 pub struct RedactedString(pub String);
 
@@ -516,109 +878,476 @@ pub fn process_id(id: &[u8; 32]) -> &str {
     std::str::from_utf8(&id[..len]).unwrap_or_default()
 }
 
-// Max message size 10MB
-pub const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+/// Maximum number of devices a client may report. `DevicesInfo` packs per-device
+/// fields into u64/u128 lanes, so anything past eight cannot be represented.
+pub const MAX_DEVICES_PER_CLIENT: usize = 8;
 
-/// Reads a command from an async reader.
-/// The format is a 4-byte length prefix (u32) followed by the bin-encoded command.
-pub async fn read_command<R: AsyncRead + Unpin>(
-    reader: &mut R,
-    buf: &mut BytesMut,
-) -> Result<Command> {
-    let mut len_buf = [0u8; 4];
-    reader.read_exact(&mut len_buf).await?;
-    let len = u32::from_be_bytes(len_buf) as usize;
-    if len > MAX_MESSAGE_SIZE {
+impl Command {
+    fn devices_info(&self) -> Option<&Vec<DevicesInfo>> {
+        match self {
+            Command::V1(CommandV1::Login { devices_info, .. })
+            | Command::V1(CommandV1::Heartbeat { devices_info, .. })
+            | Command::V1(CommandV1::ModelStatus {
+                auto_models_device: devices_info,
+                ..
+            }) => Some(devices_info),
+            _ => None,
+        }
+    }
+
+    fn devices_info_mut(&mut self) -> Option<&mut Vec<DevicesInfo>> {
+        match self {
+            Command::V1(CommandV1::Login { devices_info, .. })
+            | Command::V1(CommandV1::Heartbeat { devices_info, .. })
+            | Command::V1(CommandV1::ModelStatus {
+                auto_models_device: devices_info,
+                ..
+            }) => Some(devices_info),
+            _ => None,
+        }
+    }
+}
+
+impl Command {
+    /// Oldest `PROTOCOL_REVISION` that knows this command. Variants appended
+    /// from now on return the revision that added them.
+    pub fn min_revision(&self) -> u32 {
+        match self {
+            Command::V1(CommandV1::DescribeWorker { .. })
+            | Command::V1(CommandV1::WorkerDescription { .. }) => 3,
+            Command::V1(CommandV1::ContextWindow { .. }) => 4,
+            Command::V1(CommandV1::ModelInferenceTask { .. }) => 5,
+            Command::V1(CommandV1::ServerRevision { .. }) => 6,
+            // Everything else predates revision tracking
+            _ => 1,
+        }
+    }
+
+    /// Whether a peer at `peer_revision` can decode this command.
+    pub fn supported_by(&self, peer_revision: u32) -> bool {
+        self.min_revision() <= peer_revision
+    }
+
+    /// Variant name for logs and errors, e.g. "V1::Login".
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Command::V1(cmd) => match cmd {
+                CommandV1::RequestNewProxyConn { .. } => "V1::RequestNewProxyConn",
+                CommandV1::NewProxyConn { .. } => "V1::NewProxyConn",
+                CommandV1::Login { .. } => "V1::Login",
+                CommandV1::LoginResult { .. } => "V1::LoginResult",
+                CommandV1::Heartbeat { .. } => "V1::Heartbeat",
+                CommandV1::PullModelResult { .. } => "V1::PullModelResult",
+                CommandV1::ModelStatus { .. } => "V1::ModelStatus",
+                CommandV1::InferenceTask { .. } => "V1::InferenceTask",
+                CommandV1::ChatInferenceTask { .. } => "V1::ChatInferenceTask",
+                CommandV1::CancelInference { .. } => "V1::CancelInference",
+                CommandV1::InferenceResult { .. } => "V1::InferenceResult",
+                CommandV1::InferenceResultChunk { .. } => "V1::InferenceResultChunk",
+                CommandV1::ModelDownloadProgress { .. } => "V1::ModelDownloadProgress",
+                CommandV1::HeartbeatLite { .. } => "V1::HeartbeatLite",
+                CommandV1::Capabilities { .. } => "V1::Capabilities",
+                CommandV1::DescribeWorker { .. } => "V1::DescribeWorker",
+                CommandV1::WorkerDescription { .. } => "V1::WorkerDescription",
+                CommandV1::ContextWindow { .. } => "V1::ContextWindow",
+                CommandV1::ModelInferenceTask { .. } => "V1::ModelInferenceTask",
+                CommandV1::ServerRevision { .. } => "V1::ServerRevision",
+            },
+            Command::V2(cmd) => match cmd {
+                CommandV2::P2PConnectionRequest { .. } => "V2::P2PConnectionRequest",
+                CommandV2::P2PConnectionConfig { .. } => "V2::P2PConnectionConfig",
+                CommandV2::P2PCandidates { .. } => "V2::P2PCandidates",
+                CommandV2::P2PDataPlaneEnvelope { .. } => "V2::P2PDataPlaneEnvelope",
+                CommandV2::P2PInferenceRequest { .. } => "V2::P2PInferenceRequest",
+                CommandV2::P2PInferenceChunk { .. } => "V2::P2PInferenceChunk",
+                CommandV2::P2PInferenceDone { .. } => "V2::P2PInferenceDone",
+                CommandV2::P2PCancelInference { .. } => "V2::P2PCancelInference",
+                CommandV2::P2PConnectionInfo { .. } => "V2::P2PConnectionInfo",
+                CommandV2::P2PConnectionEstablished { .. } => "V2::P2PConnectionEstablished",
+                CommandV2::P2PConnectionFailed { .. } => "V2::P2PConnectionFailed",
+            },
+        }
+    }
+}
+
+fn devices_info_oversized(devices: &[DevicesInfo]) -> bool {
+    devices.len() > MAX_DEVICES_PER_CLIENT
+        || devices
+            .iter()
+            .any(|d| d.num as usize > MAX_DEVICES_PER_CLIENT)
+}
+
+/// Truncates device reports to `MAX_DEVICES_PER_CLIENT` before sending, and
+/// the device counts they carry to the entries kept. Returns the command
+/// unchanged (borrowed) when it is already within bounds.
+fn cap_devices_info(command: &Command) -> std::borrow::Cow<'_, Command> {
+    match command.devices_info() {
+        Some(devices) if devices_info_oversized(devices) => {}
+        _ => return std::borrow::Cow::Borrowed(command),
+    }
+
+    let mut capped = command.clone();
+    if let Some(devices) = capped.devices_info_mut() {
         warn!(
-            "read_command: Message too large: {} bytes (max: {} bytes)",
-            len, MAX_MESSAGE_SIZE
+            "Device report exceeds {} devices (entries: {}), truncating",
+            MAX_DEVICES_PER_CLIENT,
+            devices.len()
         );
-        return Err(anyhow!("Message too large"));
+        devices.truncate(MAX_DEVICES_PER_CLIENT);
+        let kept = devices.len() as u16;
+        for device in devices.iter_mut() {
+            device.num = device.num.min(kept);
+        }
+    }
+    if let Command::V1(CommandV1::Heartbeat {
+        device_count,
+        devices_info,
+        ..
+    }) = &mut capped
+    {
+        *device_count = (*device_count).min(devices_info.len() as u16);
+    }
+    std::borrow::Cow::Owned(capped)
+}
+
+/// Rejects decoded commands whose device report exceeds `MAX_DEVICES_PER_CLIENT`.
+fn validate_devices_info(command: &Command) -> Result<()> {
+    match command.devices_info() {
+        Some(devices) if devices_info_oversized(devices) => {
+            warn!(
+                "Rejecting device report with {} entries (max: {})",
+                devices.len(),
+                MAX_DEVICES_PER_CLIENT
+            );
+            Err(anyhow!("Too many devices in report"))
+        }
+        _ => Ok(()),
     }
+}
 
-    let config = bincode_config::standard()
+/// Default maximum frame size (10MB).
+pub const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+
+/// First byte of every frame payload; lets a reader tell our frames apart from
+/// builds that predate the format tag.
+pub const FRAME_MAGIC: u8 = 0xF7;
+/// Version of the bincode `Command` layout. Bump it whenever fields change or
+/// variants are removed or reordered so mismatched builds fail loudly instead of
+/// mis-decoding. Appending a variant does not need a bump; see
+/// `PROTOCOL_REVISION`.
+pub const WIRE_FORMAT_VERSION: u8 = 1;
+
+/// Revision of the command set, sent by workers as `Login.version`.
+///
+/// Compatibility guarantees within one `WIRE_FORMAT_VERSION`:
+/// - New commands are only ever appended to `CommandV1`/`CommandV2`, and each
+///   append bumps this revision; `Command::min_revision` records which one.
+/// - Readers from revision 2 on skip, with a warning, any frame holding a
+///   variant they don't know instead of dropping the connection.
+/// - Senders hold back commands whose `min_revision` is above the peer's
+///   revision, so revision 1 peers, which can't skip, never receive them.
+pub const PROTOCOL_REVISION: u32 = 6;
+
+// Enums whose tag picks the command; new variants are only appended to these
+const COMMAND_ENUMS: &[&str] = &["Command", "CommandV1", "CommandV2"];
+
+/// Bincode configuration for `Command` payloads on every transport. TCP frames
+/// and UDP datagrams must both use it, or a command encoded on one path no
+/// longer decodes on the other.
+pub fn command_bincode_config() -> impl bincode_config::Config {
+    bincode_config::standard()
         .with_fixed_int_encoding()
-        .with_little_endian();
+        .with_little_endian()
+}
 
-    buf.clear();
-    buf.resize(len, 0);
-    reader.read_exact(buf).await?;
+/// Frame-level errors from `read_command` and friends, reachable through
+/// `anyhow::Error::downcast_ref`. The offending frame has already been consumed
+/// when they are returned, so a reader may skip it and keep reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError {
+    /// The peer tagged the frame with a wire format version this build can't decode.
+    UnsupportedVersion(u8),
+    /// The frame has no `FRAME_MAGIC` header, i.e. it comes from a build that
+    /// predates the header. Accepted as version 1 with the `legacy-frames` feature.
+    MissingHeader,
+}
 
-    let (command, _) = bincode::decode_from_slice(buf.as_ref(), config)
-        .map_err(|e| anyhow!("Failed to deserialize command: {}", e))?;
-    Ok(command)
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::UnsupportedVersion(version) => write!(
+                f,
+                "Incompatible protocol: peer wire format version {} (expected {}); \
+                 upgrade both ends to the same release",
+                version, WIRE_FORMAT_VERSION
+            ),
+            CommandError::MissingHeader => write!(
+                f,
+                "Incompatible protocol: frame has no 0x{:02x} header; \
+                 the peer is likely running an older build",
+                FRAME_MAGIC
+            ),
+        }
+    }
 }
 
-/// Writes a command to an async writer.
-/// The format is a 4-byte length prefix (u32) followed by the JSON-encoded command.
-pub async fn write_command<W: AsyncWrite + Unpin>(writer: &mut W, command: &Command) -> Result<()> {
-    let config = bincode_config::standard()
-        .with_fixed_int_encoding()
-        .with_little_endian();
-    let buf = bincode::encode_to_vec(command, config)?;
-    let len = buf.len() as u32;
-    if len as usize > MAX_MESSAGE_SIZE {
-        warn!(
-            "write_command: Message too large: {} bytes (max: {} bytes)",
-            len, MAX_MESSAGE_SIZE
-        );
-        return Err(anyhow!("Message too large"));
+impl std::error::Error for CommandError {}
+
+/// Framing parameters for the length-prefixed command protocol.
+///
+/// Each frame is a 4-byte big-endian length followed by `FRAME_MAGIC`,
+/// `WIRE_FORMAT_VERSION` and the bincode-encoded command. Frames larger than
+/// `max_message_size` are rejected on both ends, so peers that agree on a
+/// different limit can use their own `Framing`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Framing {
+    pub max_message_size: usize,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self {
+            max_message_size: MAX_MESSAGE_SIZE,
+        }
     }
+}
 
-    writer.write_all(&len.to_be_bytes()).await?;
-    writer.write_all(&buf).await?;
-    writer.flush().await?;
-    Ok(())
+impl Framing {
+    pub const fn new(max_message_size: usize) -> Self {
+        Self { max_message_size }
+    }
+
+    fn check_incoming_len(&self, len: usize) -> Result<()> {
+        if len > self.max_message_size {
+            warn!(
+                "read_command: Message too large: {} bytes (max: {} bytes)",
+                len, self.max_message_size
+            );
+            return Err(anyhow!(
+                "Incoming message too large: {} bytes (max: {} bytes)",
+                len,
+                self.max_message_size
+            ));
+        }
+        Ok(())
+    }
+
+    fn encode_frame(&self, command: &Command) -> Result<Vec<u8>> {
+        let command = cap_devices_info(command);
+        let mut buf = vec![FRAME_MAGIC, WIRE_FORMAT_VERSION];
+        bincode::encode_into_std_write(command.as_ref(), &mut buf, command_bincode_config())?;
+        if buf.len() > self.max_message_size || buf.len() > u32::MAX as usize {
+            warn!(
+                "write_command: {} message too large: {} bytes (max: {} bytes)",
+                command.variant_name(),
+                buf.len(),
+                self.max_message_size
+            );
+            return Err(anyhow!(
+                "{} message too large: {} bytes (max: {} bytes)",
+                command.variant_name(),
+                buf.len(),
+                self.max_message_size
+            ));
+        }
+        Ok(buf)
+    }
+
+    /// Splits a frame into its wire format version and bincode payload.
+    fn frame_payload(buf: &[u8]) -> Result<(u8, &[u8]), CommandError> {
+        match buf {
+            [FRAME_MAGIC, version, payload @ ..] => Ok((*version, payload)),
+            // Untagged frames start with the little-endian `Command` discriminant
+            // (0 or 1), so they can't be mistaken for `FRAME_MAGIC`.
+            _ if cfg!(feature = "legacy-frames") => Ok((1, buf)),
+            _ => Err(CommandError::MissingHeader),
+        }
+    }
+
+    /// Decodes one frame. Returns `None` for a command variant this build
+    /// doesn't know, i.e. one appended by a newer peer; readers skip those
+    /// frames and carry on with the next one.
+    fn decode_frame(buf: &[u8]) -> Result<Option<Command>> {
+        let (version, payload) = Self::frame_payload(buf)?;
+        if version != WIRE_FORMAT_VERSION {
+            return Err(CommandError::UnsupportedVersion(version).into());
+        }
+
+        let command = match bincode::decode_from_slice(payload, command_bincode_config()) {
+            Ok((command, _)) => command,
+            // Only a command tag this build doesn't know means a newer peer; an
+            // unknown variant of a nested enum is a corrupt frame
+            Err(bincode::error::DecodeError::UnexpectedVariant {
+                type_name, found, ..
+            }) if COMMAND_ENUMS.contains(&type_name) => {
+                warn!(
+                    "read_command: skipping frame with unknown {} variant {} (newer peer?)",
+                    type_name, found
+                );
+                return Ok(None);
+            }
+            Err(e) => return Err(anyhow!("Failed to deserialize command: {}", e)),
+        };
+        validate_devices_info(&command)?;
+        Ok(Some(command))
+    }
+
+    /// Reads a command from an async reader.
+    pub async fn read_command<R: AsyncRead + Unpin>(
+        &self,
+        reader: &mut R,
+        buf: &mut BytesMut,
+    ) -> Result<Command> {
+        loop {
+            let mut len_buf = [0u8; 4];
+            reader.read_exact(&mut len_buf).await?;
+            let len = u32::from_be_bytes(len_buf) as usize;
+            self.check_incoming_len(len)?;
+
+            buf.clear();
+            buf.resize(len, 0);
+            reader.read_exact(buf).await?;
+
+            if let Some(command) = Self::decode_frame(buf.as_ref())? {
+                return Ok(command);
+            }
+        }
+    }
+
+    /// Writes a command to an async writer.
+    pub async fn write_command<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+        command: &Command,
+    ) -> Result<()> {
+        let buf = self.encode_frame(command)?;
+        writer.write_all(&(buf.len() as u32).to_be_bytes()).await?;
+        writer.write_all(&buf).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Synchronous version of `read_command` for blocking readers.
+    pub fn read_command_sync<R: std::io::Read>(&self, reader: &mut R) -> Result<Command> {
+        loop {
+            let mut len_buf = [0u8; 4];
+            reader.read_exact(&mut len_buf)?;
+            let len = u32::from_be_bytes(len_buf) as usize;
+            self.check_incoming_len(len)?;
+
+            let mut buf = vec![0u8; len];
+            reader.read_exact(&mut buf)?;
+
+            if let Some(command) = Self::decode_frame(&buf)? {
+                return Ok(command);
+            }
+        }
+    }
+
+    /// Synchronous version of `write_command` for blocking writers.
+    pub fn write_command_sync<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+        command: &Command,
+    ) -> Result<()> {
+        let buf = self.encode_frame(command)?;
+        writer.write_all(&(buf.len() as u32).to_be_bytes())?;
+        writer.write_all(&buf)?;
+        writer.flush()?;
+        Ok(())
+    }
 }
 
-/// Synchronous version: Reads a command from a blocking reader.
-/// The format is a 4-byte length prefix (u32) followed by the bincode-encoded command.
-pub fn read_command_sync<R: std::io::Read>(reader: &mut R) -> Result<Command> {
-    let mut len_buf = [0u8; 4];
-    reader.read_exact(&mut len_buf)?;
-    let len = u32::from_be_bytes(len_buf) as usize;
+/// `tokio_util` codec for the length-prefixed command protocol.
+///
+/// Partial frames stay buffered until the rest arrives, so wrapping a stream in
+/// `Framed::new(stream, CommandCodec::default())` yields whole `Command`s only.
+/// The wire format is the same as `read_command`/`write_command`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommandCodec {
+    framing: Framing,
+}
 
-    if len > MAX_MESSAGE_SIZE {
-        warn!(
-            "read_command_sync: Message too large: {} bytes (max: {} bytes)",
-            len, MAX_MESSAGE_SIZE
-        );
-        return Err(anyhow!("Message too large"));
+impl CommandCodec {
+    pub const fn new(framing: Framing) -> Self {
+        Self { framing }
     }
+}
 
-    let config = bincode_config::standard()
-        .with_fixed_int_encoding()
-        .with_little_endian();
+impl Decoder for CommandCodec {
+    type Item = Command;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Command>> {
+        loop {
+            if src.len() < 4 {
+                src.reserve(4 - src.len());
+                return Ok(None);
+            }
+            let mut len_buf = [0u8; 4];
+            len_buf.copy_from_slice(&src[..4]);
+            let len = u32::from_be_bytes(len_buf) as usize;
+            self.framing.check_incoming_len(len)?;
+
+            if src.len() < 4 + len {
+                src.reserve(4 + len - src.len());
+                return Ok(None);
+            }
+            src.advance(4);
+            let frame = src.split_to(len);
+            if let Some(command) = Framing::decode_frame(&frame)? {
+                return Ok(Some(command));
+            }
+        }
+    }
+}
 
-    let mut buf = vec![0u8; len];
-    reader.read_exact(&mut buf)?;
+impl Encoder<&Command> for CommandCodec {
+    type Error = anyhow::Error;
 
-    let (command, _) = bincode::decode_from_slice(&buf, config)
-        .map_err(|e| anyhow!("Failed to deserialize command: {}", e))?;
-    Ok(command)
+    fn encode(&mut self, command: &Command, dst: &mut BytesMut) -> Result<()> {
+        let buf = self.framing.encode_frame(command)?;
+        dst.reserve(4 + buf.len());
+        dst.put_u32(buf.len() as u32);
+        dst.extend_from_slice(&buf);
+        Ok(())
+    }
 }
 
-/// Synchronous version: Writes a command to a blocking writer.
-/// The format is a 4-byte length prefix (u32) followed by the bincode-encoded command.
-pub fn write_command_sync<W: std::io::Write>(writer: &mut W, command: &Command) -> Result<()> {
-    let config = bincode_config::standard()
-        .with_fixed_int_encoding()
-        .with_little_endian();
-    let buf = bincode::encode_to_vec(command, config)?;
-    let len = buf.len() as u32;
+impl Encoder<Command> for CommandCodec {
+    type Error = anyhow::Error;
 
-    if len as usize > MAX_MESSAGE_SIZE {
-        warn!(
-            "write_command_sync: Message too large: {} bytes (max: {} bytes)",
-            len, MAX_MESSAGE_SIZE
-        );
-        return Err(anyhow!("Message too large"));
+    fn encode(&mut self, command: Command, dst: &mut BytesMut) -> Result<()> {
+        self.encode(&command, dst)
     }
+}
 
-    writer.write_all(&len.to_be_bytes())?;
-    writer.write_all(&buf)?;
-    writer.flush()?;
-    Ok(())
+/// Reads a command from an async reader using the default `Framing`: a 4-byte
+/// big-endian length, then `FRAME_MAGIC`, `WIRE_FORMAT_VERSION` and the
+/// bincode-encoded command.
+pub async fn read_command<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buf: &mut BytesMut,
+) -> Result<Command> {
+    Framing::default().read_command(reader, buf).await
+}
+
+/// Writes a command to an async writer using the default `Framing`; see
+/// `read_command` for the frame layout.
+pub async fn write_command<W: AsyncWrite + Unpin>(writer: &mut W, command: &Command) -> Result<()> {
+    Framing::default().write_command(writer, command).await
+}
+
+/// Synchronous version of `read_command` for blocking readers, using the
+/// same `Framing` frame layout.
+pub fn read_command_sync<R: std::io::Read>(reader: &mut R) -> Result<Command> {
+    Framing::default().read_command_sync(reader)
+}
+
+/// Synchronous version of `write_command` for blocking writers, using the
+/// same `Framing` frame layout.
+pub fn write_command_sync<W: std::io::Write>(writer: &mut W, command: &Command) -> Result<()> {
+    Framing::default().write_command_sync(writer, command)
 }
 
 /// Joins two streams, copying data in both directions.
@@ -647,7 +1376,7 @@ where
     Ok(())
 }
 
-//TODO: vendor to id apple and apple
+// PCI vendor IDs; vendors with several IDs list their primary one first
 const VENDOR_TO_ID: &[(&str, u16)] = &[
     ("Apple", 0x106b),
     ("Apple", 0x6810),
@@ -657,12 +1386,22 @@ const VENDOR_TO_ID: &[(&str, u16)] = &[
     ("NVIDIA", 0x10de),
 ];
 
+/// Primary vendor ID for `vendor`. Use `vendor_to_ids` to match devices
+/// reporting any of the vendor's IDs.
 pub fn vendor_to_id(vendor: &str) -> Option<u16> {
+    vendor_to_ids(vendor).first().copied()
+}
+
+/// Every vendor ID registered for `vendor`, primary first; empty for an
+/// unknown vendor.
+pub fn vendor_to_ids(vendor: &str) -> Vec<u16> {
     VENDOR_TO_ID
         .iter()
-        .find(|(s, _)| *s == vendor)
+        .filter(|(s, _)| *s == vendor)
         .map(|(_, id)| *id)
+        .collect()
 }
+
 pub fn id_to_vendor(id: u16) -> Option<&'static str> {
     VENDOR_TO_ID.iter().find(|(_, i)| *i == id).map(|(s, _)| *s)
 }
@@ -685,8 +1424,11 @@ pub fn os_type_str(os_type_src: &OsType) -> Option<&'static str> {
 
 use lazy_static::lazy_static;
 lazy_static! {
-    pub static ref GPU_CONFIG: GpuModelConfig =
-        GpuModelConfig::load().expect("Failed to load GPU config");
+    /// GPU model table used by `model_to_id` and friends; `GPU_CONFIG.reload`
+    /// swaps in files from a deployment's config directory.
+    pub static ref GPU_CONFIG: SharedGpuModelConfig = SharedGpuModelConfig::new(
+        GpuModelConfig::load().expect("Failed to load GPU config")
+    );
 }
 
 pub fn model_to_id(model: &str) -> Option<u16> {
@@ -694,10 +1436,7 @@ pub fn model_to_id(model: &str) -> Option<u16> {
 }
 
 pub fn id_to_model(id: u16) -> Option<String> {
-    GPU_CONFIG
-        .model_to_id
-        .iter()
-        .find_map(|(k, &v)| if v == id { Some(k.clone()) } else { None })
+    GPU_CONFIG.read().get_model(id).map(str::to_string)
 }
 
 pub fn to_tflops(id: u16) -> Option<f32> {
@@ -775,6 +1514,69 @@ fn test_id_to_model() {
     assert_eq!(vendor_to_id("NVIDIA"), Some(0x10de));
 }
 
+#[test]
+fn test_id_to_model_matches_a_scan_of_the_whole_table() {
+    let config = GpuModelConfig::load().unwrap();
+    assert!(!config.model_to_id.is_empty());
+    let scan = |id: u16| {
+        config
+            .model_to_id
+            .iter()
+            .find_map(|(k, &v)| if v == id { Some(k.clone()) } else { None })
+    };
+    for (model, &id) in &config.model_to_id {
+        assert_eq!(id_to_model(id).as_deref(), Some(model.as_str()));
+        assert_eq!(id_to_model(id), scan(id));
+    }
+    for id in [0, 0xfffe, u16::MAX] {
+        assert_eq!(id_to_model(id), scan(id));
+    }
+}
+
+#[test]
+fn test_gpu_config_reload_picks_up_new_models() {
+    let dir = std::env::temp_dir().join(format!("gpuf-gpu-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = SharedGpuModelConfig::new(GpuModelConfig::load().unwrap());
+    assert_eq!(config.get_id("GeForce RTX 6090"), None);
+
+    // Only the model table is overridden; TFLOPS come from the built-in table
+    std::fs::write(
+        dir.join("model_to_id.json"),
+        r#"{"Apple M1": 1, "GeForce RTX 6090": 40000}"#,
+    )
+    .unwrap();
+    config.reload(&dir).unwrap();
+    assert_eq!(config.get_id("GeForce RTX 6090"), Some(40000));
+    assert_eq!(config.read().get_model(40000), Some("GeForce RTX 6090"));
+    assert_eq!(config.get_id("Apple M1 Pro"), None);
+    assert_eq!(config.get_tflops(1), Some(2.6));
+
+    // A broken file keeps the previous config
+    std::fs::write(dir.join("id_to_tflops.json"), "{ not json").unwrap();
+    assert!(config.reload(&dir).is_err());
+    assert_eq!(config.get_id("GeForce RTX 6090"), Some(40000));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_vendor_ids_cover_every_id_of_a_vendor() {
+    assert_eq!(vendor_to_ids("Apple"), vec![0x106b, 0x6810]);
+    assert_eq!(vendor_to_ids("AMD"), vec![0x1022, 0x1002]);
+    assert_eq!(vendor_to_ids("NVIDIA"), vec![0x10de]);
+    assert!(vendor_to_ids("Qualcomm").is_empty());
+    assert_eq!(vendor_to_id("Qualcomm"), None);
+
+    for vendor in ["Apple", "Intel", "AMD", "NVIDIA"] {
+        for id in vendor_to_ids(vendor) {
+            assert_eq!(id_to_vendor(id), Some(vendor), "{:#06x}", id);
+        }
+    }
+    assert_eq!(id_to_vendor(0x6810), Some("Apple"));
+    assert_eq!(id_to_vendor(0xffff), None);
+}
+
 #[test]
 fn test_format_bytes() {
     let mut value = 0;
@@ -785,6 +1587,64 @@ fn test_format_bytes() {
     assert_eq!(value, value2);
 }
 
+#[test]
+fn test_log_labels_redact_ids_addresses_and_prompts() {
+    assert_eq!(client_id_log_label(&[0xab; 16]), "ababab...abab");
+    assert_eq!(
+        addr_log_label(&"203.0.113.42:17000".parse().unwrap()),
+        "203.0.113.x:17000"
+    );
+    assert_eq!(
+        addr_log_label(&"[2001:db8:85a3::8a2e:370:7334]:443".parse().unwrap()),
+        "[2001:db8:85a3::x]:443"
+    );
+
+    let label = prompt_log_label("my secret prompt");
+    assert!(label.starts_with("<prompt 16 bytes, fnv "), "{}", label);
+    assert!(!label.contains("secret"));
+    assert_eq!(label, prompt_log_label("my secret prompt"));
+    assert_ne!(label, prompt_log_label("my other prompt!"));
+}
+
+#[test]
+fn test_devices_info_device_slots_round_trip() {
+    let devices: Vec<DeviceInfo> = (0..DevicesInfo::MAX_DEVICES)
+        .map(|slot| DeviceInfo {
+            index: slot as u8,
+            usage: 10 + slot as u8,
+            mem_usage: 20 + slot as u8,
+            power_usage: 30 + slot as u8,
+            vendor_id: 0x10de,
+            device_id: 0x2684 + slot as u16,
+            temp: 40 + slot as u32,
+            memsize_gb: 24 + slot as u16,
+            powerlimit_w: 450 - slot as u16,
+        })
+        .collect();
+
+    let mut info = DevicesInfo::default();
+    for (slot, dev) in devices.iter().enumerate() {
+        info.set_device(slot, dev).unwrap();
+        assert_eq!(info.num as usize, slot + 1);
+    }
+    for (slot, dev) in devices.iter().enumerate() {
+        assert_eq!(info.get_device(slot).as_ref(), Some(dev));
+    }
+
+    // Rewriting a slot leaves its neighbours and `num` alone.
+    let mut hot = devices[3].clone();
+    hot.temp = 300;
+    info.set_device(3, &hot).unwrap();
+    assert_eq!(info.num as usize, DevicesInfo::MAX_DEVICES);
+    assert_eq!(info.get_device(3).unwrap().temp, 255);
+    assert_eq!(info.get_device(2).as_ref(), Some(&devices[2]));
+    assert_eq!(info.get_device(4).as_ref(), Some(&devices[4]));
+
+    assert!(info.set_device(DevicesInfo::MAX_DEVICES, &hot).is_err());
+    assert!(info.get_device(DevicesInfo::MAX_DEVICES).is_none());
+    assert!(DevicesInfo::default().get_device(0).is_none());
+}
+
 #[tokio::test]
 async fn test_command_serialization_roundtrip() {
     // Create a Vec<u8> buffer for writing
@@ -906,3 +1766,800 @@ async fn test_command_serialization_roundtrip() {
         _ => panic!("Command version mismatch"),
     }
 }
+
+#[cfg(test)]
+fn heartbeat_with_devices(entries: usize, num: u16) -> Command {
+    Command::V1(CommandV1::Heartbeat {
+        client_id: [7; 16],
+        system_info: SystemInfo {
+            cpu_usage: 0,
+            memory_usage: 0,
+            disk_usage: 0,
+            network_rx: 0,
+            network_tx: 0,
+        },
+        device_count: num,
+        device_memtotal_gb: 0,
+        device_total_tflops: 0,
+        devices_info: (0..entries)
+            .map(|_| DevicesInfo {
+                num,
+                ..DevicesInfo::default()
+            })
+            .collect(),
+    })
+}
+
+#[tokio::test]
+async fn test_devices_info_cap_boundary() {
+    // Exactly at the cap round-trips untouched.
+    let mut buf = Vec::new();
+    write_command(&mut buf, &heartbeat_with_devices(MAX_DEVICES_PER_CLIENT, 8))
+        .await
+        .unwrap();
+    let mut read_buf = BytesMut::new();
+    let cmd = read_command(&mut std::io::Cursor::new(&buf[..]), &mut read_buf)
+        .await
+        .unwrap();
+    assert_eq!(cmd.devices_info().unwrap().len(), MAX_DEVICES_PER_CLIENT);
+
+    // One over the cap is truncated on write.
+    let mut buf = Vec::new();
+    write_command_sync(
+        &mut buf,
+        &heartbeat_with_devices(MAX_DEVICES_PER_CLIENT + 1, 9),
+    )
+    .unwrap();
+    let cmd = read_command_sync(&mut std::io::Cursor::new(&buf[..])).unwrap();
+    let devices = cmd.devices_info().unwrap();
+    assert_eq!(devices.len(), MAX_DEVICES_PER_CLIENT);
+    assert!(devices
+        .iter()
+        .all(|d| d.num as usize == MAX_DEVICES_PER_CLIENT));
+    match cmd {
+        Command::V1(CommandV1::Heartbeat { device_count, .. }) => {
+            assert_eq!(device_count as usize, MAX_DEVICES_PER_CLIENT)
+        }
+        _ => panic!("Unexpected command variant"),
+    }
+
+    // Few entries claiming too many devices keep only as many as they list
+    let mut buf = Vec::new();
+    write_command_sync(&mut buf, &heartbeat_with_devices(2, 40)).unwrap();
+    let cmd = read_command_sync(&mut std::io::Cursor::new(&buf[..])).unwrap();
+    assert!(cmd.devices_info().unwrap().iter().all(|d| d.num == 2));
+    match cmd {
+        Command::V1(CommandV1::Heartbeat { device_count, .. }) => assert_eq!(device_count, 2),
+        _ => panic!("Unexpected command variant"),
+    }
+
+    // A peer that bypasses the writer cap is rejected on read.
+    let config = bincode_config::standard()
+        .with_fixed_int_encoding()
+        .with_little_endian();
+    let payload = bincode::encode_to_vec(
+        heartbeat_with_devices(MAX_DEVICES_PER_CLIENT + 1, 1),
+        config,
+    )
+    .unwrap();
+    let raw = raw_frame(FRAME_MAGIC, WIRE_FORMAT_VERSION, &payload);
+    let err = read_command_sync(&mut std::io::Cursor::new(&raw[..])).unwrap_err();
+    assert!(
+        !err.to_string().contains("Incompatible protocol"),
+        "{}",
+        err
+    );
+}
+
+#[cfg(test)]
+fn raw_frame(magic: u8, version: u8, payload: &[u8]) -> Vec<u8> {
+    let mut raw = ((payload.len() + 2) as u32).to_be_bytes().to_vec();
+    raw.extend_from_slice(&[magic, version]);
+    raw.extend_from_slice(payload);
+    raw
+}
+
+#[test]
+fn test_frame_header_mismatch_is_reported_as_incompatible_protocol() {
+    let config = bincode_config::standard()
+        .with_fixed_int_encoding()
+        .with_little_endian();
+    let payload = bincode::encode_to_vec(heartbeat_with_devices(1, 1), config).unwrap();
+
+    let ok = raw_frame(FRAME_MAGIC, WIRE_FORMAT_VERSION, &payload);
+    assert!(read_command_sync(&mut std::io::Cursor::new(&ok[..])).is_ok());
+
+    let newer = raw_frame(FRAME_MAGIC, WIRE_FORMAT_VERSION + 1, &payload);
+    let err = read_command_sync(&mut std::io::Cursor::new(&newer[..])).unwrap_err();
+    assert!(err.to_string().contains("Incompatible protocol"), "{}", err);
+    assert!(err.to_string().contains("version"), "{}", err);
+
+    // Frames from a build without the header
+    let mut untagged = (payload.len() as u32).to_be_bytes().to_vec();
+    untagged.extend_from_slice(&payload);
+    let result = read_command_sync(&mut std::io::Cursor::new(&untagged[..]));
+    if cfg!(feature = "legacy-frames") {
+        assert_eq!(result.unwrap().variant_name(), "V1::Heartbeat");
+    } else {
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("Incompatible protocol"), "{}", err);
+        assert_eq!(
+            err.downcast_ref::<CommandError>(),
+            Some(&CommandError::MissingHeader)
+        );
+    }
+}
+
+#[test]
+fn test_unsupported_version_is_typed_and_skippable() {
+    let config = bincode_config::standard()
+        .with_fixed_int_encoding()
+        .with_little_endian();
+    let payload = bincode::encode_to_vec(heartbeat_with_devices(1, 1), config).unwrap();
+
+    // A newer peer's frame followed by one this build understands.
+    let mut stream = raw_frame(FRAME_MAGIC, WIRE_FORMAT_VERSION + 1, b"future layout");
+    write_command_sync(&mut stream, &heartbeat_with_devices(2, 2)).unwrap();
+    stream.extend(raw_frame(FRAME_MAGIC, WIRE_FORMAT_VERSION, &payload));
+
+    let mut reader = std::io::Cursor::new(&stream[..]);
+    let err = read_command_sync(&mut reader).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<CommandError>(),
+        Some(&CommandError::UnsupportedVersion(WIRE_FORMAT_VERSION + 1))
+    );
+
+    // The rejected frame was consumed, so the reader stays aligned.
+    let cmd = read_command_sync(&mut reader).unwrap();
+    assert_eq!(cmd.devices_info().unwrap().len(), 2);
+    let cmd = read_command_sync(&mut reader).unwrap();
+    assert_eq!(cmd.devices_info().unwrap().len(), 1);
+}
+
+#[test]
+fn test_unknown_command_variants_are_skipped() {
+    // Commands appended by a newer release: a V1 variant and a V3 enum
+    let mut future_v1 = 0u32.to_le_bytes().to_vec();
+    future_v1.extend_from_slice(&u32::MAX.to_le_bytes());
+    future_v1.extend_from_slice(b"fields this build can't parse");
+    let future_v3 = 2u32.to_le_bytes();
+
+    let mut stream = raw_frame(FRAME_MAGIC, WIRE_FORMAT_VERSION, &future_v1);
+    stream.extend(raw_frame(FRAME_MAGIC, WIRE_FORMAT_VERSION, &future_v3));
+    write_command_sync(&mut stream, &heartbeat_with_devices(2, 2)).unwrap();
+
+    let cmd = read_command_sync(&mut std::io::Cursor::new(&stream[..])).unwrap();
+    assert_eq!(cmd.variant_name(), "V1::Heartbeat");
+    assert_eq!(cmd.devices_info().unwrap().len(), 2);
+
+    let mut src = BytesMut::from(&stream[..]);
+    let cmd = CommandCodec::default().decode(&mut src).unwrap().unwrap();
+    assert_eq!(cmd.variant_name(), "V1::Heartbeat");
+    assert!(src.is_empty());
+
+    // Corrupt payloads of known variants are still errors
+    let mut truncated = 0u32.to_le_bytes().to_vec();
+    truncated.extend_from_slice(&4u32.to_le_bytes());
+    let raw = raw_frame(FRAME_MAGIC, WIRE_FORMAT_VERSION, &truncated);
+    assert!(read_command_sync(&mut std::io::Cursor::new(&raw[..])).is_err());
+
+    // So is a known command holding an unknown variant of a nested enum: a
+    // Login whose OsType is out of range
+    let mut bad_login = 0u32.to_le_bytes().to_vec();
+    bad_login.extend_from_slice(&2u32.to_le_bytes());
+    bad_login.extend_from_slice(&[7; 16]);
+    bad_login.extend_from_slice(&PROTOCOL_REVISION.to_le_bytes());
+    bad_login.extend_from_slice(&99u32.to_le_bytes());
+    let raw = raw_frame(FRAME_MAGIC, WIRE_FORMAT_VERSION, &bad_login);
+    assert!(read_command_sync(&mut std::io::Cursor::new(&raw[..])).is_err());
+
+    assert!(heartbeat_with_devices(1, 1).min_revision() <= PROTOCOL_REVISION);
+}
+
+#[test]
+fn test_worker_description_round_trips_and_needs_revision_3() {
+    let description = WorkerDescription {
+        version: "0.1.0".to_string(),
+        protocol_revision: PROTOCOL_REVISION,
+        os: "android".to_string(),
+        engine: "LLAMA".to_string(),
+        model: Some("qwen2-0_5b-instruct-q4_0.gguf".to_string()),
+        n_ctx: 4096,
+        n_batch: 512,
+        n_gpu_layers: None,
+        device_summary: "android aarch64, 8 CPUs".to_string(),
+    };
+    let reply = Command::V1(CommandV1::WorkerDescription {
+        request_id: "req-1".to_string(),
+        description: description.clone(),
+    });
+    let mut buf = Vec::new();
+    write_command_sync(&mut buf, &reply).unwrap();
+    match read_command_sync(&mut std::io::Cursor::new(&buf[..])).unwrap() {
+        Command::V1(CommandV1::WorkerDescription {
+            request_id,
+            description: decoded,
+        }) => {
+            assert_eq!(request_id, "req-1");
+            assert_eq!(decoded, description);
+        }
+        other => panic!("unexpected {}", other.variant_name()),
+    }
+
+    let request = Command::V1(CommandV1::DescribeWorker {
+        request_id: "req-1".to_string(),
+    });
+    assert_eq!(request.min_revision(), 3);
+    assert_eq!(reply.min_revision(), 3);
+    assert!(request.min_revision() <= PROTOCOL_REVISION);
+}
+
+#[test]
+fn test_context_window_needs_revision_4() {
+    let command = Command::V1(CommandV1::ContextWindow {
+        client_id: [5; 16],
+        n_ctx: 4096,
+    });
+    assert_eq!(command.min_revision(), 4);
+    assert!(command.min_revision() <= PROTOCOL_REVISION);
+    match read_command_sync(&mut std::io::Cursor::new(&wire_bytes(&command)[..])).unwrap() {
+        Command::V1(CommandV1::ContextWindow { client_id, n_ctx }) => {
+            assert_eq!(client_id, [5; 16]);
+            assert_eq!(n_ctx, 4096);
+        }
+        other => panic!("unexpected {}", other.variant_name()),
+    }
+    // Servers before revision 4 can't be sent it
+    assert!(!command.supported_by(3));
+    assert!(command.supported_by(4));
+}
+
+#[test]
+fn test_server_revision_needs_revision_6() {
+    let command = Command::V1(CommandV1::ServerRevision {
+        protocol_revision: PROTOCOL_REVISION,
+    });
+    assert_eq!(command.min_revision(), 6);
+    assert!(command.supported_by(PROTOCOL_REVISION));
+    match read_command_sync(&mut std::io::Cursor::new(&wire_bytes(&command)[..])).unwrap() {
+        Command::V1(CommandV1::ServerRevision { protocol_revision }) => {
+            assert_eq!(protocol_revision, PROTOCOL_REVISION);
+        }
+        other => panic!("unexpected {}", other.variant_name()),
+    }
+}
+
+#[cfg(test)]
+fn login_with_devices(entries: usize) -> Command {
+    Command::V1(CommandV1::Login {
+        client_id: [9; 16],
+        version: 1,
+        os_type: OsType::LINUX,
+        auto_models: true,
+        system_info: SystemInfo {
+            cpu_usage: 10,
+            memory_usage: 20,
+            disk_usage: 30,
+            network_rx: 1_000,
+            network_tx: 2_000,
+        },
+        device_memtotal_gb: 8 * 80,
+        device_total_tflops: 8 * 312,
+        devices_info: (0..entries)
+            .map(|i| DevicesInfo {
+                num: entries as u16,
+                pod_id: i as u16,
+                vendor_id: 0x10de,
+                device_id: 0x2330,
+                memsize_gb: 80,
+                powerlimit_w: 700,
+                ..DevicesInfo::default()
+            })
+            .collect(),
+    })
+}
+
+#[tokio::test]
+async fn test_login_with_max_devices_round_trips() {
+    let login = login_with_devices(MAX_DEVICES_PER_CLIENT);
+    let mut buf = Vec::new();
+    write_command(&mut buf, &login).await.unwrap();
+
+    let mut read_buf = BytesMut::new();
+    let cmd = read_command(&mut std::io::Cursor::new(&buf[..]), &mut read_buf)
+        .await
+        .unwrap();
+    assert_eq!(cmd.variant_name(), "V1::Login");
+    let devices = cmd.devices_info().unwrap();
+    assert_eq!(devices.len(), MAX_DEVICES_PER_CLIENT);
+    assert_eq!(devices[7].pod_id, 7);
+}
+
+#[test]
+fn test_framing_limit_is_configurable_and_names_variant() {
+    let login = login_with_devices(MAX_DEVICES_PER_CLIENT);
+    let mut buf = Vec::new();
+    Framing::default()
+        .write_command_sync(&mut buf, &login)
+        .unwrap();
+
+    let small = Framing::new(buf.len() / 2);
+    let err = small
+        .write_command_sync(&mut Vec::new(), &login)
+        .unwrap_err();
+    assert!(err.to_string().contains("V1::Login"), "{}", err);
+    assert!(small
+        .read_command_sync(&mut std::io::Cursor::new(&buf[..]))
+        .is_err());
+    assert!(Framing::new(buf.len())
+        .read_command_sync(&mut std::io::Cursor::new(&buf[..]))
+        .is_ok());
+}
+
+#[test]
+fn test_command_codec_reassembles_byte_at_a_time() {
+    let login = login_with_devices(MAX_DEVICES_PER_CLIENT);
+    let heartbeat = heartbeat_with_devices(2, 2);
+    let mut codec = CommandCodec::default();
+    let mut wire = BytesMut::new();
+    codec.encode(&login, &mut wire).unwrap();
+    codec.encode(heartbeat, &mut wire).unwrap();
+
+    // Same bytes as the stream writer
+    let mut expected = Vec::new();
+    write_command_sync(&mut expected, &login).unwrap();
+    assert_eq!(&wire[..expected.len()], &expected[..]);
+
+    let mut src = BytesMut::new();
+    let mut decoded = Vec::new();
+    for byte in wire.iter() {
+        src.put_u8(*byte);
+        if let Some(cmd) = codec.decode(&mut src).unwrap() {
+            decoded.push(cmd);
+        }
+    }
+    assert!(src.is_empty());
+    assert_eq!(decoded.len(), 2);
+    assert_eq!(decoded[0].variant_name(), "V1::Login");
+    assert_eq!(decoded[0].devices_info().unwrap()[7].pod_id, 7);
+    assert_eq!(decoded[1].variant_name(), "V1::Heartbeat");
+}
+
+#[test]
+fn test_command_codec_rejects_oversized_length_prefix() {
+    let mut codec = CommandCodec::new(Framing::new(16));
+    let mut src = BytesMut::from(&17u32.to_be_bytes()[..]);
+    assert!(codec.decode(&mut src).is_err());
+}
+
+#[tokio::test]
+async fn test_command_codec_framed_stream() {
+    use futures_util::StreamExt;
+    use tokio_util::codec::FramedRead;
+
+    // A small duplex buffer forces the frame to arrive in several reads
+    let (mut client, server) = tokio::io::duplex(64);
+    let mut reader = FramedRead::new(server, CommandCodec::default());
+
+    let login = login_with_devices(MAX_DEVICES_PER_CLIENT);
+    let send = tokio::spawn(async move {
+        write_command(&mut client, &login).await.unwrap();
+    });
+    let cmd = reader.next().await.unwrap().unwrap();
+    send.await.unwrap();
+    assert_eq!(cmd.variant_name(), "V1::Login");
+    assert_eq!(cmd.devices_info().unwrap().len(), MAX_DEVICES_PER_CLIENT);
+}
+
+// One of every `CommandV1` variant, inference ones included
+#[cfg(test)]
+fn every_v1_command() -> Vec<Command> {
+    let device = DevicesInfo {
+        num: 1,
+        vendor_id: 0x10de,
+        memsize_gb: 24,
+        usage: 40,
+        ..DevicesInfo::default()
+    };
+    let system_info = SystemInfo {
+        cpu_usage: 12,
+        memory_usage: 34,
+        disk_usage: 56,
+        network_rx: 789,
+        network_tx: 1_011,
+    };
+    let pods_model = vec![PodModel {
+        pod_id: 0,
+        model_name: Some("llama-3.2-1b-q4_k_m".to_string()),
+        download_url: None,
+        checksum: Some("abc123".to_string()),
+        expected_size: Some(770_000_000),
+    }];
+    let commands = vec![
+        CommandV1::RequestNewProxyConn {
+            proxy_conn_id: [1; 16],
+        },
+        CommandV1::NewProxyConn {
+            proxy_conn_id: [2; 16],
+        },
+        CommandV1::Login {
+            client_id: [3; 16],
+            version: 7,
+            os_type: OsType::ANDROID,
+            auto_models: true,
+            system_info: system_info.clone(),
+            device_memtotal_gb: 24,
+            device_total_tflops: 80,
+            devices_info: vec![device.clone()],
+        },
+        CommandV1::LoginResult {
+            success: true,
+            pods_model: pods_model.clone(),
+            error: None,
+        },
+        CommandV1::Heartbeat {
+            client_id: [3; 16],
+            system_info: system_info.clone(),
+            device_count: 1,
+            device_memtotal_gb: 24,
+            device_total_tflops: 80,
+            devices_info: vec![device.clone()],
+        },
+        CommandV1::PullModelResult {
+            pods_model,
+            error: Some("disk full".to_string()),
+        },
+        CommandV1::ModelStatus {
+            client_id: [3; 16],
+            models: vec![Model {
+                id: "llama-3.2-1b".to_string(),
+                object: "model".to_string(),
+                created: 1_700_000_000,
+                owned_by: "gpuf".to_string(),
+            }],
+            auto_models_device: vec![device.clone()],
+        },
+        CommandV1::InferenceTask {
+            task_id: "task-1".to_string(),
+            prompt: "Why is the sky blue? 🌤".to_string(),
+            max_tokens: 128,
+            temperature: 0.7,
+            top_k: 40,
+            top_p: 0.9,
+            repeat_penalty: 1.1,
+            repeat_last_n: -1,
+            min_keep: 1,
+        },
+        CommandV1::ChatInferenceTask {
+            task_id: "task-2".to_string(),
+            model: "llama-3.2-1b".to_string(),
+            messages: vec![
+                ChatMessage {
+                    role: "system".to_string(),
+                    content: "Be brief.".to_string(),
+                },
+                ChatMessage {
+                    role: "user".to_string(),
+                    content: "Hello".to_string(),
+                },
+            ],
+            max_tokens: 64,
+            temperature: 0.2,
+            top_k: 20,
+            top_p: 0.95,
+            repeat_penalty: 1.0,
+            repeat_last_n: 64,
+            min_keep: 0,
+        },
+        CommandV1::CancelInference {
+            task_id: "task-2".to_string(),
+        },
+        CommandV1::InferenceResult {
+            task_id: "task-1".to_string(),
+            success: false,
+            result: None,
+            error: Some("context overflow".to_string()),
+            execution_time_ms: 1_234,
+            prompt_tokens: 9,
+            completion_tokens: 0,
+        },
+        CommandV1::InferenceResultChunk {
+            task_id: "task-2".to_string(),
+            seq: 3,
+            delta: "Hi".to_string(),
+            phase: OutputPhase::Final,
+            done: true,
+            error: None,
+            prompt_tokens: 12,
+            completion_tokens: 4,
+            analysis_tokens: 1,
+            final_tokens: 3,
+        },
+        CommandV1::ModelDownloadProgress {
+            client_id: [3; 16],
+            model_name: "llama-3.2-1b".to_string(),
+            downloaded_bytes: 1 << 20,
+            total_bytes: 1 << 30,
+            percentage: 0.1,
+            speed_bps: 4096,
+            status: DownloadStatus::Downloading,
+            error: None,
+        },
+        CommandV1::HeartbeatLite {
+            client_id: [3; 16],
+            system_info,
+            devices_usage: vec![device.usage_at(0)],
+        },
+        CommandV1::Capabilities {
+            client_id: [3; 16],
+            quant_types: vec![QuantType::Q4KM, QuantType::F16],
+        },
+        CommandV1::DescribeWorker {
+            request_id: "describe-1".to_string(),
+        },
+        CommandV1::WorkerDescription {
+            request_id: "describe-1".to_string(),
+            description: WorkerDescription {
+                version: "1.0.0".to_string(),
+                protocol_revision: PROTOCOL_REVISION,
+                os: "android".to_string(),
+                engine: "Llama".to_string(),
+                model: Some("llama-3.2-1b".to_string()),
+                n_ctx: 4096,
+                n_batch: 512,
+                n_gpu_layers: None,
+                device_summary: "android aarch64, 8 CPUs".to_string(),
+            },
+        },
+        CommandV1::ContextWindow {
+            client_id: [3; 16],
+            n_ctx: 8192,
+        },
+        CommandV1::ModelInferenceTask {
+            task_id: "task-3".to_string(),
+            model: "llama-3.2-1b".to_string(),
+            prompt: "Once upon a time".to_string(),
+            max_tokens: 32,
+            temperature: 1.0,
+            top_k: 40,
+            top_p: 1.0,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+            min_keep: 1,
+        },
+        CommandV1::ServerRevision {
+            protocol_revision: PROTOCOL_REVISION,
+        },
+    ];
+    // Not exhaustive on purpose: a new variant stops this from compiling until
+    // it is added to the list above
+    for command in &commands {
+        match command {
+            CommandV1::RequestNewProxyConn { .. }
+            | CommandV1::NewProxyConn { .. }
+            | CommandV1::Login { .. }
+            | CommandV1::LoginResult { .. }
+            | CommandV1::Heartbeat { .. }
+            | CommandV1::PullModelResult { .. }
+            | CommandV1::ModelStatus { .. }
+            | CommandV1::InferenceTask { .. }
+            | CommandV1::ChatInferenceTask { .. }
+            | CommandV1::CancelInference { .. }
+            | CommandV1::InferenceResult { .. }
+            | CommandV1::InferenceResultChunk { .. }
+            | CommandV1::ModelDownloadProgress { .. }
+            | CommandV1::HeartbeatLite { .. }
+            | CommandV1::Capabilities { .. }
+            | CommandV1::DescribeWorker { .. }
+            | CommandV1::WorkerDescription { .. }
+            | CommandV1::ContextWindow { .. }
+            | CommandV1::ModelInferenceTask { .. }
+            | CommandV1::ServerRevision { .. } => {}
+        }
+    }
+    commands.into_iter().map(Command::V1).collect()
+}
+
+// `Command` has no `PartialEq`; identical frames mean identical commands
+#[cfg(test)]
+fn wire_bytes(command: &Command) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_command_sync(&mut buf, command).unwrap();
+    buf
+}
+
+#[tokio::test]
+async fn test_every_v1_command_crosses_a_duplex_pipe_both_ways() {
+    let commands = every_v1_command();
+    let names: std::collections::HashSet<_> = commands.iter().map(Command::variant_name).collect();
+    assert_eq!(names.len(), commands.len());
+
+    // Buffers smaller than most frames, so both ends block on each other
+    // mid-frame while writing and reading at the same time
+    let (worker, server) = tokio::io::duplex(64);
+    let exchange = |stream: tokio::io::DuplexStream| {
+        let commands = commands.clone();
+        async move {
+            let (mut reader, mut writer) = tokio::io::split(stream);
+            let count = commands.len();
+            let send = async {
+                for command in &commands {
+                    write_command(&mut writer, command).await.unwrap();
+                }
+            };
+            let receive = async {
+                let mut buf = BytesMut::new();
+                let mut received = Vec::with_capacity(count);
+                for _ in 0..count {
+                    received.push(read_command(&mut reader, &mut buf).await.unwrap());
+                }
+                received
+            };
+            tokio::join!(send, receive).1
+        }
+    };
+    let (at_server, at_worker) = tokio::join!(exchange(worker), exchange(server));
+
+    for received in [at_server, at_worker] {
+        assert_eq!(received.len(), commands.len());
+        for (sent, got) in commands.iter().zip(&received) {
+            assert_eq!(wire_bytes(got), wire_bytes(sent), "{}", sent.variant_name());
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_login_result_heartbeat_exchange_over_duplex() {
+    let commands = every_v1_command();
+    let find = |name: &str| {
+        commands
+            .iter()
+            .find(|command| command.variant_name() == name)
+            .unwrap()
+            .clone()
+    };
+    let (mut worker, mut server) = tokio::io::duplex(64);
+    let mut worker_buf = BytesMut::new();
+    let mut server_buf = BytesMut::new();
+
+    let login = find("V1::Login");
+    let (sent, received) = tokio::join!(
+        write_command(&mut worker, &login),
+        read_command(&mut server, &mut server_buf)
+    );
+    sent.unwrap();
+    assert_eq!(wire_bytes(&received.unwrap()), wire_bytes(&login));
+
+    let result = find("V1::LoginResult");
+    let (sent, received) = tokio::join!(
+        write_command(&mut server, &result),
+        read_command(&mut worker, &mut worker_buf)
+    );
+    sent.unwrap();
+    match received.unwrap() {
+        Command::V1(CommandV1::LoginResult {
+            success,
+            pods_model,
+            ..
+        }) => {
+            assert!(success);
+            assert_eq!(pods_model[0].expected_size, Some(770_000_000));
+        }
+        other => panic!("unexpected {}", other.variant_name()),
+    }
+
+    let heartbeat = find("V1::Heartbeat");
+    let (sent, received) = tokio::join!(
+        write_command(&mut worker, &heartbeat),
+        read_command(&mut server, &mut server_buf)
+    );
+    sent.unwrap();
+    assert_eq!(received.unwrap().devices_info().unwrap().len(), 1);
+
+    // A worker going away mid-session is an error, not a hang
+    drop(worker);
+    assert!(read_command(&mut server, &mut server_buf).await.is_err());
+}
+
+#[test]
+fn test_heartbeat_lite_trims_static_fields() {
+    let config = bincode_config::standard()
+        .with_fixed_int_encoding()
+        .with_little_endian();
+    let devices = match login_with_devices(MAX_DEVICES_PER_CLIENT) {
+        Command::V1(CommandV1::Login { devices_info, .. }) => devices_info,
+        _ => unreachable!(),
+    };
+    let system_info = SystemInfo {
+        cpu_usage: 42,
+        memory_usage: 55,
+        disk_usage: 70,
+        network_rx: 123_456,
+        network_tx: 654_321,
+    };
+    let full = Command::V1(CommandV1::Heartbeat {
+        client_id: [9; 16],
+        system_info: system_info.clone(),
+        device_count: MAX_DEVICES_PER_CLIENT as u16,
+        device_memtotal_gb: 8 * 80,
+        device_total_tflops: 8 * 312,
+        devices_info: devices.clone(),
+    });
+
+    // Usage moved on one device only.
+    let mut current = devices.clone();
+    current[3].usage = 97;
+    current[3].temp = 81;
+    let delta = device_usage_delta(&devices, &current).unwrap();
+    assert_eq!(delta, vec![current[3].usage_at(3)]);
+
+    let lite = |devices_usage: Vec<DeviceUsage>| {
+        Command::V1(CommandV1::HeartbeatLite {
+            client_id: [9; 16],
+            system_info: system_info.clone(),
+            devices_usage,
+        })
+    };
+    let all_changed: Vec<DeviceUsage> = (0..MAX_DEVICES_PER_CLIENT as u16)
+        .map(|i| current[i as usize].usage_at(i))
+        .collect();
+
+    let full_len = bincode::encode_to_vec(&full, config).unwrap().len();
+    let one_len = bincode::encode_to_vec(lite(delta.clone()), config)
+        .unwrap()
+        .len();
+    let all_len = bincode::encode_to_vec(lite(all_changed), config)
+        .unwrap()
+        .len();
+    // Payload bytes: 1005 for a full 8-device report, 323 (-68%) when every
+    // device changed and 85 (-92%) when one did.
+    assert_eq!((full_len, all_len, one_len), (1005, 323, 85));
+
+    // The server rebuilds the full report from the last one it saw.
+    let mut rebuilt = devices.clone();
+    apply_device_usage(&mut rebuilt, &delta);
+    assert_eq!(rebuilt[3].usage, 97);
+    assert_eq!(rebuilt[3].temp, 81);
+    assert!(rebuilt[3].same_static_fields(&devices[3]));
+
+    // Static changes force a full heartbeat.
+    current[0].memsize_gb = 40;
+    assert!(device_usage_delta(&devices, &current).is_none());
+    assert!(device_usage_delta(&devices, &devices[..1]).is_none());
+}
+
+#[test]
+fn test_quant_types_parse_and_detect_from_model_names() {
+    for quant in QuantType::ALL {
+        assert_eq!(quant.as_str().parse::<QuantType>().unwrap(), *quant);
+    }
+    assert_eq!("q4_k_m".parse::<QuantType>().unwrap(), QuantType::Q4KM);
+    assert!("Q4_K".parse::<QuantType>().is_err());
+
+    assert_eq!(
+        QuantType::detect("qwen2.5-0.5b-instruct-q4_k_m.gguf"),
+        Some(QuantType::Q4KM)
+    );
+    assert_eq!(
+        QuantType::detect("Llama-3.2-1B-Instruct-Q8_0"),
+        Some(QuantType::Q8_0)
+    );
+    assert_eq!(QuantType::detect("gemma-2b-it-f16"), Some(QuantType::F16));
+    assert_eq!(QuantType::detect("Llama-3.2-1B-Instruct"), None);
+
+    let config = bincode_config::standard()
+        .with_fixed_int_encoding()
+        .with_little_endian();
+    let command = Command::V1(CommandV1::Capabilities {
+        client_id: [3; 16],
+        quant_types: vec![QuantType::Q4_0, QuantType::Q8_0],
+    });
+    let bytes = bincode::encode_to_vec(&command, config).unwrap();
+    let (decoded, _): (Command, usize) = bincode::decode_from_slice(&bytes, config).unwrap();
+    match decoded {
+        Command::V1(CommandV1::Capabilities { quant_types, .. }) => {
+            assert_eq!(quant_types, vec![QuantType::Q4_0, QuantType::Q8_0])
+        }
+        other => panic!("unexpected {}", other.variant_name()),
+    }
+}
@@ -263,7 +263,10 @@ fn main() {
         } else {
             let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
 
+            let config = cbindgen::Config::from_root_or_default(&crate_dir);
+
             cbindgen::Builder::new()
+                .with_config(config)
                 .with_crate(crate_dir)
                 .with_language(cbindgen::Language::C)
                 .with_pragma_once(true)
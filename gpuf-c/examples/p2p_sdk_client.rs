@@ -2,8 +2,8 @@ use anyhow::{anyhow, Result};
 use bytes::BytesMut;
 use clap::Parser;
 use common::{
-    read_command, write_command, Command, CommandV1, CommandV2, DevicesInfo, OsType, P2PTransport,
-    SystemInfo, MAX_MESSAGE_SIZE,
+    command_bincode_config, read_command, write_command, Command, CommandV1, CommandV2,
+    DevicesInfo, OsType, P2PTransport, SystemInfo, MAX_MESSAGE_SIZE,
 };
 use crc32fast::Hasher as Crc32;
 use hmac::{Hmac, Mac};
@@ -29,10 +29,7 @@ use tokio_rustls::{
 };
 
 fn udp_encode_command(command: &Command) -> Result<Vec<u8>> {
-    let config = bincode::config::standard()
-        .with_fixed_int_encoding()
-        .with_little_endian();
-    let payload = bincode::encode_to_vec(command, config)?;
+    let payload = bincode::encode_to_vec(command, command_bincode_config())?;
     let len = payload.len() as u32;
     let mut out = Vec::with_capacity(4 + payload.len());
     out.extend_from_slice(&len.to_be_bytes());
@@ -48,10 +45,7 @@ fn udp_decode_command(datagram: &[u8]) -> Result<Command> {
     if datagram.len() < 4 + len {
         return Err(anyhow!("udp datagram truncated"));
     }
-    let config = bincode::config::standard()
-        .with_fixed_int_encoding()
-        .with_little_endian();
-    let (cmd, _) = bincode::decode_from_slice(&datagram[4..4 + len], config)
+    let (cmd, _) = bincode::decode_from_slice(&datagram[4..4 + len], command_bincode_config())
         .map_err(|e| anyhow!("Failed to deserialize command: {}", e))?;
     Ok(cmd)
 }
@@ -194,7 +188,7 @@ fn stun_parse_xor_addr(v: &[u8], txid: &[u8; 12]) -> Option<std::net::SocketAddr
 }
 
 const P2P_UDP_MAGIC: [u8; 4] = *b"P2PU";
-const P2P_UDP_VERSION: u8 = 2;
+const P2P_UDP_VERSION: u8 = 3;
 const P2P_UDP_FLAG_ACK: u8 = 0x01;
 const P2P_UDP_HEADER_LEN: usize = 4 + 1 + 1 + 4 + 2 + 2 + 8 + 32;
 const P2P_UDP_MTU_PAYLOAD: usize = 1200;
@@ -292,17 +286,15 @@ fn p2p_udp_validate_fragment(
     payload: &[u8],
     tag: &[u8; 32],
 ) -> Result<()> {
-    if (flags & P2P_UDP_FLAG_ACK) != 0 {
-        if frag_idx != 0 || frag_cnt != 0 || !payload.is_empty() {
-            return Err(anyhow!("invalid p2p udp ack metadata"));
-        }
-    } else {
-        if frag_cnt == 0 || frag_cnt as usize > P2P_MAX_FRAGMENTS_PER_MESSAGE {
-            return Err(anyhow!("invalid p2p udp fragment count"));
-        }
-        if frag_idx >= frag_cnt {
-            return Err(anyhow!("invalid p2p udp fragment index"));
-        }
+    if (flags & P2P_UDP_FLAG_ACK) != 0 && !payload.is_empty() {
+        return Err(anyhow!("invalid p2p udp ack metadata"));
+    }
+    // ACKs name the fragment they acknowledge, so both carry fragment metadata
+    if frag_cnt == 0 || frag_cnt as usize > P2P_MAX_FRAGMENTS_PER_MESSAGE {
+        return Err(anyhow!("invalid p2p udp fragment count"));
+    }
+    if frag_idx >= frag_cnt {
+        return Err(anyhow!("invalid p2p udp fragment index"));
     }
     if !p2p_timestamp_is_fresh(timestamp, p2p_now_secs()) {
         return Err(anyhow!("stale p2p udp fragment"));
@@ -329,26 +321,20 @@ async fn p2p_udp_send_ack(
     connection_id: [u8; 16],
     secret: [u8; 32],
     msg_id: u32,
+    frag_idx: u16,
+    frag_cnt: u16,
 ) {
-    let timestamp = p2p_now_secs();
-    let tag = p2p_udp_tag(
-        &secret,
-        &connection_id,
-        P2P_UDP_FLAG_ACK,
-        msg_id,
-        0,
-        0,
-        timestamp,
-        &[],
-    );
-    let hdr = p2p_udp_make_header(P2P_UDP_FLAG_ACK, msg_id, 0, 0, timestamp, &tag);
-    let _ = socket.send_to(&hdr, to).await;
+    let ack = p2p_udp_ack_packet(connection_id, secret, msg_id, frag_idx, frag_cnt);
+    let _ = socket.send_to(&ack, to).await;
 }
 
+/// ACK for fragment `frag_idx` of a `frag_cnt`-fragment message.
 fn p2p_udp_ack_packet(
     connection_id: [u8; 16],
     secret: [u8; 32],
     msg_id: u32,
+    frag_idx: u16,
+    frag_cnt: u16,
 ) -> [u8; P2P_UDP_HEADER_LEN] {
     let timestamp = p2p_now_secs();
     let tag = p2p_udp_tag(
@@ -356,12 +342,19 @@ fn p2p_udp_ack_packet(
         &connection_id,
         P2P_UDP_FLAG_ACK,
         msg_id,
-        0,
-        0,
+        frag_idx,
+        frag_cnt,
         timestamp,
         &[],
     );
-    p2p_udp_make_header(P2P_UDP_FLAG_ACK, msg_id, 0, 0, timestamp, &tag)
+    p2p_udp_make_header(
+        P2P_UDP_FLAG_ACK,
+        msg_id,
+        frag_idx,
+        frag_cnt,
+        timestamp,
+        &tag,
+    )
 }
 
 async fn p2p_udp_send_reliable(
@@ -417,8 +410,8 @@ async fn p2p_udp_send_reliable(
                 {
                     let valid_ack = (flags & P2P_UDP_FLAG_ACK) != 0
                         && ack_id == msg_id
-                        && ack_frag_idx == 0
-                        && ack_frag_cnt == 0
+                        && ack_frag_idx == frag_idx as u16
+                        && ack_frag_cnt == frag_cnt as u16
                         && p2p_udp_validate_fragment(
                             &secret,
                             &connection_id,
@@ -497,19 +490,18 @@ async fn turn_allocate_udp(
     username: &str,
     password: &str,
 ) -> Result<(UdpSocket, std::net::SocketAddr, String, String)> {
-    let url = Url::parse(turn_url)?;
-    let host = url
-        .host_str()
-        .ok_or_else(|| anyhow!("turn url missing host"))?;
-    let port = url
-        .port_or_known_default()
-        .ok_or_else(|| anyhow!("turn url missing port"))?;
-    let server = (host, port)
+    let (host, port) = parse_turns_url(turn_url)?;
+    let server = (host.as_str(), port)
         .to_socket_addrs()?
         .next()
         .ok_or_else(|| anyhow!("turn host resolve failed"))?;
 
-    let sock = UdpSocket::bind("0.0.0.0:0").await?;
+    let local = if server.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    let sock = UdpSocket::bind(local).await?;
     sock.connect(server).await?;
 
     let requested_transport_t: u16 = 0x0019;
@@ -708,8 +700,8 @@ async fn turn_send_reliable_over_indication(
                         {
                             let valid_ack = (flags & P2P_UDP_FLAG_ACK) != 0
                                 && ack_id == msg_id
-                                && ack_frag_idx == 0
-                                && ack_frag_cnt == 0
+                                && ack_frag_idx == frag_idx as u16
+                                && ack_frag_cnt == frag_cnt as u16
                                 && p2p_udp_validate_fragment(
                                     &secret,
                                     &connection_id,
@@ -776,13 +768,16 @@ fn stun_get_text_attr(attrs: &[(u16, Vec<u8>)], t: u16) -> Option<String> {
 
 fn parse_turns_url(turn_url: &str) -> Result<(String, u16)> {
     let url = Url::parse(turn_url)?;
-    let host = url
-        .host_str()
-        .ok_or_else(|| anyhow!("turn url missing host"))?;
+    // Bare IPv6 address, without the URL's brackets
+    let host = match url.host() {
+        Some(url::Host::Ipv6(addr)) => addr.to_string(),
+        Some(host) => host.to_string(),
+        None => return Err(anyhow!("turn url missing host")),
+    };
     let port = url
         .port_or_known_default()
         .ok_or_else(|| anyhow!("turn url missing port"))?;
-    Ok((host.to_string(), port))
+    Ok((host, port))
 }
 
 #[cfg(not(target_os = "android"))]
@@ -803,8 +798,7 @@ async fn turn_tls_connect(
     port: u16,
     cert_chain_path: &str,
 ) -> Result<tokio_rustls::client::TlsStream<TcpStream>> {
-    let addr = format!("{}:{}", host, port);
-    let stream = TcpStream::connect(&addr).await?;
+    let stream = TcpStream::connect((host, port)).await?;
 
     let certs = load_root_cert(cert_chain_path)?;
     let mut roots = RootCertStore::empty();
@@ -1194,7 +1188,16 @@ async fn main() -> Result<()> {
             {
                 continue;
             }
-            p2p_udp_send_ack(&socket, from, connection_id, data_plane_secret, msg_id).await;
+            p2p_udp_send_ack(
+                &socket,
+                from,
+                connection_id,
+                data_plane_secret,
+                msg_id,
+                frag_idx,
+                frag_cnt,
+            )
+            .await;
 
             let entry = inflight.entry(msg_id).or_default();
             entry.insert(frag_idx, payload.to_vec());
@@ -1320,7 +1323,13 @@ async fn main() -> Result<()> {
                     continue;
                 }
 
-                let ack = p2p_udp_ack_packet(connection_id, data_plane_secret, msg_id);
+                let ack = p2p_udp_ack_packet(
+                    connection_id,
+                    data_plane_secret,
+                    msg_id,
+                    frag_idx,
+                    frag_cnt,
+                );
                 turn_send_indication(&turn_sock, peer_relay, &ack).await?;
 
                 let entry = inflight.entry(msg_id).or_default();
@@ -1362,3 +1371,70 @@ async fn main() -> Result<()> {
     println!("P2P output:\n{}", out);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acks_name_the_fragment_they_acknowledge() {
+        let (cid, secret) = ([3u8; 16], [5u8; 32]);
+        let ack = p2p_udp_ack_packet(cid, secret, 9, 2, 4);
+        let (flags, msg_id, frag_idx, frag_cnt, ts, tag) = p2p_udp_parse_header(&ack).unwrap();
+        assert_eq!((msg_id, frag_idx, frag_cnt), (9, 2, 4));
+        assert!(p2p_udp_validate_fragment(
+            &secret,
+            &cid,
+            flags,
+            msg_id,
+            frag_idx,
+            frag_cnt,
+            ts,
+            &[],
+            &tag
+        )
+        .is_ok());
+
+        // The whole-message ACK of the stop-and-wait scheme is no longer valid
+        let ts = p2p_now_secs();
+        let tag = p2p_udp_tag(&secret, &cid, P2P_UDP_FLAG_ACK, 9, 0, 0, ts, &[]);
+        assert!(
+            p2p_udp_validate_fragment(&secret, &cid, P2P_UDP_FLAG_ACK, 9, 0, 0, ts, &[], &tag)
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn reliable_send_completes_against_per_fragment_acks() {
+        let (cid, secret) = ([3u8; 16], [5u8; 32]);
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let to = receiver.local_addr().unwrap();
+        let payload: Vec<u8> = (0..4000u32).map(|i| (i % 251) as u8).collect();
+
+        let rx = tokio::spawn(async move {
+            let mut parts = std::collections::HashMap::new();
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                let (n, from) = receiver.recv_from(&mut buf).await.unwrap();
+                let (flags, msg_id, frag_idx, frag_cnt, ts, tag) =
+                    p2p_udp_parse_header(&buf[..n]).unwrap();
+                let body = &buf[P2P_UDP_HEADER_LEN..n];
+                p2p_udp_validate_fragment(
+                    &secret, &cid, flags, msg_id, frag_idx, frag_cnt, ts, body, &tag,
+                )
+                .unwrap();
+                p2p_udp_send_ack(&receiver, from, cid, secret, msg_id, frag_idx, frag_cnt).await;
+                parts.insert(frag_idx, body.to_vec());
+                if let Some(full) = p2p_udp_try_reassemble(&mut parts, frag_cnt) {
+                    return full;
+                }
+            }
+        });
+
+        p2p_udp_send_reliable(&sender, to, cid, secret, 1, &payload)
+            .await
+            .unwrap();
+        assert_eq!(rx.await.unwrap(), payload);
+    }
+}
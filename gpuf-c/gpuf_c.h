@@ -10,15 +10,50 @@
 
 #define DEFAULT_OUTPUT_LIMIT (256 * 1024)
 
+/**
+ * Run models on the CPU (the default).
+ */
+#define GPUF_BACKEND_CPU 0
+
+/**
+ * Offload models to a Vulkan GPU when one is available.
+ */
+#define GPUF_BACKEND_VULKAN 1
+
 typedef enum ProjectorType {
-  Unknown = 0,
-  LLaVA = 1,
-  Qwen2VL = 2,
-  Qwen25VL = 3,
-  Qwen3VL = 4,
-  Pixtral = 5,
+  ProjectorType_Unknown = 0,
+  ProjectorType_LLaVA = 1,
+  ProjectorType_Qwen2VL = 2,
+  ProjectorType_Qwen25VL = 3,
+  ProjectorType_Qwen3VL = 4,
+  ProjectorType_Pixtral = 5,
 } ProjectorType;
 
+/**
+ * Result codes returned by the remote worker C API. The values are part of
+ * the ABI and never change; `gpuf_error_string` describes each of them.
+ */
+enum GpufError {
+  GpufError_Ok = 0,
+  GpufError_BackendInit = -1,
+  GpufError_PathConversion = -2,
+  GpufError_ModelLoad = -3,
+  GpufError_ContextCreate = -4,
+  GpufError_InvalidArgument = -5,
+  GpufError_InvalidTlsPolicy = -6,
+  GpufError_WorkerStart = -7,
+  GpufError_NoModelLoaded = -8,
+  GpufError_BufferTooSmall = -9,
+  GpufError_Internal = -10,
+  GpufError_Unsupported = -11,
+  /**
+   * The call succeeded but the server could not be told; it learns on the
+   * next model status report.
+   */
+  GpufError_ServerNotNotified = 1,
+};
+typedef int32_t GpufError;
+
 typedef struct llama_model {
   uint8_t _private[0];
 } llama_model;
@@ -99,6 +134,23 @@ typedef struct llama_batch {
   int8_t *logits;
 } llama_batch;
 
+typedef struct llama_timings {
+  double prompt_eval_time_ms;
+  double eval_time_ms;
+  double total_time_ms;
+} llama_timings;
+
+typedef struct llama_completion_params {
+  const char *prompt;
+  int n_predict;
+  float temperature;
+  int top_k;
+  float top_p;
+  float repeat_penalty;
+  const char *const *stop_words;
+  int n_stop_words;
+} llama_completion_params;
+
 typedef struct MtmdContextParams {
   bool use_gpu;
   bool print_timings;
@@ -165,9 +217,54 @@ typedef struct gpuf_multimodal_model {
   enum ProjectorType projector_type;
   const struct llama_vocab *vocab;
   bool is_multimodal;
+  int image_min_tokens;
+  int image_max_tokens;
   CString _media_marker;
 } gpuf_multimodal_model;
 
+/**
+ * What a loaded multimodal model can do, filled by `gpuf_multimodal_model_info`.
+ */
+typedef struct gpuf_multimodal_info {
+  enum ProjectorType projector_type;
+  bool supports_vision;
+  bool supports_audio;
+  /**
+   * Per-image token bounds requested at load; -1 means the projector default.
+   */
+  int image_min_tokens;
+  int image_max_tokens;
+  /**
+   * Marker to place in prompts where media goes. Owned by the model and valid
+   * until it is freed.
+   */
+  const char *media_marker;
+} gpuf_multimodal_info;
+
+/**
+ * Vulkan device as reported by `gpuf_vulkan_device_info`.
+ */
+typedef struct gpuf_vulkan_device {
+  /**
+   * NUL-terminated device name.
+   */
+  char name[256];
+  uint32_t vendor_id;
+  uint32_t device_id;
+  /**
+   * Packed `VK_MAKE_API_VERSION` value supported by the device.
+   */
+  uint32_t api_version;
+  /**
+   * Total size of device-local memory heaps in bytes.
+   */
+  uint64_t memory_bytes;
+  /**
+   * 1 for software/CPU implementations (e.g. SwiftShader), 0 for GPUs.
+   */
+  int is_software;
+} gpuf_vulkan_device;
+
 /**
  * Token callback: called for each generated token
  * Parameters: user_data, token_text, token_id
@@ -270,6 +367,8 @@ extern void *llama_get_memory(struct llama_context *ctx);
 
 extern bool llama_memory_seq_rm(void *mem, int seq_id, LlamaPos p0, LlamaPos p1);
 
+extern LlamaPos llama_memory_seq_pos_max(void *mem, int seq_id);
+
 extern void llama_memory_clear(void *mem, bool data);
 
 extern struct llama_sampler *llama_sampler_chain_init(struct llama_sampler_chain_params params);
@@ -340,6 +439,17 @@ extern int llama_chat_apply_template(const char *tmpl,
  */
 struct llama_context *gpuf_create_context(struct llama_model *model);
 
+/**
+ * Frees a context created by `gpuf_create_context` or
+ * `gpuf_create_multimodal_context`. NULL is ignored, as is the SDK's own
+ * context, which is released by `gpuf_unload_model`.
+ *
+ * # Safety
+ * `ctx` must be NULL or a context created by this library that has not been
+ * freed yet. It must not be used after this call.
+ */
+void gpuf_free_context(struct llama_context *ctx);
+
 /**
  * Start async model loading (realistic implementation)
  *
@@ -357,7 +467,8 @@ bool gpuf_load_model_async_start(const char *_path);
 int32_t gpuf_load_model_get_status(void);
 
 /**
- * Get loading progress (limited but realistic)
+ * Get loading progress: the fraction of tensors llama.cpp has loaded so far,
+ * 1.0 once loading completed, -1.0 if not started or failed
  */
 float gpuf_load_model_get_progress(void);
 
@@ -424,10 +535,53 @@ bool gpuf_is_model_loaded(void);
 bool gpuf_is_context_ready(void);
 
 /**
- * Get model loading status
+ * Get model loading status: 0 not loaded (or unloaded), 1 loading, 2 model
+ * and context ready, 3 the last load failed; see `gpuf_get_model_error`.
  */
 int gpuf_get_model_status(void);
 
+/**
+ * Same as `gpuf_get_last_load_error`: copy why the model failed to load
+ * while `gpuf_get_model_status` reports 3.
+ *
+ * # Safety
+ * `out` must point to a writable buffer of at least `out_len` bytes.
+ */
+int gpuf_get_model_error(char *out, int out_len);
+
+/**
+ * Set how many tokens `gpuf_warm_context` decodes (default 1, at most 64).
+ *
+ * One token is the cheapest warmup. Larger counts prefill a short fixed prompt
+ * and decode the remaining tokens one at a time, which pages in all weights and
+ * initializes every kernel (e.g. Vulkan shader pipelines) before the first real
+ * request. Changing the count lets the next `gpuf_warm_context` call run again.
+ * Returns 0 on success, -1 if `tokens` is out of range.
+ */
+int gpuf_set_warmup_tokens(int tokens);
+
+/**
+ * Duration of the most recent `gpuf_warm_context` warmup in milliseconds,
+ * or -1 if no warmup has run yet.
+ */
+int gpuf_get_last_warmup_ms(void);
+
+/**
+ * Pre-create the context for the currently loaded model and run a warmup of
+ * `gpuf_set_warmup_tokens` tokens (one by default).
+ *
+ * Intended to be called right after model loading completes (while the app shows a
+ * "preparing" state) so the first user request does not pay context creation and
+ * warmup cost. The created context is cached in the global context slot.
+ *
+ * Safe to call before any generation and idempotent: once the current context has been
+ * warmed, further calls return 0 without touching it.
+ *
+ * Returns the warmup time in milliseconds (>= 0), or:
+ * -1 if no model is loaded, -2 if context creation failed, -3 if the warmup decode failed.
+ */
+int gpuf_warm_context(void);
+
 /**
  *
  * # Safety
@@ -436,6 +590,31 @@ int gpuf_get_model_status(void);
  */
 struct llama_model *gpuf_load_model(const char *path);
 
+/**
+ * Load a model with explicit GPU offload and memory mapping settings.
+ *
+ * `n_gpu_layers` is the number of layers to offload to the GPU backend (0 keeps
+ * the model on the CPU); values above 999 are clamped, negative values fail.
+ * `use_mmap` maps the file instead of reading it into memory and `use_mlock`
+ * pins the weights in RAM. `gpuf_load_model` remains the CPU/preferred-backend
+ * default with mmap on.
+ *
+ * Returns NULL on failure; see `gpuf_get_last_load_error`.
+ *
+ * # Safety
+ * `path` must be a valid, NUL-terminated C string pointer and must remain valid for the duration
+ * of this call.
+ */
+struct llama_model *gpuf_load_model_ex(const char *path, int n_gpu_layers, bool use_mmap, bool use_mlock);
+
+/**
+ * Check whether a model loaded with `gpuf_load_model` is a vision model whose
+ * image support needs `gpuf_load_multimodal_model` and an mmproj file.
+ *
+ * Returns 1 if it does, 0 if not, -1 if `model` is null.
+ */
+int gpuf_model_requires_mmproj(const struct llama_model *model);
+
 /**
  *
  * # Safety
@@ -449,6 +628,34 @@ struct gpuf_multimodal_model *gpuf_load_multimodal_model(const char *_text_model
                                                          const char *_mmproj_path);
 
 /**
+ * Load a multimodal model with a per-image token budget.
+ *
+ * More image tokens keep more detail (useful for documents) at the cost of
+ * speed and context; fewer suit quick captioning. A value <= 0 keeps the
+ * projector's default for that bound. Returns NULL if both bounds are set and
+ * `image_min_tokens > image_max_tokens`.
+ *
+ * # Safety
+ * `text_model_path` and `mmproj_path` must be valid, NUL-terminated C string pointers and must
+ * remain valid for the duration of this call.
+ */
+struct gpuf_multimodal_model *gpuf_load_multimodal_model_with_image_tokens(const char *text_model_path,
+                                                                           const char *mmproj_path,
+                                                                           int image_min_tokens,
+                                                                           int image_max_tokens);
+
+/**
+ * Set the context size, in tokens, of contexts created by
+ * `gpuf_create_multimodal_context` (default 4096). Image embeddings take
+ * context too, so larger images need a larger context. 0 uses the context
+ * size the model was trained with. Takes effect on the next context created.
+ * Returns 0 on success, -1 on negative input.
+ */
+int gpuf_set_multimodal_n_ctx(int n_ctx);
+
+/**
+ * Create a context for a multimodal model, sized by
+ * `gpuf_set_multimodal_n_ctx` (default 4096 tokens).
  *
  * # Safety
  * `multimodal_model` must be a valid pointer returned by `gpuf_load_multimodal_model` and must
@@ -458,6 +665,27 @@ struct llama_context *gpuf_create_multimodal_context(struct gpuf_multimodal_mode
 
 struct llama_context *gpuf_create_multimodal_context(struct gpuf_multimodal_model *_multimodal_model);
 
+/**
+ * Create a context for a multimodal model with an explicit size.
+ *
+ * Every image takes up to the model's `image_max_tokens` of context before
+ * the prompt and the generated tokens, so `n_ctx` should be at least
+ * `image_max_tokens + prompt tokens + max_tokens`. `n_ctx <= 0` uses the
+ * size set by `gpuf_set_multimodal_n_ctx` and `n_batch <= 0` uses 512.
+ * Returns NULL when the context cannot hold one image of `image_max_tokens`.
+ *
+ * # Safety
+ * `multimodal_model` must be a valid pointer returned by `gpuf_load_multimodal_model` and must
+ * remain valid for the duration of this call.
+ */
+struct llama_context *gpuf_create_multimodal_context_with_params(struct gpuf_multimodal_model *multimodal_model,
+                                                                 int n_ctx,
+                                                                 int n_batch);
+
+struct llama_context *gpuf_create_multimodal_context_with_params(struct gpuf_multimodal_model *_multimodal_model,
+                                                                 int _n_ctx,
+                                                                 int _n_batch);
+
 /**
  * # Safety
  * - `multimodal_model` must be a valid pointer returned by `gpuf_load_multimodal_model`.
@@ -467,6 +695,10 @@ struct llama_context *gpuf_create_multimodal_context(struct gpuf_multimodal_mode
  * - `image_data` must be a valid pointer to `image_size` bytes (may be null only if
  *   `image_size == 0`).
  * - `output` must be a valid writable buffer of at least `output_len` bytes.
+ *
+ * Returns -2 (with an explanatory message in `output`) when an image is sent
+ * to a model loaded without an mmproj, and -3 (also with a message) when the
+ * prompt, the image tokens and `max_tokens` do not fit the context.
  */
 int gpuf_generate_multimodal(struct gpuf_multimodal_model *_multimodal_model,
                              struct llama_context *_ctx,
@@ -534,6 +766,15 @@ int gpuf_get_multimodal_info(struct gpuf_multimodal_model *multimodal_model, boo
 
 int gpuf_get_multimodal_info(struct gpuf_multimodal_model *_multimodal_model, bool *_has_vision);
 
+/**
+ * Fill `info` with the projector type, vision/audio support, image token
+ * bounds and media marker of a loaded multimodal model.
+ *
+ * Returns 0 on success, -1 if a pointer is null or the model has no mtmd context.
+ */
+int gpuf_multimodal_model_info(struct gpuf_multimodal_model *multimodal_model,
+                               struct gpuf_multimodal_info *info);
+
 int gpuf_get_vision_tokens(struct gpuf_multimodal_model *multimodal_model,
                            char *start_token,
                            char *end_token,
@@ -546,6 +787,44 @@ int gpuf_get_vision_tokens(struct gpuf_multimodal_model *_multimodal_model,
                            char *_media_token,
                            int _max_length);
 
+/**
+ * Compute an L2-normalized embedding of `text` with the loaded model and copy
+ * it into `out`.
+ *
+ * The context is switched to embeddings mode for the call and its KV cache is
+ * cleared, so any conversation state in `ctx` is lost. The text must fit in
+ * one batch of the context.
+ *
+ * Returns the embedding dimension, or:
+ * the negated dimension if `out_len` is too small (nothing is written),
+ * -1 on invalid arguments or if the model could not produce an embedding.
+ */
+int gpuf_embed_text(struct llama_context *ctx, const char *text, float *out, int out_len);
+
+/**
+ * Snapshot the KV cache (and the rest of the context state) of `ctx` into
+ * `out`, e.g. before `set_remote_worker_model` swaps the context out.
+ *
+ * Returns the bytes written, or:
+ * the negated required size if `out` is null or `out_len` is too small
+ * (nothing is written; call with a null `out` to query the size),
+ * -1 if `ctx` is null or the state could not be serialized.
+ */
+intptr_t gpuf_kv_cache_save(struct llama_context *ctx, uint8_t *out, uintptr_t out_len);
+
+/**
+ * Restore a snapshot from `gpuf_kv_cache_save` into `ctx`.
+ *
+ * The context must belong to the same model the snapshot was taken with.
+ * On success generation can continue from the restored conversation, e.g.
+ * with `gpuf_continue_generation`. Restoring into the SDK's own context waits
+ * for any generation running on it to finish first.
+ *
+ * Returns the number of tokens in the restored cache, or -1 on invalid
+ * arguments or if the data does not match the context.
+ */
+int gpuf_kv_cache_load(struct llama_context *ctx, const uint8_t *data, uintptr_t len);
+
 int gpuf_generate_final_solution_text(const struct llama_model *model,
                                       struct llama_context *ctx,
                                       const char *prompt,
@@ -566,10 +845,50 @@ int gpuf_generate_with_sampling(const struct llama_model *model,
                                 LlamaToken *token_buffer,
                                 int token_buffer_size);
 
+/**
+ * Continue the previous `gpuf_generate_with_sampling` completion for up to
+ * `additional_tokens` more tokens, decoding from the current KV position
+ * without re-running prefill.
+ *
+ * Preconditions: `ctx` must be the context used by that completion and no
+ * other generation, warmup or model swap may have touched sequence 0 since.
+ * The KV cache must still end right before `GLOBAL_CONTEXT_POSITION`. Meant
+ * for resuming after finish reason "length"; sampler history (repeat penalty)
+ * is not carried over from the previous call. Output stops at the
+ * `gpuf_set_stop_words` list like the streaming generation loops.
+ *
+ * Returns the number of bytes written to `output` (NUL terminated), -1 on
+ * invalid arguments, -2 if the context state can no longer be continued
+ * (start a fresh generation instead) and -3 if the context window is full.
+ */
+int gpuf_continue_generation(struct llama_context *ctx,
+                             int additional_tokens,
+                             float temperature,
+                             int top_k,
+                             float top_p,
+                             float repeat_penalty,
+                             char *output,
+                             int output_len);
+
 const char *gpuf_system_info(void);
 
 const char *gpuf_version(void);
 
+/**
+ * Write this worker's description as JSON into `output`: build version,
+ * protocol revision, OS, engine, loaded model, context size, batch size and
+ * a short device summary. The same data answers the server's describe
+ * requests.
+ *
+ * Returns `GpufError::InvalidArgument` for a null or empty buffer and
+ * `GpufError::BufferTooSmall` if the JSON (plus NUL) does not fit in
+ * `output_len` bytes.
+ *
+ * # Safety
+ * `output` must be writable for `output_len` bytes.
+ */
+GpufError gpuf_describe_worker(char *output, int output_len);
+
 int gpuf_init(void);
 
 int gpuf_cleanup(void);
@@ -579,8 +898,179 @@ int gpuf_cleanup(void);
  */
 int gpuf_stop_generation(struct llama_context *_ctx);
 
+/**
+ * Stop only the generation started as request `handle` by
+ * `gpuf_start_generation_async_cancellable`; other requests keep running.
+ * Returns 0 if the request was signalled, -1 if it is unknown or already
+ * finished.
+ */
+int gpuf_cancel_request(uint64_t handle);
+
+/**
+ * Configure the generation loop detector.
+ *
+ * Generation stops with finish reason "repetition" once any pattern of 1 to
+ * `ngram_size` tokens repeats back-to-back more than `max_repeats` times.
+ * Passing 0 for either value disables the check. Returns 0 on success, -1 on
+ * negative input.
+ */
+int gpuf_set_repetition_guard(int ngram_size, int max_repeats);
+
+/**
+ * Configure the frequency and presence penalties of mobile generation and
+ * how many recent tokens the penalties (including the per-call repeat
+ * penalty) look back over.
+ *
+ * Both penalties default to 0 (off); `penalty_last_n` defaults to -1, the
+ * whole context, and 0 disables the penalties. Returns 0 on success, -1 on a
+ * non-finite penalty or `penalty_last_n` below -1.
+ */
+int gpuf_set_penalties(float frequency_penalty, float presence_penalty, int penalty_last_n);
+
+/**
+ * Cap streaming generation at `max_tokens_per_sec` tokens/s by sleeping
+ * between tokens, keeping passively cooled devices under a thermal ceiling at
+ * the cost of latency. 0 removes the cap. Returns 0 on success, -1 on negative
+ * input.
+ */
+int gpuf_set_max_tokens_per_sec(int max_tokens_per_sec);
+
+/**
+ * Set which `<|...|>` control tokens are stripped from streamed output.
+ * `tokens` is a comma-separated list: with `keep_only` 0 exactly these are
+ * stripped, otherwise every control token except these is. NULL restores the
+ * built-in list. Returns 0 on success, -1 if `tokens` is not valid UTF-8.
+ */
+int gpuf_set_control_tokens(const char *tokens, int keep_only);
+
+/**
+ * Pin inference threads to the CPUs set in `core_mask` (bit N = CPU N), e.g.
+ * to keep them off efficiency cores. 0 removes the pinning. Takes effect on
+ * the next generation; a no-op on platforms without `sched_setaffinity`.
+ * Returns 0.
+ */
+int gpuf_set_thread_affinity(uint64_t core_mask);
+
+/**
+ * Pin inference threads to the cores clocked above the slowest cluster
+ * (the big/prime cores of a big.LITTLE SoC), or remove the pinning when
+ * `enabled` is false. Returns the number of cores selected; 0 when disabled
+ * or when the CPU has no distinct performance cores, leaving threads unpinned.
+ */
+int gpuf_set_performance_cores_only(bool enabled);
+
+/**
+ * Set the stop words used by the streaming and multimodal generation loops.
+ *
+ * Generation halts once the output contains any of them, and the matched stop
+ * word is left out of the emitted text. Passing `n_stop_words == 0` clears the
+ * list. Returns 0 on success, -1 on a null or non-UTF-8 entry.
+ *
+ * # Safety
+ * `stop_words` must point to `n_stop_words` NUL-terminated C strings.
+ */
+int gpuf_set_stop_words(const char *const *stop_words, int n_stop_words);
+
+/**
+ * Set the seed used by the final sampling step of mobile generation.
+ *
+ * A fixed seed makes output reproducible for the same prompt and parameters
+ * (the default is 1234); `0xFFFFFFFF` draws a random seed for every generation.
+ */
+void gpuf_set_seed(uint32_t seed);
+
+/**
+ * Report memory pool usage so callers can back off before `allocate_from_pool`
+ * starts failing. Any of the out pointers may be null.
+ * Returns 0 when the pool is initialized, -1 otherwise (values are then 0).
+ */
+int gpuf_memory_pool_stats(size_t *used, size_t *peak, size_t *size);
+
+/**
+ * Finish reason of the most recent generation: "stop", "length" or
+ * "repetition". Returns NULL if nothing has been generated yet. The returned
+ * string is static and must not be freed.
+ */
+const char *gpuf_get_last_finish_reason(void);
+
+/**
+ * Copy the timings of the most recent generation into `out`.
+ *
+ * Covers `gpuf_start_generation_async` and the buffered completion path:
+ * prompt evaluation, time spent sampling and decoding generated tokens, and
+ * the wall-clock total. Returns 0 on success, -1 if `out` is null or nothing
+ * has been generated yet.
+ */
+int gpuf_get_last_timings(llama_timings *out);
+
+/**
+ * Copy why the last `gpuf_load_model` / `gpuf_load_multimodal_model` call
+ * failed into `out` (NUL terminated, truncated to fit), e.g. "Insufficient
+ * memory: model needs about 4352 MB but only 2911 MB is available".
+ * Returns the bytes copied, 0 if the last load succeeded, -1 on invalid arguments.
+ *
+ * # Safety
+ * `out` must point to a writable buffer of at least `out_len` bytes.
+ */
+int gpuf_get_last_load_error(char *out, int out_len);
+
+/**
+ * Number of Vulkan devices found on this device (0 if Vulkan is unavailable).
+ */
+int gpuf_vulkan_device_count(void);
+
+/**
+ * Fill `out` with the Vulkan device at `index`.
+ * Returns 0 on success, -1 if `out` is null or `index` is out of range.
+ *
+ * # Safety
+ * `out` must be null or point to writable memory for one `gpuf_vulkan_device`.
+ */
+int gpuf_vulkan_device_info(int index, struct gpuf_vulkan_device *out);
+
+/**
+ * Select the backend used by subsequent `gpuf_load_model` / `gpuf_create_context`
+ * calls: `GPUF_BACKEND_CPU` (default) or `GPUF_BACKEND_VULKAN`. Vulkan falls
+ * back to the CPU when no usable device is found; check
+ * `gpuf_get_last_backend` for what actually ran.
+ * Returns 0 on success, -1 for an unknown backend.
+ */
+int gpuf_set_preferred_backend(int backend);
+
+/**
+ * Backend that served the most recent generation: "cpu" or "vulkan".
+ * Returns NULL if nothing has been generated yet. The returned string is
+ * static and must not be freed.
+ */
+const char *gpuf_get_last_backend(void);
+
+/**
+ * Total length in bytes of the last buffered generation result.
+ *
+ * gpuf_generate_with_sampling, gpuf_continue_generation and
+ * gpuf_generate_multimodal keep their full output even when it doesn't fit
+ * the caller's buffer. The output was truncated when this is larger than the
+ * length they returned; gpuf_get_last_result then returns the rest.
+ */
+int gpuf_get_last_result_len(void);
+
+/**
+ * Copy the next chunk of the last buffered generation result into `out`.
+ *
+ * Each call continues where the previous one stopped. After a new generation
+ * it starts right after the part already copied into the generation's own
+ * output buffer, so it returns 0 straight away unless that output was
+ * truncated. Chunks never split a UTF-8 character and are NUL terminated.
+ * Returns the bytes written, 0 once the whole result has been read, or -1 on
+ * invalid arguments (`out_len` must be at least 5 so a full character always
+ * fits).
+ */
+int gpuf_get_last_result(char *out, int out_len);
+
 /**
  * Start async generation with streaming callback (simplified version)
+ *
+ * Uses sequence 0; see `gpuf_start_generation_async_seq` to run on another sequence.
  */
 int gpuf_start_generation_async(struct llama_context *ctx,
                                 const char *prompt,
@@ -592,6 +1082,63 @@ int gpuf_start_generation_async(struct llama_context *ctx,
                                 void (*on_token_callback)(const char*, void*),
                                 void *user_data);
 
+/**
+ * Stream a completion described by `params` on sequence 0.
+ *
+ * Like `gpuf_start_generation_async` with the prompt and sampling settings
+ * taken from `params`. Generation halts at any of `params.stop_words`; when
+ * the array holds no usable entries, the `gpuf_set_stop_words` list applies.
+ * Returns the number of generated tokens, or a negative value on error.
+ *
+ * # Safety
+ * `params` must point to a valid `llama_completion_params` whose `prompt` is
+ * a NUL-terminated C string and whose `stop_words` is null or points to
+ * `n_stop_words` entries, each null or a NUL-terminated C string.
+ */
+int gpuf_start_completion(struct llama_context *ctx,
+                          const llama_completion_params *params,
+                          void (*on_token_callback)(const char*, void*),
+                          void *user_data);
+
+/**
+ * Start async generation on a specific KV sequence.
+ *
+ * Only the KV cells of `seq_id` are cleared before prefill, so other sequences sharing
+ * the same context (parallel chats) keep their state. The context must have been
+ * created with `n_seq_max > seq_id`.
+ */
+int gpuf_start_generation_async_seq(struct llama_context *ctx,
+                                    int seq_id,
+                                    const char *prompt,
+                                    int max_tokens,
+                                    float temperature,
+                                    int top_k,
+                                    float top_p,
+                                    float repeat_penalty,
+                                    void (*on_token_callback)(const char*, void*),
+                                    void *user_data);
+
+/**
+ * Start generation on a specific KV sequence in the background.
+ *
+ * Same as `gpuf_start_generation_async_seq`, but returns immediately with a
+ * request handle for `gpuf_cancel_request`. The prompt is copied before
+ * returning; `ctx` and `user_data` must stay valid until `on_done` is called
+ * with the handle and the generated token count (negative on error).
+ * Requests run one at a time. Returns 0 if the request could not be started.
+ */
+uint64_t gpuf_start_generation_async_cancellable(struct llama_context *ctx,
+                                                 int seq_id,
+                                                 const char *prompt,
+                                                 int max_tokens,
+                                                 float temperature,
+                                                 int top_k,
+                                                 float top_p,
+                                                 float repeat_penalty,
+                                                 void (*on_token_callback)(const char*, void*),
+                                                 void (*on_done)(uint64_t, int, void*),
+                                                 void *user_data);
+
 /**
  * Simple single token generation for testing
  */
@@ -618,42 +1165,53 @@ int gpuf_validate_mobile_tls_policy(const char *ca_cert_path,
 
 /**
  * Start remote worker and initialize global worker (C API)
+ *
+ * Returns `GpufError::InvalidArgument` for a missing or malformed argument
+ * and `GpufError::WorkerStart` when the login fails.
  */
-int start_remote_worker(const char *server_addr,
-                        int control_port,
-                        int proxy_port,
-                        const char *worker_type,
-                        const char *client_id);
+GpufError start_remote_worker(const char *server_addr,
+                              int control_port,
+                              int proxy_port,
+                              const char *worker_type,
+                              const char *client_id);
 
 /**
  * Start remote worker over TLS and initialize global worker (C API)
  *
  * This is additive: `start_remote_worker` keeps the legacy plaintext behavior.
- * Returns -2 when the TLS CA/SNI/SHA256 pin policy is invalid.
- */
-int start_remote_worker_with_tls(const char *server_addr,
-                                 int control_port,
-                                 int proxy_port,
-                                 const char *worker_type,
-                                 const char *client_id,
-                                 const char *ca_cert_path,
-                                 const char *control_tls_server_name,
-                                 const char *cert_sha256_pin);
-
-int start_remote_worker_with_tls(const char *_server_addr,
-                                 int _control_port,
-                                 int _proxy_port,
-                                 const char *_worker_type,
-                                 const char *_client_id,
-                                 const char *_ca_cert_path,
-                                 const char *_control_tls_server_name,
-                                 const char *_cert_sha256_pin);
-
-int start_remote_worker(const char *_server_addr,
-                        int _control_port,
-                        int _proxy_port,
-                        const char *_worker_type,
-                        const char *_client_id);
+ * Returns `GpufError::InvalidTlsPolicy` when the TLS CA/SNI/SHA256 pin policy
+ * is invalid.
+ */
+GpufError start_remote_worker_with_tls(const char *server_addr,
+                                       int control_port,
+                                       int proxy_port,
+                                       const char *worker_type,
+                                       const char *client_id,
+                                       const char *ca_cert_path,
+                                       const char *control_tls_server_name,
+                                       const char *cert_sha256_pin);
+
+GpufError start_remote_worker_with_tls(const char *_server_addr,
+                                       int _control_port,
+                                       int _proxy_port,
+                                       const char *_worker_type,
+                                       const char *_client_id,
+                                       const char *_ca_cert_path,
+                                       const char *_control_tls_server_name,
+                                       const char *_cert_sha256_pin);
+
+GpufError start_remote_worker(const char *_server_addr,
+                              int _control_port,
+                              int _proxy_port,
+                              const char *_worker_type,
+                              const char *_client_id);
+
+/**
+ * Human-readable text for a code returned by the C API, e.g. by
+ * `set_remote_worker_model` or `start_remote_worker`. The string is static
+ * and must not be freed.
+ */
+const char *gpuf_error_string(int code);
 
 /**
  * Set remote worker model (C API) - Safe Hot Swapping Version
@@ -665,11 +1223,11 @@ int start_remote_worker(const char *_server_addr,
  * - `model_path`: Path to the model file (.gguf)
  *
  * # Returns
- * - `0`: Success (model loaded and context created)
- * - `-1`: Backend initialization failed
- * - `-2`: Path conversion failed
- * - `-3`: Model loading failed
- * - `-4`: Context creation failed
+ * - `GpufError::Ok` (0): Success (model loaded and context created)
+ * - `GpufError::BackendInit` (-1): Backend initialization failed
+ * - `GpufError::PathConversion` (-2): Path conversion failed
+ * - `GpufError::ModelLoad` (-3): Model loading failed
+ * - `GpufError::ContextCreate` (-4): Context creation failed
  *
  * # Safety
  * Caller must ensure `model_path` is a valid null-terminated C string
@@ -679,23 +1237,36 @@ int start_remote_worker(const char *_server_addr,
  * Inference requests will be briefly paused during the swap but the worker
  * remains connected and continues processing afterward.
  */
-int set_remote_worker_model(const char *model_path);
+GpufError set_remote_worker_model(const char *model_path);
+
+GpufError set_remote_worker_model(const char *_model_path);
 
-int set_remote_worker_model(const char *_model_path);
+/**
+ * Free the remote worker's model and context, e.g. when the OS reports memory
+ * pressure, and tell the server so it stops routing requests for that model
+ * here.
+ *
+ * Running generations are cancelled first and the model is only freed once
+ * they have released it. `set_remote_worker_model` loads a model again.
+ * Returns `GpufError::Ok` when the model was freed and the server notified,
+ * `GpufError::ServerNotNotified` when it was freed but the server could not
+ * be reached and `GpufError::NoModelLoaded` if no model was loaded.
+ */
+GpufError gpuf_unload_model(void);
 
 /**
  * Start remote worker background tasks (C API)
  */
-int start_remote_worker_tasks(void);
+GpufError start_remote_worker_tasks(void);
 
-int start_remote_worker_tasks(void);
+GpufError start_remote_worker_tasks(void);
 
 /**
  * Start remote worker background tasks with callback support (C API)
  */
-int start_remote_worker_tasks_with_callback_ptr(void (*callback)(const char*, void*));
+GpufError start_remote_worker_tasks_with_callback_ptr(void (*callback)(const char*, void*));
 
-int start_remote_worker_tasks_with_callback_ptr(void (*_callback)(const char*, void*));
+GpufError start_remote_worker_tasks_with_callback_ptr(void (*_callback)(const char*, void*));
 
 /**
  * Register a status callback for remote worker background tasks (C API).
@@ -703,16 +1274,16 @@ int start_remote_worker_tasks_with_callback_ptr(void (*_callback)(const char*, v
  * This is the preferred iOS/Objective-C++ entry point because it keeps callback registration
  * separate from task startup and preserves a caller-provided `user_data` pointer.
  */
-int gpuf_register_remote_worker_callback(void (*callback)(const char*, void*), void *user_data);
+GpufError gpuf_register_remote_worker_callback(void (*callback)(const char*, void*), void *user_data);
 
-int gpuf_register_remote_worker_callback(void (*_callback)(const char*, void*), void *_user_data);
+GpufError gpuf_register_remote_worker_callback(void (*_callback)(const char*, void*), void *_user_data);
 
 /**
  * Stop remote worker and cleanup (C API)
  */
-int stop_remote_worker(void);
+GpufError stop_remote_worker(void);
 
-int stop_remote_worker(void);
+GpufError stop_remote_worker(void);
 
 /**
  * Get remote worker status (C API)
@@ -722,15 +1293,17 @@ int stop_remote_worker(void);
  * - `buffer_size`: Size of the output buffer
  *
  * # Returns
- * - `0`: Success (status written to buffer)
- * - `-1`: Error (buffer too small or other error)
+ * - `GpufError::Ok`: Success (status written to buffer)
+ * - `GpufError::InvalidArgument`: `buffer` is null or `buffer_size` is zero
+ * - `GpufError::BufferTooSmall`: The status does not fit in `buffer`
+ * - `GpufError::Internal`: The status could not be converted to a C string
  *
  * # Safety
  * Caller must ensure `buffer` is valid and can hold `buffer_size` bytes
  */
-int get_remote_worker_status(char *buffer, size_t buffer_size);
+GpufError get_remote_worker_status(char *buffer, size_t buffer_size);
 
-int get_remote_worker_status(char *buffer, size_t buffer_size);
+GpufError get_remote_worker_status(char *buffer, size_t buffer_size);
 
 extern const struct llama_model *llama_get_model(const struct llama_context *ctx);
 
@@ -16,14 +16,18 @@ typedef void (*gpuf_status_callback)(const char *message, void *user_data);
 struct llama_model;
 struct llama_context;
 struct gpuf_multimodal_model;
+struct gpuf_multimodal_info;
 
 int gpuf_init(void);
 int gpuf_cleanup(void);
 const char *gpuf_version(void);
 const char *gpuf_system_info(void);
+int gpuf_describe_worker(char *output, int output_len);
+const char *gpuf_error_string(int code);
 
 struct llama_model *gpuf_load_model(const char *model_path);
 struct llama_context *gpuf_create_context(struct llama_model *model);
+void gpuf_free_context(struct llama_context *ctx);
 
 int gpuf_generate_final_solution_text(
     const struct llama_model *model,
@@ -54,6 +58,8 @@ void llama_free(struct llama_context *context);
 
 int set_remote_worker_model(const char *model_path);
 
+int gpuf_unload_model(void);
+
 int start_remote_worker(
     const char *server_addr,
     int control_port,
@@ -90,10 +96,25 @@ struct gpuf_multimodal_model *gpuf_load_multimodal_model(
     const char *mmproj_path
 );
 
+struct gpuf_multimodal_model *gpuf_load_multimodal_model_with_image_tokens(
+    const char *text_model_path,
+    const char *mmproj_path,
+    int image_min_tokens,
+    int image_max_tokens
+);
+
+int gpuf_set_multimodal_n_ctx(int n_ctx);
+
 struct llama_context *gpuf_create_multimodal_context(
     struct gpuf_multimodal_model *multimodal_model
 );
 
+struct llama_context *gpuf_create_multimodal_context_with_params(
+    struct gpuf_multimodal_model *multimodal_model,
+    int n_ctx,
+    int n_batch
+);
+
 int gpuf_generate_multimodal(
     struct gpuf_multimodal_model *multimodal_model,
     struct llama_context *context,
@@ -112,6 +133,7 @@ int gpuf_generate_multimodal(
 void gpuf_free_multimodal_model(struct gpuf_multimodal_model *multimodal_model);
 bool gpuf_multimodal_supports_vision(struct gpuf_multimodal_model *multimodal_model);
 int gpuf_get_multimodal_info(struct gpuf_multimodal_model *multimodal_model, bool *has_vision);
+int gpuf_multimodal_model_info(struct gpuf_multimodal_model *multimodal_model, struct gpuf_multimodal_info *info);
 int gpuf_get_vision_tokens(
     struct gpuf_multimodal_model *multimodal_model,
     char *start_token,
@@ -17,7 +17,7 @@ use common::{ChatMessage, Command, CommandV1, Model, OsType, OutputPhase, System
 use std::ffi::{CStr, CString};
 
 #[cfg(target_os = "android")]
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Arc, Mutex, OnceLock};
 
 #[cfg(target_os = "android")]
@@ -82,6 +82,13 @@ fn command_label(command: &Command) -> &'static str {
             CommandV1::InferenceResult { .. } => "v1.inference_result",
             CommandV1::InferenceResultChunk { .. } => "v1.inference_result_chunk",
             CommandV1::ModelDownloadProgress { .. } => "v1.model_download_progress",
+            CommandV1::HeartbeatLite { .. } => "v1.heartbeat_lite",
+            CommandV1::Capabilities { .. } => "v1.capabilities",
+            CommandV1::DescribeWorker { .. } => "v1.describe_worker",
+            CommandV1::WorkerDescription { .. } => "v1.worker_description",
+            CommandV1::ContextWindow { .. } => "v1.context_window",
+            CommandV1::ModelInferenceTask { .. } => "v1.model_inference_task",
+            CommandV1::ServerRevision { .. } => "v1.server_revision",
         },
         Command::V2(_) => "v2.command",
     }
@@ -522,6 +529,24 @@ fn derive_model_id_from_path(model_path: &str) -> String {
         .to_string()
 }
 
+/// Models reported in `ModelStatus`: the loaded one, none after
+/// `gpuf_unload_model`.
+#[cfg(target_os = "android")]
+fn advertised_models() -> Vec<Model> {
+    let current_model_path = match crate::MODEL_STATUS.lock() {
+        Ok(status) if status.unloaded => return Vec::new(),
+        Ok(status) => status.current_model.clone(),
+        Err(_) => None,
+    }
+    .unwrap_or_else(|| "android".to_string());
+    vec![Model {
+        id: derive_model_id_from_path(&current_model_path),
+        object: "model".to_string(),
+        created: 0,
+        owned_by: "android".to_string(),
+    }]
+}
+
 /// Get real-time system usage information for heartbeat
 #[cfg(target_os = "android")]
 fn get_realtime_system_usage() -> (u32, u32, u32) {
@@ -731,6 +756,10 @@ pub static ANDROID_CONTROL_TLS: OnceLock<Mutex<MobileControlTlsConfig>> = OnceLo
 /// Global client_id storage for Android background tasks
 pub static ANDROID_CLIENT_ID: OnceLock<Mutex<Option<[u8; 16]>>> = OnceLock::new();
 
+/// `PROTOCOL_REVISION` the server advertised via `ServerRevision`; 1 until it does
+#[cfg(target_os = "android")]
+static ANDROID_SERVER_REVISION: AtomicU32 = AtomicU32::new(1);
+
 #[cfg(target_os = "android")]
 static ANDROID_ACTIVE_TASK_ID: OnceLock<Mutex<Option<String>>> = OnceLock::new();
 
@@ -816,8 +845,6 @@ pub async fn perform_android_login_with_tls(
         network_tx: 0,
     };
     // Create Login command (same structure as TCPWorker::login())
-    const CURRENT_VERSION: u32 = 1;
-
     // Calculate device metrics from actual device info
     let device_memtotal_gb = devices_info.memsize_gb.try_into().unwrap_or(0);
     let device_total_tflops = devices_info.total_tflops.into();
@@ -834,7 +861,7 @@ pub async fn perform_android_login_with_tls(
     }
 
     let login_cmd = CommandV1::Login {
-        version: CURRENT_VERSION,
+        version: common::PROTOCOL_REVISION,
         auto_models,
         os_type: OsType::ANDROID,
         client_id: hex::decode(client_id)
@@ -853,6 +880,8 @@ pub async fn perform_android_login_with_tls(
         .map_err(|e| anyhow!("Failed to send login command: {}", e))?;
 
     info!("✅ Android: Login command sent successfully");
+    // The context window follows once the server advertises its revision
+    ANDROID_SERVER_REVISION.store(1, Ordering::SeqCst);
 
     // Store TCP connection globally for background tasks
     let stream_arc = Arc::new(Mutex::new(stream));
@@ -920,6 +949,45 @@ fn write_v1_to_control_stream(stream: &Arc<Mutex<MobileControlStream>>, command:
     }
 }
 
+/// `ContextWindow` for the loaded model, which lets the server keep requests
+/// that cannot fit away from this device. `None` while the window is unknown or
+/// the server has not advertised a revision that knows the command.
+#[cfg(target_os = "android")]
+fn context_window_command(client_id: [u8; 16]) -> Option<Command> {
+    let n_ctx = crate::describe_worker().n_ctx;
+    let command = Command::V1(CommandV1::ContextWindow { client_id, n_ctx });
+    let server_revision = ANDROID_SERVER_REVISION.load(Ordering::SeqCst);
+    (n_ctx > 0 && command.supported_by(server_revision)).then_some(command)
+}
+
+/// Reports the advertised models and context window on the stored control
+/// connection, so the server sees a model change without waiting for the next
+/// heartbeat.
+#[cfg(target_os = "android")]
+pub fn send_model_status() -> Result<()> {
+    let stream = get_android_tcp_stream().ok_or_else(|| anyhow!("Not connected"))?;
+    let client_id = ANDROID_CLIENT_ID
+        .get()
+        .and_then(|m| m.lock().ok().and_then(|g| *g))
+        .ok_or_else(|| anyhow!("Not logged in"))?;
+    let model_status = CommandV1::ModelStatus {
+        client_id,
+        models: advertised_models(),
+        auto_models_device: Vec::new(),
+    };
+    // A new model may come with a different context window
+    let context_window = context_window_command(client_id);
+    let mut stream = stream
+        .lock()
+        .map_err(|_| anyhow!("Control stream mutex poisoned"))?;
+    common::write_command_sync(&mut *stream, &Command::V1(model_status))?;
+    if let Some(context_window) = context_window {
+        common::write_command_sync(&mut *stream, &context_window)?;
+    }
+    stream.flush()?;
+    Ok(())
+}
+
 /// Initialize global worker for Android
 #[cfg(target_os = "android")]
 pub async fn init_global_worker(args: Args) -> Result<()> {
@@ -927,7 +995,9 @@ pub async fn init_global_worker(args: Args) -> Result<()> {
 
     // Create new worker
     info!("📡 init_global_worker: About to call new_worker()...");
-    let worker = super::new_worker(args).await;
+    let worker = super::new_worker(args)
+        .await
+        .map_err(|e| anyhow!("Failed to create worker: {}", e))?;
     info!("✅ init_global_worker: new_worker() completed");
 
     // Login to server
@@ -1133,19 +1203,7 @@ pub async fn start_worker_tasks() -> Result<()> {
                                         .get()
                                         .and_then(|m| m.lock().ok().and_then(|g| *g))
                                         .unwrap_or([0u8; 16]);
-                                    let current_model_path = crate::MODEL_STATUS
-                                        .lock()
-                                        .ok()
-                                        .and_then(|s| s.current_model.clone())
-                                        .unwrap_or_else(|| "android".to_string());
-                                    let model_id = derive_model_id_from_path(&current_model_path);
-                                    println!("model_id: {}", model_id);
-                                    let models = vec![Model {
-                                        id: model_id,
-                                        object: "model".to_string(),
-                                        created: 0,
-                                        owned_by: "android".to_string(),
-                                    }];
+                                    let models = advertised_models();
                                     let model_status = CommandV1::ModelStatus {
                                         client_id,
                                         models,
@@ -1189,6 +1247,7 @@ pub async fn start_worker_tasks() -> Result<()> {
                                     }
                                 }
                             }
+                            // The SDK serves its one loaded model; the name is not checked
                             CommandV1::InferenceTask {
                                 task_id,
                                 prompt,
@@ -1199,6 +1258,16 @@ pub async fn start_worker_tasks() -> Result<()> {
                                 repeat_penalty,
                                 repeat_last_n: _,
                                 min_keep: _,
+                            }
+                            | CommandV1::ModelInferenceTask {
+                                task_id,
+                                prompt,
+                                max_tokens,
+                                temperature,
+                                top_k,
+                                top_p,
+                                repeat_penalty,
+                                ..
                             } => {
                                 println!("🔧 Android: Received inference task: {}", task_id);
                                 println!("📝 Android: Prompt received ({} bytes)", prompt.len());
@@ -1742,6 +1811,27 @@ pub async fn start_worker_tasks() -> Result<()> {
                                     }
                                 }
                             }
+                            CommandV1::ServerRevision { protocol_revision } => {
+                                ANDROID_SERVER_REVISION.store(protocol_revision, Ordering::SeqCst);
+                                let client_id = ANDROID_CLIENT_ID
+                                    .get()
+                                    .and_then(|m| m.lock().ok().and_then(|g| *g))
+                                    .unwrap_or([0u8; 16]);
+                                if let Some(context_window) = context_window_command(client_id) {
+                                    let _ =
+                                        common::write_command_sync(&mut *stream, &context_window);
+                                }
+                            }
+                            CommandV1::DescribeWorker { request_id } => {
+                                let description = CommandV1::WorkerDescription {
+                                    request_id,
+                                    description: crate::describe_worker(),
+                                };
+                                let _ = common::write_command_sync(
+                                    &mut *stream,
+                                    &Command::V1(description),
+                                );
+                            }
                             _ => {
                                 println!("⚠️ Android: Received unhandled command type");
                             }
@@ -1964,18 +2054,7 @@ pub async fn start_worker_tasks_with_callback_ptr(callback: Option<StatusCallbac
             } else {
                 println!("✅ Android: Heartbeat sent successfully");
                 {
-                    let current_model_path = crate::MODEL_STATUS
-                        .lock()
-                        .ok()
-                        .and_then(|s| s.current_model.clone())
-                        .unwrap_or_else(|| "android".to_string());
-                    let model_id = derive_model_id_from_path(&current_model_path);
-                    let models = vec![Model {
-                        id: model_id,
-                        object: "model".to_string(),
-                        created: 0,
-                        owned_by: "android".to_string(),
-                    }];
+                    let models = advertised_models();
                     let model_status = CommandV1::ModelStatus {
                         client_id,
                         models,
@@ -2100,20 +2179,7 @@ pub async fn start_worker_tasks_with_callback_ptr(callback: Option<StatusCallbac
                                             .get()
                                             .and_then(|m| m.lock().ok().and_then(|g| *g))
                                             .unwrap_or([0u8; 16]);
-                                        let current_model_path = crate::MODEL_STATUS
-                                            .lock()
-                                            .ok()
-                                            .and_then(|s| s.current_model.clone())
-                                            .unwrap_or_else(|| "android".to_string());
-                                        let model_id =
-                                            derive_model_id_from_path(&current_model_path);
-                                        println!("model_id: {}", model_id);
-                                        let models = vec![Model {
-                                            id: model_id,
-                                            object: "model".to_string(),
-                                            created: 0,
-                                            owned_by: "android".to_string(),
-                                        }];
+                                        let models = advertised_models();
                                         let model_status = CommandV1::ModelStatus {
                                             client_id,
                                             models,
@@ -2197,6 +2263,7 @@ pub async fn start_worker_tasks_with_callback_ptr(callback: Option<StatusCallbac
                                         }
                                     }
                                 }
+                                // The SDK serves its one loaded model
                                 CommandV1::InferenceTask {
                                     task_id,
                                     prompt,
@@ -2207,6 +2274,16 @@ pub async fn start_worker_tasks_with_callback_ptr(callback: Option<StatusCallbac
                                     repeat_penalty,
                                     repeat_last_n: _,
                                     min_keep: _,
+                                }
+                                | CommandV1::ModelInferenceTask {
+                                    task_id,
+                                    prompt,
+                                    max_tokens,
+                                    temperature,
+                                    top_k,
+                                    top_p,
+                                    repeat_penalty,
+                                    ..
                                 } => {
                                     println!("🔧 Android: Received inference task: {}", task_id);
                                     println!(
@@ -2874,6 +2951,31 @@ pub async fn start_worker_tasks_with_callback_ptr(callback: Option<StatusCallbac
                                         }
                                     }
                                 }
+                                CommandV1::ServerRevision { protocol_revision } => {
+                                    ANDROID_SERVER_REVISION
+                                        .store(protocol_revision, Ordering::SeqCst);
+                                    let client_id = ANDROID_CLIENT_ID
+                                        .get()
+                                        .and_then(|m| m.lock().ok().and_then(|g| *g))
+                                        .unwrap_or([0u8; 16]);
+                                    if let Some(context_window) = context_window_command(client_id)
+                                    {
+                                        let _ = common::write_command_sync(
+                                            &mut *stream,
+                                            &context_window,
+                                        );
+                                    }
+                                }
+                                CommandV1::DescribeWorker { request_id } => {
+                                    let description = CommandV1::WorkerDescription {
+                                        request_id,
+                                        description: crate::describe_worker(),
+                                    };
+                                    let _ = common::write_command_sync(
+                                        &mut *stream,
+                                        &Command::V1(description),
+                                    );
+                                }
                                 _ => {
                                     println!("⚠️ Android: Received unhandled command type");
                                     invoke_callback("WARNING", "Received unhandled command type");
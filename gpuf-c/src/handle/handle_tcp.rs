@@ -1,24 +1,31 @@
 use super::*;
+use crate::handle::handle_udp::P2PTransport as P2PDataTransport;
+use crate::handle::handle_udp::P2PUdpReassemblyState;
 #[cfg(not(target_os = "android"))]
-use crate::handle::handle_udp::{P2PReplayWindow, P2PUdpReassemblyState};
+use crate::handle::handle_udp::TurnTimeouts;
+#[cfg(not(target_os = "android"))]
+use crate::handle::handle_udp::{
+    DirectUdp, FramedPacket, PunchAcks, ReliableFramer, TurnIndication, TurnRefreshOutcome,
+    TurnTcp, TurnTransactions,
+};
 // LLM engine is not available in lightweight Android version
 #[cfg(not(target_os = "android"))]
 use crate::llm_engine::{self, llama_engine::LlamaEngine};
+use crate::util::generation::ControlTokenFilter;
 use crate::util::system_info::{
-    collect_device_info, collect_system_info, get_engine_models, pull_ollama_model,
+    collect_device_info, collect_system_info, device_summary, get_engine_models, pull_ollama_model,
 };
 use crate::util::{log_icon, security_metrics};
 use anyhow::{anyhow, Result};
 use common::{
-    format_bytes, format_duration, join_streams, read_command, write_command, Command, CommandV1,
-    CommandV2, DownloadStatus, EngineType as ClientEngineType, Model, OsType, OutputPhase,
-    P2PCandidate, P2PCandidateType, P2PConnectionType, P2PTransport, PodModel, SystemInfo,
-    MAX_MESSAGE_SIZE,
+    device_usage_delta, format_bytes, format_duration, join_streams, read_command, write_command,
+    Command, CommandV1, CommandV2, DownloadStatus, EngineType as ClientEngineType, Model, OsType,
+    OutputPhase, P2PCandidate, P2PCandidateType, P2PConnectionType, P2PTransport, PodModel,
+    SystemInfo, WorkerDescription, MAX_MESSAGE_SIZE,
 };
 use tokio::io::AsyncWriteExt;
 
-#[cfg(not(target_os = "android"))]
-use futures_util::StreamExt;
+use futures_util::{Stream, StreamExt};
 
 use bytes::BytesMut;
 use std::collections::HashMap;
@@ -29,7 +36,8 @@ use std::fs::File;
 use std::io::BufReader;
 use std::net::ToSocketAddrs;
 #[cfg(not(target_os = "android"))]
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::TcpStream;
@@ -47,6 +55,14 @@ use std::sync::OnceLock;
 #[cfg(not(target_os = "android"))]
 static GLOBAL_ENGINE: OnceLock<Arc<Mutex<Option<AnyEngine>>>> = OnceLock::new();
 
+/// Engine cache shared with the other worker transports of this process.
+#[cfg(not(target_os = "android"))]
+pub(super) fn global_engine() -> Arc<Mutex<Option<AnyEngine>>> {
+    GLOBAL_ENGINE
+        .get_or_init(|| Arc::new(Mutex::new(None)))
+        .clone()
+}
+
 #[cfg(not(target_os = "android"))]
 use tokio_rustls::{
     rustls::{
@@ -59,66 +75,9 @@ use tracing::{debug, error, info, warn};
 use url::Url;
 
 const DEFAULT_TURNS_PORT: u16 = 5349;
-
-// Filter internal GGUF control tokens from streaming output
-fn filter_control_tokens(text: &str) -> String {
-    let mut result = String::new();
-    let mut chars = text.chars().peekable();
-    let mut buffer = String::new();
-
-    while let Some(ch) = chars.next() {
-        buffer.push(ch);
-
-        // Check for any control token patterns
-        if buffer.contains("<|") {
-            // Skip until we find a safe point
-            while let Some(c) = chars.next() {
-                buffer.push(c);
-                if buffer.ends_with(">") {
-                    // Check if this was a control token
-                    if buffer.contains("<|channel|>")
-                        || buffer.contains("<|start|>")
-                        || buffer.contains("<|end|>")
-                        || buffer.contains("<|message|>")
-                    {
-                        buffer.clear();
-                        break;
-                    }
-                    // If it's not a recognized control token, keep it
-                    let safe_end = buffer.find('>').unwrap_or(buffer.len()) + 1;
-                    result.push_str(&buffer[..safe_end]);
-                    buffer.clear();
-                    break;
-                }
-                if buffer.len() > 50 {
-                    // Safety: prevent infinite growth
-                    buffer.clear();
-                    break;
-                }
-            }
-            continue;
-        }
-
-        // Flush safe content periodically
-        if buffer.len() > 20 {
-            let safe_end = buffer.find('<').unwrap_or(buffer.len());
-            if safe_end > 0 {
-                result.push_str(&buffer[..safe_end]);
-                buffer.drain(0..safe_end);
-            }
-        }
-    }
-
-    // Flush remaining buffer
-    result.push_str(&buffer);
-
-    // Final cleanup
-    result
-        .replace("<|end|>", "")
-        .replace("<|start|>", "")
-        .replace("<|channel|>", "")
-        .replace("<|message|>", "")
-}
+/// How long a model swap waits for cancelled generations to wind down.
+#[cfg(not(target_os = "android"))]
+const MODEL_SWAP_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Clone)]
 struct PhaseSplitter {
@@ -142,6 +101,14 @@ struct P2PConnectionRuntimeConfig {
     turn_password: String,
     _peer_hex: String,
     data_plane_secret: [u8; 32],
+    /// Where the data-plane socket is bound.
+    data_plane_addr: std::net::SocketAddr,
+    /// Data-plane socket the candidates were gathered on; hole punching
+    /// goes out from it so the peer's replies reach the serve loop.
+    #[cfg(not(target_os = "android"))]
+    socket: Arc<UdpSocket>,
+    #[cfg(not(target_os = "android"))]
+    punches: Arc<PunchAcks>,
 }
 
 impl PhaseSplitter {
@@ -388,117 +355,102 @@ fn derive_model_id_from_path(model_path: &str) -> String {
     base.to_string()
 }
 
-const CURRENT_VERSION: u32 = 1;
-
-impl ClientWorker {
-    /// Execute inference task using local LLM engine (Android specific)
-
-    async fn execute_inference_task(
-        &self,
-        prompt: &str,
-        max_tokens: u32,
-        temperature: f32,
-        top_k: u32,
-        top_p: f32,
-        repeat_penalty: f32,
-        repeat_last_n: i32,
-        min_keep: u32,
-    ) -> Result<String> {
-        #[cfg(target_os = "android")]
-        let _ = (
-            temperature,
-            top_k,
-            top_p,
-            repeat_penalty,
-            repeat_last_n,
-            min_keep,
-        );
-        #[cfg(not(target_os = "android"))]
-        {
-            let engine_guard = self.engine.lock().await;
-            let engine = engine_guard
-                .as_ref()
-                .ok_or_else(|| anyhow!("Engine not initialized"))?;
-
-            match engine {
-                AnyEngine::Llama(llama) => {
-                    let sampling = crate::llm_engine::llama_engine::SamplingParams {
-                        temperature: temperature,
-                        top_k: top_k as i32,
-                        top_p: top_p,
-                        repeat_penalty: repeat_penalty,
-                        repeat_last_n: repeat_last_n,
-                        seed: 0,
-                        min_keep: min_keep as usize,
-                        thinking_budget_tokens: None,
-                    };
-
-                    let (text, _prompt_tokens, _completion_tokens) = llama
-                        .generate_with_cached_model_sampling(prompt, max_tokens as usize, &sampling)
-                        .await?;
-                    Ok(text)
-                }
+/// Directory the worker downloads models into, next to the executable.
+fn downloaded_models_dir() -> std::path::PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(|p| p.to_path_buf()))
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+        .join("models")
+}
 
-                _ => Err(anyhow!(
-                    "execute_inference_task is only supported for LLAMA engine"
-                )),
-            }
+/// GGUF models directly under `dir` by model id. Split models are listed once,
+/// by their first shard, which is the file llama.cpp loads.
+fn on_disk_models(dir: &std::path::Path) -> Vec<(String, std::path::PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<std::path::PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "gguf"))
+        .collect();
+    // "-00001-of-" sorts before the other shards
+    files.sort();
+
+    let mut models: Vec<(String, std::path::PathBuf)> = Vec::new();
+    for path in files {
+        let id = derive_model_id_from_path(&path.to_string_lossy());
+        if !models.iter().any(|(known, _)| *known == id) {
+            models.push((id, path));
         }
+    }
+    models
+}
 
-        #[cfg(target_os = "android")]
-        {
-            use crate::{
-                gpuf_generate_final_solution_text, GLOBAL_CONTEXT_PTR, GLOBAL_INFERENCE_MUTEX,
-                GLOBAL_MODEL_PTR,
-            };
-            use std::ffi::CString;
-            use std::sync::atomic::Ordering;
-
-            // Acquire global inference lock to prevent concurrent execution
-            let _lock = GLOBAL_INFERENCE_MUTEX.lock().unwrap();
-
-            // Get global model and context pointers
-            let model_ptr = GLOBAL_MODEL_PTR.load(Ordering::SeqCst);
-            let context_ptr = GLOBAL_CONTEXT_PTR.load(Ordering::SeqCst);
+/// ChatML rendering of `messages`, ending with an open assistant turn.
+fn chatml_prompt(messages: &[common::ChatMessage]) -> String {
+    let mut prompt = String::new();
+    for msg in messages {
+        prompt.push_str(&format!(
+            "<|im_start|>{}\n{}<|im_end|>\n",
+            msg.role, msg.content
+        ));
+    }
+    prompt.push_str("<|im_start|>assistant\n");
+    prompt
+}
 
-            if model_ptr.is_null() || context_ptr.is_null() {
-                return Err(anyhow!("Model not loaded - please load a model first"));
+/// Chat prompt for models without a chat template, in the format named by
+/// the `CHAT_TEMPLATE` environment variable.
+fn chat_prompt_fallback(messages: &[common::ChatMessage]) -> String {
+    let template = std::env::var("CHAT_TEMPLATE").unwrap_or_else(|_| "simple".to_string());
+    match template.to_ascii_lowercase().as_str() {
+        "chatml" => chatml_prompt(messages),
+        "llama3" => {
+            let mut prompt = String::from("<|begin_of_text|>");
+            for msg in messages {
+                prompt.push_str(&format!(
+                    "<|start_header_id|>{}<|end_header_id|>\n\n{}<|eot_id|>",
+                    msg.role, msg.content
+                ));
             }
-
-            // Convert prompt to CString
-            let prompt_cstr = CString::new(prompt).map_err(|e| anyhow!("Invalid prompt: {}", e))?;
-
-            // Create output buffer
-            let mut output = vec![0u8; 4096];
-
-            // Execute inference using existing JNI function
-            // SAFETY: We're calling an FFI function with valid pointers:
-            // - model_ptr and context_ptr are checked for null above
-            // - prompt_cstr.as_ptr() is a valid C string pointer
-            // - output buffer is properly sized and mutable
-            let result = gpuf_generate_final_solution_text(
-                model_ptr,
-                context_ptr,
-                prompt_cstr.as_ptr(),
-                max_tokens as i32,
-                output.as_mut_ptr() as *mut std::os::raw::c_char,
-                output.len() as i32,
-            );
-
-            if result > 0 {
-                let output_str = unsafe {
-                    std::ffi::CStr::from_ptr(output.as_ptr() as *const std::os::raw::c_char)
-                        .to_str()
-                        .map_err(|e| anyhow!("Invalid UTF-8 in output: {}", e))?
+            prompt.push_str("<|start_header_id|>assistant<|end_header_id|>\n\n");
+            prompt
+        }
+        _ => {
+            let mut prompt = String::new();
+            for msg in messages {
+                let role = match msg.role.as_str() {
+                    "user" => "Human",
+                    "assistant" => "Assistant",
+                    _ => "System",
                 };
-                Ok(output_str.to_string())
-            } else {
-                Err(anyhow!("Inference failed with code: {}", result))
+                prompt.push_str(&format!("{}: {}\n\n", role, msg.content));
             }
+            prompt.push_str("Assistant: ");
+            prompt
         }
     }
+}
 
-    async fn stream_inference_task_to_server(
+/// State a generation needs besides the task itself, so every worker
+/// transport streams and cancels inference the same way. `send` delivers one
+/// `InferenceResultChunk` over the worker's control connection.
+pub(super) struct InferenceRelay<'a> {
+    #[cfg(not(target_os = "android"))]
+    pub engine: &'a Arc<Mutex<Option<AnyEngine>>>,
+    pub cancel_state: &'a Arc<CancelState>,
+    pub chunk_bytes: usize,
+}
+
+impl InferenceRelay<'_> {
+    /// Generates `prompt` with the loaded model and relays the output while it
+    /// is produced. Android runs the mobile generation loop through
+    /// `stream_loaded_model`.
+    ///
+    /// A failed generation ends the task with a done chunk carrying the error;
+    /// only a failing `send` is returned.
+    pub async fn stream_task<F, Fut>(
         &self,
         task_id: String,
         prompt: String,
@@ -509,203 +461,428 @@ impl ClientWorker {
         repeat_penalty: f32,
         repeat_last_n: i32,
         min_keep: u32,
-    ) -> Result<()> {
+        send: F,
+    ) -> Result<()>
+    where
+        F: FnMut(CommandV1) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
         #[cfg(not(target_os = "android"))]
         {
+            // Held while relaying so the model is not swapped mid-generation
             let engine_guard = self.engine.lock().await;
-            let engine = engine_guard
-                .as_ref()
-                .ok_or_else(|| anyhow!("Engine not initialized"))?;
-
-            let AnyEngine::Llama(llama) = engine else {
-                return Err(anyhow!(
-                    "stream_inference_task_to_server is only supported for LLAMA engine"
-                ));
-            };
+            let opened = async {
+                let engine = engine_guard
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Engine not initialized"))?;
 
-            let sampling = crate::llm_engine::llama_engine::SamplingParams {
-                temperature,
-                top_k: top_k as i32,
-                top_p,
-                repeat_penalty,
-                repeat_last_n,
-                seed: 0,
-                min_keep: min_keep as usize,
-                thinking_budget_tokens: None,
-            };
+                let AnyEngine::Llama(llama) = engine else {
+                    return Err(anyhow!(
+                        "Streaming inference is only supported for LLAMA engine"
+                    ));
+                };
 
-            let prompt_tokens: u32 = {
-                let prompt = prompt.clone();
-                let cached_model = llama
-                    .cached_model
-                    .as_ref()
-                    .ok_or_else(|| anyhow!("Model not loaded - call load_model() first"))?
-                    .clone();
+                let sampling = crate::llm_engine::llama_engine::SamplingParams {
+                    temperature,
+                    top_k: top_k as i32,
+                    top_p,
+                    repeat_penalty,
+                    repeat_last_n,
+                    seed: 0,
+                    min_keep: min_keep as usize,
+                    thinking_budget_tokens: None,
+                    ..Default::default()
+                };
 
-                tokio::task::spawn_blocking(move || {
-                    use llama_cpp_2::model::AddBos;
+                let prompt_tokens: u32 = {
+                    let prompt = prompt.clone();
+                    let cached_model = llama
+                        .cached_model
+                        .as_ref()
+                        .ok_or_else(|| anyhow!("Model not loaded - call load_model() first"))?
+                        .clone();
+
+                    tokio::task::spawn_blocking(move || {
+                        use llama_cpp_2::model::AddBos;
+
+                        let model_guard = cached_model
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock model: {:?}", e))?;
+
+                        let tokens = model_guard
+                            .str_to_token(&prompt, AddBos::Always)
+                            .map_err(|e| anyhow!("Failed to tokenize prompt: {:?}", e))?;
+                        Ok::<u32, anyhow::Error>(tokens.len().min(u32::MAX as usize) as u32)
+                    })
+                    .await??
+                };
 
-                    let model_guard = cached_model
-                        .lock()
-                        .map_err(|e| anyhow!("Failed to lock model: {:?}", e))?;
+                let stream = llama
+                    .stream_with_cached_model_sampling(&prompt, max_tokens as usize, &sampling)
+                    .await?;
+                Ok::<_, anyhow::Error>((prompt_tokens, stream))
+            }
+            .await;
 
-                    let tokens = model_guard
-                        .str_to_token(&prompt, AddBos::Always)
-                        .map_err(|e| anyhow!("Failed to tokenize prompt: {:?}", e))?;
-                    Ok::<u32, anyhow::Error>(tokens.len().min(u32::MAX as usize) as u32)
-                })
-                .await??
-            };
+            match opened {
+                Ok((prompt_tokens, stream)) => {
+                    self.relay(task_id, prompt_tokens, stream, send).await
+                }
+                Err(e) => self.fail_task(task_id, &e, send).await,
+            }
+        }
 
-            let stream = llama
-                .stream_with_cached_model_sampling(&prompt, max_tokens as usize, &sampling)
-                .await?;
+        #[cfg(target_os = "android")]
+        {
+            // The mobile loop applies its own repetition detection
+            let _ = (repeat_last_n, min_keep);
+
+            let opened = async {
+                let prompt_tokens = Self::count_prompt_tokens(prompt.clone()).await?;
+                let stream = crate::stream_loaded_model(
+                    &prompt,
+                    max_tokens as i32,
+                    temperature,
+                    top_k as i32,
+                    top_p,
+                    repeat_penalty,
+                )?;
+                Ok::<_, anyhow::Error>((prompt_tokens, stream))
+            }
+            .await;
+            match opened {
+                Ok((prompt_tokens, stream)) => {
+                    self.relay(task_id, prompt_tokens, stream, send).await
+                }
+                Err(e) => self.fail_task(task_id, &e, send).await,
+            }
+        }
+    }
 
-            let mut stream = Box::pin(stream);
+    /// Tokenizes `prompt` with the loaded model under `GLOBAL_INFERENCE_MUTEX`,
+    /// so a model swap cannot free the context in the middle. Waits on a
+    /// blocking thread while another generation holds the lock.
+    #[cfg(target_os = "android")]
+    async fn count_prompt_tokens(prompt: String) -> Result<u32> {
+        tokio::task::spawn_blocking(move || {
+            let _inference_lock = crate::GLOBAL_INFERENCE_MUTEX
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let ctx = crate::GLOBAL_CONTEXT_PTR.load(Ordering::SeqCst);
+            if ctx.is_null() {
+                return Err(anyhow!("Model not loaded - please load a model first"));
+            }
+            Ok(crate::tokenize(ctx, &prompt, true)?
+                .len()
+                .min(u32::MAX as usize) as u32)
+        })
+        .await?
+    }
 
-            let max_bytes: usize = self.args.stream_chunk_bytes.max(1);
-            let mut seq: u32 = 0;
-            let mut buf = String::new();
-            let mut buf_phase: OutputPhase = OutputPhase::Unknown;
-            let mut completion_tokens: u32 = 0;
-            let mut analysis_tokens: u32 = 0;
-            let mut final_tokens: u32 = 0;
-            let mut splitter = PhaseSplitter::default();
+    /// Relays generated pieces as `InferenceResultChunk`s of up to
+    /// `chunk_bytes`, split by output phase, and finishes with a done chunk.
+    /// Stops early when the task is cancelled. A stream error ends the task
+    /// with that done chunk carrying the error, so the server sees one
+    /// terminal chunk with the next `seq`.
+    pub async fn relay<S, F, Fut>(
+        &self,
+        task_id: String,
+        prompt_tokens: u32,
+        stream: S,
+        mut send: F,
+    ) -> Result<()>
+    where
+        S: Stream<Item = Result<String>>,
+        F: FnMut(CommandV1) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let mut stream = Box::pin(stream);
+
+        let max_bytes: usize = self.chunk_bytes.max(1);
+        let mut seq: u32 = 0;
+        let mut buf = String::new();
+        let mut buf_phase: OutputPhase = OutputPhase::Unknown;
+        let mut completion_tokens: u32 = 0;
+        let mut analysis_tokens: u32 = 0;
+        let mut final_tokens: u32 = 0;
+        let mut splitter = PhaseSplitter::default();
+        let mut control_filter = ControlTokenFilter::from_config();
+
+        let mut cancelled_early = false;
+        let mut failure = None;
+        let _active = self.cancel_state.register(&task_id);
+        loop {
+            if self.cancel_state.is_cancelled(&task_id).await {
+                cancelled_early = true;
+                debug!(task_id = %task_id, "Cancellation observed in stream loop");
+                break;
+            }
 
-            let mut cancelled_early = false;
-            loop {
-                {
-                    let cancelled = self.cancel_state.cancelled.lock().await;
-                    if cancelled.contains(&task_id) {
+            tokio::select! {
+                _ = self.cancel_state.notify.notified() => {
+                    if self.cancel_state.is_cancelled(&task_id).await {
                         cancelled_early = true;
-                        debug!(task_id = %task_id, "Cancellation observed in stream loop");
+                        debug!(task_id = %task_id, "Cancellation notified during streaming");
                         break;
                     }
                 }
-
-                tokio::select! {
-                    _ = self.cancel_state.notify.notified() => {
-                        let cancelled = self.cancel_state.cancelled.lock().await;
-                        if cancelled.contains(&task_id) {
-                            cancelled_early = true;
-                            debug!(task_id = %task_id, "Cancellation notified during streaming");
+                piece_res = stream.next() => {
+                    let Some(piece_res) = piece_res else {
+                        break;
+                    };
+                    let piece = match piece_res {
+                        Ok(piece) => piece,
+                        Err(e) => {
+                            warn!(task_id = %task_id, "Generation failed: {}", e);
+                            failure = Some(e.to_string());
                             break;
                         }
-                    }
-                    piece_res = stream.next() => {
-                        let Some(piece_res) = piece_res else {
-                            break;
-                        };
-                        let piece = piece_res?;
-                        let filtered = filter_control_tokens(&piece);
-                        // Each streamed `piece` corresponds to (at most) one generated token.
-                        // Never count bytes/chars here, otherwise completion_tokens can greatly exceed max_tokens.
-                        completion_tokens = completion_tokens.saturating_add(1);
-
-                        let segs = splitter.push(&filtered);
-                        for (phase, seg) in segs {
-                            if seg.is_empty() {
-                                continue;
+                    };
+                    let filtered = control_filter.push(&piece);
+                    // Each streamed `piece` corresponds to (at most) one generated token.
+                    // Never count bytes/chars here, otherwise completion_tokens can greatly exceed max_tokens.
+                    completion_tokens = completion_tokens.saturating_add(1);
+
+                    let segs = splitter.push(&filtered);
+                    for (phase, seg) in segs {
+                        if seg.is_empty() {
+                            continue;
+                        }
+                        match phase {
+                            OutputPhase::Analysis => {
+                                analysis_tokens = analysis_tokens.saturating_add(1);
                             }
-                            match phase {
-                                OutputPhase::Analysis => {
-                                    analysis_tokens = analysis_tokens.saturating_add(1);
-                                }
-                                OutputPhase::Final => {
-                                    final_tokens = final_tokens.saturating_add(1);
-                                }
-                                OutputPhase::Unknown => {}
+                            OutputPhase::Final => {
+                                final_tokens = final_tokens.saturating_add(1);
                             }
+                            OutputPhase::Unknown => {}
+                        }
 
-                            if buf.is_empty() {
-                                buf_phase = phase;
-                            } else if buf_phase != phase {
-                                let delta = std::mem::take(&mut buf);
-                                let chunk = CommandV1::InferenceResultChunk {
-                                    task_id: task_id.clone(),
-                                    seq,
-                                    delta,
-                                    phase: buf_phase,
-                                    done: false,
-                                    error: None,
-                                    prompt_tokens,
-                                    completion_tokens,
-                                    analysis_tokens,
-                                    final_tokens,
-                                };
-                                self.send_command(chunk).await?;
-                                seq = seq.wrapping_add(1);
-                                buf_phase = phase;
-                            }
+                        if buf.is_empty() {
+                            buf_phase = phase;
+                        } else if buf_phase != phase {
+                            let delta = std::mem::take(&mut buf);
+                            let chunk = CommandV1::InferenceResultChunk {
+                                task_id: task_id.clone(),
+                                seq,
+                                delta,
+                                phase: buf_phase,
+                                done: false,
+                                error: None,
+                                prompt_tokens,
+                                completion_tokens,
+                                analysis_tokens,
+                                final_tokens,
+                            };
+                            send(chunk).await?;
+                            seq = seq.wrapping_add(1);
+                            buf_phase = phase;
+                        }
 
-                            buf.push_str(&seg);
-                            if buf.len() >= max_bytes {
-                                let delta = std::mem::take(&mut buf);
-                                let chunk = CommandV1::InferenceResultChunk {
-                                    task_id: task_id.clone(),
-                                    seq,
-                                    delta,
-                                    phase: buf_phase,
-                                    done: false,
-                                    error: None,
-                                    prompt_tokens,
-                                    completion_tokens,
-                                    analysis_tokens,
-                                    final_tokens,
-                                };
-                                self.send_command(chunk).await?;
-                                seq = seq.wrapping_add(1);
-                            }
+                        buf.push_str(&seg);
+                        if buf.len() >= max_bytes {
+                            let delta = std::mem::take(&mut buf);
+                            let chunk = CommandV1::InferenceResultChunk {
+                                task_id: task_id.clone(),
+                                seq,
+                                delta,
+                                phase: buf_phase,
+                                done: false,
+                                error: None,
+                                prompt_tokens,
+                                completion_tokens,
+                                analysis_tokens,
+                                final_tokens,
+                            };
+                            send(chunk).await?;
+                            seq = seq.wrapping_add(1);
                         }
                     }
                 }
             }
+        }
 
-            if !buf.is_empty() {
-                let chunk = CommandV1::InferenceResultChunk {
-                    task_id: task_id.clone(),
-                    seq,
-                    delta: buf,
-                    phase: buf_phase,
-                    done: false,
-                    error: None,
-                    prompt_tokens,
-                    completion_tokens,
-                    analysis_tokens,
-                    final_tokens,
-                };
-                self.send_command(chunk).await?;
-                seq = seq.wrapping_add(1);
-            }
-
-            let done_chunk = CommandV1::InferenceResultChunk {
+        // An unfinished `<|...` at the end of output is plain text
+        buf.push_str(&control_filter.finish());
+        if !buf.is_empty() {
+            let chunk = CommandV1::InferenceResultChunk {
                 task_id: task_id.clone(),
                 seq,
-                delta: String::new(),
-                phase: splitter.phase(),
-                done: true,
+                delta: buf,
+                phase: buf_phase,
+                done: false,
                 error: None,
                 prompt_tokens,
                 completion_tokens,
                 analysis_tokens,
                 final_tokens,
             };
-            self.send_command(done_chunk).await?;
+            send(chunk).await?;
+            seq = seq.wrapping_add(1);
+        }
 
-            if cancelled_early {
-                debug!(task_id = %task_id, "Sent done chunk after cancellation");
-            }
+        let done_chunk = CommandV1::InferenceResultChunk {
+            task_id: task_id.clone(),
+            seq,
+            delta: String::new(),
+            phase: splitter.phase(),
+            done: true,
+            error: failure,
+            prompt_tokens,
+            completion_tokens,
+            analysis_tokens,
+            final_tokens,
+        };
+        send(done_chunk).await?;
 
-            {
-                let mut cancelled = self.cancel_state.cancelled.lock().await;
-                cancelled.remove(&task_id);
-            }
-            return Ok(());
+        if cancelled_early {
+            debug!(task_id = %task_id, "Sent done chunk after cancellation");
         }
 
+        self.cancel_state.clear(&task_id).await;
+        Ok(())
+    }
+
+    /// Ends a task that failed before producing any output with a single done
+    /// chunk carrying the error.
+    pub async fn fail_task<F, Fut>(
+        &self,
+        task_id: String,
+        error: &anyhow::Error,
+        mut send: F,
+    ) -> Result<()>
+    where
+        F: FnMut(CommandV1) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        error!(task_id = %task_id, "Inference task failed: {}", error);
+        send(CommandV1::InferenceResultChunk {
+            task_id,
+            seq: 0,
+            delta: String::new(),
+            phase: OutputPhase::Unknown,
+            done: true,
+            completion_tokens: 0,
+            prompt_tokens: 0,
+            error: Some(error.to_string()),
+            analysis_tokens: 0,
+            final_tokens: 0,
+        })
+        .await
+    }
+
+    /// Renders chat `messages` with the loaded model's template, or with
+    /// `chat_prompt_fallback` when the model has none. A template llama.cpp
+    /// cannot apply falls back to ChatML, the format most chat models were
+    /// tuned on.
+    pub async fn chat_prompt(&self, messages: &[common::ChatMessage]) -> Result<String> {
         #[cfg(target_os = "android")]
         {
-            let _ = (
+            let messages = messages.to_vec();
+            tokio::task::spawn_blocking(move || {
+                // The model must not be swapped out while its template is read
+                let _inference_lock = crate::GLOBAL_INFERENCE_MUTEX
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                let model = crate::GLOBAL_MODEL_PTR.load(Ordering::SeqCst);
+                if !crate::model_has_chat_template(model) {
+                    return chat_prompt_fallback(&messages);
+                }
+                crate::apply_chat_template(model, &messages, true).unwrap_or_else(|e| {
+                    warn!("Chat template failed, falling back to ChatML: {}", e);
+                    chatml_prompt(&messages)
+                })
+            })
+            .await
+            .map_err(Into::into)
+        }
+
+        #[cfg(not(target_os = "android"))]
+        {
+            let cached_model = {
+                let engine_guard = self.engine.lock().await;
+                let engine = engine_guard
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Engine not initialized"))?;
+
+                let AnyEngine::Llama(llama) = engine else {
+                    return Err(anyhow!(
+                        "ChatInferenceTask is only supported for LLAMA engine"
+                    ));
+                };
+
+                llama
+                    .cached_model
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Model not loaded - call load_model() first"))?
+                    .clone()
+            };
+
+            let messages_for_template = messages.to_vec();
+            let rendered =
+                tokio::task::spawn_blocking(move || -> anyhow::Result<Option<String>> {
+                    use llama_cpp_2::model::LlamaChatMessage;
+
+                    let model_guard = cached_model
+                        .lock()
+                        .map_err(|e| anyhow!("Failed to lock model: {:?}", e))?;
+
+                    let Ok(tmpl) = model_guard.chat_template(None) else {
+                        return Ok(None);
+                    };
+
+                    let mut chat = Vec::with_capacity(messages_for_template.len());
+                    for m in messages_for_template {
+                        let msg = LlamaChatMessage::new(m.role, m.content)
+                            .map_err(|e| anyhow!("Failed to build chat message: {:?}", e))?;
+                        chat.push(msg);
+                    }
+
+                    model_guard
+                        .apply_chat_template(&tmpl, &chat, true)
+                        .map(Some)
+                        .map_err(|e| anyhow!("Failed to apply chat template: {:?}", e))
+                })
+                .await;
+            match rendered {
+                Ok(Ok(Some(prompt))) => Ok(prompt),
+                Ok(Ok(None)) => Ok(chat_prompt_fallback(messages)),
+                Ok(Err(e)) => {
+                    warn!("Chat template failed, falling back to ChatML: {}", e);
+                    Ok(chatml_prompt(messages))
+                }
+                Err(e) => Err(e.into()),
+            }
+        }
+    }
+}
+
+impl ClientWorker {
+    fn inference_relay(&self) -> InferenceRelay<'_> {
+        InferenceRelay {
+            #[cfg(not(target_os = "android"))]
+            engine: &self.engine,
+            cancel_state: &self.cancel_state,
+            chunk_bytes: self.args.stream_chunk_bytes,
+        }
+    }
+
+    /// Generates `prompt` with the loaded model and relays the output to the
+    /// server while it is produced.
+    async fn stream_inference_task_to_server(
+        &self,
+        task_id: String,
+        prompt: String,
+        max_tokens: u32,
+        temperature: f32,
+        top_k: u32,
+        top_p: f32,
+        repeat_penalty: f32,
+        repeat_last_n: i32,
+        min_keep: u32,
+    ) -> Result<()> {
+        self.inference_relay()
+            .stream_task(
                 task_id,
                 prompt,
                 max_tokens,
@@ -715,47 +892,143 @@ impl ClientWorker {
                 repeat_penalty,
                 repeat_last_n,
                 min_keep,
-            );
-            Err(anyhow!("Android streaming is not implemented"))
-        }
+                |chunk| self.send_command(chunk),
+            )
+            .await
     }
 
-    fn build_chat_prompt_fallback(&self, messages: &[common::ChatMessage]) -> String {
-        let template = std::env::var("CHAT_TEMPLATE").unwrap_or_else(|_| "simple".to_string());
-        match template.to_ascii_lowercase().as_str() {
-            "chatml" => {
-                let mut prompt = String::new();
-                for msg in messages {
-                    prompt.push_str(&format!("{}\n{}\n", msg.role, msg.content));
-                }
-                prompt.push_str("\nassistant\n");
-                prompt
-            }
-            "llama3" => {
-                let mut prompt = String::from("<|begin_of_text|>");
-                for msg in messages {
-                    prompt.push_str(&format!(
-                        "<|start_header_id|>{}\n\n{}\n<|eot_id|>",
-                        msg.role, msg.content
-                    ));
-                }
-                prompt.push_str("<|start_header_id|>assistant\n\n");
-                prompt
+    /// Loads `model_id` from disk when a task asks for a model other than the
+    /// one in use, within `--model-load-timeout-secs`. Models that are not on
+    /// disk are left to the current model, as before.
+    ///
+    /// Generations on the current model are cancelled first. A load that runs
+    /// past the budget fails the task but is left to finish in the background,
+    /// so the engine is never left half-swapped and later tasks can use it.
+    #[cfg(not(target_os = "android"))]
+    async fn load_model_on_demand(&self, model_id: &str) -> Result<()> {
+        if model_id.is_empty() {
+            return Ok(());
+        }
+        let current_model = crate::MODEL_STATUS
+            .lock()
+            .ok()
+            .and_then(|status| status.current_model.clone());
+        if current_model.is_some_and(|path| derive_model_id_from_path(&path) == model_id) {
+            return Ok(());
+        }
+        let Some((_, path)) = on_disk_models(&downloaded_models_dir())
+            .into_iter()
+            .find(|(id, _)| id == model_id)
+        else {
+            return Ok(());
+        };
+        let model_path = path.to_string_lossy().to_string();
+
+        let budget = Duration::from_secs(self.args.model_load_timeout_secs);
+        let started = std::time::Instant::now();
+        self.drain_generations().await;
+        let engine = Arc::clone(&self.engine);
+        let load = tokio::spawn(async move {
+            let mut engine_guard = engine.lock().await;
+            let engine = engine_guard
+                .as_mut()
+                .ok_or_else(|| anyhow!("Engine not initialized"))?;
+            engine.set_models(vec![model_path.clone()]).await?;
+            if let Ok(mut status) = crate::MODEL_STATUS.lock() {
+                status.set_loaded(&model_path);
             }
-            _ => {
-                let mut prompt = String::new();
-                for msg in messages {
-                    let role = match msg.role.as_str() {
-                        "user" => "Human",
-                        "assistant" => "Assistant",
-                        _ => "System",
-                    };
-                    prompt.push_str(&format!("{}: {}\n\n", role, msg.content));
-                }
-                prompt.push_str("Assistant: ");
-                prompt
+            Ok::<(), anyhow::Error>(())
+        });
+        match timeout(budget, load).await {
+            Ok(Ok(Ok(()))) => {}
+            Ok(Ok(Err(e))) => return Err(anyhow!("Failed to load model '{}': {}", model_id, e)),
+            Ok(Err(e)) => return Err(anyhow!("Loading model '{}' panicked: {}", model_id, e)),
+            Err(_) => {
+                return Err(anyhow!(
+                    "Timed out loading model '{}' on demand after {}s; the load continues in the background",
+                    model_id,
+                    budget.as_secs()
+                ))
             }
         }
+
+        info!(
+            "Model {} loaded on demand in {} ms",
+            model_id,
+            started.elapsed().as_millis()
+        );
+        self.report_context_window().await
+    }
+
+    /// Stops generations against the current model before it is replaced and
+    /// waits, up to `MODEL_SWAP_DRAIN_TIMEOUT`, for them to end.
+    #[cfg(not(target_os = "android"))]
+    async fn drain_generations(&self) {
+        if self.cancel_state.cancel_all().await == 0 {
+            return;
+        }
+        if !self.cancel_state.wait_idle(MODEL_SWAP_DRAIN_TIMEOUT).await {
+            warn!(
+                "Generations still running {}s after cancellation; swapping model anyway",
+                MODEL_SWAP_DRAIN_TIMEOUT.as_secs()
+            );
+        }
+    }
+
+    /// Tells the server the current context window so it keeps requests that
+    /// cannot fit away from us. Sent once the server advertises a revision
+    /// that knows it, and again after every model load.
+    async fn report_context_window(&self) -> Result<()> {
+        let n_ctx = self.describe().await.n_ctx;
+        if n_ctx == 0 {
+            return Ok(());
+        }
+        let context_window = Command::V1(CommandV1::ContextWindow {
+            client_id: self.client_id,
+            n_ctx,
+        });
+        let server_revision = self.server_revision.load(Ordering::SeqCst);
+        if !context_window.supported_by(server_revision) {
+            debug!(
+                "Server revision {} predates ContextWindow; not reporting it",
+                server_revision
+            );
+            return Ok(());
+        }
+        write_command(&mut *self.writer.lock().await, &context_window).await?;
+        info!("Reported context window of {} tokens", n_ctx);
+        Ok(())
+    }
+
+    /// Answer to the server's `DescribeWorker`: the running engine's model and
+    /// context settings. Android reports the FFI runtime's state.
+    async fn describe(&self) -> WorkerDescription {
+        #[cfg(target_os = "android")]
+        let mut description = crate::describe_worker();
+
+        #[cfg(not(target_os = "android"))]
+        let mut description = {
+            let mut description = WorkerDescription {
+                version: crate::GPUF_VERSION.to_string(),
+                protocol_revision: common::PROTOCOL_REVISION,
+                os: std::env::consts::OS.to_string(),
+                engine: self.engine_type.to_string(),
+                ..Default::default()
+            };
+            if let Some(AnyEngine::Llama(llama)) = self.engine.lock().await.as_ref() {
+                description.model = llama
+                    .cached_model_path
+                    .clone()
+                    .or_else(|| llama.model_path.clone());
+                description.n_ctx = llama.n_ctx;
+                description.n_batch = llama.n_batch;
+                description.n_gpu_layers = Some(llama.n_gpu_layers);
+            }
+            description
+        };
+
+        description.device_summary = device_summary(&self.devices_info);
+        description
     }
 
     /// Send command to server
@@ -782,15 +1055,18 @@ impl ClientWorker {
         Ok(())
     }
 
+    /// Host and port of a `turns://` URL. IPv6 hosts are returned without
+    /// brackets, ready for `(host, port)` addresses and TLS server names.
     fn parse_turns_url(url: &str) -> Result<(String, u16)> {
         let parsed = Url::parse(url).map_err(|e| anyhow!("Invalid TURN url: {e}"))?;
         if parsed.scheme() != "turns" {
             return Err(anyhow!("TURN url must be turns://"));
         }
-        let host = parsed
-            .host_str()
-            .ok_or_else(|| anyhow!("TURN url missing host"))?
-            .to_string();
+        let host = match parsed.host() {
+            Some(url::Host::Ipv6(addr)) => addr.to_string(),
+            Some(host) => host.to_string(),
+            None => return Err(anyhow!("TURN url missing host")),
+        };
         let port = parsed.port().unwrap_or(DEFAULT_TURNS_PORT);
         Ok((host, port))
     }
@@ -800,9 +1076,11 @@ impl ClientWorker {
         host: &str,
         port: u16,
         cert_chain_path: &str,
+        op_timeout: Duration,
     ) -> Result<tokio_rustls::client::TlsStream<TcpStream>> {
-        let addr = format!("{}:{}", host, port);
-        let stream = TcpStream::connect(&addr).await?;
+        let stream = timeout(op_timeout, TcpStream::connect((host, port)))
+            .await
+            .map_err(|_| anyhow!("TURN TCP connect timed out"))??;
 
         let certs = load_root_cert(cert_chain_path)?;
         let mut roots = RootCertStore::empty();
@@ -814,7 +1092,21 @@ impl ClientWorker {
         let connector = TlsConnector::from(Arc::new(config));
         let server_name =
             ServerName::try_from(host.to_string()).map_err(|_| anyhow!("Invalid SNI name"))?;
-        Ok(connector.connect(server_name, stream).await?)
+        timeout(op_timeout, connector.connect(server_name, stream))
+            .await
+            .map_err(|_| anyhow!("TURN TLS handshake timed out"))?
+            .map_err(Into::into)
+    }
+
+    /// Next STUN message on a TURN TLS stream, waiting at most `op_timeout`.
+    #[cfg(not(target_os = "android"))]
+    async fn turn_read_response<S: tokio::io::AsyncRead + Unpin>(
+        stream: &mut S,
+        op_timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        timeout(op_timeout, Self::stun_read_message(stream))
+            .await
+            .map_err(|_| anyhow!("TURN response timed out"))?
     }
 
     #[cfg(not(target_os = "android"))]
@@ -823,6 +1115,7 @@ impl ClientWorker {
         username: &str,
         password: &str,
         cert_chain_path: &str,
+        op_timeout: Duration,
     ) -> Result<(
         tokio_rustls::client::TlsStream<TcpStream>,
         std::net::SocketAddr,
@@ -830,7 +1123,7 @@ impl ClientWorker {
         String,
     )> {
         let (host, port) = Self::parse_turns_url(turn_url)?;
-        let mut tls = Self::turn_tls_connect(&host, port, cert_chain_path).await?;
+        let mut tls = Self::turn_tls_connect(&host, port, cert_chain_path, op_timeout).await?;
 
         let requested_transport_t: u16 = 0x0019;
         let lifetime_t: u16 = 0x000d;
@@ -844,7 +1137,7 @@ impl ClientWorker {
         tls.write_all(&req).await?;
         tls.flush().await?;
 
-        let resp = Self::stun_read_message(&mut tls).await?;
+        let resp = Self::turn_read_response(&mut tls, op_timeout).await?;
         let msg_type = u16::from_be_bytes([resp[0], resp[1]]);
         if msg_type != 0x0113 {
             return Err(anyhow!(
@@ -879,7 +1172,7 @@ impl ClientWorker {
         tls.write_all(&req2).await?;
         tls.flush().await?;
 
-        let resp2 = Self::stun_read_message(&mut tls).await?;
+        let resp2 = Self::turn_read_response(&mut tls, op_timeout).await?;
         let msg_type2 = u16::from_be_bytes([resp2[0], resp2[1]]);
         if msg_type2 != 0x0103 {
             return Err(anyhow!("TURN Allocate failed type=0x{:04x}", msg_type2));
@@ -902,6 +1195,7 @@ impl ClientWorker {
         password: &str,
         realm: &str,
         nonce: &str,
+        op_timeout: Duration,
     ) -> Result<Vec<u8>> {
         let username_t: u16 = 0x0006;
         let realm_t: u16 = 0x0014;
@@ -927,7 +1221,7 @@ impl ClientWorker {
         tls.write_all(&req).await?;
         tls.flush().await?;
 
-        let resp = Self::stun_read_message(&mut *tls).await?;
+        let resp = Self::turn_read_response(&mut *tls, op_timeout).await?;
         let msg_type = u16::from_be_bytes([resp[0], resp[1]]);
         if msg_type != 0x010a {
             return Err(anyhow!("TURN Connect failed (type=0x{:04x})", msg_type));
@@ -950,9 +1244,10 @@ impl ClientWorker {
         realm: &str,
         nonce: &str,
         cert_chain_path: &str,
+        op_timeout: Duration,
     ) -> Result<tokio_rustls::client::TlsStream<TcpStream>> {
         let (host, port) = Self::parse_turns_url(turn_url)?;
-        let mut tls = Self::turn_tls_connect(&host, port, cert_chain_path).await?;
+        let mut tls = Self::turn_tls_connect(&host, port, cert_chain_path, op_timeout).await?;
 
         let username_t: u16 = 0x0006;
         let realm_t: u16 = 0x0014;
@@ -975,7 +1270,7 @@ impl ClientWorker {
 
         tls.write_all(&req).await?;
         tls.flush().await?;
-        let resp = Self::stun_read_message(&mut tls).await?;
+        let resp = Self::turn_read_response(&mut tls, op_timeout).await?;
         let msg_type = u16::from_be_bytes([resp[0], resp[1]]);
         if msg_type != 0x010b {
             return Err(anyhow!(
@@ -986,6 +1281,121 @@ impl ClientWorker {
         Ok(tls)
     }
 
+    /// Refresh over a TURN TCP control connection, as `turn_refresh` does
+    /// for UDP. The control connection carries nothing else here, so other
+    /// messages are skipped.
+    #[cfg(not(target_os = "android"))]
+    async fn turn_refresh_tcp<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(
+        stream: &mut S,
+        lifetime: u32,
+        username: &str,
+        password: &str,
+        realm: &str,
+        nonce: &mut String,
+        op_timeout: Duration,
+    ) -> Result<u32> {
+        let key = Self::stun_long_term_key(username, realm, password);
+        for _ in 0..2 {
+            let txid = Self::stun_new_txid();
+            let req = Self::turn_refresh_request(txid, lifetime, username, password, realm, nonce);
+            stream.write_all(&req).await?;
+            stream.flush().await?;
+
+            let resp = loop {
+                let msg = Self::turn_read_response(&mut *stream, op_timeout).await?;
+                if msg[8..20] == txid {
+                    break msg;
+                }
+            };
+            Self::stun_verify_response(&resp, Some(&key))?;
+            match Self::turn_refresh_outcome(&resp)? {
+                TurnRefreshOutcome::Refreshed { lifetime } => return Ok(lifetime),
+                TurnRefreshOutcome::StaleNonce { nonce: fresh } => *nonce = fresh,
+            }
+        }
+        Err(anyhow!("TURN Refresh kept reporting a stale nonce"))
+    }
+
+    /// Refreshes the allocation behind a TURN TCP control connection at half
+    /// its lifetime until aborted or a refresh fails. Closing the control
+    /// connection releases the allocation, so this task owns it.
+    #[cfg(not(target_os = "android"))]
+    async fn turn_keepalive_tcp(
+        mut control: tokio_rustls::client::TlsStream<TcpStream>,
+        username: String,
+        password: String,
+        realm: String,
+        mut nonce: String,
+        op_timeout: Duration,
+    ) {
+        let mut lifetime = Self::TURN_LIFETIME_SECS;
+        loop {
+            tokio::time::sleep(Self::turn_refresh_interval(lifetime)).await;
+            match Self::turn_refresh_tcp(
+                &mut control,
+                Self::TURN_LIFETIME_SECS,
+                &username,
+                &password,
+                &realm,
+                &mut nonce,
+                op_timeout,
+            )
+            .await
+            {
+                Ok(granted) => lifetime = granted,
+                Err(e) => {
+                    warn!("TURN Refresh failed: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Relayed data stream to `peer_relay` through one TURN server:
+    /// Allocate, Connect (which installs the permission), then ConnectionBind.
+    /// The returned task keeps the allocation alive; abort it once the data
+    /// stream is done.
+    #[cfg(not(target_os = "android"))]
+    async fn turn_relay_to_peer(
+        turn_url: &str,
+        peer_relay: std::net::SocketAddr,
+        username: &str,
+        password: &str,
+        cert_chain_path: &str,
+        op_timeout: Duration,
+    ) -> Result<(
+        tokio_rustls::client::TlsStream<TcpStream>,
+        tokio::task::JoinHandle<()>,
+    )> {
+        let (mut tls, _relayed, realm, nonce) =
+            Self::turn_allocate_tcp(turn_url, username, password, cert_chain_path, op_timeout)
+                .await?;
+        let conn_id = Self::turn_connect_peer(
+            &mut tls, peer_relay, username, password, &realm, &nonce, op_timeout,
+        )
+        .await?;
+        let data_stream = Self::turn_connection_bind(
+            turn_url,
+            &conn_id,
+            username,
+            password,
+            &realm,
+            &nonce,
+            cert_chain_path,
+            op_timeout,
+        )
+        .await?;
+        let keepalive = tokio::spawn(Self::turn_keepalive_tcp(
+            tls,
+            username.to_string(),
+            password.to_string(),
+            realm,
+            nonce,
+            op_timeout,
+        ));
+        Ok((data_stream, keepalive))
+    }
+
     async fn send_command_v2(&self, command: CommandV2) -> Result<()> {
         use common::{write_command, Command};
 
@@ -1031,12 +1441,13 @@ impl ClientWorker {
                     seed: 0,
                     min_keep: min_keep as usize,
                     thinking_budget_tokens: None,
+                    ..Default::default()
                 };
 
-                let (text, _prompt_tokens, _completion_tokens) = llama
+                let output = llama
                     .generate_with_cached_model_sampling(prompt, max_tokens as usize, &sampling)
                     .await?;
-                Ok(text)
+                Ok(output.text)
             }
             _ => Err(anyhow!(
                 "execute_inference_task is only supported for LLAMA engine"
@@ -1047,52 +1458,33 @@ impl ClientWorker {
     #[cfg(not(target_os = "android"))]
     async fn serve_p2p_io_with_engine<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(
         engine: Arc<Mutex<Option<AnyEngine>>>,
-        mut stream: S,
+        stream: S,
         connection_id: [u8; 16],
         data_plane_secret: [u8; 32],
     ) -> Result<()> {
-        let mut buf = BytesMut::with_capacity(MAX_MESSAGE_SIZE);
-        let mut inbound_replay = P2PReplayWindow::new();
-        let mut outbound_seq: u64 = 1;
-        async fn write_signed_p2p_command<S: tokio::io::AsyncWrite + Unpin>(
-            stream: &mut S,
+        let transport = TurnTcp::new(stream, connection_id, data_plane_secret);
+        Self::serve_p2p_transport_with_engine(engine, transport, connection_id).await
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn serve_p2p_transport_with_engine<T: P2PDataTransport>(
+        engine: Arc<Mutex<Option<AnyEngine>>>,
+        transport: T,
+        connection_id: [u8; 16],
+    ) -> Result<()> {
+        let mut next_msg_id: u32 = 1;
+        async fn write_signed_p2p_command<T: P2PDataTransport>(
+            transport: &T,
             command: &Command,
-            connection_id: [u8; 16],
-            data_plane_secret: [u8; 32],
-            outbound_seq: &mut u64,
+            next_msg_id: &mut u32,
         ) -> Result<()> {
-            let signed = ClientWorker::p2p_encode_data_plane_envelope(
-                command,
-                connection_id,
-                data_plane_secret,
-                *outbound_seq,
-                ClientWorker::p2p_now_secs(),
-            )?;
-            *outbound_seq = outbound_seq.wrapping_add(1);
-            write_command(stream, &signed).await?;
-            stream.flush().await?;
-            Ok(())
+            let msg_id = *next_msg_id;
+            *next_msg_id = next_msg_id.wrapping_add(1);
+            let payload = ClientWorker::p2p_udp_encode_command_payload(command)?;
+            transport.send(msg_id, &payload).await
         }
         loop {
-            let signed_cmd = read_command(&mut stream, &mut buf).await?;
-            let cmd = match Self::p2p_decode_data_plane_envelope(
-                signed_cmd,
-                connection_id,
-                data_plane_secret,
-                &mut inbound_replay,
-            ) {
-                Ok(cmd) => cmd,
-                Err(e) => {
-                    let err = e.to_string();
-                    if err.contains("replay") {
-                        security_metrics::record_p2p_replay_rejection();
-                    } else {
-                        security_metrics::record_p2p_auth_rejection();
-                    }
-                    warn!("P2P TCP data-plane authentication rejected frame: {}", err);
-                    continue;
-                }
-            };
+            let cmd = Self::udp_decode_command(&transport.recv().await?)?;
             match cmd {
                 Command::V2(CommandV2::P2PInferenceRequest {
                     connection_id: req_conn_id,
@@ -1131,14 +1523,7 @@ impl ClientWorker {
                             analysis_tokens: 0,
                             final_tokens: 0,
                         });
-                        write_signed_p2p_command(
-                            &mut stream,
-                            &chunk,
-                            connection_id,
-                            data_plane_secret,
-                            &mut outbound_seq,
-                        )
-                        .await?;
+                        write_signed_p2p_command(&transport, &chunk, &mut next_msg_id).await?;
                         continue;
                     };
 
@@ -1151,6 +1536,7 @@ impl ClientWorker {
                         seed: 0,
                         min_keep: min_keep as usize,
                         thinking_budget_tokens: None,
+                        ..Default::default()
                     };
 
                     let token_stream = llama
@@ -1162,13 +1548,14 @@ impl ClientWorker {
                     let mut buf = String::new();
                     let mut buf_phase: OutputPhase = OutputPhase::Unknown;
                     let mut splitter = PhaseSplitter::default();
+                    let mut control_filter = ControlTokenFilter::from_config();
                     let mut analysis_tokens: u32 = 0;
                     let mut final_tokens: u32 = 0;
                     let max_bytes: usize = 64;
 
                     while let Some(piece_res) = token_stream.next().await {
                         let piece = piece_res?;
-                        let filtered = filter_control_tokens(&piece);
+                        let filtered = control_filter.push(&piece);
                         let segs = splitter.push(&filtered);
                         for (phase, seg) in segs {
                             if seg.is_empty() {
@@ -1197,14 +1584,8 @@ impl ClientWorker {
                                     analysis_tokens,
                                     final_tokens,
                                 });
-                                write_signed_p2p_command(
-                                    &mut stream,
-                                    &chunk,
-                                    connection_id,
-                                    data_plane_secret,
-                                    &mut outbound_seq,
-                                )
-                                .await?;
+                                write_signed_p2p_command(&transport, &chunk, &mut next_msg_id)
+                                    .await?;
                                 seq = seq.wrapping_add(1);
                                 buf_phase = phase;
                             }
@@ -1223,18 +1604,14 @@ impl ClientWorker {
                                     analysis_tokens,
                                     final_tokens,
                                 });
-                                write_signed_p2p_command(
-                                    &mut stream,
-                                    &chunk,
-                                    connection_id,
-                                    data_plane_secret,
-                                    &mut outbound_seq,
-                                )
-                                .await?;
+                                write_signed_p2p_command(&transport, &chunk, &mut next_msg_id)
+                                    .await?;
                             }
                         }
                     }
 
+                    // An unfinished `<|...` at the end of output is plain text
+                    buf.push_str(&control_filter.finish());
                     if !buf.is_empty() {
                         let delta = std::mem::take(&mut buf);
                         let chunk = Command::V2(CommandV2::P2PInferenceChunk {
@@ -1248,14 +1625,7 @@ impl ClientWorker {
                             analysis_tokens,
                             final_tokens,
                         });
-                        write_signed_p2p_command(
-                            &mut stream,
-                            &chunk,
-                            connection_id,
-                            data_plane_secret,
-                            &mut outbound_seq,
-                        )
-                        .await?;
+                        write_signed_p2p_command(&transport, &chunk, &mut next_msg_id).await?;
                     }
 
                     let done = Command::V2(CommandV2::P2PInferenceDone {
@@ -1267,14 +1637,7 @@ impl ClientWorker {
                         analysis_tokens,
                         final_tokens,
                     });
-                    write_signed_p2p_command(
-                        &mut stream,
-                        &done,
-                        connection_id,
-                        data_plane_secret,
-                        &mut outbound_seq,
-                    )
-                    .await?;
+                    write_signed_p2p_command(&transport, &done, &mut next_msg_id).await?;
                 }
 
                 Command::V2(CommandV2::P2PCancelInference {
@@ -1292,16 +1655,6 @@ impl ClientWorker {
         }
     }
 
-    #[cfg(not(target_os = "android"))]
-    async fn serve_p2p_stream_with_engine(
-        engine: Arc<Mutex<Option<AnyEngine>>>,
-        stream: TcpStream,
-        connection_id: [u8; 16],
-        data_plane_secret: [u8; 32],
-    ) -> Result<()> {
-        Self::serve_p2p_io_with_engine(engine, stream, connection_id, data_plane_secret).await
-    }
-
     #[cfg(not(target_os = "android"))]
     async fn turn_control_loop(
         mut tls: tokio_rustls::client::TlsStream<TcpStream>,
@@ -1311,6 +1664,7 @@ impl ClientWorker {
         realm: String,
         nonce: String,
         cert_chain_path: String,
+        op_timeout: Duration,
         engine: Arc<Mutex<Option<AnyEngine>>>,
         connection_id: [u8; 16],
         data_plane_secret: [u8; 32],
@@ -1358,6 +1712,7 @@ impl ClientWorker {
                 &realm,
                 &nonce,
                 &cert_chain_path,
+                op_timeout,
             )
             .await
             {
@@ -1467,26 +1822,32 @@ impl ClientWorker {
                             "Creating LLAMA engine with configured model path ({} bytes)",
                             model_path.len()
                         );
-                        llm_engine::AnyEngine::Llama(LlamaEngine::with_config(
-                            model_path.clone(),
-                            args.n_ctx,
-                            args.n_batch,
-                            args.n_gpu_layers,
-                            args.llama_split_mode.clone(),
-                            args.llama_main_gpu,
-                            args.llama_devices.clone(),
-                        ))
+                        llm_engine::AnyEngine::Llama(
+                            LlamaEngine::with_config(
+                                model_path.clone(),
+                                args.n_ctx,
+                                args.n_batch,
+                                args.n_gpu_layers,
+                                args.llama_split_mode.clone(),
+                                args.llama_main_gpu,
+                                args.llama_devices.clone(),
+                            )
+                            .with_max_resident_models(args.max_resident_models),
+                        )
                     } else {
                         // Create engine without model (will be set later)
                         info!("Creating LLAMA engine without model (will be set later)");
-                        llm_engine::AnyEngine::Llama(LlamaEngine::with_runtime_config(
-                            args.n_ctx,
-                            args.n_batch,
-                            args.n_gpu_layers,
-                            args.llama_split_mode.clone(),
-                            args.llama_main_gpu,
-                            args.llama_devices.clone(),
-                        ))
+                        llm_engine::AnyEngine::Llama(
+                            LlamaEngine::with_runtime_config(
+                                args.n_ctx,
+                                args.n_batch,
+                                args.n_gpu_layers,
+                                args.llama_split_mode.clone(),
+                                args.llama_main_gpu,
+                                args.llama_devices.clone(),
+                            )
+                            .with_max_resident_models(args.max_resident_models),
+                        )
                     };
 
                     // Initialize the engine (only on first startup)
@@ -1612,26 +1973,32 @@ impl ClientWorker {
                             "Creating LLAMA engine with configured model path ({} bytes)",
                             model_path.len()
                         );
-                        llm_engine::AnyEngine::Llama(LlamaEngine::with_config(
-                            model_path.clone(),
-                            args.n_ctx,
-                            args.n_batch,
-                            args.n_gpu_layers,
-                            args.llama_split_mode.clone(),
-                            args.llama_main_gpu,
-                            args.llama_devices.clone(),
-                        ))
+                        llm_engine::AnyEngine::Llama(
+                            LlamaEngine::with_config(
+                                model_path.clone(),
+                                args.n_ctx,
+                                args.n_batch,
+                                args.n_gpu_layers,
+                                args.llama_split_mode.clone(),
+                                args.llama_main_gpu,
+                                args.llama_devices.clone(),
+                            )
+                            .with_max_resident_models(args.max_resident_models),
+                        )
                     } else {
                         // Create engine without model (will be set later)
                         info!("Creating LLAMA engine without model (will be set later)");
-                        llm_engine::AnyEngine::Llama(LlamaEngine::with_runtime_config(
-                            args.n_ctx,
-                            args.n_batch,
-                            args.n_gpu_layers,
-                            args.llama_split_mode.clone(),
-                            args.llama_main_gpu,
-                            args.llama_devices.clone(),
-                        ))
+                        llm_engine::AnyEngine::Llama(
+                            LlamaEngine::with_runtime_config(
+                                args.n_ctx,
+                                args.n_batch,
+                                args.n_gpu_layers,
+                                args.llama_split_mode.clone(),
+                                args.llama_main_gpu,
+                                args.llama_devices.clone(),
+                            )
+                            .with_max_resident_models(args.max_resident_models),
+                        )
                     };
 
                     // Initialize the engine (only on first startup)
@@ -1714,10 +2081,8 @@ impl ClientWorker {
             engine_type,
             args,
             network_monitor,
-            cancel_state: Arc::new(CancelState {
-                cancelled: Mutex::new(HashSet::new()),
-                notify: tokio::sync::Notify::new(),
-            }),
+            cancel_state: Arc::new(CancelState::new()),
+            server_revision: AtomicU32::new(1),
         };
         Ok(worker)
     }
@@ -1778,11 +2143,7 @@ impl ClientWorker {
             .ok_or_else(|| anyhow!("Model {} is missing required SHA256 checksum", model_name))?;
 
         // Get models directory (same level as executable)
-        let models_dir = std::env::current_exe()
-            .ok()
-            .and_then(|p| p.parent().map(|p| p.to_path_buf()))
-            .unwrap_or_else(|| std::path::PathBuf::from("."))
-            .join("models");
+        let models_dir = downloaded_models_dir();
 
         // Create models directory if it doesn't exist
         tokio::fs::create_dir_all(&models_dir).await?;
@@ -1830,6 +2191,7 @@ impl ClientWorker {
             // Load model into engine (only on non-Android platforms)
             #[cfg(not(target_os = "android"))]
             {
+                self.drain_generations().await;
                 let mut engine_guard = self.engine.lock().await;
                 if let Some(engine) = engine_guard.as_mut() {
                     match engine.set_models(vec![model_path_str.clone()]).await {
@@ -1849,6 +2211,8 @@ impl ClientWorker {
                         }
                     }
                 }
+                drop(engine_guard);
+                self.report_context_window().await?;
             }
             return Ok(());
         }
@@ -1997,6 +2361,7 @@ impl ClientWorker {
                     #[cfg(not(target_os = "android"))]
                     {
                         info!("Loading model {} into engine", model_name);
+                        self.drain_generations().await;
                         let mut engine_guard = self.engine.lock().await;
                         if let Some(engine) = engine_guard.as_mut() {
                             match engine.set_models(vec![model_path_str.clone()]).await {
@@ -2019,6 +2384,8 @@ impl ClientWorker {
                                 }
                             }
                         }
+                        drop(engine_guard);
+                        self.report_context_window().await?;
                     }
                     return Ok(());
                 }
@@ -2214,8 +2581,10 @@ impl WorkerHandle for ClientWorker {
     fn login(&self) -> impl Future<Output = Result<()>> + Send {
         async move {
             info!("{} Starting login process...", log_icon("🔧", "[LOGIN]"));
+            // A reconnect may reach a different server build
+            self.server_revision.store(1, Ordering::SeqCst);
             let login_cmd = CommandV1::Login {
-                version: CURRENT_VERSION,
+                version: common::PROTOCOL_REVISION,
                 auto_models: self.args.llama_model_path.is_none(),
                 os_type: self.os_type.clone(),
                 client_id: self.client_id.clone(),
@@ -2234,6 +2603,20 @@ impl WorkerHandle for ClientWorker {
                         "{} Login command written successfully",
                         log_icon("✅", "[OK]")
                     );
+                    // Only sent when configured so older servers never see it;
+                    // without it the server assumes every quantization works.
+                    if !self.args.quant_types.is_empty() {
+                        let capabilities = CommandV1::Capabilities {
+                            client_id: self.client_id,
+                            quant_types: self.args.quant_types.clone(),
+                        };
+                        write_command(&mut *self.writer.lock().await, &Command::V1(capabilities))
+                            .await?;
+                        info!(
+                            "Reported quantization capabilities: {:?}",
+                            self.args.quant_types
+                        );
+                    }
                     Ok(())
                 }
                 Err(e) => {
@@ -2286,18 +2669,25 @@ impl WorkerHandle for ClientWorker {
                                 "current_model_path present={}",
                                 current_model_path.is_some()
                             );
-                            match current_model_path {
-                                Some(model_path) => {
-                                    let model_id = derive_model_id_from_path(&model_path);
-                                    vec![Model {
-                                        id: model_id,
-                                        object: "model".to_string(),
-                                        created: 0,
-                                        owned_by: "gpuf-c".to_string(),
-                                    }]
+                            let mut model_ids: Vec<String> = current_model_path
+                                .iter()
+                                .map(|path| derive_model_id_from_path(path))
+                                .collect();
+                            // Downloaded models are served too, loaded on demand
+                            for (id, _) in on_disk_models(&downloaded_models_dir()) {
+                                if !model_ids.contains(&id) {
+                                    model_ids.push(id);
                                 }
-                                None => Vec::new(),
                             }
+                            model_ids
+                                .into_iter()
+                                .map(|id| Model {
+                                    id,
+                                    object: "model".to_string(),
+                                    created: 0,
+                                    owned_by: "gpuf-c".to_string(),
+                                })
+                                .collect()
                         }
                         _ => Vec::new(),
                     };
@@ -2338,9 +2728,15 @@ impl WorkerHandle for ClientWorker {
             let network_monitor = Arc::clone(&self.network_monitor);
             let engine_type = self.engine_type; // Clone engine_type for use in spawn
                                                 // network_monitor.lock().await.update();
+            let lite_heartbeat = self.args.lite_heartbeat;
+            let full_heartbeat_every = self.args.full_heartbeat_every.max(1);
             tokio::spawn(async move {
                 let mut interval = interval(Duration::from_secs(120)); // Send heartbeat every 120 seconds
 
+                // Devices from the last heartbeat and how many lite ones followed it
+                let mut last_devices: Option<Vec<DevicesInfo>> = None;
+                let mut lite_sent: u32 = 0;
+
                 loop {
                     interval.tick().await;
 
@@ -2381,27 +2777,54 @@ impl WorkerHandle for ClientWorker {
                         format_duration!(session_stats.2.as_secs())
                     );
 
+                    let system_info = SystemInfo {
+                        cpu_usage: cpu_usage,
+                        memory_usage: memory_usage,
+                        disk_usage: disk_usage,
+                        network_rx: stats.0,
+                        network_tx: stats.1,
+                    };
+                    let devices_info = vec![device_info];
+
+                    // Lite heartbeats only carry changed usage readings; the server
+                    // keeps the rest from the last full one. Static changes and the
+                    // periodic resync send a full heartbeat.
+                    let devices_usage = match &last_devices {
+                        Some(previous)
+                            if lite_heartbeat && lite_sent + 1 < full_heartbeat_every =>
+                        {
+                            device_usage_delta(previous, &devices_info)
+                        }
+                        _ => None,
+                    };
+                    let command = match devices_usage {
+                        Some(devices_usage) => {
+                            lite_sent += 1;
+                            debug!("heartbeat: lite, {} changed devices", devices_usage.len());
+                            CommandV1::HeartbeatLite {
+                                client_id: *client_id,
+                                system_info,
+                                devices_usage,
+                            }
+                        }
+                        None => {
+                            lite_sent = 0;
+                            let device = &devices_info[0];
+                            CommandV1::Heartbeat {
+                                client_id: *client_id,
+                                system_info,
+                                // TODO: devices_info device_count device_total_tflops and device_memtotal_gb is single device
+                                device_memtotal_gb: device.memtotal_gb as u32,
+                                device_total_tflops: device.total_tflops as u32,
+                                device_count: device.num as u16,
+                                devices_info: devices_info.clone(),
+                            }
+                        }
+                    };
+                    last_devices = Some(devices_info);
+
                     let mut writer = writer_clone.lock().await;
-                    if let Err(e) = write_command(
-                        &mut *writer,
-                        &Command::V1(CommandV1::Heartbeat {
-                            client_id: *client_id,
-                            system_info: SystemInfo {
-                                cpu_usage: cpu_usage,
-                                memory_usage: memory_usage,
-                                disk_usage: disk_usage,
-                                network_rx: stats.0,
-                                network_tx: stats.1,
-                            },
-                            // TODO: devices_info device_count device_total_tflops and device_memtotal_gb is single device
-                            device_memtotal_gb: device_info.memtotal_gb as u32,
-                            device_total_tflops: device_info.total_tflops as u32,
-                            device_count: device_info.num as u16,
-                            devices_info: vec![device_info],
-                        }),
-                    )
-                    .await
-                    {
+                    if let Err(e) = write_command(&mut *writer, &Command::V1(command)).await {
                         error!("Failed to send heartbeat: {}", e);
                         break;
                     }
@@ -2442,11 +2865,7 @@ impl WorkerHandle for ClientWorker {
                         match cmd_v1 {
                             CommandV1::CancelInference { task_id } => {
                                 debug!(task_id = %task_id, "Received CancelInference");
-                                {
-                                    let mut cancelled = self.cancel_state.cancelled.lock().await;
-                                    cancelled.insert(task_id);
-                                }
-                                self.cancel_state.notify.notify_waiters();
+                                self.cancel_state.cancel(task_id).await;
                             }
                             CommandV1::LoginResult {
                                 success,
@@ -2563,7 +2982,7 @@ impl WorkerHandle for ClientWorker {
                             }
                             CommandV1::ChatInferenceTask {
                                 task_id,
-                                model: _model,
+                                model,
                                 messages,
                                 max_tokens,
                                 temperature,
@@ -2579,113 +2998,39 @@ impl WorkerHandle for ClientWorker {
                                     messages.len(),
                                     max_tokens
                                 );
-                                let prompt = {
-                                    #[cfg(target_os = "android")]
-                                    {
-                                        self.build_chat_prompt_fallback(&messages)
-                                    }
-
-                                    #[cfg(not(target_os = "android"))]
-                                    {
-                                        let cached_model = {
-                                            let engine_guard = self.engine.lock().await;
-                                            let engine = engine_guard
-                                                .as_ref()
-                                                .ok_or_else(|| anyhow!("Engine not initialized"))?;
-
-                                            let AnyEngine::Llama(llama) = engine else {
-                                                return Err(anyhow!(
-                                                    "ChatInferenceTask is only supported for LLAMA engine"
-                                                ));
-                                            };
-
-                                            llama.cached_model
-                                                .as_ref()
-                                                .ok_or_else(|| {
-                                                    anyhow!("Model not loaded - call load_model() first")
-                                                })?
-                                                .clone()
-                                        };
-
-                                        let messages_for_fallback = messages.clone();
-                                        match tokio::task::spawn_blocking(
-                                            move || -> anyhow::Result<String> {
-                                                use llama_cpp_2::model::LlamaChatMessage;
-
-                                                let model_guard =
-                                                    cached_model.lock().map_err(|e| {
-                                                        anyhow!("Failed to lock model: {:?}", e)
-                                                    })?;
-
-                                                let tmpl = model_guard
-                                                    .chat_template(None)
-                                                    .map_err(|e| {
-                                                        anyhow!(
-                                                            "Failed to get chat template: {:?}",
-                                                            e
-                                                        )
-                                                    })?;
-
-                                                let mut chat =
-                                                    Vec::with_capacity(messages_for_fallback.len());
-                                                for m in messages_for_fallback {
-                                                    let msg =
-                                                        LlamaChatMessage::new(m.role, m.content)
-                                                            .map_err(|e| {
-                                                                anyhow!(
-                                                            "Failed to build chat message: {:?}",
-                                                            e
-                                                        )
-                                                            })?;
-                                                    chat.push(msg);
-                                                }
-
-                                                model_guard
-                                                    .apply_chat_template(&tmpl, &chat, true)
-                                                    .map_err(|e| {
-                                                        anyhow!(
-                                                            "Failed to apply chat template: {:?}",
-                                                            e
-                                                        )
-                                                    })
-                                            },
-                                        )
-                                        .await
-                                        {
-                                            Ok(Ok(p)) => p,
-                                            _ => self.build_chat_prompt_fallback(&messages),
+                                #[cfg(not(target_os = "android"))]
+                                if let Err(e) = self.load_model_on_demand(&model).await {
+                                    self.inference_relay()
+                                        .fail_task(task_id, &e, |chunk| self.send_command(chunk))
+                                        .await?;
+                                    continue;
+                                }
+                                #[cfg(target_os = "android")]
+                                let _ = model;
+                                let prompt =
+                                    match self.inference_relay().chat_prompt(&messages).await {
+                                        Ok(prompt) => prompt,
+                                        Err(e) => {
+                                            self.inference_relay()
+                                                .fail_task(task_id, &e, |chunk| {
+                                                    self.send_command(chunk)
+                                                })
+                                                .await?;
+                                            continue;
                                         }
-                                    }
-                                };
-                                let result = self
-                                    .stream_inference_task_to_server(
-                                        task_id.clone(),
-                                        prompt,
-                                        max_tokens,
-                                        temperature,
-                                        top_k,
-                                        top_p,
-                                        repeat_penalty,
-                                        repeat_last_n,
-                                        min_keep,
-                                    )
-                                    .await;
-
-                                if let Err(e) = result {
-                                    let chunk = CommandV1::InferenceResultChunk {
-                                        task_id,
-                                        seq: 0,
-                                        delta: String::new(),
-                                        phase: OutputPhase::Unknown,
-                                        done: true,
-                                        completion_tokens: 0,
-                                        prompt_tokens: 0,
-                                        error: Some(e.to_string()),
-                                        analysis_tokens: 0,
-                                        final_tokens: 0,
                                     };
-                                    self.send_command(chunk).await?;
-                                }
+                                self.stream_inference_task_to_server(
+                                    task_id,
+                                    prompt,
+                                    max_tokens,
+                                    temperature,
+                                    top_k,
+                                    top_p,
+                                    repeat_penalty,
+                                    repeat_last_n,
+                                    min_keep,
+                                )
+                                .await?;
                             }
                             CommandV1::InferenceTask {
                                 task_id,
@@ -2703,129 +3048,69 @@ impl WorkerHandle for ClientWorker {
                                     task_id, max_tokens
                                 );
 
-                                let start_time = std::time::Instant::now();
-
-                                #[cfg(not(target_os = "android"))]
-                                {
-                                    let result = self
-                                        .stream_inference_task_to_server(
-                                            task_id.clone(),
-                                            prompt.clone(),
-                                            max_tokens,
-                                            temperature,
-                                            top_k,
-                                            top_p,
-                                            repeat_penalty,
-                                            repeat_last_n,
-                                            min_keep,
-                                        )
-                                        .await;
-
-                                    let _execution_time = start_time.elapsed().as_millis() as u64;
-                                    if let Err(e) = result {
-                                        let chunk = CommandV1::InferenceResultChunk {
-                                            task_id,
-                                            seq: 0,
-                                            delta: String::new(),
-                                            phase: OutputPhase::Unknown,
-                                            done: true,
-                                            completion_tokens: 0,
-                                            prompt_tokens: 0,
-                                            error: Some(e.to_string()),
-                                            analysis_tokens: 0,
-                                            final_tokens: 0,
-                                        };
-                                        self.send_command(chunk).await?;
-                                    }
-                                }
-
-                                #[cfg(target_os = "android")]
-                                {
-                                    let result = self
-                                        .execute_inference_task(
-                                            &prompt,
-                                            max_tokens,
-                                            temperature,
-                                            top_k,
-                                            top_p,
-                                            repeat_penalty,
-                                            repeat_last_n,
-                                            min_keep,
-                                        )
-                                        .await;
-
-                                    let _execution_time = start_time.elapsed().as_millis() as u64;
-
-                                    match result {
-                                        Ok(output) => {
-                                            let mut seq: u32 = 0;
-                                            let max_bytes: usize =
-                                                self.args.stream_chunk_bytes.max(1);
-                                            let mut start: usize = 0;
-                                            while start < output.len() {
-                                                let mut end = (start + max_bytes).min(output.len());
-                                                while end < output.len()
-                                                    && !output.is_char_boundary(end)
-                                                {
-                                                    end -= 1;
-                                                }
-                                                if end == start {
-                                                    end = output
-                                                        .char_indices()
-                                                        .nth(1)
-                                                        .map(|(i, _)| i)
-                                                        .unwrap_or(output.len());
-                                                }
-
-                                                let delta = output[start..end].to_string();
-                                                let chunk = CommandV1::InferenceResultChunk {
-                                                    task_id: task_id.clone(),
-                                                    seq,
-                                                    delta,
-                                                    phase: OutputPhase::Unknown,
-                                                    done: false,
-                                                    error: None,
-                                                    prompt_tokens: 0,
-                                                    completion_tokens: 0,
-                                                    analysis_tokens: 0,
-                                                    final_tokens: 0,
-                                                };
-                                                self.send_command(chunk).await?;
-                                                seq = seq.wrapping_add(1);
-                                                start = end;
-                                            }
-
-                                            let done_chunk = CommandV1::InferenceResultChunk {
-                                                task_id,
-                                                seq,
-                                                delta: String::new(),
-                                                phase: OutputPhase::Unknown,
-                                                done: true,
-                                                error: None,
-                                                prompt_tokens: 0,
-                                                completion_tokens: 0,
-                                                analysis_tokens: 0,
-                                                final_tokens: 0,
-                                            };
-                                            self.send_command(done_chunk).await?;
-                                        }
-                                        Err(e) => {
-                                            let chunk = CommandV1::InferenceResultChunk {
-                                                task_id,
-                                                seq: 0,
-                                                delta: String::new(),
-                                                phase: OutputPhase::Unknown,
-                                                done: true,
-                                                error: Some(e.to_string()),
-                                                prompt_tokens: 0,
-                                                completion_tokens: 0,
-                                                analysis_tokens: 0,
-                                                final_tokens: 0,
-                                            };
-                                            self.send_command(chunk).await?;
-                                        }
-                                    }
+                                self.stream_inference_task_to_server(
+                                    task_id,
+                                    prompt,
+                                    max_tokens,
+                                    temperature,
+                                    top_k,
+                                    top_p,
+                                    repeat_penalty,
+                                    repeat_last_n,
+                                    min_keep,
+                                )
+                                .await?;
+                            }
+                            CommandV1::ModelInferenceTask {
+                                task_id,
+                                model,
+                                prompt,
+                                max_tokens,
+                                temperature,
+                                top_k,
+                                top_p,
+                                repeat_penalty,
+                                repeat_last_n,
+                                min_keep,
+                            } => {
+                                info!(
+                                    "Received inference task: {} model: {} max_tokens: {}",
+                                    task_id, model, max_tokens
+                                );
+                                #[cfg(not(target_os = "android"))]
+                                if let Err(e) = self.load_model_on_demand(&model).await {
+                                    self.inference_relay()
+                                        .fail_task(task_id, &e, |chunk| self.send_command(chunk))
+                                        .await?;
+                                    continue;
                                 }
+                                self.stream_inference_task_to_server(
+                                    task_id,
+                                    prompt,
+                                    max_tokens,
+                                    temperature,
+                                    top_k,
+                                    top_p,
+                                    repeat_penalty,
+                                    repeat_last_n,
+                                    min_keep,
+                                )
+                                .await?;
+                            }
+                            CommandV1::ServerRevision { protocol_revision } => {
+                                debug!(protocol_revision, "Received ServerRevision");
+                                self.server_revision
+                                    .store(protocol_revision, Ordering::SeqCst);
+                                self.report_context_window().await?;
+                            }
+                            CommandV1::DescribeWorker { request_id } => {
+                                debug!(request_id = %request_id, "Received DescribeWorker");
+                                let description = self.describe().await;
+                                self.send_command(CommandV1::WorkerDescription {
+                                    request_id,
+                                    description,
+                                })
+                                .await?;
                             }
                             _ => {
                                 warn!("Received unexpected CommandV1 variant; payload redacted");
@@ -2847,16 +3132,6 @@ impl WorkerHandle for ClientWorker {
                             } => {
                                 let turn_password = turn_password.into_inner();
                                 let data_plane_secret = data_plane_secret.into_inner();
-                                p2p_turn_config.insert(
-                                    connection_id,
-                                    P2PConnectionRuntimeConfig {
-                                        turn_urls: turn_urls.clone(),
-                                        turn_username: turn_username.clone(),
-                                        turn_password: turn_password.clone(),
-                                        _peer_hex: hex::encode(peer_id),
-                                        data_plane_secret,
-                                    },
-                                );
 
                                 // Mode 1: gpuf-c acts as server for P2P data-plane.
                                 // Start a UDP socket for P2P data-plane.
@@ -2874,15 +3149,39 @@ impl WorkerHandle for ClientWorker {
                                     Arc::new(UdpSocket::bind(&bind_addr).await.map_err(|e| {
                                         anyhow!("P2P UDP bind failed on {}: {}", bind_addr, e)
                                     })?);
-                                let local_port = socket.local_addr()?.port();
+                                let data_plane_addr = socket.local_addr()?;
+                                let local_port = data_plane_addr.port();
                                 let advertise_ip = self.get_advertise_ip().await?;
+                                #[cfg(not(target_os = "android"))]
+                                let punches = Arc::new(PunchAcks::default());
+                                p2p_turn_config.insert(
+                                    connection_id,
+                                    P2PConnectionRuntimeConfig {
+                                        turn_urls: turn_urls.clone(),
+                                        turn_username: turn_username.clone(),
+                                        turn_password: turn_password.clone(),
+                                        _peer_hex: hex::encode(peer_id),
+                                        data_plane_secret,
+                                        data_plane_addr,
+                                        #[cfg(not(target_os = "android"))]
+                                        socket: Arc::clone(&socket),
+                                        #[cfg(not(target_os = "android"))]
+                                        punches: Arc::clone(&punches),
+                                    },
+                                );
 
                                 #[cfg(not(target_os = "android"))]
                                 {
                                     let engine = Arc::clone(&self.engine);
                                     let socket = Arc::clone(&socket);
                                     let data_plane_secret_copy = data_plane_secret;
+                                    let send_window = self.args.p2p_send_window;
                                     tokio::spawn(async move {
+                                        let framer = ReliableFramer::new(
+                                            connection_id,
+                                            data_plane_secret_copy,
+                                        )
+                                        .with_window(send_window);
                                         let mut next_msg_id: u32 = 1;
                                         let mut reassembly = P2PUdpReassemblyState::new();
                                         let mut buf = vec![0u8; 64 * 1024];
@@ -2899,49 +3198,37 @@ impl WorkerHandle for ClientWorker {
                                                 continue;
                                             }
 
-                                            let Some((flags, msg_id, frag_idx, frag_cnt, ts, tag)) =
-                                                Self::p2p_udp_parse_header(&buf[..n])
-                                            else {
-                                                security_metrics::record_p2p_auth_rejection();
-                                                reassembly.record_invalid_source(from);
-                                                continue;
+                                            let (msg_id, frag_idx, frag_cnt, payload) = match framer
+                                                .open(&buf[..n])
+                                            {
+                                                Ok(FramedPacket::Fragment {
+                                                    msg_id,
+                                                    frag_idx,
+                                                    frag_cnt,
+                                                    payload,
+                                                }) => (msg_id, frag_idx, frag_cnt, payload),
+                                                Ok(FramedPacket::Ack { msg_id, .. }) => {
+                                                    punches.deliver(from, msg_id);
+                                                    continue;
+                                                }
+                                                Err(e) => {
+                                                    security_metrics::record_p2p_auth_rejection();
+                                                    warn!("P2P UDP authentication rejected packet from {}: {}", from, e);
+                                                    reassembly.record_invalid_source(from);
+                                                    continue;
+                                                }
                                             };
 
-                                            if (flags & Self::P2P_UDP_FLAG_ACK) != 0 {
-                                                continue;
-                                            }
-
-                                            let payload = &buf[Self::P2P_UDP_HEADER_LEN..n];
-                                            if let Err(e) = Self::p2p_udp_validate_fragment(
-                                                &data_plane_secret_copy,
-                                                &connection_id,
-                                                flags,
-                                                msg_id,
-                                                frag_idx,
-                                                frag_cnt,
-                                                ts,
-                                                payload,
-                                                &tag,
-                                                Self::p2p_now_secs(),
-                                            ) {
-                                                security_metrics::record_p2p_auth_rejection();
-                                                warn!("P2P UDP authentication rejected packet from {}: {}", from, e);
-                                                reassembly.record_invalid_source(from);
-                                                continue;
-                                            }
-
                                             let full = match reassembly.accept_fragment(
                                                 from, msg_id, frag_idx, frag_cnt, payload,
                                             ) {
                                                 Ok(v) => {
-                                                    Self::p2p_udp_send_ack(
-                                                        &socket,
-                                                        from,
-                                                        connection_id,
-                                                        data_plane_secret_copy,
-                                                        msg_id,
-                                                    )
-                                                    .await;
+                                                    let _ = socket
+                                                        .send_to(
+                                                            &framer.ack(msg_id, frag_idx, frag_cnt),
+                                                            from,
+                                                        )
+                                                        .await;
                                                     v
                                                 }
                                                 Err(e) => {
@@ -2983,6 +3270,7 @@ impl WorkerHandle for ClientWorker {
                                             if req_conn_id != connection_id {
                                                 continue;
                                             }
+                                            let reply = DirectUdp::new(&socket, from, framer);
 
                                             // Stream inference over UDP data-plane.
                                             let sampling =
@@ -2995,6 +3283,7 @@ impl WorkerHandle for ClientWorker {
                                                     seed: 0,
                                                     min_keep: min_keep as usize,
                                                     thinking_budget_tokens: None,
+                                                    ..Default::default()
                                                 };
 
                                             let token_stream_res = {
@@ -3026,15 +3315,7 @@ impl WorkerHandle for ClientWorker {
                                                             let msg_id = next_msg_id;
                                                             next_msg_id =
                                                                 next_msg_id.wrapping_add(1);
-                                                            let _ = Self::p2p_udp_send_reliable(
-                                                                &socket,
-                                                                from,
-                                                                connection_id,
-                                                                data_plane_secret_copy,
-                                                                msg_id,
-                                                                &pkt,
-                                                            )
-                                                            .await;
+                                                            let _ = reply.send(msg_id, &pkt).await;
                                                         }
                                                         continue;
                                                     }
@@ -3057,15 +3338,7 @@ impl WorkerHandle for ClientWorker {
                                                     {
                                                         let msg_id = next_msg_id;
                                                         next_msg_id = next_msg_id.wrapping_add(1);
-                                                        let _ = Self::p2p_udp_send_reliable(
-                                                            &socket,
-                                                            from,
-                                                            connection_id,
-                                                            data_plane_secret_copy,
-                                                            msg_id,
-                                                            &pkt,
-                                                        )
-                                                        .await;
+                                                        let _ = reply.send(msg_id, &pkt).await;
                                                     }
                                                     continue;
                                                 };
@@ -3099,15 +3372,7 @@ impl WorkerHandle for ClientWorker {
                                                     {
                                                         let msg_id = next_msg_id;
                                                         next_msg_id = next_msg_id.wrapping_add(1);
-                                                        let _ = Self::p2p_udp_send_reliable(
-                                                            &socket,
-                                                            from,
-                                                            connection_id,
-                                                            data_plane_secret_copy,
-                                                            msg_id,
-                                                            &pkt,
-                                                        )
-                                                        .await;
+                                                        let _ = reply.send(msg_id, &pkt).await;
                                                     }
                                                     continue;
                                                 }
@@ -3115,6 +3380,8 @@ impl WorkerHandle for ClientWorker {
 
                                             let mut token_stream = Box::pin(token_stream);
                                             let mut seq: u32 = 0;
+                                            let mut control_filter =
+                                                ControlTokenFilter::from_config();
 
                                             while let Some(piece_res) = token_stream.next().await {
                                                 let piece = match piece_res {
@@ -3141,20 +3408,12 @@ impl WorkerHandle for ClientWorker {
                                                             let msg_id = next_msg_id;
                                                             next_msg_id =
                                                                 next_msg_id.wrapping_add(1);
-                                                            let _ = Self::p2p_udp_send_reliable(
-                                                                &socket,
-                                                                from,
-                                                                connection_id,
-                                                                data_plane_secret_copy,
-                                                                msg_id,
-                                                                &pkt,
-                                                            )
-                                                            .await;
+                                                            let _ = reply.send(msg_id, &pkt).await;
                                                         }
                                                         break;
                                                     }
                                                 };
-                                                let filtered = filter_control_tokens(&piece);
+                                                let filtered = control_filter.push(&piece);
                                                 if filtered.is_empty() {
                                                     continue;
                                                 }
@@ -3196,20 +3455,36 @@ impl WorkerHandle for ClientWorker {
                                                     {
                                                         let msg_id = next_msg_id;
                                                         next_msg_id = next_msg_id.wrapping_add(1);
-                                                        let _ = Self::p2p_udp_send_reliable(
-                                                            &socket,
-                                                            from,
-                                                            connection_id,
-                                                            data_plane_secret_copy,
-                                                            msg_id,
-                                                            &pkt,
-                                                        )
-                                                        .await;
+                                                        let _ = reply.send(msg_id, &pkt).await;
                                                     }
                                                     seq = seq.wrapping_add(1);
                                                 }
                                             }
 
+                                            // An unfinished `<|...` at the end of output is plain text
+                                            let tail = control_filter.finish();
+                                            if !tail.is_empty() {
+                                                let chunk =
+                                                    Command::V2(CommandV2::P2PInferenceChunk {
+                                                        connection_id,
+                                                        task_id: task_id.clone(),
+                                                        seq,
+                                                        delta: tail,
+                                                        phase: OutputPhase::Unknown,
+                                                        done: false,
+                                                        error: None,
+                                                        analysis_tokens: 0,
+                                                        final_tokens: 0,
+                                                    });
+                                                if let Ok(pkt) =
+                                                    Self::p2p_udp_encode_command_payload(&chunk)
+                                                {
+                                                    let msg_id = next_msg_id;
+                                                    next_msg_id = next_msg_id.wrapping_add(1);
+                                                    let _ = reply.send(msg_id, &pkt).await;
+                                                }
+                                            }
+
                                             let done = Command::V2(CommandV2::P2PInferenceDone {
                                                 connection_id,
                                                 task_id,
@@ -3224,15 +3499,7 @@ impl WorkerHandle for ClientWorker {
                                             {
                                                 let msg_id = next_msg_id;
                                                 next_msg_id = next_msg_id.wrapping_add(1);
-                                                let _ = Self::p2p_udp_send_reliable(
-                                                    &socket,
-                                                    from,
-                                                    connection_id,
-                                                    data_plane_secret_copy,
-                                                    msg_id,
-                                                    &pkt,
-                                                )
-                                                .await;
+                                                let _ = reply.send(msg_id, &pkt).await;
                                             }
                                         }
                                     });
@@ -3264,23 +3531,36 @@ impl WorkerHandle for ClientWorker {
                                 }
 
                                 #[cfg(not(target_os = "android"))]
-                                if let Some(turn_url) = turn_urls.first() {
+                                if !turn_urls.is_empty() {
                                     let writer = Arc::clone(&self.writer);
                                     let source_client_id_copy = self.client_id;
                                     let peer_id_copy = peer_id;
                                     let connection_id_copy = connection_id;
                                     let data_plane_secret_copy = data_plane_secret;
-                                    let turn_url = turn_url.clone();
+                                    let turn_urls = turn_urls.clone();
                                     let username = turn_username.clone();
                                     let password = turn_password.clone();
+                                    let timeouts = TurnTimeouts::from_args(&self.args);
+                                    let send_window = self.args.p2p_send_window;
                                     let engine = Arc::clone(&self.engine);
                                     tokio::spawn(async move {
-                                        match Self::turn_allocate_udp(
-                                            &turn_url, &username, &password,
+                                        let credentials = (&username, &password);
+                                        match Self::turn_with_failover(
+                                            &turn_urls,
+                                            timeouts.setup,
+                                            |turn_url| async move {
+                                                Self::turn_allocate_udp(
+                                                    &turn_url,
+                                                    credentials.0,
+                                                    credentials.1,
+                                                    timeouts.op,
+                                                )
+                                                .await
+                                            },
                                         )
                                         .await
                                         {
-                                            Ok((turn_sock, relayed, realm, nonce)) => {
+                                            Ok((_, (turn_sock, relayed, realm, nonce))) => {
                                                 let relay_candidate = P2PCandidate {
                                                     candidate_type: P2PCandidateType::Relay,
                                                     transport: P2PTransport::Udp,
@@ -3303,19 +3583,39 @@ impl WorkerHandle for ClientWorker {
                                                     );
                                                 }
 
-                                                let mut permitted: HashSet<std::net::SocketAddr> =
-                                                    HashSet::new();
+                                                let nonce = Arc::new(Mutex::new(nonce));
+                                                let permitted: Arc<
+                                                    Mutex<HashSet<std::net::SocketAddr>>,
+                                                > = Arc::new(Mutex::new(HashSet::new()));
+                                                let transactions =
+                                                    Arc::new(TurnTransactions::default());
+                                                let _keepalive = TurnKeepalive(tokio::spawn(
+                                                    Self::turn_keepalive_udp(
+                                                        Arc::clone(&turn_sock),
+                                                        username.clone(),
+                                                        password.clone(),
+                                                        realm.clone(),
+                                                        Arc::clone(&nonce),
+                                                        Arc::clone(&permitted),
+                                                        Arc::clone(&transactions),
+                                                        timeouts,
+                                                    ),
+                                                ));
                                                 let mut reassembly = P2PUdpReassemblyState::new();
-                                                let mut inbox: VecDeque<(
-                                                    std::net::SocketAddr,
-                                                    Vec<u8>,
-                                                )> = VecDeque::new();
+                                                let inbox: Mutex<
+                                                    VecDeque<(std::net::SocketAddr, Vec<u8>)>,
+                                                > = Mutex::new(VecDeque::new());
+                                                let framer = ReliableFramer::new(
+                                                    connection_id_copy,
+                                                    data_plane_secret_copy,
+                                                )
+                                                .with_window(send_window);
                                                 let mut next_msg_id: u32 = 1;
                                                 let mut buf = vec![0u8; 4096];
 
                                                 loop {
-                                                    let (peer, data) = if let Some((p, d)) =
-                                                        inbox.pop_front()
+                                                    let queued = inbox.lock().await.pop_front();
+                                                    let (peer, data) = if let Some((p, d)) = queued
                                                     {
                                                         (p, d)
                                                     } else {
@@ -3327,6 +3627,10 @@ impl WorkerHandle for ClientWorker {
                                                                 return;
                                                             }
                                                         };
+                                                        // Answers to the keepalive's requests
+                                                        if transactions.deliver(&buf[..n]) {
+                                                            continue;
+                                                        }
                                                         let Some((peer, data)) =
                                                             Self::turn_parse_data_indication(
                                                                 &buf[..n],
@@ -3337,11 +3641,20 @@ impl WorkerHandle for ClientWorker {
                                                         (peer, data)
                                                     };
 
-                                                    if !permitted.contains(&peer) {
+                                                    if !permitted.lock().await.contains(&peer) {
+                                                        let current_nonce =
+                                                            nonce.lock().await.clone();
                                                         if let Err(e) =
                                                             Self::turn_create_permission(
-                                                                &turn_sock, peer, &username,
-                                                                &password, &realm, &nonce,
+                                                                &turn_sock,
+                                                                peer,
+                                                                &username,
+                                                                &password,
+                                                                &realm,
+                                                                &current_nonce,
+                                                                &transactions,
+                                                                Some(&inbox),
+                                                                timeouts.op,
                                                             )
                                                             .await
                                                         {
@@ -3350,7 +3663,7 @@ impl WorkerHandle for ClientWorker {
                                                                 e
                                                             );
                                                         } else {
-                                                            permitted.insert(peer);
+                                                            permitted.lock().await.insert(peer);
                                                         }
                                                     }
 
@@ -3358,161 +3671,116 @@ impl WorkerHandle for ClientWorker {
                                                         continue;
                                                     }
 
-                                                    if let Some((
-                                                        flags,
-                                                        msg_id,
-                                                        frag_idx,
-                                                        frag_cnt,
-                                                        ts,
-                                                        tag,
-                                                    )) = Self::p2p_udp_parse_header(&data)
-                                                    {
-                                                        if (flags & Self::P2P_UDP_FLAG_ACK) != 0 {
-                                                            continue;
-                                                        }
-                                                        let payload =
-                                                            &data[Self::P2P_UDP_HEADER_LEN..];
-                                                        if let Err(e) =
-                                                            Self::p2p_udp_validate_fragment(
-                                                                &data_plane_secret_copy,
-                                                                &connection_id_copy,
-                                                                flags,
+                                                    let (msg_id, frag_idx, frag_cnt, payload) =
+                                                        match framer.open(&data) {
+                                                            Ok(FramedPacket::Fragment {
                                                                 msg_id,
                                                                 frag_idx,
                                                                 frag_cnt,
-                                                                ts,
                                                                 payload,
-                                                                &tag,
-                                                                Self::p2p_now_secs(),
-                                                            )
-                                                        {
-                                                            security_metrics::record_p2p_auth_rejection();
-                                                            warn!(
-                                                                "TURN/UDP authentication rejected packet from {}: {}",
-                                                                peer, e
-                                                            );
-                                                            reassembly.record_invalid_source(peer);
-                                                            continue;
-                                                        }
-
-                                                        let full = match reassembly.accept_fragment(
-                                                            peer, msg_id, frag_idx, frag_cnt,
-                                                            payload,
-                                                        ) {
-                                                            Ok(v) => {
-                                                                let hdr = Self::p2p_udp_ack_packet(
-                                                                    connection_id_copy,
-                                                                    data_plane_secret_copy,
-                                                                    msg_id,
-                                                                );
-                                                                let _ = Self::turn_send_indication(
-                                                                    &turn_sock, peer, &hdr,
-                                                                )
-                                                                .await;
-                                                                v
+                                                            }) => (
+                                                                msg_id, frag_idx, frag_cnt, payload,
+                                                            ),
+                                                            Ok(FramedPacket::Ack { .. }) => {
+                                                                continue
                                                             }
                                                             Err(e) => {
-                                                                security_metrics::record_p2p_reassembly_rejection();
+                                                                security_metrics::record_p2p_auth_rejection();
                                                                 warn!(
-                                                                    "TURN/UDP reassembly rejected packet from {}: {}",
-                                                                    peer, e
+                                                                    "TURN/UDP authentication rejected packet from {}: {}",
+                                                                    common::addr_log_label(&peer), e
                                                                 );
                                                                 reassembly
                                                                     .record_invalid_source(peer);
                                                                 continue;
                                                             }
                                                         };
-                                                        let Some(full) = full else {
-                                                            continue;
-                                                        };
 
-                                                        let cmd =
-                                                            match Self::udp_decode_command(&full) {
-                                                                Ok(c) => c,
-                                                                Err(e) => {
-                                                                    warn!(
-                                                                    "TURN/UDP decode failed: {}",
-                                                                    e
-                                                                );
-                                                                    continue;
-                                                                }
-                                                            };
-
-                                                        let Command::V2(
-                                                            CommandV2::P2PInferenceRequest {
-                                                                connection_id: req_conn_id,
-                                                                task_id,
-                                                                model: _model,
-                                                                prompt,
-                                                                max_tokens,
-                                                                temperature,
-                                                                top_k,
-                                                                top_p,
-                                                                repeat_penalty,
-                                                                repeat_last_n,
-                                                                min_keep,
-                                                            },
-                                                        ) = cmd
-                                                        else {
+                                                    let full = match reassembly.accept_fragment(
+                                                        peer, msg_id, frag_idx, frag_cnt, payload,
+                                                    ) {
+                                                        Ok(v) => {
+                                                            let _ = Self::turn_send_indication(
+                                                                &turn_sock,
+                                                                peer,
+                                                                &framer.ack(
+                                                                    msg_id, frag_idx, frag_cnt,
+                                                                ),
+                                                            )
+                                                            .await;
+                                                            v
+                                                        }
+                                                        Err(e) => {
+                                                            security_metrics::record_p2p_reassembly_rejection();
+                                                            warn!(
+                                                                "TURN/UDP reassembly rejected packet from {}: {}",
+                                                                common::addr_log_label(&peer), e
+                                                            );
+                                                            reassembly.record_invalid_source(peer);
                                                             continue;
-                                                        };
-                                                        if req_conn_id != connection_id_copy {
+                                                        }
+                                                    };
+                                                    let Some(full) = full else {
+                                                        continue;
+                                                    };
+
+                                                    let cmd = match Self::udp_decode_command(&full)
+                                                    {
+                                                        Ok(c) => c,
+                                                        Err(e) => {
+                                                            warn!("TURN/UDP decode failed: {}", e);
                                                             continue;
                                                         }
+                                                    };
 
-                                                        // Stream inference over TURN/UDP data-plane.
-                                                        let sampling = crate::llm_engine::llama_engine::SamplingParams {
+                                                    let Command::V2(
+                                                        CommandV2::P2PInferenceRequest {
+                                                            connection_id: req_conn_id,
+                                                            task_id,
+                                                            model: _model,
+                                                            prompt,
+                                                            max_tokens,
                                                             temperature,
-                                                            top_k: top_k as i32,
+                                                            top_k,
                                                             top_p,
                                                             repeat_penalty,
                                                             repeat_last_n,
-                                                            seed: 0,
-                                                            min_keep: min_keep as usize,
-                                                            thinking_budget_tokens: None,
-                                                        };
+                                                            min_keep,
+                                                        },
+                                                    ) = cmd
+                                                    else {
+                                                        continue;
+                                                    };
+                                                    if req_conn_id != connection_id_copy {
+                                                        continue;
+                                                    }
+                                                    let reply = TurnIndication::new(
+                                                        &turn_sock,
+                                                        peer,
+                                                        framer,
+                                                        &inbox,
+                                                        &transactions,
+                                                    );
 
-                                                        let token_stream_res = {
-                                                            let engine_guard = engine.lock().await;
-                                                            let engine_ref = match engine_guard
-                                                                .as_ref()
-                                                            {
-                                                                Some(v) => v,
-                                                                None => {
-                                                                    let chunk = Command::V2(CommandV2::P2PInferenceChunk {
-                                                                        connection_id: connection_id_copy,
-                                                                        task_id: task_id.clone(),
-                                                                        seq: 0,
-                                                                        delta: String::new(),
-                                                                        phase: OutputPhase::Unknown,
-                                                                        done: true,
-                                                                        error: Some("Engine not initialized".to_string()),
-                                                                        analysis_tokens: 0,
-                                                                        final_tokens: 0,
-                                                                    });
-                                                                    if let Ok(pkt) =
-                                                                        Self::p2p_udp_encode_command_payload(&chunk)
-                                                                    {
-                                                                        let msg_id = next_msg_id;
-                                                                        next_msg_id = next_msg_id.wrapping_add(1);
-                                                                        let _ = Self::turn_send_reliable_over_indication(
-                                                                            &turn_sock,
-                                                                            peer,
-                                                                            connection_id_copy,
-                                                                            data_plane_secret_copy,
-                                                                            msg_id,
-                                                                            &pkt,
-                                                                            &mut inbox,
-                                                                        )
-                                                                        .await;
-                                                                    }
-                                                                    continue;
-                                                                }
-                                                            };
+                                                    // Stream inference over TURN/UDP data-plane.
+                                                    let sampling = crate::llm_engine::llama_engine::SamplingParams {
+                                                        temperature,
+                                                        top_k: top_k as i32,
+                                                        top_p,
+                                                        repeat_penalty,
+                                                        repeat_last_n,
+                                                        seed: 0,
+                                                        min_keep: min_keep as usize,
+                                                        thinking_budget_tokens: None,
+                                                        ..Default::default()
+                                                    };
 
-                                                            let AnyEngine::Llama(llama) =
-                                                                engine_ref
-                                                            else {
+                                                    let token_stream_res = {
+                                                        let engine_guard = engine.lock().await;
+                                                        let engine_ref = match engine_guard.as_ref()
+                                                        {
+                                                            Some(v) => v,
+                                                            None => {
                                                                 let chunk = Command::V2(CommandV2::P2PInferenceChunk {
                                                                     connection_id: connection_id_copy,
                                                                     task_id: task_id.clone(),
@@ -3520,7 +3788,7 @@ impl WorkerHandle for ClientWorker {
                                                                     delta: String::new(),
                                                                     phase: OutputPhase::Unknown,
                                                                     done: true,
-                                                                    error: Some("P2P TURN/UDP streaming is only supported for LLAMA engine".to_string()),
+                                                                    error: Some("Engine not initialized".to_string()),
                                                                     analysis_tokens: 0,
                                                                     final_tokens: 0,
                                                                 });
@@ -3529,38 +3797,97 @@ impl WorkerHandle for ClientWorker {
                                                                 {
                                                                     let msg_id = next_msg_id;
                                                                     next_msg_id = next_msg_id.wrapping_add(1);
-                                                                    let _ = Self::turn_send_reliable_over_indication(
-                                                                        &turn_sock,
-                                                                        peer,
-                                                                        connection_id_copy,
-                                                                        data_plane_secret_copy,
-                                                                        msg_id,
-                                                                        &pkt,
-                                                                        &mut inbox,
-                                                                    )
-                                                                    .await;
+                                                                    let _ = reply.send(msg_id, &pkt).await;
                                                                 }
                                                                 continue;
-                                                            };
+                                                            }
+                                                        };
 
-                                                            llama
-                                                                .stream_with_cached_model_sampling(
-                                                                    &prompt,
-                                                                    max_tokens as usize,
-                                                                    &sampling,
+                                                        let AnyEngine::Llama(llama) = engine_ref
+                                                        else {
+                                                            let chunk = Command::V2(CommandV2::P2PInferenceChunk {
+                                                                connection_id: connection_id_copy,
+                                                                task_id: task_id.clone(),
+                                                                seq: 0,
+                                                                delta: String::new(),
+                                                                phase: OutputPhase::Unknown,
+                                                                done: true,
+                                                                error: Some("P2P TURN/UDP streaming is only supported for LLAMA engine".to_string()),
+                                                                analysis_tokens: 0,
+                                                                final_tokens: 0,
+                                                            });
+                                                            if let Ok(pkt) =
+                                                                Self::p2p_udp_encode_command_payload(
+                                                                    &chunk,
                                                                 )
-                                                                .await
+                                                            {
+                                                                let msg_id = next_msg_id;
+                                                                next_msg_id =
+                                                                    next_msg_id.wrapping_add(1);
+                                                                let _ =
+                                                                    reply.send(msg_id, &pkt).await;
+                                                            }
+                                                            continue;
                                                         };
 
-                                                        let token_stream = match token_stream_res {
-                                                            Ok(s) => s,
+                                                        llama
+                                                            .stream_with_cached_model_sampling(
+                                                                &prompt,
+                                                                max_tokens as usize,
+                                                                &sampling,
+                                                            )
+                                                            .await
+                                                    };
+
+                                                    let token_stream = match token_stream_res {
+                                                        Ok(s) => s,
+                                                        Err(e) => {
+                                                            let chunk = Command::V2(
+                                                                CommandV2::P2PInferenceChunk {
+                                                                    connection_id:
+                                                                        connection_id_copy,
+                                                                    task_id: task_id.clone(),
+                                                                    seq: 0,
+                                                                    delta: String::new(),
+                                                                    phase: OutputPhase::Unknown,
+                                                                    done: true,
+                                                                    error: Some(e.to_string()),
+                                                                    analysis_tokens: 0,
+                                                                    final_tokens: 0,
+                                                                },
+                                                            );
+                                                            if let Ok(pkt) =
+                                                                Self::p2p_udp_encode_command_payload(
+                                                                    &chunk,
+                                                                )
+                                                            {
+                                                                let msg_id = next_msg_id;
+                                                                next_msg_id =
+                                                                    next_msg_id.wrapping_add(1);
+                                                                let _ =
+                                                                    reply.send(msg_id, &pkt).await;
+                                                            }
+                                                            continue;
+                                                        }
+                                                    };
+
+                                                    let mut token_stream = Box::pin(token_stream);
+                                                    let mut seq: u32 = 0;
+                                                    let mut control_filter =
+                                                        ControlTokenFilter::from_config();
+
+                                                    while let Some(piece_res) =
+                                                        token_stream.next().await
+                                                    {
+                                                        let piece = match piece_res {
+                                                            Ok(p) => p,
                                                             Err(e) => {
                                                                 let chunk = Command::V2(
                                                                     CommandV2::P2PInferenceChunk {
                                                                         connection_id:
                                                                             connection_id_copy,
                                                                         task_id: task_id.clone(),
-                                                                        seq: 0,
+                                                                        seq,
                                                                         delta: String::new(),
                                                                         phase: OutputPhase::Unknown,
                                                                         done: true,
@@ -3574,154 +3901,111 @@ impl WorkerHandle for ClientWorker {
                                                                 {
                                                                     let msg_id = next_msg_id;
                                                                     next_msg_id = next_msg_id.wrapping_add(1);
-                                                                    let _ = Self::turn_send_reliable_over_indication(
-                                                                        &turn_sock,
-                                                                        peer,
-                                                                        connection_id_copy,
-                                                                        data_plane_secret_copy,
-                                                                        msg_id,
-                                                                        &pkt,
-                                                                        &mut inbox,
-                                                                    )
-                                                                    .await;
+                                                                    let _ = reply.send(msg_id, &pkt).await;
                                                                 }
-                                                                continue;
+                                                                break;
                                                             }
                                                         };
+                                                        let filtered = control_filter.push(&piece);
+                                                        if filtered.is_empty() {
+                                                            continue;
+                                                        }
 
-                                                        let mut token_stream =
-                                                            Box::pin(token_stream);
-                                                        let mut seq: u32 = 0;
-
-                                                        while let Some(piece_res) =
-                                                            token_stream.next().await
-                                                        {
-                                                            let piece = match piece_res {
-                                                                Ok(p) => p,
-                                                                Err(e) => {
-                                                                    let chunk = Command::V2(CommandV2::P2PInferenceChunk {
-                                                                        connection_id: connection_id_copy,
-                                                                        task_id: task_id.clone(),
-                                                                        seq,
-                                                                        delta: String::new(),
-                                                                        phase: OutputPhase::Unknown,
-                                                                        done: true,
-                                                                        error: Some(e.to_string()),
-                                                                        analysis_tokens: 0,
-                                                                        final_tokens: 0,
-                                                                    });
-                                                                    if let Ok(pkt) =
-                                                                        Self::p2p_udp_encode_command_payload(&chunk)
-                                                                    {
-                                                                        let msg_id = next_msg_id;
-                                                                        next_msg_id = next_msg_id.wrapping_add(1);
-                                                                        let _ = Self::turn_send_reliable_over_indication(
-                                                                            &turn_sock,
-                                                                            peer,
-                                                                            connection_id_copy,
-                                                                            data_plane_secret_copy,
-                                                                            msg_id,
-                                                                            &pkt,
-                                                                            &mut inbox,
-                                                                        )
-                                                                        .await;
-                                                                    }
-                                                                    break;
-                                                                }
-                                                            };
-                                                            let filtered =
-                                                                filter_control_tokens(&piece);
-                                                            if filtered.is_empty() {
-                                                                continue;
+                                                        let mut start: usize = 0;
+                                                        let max_bytes: usize = 64;
+                                                        while start < filtered.len() {
+                                                            let mut end = (start + max_bytes)
+                                                                .min(filtered.len());
+                                                            while end < filtered.len()
+                                                                && !filtered.is_char_boundary(end)
+                                                            {
+                                                                end -= 1;
                                                             }
+                                                            if end == start {
+                                                                end = filtered
+                                                                    .char_indices()
+                                                                    .nth(1)
+                                                                    .map(|(i, _)| i)
+                                                                    .unwrap_or(filtered.len());
+                                                            }
+                                                            let delta =
+                                                                filtered[start..end].to_string();
+                                                            start = end;
 
-                                                            let mut start: usize = 0;
-                                                            let max_bytes: usize = 64;
-                                                            while start < filtered.len() {
-                                                                let mut end = (start + max_bytes)
-                                                                    .min(filtered.len());
-                                                                while end < filtered.len()
-                                                                    && !filtered
-                                                                        .is_char_boundary(end)
-                                                                {
-                                                                    end -= 1;
-                                                                }
-                                                                if end == start {
-                                                                    end = filtered
-                                                                        .char_indices()
-                                                                        .nth(1)
-                                                                        .map(|(i, _)| i)
-                                                                        .unwrap_or(filtered.len());
-                                                                }
-                                                                let delta = filtered[start..end]
-                                                                    .to_string();
-                                                                start = end;
-
-                                                                let chunk = Command::V2(
-                                                                    CommandV2::P2PInferenceChunk {
-                                                                        connection_id:
-                                                                            connection_id_copy,
-                                                                        task_id: task_id.clone(),
-                                                                        seq,
-                                                                        delta,
-                                                                        phase: OutputPhase::Unknown,
-                                                                        done: false,
-                                                                        error: None,
-                                                                        analysis_tokens: 0,
-                                                                        final_tokens: 0,
-                                                                    },
-                                                                );
-                                                                if let Ok(pkt) =
-                                                                    Self::p2p_udp_encode_command_payload(&chunk)
-                                                                {
-                                                                    let msg_id = next_msg_id;
-                                                                    next_msg_id = next_msg_id.wrapping_add(1);
-                                                                    let _ = Self::turn_send_reliable_over_indication(
-                                                                        &turn_sock,
-                                                                        peer,
+                                                            let chunk = Command::V2(
+                                                                CommandV2::P2PInferenceChunk {
+                                                                    connection_id:
                                                                         connection_id_copy,
-                                                                        data_plane_secret_copy,
-                                                                        msg_id,
-                                                                        &pkt,
-                                                                        &mut inbox,
-                                                                    )
-                                                                    .await;
-                                                                }
-                                                                seq = seq.wrapping_add(1);
+                                                                    task_id: task_id.clone(),
+                                                                    seq,
+                                                                    delta,
+                                                                    phase: OutputPhase::Unknown,
+                                                                    done: false,
+                                                                    error: None,
+                                                                    analysis_tokens: 0,
+                                                                    final_tokens: 0,
+                                                                },
+                                                            );
+                                                            if let Ok(pkt) =
+                                                                Self::p2p_udp_encode_command_payload(
+                                                                    &chunk,
+                                                                )
+                                                            {
+                                                                let msg_id = next_msg_id;
+                                                                next_msg_id =
+                                                                    next_msg_id.wrapping_add(1);
+                                                                let _ =
+                                                                    reply.send(msg_id, &pkt).await;
                                                             }
+                                                            seq = seq.wrapping_add(1);
                                                         }
+                                                    }
 
-                                                        let done = Command::V2(
-                                                            CommandV2::P2PInferenceDone {
+                                                    // An unfinished `<|...` at the end of output is plain text
+                                                    let tail = control_filter.finish();
+                                                    if !tail.is_empty() {
+                                                        let chunk = Command::V2(
+                                                            CommandV2::P2PInferenceChunk {
                                                                 connection_id: connection_id_copy,
-                                                                task_id,
-                                                                prompt_tokens: 0,
-                                                                completion_tokens: 0,
-                                                                total_tokens: 0,
+                                                                task_id: task_id.clone(),
+                                                                seq,
+                                                                delta: tail,
+                                                                phase: OutputPhase::Unknown,
+                                                                done: false,
+                                                                error: None,
                                                                 analysis_tokens: 0,
                                                                 final_tokens: 0,
                                                             },
                                                         );
                                                         if let Ok(pkt) =
                                                             Self::p2p_udp_encode_command_payload(
-                                                                &done,
+                                                                &chunk,
                                                             )
                                                         {
                                                             let msg_id = next_msg_id;
                                                             next_msg_id =
                                                                 next_msg_id.wrapping_add(1);
-                                                            let _ = Self::turn_send_reliable_over_indication(
-                                                                &turn_sock,
-                                                                peer,
-                                                                connection_id_copy,
-                                                                data_plane_secret_copy,
-                                                                msg_id,
-                                                                &pkt,
-                                                                &mut inbox,
-                                                            )
-                                                            .await;
+                                                            let _ = reply.send(msg_id, &pkt).await;
                                                         }
                                                     }
+
+                                                    let done =
+                                                        Command::V2(CommandV2::P2PInferenceDone {
+                                                            connection_id: connection_id_copy,
+                                                            task_id,
+                                                            prompt_tokens: 0,
+                                                            completion_tokens: 0,
+                                                            total_tokens: 0,
+                                                            analysis_tokens: 0,
+                                                            final_tokens: 0,
+                                                        });
+                                                    if let Ok(pkt) =
+                                                        Self::p2p_udp_encode_command_payload(&done)
+                                                    {
+                                                        let msg_id = next_msg_id;
+                                                        next_msg_id = next_msg_id.wrapping_add(1);
+                                                        let _ = reply.send(msg_id, &pkt).await;
+                                                    }
                                                 }
                                             }
                                             Err(e) => {
@@ -3751,161 +4035,68 @@ impl WorkerHandle for ClientWorker {
                                     continue;
                                 }
 
-                                // Try direct TCP connect to host/srflx candidates.
-                                let mut last_err: Option<anyhow::Error> = None;
-                                for c in &candidates {
-                                    if !matches!(
-                                        c.candidate_type,
-                                        P2PCandidateType::Host | P2PCandidateType::Srflx
-                                    ) {
+                                let Some(config) = p2p_turn_config.get(&connection_id) else {
+                                    let failed = CommandV2::P2PConnectionFailed {
+                                        peer_id: source_client_id,
+                                        connection_id,
+                                        error: "missing P2P connection config".to_string(),
+                                    };
+                                    self.send_command_v2(failed).await?;
+                                    continue;
+                                };
+                                let connector = WorkerP2PConnector {
+                                    config,
+                                    client_id: self.client_id,
+                                    connection_id,
+                                    cert_chain_path: &self.args.cert_chain_path,
+                                    #[cfg(not(target_os = "android"))]
+                                    timeouts: TurnTimeouts::from_args(&self.args),
+                                };
+                                let local =
+                                    Self::local_candidate_addrs(config.data_plane_addr).await;
+                                let link = match establish_p2p(
+                                    &connector,
+                                    source_client_id,
+                                    connection_id,
+                                    &local,
+                                    &candidates,
+                                    |cmd| self.send_command_v2(cmd),
+                                )
+                                .await
+                                {
+                                    Ok(link) => link,
+                                    Err(e) => {
+                                        warn!("P2P connection to peer failed: {}", e);
                                         continue;
                                     }
+                                };
 
-                                    let addr = c.addr.clone();
-                                    match timeout(Duration::from_secs(3), TcpStream::connect(&addr))
-                                        .await
-                                    {
-                                        Ok(Ok(stream)) => {
-                                            #[cfg(target_os = "android")]
-                                            let _ = &stream;
-                                            let established = CommandV2::P2PConnectionEstablished {
-                                                peer_id: source_client_id,
-                                                connection_id,
-                                                connection_type: P2PConnectionType::Direct,
-                                            };
-                                            self.send_command_v2(established).await?;
-
-                                            #[cfg(not(target_os = "android"))]
-                                            {
-                                                let Some(data_plane_secret) = p2p_turn_config
-                                                    .get(&connection_id)
-                                                    .map(|c| c.data_plane_secret)
-                                                else {
-                                                    warn!("Missing P2P data-plane secret for direct stream");
-                                                    continue;
-                                                };
-                                                let engine = Arc::clone(&self.engine);
-                                                tokio::spawn(async move {
-                                                    if let Err(e) =
-                                                        Self::serve_p2p_stream_with_engine(
-                                                            engine,
-                                                            stream,
-                                                            connection_id,
-                                                            data_plane_secret,
-                                                        )
-                                                        .await
-                                                    {
-                                                        error!("P2P direct data-plane stream error: {}", e);
-                                                    }
-                                                });
-                                            }
-
-                                            last_err = None;
-                                            break;
-                                        }
-                                        Ok(Err(e)) => last_err = Some(e.into()),
-                                        Err(e) => last_err = Some(anyhow!("connect timeout: {e}")),
+                                match link {
+                                    P2PLink::Direct(peer) => {
+                                        // The data-plane serve loop already answers
+                                        // whatever arrives on the punched path.
+                                        debug!(
+                                            "P2P hole punched to {}",
+                                            common::addr_log_label(&peer)
+                                        );
                                     }
-                                }
-
-                                if last_err.is_some() {
+                                    #[cfg(target_os = "android")]
+                                    P2PLink::Relay(relay) => match relay {},
                                     #[cfg(not(target_os = "android"))]
-                                    {
-                                        if let Some(turn_config) =
-                                            p2p_turn_config.get(&connection_id).cloned()
-                                        {
-                                            let username = turn_config.turn_username;
-                                            let password = turn_config.turn_password;
-                                            let data_plane_secret = turn_config.data_plane_secret;
-                                            if let Some(turn_url) = turn_config.turn_urls.first() {
-                                                match Self::turn_allocate_tcp(
-                                                    turn_url,
-                                                    &username,
-                                                    &password,
-                                                    &self.args.cert_chain_path,
-                                                )
-                                                .await
-                                                {
-                                                    Ok((mut tls, _relayed, realm, nonce)) => {
-                                                        // Find peer relay candidate from incoming list.
-                                                        let peer_relay = candidates
-                                                            .iter()
-                                                            .find(|c| {
-                                                                matches!(
-                                                                    c.candidate_type,
-                                                                    P2PCandidateType::Relay
-                                                                )
-                                                            })
-                                                            .and_then(|c| c.addr.parse().ok());
-                                                        if let Some(peer_relay) = peer_relay {
-                                                            match Self::turn_connect_peer(
-                                                                &mut tls,
-                                                                peer_relay,
-                                                                &username,
-                                                                &password,
-                                                                &realm,
-                                                                &nonce,
-                                                            )
-                                                            .await
-                                                            {
-                                                                Ok(conn_id) => {
-                                                                    match Self::turn_connection_bind(
-                                                                        turn_url,
-                                                                        &conn_id,
-                                                                        &username,
-                                                                        &password,
-                                                                        &realm,
-                                                                        &nonce,
-                                                                        &self.args.cert_chain_path,
-                                                                    )
-                                                                    .await
-                                                                    {
-                                                                        Ok(data_stream) => {
-                                                                            let established = CommandV2::P2PConnectionEstablished {
-                                                                                peer_id: source_client_id,
-                                                                                connection_id,
-                                                                                connection_type: P2PConnectionType::TURN,
-                                                                            };
-                                                                            self.send_command_v2(established).await?;
-
-                                                                            let engine = Arc::clone(&self.engine);
-                                                                            tokio::spawn(async move {
-                                                                                if let Err(e) = Self::serve_p2p_io_with_engine(engine, data_stream, connection_id, data_plane_secret).await {
-                                                                                    error!("TURN data-plane stream error: {}", e);
-                                                                                }
-                                                                            });
-                                                                            continue;
-                                                                        }
-                                                                        Err(e2) => {
-                                                                            last_err = Some(e2);
-                                                                        }
-                                                                    }
-                                                                }
-                                                                Err(e2) => {
-                                                                    last_err = Some(e2);
-                                                                }
-                                                            }
-                                                        }
-                                                    }
-                                                    Err(e2) => {
-                                                        last_err = Some(e2);
-                                                    }
-                                                }
+                                    P2PLink::Relay(relay) => {
+                                        let engine = Arc::clone(&self.engine);
+                                        tokio::spawn(async move {
+                                            if let Err(e) = Self::serve_p2p_transport_with_engine(
+                                                engine,
+                                                relay,
+                                                connection_id,
+                                            )
+                                            .await
+                                            {
+                                                error!("P2P TURN data-plane stream error: {}", e);
                                             }
-                                        }
+                                        });
                                     }
-
-                                    let error_message = last_err
-                                        .as_ref()
-                                        .map(|e| e.to_string())
-                                        .unwrap_or_else(|| "unknown error".to_string());
-
-                                    let failed = CommandV2::P2PConnectionFailed {
-                                        peer_id: source_client_id,
-                                        connection_id,
-                                        error: format!("connect failed: {}", error_message),
-                                    };
-                                    self.send_command_v2(failed).await?;
                                 }
                             }
 
@@ -3920,6 +4111,343 @@ impl WorkerHandle for ClientWorker {
     }
 }
 
+/// Per-candidate budget for a hole punch before moving on.
+const P2P_DIRECT_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Host addresses: reachable without crossing a NAT, at least on the same
+/// network.
+fn is_host_candidate(ip: std::net::IpAddr) -> bool {
+    match ip {
+        std::net::IpAddr::V4(v4) => {
+            v4.is_private() || v4.is_loopback() || v4.is_link_local() || v4.is_unspecified()
+        }
+        std::net::IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            v6.is_loopback()
+                || v6.is_unspecified()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// Same /24 (IPv4) or /64 (IPv6) between two host addresses.
+fn same_subnet(a: std::net::IpAddr, b: std::net::IpAddr) -> bool {
+    if !is_host_candidate(a) || !is_host_candidate(b) {
+        return false;
+    }
+    match (a, b) {
+        (std::net::IpAddr::V4(a), std::net::IpAddr::V4(b)) => a.octets()[..3] == b.octets()[..3],
+        (std::net::IpAddr::V6(a), std::net::IpAddr::V6(b)) => {
+            a.segments()[..4] == b.segments()[..4]
+        }
+        _ => false,
+    }
+}
+
+/// ICE type preference (RFC 8445 §5.1.2.2) of a local address. Local
+/// addresses carry no declared type, so private ones count as host
+/// candidates and public ones as server-reflexive.
+fn local_type_preference(addr: &std::net::SocketAddr) -> u64 {
+    if is_host_candidate(addr.ip()) {
+        126
+    } else {
+        100
+    }
+}
+
+/// ICE type preference of a candidate as the peer declared it.
+fn declared_type_preference(candidate_type: &P2PCandidateType) -> u64 {
+    match candidate_type {
+        P2PCandidateType::Host => 126,
+        P2PCandidateType::Srflx => 100,
+        P2PCandidateType::Relay => 0,
+    }
+}
+
+/// Local/peer address pairs to hole punch, best first: same-subnet pairs,
+/// then by the RFC 8445 §6.1.2.3 pair priority of their type preferences,
+/// then by the priority the peer declared. Only the peer's host and srflx
+/// candidates are paired; `establish_p2p` falls back to the relay one once
+/// every pair has failed. A peer address that no local address shares a
+/// family with is paired with that family's unspecified address, leaving
+/// the route to the OS. Ties keep the input order.
+pub(super) fn prioritize_candidates(
+    local: &[std::net::SocketAddr],
+    peer: &[P2PCandidate],
+) -> Vec<(std::net::SocketAddr, std::net::SocketAddr)> {
+    let mut pairs = Vec::new();
+    for candidate in peer {
+        if !matches!(
+            candidate.candidate_type,
+            P2PCandidateType::Host | P2PCandidateType::Srflx
+        ) {
+            continue;
+        }
+        let Ok(addr) = candidate.addr.parse::<std::net::SocketAddr>() else {
+            continue;
+        };
+        let declared = (
+            declared_type_preference(&candidate.candidate_type),
+            candidate.priority,
+        );
+        let mut same_family = local
+            .iter()
+            .filter(|l| l.is_ipv4() == addr.is_ipv4())
+            .peekable();
+        if same_family.peek().is_none() {
+            let any = if addr.is_ipv4() {
+                std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED)
+            } else {
+                std::net::IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED)
+            };
+            pairs.push((std::net::SocketAddr::new(any, 0), addr, declared));
+        }
+        pairs.extend(same_family.map(|l| (*l, addr, declared)));
+    }
+    pairs.sort_by_cached_key(|(l, p, (d, declared_priority))| {
+        let g = local_type_preference(l);
+        let pair_priority = (g.min(*d) << 32) + 2 * g.max(*d) + u64::from(g > *d);
+        std::cmp::Reverse((
+            same_subnet(l.ip(), p.ip()),
+            pair_priority,
+            *declared_priority,
+        ))
+    });
+    pairs.into_iter().map(|(l, p, _)| (l, p)).collect()
+}
+
+/// The two ways `establish_p2p` can reach a peer. The worker uses real
+/// sockets; tests substitute fakes.
+pub(super) trait P2PConnector {
+    type Transport: P2PDataTransport;
+    /// UDP hole punch from the connection's data-plane socket to one of the
+    /// peer's host/srflx candidates. Succeeds once the peer acknowledges a
+    /// probe.
+    async fn punch(&self, peer: std::net::SocketAddr) -> Result<()>;
+    /// Connection through a TURN server to the peer's relay candidate.
+    async fn relay(&self, peer_relay: std::net::SocketAddr) -> Result<Self::Transport>;
+}
+
+/// Path `establish_p2p` settled on.
+pub(super) enum P2PLink<T> {
+    /// Hole punched to this peer address. The data-plane socket's serve loop
+    /// already answers it, so there is no separate transport to run.
+    Direct(std::net::SocketAddr),
+    /// Relayed transport to serve on its own.
+    Relay(T),
+}
+
+impl<T> P2PLink<T> {
+    /// Type reported in `P2PConnectionEstablished`. The relay fallback goes
+    /// through a TURN server, so it is `TURN`; `P2PConnectionType::Relay`
+    /// means relaying through gpuf-s, which `establish_p2p` never does.
+    pub(super) fn connection_type(&self) -> P2PConnectionType {
+        match self {
+            P2PLink::Direct(_) => P2PConnectionType::Direct,
+            P2PLink::Relay(_) => P2PConnectionType::TURN,
+        }
+    }
+}
+
+/// Connects to `peer_id` from its candidates: hole punches to each
+/// host/srflx candidate first, in `prioritize_candidates` order against
+/// `local`, then the relay candidate through TURN. Reports the outcome to
+/// gpuf-s through `emit` as `P2PConnectionEstablished` or
+/// `P2PConnectionFailed`; a failure is also the returned error.
+pub(super) async fn establish_p2p<C, F, Fut>(
+    connector: &C,
+    peer_id: [u8; 16],
+    connection_id: [u8; 16],
+    local: &[std::net::SocketAddr],
+    candidates: &[P2PCandidate],
+    mut emit: F,
+) -> Result<P2PLink<C::Transport>>
+where
+    C: P2PConnector,
+    F: FnMut(CommandV2) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    let mut last_err = anyhow!("no usable candidate");
+    let mut established = None;
+    let mut punched = HashSet::new();
+    for (_, peer) in prioritize_candidates(local, candidates) {
+        // Every local address shares the one data-plane socket
+        if !punched.insert(peer) {
+            continue;
+        }
+        match timeout(P2P_DIRECT_CONNECT_TIMEOUT, connector.punch(peer)).await {
+            Ok(Ok(())) => {
+                established = Some(P2PLink::Direct(peer));
+                break;
+            }
+            Ok(Err(e)) => last_err = e,
+            Err(e) => last_err = anyhow!("hole punch timeout: {e}"),
+        }
+    }
+
+    if established.is_none() {
+        let peer_relay = candidates
+            .iter()
+            .find(|c| matches!(c.candidate_type, P2PCandidateType::Relay))
+            .and_then(|c| c.addr.parse().ok());
+        if let Some(peer_relay) = peer_relay {
+            match connector.relay(peer_relay).await {
+                Ok(transport) => established = Some(P2PLink::Relay(transport)),
+                Err(e) => last_err = e,
+            }
+        }
+    }
+
+    match established {
+        Some(link) => {
+            emit(CommandV2::P2PConnectionEstablished {
+                peer_id,
+                connection_id,
+                connection_type: link.connection_type(),
+            })
+            .await?;
+            Ok(link)
+        }
+        None => {
+            emit(CommandV2::P2PConnectionFailed {
+                peer_id,
+                connection_id,
+                error: format!("connect failed: {}", last_err),
+            })
+            .await?;
+            Err(last_err)
+        }
+    }
+}
+
+/// Keeps a TURN allocation refreshed until dropped.
+#[cfg(not(target_os = "android"))]
+struct TurnKeepalive(tokio::task::JoinHandle<()>);
+
+#[cfg(not(target_os = "android"))]
+impl Drop for TurnKeepalive {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// TURN relay to a P2P peer as dialled by `WorkerP2PConnector`, holding its
+/// allocation for as long as it is served.
+#[cfg(not(target_os = "android"))]
+struct P2PRelay {
+    transport: TurnTcp<tokio_rustls::client::TlsStream<TcpStream>>,
+    _keepalive: TurnKeepalive,
+}
+
+#[cfg(not(target_os = "android"))]
+impl P2PDataTransport for P2PRelay {
+    async fn send(&self, msg_id: u32, payload: &[u8]) -> Result<()> {
+        self.transport.send(msg_id, payload).await
+    }
+
+    async fn recv(&self) -> Result<Vec<u8>> {
+        self.transport.recv().await
+    }
+}
+
+/// TURN relays are desktop only, so Android never has one to serve.
+#[cfg(target_os = "android")]
+enum P2PRelay {}
+
+#[cfg(target_os = "android")]
+impl P2PDataTransport for P2PRelay {
+    async fn send(&self, _msg_id: u32, _payload: &[u8]) -> Result<()> {
+        match *self {}
+    }
+
+    async fn recv(&self) -> Result<Vec<u8>> {
+        match *self {}
+    }
+}
+
+struct WorkerP2PConnector<'a> {
+    config: &'a P2PConnectionRuntimeConfig,
+    client_id: [u8; 16],
+    connection_id: [u8; 16],
+    cert_chain_path: &'a str,
+    #[cfg(not(target_os = "android"))]
+    timeouts: TurnTimeouts,
+}
+
+impl P2PConnector for WorkerP2PConnector<'_> {
+    type Transport = P2PRelay;
+
+    #[cfg(target_os = "android")]
+    async fn punch(&self, _peer: std::net::SocketAddr) -> Result<()> {
+        let _ = (self.client_id, self.connection_id);
+        Err(anyhow!("the P2P data plane is not served on Android"))
+    }
+
+    /// Sends a signed probe until the peer ACKs it, which opens our NAT
+    /// mapping towards the peer on the way.
+    #[cfg(not(target_os = "android"))]
+    async fn punch(&self, peer: std::net::SocketAddr) -> Result<()> {
+        let framer = ReliableFramer::new(self.connection_id, self.config.data_plane_secret);
+        let probe = ClientWorker::p2p_udp_encode_command_payload(&Command::V2(
+            CommandV2::P2PConnectionEstablished {
+                peer_id: self.client_id,
+                connection_id: self.connection_id,
+                connection_type: P2PConnectionType::Direct,
+            },
+        ))?;
+        let mut pending = self.config.punches.register(peer);
+        loop {
+            for packet in framer.fragment(PunchAcks::PROBE_MSG_ID, &probe)? {
+                self.config.socket.send_to(&packet, peer).await?;
+            }
+            match timeout(ReliableFramer::ACK_TIMEOUT, &mut pending.acked).await {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(_)) => return Err(anyhow!("hole punch to peer was superseded")),
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[cfg(target_os = "android")]
+    async fn relay(&self, _peer_relay: std::net::SocketAddr) -> Result<P2PRelay> {
+        let _ = (self.config, self.cert_chain_path);
+        Err(anyhow!("TURN relay is not available on Android"))
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn relay(&self, peer_relay: std::net::SocketAddr) -> Result<P2PRelay> {
+        let username = &self.config.turn_username;
+        let password = &self.config.turn_password;
+        let cert_chain_path = self.cert_chain_path;
+        let op_timeout = self.timeouts.op;
+        let (_, (data_stream, keepalive)) = ClientWorker::turn_with_failover(
+            &self.config.turn_urls,
+            self.timeouts.setup,
+            |turn_url| async move {
+                ClientWorker::turn_relay_to_peer(
+                    &turn_url,
+                    peer_relay,
+                    username,
+                    password,
+                    cert_chain_path,
+                    op_timeout,
+                )
+                .await
+            },
+        )
+        .await?;
+        Ok(P2PRelay {
+            transport: TurnTcp::new(
+                data_stream,
+                self.connection_id,
+                self.config.data_plane_secret,
+            ),
+            _keepalive: TurnKeepalive(keepalive),
+        })
+    }
+}
+
 async fn connect_control_stream(
     args: &Args,
     addr: std::net::SocketAddr,
@@ -3930,7 +4458,7 @@ async fn connect_control_stream(
         if !addr.ip().is_loopback() {
             warn!(
                 "SECURITY: gpuf-s control connection to {} is plaintext TCP; enable --control-tls before remote production deployment",
-                args.server_addr
+                common::addr_log_label(&addr)
             );
         }
         let (reader, writer) = tcp_stream.into_split();
@@ -4020,7 +4548,11 @@ pub async fn create_proxy_connection(
     let tcp_stream = match TcpStream::connect(addr).await {
         Ok(stream) => stream,
         Err(e) => {
-            error!(" create proxy connection failed {}: {}", addr, e);
+            error!(
+                " create proxy connection failed {}: {}",
+                common::addr_log_label(&addr),
+                e
+            );
             return Err(e.into());
         }
     };
@@ -4157,7 +4689,11 @@ pub async fn create_proxy_connection(
     let mut tcp_stream = match TcpStream::connect(addr).await {
         Ok(stream) => stream,
         Err(e) => {
-            error!("create proxy connection failed {}: {}", addr, e);
+            error!(
+                "create proxy connection failed {}: {}",
+                common::addr_log_label(&addr),
+                e
+            );
             return Err(e.into());
         }
     };
@@ -4409,4 +4945,322 @@ mod control_stream_tests {
         server.await??;
         Ok(())
     }
+
+    #[test]
+    fn turns_urls_parse_ipv4_hostnames_and_ipv6_literals() {
+        let parse = |url| ClientWorker::parse_turns_url(url).ok();
+        assert_eq!(
+            parse("turns://203.0.113.7:5350"),
+            Some(("203.0.113.7".to_string(), 5350))
+        );
+        assert_eq!(
+            parse("turns://turn.example.com"),
+            Some(("turn.example.com".to_string(), DEFAULT_TURNS_PORT))
+        );
+        assert_eq!(
+            parse("turns://[2001:db8::1]:5350?transport=tcp"),
+            Some(("2001:db8::1".to_string(), 5350))
+        );
+        assert_eq!(
+            parse("turns://[2001:db8::1]"),
+            Some(("2001:db8::1".to_string(), DEFAULT_TURNS_PORT))
+        );
+        assert_eq!(parse("turn://turn.example.com"), None);
+    }
+
+    /// Relayed transport standing in for a TURN connection.
+    struct FakeRelay(&'static str);
+
+    impl P2PDataTransport for FakeRelay {
+        async fn send(&self, _msg_id: u32, _payload: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        async fn recv(&self) -> Result<Vec<u8>> {
+            Ok(self.0.as_bytes().to_vec())
+        }
+    }
+
+    /// Direct candidates that never answer a hole punch and a TURN relay
+    /// that does.
+    struct UnreachableDirect {
+        punch_attempts: std::cell::RefCell<Vec<String>>,
+    }
+
+    impl P2PConnector for UnreachableDirect {
+        type Transport = FakeRelay;
+
+        async fn punch(&self, peer: std::net::SocketAddr) -> Result<()> {
+            self.punch_attempts.borrow_mut().push(peer.to_string());
+            Err(anyhow!("no answer to hole punch"))
+        }
+
+        async fn relay(&self, _peer_relay: std::net::SocketAddr) -> Result<FakeRelay> {
+            Ok(FakeRelay("relayed"))
+        }
+    }
+
+    #[tokio::test]
+    async fn establish_p2p_falls_back_to_relay_when_direct_fails() {
+        let candidate = |candidate_type, addr: &str| P2PCandidate {
+            candidate_type,
+            transport: P2PTransport::Udp,
+            addr: addr.to_string(),
+            priority: 0,
+        };
+        let candidates = [
+            candidate(P2PCandidateType::Host, "192.168.1.20:17000"),
+            candidate(P2PCandidateType::Srflx, "203.0.113.7:17000"),
+            candidate(P2PCandidateType::Relay, "198.51.100.9:49152"),
+        ];
+        let connector = UnreachableDirect {
+            punch_attempts: Default::default(),
+        };
+        let emitted = std::cell::RefCell::new(Vec::new());
+
+        let local = ["192.168.1.10:0".parse().unwrap()];
+        let link = establish_p2p(&connector, [1; 16], [2; 16], &local, &candidates, |cmd| {
+            emitted.borrow_mut().push(cmd);
+            std::future::ready(Ok(()))
+        })
+        .await
+        .expect("relay should have connected");
+
+        assert_eq!(
+            *connector.punch_attempts.borrow(),
+            ["192.168.1.20:17000", "203.0.113.7:17000"]
+        );
+        assert_eq!(link.connection_type(), P2PConnectionType::TURN);
+        let P2PLink::Relay(relay) = link else {
+            panic!("expected the relayed transport");
+        };
+        assert_eq!(relay.recv().await.unwrap(), b"relayed");
+        let emitted = emitted.into_inner();
+        assert_eq!(emitted.len(), 1);
+        assert!(matches!(
+            &emitted[0],
+            CommandV2::P2PConnectionEstablished {
+                peer_id: [1, ..],
+                connection_id: [2, ..],
+                connection_type: P2PConnectionType::TURN,
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn hole_punch_succeeds_once_the_peer_acks_a_probe() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        let config = P2PConnectionRuntimeConfig {
+            turn_urls: Vec::new(),
+            turn_username: String::new(),
+            turn_password: String::new(),
+            _peer_hex: String::new(),
+            data_plane_secret: [7; 32],
+            data_plane_addr: socket.local_addr().unwrap(),
+            socket: Arc::clone(&socket),
+            punches: Arc::new(PunchAcks::default()),
+        };
+        let framer = ReliableFramer::new([2; 16], [7; 32]);
+
+        // The peer ACKs the probe; reads on our socket stay with the
+        // stand-in serve loop, which routes the ACK to the punch.
+        let peer_side = async {
+            let mut buf = vec![0u8; 2048];
+            let (n, from) = peer.recv_from(&mut buf).await.unwrap();
+            let Ok(FramedPacket::Fragment {
+                msg_id,
+                frag_idx,
+                frag_cnt,
+                ..
+            }) = framer.open(&buf[..n])
+            else {
+                panic!("probe was not a signed fragment");
+            };
+            peer.send_to(&framer.ack(msg_id, frag_idx, frag_cnt), from)
+                .await
+                .unwrap();
+        };
+        let punches = Arc::clone(&config.punches);
+        let serve_loop = tokio::spawn({
+            let socket = Arc::clone(&socket);
+            async move {
+                let mut buf = vec![0u8; 2048];
+                loop {
+                    let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+                    if let Ok(FramedPacket::Ack { msg_id, .. }) = framer.open(&buf[..n]) {
+                        punches.deliver(from, msg_id);
+                    }
+                }
+            }
+        });
+
+        let connector = WorkerP2PConnector {
+            config: &config,
+            client_id: [1; 16],
+            connection_id: [2; 16],
+            cert_chain_path: "",
+            timeouts: TurnTimeouts {
+                op: Duration::from_secs(1),
+                setup: Duration::from_secs(1),
+            },
+        };
+        let (punched, ()) = tokio::join!(
+            timeout(P2P_DIRECT_CONNECT_TIMEOUT, connector.punch(peer_addr)),
+            peer_side
+        );
+        serve_loop.abort();
+
+        punched.expect("hole punch timed out").unwrap();
+    }
+
+    fn addrs(list: &[&str]) -> Vec<std::net::SocketAddr> {
+        list.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    fn candidates(list: &[(P2PCandidateType, &str, u32)]) -> Vec<P2PCandidate> {
+        list.iter()
+            .map(|(candidate_type, addr, priority)| P2PCandidate {
+                candidate_type: candidate_type.clone(),
+                transport: P2PTransport::Udp,
+                addr: addr.to_string(),
+                priority: *priority,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_subnet_pairs_rank_above_cross_nat_pairs() {
+        let local = addrs(&["192.168.1.10:0", "198.51.100.4:0"]);
+        let peer = addrs(&["203.0.113.7:17000", "10.0.0.5:17000", "192.168.1.20:17000"]);
+        let declared = candidates(&[
+            (P2PCandidateType::Srflx, "203.0.113.7:17000", 100),
+            (P2PCandidateType::Host, "10.0.0.5:17000", 200),
+            (P2PCandidateType::Host, "192.168.1.20:17000", 200),
+        ]);
+
+        let pairs = prioritize_candidates(&local, &declared);
+
+        assert_eq!(pairs.len(), 6);
+        assert_eq!(pairs[0], (local[0], peer[2]));
+        // Host/host across subnets still beats anything through a NAT.
+        assert_eq!(pairs[1], (local[0], peer[1]));
+        let last = &pairs[pairs.len() - 1];
+        assert_eq!(*last, (local[1], peer[0]));
+    }
+
+    #[test]
+    fn pairs_rank_by_the_declared_type_and_priority() {
+        let local = addrs(&["198.51.100.4:0"]);
+        let declared = candidates(&[
+            (P2PCandidateType::Srflx, "203.0.113.7:17000", 100),
+            (P2PCandidateType::Host, "203.0.113.8:17000", 150),
+            (P2PCandidateType::Host, "203.0.113.9:17000", 200),
+            (P2PCandidateType::Relay, "203.0.113.10:49152", 50),
+        ]);
+
+        let pairs = prioritize_candidates(&local, &declared);
+
+        // Public host candidates still rank as hosts, the higher declared
+        // priority first; the relay candidate is left to the TURN fallback.
+        let order: Vec<_> = pairs.iter().map(|(_, p)| p.to_string()).collect();
+        assert_eq!(
+            order,
+            [
+                "203.0.113.9:17000",
+                "203.0.113.8:17000",
+                "203.0.113.7:17000"
+            ]
+        );
+    }
+
+    #[test]
+    fn candidate_pairs_never_mix_address_families() {
+        let local = addrs(&["192.168.1.10:0", "[fd00::10]:0"]);
+        let peer = addrs(&["[fd00::20]:17000", "203.0.113.7:17000"]);
+        let declared = candidates(&[
+            (P2PCandidateType::Host, "[fd00::20]:17000", 200),
+            (P2PCandidateType::Srflx, "203.0.113.7:17000", 100),
+        ]);
+
+        let pairs = prioritize_candidates(&local, &declared);
+
+        assert_eq!(
+            pairs,
+            [(local[1], peer[0]), (local[0], peer[1])],
+            "the same-/64 IPv6 pair ranks first"
+        );
+    }
+
+    #[test]
+    fn peers_of_a_family_without_a_local_address_are_still_tried() {
+        let local = addrs(&["192.168.1.10:0"]);
+        let peer = addrs(&["[2001:db8::20]:17000", "203.0.113.7:17000"]);
+        let declared = candidates(&[
+            (P2PCandidateType::Host, "[2001:db8::20]:17000", 200),
+            (P2PCandidateType::Srflx, "203.0.113.7:17000", 100),
+        ]);
+
+        let pairs = prioritize_candidates(&local, &declared);
+
+        let unspecified_v6 = addrs(&["[::]:0"])[0];
+        assert_eq!(pairs, [(unspecified_v6, peer[0]), (local[0], peer[1])]);
+    }
+
+    #[tokio::test]
+    async fn output_below_the_chunk_threshold_still_flushes() {
+        let engine = global_engine();
+        let cancel_state = Arc::new(CancelState::new());
+        let relay = InferenceRelay {
+            engine: &engine,
+            cancel_state: &cancel_state,
+            chunk_bytes: 64,
+        };
+        let pieces = ["Hi", " there"].map(|piece| Ok(piece.to_string()));
+        let mut sent = Vec::new();
+        relay
+            .relay(
+                "task-1".to_string(),
+                1,
+                futures_util::stream::iter(pieces),
+                |chunk| {
+                    sent.push(chunk);
+                    async { Ok(()) }
+                },
+            )
+            .await
+            .unwrap();
+
+        let [CommandV1::InferenceResultChunk {
+            delta, done: false, ..
+        }, CommandV1::InferenceResultChunk {
+            delta: tail,
+            done: true,
+            completion_tokens,
+            ..
+        }] = sent.as_slice()
+        else {
+            panic!("expected one partial chunk and a done chunk");
+        };
+        assert_eq!(delta, "Hi there");
+        assert!(tail.is_empty());
+        assert_eq!(*completion_tokens, 2);
+    }
+
+    #[test]
+    fn chatml_fallback_wraps_each_turn_in_markers() {
+        let messages =
+            [("system", "Be brief."), ("user", "Hi")].map(|(role, content)| common::ChatMessage {
+                role: role.to_string(),
+                content: content.to_string(),
+            });
+
+        assert_eq!(
+            chatml_prompt(&messages),
+            "<|im_start|>system\nBe brief.<|im_end|>\n\
+             <|im_start|>user\nHi<|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+    }
 }
@@ -1,5 +1,9 @@
 use super::*;
+#[cfg(not(target_os = "android"))]
+use crate::util::security_metrics;
 use bincode::{self as bincode, config as bincode_config};
+#[cfg(not(target_os = "android"))]
+use bytes::BytesMut;
 use crc32fast::Hasher as Crc32;
 use hmac::{Hmac, Mac};
 use sha1::Sha1;
@@ -10,15 +14,164 @@ use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::io::AsyncReadExt;
+#[cfg(not(target_os = "android"))]
+use tokio::io::AsyncWriteExt;
 use tokio::net::UdpSocket;
-use tokio::time::timeout;
+use tokio::time::{timeout, timeout_at};
 #[cfg(not(target_os = "android"))]
 use url::Url;
 
 use anyhow::{anyhow, Result};
-use common::{Command, CommandV2, MAX_MESSAGE_SIZE};
+use common::{command_bincode_config, Command, CommandV2, MAX_MESSAGE_SIZE};
+#[cfg(not(target_os = "android"))]
+use common::{read_command, write_command};
+#[cfg(not(target_os = "android"))]
+use tracing::info;
 use tracing::warn;
 
+// Port of `stun:` URLs that do not name one (RFC 7064)
+const DEFAULT_STUN_PORT: u16 = 3478;
+
+/// Result of a TURN Refresh the server answered.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum TurnRefreshOutcome {
+    Refreshed { lifetime: u32 },
+    StaleNonce { nonce: String },
+}
+
+/// Time limits of TURN relay setup, from `--turn-op-timeout-secs` and
+/// `--turn-setup-timeout-secs`.
+#[cfg(not(target_os = "android"))]
+#[derive(Debug, Clone, Copy)]
+pub(super) struct TurnTimeouts {
+    /// One step: the TLS connect or a single request/response exchange.
+    pub op: Duration,
+    /// The whole setup through one server before failing over to the next.
+    pub setup: Duration,
+}
+
+#[cfg(not(target_os = "android"))]
+impl TurnTimeouts {
+    pub(super) fn from_args(args: &Args) -> Self {
+        Self {
+            op: Duration::from_secs(args.turn_op_timeout_secs.max(1)),
+            setup: Duration::from_secs(args.turn_setup_timeout_secs.max(1)),
+        }
+    }
+}
+
+/// STUN requests on a TURN/UDP socket still waiting for their response.
+/// Several tasks send on the socket but reads stay with whoever is serving
+/// relayed data, so a response is handed over by transaction ID.
+#[cfg(not(target_os = "android"))]
+#[derive(Default)]
+pub(super) struct TurnTransactions {
+    pending: std::sync::Mutex<HashMap<[u8; 12], tokio::sync::oneshot::Sender<Vec<u8>>>>,
+}
+
+#[cfg(not(target_os = "android"))]
+impl TurnTransactions {
+    fn register(&self, txid: [u8; 12]) -> tokio::sync::oneshot::Receiver<Vec<u8>> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(txid, tx);
+        rx
+    }
+
+    fn forget(&self, txid: &[u8; 12]) {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(txid);
+    }
+
+    /// Hands `msg` to the request it answers. False when it answers none.
+    pub(super) fn deliver(&self, msg: &[u8]) -> bool {
+        let Some(txid) = msg.get(8..20).and_then(|t| <[u8; 12]>::try_from(t).ok()) else {
+            return false;
+        };
+        let waiter = self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&txid);
+        match waiter {
+            Some(tx) => {
+                let _ = tx.send(msg.to_vec());
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Hole-punch probes on a P2P data-plane socket still waiting for the peer's
+/// ACK. The socket's serve loop owns its reads, so it hands each probe ACK
+/// over by source address.
+#[cfg(not(target_os = "android"))]
+#[derive(Default)]
+pub(super) struct PunchAcks {
+    pending: std::sync::Mutex<HashMap<SocketAddr, tokio::sync::oneshot::Sender<()>>>,
+}
+
+/// Probe registered with `PunchAcks`; unregisters itself when dropped.
+#[cfg(not(target_os = "android"))]
+pub(super) struct PendingPunch<'a> {
+    acks: &'a PunchAcks,
+    peer: SocketAddr,
+    pub(super) acked: tokio::sync::oneshot::Receiver<()>,
+}
+
+#[cfg(not(target_os = "android"))]
+impl PunchAcks {
+    /// Message ID of probes. Replies on the data plane count up from 1, so
+    /// it never collides with one.
+    pub(super) const PROBE_MSG_ID: u32 = 0;
+
+    pub(super) fn register(&self, peer: SocketAddr) -> PendingPunch<'_> {
+        let (tx, acked) = tokio::sync::oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(peer, tx);
+        PendingPunch {
+            acks: self,
+            peer,
+            acked,
+        }
+    }
+
+    /// Wakes the probe to `from` if `msg_id` acknowledges one. False when
+    /// no probe was waiting for it.
+    pub(super) fn deliver(&self, from: SocketAddr, msg_id: u32) -> bool {
+        if msg_id != Self::PROBE_MSG_ID {
+            return false;
+        }
+        let waiter = self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&from);
+        match waiter {
+            Some(tx) => tx.send(()).is_ok(),
+            None => false,
+        }
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+impl Drop for PendingPunch<'_> {
+    fn drop(&mut self) {
+        self.acks
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.peer);
+    }
+}
+
 #[derive(Debug)]
 pub(super) struct P2PReplayWindow {
     seen: HashSet<u64>,
@@ -151,7 +304,7 @@ impl P2PUdpReassemblyState {
             state.bad_count = 0;
             warn!(
                 "P2P UDP source {} temporarily banned after invalid traffic",
-                from.ip()
+                common::addr_log_label(&from)
             );
         }
     }
@@ -252,9 +405,517 @@ impl P2PUdpReassemblyState {
     }
 }
 
+/// Authenticated fragmentation shared by the datagram P2P transports: splits
+/// a message into tagged fragments, builds and recognises ACKs, and checks
+/// inbound packets. It never touches a socket.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct ReliableFramer {
+    connection_id: [u8; 16],
+    secret: [u8; 32],
+    window: u16,
+}
+
+/// Inbound packet that passed authentication.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum FramedPacket<'a> {
+    Ack {
+        msg_id: u32,
+        frag_idx: u16,
+    },
+    Fragment {
+        msg_id: u32,
+        frag_idx: u16,
+        frag_cnt: u16,
+        payload: &'a [u8],
+    },
+}
+
+impl ReliableFramer {
+    pub(super) const SEND_ATTEMPTS: u32 = 10;
+    pub(super) const ACK_TIMEOUT: Duration = Duration::from_millis(400);
+    pub(super) const DEFAULT_WINDOW: u16 = 8;
+
+    pub(super) fn new(connection_id: [u8; 16], secret: [u8; 32]) -> Self {
+        Self {
+            connection_id,
+            secret,
+            window: Self::DEFAULT_WINDOW,
+        }
+    }
+
+    /// Fragments of one message that may be unacknowledged at once.
+    pub(super) fn with_window(mut self, window: u16) -> Self {
+        self.window = window.max(1);
+        self
+    }
+
+    /// Packets carrying `payload`, in fragment order.
+    pub(super) fn fragment(&self, msg_id: u32, payload: &[u8]) -> Result<Vec<Vec<u8>>> {
+        self.fragment_at(msg_id, payload, ClientWorker::p2p_now_secs())
+    }
+
+    fn fragment_at(&self, msg_id: u32, payload: &[u8], timestamp: u64) -> Result<Vec<Vec<u8>>> {
+        let max_payload =
+            ClientWorker::P2P_UDP_MTU_PAYLOAD.saturating_sub(ClientWorker::P2P_UDP_HEADER_LEN);
+        if max_payload == 0 {
+            return Err(anyhow!("p2p udp mtu too small"));
+        }
+        let frag_cnt = payload.len().div_ceil(max_payload).max(1);
+        if frag_cnt > ClientWorker::P2P_MAX_FRAGMENTS_PER_MESSAGE as usize {
+            return Err(anyhow!("p2p udp too many fragments"));
+        }
+
+        let mut packets = Vec::with_capacity(frag_cnt);
+        for frag_idx in 0..frag_cnt {
+            let start = frag_idx * max_payload;
+            let end = ((frag_idx + 1) * max_payload).min(payload.len());
+            let frag_payload = &payload[start..end];
+            let tag = ClientWorker::p2p_udp_tag(
+                &self.secret,
+                &self.connection_id,
+                0,
+                msg_id,
+                frag_idx as u16,
+                frag_cnt as u16,
+                timestamp,
+                frag_payload,
+            );
+            let hdr = ClientWorker::p2p_udp_make_header(
+                0,
+                msg_id,
+                frag_idx as u16,
+                frag_cnt as u16,
+                timestamp,
+                &tag,
+            );
+            let mut pkt = Vec::with_capacity(ClientWorker::P2P_UDP_HEADER_LEN + frag_payload.len());
+            pkt.extend_from_slice(&hdr);
+            pkt.extend_from_slice(frag_payload);
+            packets.push(pkt);
+        }
+        Ok(packets)
+    }
+
+    /// ACK for fragment `frag_idx` of a `frag_cnt`-fragment message.
+    pub(super) fn ack(
+        &self,
+        msg_id: u32,
+        frag_idx: u16,
+        frag_cnt: u16,
+    ) -> [u8; ClientWorker::P2P_UDP_HEADER_LEN] {
+        ClientWorker::p2p_udp_ack_packet(
+            self.connection_id,
+            self.secret,
+            msg_id,
+            frag_idx,
+            frag_cnt,
+        )
+    }
+
+    /// Parses and authenticates one packet from the peer.
+    pub(super) fn open<'a>(&self, packet: &'a [u8]) -> Result<FramedPacket<'a>> {
+        let (flags, msg_id, frag_idx, frag_cnt, timestamp, tag) =
+            ClientWorker::p2p_udp_parse_header(packet)
+                .ok_or_else(|| anyhow!("malformed p2p udp header"))?;
+        let payload = &packet[ClientWorker::P2P_UDP_HEADER_LEN..];
+        ClientWorker::p2p_udp_validate_fragment(
+            &self.secret,
+            &self.connection_id,
+            flags,
+            msg_id,
+            frag_idx,
+            frag_cnt,
+            timestamp,
+            payload,
+            &tag,
+            ClientWorker::p2p_now_secs(),
+        )?;
+        if (flags & ClientWorker::P2P_UDP_FLAG_ACK) != 0 {
+            return Ok(FramedPacket::Ack { msg_id, frag_idx });
+        }
+        Ok(FramedPacket::Fragment {
+            msg_id,
+            frag_idx,
+            frag_cnt,
+            payload,
+        })
+    }
+
+    /// Fragment of `msg_id` that `packet` acknowledges, if it is such an ACK.
+    pub(super) fn acked_fragment(&self, packet: &[u8], msg_id: u32) -> Option<u16> {
+        match self.open(packet) {
+            Ok(FramedPacket::Ack {
+                msg_id: id,
+                frag_idx,
+            }) if id == msg_id => Some(frag_idx),
+            _ => None,
+        }
+    }
+}
+
+/// Selective-repeat sender state for one message. Up to `window` fragments
+/// are unacknowledged at once; each is retransmitted on its own timeout until
+/// the peer ACKs it or it runs out of attempts.
+#[derive(Debug)]
+pub(super) struct SendWindow {
+    msg_id: u32,
+    window: usize,
+    acked: Vec<bool>,
+    sent_at: Vec<Option<Instant>>,
+    attempts: Vec<u32>,
+    // First unacknowledged fragment and first fragment never sent
+    base: usize,
+    next: usize,
+}
+
+impl SendWindow {
+    pub(super) fn new(msg_id: u32, frag_cnt: usize, window: u16) -> Self {
+        Self {
+            msg_id,
+            window: usize::from(window.max(1)),
+            acked: vec![false; frag_cnt],
+            sent_at: vec![None; frag_cnt],
+            attempts: vec![0; frag_cnt],
+            base: 0,
+            next: 0,
+        }
+    }
+
+    /// Fragments to put on the wire at `now`: those whose ACK timed out, then
+    /// new ones while the window has room. Each is counted as sent.
+    pub(super) fn due(&mut self, now: Instant) -> Result<Vec<usize>> {
+        let mut due = Vec::new();
+        for idx in self.base..self.next {
+            let timed_out = self.sent_at[idx]
+                .is_some_and(|sent| now.duration_since(sent) >= ReliableFramer::ACK_TIMEOUT);
+            if self.acked[idx] || !timed_out {
+                continue;
+            }
+            if self.attempts[idx] >= ReliableFramer::SEND_ATTEMPTS {
+                return Err(anyhow!("p2p udp send timeout msg_id={}", self.msg_id));
+            }
+            due.push(idx);
+        }
+        let end = (self.base + self.window).min(self.acked.len());
+        while self.next < end {
+            due.push(self.next);
+            self.next += 1;
+        }
+        for &idx in &due {
+            self.sent_at[idx] = Some(now);
+            self.attempts[idx] += 1;
+        }
+        Ok(due)
+    }
+
+    pub(super) fn ack(&mut self, frag_idx: u16) {
+        let idx = usize::from(frag_idx);
+        if idx >= self.next {
+            return;
+        }
+        self.acked[idx] = true;
+        while self.base < self.next && self.acked[self.base] {
+            self.base += 1;
+        }
+    }
+
+    pub(super) fn is_complete(&self) -> bool {
+        self.base == self.acked.len()
+    }
+
+    /// When the earliest outstanding fragment times out.
+    pub(super) fn next_deadline(&self) -> Option<Instant> {
+        (self.base..self.next)
+            .filter(|&idx| !self.acked[idx])
+            .filter_map(|idx| self.sent_at[idx])
+            .min()
+            .map(|sent| sent + ReliableFramer::ACK_TIMEOUT)
+    }
+}
+
+/// Reliable, authenticated message channel to one P2P peer. `send` returns
+/// once the peer has the whole message; `recv` yields the next complete one.
+/// Payloads are commands encoded with `udp_encode_command`.
+pub(super) trait P2PTransport {
+    async fn send(&self, msg_id: u32, payload: &[u8]) -> Result<()>;
+    async fn recv(&self) -> Result<Vec<u8>>;
+}
+
+/// Fragments exchanged directly with the peer over a UDP socket.
+pub(super) struct DirectUdp<'a> {
+    socket: &'a UdpSocket,
+    peer: SocketAddr,
+    framer: ReliableFramer,
+    reassembly: Mutex<P2PUdpReassemblyState>,
+}
+
+impl<'a> DirectUdp<'a> {
+    pub(super) fn new(socket: &'a UdpSocket, peer: SocketAddr, framer: ReliableFramer) -> Self {
+        Self {
+            socket,
+            peer,
+            framer,
+            reassembly: Mutex::new(P2PUdpReassemblyState::new()),
+        }
+    }
+}
+
+impl P2PTransport for DirectUdp<'_> {
+    async fn send(&self, msg_id: u32, payload: &[u8]) -> Result<()> {
+        let packets = self.framer.fragment(msg_id, payload)?;
+        let mut window = SendWindow::new(msg_id, packets.len(), self.framer.window);
+        let mut ack_buf = [0u8; ClientWorker::P2P_UDP_HEADER_LEN];
+        while !window.is_complete() {
+            for idx in window.due(Instant::now())? {
+                self.socket.send_to(&packets[idx], self.peer).await?;
+            }
+            let Some(deadline) = window.next_deadline() else {
+                continue;
+            };
+            let ack_res = timeout_at(deadline.into(), self.socket.recv_from(&mut ack_buf)).await;
+            if let Ok(Ok((n, from))) = ack_res {
+                if from != self.peer {
+                    continue;
+                }
+                if let Some(frag_idx) = self.framer.acked_fragment(&ack_buf[..n], msg_id) {
+                    window.ack(frag_idx);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn recv(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let (n, from) = self.socket.recv_from(&mut buf).await?;
+            if from != self.peer {
+                continue;
+            }
+            let Ok(FramedPacket::Fragment {
+                msg_id,
+                frag_idx,
+                frag_cnt,
+                payload,
+            }) = self.framer.open(&buf[..n])
+            else {
+                continue;
+            };
+            let accepted = self
+                .reassembly
+                .lock()
+                .await
+                .accept_fragment(from, msg_id, frag_idx, frag_cnt, payload);
+            let Ok(full) = accepted else {
+                continue;
+            };
+            self.socket
+                .send_to(&self.framer.ack(msg_id, frag_idx, frag_cnt), from)
+                .await?;
+            if let Some(full) = full {
+                return Ok(full);
+            }
+        }
+    }
+}
+
+/// Fragments relayed through a TURN/UDP allocation as Send and Data
+/// indications. Traffic from other peers that arrives while waiting for an
+/// ACK is parked in the shared `inbox`.
+#[cfg(not(target_os = "android"))]
+pub(super) struct TurnIndication<'a> {
+    sock: &'a UdpSocket,
+    peer: SocketAddr,
+    framer: ReliableFramer,
+    inbox: &'a Mutex<VecDeque<(SocketAddr, Vec<u8>)>>,
+    transactions: &'a TurnTransactions,
+    reassembly: Mutex<P2PUdpReassemblyState>,
+}
+
+#[cfg(not(target_os = "android"))]
+impl<'a> TurnIndication<'a> {
+    pub(super) fn new(
+        sock: &'a UdpSocket,
+        peer: SocketAddr,
+        framer: ReliableFramer,
+        inbox: &'a Mutex<VecDeque<(SocketAddr, Vec<u8>)>>,
+        transactions: &'a TurnTransactions,
+    ) -> Self {
+        Self {
+            sock,
+            peer,
+            framer,
+            inbox,
+            transactions,
+            reassembly: Mutex::new(P2PUdpReassemblyState::new()),
+        }
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+impl P2PTransport for TurnIndication<'_> {
+    async fn send(&self, msg_id: u32, payload: &[u8]) -> Result<()> {
+        let packets = self.framer.fragment(msg_id, payload)?;
+        let mut window = SendWindow::new(msg_id, packets.len(), self.framer.window);
+        let mut buf = vec![0u8; 4096];
+        while !window.is_complete() {
+            for idx in window.due(Instant::now())? {
+                ClientWorker::turn_send_indication(self.sock, self.peer, &packets[idx]).await?;
+            }
+            let Some(deadline) = window.next_deadline() else {
+                continue;
+            };
+            let recv_res = timeout_at(deadline.into(), self.sock.recv(&mut buf)).await;
+            if let Ok(Ok(n)) = recv_res {
+                if self.transactions.deliver(&buf[..n]) {
+                    continue;
+                }
+                if let Some((src, data)) = ClientWorker::turn_parse_data_indication(&buf[..n]) {
+                    let acked = (src == self.peer)
+                        .then(|| self.framer.acked_fragment(&data, msg_id))
+                        .flatten();
+                    match acked {
+                        Some(frag_idx) => window.ack(frag_idx),
+                        None => self.inbox.lock().await.push_back((src, data)),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn recv(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let queued = {
+                let mut inbox = self.inbox.lock().await;
+                inbox
+                    .iter()
+                    .position(|(src, _)| *src == self.peer)
+                    .and_then(|idx| inbox.remove(idx))
+            };
+            let data = match queued {
+                Some((_, data)) => data,
+                None => {
+                    let n = self.sock.recv(&mut buf).await?;
+                    if self.transactions.deliver(&buf[..n]) {
+                        continue;
+                    }
+                    let Some((src, data)) = ClientWorker::turn_parse_data_indication(&buf[..n])
+                    else {
+                        continue;
+                    };
+                    if src != self.peer {
+                        self.inbox.lock().await.push_back((src, data));
+                        continue;
+                    }
+                    data
+                }
+            };
+            let Ok(FramedPacket::Fragment {
+                msg_id,
+                frag_idx,
+                frag_cnt,
+                payload,
+            }) = self.framer.open(&data)
+            else {
+                continue;
+            };
+            let accepted = self
+                .reassembly
+                .lock()
+                .await
+                .accept_fragment(self.peer, msg_id, frag_idx, frag_cnt, payload);
+            let Ok(full) = accepted else {
+                continue;
+            };
+            ClientWorker::turn_send_indication(
+                self.sock,
+                self.peer,
+                &self.framer.ack(msg_id, frag_idx, frag_cnt),
+            )
+            .await?;
+            if let Some(full) = full {
+                return Ok(full);
+            }
+        }
+    }
+}
+
+/// Signed command frames over a TURN TCP relay stream, or a direct TCP
+/// connection which speaks the same framing. TCP already delivers in order,
+/// so each message is one data-plane envelope whose sequence number is the
+/// `msg_id`. Reads and writes share the stream, so a pending `recv` holds off
+/// `send`.
+#[cfg(not(target_os = "android"))]
+pub(super) struct TurnTcp<S> {
+    stream: Mutex<S>,
+    read_buf: Mutex<BytesMut>,
+    replay: Mutex<P2PReplayWindow>,
+    connection_id: [u8; 16],
+    secret: [u8; 32],
+}
+
+#[cfg(not(target_os = "android"))]
+impl<S> TurnTcp<S> {
+    pub(super) fn new(stream: S, connection_id: [u8; 16], secret: [u8; 32]) -> Self {
+        Self {
+            stream: Mutex::new(stream),
+            read_buf: Mutex::new(BytesMut::with_capacity(MAX_MESSAGE_SIZE)),
+            replay: Mutex::new(P2PReplayWindow::new()),
+            connection_id,
+            secret,
+        }
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+impl<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin> P2PTransport for TurnTcp<S> {
+    async fn send(&self, msg_id: u32, payload: &[u8]) -> Result<()> {
+        let command = ClientWorker::udp_decode_command(payload)?;
+        let signed = ClientWorker::p2p_encode_data_plane_envelope(
+            &command,
+            self.connection_id,
+            self.secret,
+            msg_id as u64,
+            ClientWorker::p2p_now_secs(),
+        )?;
+        let mut stream = self.stream.lock().await;
+        write_command(&mut *stream, &signed).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+
+    async fn recv(&self) -> Result<Vec<u8>> {
+        let mut stream = self.stream.lock().await;
+        let mut read_buf = self.read_buf.lock().await;
+        loop {
+            let signed = read_command(&mut *stream, &mut read_buf).await?;
+            let decoded = ClientWorker::p2p_decode_data_plane_envelope(
+                signed,
+                self.connection_id,
+                self.secret,
+                &mut *self.replay.lock().await,
+            );
+            match decoded {
+                Ok(command) => return ClientWorker::udp_encode_command(&command),
+                Err(e) => {
+                    let err = e.to_string();
+                    if err.contains("replay") {
+                        security_metrics::record_p2p_replay_rejection();
+                    } else {
+                        security_metrics::record_p2p_auth_rejection();
+                    }
+                    warn!("P2P TCP data-plane authentication rejected frame: {}", err);
+                }
+            }
+        }
+    }
+}
+
 impl ClientWorker {
     pub(super) const P2P_UDP_MAGIC: [u8; 4] = *b"P2PU";
-    pub(super) const P2P_UDP_VERSION: u8 = 2;
+    pub(super) const P2P_UDP_VERSION: u8 = 3;
     pub(super) const P2P_UDP_FLAG_ACK: u8 = 0x01;
     pub(super) const P2P_UDP_HEADER_LEN: usize = 4 + 1 + 1 + 4 + 2 + 2 + 8 + 32;
     pub(super) const P2P_UDP_MTU_PAYLOAD: usize = 1200;
@@ -383,17 +1044,15 @@ impl ClientWorker {
         now: u64,
     ) -> Result<()> {
         let is_ack = (flags & Self::P2P_UDP_FLAG_ACK) != 0;
-        if is_ack {
-            if frag_idx != 0 || frag_cnt != 0 || !payload.is_empty() {
-                return Err(anyhow!("invalid p2p udp ack metadata"));
-            }
-        } else {
-            if frag_cnt == 0 || frag_cnt > Self::P2P_MAX_FRAGMENTS_PER_MESSAGE {
-                return Err(anyhow!("invalid p2p udp fragment count"));
-            }
-            if frag_idx >= frag_cnt {
-                return Err(anyhow!("invalid p2p udp fragment index"));
-            }
+        if is_ack && !payload.is_empty() {
+            return Err(anyhow!("invalid p2p udp ack metadata"));
+        }
+        // ACKs name the fragment they acknowledge, so both carry fragment metadata
+        if frag_cnt == 0 || frag_cnt > Self::P2P_MAX_FRAGMENTS_PER_MESSAGE {
+            return Err(anyhow!("invalid p2p udp fragment count"));
+        }
+        if frag_idx >= frag_cnt {
+            return Err(anyhow!("invalid p2p udp fragment index"));
         }
         if !Self::p2p_timestamp_is_fresh(timestamp, now) {
             return Err(anyhow!("stale p2p udp fragment"));
@@ -414,134 +1073,32 @@ impl ClientWorker {
         Ok(())
     }
 
-    pub(super) async fn p2p_udp_send_ack(
-        socket: &UdpSocket,
-        to: SocketAddr,
+    pub(super) fn p2p_udp_ack_packet(
         connection_id: [u8; 16],
         secret: [u8; 32],
         msg_id: u32,
-    ) {
+        frag_idx: u16,
+        frag_cnt: u16,
+    ) -> [u8; Self::P2P_UDP_HEADER_LEN] {
         let timestamp = Self::p2p_now_secs();
         let tag = Self::p2p_udp_tag(
             &secret,
             &connection_id,
             Self::P2P_UDP_FLAG_ACK,
             msg_id,
-            0,
-            0,
+            frag_idx,
+            frag_cnt,
             timestamp,
             &[],
         );
-        let hdr = Self::p2p_udp_make_header(Self::P2P_UDP_FLAG_ACK, msg_id, 0, 0, timestamp, &tag);
-        let _ = socket.send_to(&hdr, to).await;
-    }
-
-    pub(super) fn p2p_udp_ack_packet(
-        connection_id: [u8; 16],
-        secret: [u8; 32],
-        msg_id: u32,
-    ) -> [u8; Self::P2P_UDP_HEADER_LEN] {
-        let timestamp = Self::p2p_now_secs();
-        let tag = Self::p2p_udp_tag(
-            &secret,
-            &connection_id,
+        Self::p2p_udp_make_header(
             Self::P2P_UDP_FLAG_ACK,
             msg_id,
-            0,
-            0,
+            frag_idx,
+            frag_cnt,
             timestamp,
-            &[],
-        );
-        Self::p2p_udp_make_header(Self::P2P_UDP_FLAG_ACK, msg_id, 0, 0, timestamp, &tag)
-    }
-
-    pub(super) async fn p2p_udp_send_reliable(
-        socket: &UdpSocket,
-        to: SocketAddr,
-        connection_id: [u8; 16],
-        secret: [u8; 32],
-        msg_id: u32,
-        payload: &[u8],
-    ) -> Result<()> {
-        let max_payload = Self::P2P_UDP_MTU_PAYLOAD.saturating_sub(Self::P2P_UDP_HEADER_LEN);
-        if max_payload == 0 {
-            return Err(anyhow!("p2p udp mtu too small"));
-        }
-        let frag_cnt = ((payload.len() + max_payload - 1) / max_payload).max(1);
-        if frag_cnt > Self::P2P_MAX_FRAGMENTS_PER_MESSAGE as usize {
-            return Err(anyhow!("p2p udp too many fragments"));
-        }
-
-        for frag_idx in 0..frag_cnt {
-            let start = frag_idx * max_payload;
-            let end = ((frag_idx + 1) * max_payload).min(payload.len());
-            let frag_payload = &payload[start..end];
-            let timestamp = Self::p2p_now_secs();
-            let tag = Self::p2p_udp_tag(
-                &secret,
-                &connection_id,
-                0,
-                msg_id,
-                frag_idx as u16,
-                frag_cnt as u16,
-                timestamp,
-                frag_payload,
-            );
-            let hdr = Self::p2p_udp_make_header(
-                0,
-                msg_id,
-                frag_idx as u16,
-                frag_cnt as u16,
-                timestamp,
-                &tag,
-            );
-            let mut pkt = Vec::with_capacity(Self::P2P_UDP_HEADER_LEN + frag_payload.len());
-            pkt.extend_from_slice(&hdr);
-            pkt.extend_from_slice(frag_payload);
-
-            let mut tries = 0u32;
-            loop {
-                tries += 1;
-                socket.send_to(&pkt, to).await?;
-
-                let mut ack_buf = [0u8; Self::P2P_UDP_HEADER_LEN];
-                let ack_res =
-                    timeout(Duration::from_millis(400), socket.recv_from(&mut ack_buf)).await;
-                if let Ok(Ok((n, from))) = ack_res {
-                    if from != to {
-                        continue;
-                    }
-                    if let Some((flags, ack_id, ack_frag_idx, ack_frag_cnt, ts, tag)) =
-                        Self::p2p_udp_parse_header(&ack_buf[..n])
-                    {
-                        let valid_ack = (flags & Self::P2P_UDP_FLAG_ACK) != 0
-                            && ack_id == msg_id
-                            && ack_frag_idx == 0
-                            && ack_frag_cnt == 0
-                            && Self::p2p_udp_validate_fragment(
-                                &secret,
-                                &connection_id,
-                                flags,
-                                ack_id,
-                                ack_frag_idx,
-                                ack_frag_cnt,
-                                ts,
-                                &[],
-                                &tag,
-                                Self::p2p_now_secs(),
-                            )
-                            .is_ok();
-                        if valid_ack {
-                            break;
-                        }
-                    }
-                }
-                if tries >= 10 {
-                    return Err(anyhow!("p2p udp send timeout msg_id={msg_id}"));
-                }
-            }
-        }
-        Ok(())
+            &tag,
+        )
     }
 
     pub(super) fn p2p_udp_encode_command_payload(command: &Command) -> Result<Vec<u8>> {
@@ -639,6 +1196,15 @@ impl ClientWorker {
         Ok(inner)
     }
 
+    pub(super) const STUN_ATTR_MESSAGE_INTEGRITY: u16 = 0x0008;
+    pub(super) const STUN_ATTR_FINGERPRINT: u16 = 0x8028;
+    // Allocate, Refresh and CreatePermission success responses
+    const STUN_AUTHENTICATED_SUCCESS: [u16; 3] = [0x0103, 0x0104, 0x0108];
+    /// Allocation lifetime requested on Allocate and Refresh, in seconds.
+    pub(super) const TURN_LIFETIME_SECS: u32 = 600;
+    /// Lifetime of a TURN permission, fixed by RFC 8656.
+    pub(super) const TURN_PERMISSION_LIFETIME_SECS: u32 = 300;
+
     pub(super) fn stun_new_txid() -> [u8; 12] {
         uuid::Uuid::new_v4().as_bytes()[..12]
             .try_into()
@@ -673,53 +1239,170 @@ impl ClientWorker {
         header.extend_from_slice(&0x2112A442u32.to_be_bytes());
         header.extend_from_slice(&txid);
 
-        let mut msg = header;
-        msg.extend_from_slice(&body);
+        let mut msg = header;
+        msg.extend_from_slice(&body);
+
+        if let Some((username, realm, password)) = mi {
+            let key = Self::stun_long_term_key(username, realm, password);
+            let out = Self::stun_integrity_mac(&msg, &key).finalize().into_bytes();
+            msg.extend_from_slice(&Self::STUN_ATTR_MESSAGE_INTEGRITY.to_be_bytes());
+            msg.extend_from_slice(&(out.len() as u16).to_be_bytes());
+            msg.extend_from_slice(&out);
+        }
+
+        if fingerprint {
+            let fp = Self::stun_fingerprint(&msg);
+            msg.extend_from_slice(&Self::STUN_ATTR_FINGERPRINT.to_be_bytes());
+            msg.extend_from_slice(&4u16.to_be_bytes());
+            msg.extend_from_slice(&fp.to_be_bytes());
+        }
+
+        let len = (msg.len() - 20) as u16;
+        msg[2..4].copy_from_slice(&len.to_be_bytes());
+        msg
+    }
+
+    /// Long-term credential key, MD5(username:realm:password) (RFC 5389 15.4).
+    pub(super) fn stun_long_term_key(username: &str, realm: &str, password: &str) -> [u8; 16] {
+        md5::compute(format!("{}:{}:{}", username, realm, password)).0
+    }
+
+    /// HMAC-SHA1 for a MESSAGE-INTEGRITY attribute appended to `prefix`, the
+    /// message up to that attribute. The header length is taken to end at
+    /// the attribute, as RFC 5389 15.4 requires.
+    fn stun_integrity_mac(prefix: &[u8], key: &[u8]) -> Hmac<Sha1> {
+        let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("hmac sha1 key");
+        mac.update(&prefix[..2]);
+        mac.update(&((prefix.len() - 20 + 24) as u16).to_be_bytes());
+        mac.update(&prefix[4..]);
+        mac
+    }
+
+    /// CRC for a FINGERPRINT attribute appended to `prefix` (RFC 5389 15.5).
+    fn stun_fingerprint(prefix: &[u8]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(&prefix[..2]);
+        crc.update(&((prefix.len() - 20 + 8) as u16).to_be_bytes());
+        crc.update(&prefix[4..]);
+        crc.finalize() ^ 0x5354554e
+    }
+
+    /// Offset and value of the first `attr_type` attribute of `msg`.
+    fn stun_find_attr(msg: &[u8], attr_type: u16) -> Option<(usize, &[u8])> {
+        let len = u16::from_be_bytes([*msg.get(2)?, *msg.get(3)?]) as usize;
+        let end = 20usize.checked_add(len).filter(|&end| end <= msg.len())?;
+        let mut pos = 20;
+        while pos + 4 <= end {
+            let t = u16::from_be_bytes([msg[pos], msg[pos + 1]]);
+            let l = u16::from_be_bytes([msg[pos + 2], msg[pos + 3]]) as usize;
+            if pos + 4 + l > end {
+                return None;
+            }
+            if t == attr_type {
+                return Some((pos, &msg[pos + 4..pos + 4 + l]));
+            }
+            pos += 4 + l + (4 - (l % 4)) % 4;
+        }
+        None
+    }
+
+    /// Checks the MESSAGE-INTEGRITY attribute of `msg` against `key`. Fails
+    /// when the attribute is missing or does not match.
+    pub(super) fn stun_verify_integrity(msg: &[u8], key: &[u8]) -> Result<()> {
+        let (offset, value) = Self::stun_find_attr(msg, Self::STUN_ATTR_MESSAGE_INTEGRITY)
+            .ok_or_else(|| anyhow!("STUN message has no MESSAGE-INTEGRITY"))?;
+        Self::stun_integrity_mac(&msg[..offset], key)
+            .verify_slice(value)
+            .map_err(|_| anyhow!("STUN MESSAGE-INTEGRITY mismatch"))
+    }
 
-        if let Some((username, realm, password)) = mi {
-            // MESSAGE-INTEGRITY is computed over the message up to the MI attribute (with header length set accordingly).
-            // Key for long-term credentials is MD5(username:realm:password).
-            let key_src = format!("{}:{}:{}", username, realm, password);
-            let key = md5::compute(key_src.as_bytes());
-
-            let mi_attr_len = 20usize;
-            let mi_total = 4 + mi_attr_len;
-            let fp_total = if fingerprint { 8 } else { 0 };
-            let new_len = (msg.len() - 20 + mi_total + fp_total) as u16;
-            msg[2..4].copy_from_slice(&new_len.to_be_bytes());
-
-            // Append MI header with zeroed value for HMAC calc length purposes.
-            let mut tmp = msg.clone();
-            tmp.extend_from_slice(&0x0008u16.to_be_bytes());
-            tmp.extend_from_slice(&(mi_attr_len as u16).to_be_bytes());
-            tmp.extend_from_slice(&[0u8; 20]);
-
-            let mut mac = Hmac::<Sha1>::new_from_slice(&key.0).expect("hmac key");
-            mac.update(&tmp);
-            let out = mac.finalize().into_bytes();
-
-            msg.extend_from_slice(&0x0008u16.to_be_bytes());
-            msg.extend_from_slice(&(mi_attr_len as u16).to_be_bytes());
-            msg.extend_from_slice(&out);
-        } else {
-            let new_len = (msg.len() - 20) as u16;
-            msg[2..4].copy_from_slice(&new_len.to_be_bytes());
+    /// Checks the FINGERPRINT attribute of `msg`, which must be the last one.
+    /// Fails when the attribute is missing or does not match.
+    pub(super) fn stun_verify_fingerprint(msg: &[u8]) -> Result<()> {
+        let (offset, value) = Self::stun_find_attr(msg, Self::STUN_ATTR_FINGERPRINT)
+            .ok_or_else(|| anyhow!("STUN message has no FINGERPRINT"))?;
+        let len = u16::from_be_bytes([msg[2], msg[3]]) as usize;
+        if value.len() != 4 || offset + 8 != 20 + len {
+            return Err(anyhow!("malformed STUN FINGERPRINT"));
         }
+        if value != Self::stun_fingerprint(&msg[..offset]).to_be_bytes() {
+            return Err(anyhow!("STUN FINGERPRINT mismatch"));
+        }
+        Ok(())
+    }
 
-        if fingerprint {
-            // FINGERPRINT over entire message up to the fingerprint attribute (with header length set accordingly).
-            let new_len = (msg.len() - 20 + 8) as u16;
-            msg[2..4].copy_from_slice(&new_len.to_be_bytes());
-
-            let mut crc = Crc32::new();
-            crc.update(&msg);
-            let fp = crc.finalize() ^ 0x5354554e;
-            msg.extend_from_slice(&0x8028u16.to_be_bytes());
-            msg.extend_from_slice(&4u16.to_be_bytes());
-            msg.extend_from_slice(&fp.to_be_bytes());
+    /// Verifies whichever of FINGERPRINT and MESSAGE-INTEGRITY a response
+    /// carries, the latter only when the request was authenticated with
+    /// `key`. Authenticated Allocate, Refresh and CreatePermission success
+    /// responses must carry MESSAGE-INTEGRITY, so a spoofed one without it
+    /// is rejected; error responses such as a 401 challenge may omit it.
+    pub(super) fn stun_verify_response(resp: &[u8], key: Option<&[u8]>) -> Result<()> {
+        if resp.len() < 20 {
+            return Err(anyhow!("STUN response too short"));
+        }
+        if Self::stun_find_attr(resp, Self::STUN_ATTR_FINGERPRINT).is_some() {
+            Self::stun_verify_fingerprint(resp)?;
+        }
+        if let Some(key) = key {
+            let msg_type = u16::from_be_bytes([resp[0], resp[1]]);
+            if Self::STUN_AUTHENTICATED_SUCCESS.contains(&msg_type)
+                || Self::stun_find_attr(resp, Self::STUN_ATTR_MESSAGE_INTEGRITY).is_some()
+            {
+                Self::stun_verify_integrity(resp, key)?;
+            }
         }
+        Ok(())
+    }
 
-        msg
+    /// Runs `setup` against each TURN server in order until one completes
+    /// within `deadline`, and returns the server used with its result. Fails
+    /// with the last server's error when none does.
+    #[cfg(not(target_os = "android"))]
+    pub(super) async fn turn_with_failover<T, F, Fut>(
+        turn_urls: &[String],
+        deadline: Duration,
+        mut setup: F,
+    ) -> Result<(String, T)>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut last_err = anyhow!("No TURN server configured");
+        for turn_url in turn_urls {
+            let started = Instant::now();
+            match timeout(deadline, setup(turn_url.clone())).await {
+                Ok(Ok(value)) => {
+                    info!(
+                        "TURN relay set up via {} in {}ms",
+                        turn_url,
+                        started.elapsed().as_millis()
+                    );
+                    return Ok((turn_url.clone(), value));
+                }
+                Ok(Err(e)) => {
+                    warn!(
+                        "TURN setup via {} failed after {}ms: {}",
+                        turn_url,
+                        started.elapsed().as_millis(),
+                        e
+                    );
+                    last_err = e;
+                }
+                Err(_) => {
+                    warn!(
+                        "TURN setup via {} timed out after {}s",
+                        turn_url,
+                        deadline.as_secs()
+                    );
+                    last_err = anyhow!(
+                        "TURN setup via {} timed out after {}s",
+                        turn_url,
+                        deadline.as_secs()
+                    );
+                }
+            }
+        }
+        Err(last_err)
     }
 
     pub(super) async fn stun_read_message<S: tokio::io::AsyncRead + Unpin>(
@@ -756,6 +1439,7 @@ impl ClientWorker {
         let mut buf = [0u8; 1500];
         let (n, _from) = timeout(Duration::from_secs(3), socket.recv_from(&mut buf)).await??;
         let resp = &buf[..n];
+        Self::stun_verify_response(resp, None)?;
         let attrs = Self::stun_attr_iter(resp)?;
         let mapped = attrs
             .iter()
@@ -766,10 +1450,7 @@ impl ClientWorker {
     }
 
     pub(super) fn udp_encode_command(command: &Command) -> Result<Vec<u8>> {
-        let config = bincode_config::standard()
-            .with_fixed_int_encoding()
-            .with_little_endian();
-        let payload = bincode::encode_to_vec(command, config)?;
+        let payload = bincode::encode_to_vec(command, command_bincode_config())?;
         let len = payload.len() as u32;
         let mut out = Vec::with_capacity(4 + payload.len());
         out.extend_from_slice(&len.to_be_bytes());
@@ -785,10 +1466,7 @@ impl ClientWorker {
         if datagram.len() < 4 + len {
             return Err(anyhow!("udp datagram truncated"));
         }
-        let config = bincode_config::standard()
-            .with_fixed_int_encoding()
-            .with_little_endian();
-        let (cmd, _) = bincode::decode_from_slice(&datagram[4..4 + len], config)
+        let (cmd, _) = bincode::decode_from_slice(&datagram[4..4 + len], command_bincode_config())
             .map_err(|e| anyhow!("Failed to deserialize command: {}", e))?;
         Ok(cmd)
     }
@@ -829,6 +1507,7 @@ impl ClientWorker {
         turn_url: &str,
         username: &str,
         password: &str,
+        op_timeout: Duration,
     ) -> Result<(Arc<UdpSocket>, std::net::SocketAddr, String, String)> {
         let url = Url::parse(turn_url)?;
         let host = url
@@ -852,13 +1531,14 @@ impl ClientWorker {
         let mut attrs = Vec::new();
         // UDP = 17
         attrs.push((&requested_transport_t, vec![17u8, 0, 0, 0]));
-        attrs.push((&lifetime_t, 600u32.to_be_bytes().to_vec()));
+        attrs.push((&lifetime_t, Self::TURN_LIFETIME_SECS.to_be_bytes().to_vec()));
         let req = Self::stun_build_message(0x0003, txid, &attrs, None, true);
         sock.send(&req).await?;
 
         let mut buf = vec![0u8; 2048];
-        let n = timeout(Duration::from_secs(3), sock.recv(&mut buf)).await??;
+        let n = timeout(op_timeout, sock.recv(&mut buf)).await??;
         let resp = &buf[..n];
+        Self::stun_verify_response(resp, None)?;
         let msg_type = u16::from_be_bytes([resp[0], resp[1]]);
         if msg_type != 0x0113 {
             return Err(anyhow!(
@@ -883,7 +1563,7 @@ impl ClientWorker {
         attrs2.push((&realm_t, realm.as_bytes().to_vec()));
         attrs2.push((&nonce_t, nonce.as_bytes().to_vec()));
         attrs2.push((&requested_transport_t, vec![17u8, 0, 0, 0]));
-        attrs2.push((&lifetime_t, 600u32.to_be_bytes().to_vec()));
+        attrs2.push((&lifetime_t, Self::TURN_LIFETIME_SECS.to_be_bytes().to_vec()));
         let req2 = Self::stun_build_message(
             0x0003,
             txid2,
@@ -893,8 +1573,10 @@ impl ClientWorker {
         );
         sock.send(&req2).await?;
 
-        let n2 = timeout(Duration::from_secs(3), sock.recv(&mut buf)).await??;
+        let n2 = timeout(op_timeout, sock.recv(&mut buf)).await??;
         let resp2 = &buf[..n2];
+        let key = Self::stun_long_term_key(username, &realm, password);
+        Self::stun_verify_response(resp2, Some(&key))?;
         let msg_type2 = u16::from_be_bytes([resp2[0], resp2[1]]);
         if msg_type2 != 0x0103 {
             return Err(anyhow!("TURN Allocate failed type=0x{:04x}", msg_type2));
@@ -909,6 +1591,46 @@ impl ClientWorker {
         Ok((sock, relayed, realm, nonce))
     }
 
+    /// Sends a STUN request and waits for its response. With `inbox` the
+    /// caller owns the socket's reads, so this reads until the response
+    /// arrives, handing other responses to `transactions` and parking
+    /// relayed data in `inbox`. Without it, the reading task delivers.
+    #[cfg(not(target_os = "android"))]
+    async fn turn_transaction(
+        sock: &UdpSocket,
+        txid: [u8; 12],
+        req: &[u8],
+        transactions: &TurnTransactions,
+        inbox: Option<&Mutex<VecDeque<(SocketAddr, Vec<u8>)>>>,
+        op_timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        let mut response = transactions.register(txid);
+        let exchange = async {
+            sock.send(req).await?;
+            let Some(inbox) = inbox else {
+                return response
+                    .await
+                    .map_err(|_| anyhow!("TURN transaction abandoned"));
+            };
+            let mut buf = vec![0u8; 2048];
+            loop {
+                let n = sock.recv(&mut buf).await?;
+                if transactions.deliver(&buf[..n]) {
+                    if let Ok(resp) = response.try_recv() {
+                        return Ok(resp);
+                    }
+                } else if let Some(indication) = Self::turn_parse_data_indication(&buf[..n]) {
+                    inbox.lock().await.push_back(indication);
+                }
+            }
+        };
+        let result = timeout(op_timeout, exchange)
+            .await
+            .map_err(|_| anyhow!("TURN request timed out"));
+        transactions.forget(&txid);
+        result?
+    }
+
     #[cfg(not(target_os = "android"))]
     pub(super) async fn turn_create_permission(
         sock: &UdpSocket,
@@ -917,6 +1639,9 @@ impl ClientWorker {
         password: &str,
         realm: &str,
         nonce: &str,
+        transactions: &TurnTransactions,
+        inbox: Option<&Mutex<VecDeque<(SocketAddr, Vec<u8>)>>>,
+        op_timeout: Duration,
     ) -> Result<()> {
         let username_t: u16 = 0x0006;
         let realm_t: u16 = 0x0014;
@@ -937,11 +1662,10 @@ impl ClientWorker {
             Some((username, realm, password)),
             true,
         );
-        sock.send(&req).await?;
-
-        let mut buf = vec![0u8; 2048];
-        let n = timeout(Duration::from_secs(3), sock.recv(&mut buf)).await??;
-        let resp = &buf[..n];
+        let resp =
+            Self::turn_transaction(sock, txid, &req, transactions, inbox, op_timeout).await?;
+        let key = Self::stun_long_term_key(username, realm, password);
+        Self::stun_verify_response(&resp, Some(&key))?;
         let msg_type = u16::from_be_bytes([resp[0], resp[1]]);
         if msg_type != 0x0108 {
             return Err(anyhow!(
@@ -952,6 +1676,169 @@ impl ClientWorker {
         Ok(())
     }
 
+    /// Delay before refreshing an allocation granted for `lifetime` seconds.
+    pub(super) fn turn_refresh_interval(lifetime: u32) -> Duration {
+        Duration::from_secs(u64::from(lifetime / 2).max(1))
+    }
+
+    /// Refresh request (method 0x0004) asking for `lifetime` more seconds on
+    /// the allocation, authenticated like the Allocate that created it.
+    pub(super) fn turn_refresh_request(
+        txid: [u8; 12],
+        lifetime: u32,
+        username: &str,
+        password: &str,
+        realm: &str,
+        nonce: &str,
+    ) -> Vec<u8> {
+        let username_t: u16 = 0x0006;
+        let realm_t: u16 = 0x0014;
+        let nonce_t: u16 = 0x0015;
+        let lifetime_t: u16 = 0x000d;
+        let attrs = [
+            (&username_t, username.as_bytes().to_vec()),
+            (&realm_t, realm.as_bytes().to_vec()),
+            (&nonce_t, nonce.as_bytes().to_vec()),
+            (&lifetime_t, lifetime.to_be_bytes().to_vec()),
+        ];
+        Self::stun_build_message(
+            0x0004,
+            txid,
+            &attrs,
+            Some((username, realm, password)),
+            true,
+        )
+    }
+
+    /// Reads a Refresh response: the lifetime granted, or the nonce to retry
+    /// with after a 438 (Stale Nonce). Other errors fail.
+    pub(super) fn turn_refresh_outcome(resp: &[u8]) -> Result<TurnRefreshOutcome> {
+        let attrs = Self::stun_attr_iter(resp)?;
+        let msg_type = u16::from_be_bytes([resp[0], resp[1]]);
+        match msg_type {
+            0x0104 => {
+                let lifetime = attrs
+                    .iter()
+                    .find(|(t, _)| *t == 0x000d)
+                    .and_then(|(_, v)| v.get(..4)?.try_into().ok())
+                    .map(u32::from_be_bytes)
+                    .unwrap_or(Self::TURN_LIFETIME_SECS);
+                Ok(TurnRefreshOutcome::Refreshed { lifetime })
+            }
+            0x0114 => {
+                let code = attrs
+                    .iter()
+                    .find(|(t, _)| *t == 0x0009)
+                    .filter(|(_, v)| v.len() >= 4)
+                    .map(|(_, v)| u16::from(v[2] & 0x07) * 100 + u16::from(v[3]))
+                    .unwrap_or(0);
+                match Self::stun_get_text_attr(&attrs, 0x0015) {
+                    Some(nonce) if code == 438 => Ok(TurnRefreshOutcome::StaleNonce { nonce }),
+                    _ => Err(anyhow!("TURN Refresh failed with error {}", code)),
+                }
+            }
+            other => Err(anyhow!("TURN Refresh failed type=0x{:04x}", other)),
+        }
+    }
+
+    /// Extends the allocation on `sock` by `lifetime` seconds and returns
+    /// the lifetime the server granted. A 438 (Stale Nonce) updates `nonce`
+    /// and retries once. The response is read as `turn_transaction` does.
+    #[cfg(not(target_os = "android"))]
+    pub(super) async fn turn_refresh(
+        sock: &UdpSocket,
+        lifetime: u32,
+        username: &str,
+        password: &str,
+        realm: &str,
+        nonce: &mut String,
+        transactions: &TurnTransactions,
+        inbox: Option<&Mutex<VecDeque<(SocketAddr, Vec<u8>)>>>,
+        op_timeout: Duration,
+    ) -> Result<u32> {
+        let key = Self::stun_long_term_key(username, realm, password);
+        for _ in 0..2 {
+            let txid = Self::stun_new_txid();
+            let req = Self::turn_refresh_request(txid, lifetime, username, password, realm, nonce);
+            let resp =
+                Self::turn_transaction(sock, txid, &req, transactions, inbox, op_timeout).await?;
+            Self::stun_verify_response(&resp, Some(&key))?;
+            match Self::turn_refresh_outcome(&resp)? {
+                TurnRefreshOutcome::Refreshed { lifetime } => return Ok(lifetime),
+                TurnRefreshOutcome::StaleNonce { nonce: fresh } => *nonce = fresh,
+            }
+        }
+        Err(anyhow!("TURN Refresh kept reporting a stale nonce"))
+    }
+
+    /// Keeps a TURN/UDP allocation and the permissions of the peers in
+    /// `permitted` alive until aborted. Runs beside the task reading `sock`,
+    /// which delivers the responses, so a long generation streamed from
+    /// that task does not hold up the refresh.
+    #[cfg(not(target_os = "android"))]
+    pub(super) async fn turn_keepalive_udp(
+        sock: Arc<UdpSocket>,
+        username: String,
+        password: String,
+        realm: String,
+        nonce: Arc<Mutex<String>>,
+        permitted: Arc<Mutex<HashSet<SocketAddr>>>,
+        transactions: Arc<TurnTransactions>,
+        timeouts: TurnTimeouts,
+    ) {
+        let permission_interval = Self::turn_refresh_interval(Self::TURN_PERMISSION_LIFETIME_SECS);
+        let mut delay = Self::turn_refresh_interval(Self::TURN_LIFETIME_SECS);
+        loop {
+            // Permissions expire after 300 s whatever the allocation lifetime
+            tokio::time::sleep(delay.min(permission_interval)).await;
+
+            let mut fresh_nonce = nonce.lock().await.clone();
+            let refreshed = Self::turn_refresh(
+                &sock,
+                Self::TURN_LIFETIME_SECS,
+                &username,
+                &password,
+                &realm,
+                &mut fresh_nonce,
+                &transactions,
+                None,
+                timeouts.op,
+            )
+            .await;
+            *nonce.lock().await = fresh_nonce.clone();
+            delay = match refreshed {
+                Ok(granted) => Self::turn_refresh_interval(granted),
+                Err(e) => {
+                    warn!("TURN Refresh failed: {}", e);
+                    timeouts.setup
+                }
+            };
+
+            let peers: Vec<SocketAddr> = permitted.lock().await.iter().copied().collect();
+            for peer in peers {
+                if let Err(e) = Self::turn_create_permission(
+                    &sock,
+                    peer,
+                    &username,
+                    &password,
+                    &realm,
+                    &fresh_nonce,
+                    &transactions,
+                    None,
+                    timeouts.op,
+                )
+                .await
+                {
+                    warn!(
+                        "TURN CreatePermission refresh for {} failed: {}",
+                        common::addr_log_label(&peer),
+                        e
+                    );
+                }
+            }
+        }
+    }
+
     #[cfg(not(target_os = "android"))]
     pub(super) async fn turn_send_indication(
         sock: &UdpSocket,
@@ -996,99 +1883,6 @@ impl ClientWorker {
         Some((peer, data))
     }
 
-    #[cfg(not(target_os = "android"))]
-    pub(super) async fn turn_send_reliable_over_indication(
-        sock: &UdpSocket,
-        peer: SocketAddr,
-        connection_id: [u8; 16],
-        secret: [u8; 32],
-        msg_id: u32,
-        payload: &[u8],
-        inbox: &mut VecDeque<(SocketAddr, Vec<u8>)>,
-    ) -> Result<()> {
-        let max_payload = Self::P2P_UDP_MTU_PAYLOAD.saturating_sub(Self::P2P_UDP_HEADER_LEN);
-        if max_payload == 0 {
-            return Err(anyhow!("p2p udp mtu too small"));
-        }
-        let frag_cnt = ((payload.len() + max_payload - 1) / max_payload).max(1);
-        if frag_cnt > Self::P2P_MAX_FRAGMENTS_PER_MESSAGE as usize {
-            return Err(anyhow!("p2p udp too many fragments"));
-        }
-
-        for frag_idx in 0..frag_cnt {
-            let start = frag_idx * max_payload;
-            let end = ((frag_idx + 1) * max_payload).min(payload.len());
-            let frag_payload = &payload[start..end];
-            let timestamp = Self::p2p_now_secs();
-            let tag = Self::p2p_udp_tag(
-                &secret,
-                &connection_id,
-                0,
-                msg_id,
-                frag_idx as u16,
-                frag_cnt as u16,
-                timestamp,
-                frag_payload,
-            );
-            let hdr = Self::p2p_udp_make_header(
-                0,
-                msg_id,
-                frag_idx as u16,
-                frag_cnt as u16,
-                timestamp,
-                &tag,
-            );
-            let mut pkt = Vec::with_capacity(Self::P2P_UDP_HEADER_LEN + frag_payload.len());
-            pkt.extend_from_slice(&hdr);
-            pkt.extend_from_slice(frag_payload);
-
-            let mut tries = 0u32;
-            loop {
-                tries += 1;
-                Self::turn_send_indication(sock, peer, &pkt).await?;
-
-                let mut buf = vec![0u8; 4096];
-                let recv_res = timeout(Duration::from_millis(400), sock.recv(&mut buf)).await;
-                if let Ok(Ok(n)) = recv_res {
-                    if let Some((src, data)) = Self::turn_parse_data_indication(&buf[..n]) {
-                        if src == peer {
-                            if let Some((flags, ack_id, ack_frag_idx, ack_frag_cnt, ts, ack_tag)) =
-                                Self::p2p_udp_parse_header(&data)
-                            {
-                                let valid_ack = (flags & Self::P2P_UDP_FLAG_ACK) != 0
-                                    && ack_id == msg_id
-                                    && ack_frag_idx == 0
-                                    && ack_frag_cnt == 0
-                                    && Self::p2p_udp_validate_fragment(
-                                        &secret,
-                                        &connection_id,
-                                        flags,
-                                        ack_id,
-                                        ack_frag_idx,
-                                        ack_frag_cnt,
-                                        ts,
-                                        &[],
-                                        &ack_tag,
-                                        Self::p2p_now_secs(),
-                                    )
-                                    .is_ok();
-                                if valid_ack {
-                                    break;
-                                }
-                            }
-                        }
-                        inbox.push_back((src, data));
-                    }
-                }
-
-                if tries >= 10 {
-                    return Err(anyhow!("p2p udp send timeout msg_id={msg_id}"));
-                }
-            }
-        }
-        Ok(())
-    }
-
     pub(super) fn stun_attr_iter(msg: &[u8]) -> Result<Vec<(u16, Vec<u8>)>> {
         if msg.len() < 20 {
             return Err(anyhow!("stun msg too short"));
@@ -1151,12 +1945,38 @@ impl ClientWorker {
         None
     }
 
+    /// Host and port of a `stun:` URL such as `stun:stun.example.com:3478` or
+    /// `stun:[2001:db8::1]:3478`. IPv6 hosts are returned without brackets and
+    /// the port defaults to 3478.
     pub(super) fn parse_stun_host_port(url: &str) -> Option<(String, u16)> {
         let url = url.trim();
         let rest = url.strip_prefix("stun:").unwrap_or(url);
         let rest = rest.strip_prefix("//").unwrap_or(rest);
-        let (host, port) = rest.rsplit_once(':')?;
-        let port: u16 = port.parse().ok()?;
+        // Ignore a query such as ?transport=udp
+        let rest = rest.split('?').next().unwrap_or(rest);
+        let (host, port) = if let Some(bracketed) = rest.strip_prefix('[') {
+            let (host, after) = bracketed.split_once(']')?;
+            host.parse::<std::net::Ipv6Addr>().ok()?;
+            match after {
+                "" => (host, None),
+                after => (host, Some(after.strip_prefix(':')?)),
+            }
+        } else if rest.parse::<std::net::Ipv6Addr>().is_ok() {
+            // Unbracketed IPv6 literal; every colon belongs to the address
+            (rest, None)
+        } else {
+            match rest.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (rest, None),
+            }
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let port = match port {
+            Some(port) => port.parse().ok()?,
+            None => DEFAULT_STUN_PORT,
+        };
         Some((host.to_string(), port))
     }
 
@@ -1267,16 +2087,25 @@ impl ClientWorker {
         let Some((host, port)) = Self::parse_stun_host_port(stun_url) else {
             return Err(anyhow!("Invalid STUN url: {stun_url}"));
         };
-        let server = format!("{}:{}", host, port);
+        let server = tokio::net::lookup_host((host.as_str(), port))
+            .await?
+            .next()
+            .ok_or_else(|| anyhow!("STUN host resolve failed"))?;
 
-        let sock = UdpSocket::bind("0.0.0.0:0").await?;
+        let local = if server.is_ipv6() {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let sock = UdpSocket::bind(local).await?;
         let (txid, req) = Self::build_stun_binding_request();
 
-        sock.send_to(&req, &server).await?;
+        sock.send_to(&req, server).await?;
 
         let mut buf = [0u8; 1500];
         let (n, _addr) = timeout(Duration::from_secs(3), sock.recv_from(&mut buf)).await??;
         let resp = &buf[..n];
+        Self::stun_verify_response(resp, None)?;
         Self::parse_xor_mapped_address(resp, &txid)
             .ok_or_else(|| anyhow!("Failed to parse STUN XOR-MAPPED-ADDRESS"))
     }
@@ -1288,6 +2117,38 @@ impl ClientWorker {
         sock.connect("1.1.1.1:80").await?;
         Ok(sock.local_addr()?.ip())
     }
+
+    /// IPv6 counterpart of `detect_outbound_ip`.
+    pub(super) async fn detect_outbound_ipv6() -> Result<std::net::IpAddr> {
+        let sock = UdpSocket::bind("[::]:0").await?;
+        sock.connect("[2606:4700:4700::1111]:80").await?;
+        Ok(sock.local_addr()?.ip())
+    }
+
+    /// Local addresses to rank P2P candidate pairs against: the data-plane
+    /// socket's own address when it is bound to a specific one, and the
+    /// outbound interface of each address family that has a route. All of
+    /// them carry the socket's port.
+    pub(super) async fn local_candidate_addrs(bound: SocketAddr) -> Vec<SocketAddr> {
+        let mut ips = Vec::new();
+        if !bound.ip().is_unspecified() {
+            ips.push(bound.ip());
+        }
+        for ip in [
+            Self::detect_outbound_ip().await,
+            Self::detect_outbound_ipv6().await,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        }
+        ips.into_iter()
+            .map(|ip| SocketAddr::new(ip, bound.port()))
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -1360,6 +2221,54 @@ mod p2p_security_tests {
         .is_err());
     }
 
+    #[test]
+    fn stun_urls_parse_ipv4_hostnames_and_ipv6_literals() {
+        let parse = ClientWorker::parse_stun_host_port;
+        assert_eq!(
+            parse("stun:203.0.113.7:3479"),
+            Some(("203.0.113.7".to_string(), 3479))
+        );
+        assert_eq!(
+            parse("stun:stun.l.google.com:19302"),
+            Some(("stun.l.google.com".to_string(), 19302))
+        );
+        assert_eq!(
+            parse("stun:stun.example.com"),
+            Some(("stun.example.com".to_string(), 3478))
+        );
+        assert_eq!(
+            parse("stun:[2001:db8::1]:3479"),
+            Some(("2001:db8::1".to_string(), 3479))
+        );
+        assert_eq!(
+            parse("stun://[2001:db8::1]?transport=udp"),
+            Some(("2001:db8::1".to_string(), 3478))
+        );
+        assert_eq!(
+            parse("stun:2001:db8::1"),
+            Some(("2001:db8::1".to_string(), 3478))
+        );
+
+        assert_eq!(parse("stun:[2001:db8::1"), None);
+        assert_eq!(parse("stun:[2001:db8::1]3478"), None);
+        assert_eq!(parse("stun:[not-ipv6]:3478"), None);
+        assert_eq!(parse("stun::3478"), None);
+        assert_eq!(parse("stun:host:99999"), None);
+    }
+
+    #[test]
+    fn udp_payload_matches_tcp_frame_payload() {
+        let command = sample_command([6u8; 16]);
+        let mut tcp = Vec::new();
+        common::write_command_sync(&mut tcp, &command).unwrap();
+        let udp = ClientWorker::udp_encode_command(&command).unwrap();
+
+        // TCP: length, magic and version ahead of the payload; UDP: length only
+        assert_eq!(tcp[6..], udp[4..]);
+        let decoded = ClientWorker::udp_decode_command(&udp).unwrap();
+        assert_eq!(decoded.variant_name(), command.variant_name());
+    }
+
     #[test]
     fn tcp_envelope_rejects_replay_and_cross_connection() {
         let secret = [9u8; 32];
@@ -1427,6 +2336,435 @@ mod p2p_security_tests {
             .is_none());
     }
 
+    #[test]
+    fn framer_fragments_reassemble_to_the_original_payload() {
+        let framer = ReliableFramer::new([4u8; 16], [9u8; 32]);
+        let from: SocketAddr = "127.0.0.1:9999".parse().unwrap();
+        let payload: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+
+        let mut packets = framer.fragment(5, &payload).unwrap();
+        assert_eq!(packets.len(), 3);
+        assert!(packets
+            .iter()
+            .all(|pkt| pkt.len() <= ClientWorker::P2P_UDP_MTU_PAYLOAD));
+
+        // Arrival order does not matter
+        packets.reverse();
+        let mut state = P2PUdpReassemblyState::new();
+        let mut full = None;
+        for pkt in &packets {
+            let FramedPacket::Fragment {
+                msg_id,
+                frag_idx,
+                frag_cnt,
+                payload,
+            } = framer.open(pkt).unwrap()
+            else {
+                panic!("expected a data fragment");
+            };
+            assert_eq!(msg_id, 5);
+            full = state
+                .accept_fragment(from, msg_id, frag_idx, frag_cnt, payload)
+                .unwrap();
+        }
+        assert_eq!(full.unwrap(), payload);
+
+        let empty = framer.fragment(6, &[]).unwrap();
+        assert_eq!(empty.len(), 1);
+        assert!(matches!(
+            framer.open(&empty[0]).unwrap(),
+            FramedPacket::Fragment {
+                msg_id: 6,
+                frag_idx: 0,
+                frag_cnt: 1,
+                payload: [],
+            }
+        ));
+    }
+
+    #[test]
+    fn framer_rejects_tampered_foreign_and_oversized_messages() {
+        let framer = ReliableFramer::new([4u8; 16], [9u8; 32]);
+        let foreign = ReliableFramer::new([4u8; 16], [8u8; 32]);
+
+        let mut pkt = framer.fragment(1, b"hello").unwrap().remove(0);
+        assert!(foreign.open(&pkt).is_err());
+        *pkt.last_mut().unwrap() ^= 0xff;
+        assert!(framer.open(&pkt).is_err());
+        assert!(framer.open(&pkt[..10]).is_err());
+
+        let ack = framer.ack(7, 2, 3);
+        assert_eq!(framer.acked_fragment(&ack, 7), Some(2));
+        assert_eq!(framer.acked_fragment(&ack, 8), None);
+        assert_eq!(foreign.acked_fragment(&ack, 7), None);
+        let data = framer.fragment(7, b"").unwrap().remove(0);
+        assert_eq!(framer.acked_fragment(&data, 7), None);
+
+        let max_payload = ClientWorker::P2P_UDP_MTU_PAYLOAD - ClientWorker::P2P_UDP_HEADER_LEN;
+        let limit = max_payload * ClientWorker::P2P_MAX_FRAGMENTS_PER_MESSAGE as usize;
+        assert!(framer.fragment(2, &vec![0u8; limit]).is_ok());
+        assert!(framer.fragment(3, &vec![0u8; limit + 1]).is_err());
+    }
+
+    #[test]
+    fn send_window_retransmits_only_unacknowledged_fragments() {
+        let start = Instant::now();
+        let mut window = SendWindow::new(1, 5, 2);
+        assert_eq!(window.due(start).unwrap(), vec![0, 1]);
+        assert!(window.due(start).unwrap().is_empty());
+
+        // An ACK beyond the first outstanding fragment does not slide the window
+        window.ack(1);
+        assert!(window.due(start).unwrap().is_empty());
+        window.ack(0);
+        assert_eq!(window.due(start).unwrap(), vec![2, 3]);
+
+        window.ack(2);
+        let later = start + ReliableFramer::ACK_TIMEOUT;
+        assert_eq!(window.next_deadline(), Some(later));
+        assert_eq!(window.due(later).unwrap(), vec![3, 4]);
+        window.ack(4);
+        window.ack(3);
+        assert!(window.is_complete());
+
+        let mut window = SendWindow::new(2, 1, 8);
+        let mut now = start;
+        for _ in 0..ReliableFramer::SEND_ATTEMPTS {
+            assert_eq!(window.due(now).unwrap(), vec![0]);
+            now += ReliableFramer::ACK_TIMEOUT;
+        }
+        assert!(window.due(now).is_err());
+    }
+
+    /// Relays datagrams between `a` and `b` after `delay`, which gives the
+    /// link a round trip of twice that.
+    #[cfg(not(target_os = "android"))]
+    async fn spawn_delay_link(a: SocketAddr, b: SocketAddr, delay: Duration) -> SocketAddr {
+        let relay = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let addr = relay.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 64 * 1024];
+            while let Ok((n, from)) = relay.recv_from(&mut buf).await {
+                let to = if from == a { b } else { a };
+                let relay = Arc::clone(&relay);
+                let pkt = buf[..n].to_vec();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let _ = relay.send_to(&pkt, to).await;
+                });
+            }
+        });
+        addr
+    }
+
+    #[cfg(not(target_os = "android"))]
+    #[tokio::test]
+    async fn windowed_send_takes_fewer_round_trips_than_stop_and_wait() {
+        let delay = Duration::from_millis(10);
+        let payload: Vec<u8> = (0..64 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let link = spawn_delay_link(
+            sender.local_addr().unwrap(),
+            receiver.local_addr().unwrap(),
+            delay,
+        )
+        .await;
+        let framer = ReliableFramer::new([4u8; 16], [9u8; 32]);
+        let frag_cnt = framer.fragment(0, &payload).unwrap().len() as u128;
+
+        // A window of one is the old stop-and-wait scheme
+        let mut round_trips = Vec::new();
+        for (msg_id, window) in [(1, 1), (2, ReliableFramer::DEFAULT_WINDOW)] {
+            let tx = DirectUdp::new(&sender, link, framer.with_window(window));
+            let rx = DirectUdp::new(&receiver, link, framer);
+            let started = Instant::now();
+            let (sent, received) = tokio::join!(tx.send(msg_id, &payload), rx.recv());
+            sent.unwrap();
+            assert_eq!(received.unwrap(), payload);
+            round_trips.push(started.elapsed().as_millis() / (2 * delay).as_millis());
+        }
+        assert!(
+            round_trips[0] >= frag_cnt,
+            "stop-and-wait took {} round trips for {} fragments",
+            round_trips[0],
+            frag_cnt
+        );
+        assert!(
+            round_trips[1] * 4 <= round_trips[0],
+            "window of {} took {} round trips, stop-and-wait {}",
+            ReliableFramer::DEFAULT_WINDOW,
+            round_trips[1],
+            round_trips[0]
+        );
+    }
+
+    #[test]
+    fn stun_integrity_and_fingerprint_reject_tampered_messages() {
+        let credentials = ("alice", "example.org", "secret");
+        let key = ClientWorker::stun_long_term_key(credentials.0, credentials.1, credentials.2);
+        let lifetime_t: u16 = 0x000d;
+        let attrs = [(&lifetime_t, 600u32.to_be_bytes().to_vec())];
+        let msg =
+            ClientWorker::stun_build_message(0x0103, [5u8; 12], &attrs, Some(credentials), true);
+        assert!(ClientWorker::stun_verify_integrity(&msg, &key).is_ok());
+        assert!(ClientWorker::stun_verify_fingerprint(&msg).is_ok());
+        assert!(ClientWorker::stun_verify_response(&msg, Some(&key)).is_ok());
+
+        let wrong = ClientWorker::stun_long_term_key("alice", "example.org", "guess");
+        assert!(ClientWorker::stun_verify_integrity(&msg, &wrong).is_err());
+
+        // Flip a bit of the LIFETIME value
+        let mut tampered = msg.clone();
+        tampered[24] ^= 0x01;
+        assert!(ClientWorker::stun_verify_integrity(&tampered, &key).is_err());
+        assert!(ClientWorker::stun_verify_fingerprint(&tampered).is_err());
+        assert!(ClientWorker::stun_verify_response(&tampered, None).is_err());
+
+        // Responses without the attributes are only checked for what they carry
+        let plain = ClientWorker::stun_build_message(0x0101, [5u8; 12], &attrs, None, false);
+        assert!(ClientWorker::stun_verify_integrity(&plain, &key).is_err());
+        assert!(ClientWorker::stun_verify_fingerprint(&plain).is_err());
+        assert!(ClientWorker::stun_verify_response(&plain, Some(&key)).is_ok());
+    }
+
+    #[test]
+    fn authenticated_turn_success_without_integrity_is_rejected() {
+        let key = ClientWorker::stun_long_term_key("alice", "example.org", "secret");
+        let lifetime_t: u16 = 0x000d;
+        let attrs = [(&lifetime_t, 600u32.to_be_bytes().to_vec())];
+        for msg_type in [0x0103, 0x0104, 0x0108] {
+            let spoofed = ClientWorker::stun_build_message(msg_type, [7u8; 12], &attrs, None, true);
+            assert!(ClientWorker::stun_verify_response(&spoofed, Some(&key)).is_err());
+            // Unauthenticated requests have no key to check it with
+            assert!(ClientWorker::stun_verify_response(&spoofed, None).is_ok());
+        }
+
+        // A 401 challenge carries no MESSAGE-INTEGRITY
+        let challenge = ClientWorker::stun_build_message(0x0113, [7u8; 12], &attrs, None, true);
+        assert!(ClientWorker::stun_verify_response(&challenge, Some(&key)).is_ok());
+    }
+
+    #[test]
+    fn stun_verification_accepts_the_rfc_5769_sample_response() {
+        // RFC 5769 2.2, short-term credential with password "VOkJxbRl1RmTxUk/WvJxBt"
+        let resp: [u8; 80] = [
+            0x01, 0x01, 0x00, 0x3c, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34,
+            0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00, 0x0b, 0x74, 0x65, 0x73, 0x74,
+            0x20, 0x76, 0x65, 0x63, 0x74, 0x6f, 0x72, 0x20, 0x00, 0x20, 0x00, 0x08, 0x00, 0x01,
+            0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43, 0x00, 0x08, 0x00, 0x14, 0x2b, 0x91, 0xf5, 0x99,
+            0xfd, 0x9e, 0x90, 0xc3, 0x8c, 0x74, 0x89, 0xf9, 0x2a, 0xf9, 0xba, 0x53, 0xf0, 0x6b,
+            0xe7, 0xd7, 0x80, 0x28, 0x00, 0x04, 0xc0, 0x7d, 0x4c, 0x96,
+        ];
+        let txid: [u8; 12] = resp[8..20].try_into().unwrap();
+        assert!(ClientWorker::stun_verify_response(&resp, Some(b"VOkJxbRl1RmTxUk/WvJxBt")).is_ok());
+        assert_eq!(
+            ClientWorker::parse_xor_mapped_address(&resp, &txid),
+            Some("192.0.2.1:32853".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn turn_refresh_request_carries_credentials_and_lifetime() {
+        let txid = [6u8; 12];
+        let req =
+            ClientWorker::turn_refresh_request(txid, 600, "alice", "secret", "example.org", "n1");
+        assert_eq!(u16::from_be_bytes([req[0], req[1]]), 0x0004);
+        assert_eq!(req[8..20], txid);
+
+        let attrs = ClientWorker::stun_attr_iter(&req).unwrap();
+        let types: Vec<u16> = attrs.iter().map(|(t, _)| *t).collect();
+        assert_eq!(types, [0x0006, 0x0014, 0x0015, 0x000d, 0x0008, 0x8028]);
+        assert_eq!(
+            ClientWorker::stun_get_text_attr(&attrs, 0x0006).as_deref(),
+            Some("alice")
+        );
+        assert_eq!(
+            ClientWorker::stun_get_text_attr(&attrs, 0x0015).as_deref(),
+            Some("n1")
+        );
+        assert_eq!(attrs[3].1, 600u32.to_be_bytes());
+        let key = ClientWorker::stun_long_term_key("alice", "example.org", "secret");
+        assert!(ClientWorker::stun_verify_response(&req, Some(&key)).is_ok());
+    }
+
+    #[test]
+    fn turn_refresh_outcome_reads_lifetime_and_stale_nonce() {
+        let lifetime_t: u16 = 0x000d;
+        let error_t: u16 = 0x0009;
+        let nonce_t: u16 = 0x0015;
+        let ok = ClientWorker::stun_build_message(
+            0x0104,
+            [1u8; 12],
+            &[(&lifetime_t, 300u32.to_be_bytes().to_vec())],
+            None,
+            true,
+        );
+        assert_eq!(
+            ClientWorker::turn_refresh_outcome(&ok).unwrap(),
+            TurnRefreshOutcome::Refreshed { lifetime: 300 }
+        );
+
+        let stale_code = [&[0u8, 0, 4, 38][..], b"Stale Nonce"].concat();
+        let stale = ClientWorker::stun_build_message(
+            0x0114,
+            [1u8; 12],
+            &[(&error_t, stale_code), (&nonce_t, b"n2".to_vec())],
+            None,
+            true,
+        );
+        assert_eq!(
+            ClientWorker::turn_refresh_outcome(&stale).unwrap(),
+            TurnRefreshOutcome::StaleNonce {
+                nonce: "n2".to_string()
+            }
+        );
+
+        let unauthorized = [&[0u8, 0, 4, 1][..], b"Unauthorized"].concat();
+        let denied = ClientWorker::stun_build_message(
+            0x0114,
+            [1u8; 12],
+            &[(&error_t, unauthorized), (&nonce_t, b"n2".to_vec())],
+            None,
+            true,
+        );
+        let err = ClientWorker::turn_refresh_outcome(&denied).unwrap_err();
+        assert!(err.to_string().contains("401"), "{}", err);
+    }
+
+    #[cfg(not(target_os = "android"))]
+    #[tokio::test]
+    async fn turn_refresh_retries_with_a_fresh_nonce_and_parks_data() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server.local_addr().unwrap()).await.unwrap();
+        let peer: SocketAddr = "127.0.0.1:7000".parse().unwrap();
+
+        let fake_turn = async {
+            let error_t: u16 = 0x0009;
+            let nonce_t: u16 = 0x0015;
+            let lifetime_t: u16 = 0x000d;
+            let key = ClientWorker::stun_long_term_key("alice", "example.org", "secret");
+            let mut buf = vec![0u8; 2048];
+            let mut nonces = Vec::new();
+            for attempt in 0..2 {
+                let (n, from) = server.recv_from(&mut buf).await.unwrap();
+                let req = &buf[..n];
+                assert!(ClientWorker::stun_verify_integrity(req, &key).is_ok());
+                let attrs = ClientWorker::stun_attr_iter(req).unwrap();
+                nonces.push(ClientWorker::stun_get_text_attr(&attrs, 0x0015).unwrap());
+                let txid: [u8; 12] = req[8..20].try_into().unwrap();
+
+                // Relayed traffic may arrive before the response
+                let data = ClientWorker::stun_build_message(
+                    0x0017,
+                    ClientWorker::stun_new_txid(),
+                    &[
+                        (
+                            &0x0012,
+                            ClientWorker::turn_encode_xor_peer_address(peer, &[0u8; 12]),
+                        ),
+                        (&0x0013, vec![attempt]),
+                    ],
+                    None,
+                    false,
+                );
+                server.send_to(&data, from).await.unwrap();
+
+                let resp = if attempt == 0 {
+                    let code = [&[0u8, 0, 4, 38][..], b"Stale Nonce"].concat();
+                    ClientWorker::stun_build_message(
+                        0x0114,
+                        txid,
+                        &[(&error_t, code), (&nonce_t, b"fresh".to_vec())],
+                        None,
+                        true,
+                    )
+                } else {
+                    ClientWorker::stun_build_message(
+                        0x0104,
+                        txid,
+                        &[(&lifetime_t, 600u32.to_be_bytes().to_vec())],
+                        Some(("alice", "example.org", "secret")),
+                        true,
+                    )
+                };
+                server.send_to(&resp, from).await.unwrap();
+            }
+            nonces
+        };
+
+        let inbox = Mutex::new(VecDeque::new());
+        let mut nonce = "stale".to_string();
+        let (granted, nonces) = tokio::join!(
+            ClientWorker::turn_refresh(
+                &client,
+                600,
+                "alice",
+                "secret",
+                "example.org",
+                &mut nonce,
+                &TurnTransactions::default(),
+                Some(&inbox),
+                Duration::from_secs(2),
+            ),
+            fake_turn
+        );
+        assert_eq!(granted.unwrap(), 600);
+        assert_eq!(nonces, ["stale", "fresh"]);
+        assert_eq!(nonce, "fresh");
+        let parked: Vec<_> = inbox.lock().await.drain(..).collect();
+        assert_eq!(parked, [(peer, vec![0]), (peer, vec![1])]);
+    }
+
+    #[cfg(not(target_os = "android"))]
+    #[tokio::test]
+    async fn turn_refresh_gets_its_response_from_the_reading_task() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server.local_addr().unwrap()).await.unwrap();
+        let transactions = TurnTransactions::default();
+
+        let fake_turn = async {
+            let mut buf = vec![0u8; 2048];
+            let (n, from) = server.recv_from(&mut buf).await.unwrap();
+            let txid: [u8; 12] = buf[8..20].try_into().unwrap();
+            let resp = ClientWorker::stun_build_message(
+                0x0104,
+                txid,
+                &[(&0x000d, 300u32.to_be_bytes().to_vec())],
+                Some(("alice", "example.org", "secret")),
+                true,
+            );
+            assert!(n > 20);
+            server.send_to(&resp, from).await.unwrap();
+        };
+        // Stands in for the loop serving relayed data, which owns the reads
+        let reader = async {
+            let mut buf = vec![0u8; 2048];
+            let n = client.recv(&mut buf).await.unwrap();
+            assert!(transactions.deliver(&buf[..n]));
+        };
+
+        let mut nonce = "n1".to_string();
+        let (granted, _, _) = tokio::join!(
+            ClientWorker::turn_refresh(
+                &client,
+                600,
+                "alice",
+                "secret",
+                "example.org",
+                &mut nonce,
+                &transactions,
+                None,
+                Duration::from_secs(2),
+            ),
+            fake_turn,
+            reader
+        );
+        assert_eq!(granted.unwrap(), 300);
+    }
+
     #[test]
     fn signed_payload_round_trip_preserves_command_shape() {
         let secret = [1u8; 32];
@@ -1465,4 +2803,45 @@ mod p2p_security_tests {
             other => panic!("unexpected decoded command: {:?}", other),
         }
     }
+
+    #[cfg(not(target_os = "android"))]
+    #[tokio::test]
+    async fn turn_setup_fails_over_to_the_next_server() {
+        let urls = vec![
+            "turns://stalled.example:5349".to_string(),
+            "turns://broken.example:5349".to_string(),
+            "turns://healthy.example:5349".to_string(),
+        ];
+        let mut tried = Vec::new();
+        let (used, value) =
+            ClientWorker::turn_with_failover(&urls, Duration::from_millis(50), |url| {
+                tried.push(url.clone());
+                async move {
+                    if url.contains("stalled") {
+                        std::future::pending::<()>().await;
+                    }
+                    if url.contains("broken") {
+                        return Err(anyhow!("TURN Allocate failed type=0x0113"));
+                    }
+                    Ok(42)
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(used, urls[2]);
+        assert_eq!(value, 42);
+        assert_eq!(tried, urls);
+
+        let err = ClientWorker::turn_with_failover(&urls[..1], Duration::from_millis(10), |_| {
+            std::future::pending::<Result<()>>()
+        })
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+        assert!(
+            ClientWorker::turn_with_failover(&[], Duration::from_millis(10), |_| async { Ok(()) })
+                .await
+                .is_err()
+        );
+    }
 }
@@ -1,43 +1,440 @@
-use crate::handle::{WSWorker, WorkerHandle};
+use crate::handle::handle_tcp::InferenceRelay;
+use crate::handle::{CancelState, WSWorker, WorkerHandle};
 use crate::util::cmd::Args;
 
-use anyhow::Result;
-use futures_util::StreamExt;
+use anyhow::{anyhow, Result};
+use common::{read_command_sync, write_command_sync, ChatMessage, Command, CommandV1};
+use futures_util::stream::FuturesUnordered;
+use futures_util::{SinkExt, StreamExt};
 use std::future::Future;
 use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::Mutex;
-use tokio_tungstenite::connect_async;
+use tokio_tungstenite::{connect_async, tungstenite::Message, WebSocketStream};
+use tracing::{debug, info, warn};
 
-//use futures_util::SinkExt;
-//use tokio_tungstenite::{WebSocketStream,tungstenite::protocol::Message};
+/// What a task asks the model to continue.
+enum TaskInput {
+    Prompt(String),
+    Chat(Vec<ChatMessage>),
+}
 
 impl WSWorker {
     pub async fn new(args: Args) -> Result<Self> {
-        let url = "ws://example.com/ws";
-        let (ws_stream, _) = connect_async(url).await?;
+        let url = format!("ws://{}:{}/ws", args.server_addr, args.control_port);
+        let (ws_stream, _) = connect_async(url.as_str()).await?;
+        Ok(Self::from_stream(ws_stream, args))
+    }
+}
+
+impl<S> WSWorker<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    pub fn from_stream(ws_stream: WebSocketStream<S>, args: Args) -> Self {
         let (write, read) = ws_stream.split();
-        Ok(Self {
+        Self {
             reader: Arc::new(Mutex::new(read)),
             writer: Arc::new(Mutex::new(write)),
             args,
-        })
+            cancel_state: Arc::new(CancelState::new()),
+            #[cfg(not(target_os = "android"))]
+            engine: crate::handle::handle_tcp::global_engine(),
+        }
+    }
+
+    async fn send_command(&self, command: CommandV1) -> Result<()> {
+        let mut frame = Vec::new();
+        write_command_sync(&mut frame, &Command::V1(command))?;
+        self.writer
+            .lock()
+            .await
+            .send(Message::binary(frame))
+            .await?;
+        Ok(())
+    }
+
+    /// Next command from the server, `None` once the connection is closed.
+    async fn read_command(&self) -> Result<Option<Command>> {
+        let mut reader = self.reader.lock().await;
+        while let Some(message) = reader.next().await {
+            match message? {
+                Message::Binary(frame) => return read_command_sync(&mut frame.as_ref()).map(Some),
+                Message::Close(_) => return Ok(None),
+                // Pings are answered by tungstenite itself
+                _ => continue,
+            }
+        }
+        Ok(None)
+    }
+
+    fn inference_relay(&self) -> InferenceRelay<'_> {
+        InferenceRelay {
+            #[cfg(not(target_os = "android"))]
+            engine: &self.engine,
+            cancel_state: &self.cancel_state,
+            chunk_bytes: self.args.stream_chunk_bytes,
+        }
+    }
+
+    /// Streams one task back to the server. A task that cannot run ends
+    /// with a single done chunk carrying the error.
+    async fn run_inference_task(
+        &self,
+        task_id: String,
+        input: TaskInput,
+        max_tokens: u32,
+        temperature: f32,
+        top_k: u32,
+        top_p: f32,
+        repeat_penalty: f32,
+        repeat_last_n: i32,
+        min_keep: u32,
+    ) -> Result<()> {
+        let relay = self.inference_relay();
+        let prompt = match input {
+            TaskInput::Prompt(prompt) => prompt,
+            TaskInput::Chat(messages) => match relay.chat_prompt(&messages).await {
+                Ok(prompt) => prompt,
+                Err(e) => {
+                    return relay
+                        .fail_task(task_id, &e, |chunk| self.send_command(chunk))
+                        .await
+                }
+            },
+        };
+        relay
+            .stream_task(
+                task_id,
+                prompt,
+                max_tokens,
+                temperature,
+                top_k,
+                top_p,
+                repeat_penalty,
+                repeat_last_n,
+                min_keep,
+                |chunk| self.send_command(chunk),
+            )
+            .await
     }
 }
 
-impl WorkerHandle for WSWorker {
+impl<S> WorkerHandle for WSWorker<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
     fn login(&self) -> impl Future<Output = Result<()>> + Send {
-        async move { todo!() }
+        async move { Err(anyhow!("gpuf-s does not serve WebSocket workers yet")) }
     }
 
     fn handler(&self) -> impl Future<Output = Result<()>> + Send {
-        async move { todo!() }
+        async move {
+            // Tasks stream while the loop keeps reading, so a CancelInference
+            // reaches a generation that is still running
+            let mut tasks = FuturesUnordered::new();
+            loop {
+                tokio::select! {
+                    Some(result) = tasks.next(), if !tasks.is_empty() => {
+                        let result: Result<()> = result;
+                        result?;
+                    }
+                    cmd = self.read_command() => {
+                        let Some(cmd) = cmd? else {
+                            warn!("WebSocket connection to server closed. Exiting handler...");
+                            return Err(anyhow!("Connection closed by server"));
+                        };
+                        match cmd {
+                            Command::V1(CommandV1::InferenceTask {
+                                task_id,
+                                prompt,
+                                max_tokens,
+                                temperature,
+                                top_k,
+                                top_p,
+                                repeat_penalty,
+                                repeat_last_n,
+                                min_keep,
+                            })
+                            | Command::V1(CommandV1::ModelInferenceTask {
+                                task_id,
+                                model: _,
+                                prompt,
+                                max_tokens,
+                                temperature,
+                                top_k,
+                                top_p,
+                                repeat_penalty,
+                                repeat_last_n,
+                                min_keep,
+                            }) => {
+                                info!(
+                                    "Received inference task: {} max_tokens: {}",
+                                    task_id, max_tokens
+                                );
+                                tasks.push(self.run_inference_task(
+                                    task_id,
+                                    TaskInput::Prompt(prompt),
+                                    max_tokens,
+                                    temperature,
+                                    top_k,
+                                    top_p,
+                                    repeat_penalty,
+                                    repeat_last_n,
+                                    min_keep,
+                                ));
+                            }
+                            Command::V1(CommandV1::ChatInferenceTask {
+                                task_id,
+                                model: _,
+                                messages,
+                                max_tokens,
+                                temperature,
+                                top_k,
+                                top_p,
+                                repeat_penalty,
+                                repeat_last_n,
+                                min_keep,
+                            }) => {
+                                info!(
+                                    "Received chat inference task: {} messages: {} max_tokens: {}",
+                                    task_id,
+                                    messages.len(),
+                                    max_tokens
+                                );
+                                tasks.push(self.run_inference_task(
+                                    task_id,
+                                    TaskInput::Chat(messages),
+                                    max_tokens,
+                                    temperature,
+                                    top_k,
+                                    top_p,
+                                    repeat_penalty,
+                                    repeat_last_n,
+                                    min_keep,
+                                ));
+                            }
+                            Command::V1(CommandV1::CancelInference { task_id }) => {
+                                debug!(task_id = %task_id, "Received CancelInference");
+                                self.cancel_state.cancel(task_id).await;
+                            }
+                            _ => {
+                                warn!("Received unexpected command over WebSocket; payload redacted");
+                            }
+                        }
+                    }
+                }
+            }
+        }
     }
 
     fn model_task(&self) -> impl Future<Output = Result<()>> + Send {
-        async move { todo!() }
+        async move { Err(anyhow!("gpuf-s does not serve WebSocket workers yet")) }
     }
 
     fn heartbeat_task(&self) -> impl Future<Output = Result<()>> + Send {
-        async move { todo!() }
+        async move { Err(anyhow!("gpuf-s does not serve WebSocket workers yet")) }
+    }
+}
+
+#[cfg(all(test, not(target_os = "android")))]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::collections::HashMap;
+    use tokio_tungstenite::tungstenite::protocol::Role;
+
+    /// A worker and the server end of its WebSocket, over an in-memory pipe.
+    async fn websocket_pair() -> (
+        WSWorker<tokio::io::DuplexStream>,
+        WSWorker<tokio::io::DuplexStream>,
+    ) {
+        let (worker_io, server_io) = tokio::io::duplex(1024);
+        let (worker_ws, server_ws) = tokio::join!(
+            WebSocketStream::from_raw_socket(worker_io, Role::Client, None),
+            WebSocketStream::from_raw_socket(server_io, Role::Server, None),
+        );
+        let args = Args::parse_from([
+            "gpuf-c",
+            "--client-id",
+            "00000000000000000000000000000001",
+            "--stream-chunk-bytes",
+            "4",
+        ]);
+        let worker = WSWorker::from_stream(worker_ws, args);
+        let server = WSWorker::from_stream(server_ws, worker.args.clone());
+        (worker, server)
+    }
+
+    #[tokio::test]
+    async fn failed_tasks_end_with_one_error_chunk() {
+        let (worker, server) = websocket_pair().await;
+
+        let server_side = async {
+            server
+                .send_command(CommandV1::InferenceTask {
+                    task_id: "prompt-task".to_string(),
+                    prompt: "Hello".to_string(),
+                    max_tokens: 16,
+                    temperature: 0.7,
+                    top_k: 40,
+                    top_p: 0.9,
+                    repeat_penalty: 1.1,
+                    repeat_last_n: 64,
+                    min_keep: 1,
+                })
+                .await
+                .unwrap();
+            server
+                .send_command(CommandV1::ChatInferenceTask {
+                    task_id: "chat-task".to_string(),
+                    model: String::new(),
+                    messages: vec![ChatMessage {
+                        role: "user".to_string(),
+                        content: "Hello".to_string(),
+                    }],
+                    max_tokens: 16,
+                    temperature: 0.7,
+                    top_k: 40,
+                    top_p: 0.9,
+                    repeat_penalty: 1.1,
+                    repeat_last_n: 64,
+                    min_keep: 1,
+                })
+                .await
+                .unwrap();
+
+            let mut chunks = HashMap::new();
+            while chunks.len() < 2 {
+                let Some(Command::V1(CommandV1::InferenceResultChunk {
+                    task_id,
+                    seq,
+                    done,
+                    error,
+                    ..
+                })) = server.read_command().await.unwrap()
+                else {
+                    panic!("expected a result chunk");
+                };
+                assert!(done, "{task_id} sent output without a model");
+                assert_eq!(seq, 0);
+                assert!(error.is_some());
+                assert!(
+                    chunks.insert(task_id.clone(), seq).is_none(),
+                    "{task_id} ended twice"
+                );
+            }
+            server.writer.lock().await.close().await.unwrap();
+            // Nothing else may follow the terminal chunks
+            assert!(server.read_command().await.unwrap().is_none());
+            chunks
+        };
+
+        // No model is loaded, so both tasks fail before generating
+        let (handled, chunks) = tokio::join!(worker.handler(), server_side);
+        assert!(handled.is_err());
+        assert_eq!(chunks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn stream_error_ends_the_task_with_the_next_seq() {
+        let (worker, server) = websocket_pair().await;
+
+        let pieces = vec![
+            Ok("Hello".to_string()),
+            Err(anyhow!("decode failed")),
+            Ok("never sent".to_string()),
+        ];
+        worker
+            .inference_relay()
+            .relay(
+                "task-1".to_string(),
+                1,
+                futures_util::stream::iter(pieces),
+                |chunk| worker.send_command(chunk),
+            )
+            .await
+            .unwrap();
+
+        let mut expected_seq = 0;
+        loop {
+            let Some(Command::V1(CommandV1::InferenceResultChunk {
+                seq, done, error, ..
+            })) = server.read_command().await.unwrap()
+            else {
+                panic!("expected a result chunk");
+            };
+            assert_eq!(seq, expected_seq);
+            expected_seq += 1;
+            if done {
+                assert_eq!(error.as_deref(), Some("decode failed"));
+                break;
+            }
+            assert_eq!(error, None);
+        }
+        assert!(expected_seq > 1, "output before the error was dropped");
+    }
+
+    #[tokio::test]
+    async fn task_chunks_arrive_in_order_over_a_websocket_pair() {
+        let (worker, server) = websocket_pair().await;
+
+        server
+            .send_command(CommandV1::InferenceTask {
+                task_id: "task-1".to_string(),
+                prompt: "Hello".to_string(),
+                max_tokens: 16,
+                temperature: 0.7,
+                top_k: 40,
+                top_p: 0.9,
+                repeat_penalty: 1.1,
+                repeat_last_n: 64,
+                min_keep: 1,
+            })
+            .await
+            .unwrap();
+
+        let Some(Command::V1(CommandV1::InferenceTask { task_id, .. })) =
+            worker.read_command().await.unwrap()
+        else {
+            panic!("worker did not receive the task");
+        };
+        let pieces = ["Hel", "lo", ", ", "wor", "ld"].map(|piece| Ok(piece.to_string()));
+        worker
+            .inference_relay()
+            .relay(task_id, 1, futures_util::stream::iter(pieces), |chunk| {
+                worker.send_command(chunk)
+            })
+            .await
+            .unwrap();
+
+        let mut text = String::new();
+        let mut expected_seq = 0;
+        loop {
+            let Some(Command::V1(CommandV1::InferenceResultChunk {
+                task_id,
+                seq,
+                delta,
+                done,
+                error,
+                completion_tokens,
+                ..
+            })) = server.read_command().await.unwrap()
+            else {
+                panic!("expected a result chunk");
+            };
+            assert_eq!(task_id, "task-1");
+            assert_eq!(seq, expected_seq);
+            assert_eq!(error, None);
+            expected_seq += 1;
+            if done {
+                assert_eq!(completion_tokens, 5);
+                break;
+            }
+            assert!(!delta.is_empty());
+            text.push_str(&delta);
+        }
+        assert_eq!(text, "Hello, world");
+        assert!(expected_seq > 2, "output was not split into chunks");
     }
 }
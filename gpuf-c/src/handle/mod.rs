@@ -12,14 +12,16 @@ use crate::llm_engine::Engine;
 use common::{DevicesInfo, EngineType as ClientEngineType, OsType, SystemInfo};
 use tracing::{error, info};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use tokio::sync::Mutex;
 
 use futures_util::stream::{SplitSink, SplitStream};
 use std::future::Future;
 #[allow(unused_imports)]
 use std::marker::PhantomData;
+use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 // LLM engine is not available in lightweight Android version
@@ -61,6 +63,9 @@ pub struct ClientWorker {
     engine_type: ClientEngineType,
     args: Args,
     cancel_state: Arc<CancelState>,
+    /// `PROTOCOL_REVISION` the server advertised via `ServerRevision`; 1
+    /// until it does.
+    server_revision: AtomicU32,
     #[cfg(not(target_os = "android"))]
     engine: Arc<Mutex<Option<AnyEngine>>>,
     #[cfg(target_os = "android")]
@@ -72,27 +77,124 @@ pub type UDPWorker = ClientWorker;
 
 pub struct CancelState {
     pub cancelled: Mutex<HashSet<String>>,
+    /// Task ids currently generating on this worker.
+    pub active: std::sync::Mutex<HashSet<String>>,
     pub notify: Notify,
+    /// Signalled when the last active task finishes.
+    idle: Notify,
+}
+
+/// Keeps a task listed as active until dropped, so early returns cannot leak entries.
+pub struct ActiveTaskGuard {
+    state: Arc<CancelState>,
+    task_id: String,
+}
+
+impl Drop for ActiveTaskGuard {
+    fn drop(&mut self) {
+        let mut active = self.state.active_set();
+        active.remove(&self.task_id);
+        if active.is_empty() {
+            self.state.idle.notify_waiters();
+        }
+    }
+}
+
+impl CancelState {
+    pub fn new() -> Self {
+        Self {
+            cancelled: Mutex::new(HashSet::new()),
+            active: std::sync::Mutex::new(HashSet::new()),
+            notify: Notify::new(),
+            idle: Notify::new(),
+        }
+    }
+
+    fn active_set(&self) -> std::sync::MutexGuard<'_, HashSet<String>> {
+        self.active
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Marks a task as in flight so it can be listed and bulk-cancelled.
+    pub fn register(self: &Arc<Self>, task_id: &str) -> ActiveTaskGuard {
+        self.active_set().insert(task_id.to_string());
+        ActiveTaskGuard {
+            state: Arc::clone(self),
+            task_id: task_id.to_string(),
+        }
+    }
+
+    pub async fn is_cancelled(&self, task_id: &str) -> bool {
+        self.cancelled.lock().await.contains(task_id)
+    }
+
+    pub async fn cancel(&self, task_id: String) {
+        self.cancelled.lock().await.insert(task_id);
+        self.notify.notify_waiters();
+    }
+
+    /// Drops a pending cancellation once the task has finished.
+    pub async fn clear(&self, task_id: &str) {
+        self.cancelled.lock().await.remove(task_id);
+    }
+
+    pub fn active_tasks(&self) -> Vec<String> {
+        self.active_set().iter().cloned().collect()
+    }
+
+    /// Signals every in-flight task to stop and returns how many were cancelled.
+    /// Call before swapping or freeing a model so no generation keeps running on it.
+    pub async fn cancel_all(&self) -> usize {
+        let active = self.active_tasks();
+        if active.is_empty() {
+            return 0;
+        }
+        {
+            let mut cancelled = self.cancelled.lock().await;
+            cancelled.extend(active.iter().cloned());
+        }
+        self.notify.notify_waiters();
+        info!("Cancelling {} in-flight inference task(s)", active.len());
+        active.len()
+    }
+
+    /// Waits up to `limit` for every active task to finish, as after
+    /// `cancel_all`. Returns false if some were still running.
+    pub async fn wait_idle(&self, limit: std::time::Duration) -> bool {
+        tokio::time::timeout(limit, async {
+            loop {
+                // Created before the check so a finish in between still wakes it
+                let idle = self.idle.notified();
+                if self.active_set().is_empty() {
+                    return;
+                }
+                idle.await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+}
+
+impl Default for CancelState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 // WS worker
 #[allow(dead_code)]
 
-pub struct WSWorker {
-    reader: Arc<
-        Mutex<
-            SplitStream<WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>>,
-        >,
-    >,
-    writer: Arc<
-        Mutex<
-            SplitSink<
-                WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>,
-                Message,
-            >,
-        >,
-    >,
+/// Worker speaking the control protocol over a WebSocket, one length-prefixed
+/// command per binary message.
+pub struct WSWorker<S = tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>> {
+    reader: Arc<Mutex<SplitStream<WebSocketStream<S>>>>,
+    writer: Arc<Mutex<SplitSink<WebSocketStream<S>, Message>>>,
     args: Args,
+    cancel_state: Arc<CancelState>,
+    #[cfg(not(target_os = "android"))]
+    engine: Arc<Mutex<Option<AnyEngine>>>,
 }
 
 pub enum AutoWorker {
@@ -130,67 +232,236 @@ impl WorkerHandle for AutoWorker {
     }
 }
 
-pub async fn new_worker(args: Args) -> AutoWorker {
+/// Delays between worker connection attempts: exponential from `initial` up to
+/// `max`, each randomized by +/-`jitter` so reconnecting clients spread out.
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectBackoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub jitter: f64,
+}
+
+impl Default for ReconnectBackoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl ReconnectBackoff {
+    /// Delay before retry `attempt` (0-based), without jitter.
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        self.initial
+            .checked_mul(factor)
+            .unwrap_or(self.max)
+            .min(self.max)
+    }
+
+    /// `base_delay` scaled by `1 + jitter * unit`, with `unit` in [-1, 1].
+    pub fn delay(&self, attempt: u32, unit: f64) -> Duration {
+        self.base_delay(attempt)
+            .mul_f64(1.0 + self.jitter * unit.clamp(-1.0, 1.0))
+    }
+}
+
+/// Runs `connect` until it succeeds, sleeping with `backoff` between attempts.
+/// Gives up with the last error after `max_retries` retries; 0 retries forever.
+async fn connect_with_backoff<T, C, CFut, S, SFut>(
+    backoff: ReconnectBackoff,
+    max_retries: u32,
+    mut connect: C,
+    mut sleep: S,
+) -> Result<T>
+where
+    C: FnMut() -> CFut,
+    CFut: Future<Output = Result<T>>,
+    S: FnMut(Duration) -> SFut,
+    SFut: Future<Output = ()>,
+{
+    let mut attempt = 0u32;
+    loop {
+        let err = match connect().await {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        if max_retries != 0 && attempt >= max_retries {
+            return Err(err.context(format!(
+                "Giving up on worker connection after {} retries",
+                max_retries
+            )));
+        }
+
+        let delay = backoff.delay(attempt, rand::random_range(-1.0..=1.0));
+        error!(
+            "Failed to create worker: {}. Retrying in {:.1}s (retry {}{})",
+            err,
+            delay.as_secs_f64(),
+            attempt + 1,
+            if max_retries == 0 {
+                String::new()
+            } else {
+                format!("/{}", max_retries)
+            }
+        );
+        sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+pub async fn new_worker(args: Args) -> Result<AutoWorker> {
     info!(
         "{} new_worker: Starting worker creation...",
         log_icon("🔧", "[INIT]")
     );
     // TODO: IPC shared memory should be selected
-    loop {
-        info!(
-            "{} new_worker: Loop iteration for worker type: {:?}",
-            log_icon("🔄", "[LOOP]"),
-            args.worker_type
-        );
-        match args.worker_type {
-            WorkerType::TCP => {
-                info!(
-                    "{} new_worker: Creating TCP worker...",
-                    log_icon("📡", "[TCP]")
-                );
-                match TCPWorker::new(args.clone()).await {
-                    Ok(worker) => {
+    if let WorkerType::WS = args.worker_type {
+        // The server has no WebSocket control endpoint yet, so a WS worker
+        // could never log in
+        return Err(anyhow!(
+            "gpuf-s does not serve WebSocket workers yet; use --worker-type tcp"
+        ));
+    }
+    let max_retries = args.connect_max_retries;
+    connect_with_backoff(
+        ReconnectBackoff::default(),
+        max_retries,
+        || {
+            let args = args.clone();
+            async move {
+                match args.worker_type {
+                    WorkerType::TCP => {
+                        info!(
+                            "{} new_worker: Creating TCP worker...",
+                            log_icon("📡", "[TCP]")
+                        );
+                        let worker = TCPWorker::new(args).await?;
                         info!(
                             "{} new_worker: TCP worker created successfully",
                             log_icon("✅", "[OK]")
                         );
-                        return AutoWorker::TCP(worker);
+                        Ok(AutoWorker::TCP(worker))
                     }
-                    Err(e) => {
-                        error!(
-                            "Failed to create TCP worker: {}. Retrying in 5 seconds...",
-                            e
+                    WorkerType::WS => {
+                        info!(
+                            "{} new_worker: Creating WS worker...",
+                            log_icon("🌐", "[WS]")
                         );
-                    }
-                }
-            }
-            WorkerType::WS => {
-                info!(
-                    "{} new_worker: Creating WS worker...",
-                    log_icon("🌐", "[WS]")
-                );
-                match WSWorker::new(args.clone()).await {
-                    Ok(worker) => {
+                        let worker = WSWorker::new(args).await?;
                         info!(
                             "{} new_worker: WS worker created successfully",
                             log_icon("✅", "[OK]")
                         );
-                        return AutoWorker::WS(worker);
-                    }
-                    Err(e) => {
-                        error!(
-                            "Failed to create WS worker: {}. Retrying in 5 seconds...",
-                            e
-                        );
+                        Ok(AutoWorker::WS(worker))
                     }
                 }
             }
+        },
+        tokio::time::sleep,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn cancel_all_signals_every_active_task() {
+        let state = Arc::new(CancelState::new());
+        let first = state.register("task-a");
+        let _second = state.register("task-b");
+
+        let mut active = state.active_tasks();
+        active.sort();
+        assert_eq!(active, vec!["task-a".to_string(), "task-b".to_string()]);
+
+        assert_eq!(state.cancel_all().await, 2);
+        assert!(state.is_cancelled("task-a").await);
+        assert!(state.is_cancelled("task-b").await);
+
+        drop(first);
+        assert_eq!(state.active_tasks(), vec!["task-b".to_string()]);
+        state.clear("task-a").await;
+        assert!(!state.is_cancelled("task-a").await);
+    }
+
+    #[tokio::test]
+    async fn wait_idle_returns_once_the_last_task_ends() {
+        let state = Arc::new(CancelState::new());
+        assert!(state.wait_idle(std::time::Duration::ZERO).await);
+
+        let task = state.register("task-a");
+        assert!(!state.wait_idle(std::time::Duration::from_millis(20)).await);
+
+        let waiter = tokio::spawn({
+            let state = state.clone();
+            async move { state.wait_idle(std::time::Duration::from_secs(5)).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        drop(task);
+        assert!(waiter.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn connect_backoff_doubles_to_cap_and_returns_on_success() {
+        let backoff = ReconnectBackoff::default();
+        let expected: Vec<u64> = vec![1, 2, 4, 8, 16, 32, 60, 60];
+        let failures = expected.len();
+
+        let mut attempts = 0;
+        let mut delays = Vec::new();
+        let result = connect_with_backoff(
+            backoff,
+            10,
+            || {
+                attempts += 1;
+                let attempt = attempts;
+                async move {
+                    if attempt <= failures {
+                        Err(anyhow::anyhow!("connection refused"))
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            },
+            |delay| {
+                delays.push(delay);
+                async {}
+            },
+        )
+        .await;
+
+        assert_eq!(result.unwrap(), failures + 1);
+        assert_eq!(delays.len(), failures);
+        for (delay, base) in delays.iter().zip(&expected) {
+            let base = Duration::from_secs(*base);
+            assert!(*delay >= base.mul_f64(0.8) && *delay <= base.mul_f64(1.2));
         }
+        assert_eq!(backoff.delay(3, 1.0), Duration::from_secs_f64(9.6));
+        assert_eq!(backoff.delay(40, -1.0), Duration::from_secs(48));
+    }
 
-        info!(
-            "{} new_worker: Waiting 5 seconds before retry...",
-            log_icon("⏳", "[WAIT]")
-        );
-        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+    #[tokio::test]
+    async fn connect_backoff_gives_up_after_max_retries() {
+        let mut attempts = 0;
+        let result: Result<()> = connect_with_backoff(
+            ReconnectBackoff::default(),
+            3,
+            || {
+                attempts += 1;
+                async { Err(anyhow::anyhow!("connection refused")) }
+            },
+            |_| async {},
+        )
+        .await;
+
+        let err = result.unwrap_err();
+        assert_eq!(attempts, 4);
+        assert!(err.to_string().contains("after 3 retries"), "{}", err);
+        assert!(format!("{:#}", err).contains("connection refused"));
     }
 }
@@ -7,11 +7,9 @@ use common::{
 };
 use std::ffi::{c_char, c_void};
 use std::io::Write;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Arc, Mutex, OnceLock};
 
-const CURRENT_VERSION: u32 = 1;
-
 fn derive_model_id_from_path(model_path: &str) -> String {
     let lower = model_path.to_ascii_lowercase();
     if lower.contains("llama-3") || lower.contains("llama3") {
@@ -26,6 +24,23 @@ fn derive_model_id_from_path(model_path: &str) -> String {
     "llama".to_string()
 }
 
+/// Models reported in `ModelStatus`: the loaded one, none after
+/// `gpuf_unload_model`.
+fn advertised_models() -> Vec<Model> {
+    let current_model_path = match crate::MODEL_STATUS.lock() {
+        Ok(status) if status.unloaded => return Vec::new(),
+        Ok(status) => status.current_model.clone(),
+        Err(_) => None,
+    }
+    .unwrap_or_else(|| "ios".to_string());
+    vec![Model {
+        id: derive_model_id_from_path(&current_model_path),
+        object: "model".to_string(),
+        created: 0,
+        owned_by: "ios".to_string(),
+    }]
+}
+
 fn emit_callback(callback: Option<extern "C" fn(*const c_char, *mut c_void)>, msg: &str) {
     let Some(cb) = callback else { return };
     let Ok(cmsg) = std::ffi::CString::new(msg) else {
@@ -40,6 +55,8 @@ static WORKER_SERVER_ADDR: OnceLock<Mutex<Option<String>>> = OnceLock::new();
 static WORKER_CONTROL_PORT: OnceLock<Mutex<Option<u16>>> = OnceLock::new();
 static WORKER_CONTROL_TLS: OnceLock<Mutex<MobileControlTlsConfig>> = OnceLock::new();
 static WORKER_CLIENT_ID: OnceLock<Mutex<Option<[u8; 16]>>> = OnceLock::new();
+// `PROTOCOL_REVISION` the server advertised via `ServerRevision`; 1 until it does
+static WORKER_SERVER_REVISION: AtomicU32 = AtomicU32::new(1);
 static WORKER_STOP_SIGNAL: OnceLock<Arc<AtomicBool>> = OnceLock::new();
 static WORKER_CANCELLED_TASK: OnceLock<Mutex<Option<String>>> = OnceLock::new();
 static WORKER_STATUS_CALLBACK: OnceLock<
@@ -107,6 +124,42 @@ fn clear_tcp_stream() {
     }
 }
 
+/// `ContextWindow` for the loaded model, which lets the server keep requests
+/// that cannot fit away from this device. `None` while the window is unknown or
+/// the server has not advertised a revision that knows the command.
+fn context_window_command(client_id: [u8; 16]) -> Option<Command> {
+    let n_ctx = crate::describe_worker().n_ctx;
+    let command = Command::V1(CommandV1::ContextWindow { client_id, n_ctx });
+    let server_revision = WORKER_SERVER_REVISION.load(Ordering::SeqCst);
+    (n_ctx > 0 && command.supported_by(server_revision)).then_some(command)
+}
+
+/// Reports the advertised models and context window on the stored control
+/// connection, so the server sees a model change right away.
+pub fn send_model_status() -> Result<()> {
+    let stream = get_tcp_stream().ok_or_else(|| anyhow!("Not connected"))?;
+    let client_id = WORKER_CLIENT_ID
+        .get()
+        .and_then(|m| m.lock().ok().and_then(|g| *g))
+        .ok_or_else(|| anyhow!("Not logged in"))?;
+    let model_status = CommandV1::ModelStatus {
+        client_id,
+        models: advertised_models(),
+        auto_models_device: Vec::new(),
+    };
+    // A new model may come with a different context window
+    let context_window = context_window_command(client_id);
+    let mut stream = stream
+        .lock()
+        .map_err(|_| anyhow!("Control stream mutex poisoned"))?;
+    common::write_command_sync(&mut *stream, &Command::V1(model_status))?;
+    if let Some(context_window) = context_window {
+        common::write_command_sync(&mut *stream, &context_window)?;
+    }
+    stream.flush()?;
+    Ok(())
+}
+
 fn get_worker_control_tls_config() -> MobileControlTlsConfig {
     WORKER_CONTROL_TLS
         .get()
@@ -174,7 +227,7 @@ pub async fn perform_login_with_tls(
         .map_err(|_| anyhow!("Invalid client_id length (expected 16 bytes / 32 hex chars)"))?;
 
     let login_cmd = CommandV1::Login {
-        version: CURRENT_VERSION,
+        version: common::PROTOCOL_REVISION,
         auto_models,
         os_type: os_type(),
         client_id,
@@ -186,6 +239,8 @@ pub async fn perform_login_with_tls(
 
     common::write_command_sync(&mut stream, &Command::V1(login_cmd))
         .map_err(|e| anyhow!("Failed to send login command: {}", e))?;
+    // The context window follows once the server advertises its revision
+    WORKER_SERVER_REVISION.store(1, Ordering::SeqCst);
 
     let stream_arc = Arc::new(Mutex::new(stream));
     {
@@ -486,19 +541,7 @@ pub async fn start_worker_tasks_with_callback_ptr(
                             .get()
                             .and_then(|m| m.lock().ok().and_then(|g| *g))
                             .unwrap_or([0u8; 16]);
-                        let current_model_path = crate::MODEL_STATUS
-                            .lock()
-                            .ok()
-                            .and_then(|s| s.current_model.clone())
-                            .unwrap_or_else(|| "ios".to_string());
-                        let model_id = derive_model_id_from_path(&current_model_path);
-
-                        let models = vec![Model {
-                            id: model_id,
-                            object: "model".to_string(),
-                            created: 0,
-                            owned_by: "ios".to_string(),
-                        }];
+                        let models = advertised_models();
 
                         let model_status = CommandV1::ModelStatus {
                             client_id,
@@ -535,6 +578,56 @@ pub async fn start_worker_tasks_with_callback_ptr(
 
                         emit_callback(handler_callback, "MODEL_STATUS_SENT");
                     }
+                    CommandV1::ServerRevision { protocol_revision } => {
+                        WORKER_SERVER_REVISION.store(protocol_revision, Ordering::SeqCst);
+                        let client_id = WORKER_CLIENT_ID
+                            .get()
+                            .and_then(|m| m.lock().ok().and_then(|g| *g))
+                            .unwrap_or([0u8; 16]);
+                        let Some(context_window) = context_window_command(client_id) else {
+                            continue;
+                        };
+                        let write_result = match stream_arc.lock() {
+                            Ok(mut stream) => {
+                                common::write_command_sync(&mut *stream, &context_window)
+                            }
+                            Err(_) => Err(anyhow!("Control stream mutex poisoned")),
+                        };
+                        if let Err(e) = write_result {
+                            emit_callback(
+                                handler_callback,
+                                &format!(
+                                    "CONTEXT_WINDOW_FAILED - Error redacted ({} bytes)",
+                                    e.to_string().len()
+                                ),
+                            );
+                            clear_tcp_stream();
+                            break;
+                        }
+                    }
+                    CommandV1::DescribeWorker { request_id } => {
+                        let description = CommandV1::WorkerDescription {
+                            request_id,
+                            description: crate::describe_worker(),
+                        };
+                        let write_result = match stream_arc.lock() {
+                            Ok(mut stream) => {
+                                common::write_command_sync(&mut *stream, &Command::V1(description))
+                            }
+                            Err(_) => Err(anyhow!("Control stream mutex poisoned")),
+                        };
+                        if let Err(e) = write_result {
+                            emit_callback(
+                                handler_callback,
+                                &format!(
+                                    "DESCRIBE_FAILED - Error redacted ({} bytes)",
+                                    e.to_string().len()
+                                ),
+                            );
+                            clear_tcp_stream();
+                            break;
+                        }
+                    }
                     CommandV1::CancelInference { task_id } => {
                         emit_callback(handler_callback, &format!("CANCEL_TASK - {task_id}"));
                         let slot = WORKER_CANCELLED_TASK.get_or_init(|| Mutex::new(None));
@@ -542,6 +635,7 @@ pub async fn start_worker_tasks_with_callback_ptr(
                             *guard = Some(task_id);
                         }
                     }
+                    // The SDK serves its one loaded model; the name is not checked
                     CommandV1::InferenceTask {
                         task_id,
                         prompt,
@@ -551,6 +645,16 @@ pub async fn start_worker_tasks_with_callback_ptr(
                         top_p,
                         repeat_penalty,
                         ..
+                    }
+                    | CommandV1::ModelInferenceTask {
+                        task_id,
+                        prompt,
+                        max_tokens,
+                        temperature,
+                        top_k,
+                        top_p,
+                        repeat_penalty,
+                        ..
                     } => {
                         emit_callback(handler_callback, &format!("INFERENCE_TASK - {task_id}"));
                         let effective_max_tokens = std::cmp::min(max_tokens, 512);
@@ -678,48 +782,12 @@ pub async fn start_worker_tasks_with_callback_ptr(
 fn build_chat_prompt_with_template(messages: &[common::ChatMessage]) -> String {
     #[cfg(any(target_os = "android", target_os = "ios"))]
     {
-        use crate::llama_chat_message;
-
-        // Try to use model's built-in chat template first
+        // Prefer the model's built-in chat template
         let model_ptr = crate::GLOBAL_MODEL_PTR.load(std::sync::atomic::Ordering::SeqCst);
-        if !model_ptr.is_null() {
-            // Convert messages to C-style array
-            let mut c_messages: Vec<llama_chat_message> = Vec::with_capacity(messages.len());
-            let mut c_roles: Vec<std::ffi::CString> = Vec::with_capacity(messages.len());
-            let mut c_contents: Vec<std::ffi::CString> = Vec::with_capacity(messages.len());
-
-            for msg in messages {
-                c_roles.push(std::ffi::CString::new(msg.role.clone()).unwrap_or_default());
-                c_contents.push(std::ffi::CString::new(msg.content.clone()).unwrap_or_default());
-                c_messages.push(llama_chat_message {
-                    role: c_roles.last().unwrap().as_ptr(),
-                    content: c_contents.last().unwrap().as_ptr(),
-                });
-            }
-
-            // Try to apply model's built-in template (tmpl=nullptr).
-            // SAFETY: `c_messages` points to role/content CStrings owned by
-            // `c_roles`/`c_contents`, all of which live until the call returns.
-            // `buffer` is a valid writable allocation of `buffer.len()` bytes.
-            let mut buffer = vec![0u8; 8192]; // 8KB buffer for template
-            let result = unsafe {
-                crate::llama_chat_apply_template(
-                    std::ptr::null(), // Use model's built-in template
-                    c_messages.as_ptr(),
-                    messages.len(),
-                    true, // add_ass=true
-                    buffer.as_mut_ptr() as *mut std::os::raw::c_char,
-                    buffer.len() as i32,
-                )
-            };
-
-            if result > 0 {
-                if let Ok(prompt) = std::ffi::CStr::from_bytes_until_nul(&buffer[..result as usize])
-                {
-                    if let Ok(prompt_str) = prompt.to_str() {
-                        return prompt_str.to_string();
-                    }
-                }
+        if crate::model_has_chat_template(model_ptr) {
+            match crate::apply_chat_template(model_ptr, messages, true) {
+                Ok(prompt) => return prompt,
+                Err(e) => eprintln!("❌ Failed to apply chat template: {}", e),
             }
         }
 
@@ -763,21 +831,12 @@ fn handle_inference_task(
 ) -> Result<()> {
     #[cfg(any(target_os = "android", target_os = "ios"))]
     {
+        use crate::util::generation::ControlTokenFilter;
         use crate::{
             gpuf_start_generation_async, GLOBAL_CONTEXT_PTR, GLOBAL_INFERENCE_MUTEX,
             GLOBAL_MODEL_PTR,
         };
 
-        fn filter_control_tokens(text: &str) -> String {
-            text.replace("<|end|>", "")
-                .replace("<|start|>", "")
-                .replace("<|channel|>", "")
-                .replace("<|message|>", "")
-                .replace("<|eot_id|>", "")
-                .replace("<|start_header_id|>", "")
-                .replace("<|end_header_id|>", "")
-        }
-
         #[derive(Debug, Clone)]
         struct PhaseSplitter {
             phase: common::OutputPhase,
@@ -1030,6 +1089,7 @@ fn handle_inference_task(
             max_bytes: usize,
             buf_phase: common::OutputPhase,
             splitter: PhaseSplitter,
+            control_filter: ControlTokenFilter,
             prompt_tokens: u32,
             completion_tokens: u32,
             analysis_tokens: u32,
@@ -1073,7 +1133,7 @@ fn handle_inference_task(
 
             state.completion_tokens = state.completion_tokens.saturating_add(1);
 
-            let filtered = filter_control_tokens(token_str);
+            let filtered = state.control_filter.push(token_str);
             if filtered.is_empty() {
                 return;
             }
@@ -1154,6 +1214,7 @@ fn handle_inference_task(
             max_bytes: 8,
             buf_phase: common::OutputPhase::Unknown,
             splitter: PhaseSplitter::default(),
+            control_filter: ControlTokenFilter::from_config(),
             prompt_tokens: 0,
             completion_tokens: 0,
             analysis_tokens: 0,
@@ -1202,6 +1263,8 @@ fn handle_inference_task(
 
         let was_cancelled = cb_state.cancelled.load(Ordering::Relaxed);
 
+        // An unfinished `<|...` at the end of output is plain text
+        cb_state.buf.push_str(&cb_state.control_filter.finish());
         if !cb_state.buf.is_empty() {
             let delta = std::mem::take(&mut cb_state.buf);
             let chunk = CommandV1::InferenceResultChunk {
@@ -18,12 +18,16 @@ use jni::JNIEnv;
 use std::ffi::{c_char, c_int, c_void, CStr, CString};
 use std::sync::atomic::Ordering;
 
+use crate::util::backend::format_vulkan_version;
 use crate::{
-    gpuf_cleanup, gpuf_create_context, gpuf_create_multimodal_context, gpuf_free_multimodal_model,
-    gpuf_generate_final_solution_text, gpuf_generate_multimodal, gpuf_get_model_status, gpuf_init,
-    gpuf_is_context_ready, gpuf_is_model_loaded, gpuf_load_model, gpuf_load_model_async,
-    gpuf_load_multimodal_model, gpuf_multimodal_model, gpuf_multimodal_supports_vision,
+    gpuf_cleanup, gpuf_create_context, gpuf_create_multimodal_context,
+    gpuf_create_multimodal_context_with_params, gpuf_free_multimodal_model,
+    gpuf_generate_final_solution_text, gpuf_generate_multimodal, gpuf_get_last_backend,
+    gpuf_get_model_status, gpuf_init, gpuf_is_context_ready, gpuf_is_model_loaded, gpuf_load_model,
+    gpuf_load_model_async, gpuf_load_multimodal_model, gpuf_multimodal_info, gpuf_multimodal_model,
+    gpuf_multimodal_model_info, gpuf_multimodal_supports_vision, gpuf_set_preferred_backend,
     gpuf_start_generation_async, gpuf_stop_generation, gpuf_system_info, gpuf_version,
+    gpuf_vulkan_device, gpuf_vulkan_device_count, gpuf_vulkan_device_info, last_load_error,
     llama_context, llama_model, manual_llama_completion, should_stop_generation,
     GLOBAL_CONTEXT_PTR, GLOBAL_MODEL_PTR, MODEL_STATUS,
 };
@@ -186,6 +190,20 @@ pub extern "C" fn Java_com_gpuf_c_GPUEngine_createContext(
     context_ptr as jlong
 }
 
+/// Free a context returned by createContext or createMultimodalContext
+///
+/// Java signature:
+/// public static native void freeContext(long contextPtr);
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_GPUEngine_freeContext(
+    _env: JNIEnv,
+    _class: JClass,
+    context_ptr: jlong,
+) {
+    gpuf_free_context(context_ptr as *mut llama_context);
+}
+
 /// Check if model is loaded
 ///
 /// Java signature:
@@ -292,7 +310,7 @@ pub extern "C" fn Java_com_gpuf_c_GPUEngine_startInferenceService(
     if model_ptr.is_null() {
         eprintln!("🔥 GPUFabric JNI: Failed to load model");
         let mut status = MODEL_STATUS.lock().unwrap();
-        status.set_error("Failed to load model");
+        status.set_error(&last_load_error());
         return -3;
     }
 
@@ -398,7 +416,7 @@ pub extern "C" fn Java_com_gpuf_c_GPUEngine_startInferenceServiceAsync(
     if model_ptr.is_null() {
         eprintln!("🔥 GPUFabric JNI: Failed to load model");
         let mut status = MODEL_STATUS.lock().unwrap();
-        status.set_error("Failed to load model");
+        status.set_error(&last_load_error());
         return -3;
     }
 
@@ -499,7 +517,7 @@ pub extern "C" fn Java_com_gpuf_c_GPUEngine_loadModelNew(
     if model_ptr.is_null() {
         eprintln!("🔥 GPUFabric JNI: Failed to load model");
         let mut status = MODEL_STATUS.lock().unwrap();
-        status.set_error("Failed to load model");
+        status.set_error(&last_load_error());
         return -3;
     }
 
@@ -575,6 +593,84 @@ pub extern "C" fn Java_com_gpuf_c_GPUEngine_getModelLoadingStatus(
     }
 }
 
+// ============================================================================
+// Backend Selection (CPU / Vulkan)
+// ============================================================================
+
+/// List detected Vulkan devices as a JSON array of objects with `name`,
+/// `vendor_id`, `device_id`, `api_version` ("1.3.255"), `memory_bytes` and
+/// `is_software`.
+///
+/// Java signature:
+/// public static native String getVulkanDevices();
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_GPUEngine_getVulkanDevices(
+    env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    let devices: Vec<serde_json::Value> = (0..gpuf_vulkan_device_count())
+        .filter_map(|index| {
+            let mut device = std::mem::MaybeUninit::<gpuf_vulkan_device>::uninit();
+            if gpuf_vulkan_device_info(index, device.as_mut_ptr()) != 0 {
+                return None;
+            }
+            // SAFETY: `gpuf_vulkan_device_info` fully initializes `device` on success.
+            let device = unsafe { device.assume_init() };
+            // SAFETY: The probe NUL-terminates `name`.
+            let name = unsafe { CStr::from_ptr(device.name.as_ptr()) }.to_string_lossy();
+            Some(serde_json::json!({
+                "name": name,
+                "vendor_id": device.vendor_id,
+                "device_id": device.device_id,
+                "api_version": format_vulkan_version(device.api_version),
+                "memory_bytes": device.memory_bytes,
+                "is_software": device.is_software != 0,
+            }))
+        })
+        .collect();
+
+    match env.new_string(serde_json::Value::Array(devices).to_string()) {
+        Ok(jstring) => jstring.into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Set the backend for models loaded afterwards: 0 = CPU, 1 = Vulkan.
+/// Returns 0 on success, -1 for an unknown backend.
+///
+/// Java signature:
+/// public static native int setPreferredBackend(int backend);
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_GPUEngine_setPreferredBackend(
+    _env: JNIEnv,
+    _class: JClass,
+    backend: jint,
+) -> jint {
+    gpuf_set_preferred_backend(backend)
+}
+
+/// Backend that served the most recent generation ("cpu" or "vulkan"), or
+/// null if nothing has been generated yet.
+///
+/// Java signature:
+/// public static native String getLastBackend();
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_GPUEngine_getLastBackend(env: JNIEnv, _class: JClass) -> jstring {
+    let backend = gpuf_get_last_backend();
+    if backend.is_null() {
+        return std::ptr::null_mut();
+    }
+    // SAFETY: `gpuf_get_last_backend` returns a static NUL-terminated string.
+    let backend = unsafe { CStr::from_ptr(backend) }.to_string_lossy();
+    match env.new_string(backend) {
+        Ok(jstring) => jstring.into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
 // ============================================================================
 // Text Generation
 // ============================================================================
@@ -1058,6 +1154,35 @@ pub extern "C" fn Java_com_gpuf_c_GPUEngine_createMultimodalContext(
     ctx as jlong
 }
 
+/// Create a multimodal context with an explicit context and batch size; values
+/// <= 0 keep the defaults of `createMultimodalContext`.
+///
+/// Java signature:
+/// public static native long createMultimodalContextWithParams(long multimodalModelPtr, int nCtx, int nBatch);
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_GPUEngine_createMultimodalContextWithParams(
+    _env: JNIEnv,
+    _class: JClass,
+    multimodal_model_ptr: jlong,
+    n_ctx: jint,
+    n_batch: jint,
+) -> jlong {
+    if multimodal_model_ptr == 0 {
+        println!("❌ Invalid multimodal model pointer");
+        return 0;
+    }
+
+    let multimodal_model = multimodal_model_ptr as *mut gpuf_multimodal_model;
+    let ctx = gpuf_create_multimodal_context_with_params(multimodal_model, n_ctx, n_batch);
+
+    if ctx.is_null() {
+        println!("❌ Failed to create multimodal context");
+        return 0;
+    }
+    ctx as jlong
+}
+
 /// Generate with multimodal input (text + image)
 ///
 /// Java signature:
@@ -1172,6 +1297,49 @@ pub extern "C" fn Java_com_gpuf_c_GPUEngine_supportsVision(
     }
 }
 
+/// Describe a multimodal model as JSON: projector type, vision/audio support,
+/// image token bounds and media marker. Returns null on error.
+///
+/// Java signature:
+/// public static native String getMultimodalInfo(long multimodalModelPtr);
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_GPUEngine_getMultimodalInfo(
+    env: JNIEnv,
+    _class: JClass,
+    multimodal_model_ptr: jlong,
+) -> jstring {
+    if multimodal_model_ptr == 0 {
+        return std::ptr::null_mut();
+    }
+
+    let mut info = std::mem::MaybeUninit::<gpuf_multimodal_info>::uninit();
+    let result = gpuf_multimodal_model_info(
+        multimodal_model_ptr as *mut gpuf_multimodal_model,
+        info.as_mut_ptr(),
+    );
+    if result != 0 {
+        return std::ptr::null_mut();
+    }
+    // SAFETY: `gpuf_multimodal_model_info` fully initializes `info` on success.
+    let info = unsafe { info.assume_init() };
+    // SAFETY: `media_marker` points into the live model's CString.
+    let media_marker = unsafe { CStr::from_ptr(info.media_marker) }.to_string_lossy();
+
+    let json = serde_json::json!({
+        "projector_type": info.projector_type.as_str(),
+        "supports_vision": info.supports_vision,
+        "supports_audio": info.supports_audio,
+        "image_min_tokens": info.image_min_tokens,
+        "image_max_tokens": info.image_max_tokens,
+        "media_marker": media_marker,
+    });
+    match env.new_string(json.to_string()) {
+        Ok(jstring) => jstring.into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
 /// Free multimodal model
 ///
 /// Java signature:
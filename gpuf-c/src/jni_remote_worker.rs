@@ -20,7 +20,7 @@ use std::sync::OnceLock;
 use crate::{
     get_remote_worker_status, gpuf_validate_mobile_tls_policy, set_remote_worker_model,
     start_remote_worker, start_remote_worker_tasks_with_callback_ptr, start_remote_worker_with_tls,
-    stop_remote_worker,
+    stop_remote_worker, GpufError,
 };
 
 #[cfg(target_os = "android")]
@@ -245,13 +245,17 @@ pub extern "C" fn Java_com_gpuf_c_RemoteWorker_setRemoteWorkerModel(
     // Call C API
     let result = set_remote_worker_model(model_path_c.as_ptr());
 
-    if result == 0 {
+    if result == GpufError::Ok {
         println!("✅ JNI: Model set successfully");
     } else {
-        eprintln!("❌ JNI: Failed to set model (error: {})", result);
+        eprintln!(
+            "❌ JNI: Failed to set model ({}: {})",
+            result as jint,
+            result.message().to_string_lossy()
+        );
     }
 
-    result
+    result as jint
 }
 
 #[cfg(target_os = "android")]
@@ -302,7 +306,7 @@ pub extern "C" fn Java_com_gpuf_c_RemoteWorker_startRemoteWorkerTasksWithJavaCal
         return -1;
     }
 
-    start_remote_worker_tasks_with_callback_ptr(Some(rn_status_callback))
+    start_remote_worker_tasks_with_callback_ptr(Some(rn_status_callback)) as jint
 }
 
 // ============================================================================
@@ -432,13 +436,17 @@ pub extern "C" fn Java_com_gpuf_c_RemoteWorker_startRemoteWorker(
         client_id_c.as_ptr(),
     );
 
-    if result == 0 {
+    if result == GpufError::Ok {
         println!("✅ JNI: Remote worker started successfully");
     } else {
-        eprintln!("❌ JNI: Failed to start remote worker (error: {})", result);
+        eprintln!(
+            "❌ JNI: Failed to start remote worker ({}: {})",
+            result as jint,
+            result.message().to_string_lossy()
+        );
     }
 
-    result
+    result as jint
 }
 
 // ============================================================================
@@ -514,16 +522,17 @@ pub extern "C" fn Java_com_gpuf_c_RemoteWorker_startRemoteWorkerWithTls(
         cert_sha256_pin_c.as_ptr(),
     );
 
-    if result == 0 {
+    if result == GpufError::Ok {
         println!("✅ JNI: TLS remote worker started successfully");
     } else {
         eprintln!(
-            "❌ JNI: Failed to start TLS remote worker (error: {})",
-            result
+            "❌ JNI: Failed to start TLS remote worker ({}: {})",
+            result as jint,
+            result.message().to_string_lossy()
         );
     }
 
-    result
+    result as jint
 }
 
 // ============================================================================
@@ -550,16 +559,17 @@ pub extern "C" fn Java_com_gpuf_c_RemoteWorker_startRemoteWorkerTasks(
     // Call C API with callback
     let result = start_remote_worker_tasks_with_callback_ptr(callback);
 
-    if result == 0 {
+    if result == GpufError::Ok {
         println!("✅ JNI: Remote worker tasks started successfully");
     } else {
         eprintln!(
-            "❌ JNI: Failed to start remote worker tasks (error: {})",
-            result
+            "❌ JNI: Failed to start remote worker tasks ({}: {})",
+            result as jint,
+            result.message().to_string_lossy()
         );
     }
 
-    result
+    result as jint
 }
 
 // ============================================================================
@@ -588,10 +598,11 @@ pub extern "C" fn Java_com_gpuf_c_RemoteWorker_getRemoteWorkerStatus(
         buffer.len(),
     );
 
-    if result != 0 {
+    if result != GpufError::Ok {
         eprintln!(
-            "❌ JNI: Failed to get remote worker status (error: {})",
-            result
+            "❌ JNI: Failed to get remote worker status ({}: {})",
+            result as jint,
+            result.message().to_string_lossy()
         );
         return std::ptr::null_mut();
     }
@@ -641,11 +652,15 @@ pub extern "C" fn Java_com_gpuf_c_RemoteWorker_stopRemoteWorker(
     // Call C API
     let result = stop_remote_worker();
 
-    if result == 0 {
+    if result == GpufError::Ok {
         println!("✅ JNI: Remote worker stopped successfully");
     } else {
-        eprintln!("❌ JNI: Failed to stop remote worker (error: {})", result);
+        eprintln!(
+            "❌ JNI: Failed to stop remote worker ({}: {})",
+            result as jint,
+            result.message().to_string_lossy()
+        );
     }
 
-    result
+    result as jint
 }
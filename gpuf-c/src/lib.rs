@@ -17,18 +17,39 @@ use std::ffi::{c_char, c_int, c_void, CStr, CString};
 use std::io::Write;
 #[cfg(any(target_os = "android", target_os = "ios"))]
 use std::os::raw::c_ulonglong;
-use std::sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, AtomicU64, Ordering};
+#[cfg(any(target_os = "android", target_os = "ios"))]
+use std::sync::atomic::{AtomicU32, AtomicU8, AtomicUsize};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 const DEFAULT_LLAMA_THREADS: i32 = 4;
 const DEFAULT_MTMD_THREADS: i32 = 4;
+/// Bytes `Utf8EmitBuffer` holds back before giving up on them forming UTF-8.
+const UTF8_EMIT_LIMIT: usize = 8192;
+
 struct Utf8EmitBuffer {
     buf: Vec<u8>,
+    limit: usize,
 }
 
 impl Utf8EmitBuffer {
     fn new() -> Self {
-        Self { buf: Vec::new() }
+        Self::with_limit(UTF8_EMIT_LIMIT)
+    }
+
+    /// Holds back at most `limit` bytes that don't yet form UTF-8 before
+    /// flushing them lossily.
+    fn with_limit(limit: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            limit,
+        }
+    }
+
+    /// Bytes buffered while waiting for the rest of a UTF-8 sequence.
+    fn pending_len(&self) -> usize {
+        self.buf.len()
     }
 
     fn push_and_take_valid(&mut self, bytes: &[u8]) -> String {
@@ -45,7 +66,7 @@ impl Utf8EmitBuffer {
                 let valid_up_to = e.valid_up_to();
                 if valid_up_to == 0 {
                     // Avoid unbounded growth if we keep getting bytes that never form UTF-8.
-                    if self.buf.len() > 8192 {
+                    if self.buf.len() > self.limit {
                         let s = String::from_utf8_lossy(&self.buf).to_string();
                         self.buf.clear();
                         return s;
@@ -72,6 +93,57 @@ impl Utf8EmitBuffer {
     }
 }
 
+/// Turns raw token pieces into emit-ready text: UTF-8 sequences split across
+/// tokens are reassembled, text that may be the start of a stop word is held
+/// back, and a matched stop word is trimmed from the output.
+struct TokenTextStream {
+    utf8: Utf8EmitBuffer,
+    stops: util::generation::StopSequenceMatcher,
+}
+
+impl TokenTextStream {
+    fn new(stop_words: &[String]) -> Self {
+        Self {
+            utf8: Utf8EmitBuffer::new(),
+            stops: util::generation::StopSequenceMatcher::new(stop_words.iter().cloned()),
+        }
+    }
+
+    /// Returns the text to emit and whether a stop word ended generation.
+    fn push(&mut self, piece: &[u8]) -> (String, bool) {
+        let text = self.utf8.push_and_take_valid(piece);
+        self.stops.push(&text)
+    }
+
+    /// Releases whatever is still buffered once generation ends.
+    fn finish(&mut self) -> String {
+        let tail = self.utf8.flush_lossy();
+        let (mut out, stopped) = self.stops.push(&tail);
+        if !stopped {
+            out.push_str(&self.stops.finish());
+        }
+        out
+    }
+}
+
+/// Hands one chunk of generated text to a C token callback.
+fn emit_token_text(
+    callback: Option<extern "C" fn(*const c_char, *mut c_void)>,
+    text: &str,
+    user_data: *mut c_void,
+) {
+    let Some(callback) = callback else {
+        return;
+    };
+    if text.is_empty() {
+        return;
+    }
+    match CString::new(text) {
+        Ok(token_cstr) => callback(token_cstr.as_ptr(), user_data),
+        Err(_) => println!("⚠️ Token callback skipped - CString conversion failed"),
+    }
+}
+
 // Global Tokio Runtime for async operations
 #[cfg(target_os = "android")]
 static TOKIO_RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
@@ -316,13 +388,40 @@ pub struct llama_sampler_chain_params {
 // Global context position tracking for continuous inference.
 static GLOBAL_CONTEXT_POSITION: AtomicI32 = AtomicI32::new(0);
 
+/// Next KV cache position for the shared inference context.
+fn get_context_position() -> i32 {
+    GLOBAL_CONTEXT_POSITION.load(Ordering::SeqCst)
+}
+
+fn set_context_position(pos: i32) {
+    GLOBAL_CONTEXT_POSITION.store(pos, Ordering::SeqCst);
+}
+
 // Async generation control
 static GENERATION_STOP_FLAG: AtomicBool = AtomicBool::new(false);
 static GENERATION_MUTEX: Mutex<()> = Mutex::new(());
 
-// Thread-safe generation stop control
+// Cancel flags of running gpuf_start_generation_async_cancellable requests
+static NEXT_GENERATION_REQUEST: AtomicU64 = AtomicU64::new(1);
+static GENERATION_REQUESTS: Lazy<Mutex<std::collections::HashMap<u64, Arc<AtomicBool>>>> =
+    Lazy::new(|| Mutex::new(std::collections::HashMap::new()));
+
+thread_local! {
+    // Cancel flag of the request running on this thread, if any
+    static CURRENT_REQUEST_CANCEL: std::cell::RefCell<Option<Arc<AtomicBool>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+// Thread-safe generation stop control: gpuf_stop_generation stops every
+// generation, gpuf_cancel_request only the request running on this thread.
 fn should_stop_generation() -> bool {
     GENERATION_STOP_FLAG.load(Ordering::SeqCst)
+        || CURRENT_REQUEST_CANCEL.with(|cancel| {
+            cancel
+                .borrow()
+                .as_ref()
+                .is_some_and(|cancel| cancel.load(Ordering::SeqCst))
+        })
 }
 
 fn set_generation_stop(stop: bool) {
@@ -337,6 +436,400 @@ fn cleanup_generation_control() {
     set_generation_stop(false);
 }
 
+/// Unregisters a generation request when its thread finishes, even on panic.
+struct GenerationRequestGuard(u64);
+
+impl Drop for GenerationRequestGuard {
+    fn drop(&mut self) {
+        GENERATION_REQUESTS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(&self.0);
+    }
+}
+
+/// Runs `generate` on its own thread as a cancellable request. Returns the
+/// request handle for `cancel_generation_request` and the thread's join
+/// handle; `generate` receives the request handle.
+fn spawn_generation_request<F>(
+    generate: F,
+) -> std::io::Result<(u64, std::thread::JoinHandle<c_int>)>
+where
+    F: FnOnce(u64) -> c_int + Send + 'static,
+{
+    let handle = NEXT_GENERATION_REQUEST.fetch_add(1, Ordering::Relaxed);
+    let cancel = Arc::new(AtomicBool::new(false));
+    GENERATION_REQUESTS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(handle, cancel.clone());
+    let guard = GenerationRequestGuard(handle);
+
+    let thread = std::thread::Builder::new()
+        .name(format!("gpuf-generation-{}", handle))
+        .spawn(move || {
+            let _guard = guard;
+            CURRENT_REQUEST_CANCEL.with(|current| *current.borrow_mut() = Some(cancel));
+            generate(handle)
+        })?;
+    Ok((handle, thread))
+}
+
+/// Signals the request `handle` to stop. Returns false if it is unknown or
+/// already finished.
+fn cancel_generation_request(handle: u64) -> bool {
+    match GENERATION_REQUESTS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(&handle)
+    {
+        Some(cancel) => {
+            cancel.store(true, Ordering::SeqCst);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Signals every running cancellable request to stop. Returns how many were
+/// signalled.
+fn cancel_all_generation_requests() -> usize {
+    let requests = GENERATION_REQUESTS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    for cancel in requests.values() {
+        cancel.store(true, Ordering::SeqCst);
+    }
+    requests.len()
+}
+
+// Loop detector limits applied to every mobile generation loop
+#[cfg(any(target_os = "android", target_os = "ios"))]
+static REPETITION_NGRAM_SIZE: AtomicUsize =
+    AtomicUsize::new(util::generation::DEFAULT_REPETITION_NGRAM_SIZE);
+#[cfg(any(target_os = "android", target_os = "ios"))]
+static REPETITION_MAX_REPEATS: AtomicUsize =
+    AtomicUsize::new(util::generation::DEFAULT_REPETITION_MAX_REPEATS);
+
+// Stop words applied to the streaming and multimodal generation loops
+#[cfg(any(target_os = "android", target_os = "ios"))]
+static STOP_WORDS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn configured_stop_words() -> Vec<String> {
+    STOP_WORDS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+// Upper bound on `n_stop_words`, so a garbage count cannot walk far past the array
+const MAX_STOP_WORDS: usize = 64;
+
+/// Stop words carried by `params`. A negative count or a count without an
+/// array yields none; null, empty and non-UTF-8 entries are skipped.
+///
+/// `params.stop_words` must be null or point to `n_stop_words` entries, each
+/// null or a NUL-terminated C string.
+fn collect_stop_words(params: &llama_completion_params) -> Vec<String> {
+    if params.n_stop_words <= 0 || params.stop_words.is_null() {
+        return Vec::new();
+    }
+    let count = params.n_stop_words as usize;
+    if count > MAX_STOP_WORDS {
+        println!(
+            "⚠️ Only the first {} of {} stop words are used",
+            MAX_STOP_WORDS, count
+        );
+    }
+
+    let mut words = Vec::new();
+    for i in 0..count.min(MAX_STOP_WORDS) {
+        // SAFETY: `stop_words` is non-null and holds `n_stop_words` entries per
+        // the caller contract; each entry is checked for null before reading.
+        let word = unsafe { *params.stop_words.add(i) };
+        if word.is_null() {
+            println!("⚠️ Skipping null stop word {}", i);
+            continue;
+        }
+        // SAFETY: `word` is non-null and NUL-terminated per the caller contract.
+        match unsafe { CStr::from_ptr(word) }.to_str() {
+            Ok("") => {}
+            Ok(word) => words.push(word.to_string()),
+            Err(_) => println!("⚠️ Skipping stop word {} that is not UTF-8", i),
+        }
+    }
+    words
+}
+
+// Finish reason of the most recent mobile generation (see `finish_reason_code`)
+#[cfg(any(target_os = "android", target_os = "ios"))]
+static LAST_FINISH_REASON: AtomicU8 = AtomicU8::new(0);
+
+// Seed of the final sampling step for mobile generation (see `gpuf_set_seed`)
+#[cfg(any(target_os = "android", target_os = "ios"))]
+static SAMPLER_SEED: AtomicU32 = AtomicU32::new(1234);
+
+// Frequency/presence penalties (as f32 bits) and their lookback for mobile
+// generation (see `gpuf_set_penalties`); the lookback defaults to the whole context
+#[cfg(any(target_os = "android", target_os = "ios"))]
+static FREQUENCY_PENALTY_BITS: AtomicU32 = AtomicU32::new(0);
+#[cfg(any(target_os = "android", target_os = "ios"))]
+static PRESENCE_PENALTY_BITS: AtomicU32 = AtomicU32::new(0);
+#[cfg(any(target_os = "android", target_os = "ios"))]
+static PENALTY_LAST_N: AtomicI32 = AtomicI32::new(-1);
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn mobile_sampling_params(
+    temperature: f32,
+    top_k: c_int,
+    top_p: f32,
+    repeat_penalty: f32,
+) -> util::generation::SamplingParams {
+    util::generation::SamplingParams {
+        temperature,
+        top_k,
+        top_p,
+        repeat_penalty,
+        frequency_penalty: f32::from_bits(FREQUENCY_PENALTY_BITS.load(Ordering::Relaxed)),
+        presence_penalty: f32::from_bits(PRESENCE_PENALTY_BITS.load(Ordering::Relaxed)),
+        repeat_last_n: PENALTY_LAST_N.load(Ordering::Relaxed),
+        seed: SAMPLER_SEED.load(Ordering::Relaxed),
+        ..Default::default()
+    }
+}
+
+/// Builds a llama.cpp sampler chain from `params` (see
+/// `SamplingParams::sampler_stages` for the order). The caller owns the result
+/// and releases it with `llama_sampler_free`; null if any stage failed.
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn build_sampler_chain(params: &util::generation::SamplingParams) -> *mut llama_sampler {
+    use util::generation::SamplerStage;
+
+    // SAFETY: Each sampler is freshly created by llama.cpp and handed to the
+    // chain exactly once; on failure the chain (and everything added to it)
+    // is freed before returning null.
+    unsafe {
+        let chain = llama_sampler_chain_init(llama_sampler_chain_params { no_perf: false });
+        if chain.is_null() {
+            println!("❌ Failed to create sampler chain");
+            return chain;
+        }
+
+        for stage in params.sampler_stages() {
+            let sampler = match stage {
+                SamplerStage::Penalties(penalties) => {
+                    let (last_n, repeat, frequency, presence) = penalties.args();
+                    llama_sampler_init_penalties(last_n, repeat, frequency, presence)
+                }
+                SamplerStage::TopK(k) => llama_sampler_init_top_k(k),
+                SamplerStage::Typical { p, min_keep } => llama_sampler_init_typical(p, min_keep),
+                SamplerStage::MinP { p, min_keep } => llama_sampler_init_min_p(p, min_keep),
+                SamplerStage::TopP { p, min_keep } => llama_sampler_init_top_p(p, min_keep),
+                SamplerStage::Xtc {
+                    probability,
+                    threshold,
+                    min_keep,
+                    seed,
+                } => llama_sampler_init_xtc(probability, threshold, min_keep, seed),
+                SamplerStage::Temperature(t) => llama_sampler_init_temp(t),
+                SamplerStage::Greedy => llama_sampler_init_greedy(),
+                SamplerStage::Dist(seed) => llama_sampler_init_dist(seed),
+            };
+            if sampler.is_null() {
+                println!("❌ Failed to create {:?} sampler", stage);
+                llama_sampler_free(chain);
+                return std::ptr::null_mut();
+            }
+            llama_sampler_chain_add(chain, sampler);
+        }
+        chain
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn repetition_detector() -> util::generation::RepetitionDetector {
+    util::generation::RepetitionDetector::new(
+        REPETITION_NGRAM_SIZE.load(Ordering::Relaxed),
+        REPETITION_MAX_REPEATS.load(Ordering::Relaxed),
+    )
+}
+
+/// Records a generated token against the worker-wide tokens/s cap, sleeping
+/// if generation is ahead of it.
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn pace_generation(rate_limiter: &mut util::generation::TokenRateLimiter) {
+    if rate_limiter.pace() {
+        println!(
+            "🌡️ Throttling generation to {} tokens/s",
+            rate_limiter.max_per_sec()
+        );
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn report_throttling(rate_limiter: &util::generation::TokenRateLimiter) {
+    if !rate_limiter.throttled().is_zero() {
+        println!(
+            "🌡️ Generation throttled for {} ms",
+            rate_limiter.throttled().as_millis()
+        );
+    }
+}
+
+/// `util::generation::generation_limit` for llama.cpp's c_int counts.
+fn context_generation_limit(max_tokens: c_int, n_ctx: c_int, n_past: c_int) -> c_int {
+    util::generation::generation_limit(
+        max_tokens.max(0) as usize,
+        n_ctx.max(0) as usize,
+        n_past.max(0) as usize,
+    ) as c_int
+}
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn finish_reason_code(reason: util::generation::FinishReason) -> u8 {
+    match reason {
+        util::generation::FinishReason::Stop => 1,
+        util::generation::FinishReason::Length => 2,
+        util::generation::FinishReason::Repetition => 3,
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn set_last_finish_reason(reason: util::generation::FinishReason) {
+    LAST_FINISH_REASON.store(finish_reason_code(reason), Ordering::SeqCst);
+}
+
+// Timings of the most recent generation, read via `gpuf_get_last_timings`
+static LAST_TIMINGS: Mutex<Option<llama_timings>> = Mutex::new(None);
+
+/// Measures one generation: prompt evaluation from `start` until
+/// `prompt_evaluated`, plus the summed time spent sampling and decoding each
+/// generated token. Anything else (callbacks, throttling) only counts toward
+/// the total.
+struct GenerationTimer {
+    started: Instant,
+    prompt_eval: Duration,
+    eval: Duration,
+}
+
+impl GenerationTimer {
+    fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            prompt_eval: Duration::ZERO,
+            eval: Duration::ZERO,
+        }
+    }
+
+    fn prompt_evaluated(&mut self) {
+        self.prompt_eval = self.started.elapsed();
+    }
+
+    fn token_evaluated(&mut self, token_started: Instant) {
+        self.eval += token_started.elapsed();
+    }
+
+    fn timings(&self) -> llama_timings {
+        llama_timings {
+            prompt_eval_time_ms: self.prompt_eval.as_secs_f64() * 1000.0,
+            eval_time_ms: self.eval.as_secs_f64() * 1000.0,
+            total_time_ms: self.started.elapsed().as_secs_f64() * 1000.0,
+        }
+    }
+
+    /// Publishes the timings for `gpuf_get_last_timings`.
+    fn finish(self) {
+        *LAST_TIMINGS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(self.timings());
+    }
+}
+
+// Why the most recent model load failed, read via `gpuf_get_last_load_error`
+static LAST_LOAD_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+fn set_last_load_error(error: Option<String>) {
+    *LAST_LOAD_ERROR
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = error;
+}
+
+/// Records why a model load failed for both `gpuf_get_last_load_error` and
+/// `gpuf_get_model_status`.
+fn fail_model_load(error: String) {
+    MODEL_STATUS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .set_error(&error);
+    set_last_load_error(Some(error));
+}
+
+/// Message describing why the last model load failed, for status reporting.
+pub fn last_load_error() -> String {
+    LAST_LOAD_ERROR
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+        .unwrap_or_else(|| "Failed to load model".to_string())
+}
+
+// Full text of the most recent buffered generation, read back in chunks via
+// `gpuf_get_last_result`
+struct LastResult {
+    text: String,
+    cursor: usize,
+}
+
+static LAST_RESULT: Mutex<LastResult> = Mutex::new(LastResult {
+    text: String::new(),
+    cursor: 0,
+});
+
+/// Longest prefix of `text` that fits in `max` bytes without splitting a character.
+fn utf8_prefix(text: &str, max: usize) -> &str {
+    let mut end = max.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Keeps the full result for `gpuf_get_last_result` and copies as much as fits
+/// into the caller's buffer (always NUL terminated). Returns the bytes copied;
+/// `gpuf_get_last_result` continues after them.
+///
+/// # Safety
+/// `output` must be null or writable for `output_len` bytes.
+unsafe fn store_and_copy_result(text: &str, output: *mut c_char, output_len: c_int) -> c_int {
+    let copied = if output.is_null() || output_len <= 0 {
+        ""
+    } else {
+        utf8_prefix(text, output_len as usize - 1)
+    };
+    {
+        let mut last = LAST_RESULT
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        last.text = text.to_string();
+        last.cursor = copied.len();
+    }
+
+    if output.is_null() || output_len <= 0 {
+        return 0;
+    }
+    if copied.len() < text.len() {
+        println!(
+            "⚠️ Result truncated to {} of {} bytes; fetch the rest with gpuf_get_last_result",
+            copied.len(),
+            text.len()
+        );
+    }
+    std::ptr::copy_nonoverlapping(copied.as_ptr(), output as *mut u8, copied.len());
+    *output.add(copied.len()) = 0;
+    copied.len() as c_int
+}
+
 // Global model state management
 pub static MODEL_STATUS: Lazy<Arc<Mutex<ModelStatusInfo>>> =
     Lazy::new(|| Arc::new(Mutex::new(ModelStatusInfo::new())));
@@ -347,6 +840,11 @@ pub static GLOBAL_INFERENCE_MUTEX: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(())
 // Global model and context pointers
 static GLOBAL_MODEL_PTR: AtomicPtr<llama_model> = AtomicPtr::new(std::ptr::null_mut());
 static GLOBAL_CONTEXT_PTR: AtomicPtr<llama_context> = AtomicPtr::new(std::ptr::null_mut());
+// Context that has already gone through `gpuf_warm_context`
+static WARMED_CONTEXT_PTR: AtomicPtr<llama_context> = AtomicPtr::new(std::ptr::null_mut());
+// Context whose KV state was left by `manual_llama_completion` and can be extended
+// by `gpuf_continue_generation`
+static CONTINUABLE_CONTEXT_PTR: AtomicPtr<llama_context> = AtomicPtr::new(std::ptr::null_mut());
 
 #[derive(Debug, Clone)]
 pub struct ModelStatusInfo {
@@ -354,6 +852,9 @@ pub struct ModelStatusInfo {
     pub loading_status: String,
     pub is_loaded: bool,
     pub error_message: Option<String>,
+    /// Set by `gpuf_unload_model`; no model is advertised to the server until
+    /// the next load.
+    pub unloaded: bool,
 }
 
 impl ModelStatusInfo {
@@ -363,6 +864,7 @@ impl ModelStatusInfo {
             loading_status: "Not initialized".to_string(),
             is_loaded: false,
             error_message: None,
+            unloaded: false,
         }
     }
 
@@ -371,6 +873,7 @@ impl ModelStatusInfo {
         self.loading_status = "Loading...".to_string();
         self.is_loaded = false;
         self.error_message = None;
+        self.unloaded = false;
     }
 
     pub fn set_loaded(&mut self, model_path: &str) {
@@ -378,6 +881,7 @@ impl ModelStatusInfo {
         self.loading_status = "Loaded".to_string();
         self.is_loaded = true;
         self.error_message = None;
+        self.unloaded = false;
     }
 
     pub fn set_error(&mut self, error: &str) {
@@ -391,6 +895,29 @@ impl ModelStatusInfo {
         self.loading_status = "Not initialized".to_string();
         self.is_loaded = false;
         self.error_message = None;
+        self.unloaded = false;
+    }
+
+    pub fn set_unloaded(&mut self) {
+        self.current_model = None;
+        self.loading_status = "Unloaded".to_string();
+        self.is_loaded = false;
+        self.error_message = None;
+        self.unloaded = true;
+    }
+
+    /// Code `gpuf_get_model_status` reports: 0 not loaded, 1 loading, 2
+    /// ready, 3 the last load failed.
+    pub fn status_code(&self) -> c_int {
+        if self.error_message.is_some() {
+            3
+        } else if self.is_loaded {
+            2
+        } else if self.current_model.is_some() {
+            1
+        } else {
+            0
+        }
     }
 }
 
@@ -435,6 +962,7 @@ extern "C" {
     ) -> *mut MtmdContext;
     fn mtmd_free(ctx: *mut MtmdContext);
     fn mtmd_support_vision(ctx: *mut MtmdContext) -> bool;
+    fn mtmd_support_audio(ctx: *mut MtmdContext) -> bool;
     fn mtmd_bitmap_init(nx: u32, ny: u32, data: *const u8) -> *mut MtmdBitmap;
     fn mtmd_bitmap_free(bitmap: *mut MtmdBitmap);
     fn mtmd_input_chunks_init() -> *mut MtmdInputChunks;
@@ -458,9 +986,13 @@ extern "C" {
         new_n_past: *mut MtmdLlamaPos,
     ) -> c_int;
     fn mtmd_get_output_embd(ctx: *mut MtmdContext) -> *mut f32;
+    fn mtmd_helper_get_n_tokens(chunks: *const MtmdInputChunks) -> usize;
 
     fn llama_sampler_init_top_k(k: c_int) -> *mut llama_sampler;
     fn llama_sampler_init_top_p(p: f32, min_keep: usize) -> *mut llama_sampler;
+    fn llama_sampler_init_min_p(p: f32, min_keep: usize) -> *mut llama_sampler;
+    fn llama_sampler_init_typical(p: f32, min_keep: usize) -> *mut llama_sampler;
+    fn llama_sampler_init_xtc(p: f32, t: f32, min_keep: usize, seed: u32) -> *mut llama_sampler;
     fn llama_sampler_init_temp(t: f32) -> *mut llama_sampler;
     fn llama_sampler_init_dist(seed: u32) -> *mut llama_sampler;
     fn llama_sampler_init_greedy() -> *mut llama_sampler;
@@ -479,12 +1011,14 @@ extern "C" {
     // Memory/KV cache management (llama.rn style)
     fn llama_get_memory(ctx: *mut llama_context) -> *mut c_void;
     fn llama_memory_seq_rm(mem: *mut c_void, seq_id: c_int, p0: LlamaPos, p1: LlamaPos) -> bool;
+    fn llama_memory_seq_pos_max(mem: *mut c_void, seq_id: c_int) -> LlamaPos;
     fn llama_memory_clear(mem: *mut c_void, data: bool);
 
     #[allow(non_upper_case_globals)]
     #[allow(improper_ctypes)]
     fn llama_sampler_chain_init(params: llama_sampler_chain_params) -> *mut llama_sampler;
     fn llama_sampler_chain_add(chain: *mut llama_sampler, sampler: *mut llama_sampler);
+    fn llama_sampler_chain_n(chain: *const llama_sampler) -> c_int;
     fn llama_sampler_sample(
         sampler: *mut llama_sampler,
         ctx: *mut llama_context,
@@ -501,6 +1035,12 @@ extern "C" {
 
     // 🆕 Added missing functions for proper token decoding
     fn llama_model_get_vocab(model: *const llama_model) -> *const llama_vocab;
+    fn llama_model_meta_val_str(
+        model: *const llama_model,
+        key: *const c_char,
+        buf: *mut c_char,
+        buf_size: usize,
+    ) -> c_int;
     fn llama_token_to_piece(
         vocab: *const llama_vocab,
         token: LlamaToken,
@@ -509,12 +1049,25 @@ extern "C" {
         lstrip: c_int,
         special: bool,
     ) -> c_int;
+    fn llama_detokenize(
+        vocab: *const llama_vocab,
+        tokens: *const LlamaToken,
+        n_tokens: i32,
+        text: *mut c_char,
+        text_len_max: i32,
+        remove_special: bool,
+        unparse_special: bool,
+    ) -> i32;
 
     // Alternative: direct vocab text access
     fn llama_vocab_get_text(vocab: *const llama_vocab, token: LlamaToken) -> *const c_char;
     fn llama_vocab_is_control(vocab: *const llama_vocab, token: LlamaToken) -> bool;
     fn llama_vocab_is_eog(vocab: *const llama_vocab, token: LlamaToken) -> bool;
     fn llama_get_logits(ctx: *mut llama_context) -> *const f32;
+    fn llama_get_embeddings(ctx: *mut llama_context) -> *mut f32;
+    fn llama_get_embeddings_seq(ctx: *mut llama_context, seq_id: LlamaSeqId) -> *mut f32;
+    fn llama_set_embeddings(ctx: *mut llama_context, embeddings: bool);
+    fn llama_model_n_embd(model: *const llama_model) -> i32;
 
     // Memory management functions
     fn llama_model_free(model: *mut llama_model);
@@ -524,6 +1077,8 @@ extern "C" {
     fn ggml_backend_dev_by_type(type_: i32) -> *mut ();
     fn ggml_backend_dev_get(i: i32) -> *mut ();
     fn ggml_backend_dev_count() -> i32;
+    // enum ggml_backend_dev_type: CPU = 0, GPU = 1, IGPU = 2, ACCEL = 3
+    fn ggml_backend_dev_type(device: *mut ()) -> c_int;
     fn ggml_backend_load_all();
     fn llama_model_default_params() -> llama_model_params;
     fn llama_context_default_params() -> llama_context_params;
@@ -536,84 +1091,381 @@ extern "C" {
         buf: *mut c_char,
         length: c_int,
     ) -> c_int;
+    fn llama_model_chat_template(model: *const llama_model, name: *const c_char) -> *const c_char;
+
+    // Full context state (KV cache, RNG, logits) serialization
+    fn llama_state_get_size(ctx: *mut llama_context) -> usize;
+    fn llama_state_get_data(ctx: *mut llama_context, dst: *mut u8, size: usize) -> usize;
+    fn llama_state_set_data(ctx: *mut llama_context, src: *const u8, size: usize) -> usize;
 }
 
 // ============================================================================
-// Real llama.cpp API Wrappers
+// llama.cpp Backends
 // ============================================================================
 
-#[cfg(any(target_os = "android", target_os = "ios"))]
-fn real_llama_backend_init() -> c_int {
-    // SAFETY: llama.cpp backend initialization is a process-level FFI call.
-    // This wrapper is used during SDK initialization before model/context use.
-    unsafe {
-        llama_backend_init();
-        ggml_backend_load_all(); // Load backends to solve tensor loading issues
-        0
-    }
+/// The llama.cpp calls the SDK makes outside the generation loop.
+///
+/// `Backend` is bound once below: the real llama.cpp on Android and iOS, the
+/// simulation everywhere else. Call sites go through `Backend` only, so a
+/// build never mixes simulated values into real llama.cpp calls.
+trait LlamaBackend {
+    fn backend_init() -> c_int;
+    fn backend_free();
+    fn model_default_params() -> llama_model_params;
+    fn context_default_params() -> llama_context_params;
+    fn model_load_from_file(path: *const c_char, params: llama_model_params) -> *mut llama_model;
+    fn model_free(model: *mut llama_model);
+    fn init_from_model(
+        model: *const llama_model,
+        params: llama_context_params,
+    ) -> *mut llama_context;
+    fn free_context(ctx: *mut llama_context);
+    fn n_ctx(ctx: *const llama_context) -> c_int;
+    fn n_batch(ctx: *mut llama_context) -> c_int;
+    /// Clears the KV cache of `ctx`; `data` also zeroes the buffers.
+    fn memory_clear(ctx: *mut llama_context, data: bool);
+    /// Removes positions `[p0, p1)` of `seq_id` from the KV cache (negative
+    /// bounds and ids mean all). False if nothing could be removed.
+    fn memory_seq_rm(ctx: *mut llama_context, seq_id: c_int, p0: LlamaPos, p1: LlamaPos) -> bool;
+    /// Last KV position of `seq_id`, -1 when the sequence is empty.
+    fn memory_seq_pos_max(ctx: *mut llama_context, seq_id: c_int) -> LlamaPos;
+    /// Tokenizes like `llama_tokenize`: the token count, or the negated count
+    /// needed when `tokens` is too small.
+    fn tokenize(
+        ctx: *mut llama_context,
+        text: &str,
+        tokens: &mut [LlamaToken],
+        add_bos: bool,
+    ) -> anyhow::Result<c_int>;
+    /// Detokenizes like `llama_detokenize`: the byte count written to `text`,
+    /// or the negated count needed when `text` is too small.
+    fn detokenize(
+        model: *const llama_model,
+        tokens: &[LlamaToken],
+        text: &mut [u8],
+    ) -> anyhow::Result<c_int>;
+    /// A string value from the model's GGUF metadata, `None` if the key is missing.
+    fn model_meta_str(model: *const llama_model, key: &str) -> Option<String>;
+    /// The chat template in the model's metadata, null if it has none.
+    fn model_chat_template(model: *const llama_model) -> *const c_char;
+    /// Formats like `llama_chat_apply_template`: writes at most `buf.len()`
+    /// bytes and returns the full length, negative if the template is unknown.
+    fn chat_apply_template(
+        tmpl: *const c_char,
+        chat: &[llama_chat_message],
+        add_assistant: bool,
+        buf: &mut [u8],
+    ) -> c_int;
+    fn embedding(ctx: *mut llama_context, tokens: &mut [LlamaToken]) -> anyhow::Result<Vec<f32>>;
+    fn state_size(ctx: *mut llama_context) -> usize;
+    fn state_save(ctx: *mut llama_context, out: &mut [u8]) -> usize;
+    fn state_load(ctx: *mut llama_context, data: &[u8]) -> anyhow::Result<i32>;
 }
 
+/// llama.cpp linked into the mobile builds.
 #[cfg(any(target_os = "android", target_os = "ios"))]
-fn real_llama_backend_free() {
-    // SAFETY: Releases llama.cpp process-level backend resources during cleanup.
-    unsafe { llama_backend_free() }
-}
+struct RealBackend;
 
-#[cfg(any(target_os = "android", target_os = "ios"))]
-fn real_llama_model_load_from_file(
-    path: *const c_char,
-    params: llama_model_params,
-) -> *mut llama_model {
-    // SAFETY: `path` is supplied by the C API caller and must be a valid
-    // NUL-terminated model path for the duration of this call.
-    unsafe { llama_load_model_from_file(path, params) }
-}
+/// Stand-in for llama.cpp on desktop builds and in tests.
+struct SimulatedBackend;
 
 #[cfg(any(target_os = "android", target_os = "ios"))]
-#[allow(dead_code)]
-fn real_llama_model_free(model: *mut llama_model) {
-    // SAFETY: `model` must be a llama.cpp model pointer returned by this SDK.
-    unsafe { llama_model_free(model) }
-}
+type Backend = RealBackend;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+type Backend = SimulatedBackend;
 
 #[cfg(any(target_os = "android", target_os = "ios"))]
-fn real_llama_init_from_model(
-    model: *const llama_model,
-    params: llama_context_params,
-) -> *mut llama_context {
-    // SAFETY: `model` must point to a live llama.cpp model for this call.
-    unsafe { llama_init_from_model(model, params) }
-}
+impl LlamaBackend for RealBackend {
+    fn backend_init() -> c_int {
+        // SAFETY: llama.cpp backend initialization is a process-level FFI call.
+        // This wrapper is used during SDK initialization before model/context use.
+        unsafe {
+            llama_backend_init();
+            ggml_backend_load_all(); // Load backends to solve tensor loading issues
+            0
+        }
+    }
 
-#[cfg(any(target_os = "android", target_os = "ios"))]
-#[allow(dead_code)]
-fn real_llama_free(ctx: *mut llama_context) {
-    // SAFETY: `ctx` must be a llama.cpp context pointer returned by this SDK.
-    unsafe { llama_free(ctx) }
-}
+    fn backend_free() {
+        // SAFETY: Releases llama.cpp process-level backend resources during cleanup.
+        unsafe { llama_backend_free() }
+    }
 
-//
-#[cfg(target_os = "android")]
-fn safe_llama_tokenize_with_pool(
-    _ctx: *mut llama_context,
-    _text: *const c_char,
-    _tokens: *mut LlamaToken,
-    _n_max_tokens: c_int,
-    _add_bos: bool,
-) -> c_int {
-    // Temporarily disabled - use safe_tokenize instead
-    0
-}
+    fn model_default_params() -> llama_model_params {
+        // SAFETY: Retrieves llama.cpp default model parameters by value.
+        unsafe { llama_model_default_params() }
+    }
 
-#[cfg(any(target_os = "android", target_os = "ios"))]
-// llama-cpp-rs
-/// # Safety
-/// `ctx`, `text`, and `tokens` must be valid for this call. `text` must be
-/// NUL-terminated and `tokens` must point to a writable buffer of at least
-/// `max_tokens` elements.
-pub(crate) unsafe fn safe_tokenize(
-    ctx: *mut llama_context,
-    text: *const c_char,
+    fn context_default_params() -> llama_context_params {
+        // SAFETY: Retrieves llama.cpp default context parameters by value.
+        unsafe { llama_context_default_params() }
+    }
+
+    fn model_load_from_file(path: *const c_char, params: llama_model_params) -> *mut llama_model {
+        // SAFETY: `path` is supplied by the C API caller and must be a valid
+        // NUL-terminated model path for the duration of this call.
+        unsafe { llama_load_model_from_file(path, params) }
+    }
+
+    fn model_free(model: *mut llama_model) {
+        // SAFETY: `model` must be a llama.cpp model pointer returned by this SDK.
+        unsafe { llama_model_free(model) }
+    }
+
+    fn init_from_model(
+        model: *const llama_model,
+        params: llama_context_params,
+    ) -> *mut llama_context {
+        // SAFETY: `model` must point to a live llama.cpp model for this call.
+        unsafe { llama_init_from_model(model, params) }
+    }
+
+    fn free_context(ctx: *mut llama_context) {
+        // SAFETY: `ctx` must be a llama.cpp context pointer returned by this SDK.
+        unsafe { llama_free(ctx) }
+    }
+
+    fn n_ctx(ctx: *const llama_context) -> c_int {
+        // SAFETY: `ctx` must point to a live llama.cpp context.
+        unsafe { llama_n_ctx(ctx) }
+    }
+
+    fn n_batch(ctx: *mut llama_context) -> c_int {
+        // SAFETY: `ctx` must point to a live llama.cpp context.
+        unsafe { llama_n_batch(ctx) }
+    }
+
+    fn memory_clear(ctx: *mut llama_context, data: bool) {
+        // SAFETY: `ctx` must point to a live llama.cpp context; its memory
+        // handle is owned by the context.
+        unsafe {
+            let memory = llama_get_memory(ctx);
+            if !memory.is_null() {
+                llama_memory_clear(memory, data);
+            }
+        }
+    }
+
+    fn memory_seq_rm(ctx: *mut llama_context, seq_id: c_int, p0: LlamaPos, p1: LlamaPos) -> bool {
+        // SAFETY: as for `memory_clear`.
+        unsafe {
+            let memory = llama_get_memory(ctx);
+            !memory.is_null() && llama_memory_seq_rm(memory, seq_id, p0, p1)
+        }
+    }
+
+    fn memory_seq_pos_max(ctx: *mut llama_context, seq_id: c_int) -> LlamaPos {
+        // SAFETY: as for `memory_clear`.
+        unsafe {
+            let memory = llama_get_memory(ctx);
+            if memory.is_null() {
+                return -1;
+            }
+            llama_memory_seq_pos_max(memory, seq_id)
+        }
+    }
+
+    fn tokenize(
+        ctx: *mut llama_context,
+        text: &str,
+        tokens: &mut [LlamaToken],
+        add_bos: bool,
+    ) -> anyhow::Result<c_int> {
+        // SAFETY: `ctx` is a non-null llama.cpp context owned by the caller; only
+        // its model and vocab pointers are read.
+        let vocab = unsafe {
+            let model = llama_get_model(ctx);
+            if model.is_null() {
+                anyhow::bail!("tokenize: context has no model");
+            }
+            llama_model_get_vocab(model)
+        };
+        if vocab.is_null() {
+            anyhow::bail!("tokenize: model has no vocabulary");
+        }
+
+        // SAFETY: `text` is valid for `text.len()` bytes (llama_tokenize takes an
+        // explicit length, no NUL needed) and `tokens` bounds `n_tokens_max`.
+        Ok(unsafe {
+            llama_tokenize(
+                vocab,
+                text.as_ptr() as *const c_char,
+                text.len() as c_int,
+                tokens.as_mut_ptr(),
+                tokens.len() as c_int,
+                add_bos,
+                true,
+            )
+        })
+    }
+
+    fn detokenize(
+        model: *const llama_model,
+        tokens: &[LlamaToken],
+        text: &mut [u8],
+    ) -> anyhow::Result<c_int> {
+        // SAFETY: `model` is a live, non-null llama.cpp model owned by the caller;
+        // only its vocab pointer is read.
+        let vocab = unsafe { llama_model_get_vocab(model) };
+        if vocab.is_null() {
+            anyhow::bail!("detokenize: model has no vocabulary");
+        }
+
+        // SAFETY: `tokens` is readable for `n_tokens` and `text` writable for
+        // `text_len_max` bytes; llama.cpp writes no NUL terminator.
+        Ok(unsafe {
+            llama_detokenize(
+                vocab,
+                tokens.as_ptr(),
+                tokens.len() as i32,
+                text.as_mut_ptr() as *mut c_char,
+                text.len().min(i32::MAX as usize) as i32,
+                true,
+                true,
+            )
+        })
+    }
+
+    fn model_meta_str(model: *const llama_model, key: &str) -> Option<String> {
+        let key = CString::new(key).ok()?;
+        let mut buf = vec![0u8; 256];
+        // SAFETY: `model` is a live llama.cpp model owned by the caller; `key` and
+        // `buf` outlive the call and `buf.len()` bounds the write.
+        let len = unsafe {
+            llama_model_meta_val_str(
+                model,
+                key.as_ptr(),
+                buf.as_mut_ptr() as *mut c_char,
+                buf.len(),
+            )
+        };
+        if len < 0 {
+            return None;
+        }
+        buf.truncate((len as usize).min(buf.len() - 1));
+        Some(String::from_utf8_lossy(&buf).into_owned())
+    }
+
+    fn model_chat_template(model: *const llama_model) -> *const c_char {
+        // SAFETY: `model` is a live, non-null llama.cpp model; the returned string
+        // is owned by the model and outlives the caller's use of it.
+        unsafe { llama_model_chat_template(model, std::ptr::null()) }
+    }
+
+    fn chat_apply_template(
+        tmpl: *const c_char,
+        chat: &[llama_chat_message],
+        add_assistant: bool,
+        buf: &mut [u8],
+    ) -> c_int {
+        // SAFETY: `tmpl` and the role/content strings in `chat` are live C strings
+        // for the duration of the call, and `buf.len()` bounds the write.
+        unsafe {
+            llama_chat_apply_template(
+                tmpl,
+                chat.as_ptr(),
+                chat.len(),
+                add_assistant,
+                buf.as_mut_ptr() as *mut c_char,
+                buf.len().min(c_int::MAX as usize) as c_int,
+            )
+        }
+    }
+
+    /// Runs `tokens` through `ctx` in embeddings mode and returns the sequence
+    /// embedding (pooled when the context has a pooling type, otherwise the last
+    /// token's). The KV cache is cleared before and after.
+    fn embedding(ctx: *mut llama_context, tokens: &mut [LlamaToken]) -> anyhow::Result<Vec<f32>> {
+        // SAFETY: `ctx` is a live context owned by the caller and the inference
+        // lock is held; the token buffer outlives the decode, and llama.cpp
+        // returns embeddings holding `n_embd` floats until the next decode.
+        unsafe {
+            let model = llama_get_model(ctx);
+            if model.is_null() {
+                anyhow::bail!("embed: context has no model");
+            }
+            let n_embd = llama_model_n_embd(model);
+            if n_embd <= 0 {
+                anyhow::bail!("embed: model has no embedding size");
+            }
+            let n_batch = llama_n_batch(ctx);
+            if tokens.len() > n_batch as usize {
+                anyhow::bail!(
+                    "embed: text is {} tokens but the context batch holds {}",
+                    tokens.len(),
+                    n_batch
+                );
+            }
+
+            Self::memory_clear(ctx, true);
+            llama_set_embeddings(ctx, true);
+            let result = llama_decode(
+                ctx,
+                llama_batch_get_one(tokens.as_mut_ptr(), tokens.len() as c_int),
+            );
+            let mut data = llama_get_embeddings_seq(ctx, 0);
+            if data.is_null() {
+                data = llama_get_embeddings(ctx);
+            }
+            let embedding = (result == 0 && !data.is_null())
+                .then(|| std::slice::from_raw_parts(data, n_embd as usize).to_vec());
+            llama_set_embeddings(ctx, false);
+            Self::memory_clear(ctx, false);
+
+            embedding.ok_or_else(|| anyhow::anyhow!("embed: decode returned {}", result))
+        }
+    }
+
+    fn state_size(ctx: *mut llama_context) -> usize {
+        // SAFETY: `ctx` is a live, non-null context owned by the caller.
+        unsafe { llama_state_get_size(ctx) }
+    }
+
+    fn state_save(ctx: *mut llama_context, out: &mut [u8]) -> usize {
+        // SAFETY: `ctx` is a live context and `out` is writable for its length.
+        unsafe { llama_state_get_data(ctx, out.as_mut_ptr(), out.len()) }
+    }
+
+    /// Restores state saved by `state_save` and returns the next free KV
+    /// position of sequence 0.
+    fn state_load(ctx: *mut llama_context, data: &[u8]) -> anyhow::Result<i32> {
+        // SAFETY: `ctx` is a live context and `data` is readable for its length;
+        // llama.cpp rejects data that does not match the context's model.
+        unsafe {
+            let read = llama_state_set_data(ctx, data.as_ptr(), data.len());
+            if read == 0 {
+                anyhow::bail!("kv cache: state does not match this context");
+            }
+            if llama_get_memory(ctx).is_null() {
+                anyhow::bail!("kv cache: context has no memory");
+            }
+            Ok(Self::memory_seq_pos_max(ctx, 0) + 1)
+        }
+    }
+}
+
+//
+#[cfg(target_os = "android")]
+fn safe_llama_tokenize_with_pool(
+    _ctx: *mut llama_context,
+    _text: *const c_char,
+    _tokens: *mut LlamaToken,
+    _n_max_tokens: c_int,
+    _add_bos: bool,
+) -> c_int {
+    // Temporarily disabled - use safe_tokenize instead
+    0
+}
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+// llama-cpp-rs
+/// # Safety
+/// `ctx`, `text`, and `tokens` must be valid for this call. `text` must be
+/// NUL-terminated and `tokens` must point to a writable buffer of at least
+/// `max_tokens` elements.
+pub(crate) unsafe fn safe_tokenize(
+    ctx: *mut llama_context,
+    text: *const c_char,
     tokens: *mut LlamaToken,
     max_tokens: c_int,
     add_bos: bool,
@@ -770,8 +1622,52 @@ fn test_token_decode(model: *const llama_model, token: LlamaToken) -> Option<Str
     }
 }
 
-// Enhanced token decoding with larger buffer and special token support
+/// Returns the raw piece bytes for `token`, growing the buffer when llama.cpp reports
+/// that the piece does not fit.
+///
+/// A single token may carry only part of a multi-byte glyph, so the bytes are not
+/// guaranteed to be valid UTF-8 on their own. Feed them through `Utf8EmitBuffer`
+/// to get text that is safe to emit.
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn token_to_piece_bytes(vocab: *const llama_vocab, token: LlamaToken, special: bool) -> Vec<u8> {
+    let mut buffer = vec![0u8; 64];
+
+    // SAFETY: `vocab` must be a live llama.cpp vocab pointer. `buffer` is a
+    // writable heap buffer passed with its exact length.
+    unsafe {
+        let mut result = llama_token_to_piece(
+            vocab,
+            token,
+            buffer.as_mut_ptr() as *mut c_char,
+            buffer.len() as c_int,
+            0,
+            special,
+        );
+        if result < 0 {
+            // Negative result is the required buffer size
+            buffer.resize((-result) as usize, 0);
+            result = llama_token_to_piece(
+                vocab,
+                token,
+                buffer.as_mut_ptr() as *mut c_char,
+                buffer.len() as c_int,
+                0,
+                special,
+            );
+        }
+        buffer.truncate(result.max(0) as usize);
+    }
+    buffer
+}
+
+/// Debug-only single-token decoder.
+///
+/// Decodes one token in isolation and substitutes markers such as `[utf8_fail:..]`
+/// when the piece is not valid UTF-8 by itself, which is expected for the first half
+/// of a glyph spanning two tokens. Use it only to log individual tokens; output paths
+/// must use `token_to_piece_bytes` together with `Utf8EmitBuffer`.
 #[cfg(any(target_os = "android", target_os = "ios"))]
+#[deprecated(note = "use token_to_piece_bytes with Utf8EmitBuffer for output text")]
 fn decode_token_to_text(model: *const llama_model, token: LlamaToken) -> String {
     // Use a local buffer so concurrent mobile callbacks cannot race on token decoding.
     let mut buffer = [0u8; 1024];
@@ -841,6 +1737,9 @@ pub fn manual_llama_completion(
     output: *mut c_char,
     output_len: c_int,
 ) -> c_int {
+    record_generation_backend(ctx);
+    pin_inference_thread();
+
     // SAFETY: Mobile callers pass raw llama.cpp model/context pointers and an
     // output buffer. Null prompt is checked before use; output writes are
     // bounded by `output_len` before NUL termination.
@@ -849,9 +1748,6 @@ pub fn manual_llama_completion(
         // reset_pool();
 
         // Step 1: Use safe tokenization inspired by llama-cpp-rs
-        let mut tokens = [0i32; 512]; // Static array, no allocation
-        let token_count: c_int;
-
         // DEBUG: Check raw input string before tokenization
         let _prompt_str = if prompt.is_null() {
             println!(" Prompt pointer is NULL!");
@@ -874,108 +1770,134 @@ pub fn manual_llama_completion(
             }
         };
 
-        // Use safe tokenization with fallback
-        let tokenize_result = safe_tokenize(ctx, prompt, tokens.as_mut_ptr(), 512, true);
+        // Use safe tokenization with fallback; the buffer grows for prompts
+        // longer than 512 tokens instead of truncating them
+        let tokenize_result = tokenize_with_retry(512, |buf| {
+            let len = buf.len().min(c_int::MAX as usize) as c_int;
+            Ok(safe_tokenize(ctx, prompt, buf.as_mut_ptr(), len, true))
+        });
 
-        if tokenize_result > 0 {
-            token_count = tokenize_result;
-            println!(" Safe tokenization successful! Got {} tokens", token_count);
+        let tokens = match tokenize_result {
+            Ok(tokens) if !tokens.is_empty() => {
+                println!(" Safe tokenization successful! Got {} tokens", tokens.len());
 
-            // DEBUG: Keep only aggregate prompt token diagnostics.
-            println!(
-                " INPUT DEBUG - Prompt token ids redacted ({} tokens)",
-                token_count
-            );
-        } else {
-            println!(" Safe tokenization failed, using emergency fallback");
-            // Emergency fallback to BOS only
-            tokens[0] = 1; // BOS
-            token_count = 1;
-        }
+                // DEBUG: Keep only aggregate prompt token diagnostics.
+                println!(
+                    " INPUT DEBUG - Prompt token ids redacted ({} tokens)",
+                    tokens.len()
+                );
+                tokens
+            }
+            _ => {
+                println!(" Safe tokenization failed, using emergency fallback");
+                // Emergency fallback to BOS only
+                vec![1] // BOS
+            }
+        };
+        let token_count = tokens.len().min(c_int::MAX as usize) as c_int;
 
         println!(" Using {} tokens for inference", token_count);
+        let mut timer = GenerationTimer::start();
 
         // Step 2: Clear KV cache for clean inference
-        let kv = llama_get_memory(ctx);
-        if !kv.is_null() {
-            // Clear all sequences from KV cache
-            let clear_result = llama_memory_seq_rm(kv, -1, -1, -1);
-            if clear_result {
-                println!(" KV cache cleared successfully");
-            } else {
-                println!(" KV cache clear failed, trying full clear...");
-                llama_memory_clear(kv, false);
-            }
+        CONTINUABLE_CONTEXT_PTR.store(std::ptr::null_mut(), Ordering::SeqCst);
+        // Clear all sequences from KV cache
+        if Backend::memory_seq_rm(ctx, -1, -1, -1) {
+            println!(" KV cache cleared successfully");
+        } else {
+            println!(" KV cache clear failed, trying full clear...");
+            Backend::memory_clear(ctx, false);
         }
 
         // Step 3: Global position tracking for continuous context
         // CRITICAL FIX: Reset position for new independent inference
         let current_pos = 0; // Always start from 0 for clean inference
-        GLOBAL_CONTEXT_POSITION.store(0, Ordering::SeqCst); // Reset global state
+        set_context_position(0); // Reset global state
         println!(
             " GLOBAL CONTEXT: Reset to position {} for clean inference",
             current_pos
         );
 
-        // Step 3: Create batch with global position tracking and logits request
-        let mut batch_pos_array = [0i32; 512]; // Position array for batch
-        let mut logits_array = [0i8; 512]; // Logits request array
+        // Step 3: Prefill the prompt in chunks of the context's n_batch, since
+        // llama.cpp rejects larger batches; only the last prompt token needs logits
+        let n_batch = match llama_n_batch(ctx) {
+            nb if nb > 0 => nb,
+            _ => 128,
+        };
+        let chunk_capacity = token_count.min(n_batch) as usize;
+        let mut batch_pos_array = vec![0i32; chunk_capacity]; // Position array for batch
+        let mut logits_array = vec![0i8; chunk_capacity]; // Logits request array
 
-        for i in 0..token_count {
-            batch_pos_array[i as usize] = current_pos + i;
-            // Request logits for the last token only (for sampling)
-            logits_array[i as usize] = if i == token_count - 1 { 1 } else { 0 };
-        }
+        println!(
+            "🔍 Prefilling {} tokens in batches of {}",
+            token_count, n_batch
+        );
 
-        println!("🔍 Creating initial batch with {} tokens", token_count);
+        let mut start: c_int = 0;
+        let mut last_chunk_len: c_int = 0;
+        while start < token_count {
+            let end = std::cmp::min(start + n_batch, token_count);
+            let n = end - start;
 
-        let initial_batch = llama_batch {
-            n_tokens: token_count,
-            token: tokens.as_ptr() as *mut LlamaToken,
-            embd: std::ptr::null_mut(),
-            pos: batch_pos_array.as_ptr() as *mut LlamaPos,
-            n_seq_id: std::ptr::null_mut(),
-            seq_id: std::ptr::null_mut(),
-            logits: logits_array.as_ptr() as *mut i8, // Request logits for last token
-        };
+            for i in 0..n {
+                batch_pos_array[i as usize] = current_pos + start + i;
+                // Request logits for the last token only (for sampling)
+                logits_array[i as usize] = if end == token_count && i == n - 1 {
+                    1
+                } else {
+                    0
+                };
+            }
 
-        println!("🔍 Initial batch created, about to decode...");
+            let batch = llama_batch {
+                n_tokens: n,
+                token: tokens.as_ptr().add(start as usize) as *mut LlamaToken,
+                embd: std::ptr::null_mut(),
+                pos: batch_pos_array.as_ptr() as *mut LlamaPos,
+                n_seq_id: std::ptr::null_mut(),
+                seq_id: std::ptr::null_mut(),
+                logits: logits_array.as_ptr() as *mut i8, // Request logits for last token
+            };
 
-        println!(
-            " Created batch with {} tokens, positions {} to {}",
-            token_count,
-            current_pos,
-            current_pos + token_count - 1
-        );
+            println!(
+                " Decoding batch with {} tokens, positions {} to {}",
+                n,
+                current_pos + start,
+                current_pos + end - 1
+            );
 
-        // Decode prompt
-        let decode_result = llama_decode(ctx, initial_batch);
-        if decode_result != 0 {
-            println!(" Initial decode failed with code {}", decode_result);
-            let msg = format!("Initial decode failed: code {}", decode_result);
-            let msg_bytes = msg.as_bytes();
-            let copy_len = std::cmp::min(msg_bytes.len(), output_len as usize - 1);
-            std::ptr::copy_nonoverlapping(msg.as_ptr(), output as *mut u8, copy_len);
-            *output.add(copy_len) = 0;
-            return copy_len as c_int;
+            let decode_result = llama_decode(ctx, batch);
+            if decode_result != 0 {
+                println!(" Initial decode failed with code {}", decode_result);
+                let msg = format!("Initial decode failed: code {}", decode_result);
+                let msg_bytes = msg.as_bytes();
+                let copy_len = std::cmp::min(msg_bytes.len(), output_len as usize - 1);
+                std::ptr::copy_nonoverlapping(msg.as_ptr(), output as *mut u8, copy_len);
+                *output.add(copy_len) = 0;
+                return copy_len as c_int;
+            }
+
+            last_chunk_len = n;
+            start = end;
         }
 
         println!(" Initial decode successful");
+        timer.prompt_evaluated();
 
         // Step 4: Generate tokens and update global position
         let mut generated_tokens = 0;
         let mut result_text = String::new();
         let mut next_pos = current_pos + token_count;
 
-        // Generate tokens with reasonable safety limits
-        // Context window is now 4096, support much longer generation
-        // Allow up to 4096 tokens, but ensure we don't exceed context window
-        let context_available = 4096 - current_pos - token_count;
-        let safe_generation_limit =
-            std::cmp::min(max_tokens, std::cmp::min(4096, context_available));
+        let vocab = llama_model_get_vocab(model);
+        let mut utf8_buf = Utf8EmitBuffer::new();
+
+        // Stop before the output overflows the context window
+        let n_ctx = Backend::n_ctx(ctx);
+        let safe_generation_limit = context_generation_limit(max_tokens, n_ctx, next_pos);
         println!(
-            " Generation limit: {} (requested: {}, context_available: {}, max_safe: 4096)",
-            safe_generation_limit, max_tokens, context_available
+            " Generation limit: {} (requested: {}, n_ctx: {}, n_past: {})",
+            safe_generation_limit, max_tokens, n_ctx, next_pos
         );
 
         // PROPER SAMPLER: Use actual sampling parameters
@@ -984,74 +1906,37 @@ pub fn manual_llama_completion(
             temperature, top_k, top_p, repeat_penalty
         );
 
-        // Create sampler chain
-        let chain_params = llama_sampler_chain_params { no_perf: false };
-        let persistent_sampler = llama_sampler_chain_init(chain_params);
-
+        let persistent_sampler = build_sampler_chain(&mobile_sampling_params(
+            temperature,
+            top_k,
+            top_p,
+            repeat_penalty,
+        ));
         if persistent_sampler.is_null() {
             println!(" Failed to create persistent sampler chain");
             return 0;
         }
 
-        // Add samplers in proper order (like llama.cpp examples)
-
-        // 1. Repeat penalty sampler
-        if repeat_penalty != 1.0 {
-            let repeat_sampler = llama_sampler_init_penalties(-1, repeat_penalty, 0.0, 0.0);
-            if !repeat_sampler.is_null() {
-                llama_sampler_chain_add(persistent_sampler, repeat_sampler);
-                println!(
-                    " Added Repeat penalty sampler (penalty: {})",
-                    repeat_penalty
-                );
-            }
-        }
-
-        // 2. Top-K sampler
-        if top_k > 0 {
-            let top_k_sampler = llama_sampler_init_top_k(top_k);
-            if !top_k_sampler.is_null() {
-                llama_sampler_chain_add(persistent_sampler, top_k_sampler);
-                println!(" Added Top-K sampler (k: {})", top_k);
-            }
-        }
-
-        // 3. Top-P sampler
-        if top_p < 1.0 {
-            let top_p_sampler = llama_sampler_init_top_p(top_p, 1);
-            if !top_p_sampler.is_null() {
-                llama_sampler_chain_add(persistent_sampler, top_p_sampler);
-                println!(" Added Top-P sampler (p: {})", top_p);
-            }
-        }
-
-        // 4. Temperature sampler
-        if temperature > 0.0 {
-            let temp_sampler = llama_sampler_init_temp(temperature);
-            if !temp_sampler.is_null() {
-                llama_sampler_chain_add(persistent_sampler, temp_sampler);
-                println!(" Added Temperature sampler (temp: {})", temperature);
-            }
-        }
-
-        // 5. Distribution sampler (for actual sampling)
-        let dist_sampler = llama_sampler_init_dist(1234);
-        if !dist_sampler.is_null() {
-            llama_sampler_chain_add(persistent_sampler, dist_sampler);
-            println!(" Added Distribution sampler");
-        }
-
-        println!(" Sampler chain configured with all parameters");
-
         // Track current batch size (starts with initial token_count)
         let mut current_batch_size = token_count;
+        let mut repetition = repetition_detector();
+        let mut rate_limiter = util::generation::TokenRateLimiter::from_config();
+        let mut finish_reason = util::generation::FinishReason::Length;
+        init_generation_control();
 
         for i in 0..safe_generation_limit {
+            if should_stop_generation() {
+                println!("⏹️ Generation stopped by user");
+                finish_reason = util::generation::FinishReason::Stop;
+                break;
+            }
+
             // Step 1: Sample from the last decoded position
             // After decode, logits are available at index (n_tokens - 1) for single token batches
             // For initial batch, logits are at the last token position
+            let token_started = Instant::now();
             let sampling_index = if i == 0 {
-                token_count - 1 // First iteration: sample from initial batch's last token
+                last_chunk_len - 1 // First iteration: sample from the prompt's last token
             } else {
                 0 // Subsequent iterations: single token batch, logits at index 0
             };
@@ -1070,6 +1955,7 @@ pub fn manual_llama_completion(
             if sampled_token == 2 {
                 // EOS token
                 println!(" Reached EOS token");
+                finish_reason = util::generation::FinishReason::Stop;
                 break;
             }
 
@@ -1078,14 +1964,17 @@ pub fn manual_llama_completion(
                 sampled_token, next_pos, temperature, top_k, top_p
             );
 
-            // Decode and add to result
-            let decoded_text = decode_token_to_text(model, sampled_token);
+            // Decode and add to result; pieces of a split glyph are held until complete
+            let piece = token_to_piece_bytes(vocab, sampled_token, true);
+            let decoded_text = utf8_buf.push_and_take_valid(&piece);
             result_text.push_str(&decoded_text);
             println!(" Token text redacted ({} bytes)", decoded_text.len());
 
             generated_tokens += 1;
             next_pos += 1;
 
+            let looping = repetition.push(sampled_token);
+
             // Step 2: CLEAR batch and add single new token (llama-cpp-rs style)
             println!(
                 " Clearing batch and adding new token at position {}",
@@ -1115,6 +2004,7 @@ pub fn manual_llama_completion(
                 println!(" Decode failed at step {} with code {}", i, decode_result);
                 break;
             }
+            timer.token_evaluated(token_started);
 
             // Step 4: Update batch size for next iteration
             current_batch_size = 1; // Now we have a single token batch
@@ -1123,20 +2013,35 @@ pub fn manual_llama_completion(
                 i, current_batch_size, next_pos
             );
 
+            // Stop only after the token is decoded so GLOBAL_CONTEXT_POSITION stays in sync
+            if looping {
+                println!(" Repetition loop detected, stopping generation");
+                finish_reason = util::generation::FinishReason::Repetition;
+                break;
+            }
+
             // Safety check
             if generated_tokens >= max_tokens {
                 break;
             }
+
+            pace_generation(&mut rate_limiter);
         }
+        report_throttling(&rate_limiter);
 
         // Cleanup persistent sampler at the end
         llama_sampler_free(persistent_sampler);
         println!(" Cleaned up persistent sampler");
+        set_last_finish_reason(finish_reason);
+        timer.finish();
+
+        result_text.push_str(&utf8_buf.flush_lossy());
 
-        GLOBAL_CONTEXT_POSITION.store(next_pos, Ordering::SeqCst);
+        set_context_position(next_pos);
+        CONTINUABLE_CONTEXT_PTR.store(ctx, Ordering::SeqCst);
         println!(
             " GLOBAL CONTEXT: Updated position to {}",
-            GLOBAL_CONTEXT_POSITION.load(Ordering::SeqCst)
+            get_context_position()
         );
 
         // Step 6: Return only the generated text (no debug info)
@@ -1145,33 +2050,22 @@ pub fn manual_llama_completion(
                 " CONTINUOUS CONTEXT: Generated {} tokens from pos {} (next: {})",
                 generated_tokens,
                 current_pos,
-                GLOBAL_CONTEXT_POSITION.load(Ordering::SeqCst)
+                get_context_position()
             );
             result_text
         } else {
             println!(
                 " No tokens generated - continuous context ready from pos {} (next: {})",
                 current_pos,
-                GLOBAL_CONTEXT_POSITION.load(Ordering::SeqCst)
+                get_context_position()
             );
             String::new() // Return empty string if no tokens generated
         };
 
-        let text_bytes = final_text.as_bytes();
-        let copy_len = std::cmp::min(text_bytes.len(), output_len as usize - 1);
-        std::ptr::copy_nonoverlapping(text_bytes.as_ptr(), output as *mut u8, copy_len);
-        *output.add(copy_len) = 0;
-
-        copy_len as c_int
+        store_and_copy_result(&final_text, output, output_len)
     }
 }
 
-#[cfg(any(target_os = "android", target_os = "ios"))]
-fn real_llama_n_ctx(ctx: *const llama_context) -> c_int {
-    // SAFETY: `ctx` must point to a live llama.cpp context.
-    unsafe { llama_n_ctx(ctx) }
-}
-
 // Token to text conversion (updated for new API)
 #[cfg(target_os = "android")]
 fn real_llama_token_to_piece(
@@ -1213,225 +2107,465 @@ fn real_llama_token_eos(model: *const llama_model) -> LlamaToken {
     unsafe { llama_token_eos(model) }
 }
 
-// Temporarily comment out detokenize until we verify function signature
-/*
-#[cfg(target_os = "android")]
-fn real_llama_detokenize(
-    model: *const llama_model,
-    tokens: *const LlamaToken,
-    n_tokens: c_int,
-    text: *mut c_char,
-    text_len_max: c_int,
-) -> c_int {
-    unsafe { llama_detokenize(model, tokens, n_tokens, text, text_len_max) }
+/// Runs `fill` on a buffer of `capacity` tokens and returns the tokens it
+/// wrote.
+///
+/// llama.cpp reports a buffer that is too small by returning the negated
+/// number of tokens it needs; the buffer is then grown to that size and
+/// `fill` retried, so long prompts are never cut off at the first buffer size.
+fn tokenize_with_retry<F>(capacity: usize, mut fill: F) -> anyhow::Result<Vec<LlamaToken>>
+where
+    F: FnMut(&mut [LlamaToken]) -> anyhow::Result<c_int>,
+{
+    let mut tokens: Vec<LlamaToken> = vec![0; capacity.max(1)];
+    let mut count = fill(&mut tokens)?;
+    if count < 0 {
+        tokens.resize(count.unsigned_abs() as usize, 0);
+        count = fill(&mut tokens)?;
+        if count < 0 {
+            anyhow::bail!(
+                "tokenize: still {} tokens short after resizing",
+                count.unsigned_abs()
+            );
+        }
+    }
+
+    tokens.truncate(count as usize);
+    Ok(tokens)
 }
-*/
 
-// ============================================================================
-// Non-Android (fallback to simulation)
-// ============================================================================
+/// Tokenizes `text` into an owned vector of tokens.
+pub fn tokenize(
+    ctx: *mut llama_context,
+    text: &str,
+    add_bos: bool,
+) -> anyhow::Result<Vec<LlamaToken>> {
+    if ctx.is_null() {
+        anyhow::bail!("tokenize: null context");
+    }
+    if text.len() > c_int::MAX as usize {
+        anyhow::bail!("tokenize: text too long ({} bytes)", text.len());
+    }
 
-#[cfg(not(any(target_os = "android", target_os = "ios")))]
-fn real_llama_backend_init() -> c_int {
-    simulate_llama_backend_init()
+    // Most text averages well under a token per 4 bytes; retry covers the rest
+    tokenize_with_retry(text.len() / 4 + 2, |tokens| {
+        Backend::tokenize(ctx, text, tokens, add_bos)
+    })
 }
 
-#[cfg(not(any(target_os = "android", target_os = "ios")))]
-fn real_llama_backend_free() {
-    simulate_llama_backend_free()
-}
+/// Turns `tokens` back into text, the inverse of `tokenize`: special tokens
+/// are rendered as their text and a leading BOS is dropped.
+///
+/// Like tokenization, a buffer that is too small is grown to the negated
+/// length llama.cpp reports and the call retried.
+pub fn detokenize(model: *const llama_model, tokens: &[LlamaToken]) -> anyhow::Result<String> {
+    if model.is_null() {
+        anyhow::bail!("detokenize: null model");
+    }
+    if tokens.len() > i32::MAX as usize {
+        anyhow::bail!("detokenize: too many tokens ({})", tokens.len());
+    }
 
-#[cfg(not(any(target_os = "android", target_os = "ios")))]
-fn real_llama_model_load_from_file(
-    path: *const c_char,
-    params: llama_model_params,
-) -> *mut llama_model {
-    simulate_llama_model_load_from_file(path, params)
+    // Most tokens are a few bytes; retry covers the rest
+    let mut text = vec![0u8; tokens.len() * 4 + 16];
+    let mut len = Backend::detokenize(model, tokens, &mut text)?;
+    if len < 0 {
+        text.resize(len.unsigned_abs() as usize, 0);
+        len = Backend::detokenize(model, tokens, &mut text)?;
+        if len < 0 {
+            anyhow::bail!(
+                "detokenize: still {} bytes short after resizing",
+                len.unsigned_abs()
+            );
+        }
+    }
+
+    text.truncate(len as usize);
+    // A token slice can end inside a multi-byte character
+    Ok(String::from_utf8_lossy(&text).into_owned())
 }
 
-#[cfg(not(any(target_os = "android", target_os = "ios")))]
-#[allow(dead_code)]
-fn real_llama_model_free(model: *mut llama_model) {
-    simulate_llama_model_free(model)
+/// Whether `model` carries a chat template in its GGUF metadata.
+pub fn model_has_chat_template(model: *const llama_model) -> bool {
+    !model.is_null() && !Backend::model_chat_template(model).is_null()
 }
 
-#[cfg(not(any(target_os = "android", target_os = "ios")))]
-fn real_llama_init_from_model(
+/// Formats `messages` with the model's embedded chat template. With
+/// `add_assistant` the prompt ends with the assistant header so generation
+/// continues as the assistant's reply.
+///
+/// The template is read from the model and passed explicitly, since llama.cpp
+/// treats a null template as chatml. As with tokenization, a buffer that is too
+/// small is grown to the length llama.cpp reports and the call retried.
+pub fn apply_chat_template(
     model: *const llama_model,
-    params: llama_context_params,
-) -> *mut llama_context {
-    simulate_llama_init_from_model(model, params)
-}
+    messages: &[common::ChatMessage],
+    add_assistant: bool,
+) -> anyhow::Result<String> {
+    if model.is_null() {
+        anyhow::bail!("apply_chat_template: null model");
+    }
+    let tmpl = Backend::model_chat_template(model);
+    if tmpl.is_null() {
+        anyhow::bail!("apply_chat_template: model has no chat template");
+    }
 
-#[cfg(not(any(target_os = "android", target_os = "ios")))]
-#[allow(dead_code)]
-fn real_llama_free(ctx: *mut llama_context) {
-    simulate_llama_free(ctx)
-}
+    let strings = messages
+        .iter()
+        .map(|m| {
+            Ok((
+                CString::new(m.role.as_str())?,
+                CString::new(m.content.as_str())?,
+            ))
+        })
+        .collect::<Result<Vec<_>, std::ffi::NulError>>()
+        .map_err(|_| anyhow::anyhow!("apply_chat_template: message contains a NUL byte"))?;
+    let chat: Vec<llama_chat_message> = strings
+        .iter()
+        .map(|(role, content)| llama_chat_message {
+            role: role.as_ptr(),
+            content: content.as_ptr(),
+        })
+        .collect();
+
+    // Templates add a few dozen bytes of markup per message; retry covers the rest
+    let estimate = messages
+        .iter()
+        .map(|m| m.role.len() + m.content.len() + 16)
+        .sum::<usize>()
+        + 32;
+    let mut buf = vec![0u8; estimate];
+    let mut len = Backend::chat_apply_template(tmpl, &chat, add_assistant, &mut buf);
+    if len > 0 && len as usize > buf.len() {
+        buf.resize(len as usize, 0);
+        len = Backend::chat_apply_template(tmpl, &chat, add_assistant, &mut buf);
+    }
+    if len < 0 {
+        anyhow::bail!("apply_chat_template: template is not supported by llama.cpp");
+    }
+    if len as usize > buf.len() {
+        anyhow::bail!("apply_chat_template: output grew to {} bytes on retry", len);
+    }
 
-#[cfg(not(any(target_os = "android", target_os = "ios")))]
-//
-// fn real_llama_tokenize(
-//     ctx: *mut llama_context,
-//     text: *const c_char,
-//     tokens: *mut LlamaToken,
-//     n_max_tokens: c_int,
-//     add_bos: bool,
-// ) -> c_int {
-//     simulate_llama_tokenize(ctx, text, tokens, n_max_tokens, add_bos)
-// }
-#[cfg(not(any(target_os = "android", target_os = "ios")))]
-fn real_llama_n_ctx(ctx: *const llama_context) -> c_int {
-    simulate_llama_n_ctx(ctx)
+    buf.truncate(len as usize);
+    String::from_utf8(buf)
+        .map_err(|_| anyhow::anyhow!("apply_chat_template: output is not valid UTF-8"))
 }
 
-// Simulate real llama.cpp function behavior
-fn simulate_llama_backend_init() -> c_int {
-    println!("🔧 Simulating llama_backend_init()...");
-    0 // Success
-}
+/// Token id the simulation backend uses for BOS.
+const SIMULATED_BOS_TOKEN: LlamaToken = 1;
+/// Chat template the simulation backend reports for every model.
+const SIMULATED_CHAT_TEMPLATE: &[u8] = b"chatml\0";
 
-fn simulate_llama_backend_free() {
-    println!("🧹 Simulating llama_backend_free()...");
-}
+/// KV cells held by the simulation backend's context; only state save and
+/// restore touch it.
+static SIMULATED_KV_CELLS: AtomicI32 = AtomicI32::new(0);
+/// Header of the simulation backend's serialized state.
+const SIMULATED_STATE_MAGIC: &[u8; 6] = b"GPUFKV";
+const SIMULATED_STATE_SIZE: usize = SIMULATED_STATE_MAGIC.len() + 4;
 
-fn simulate_llama_model_load_from_file(
-    path: *const c_char,
-    _params: llama_model_params,
-) -> *mut llama_model {
-    if path.is_null() {
-        return std::ptr::null_mut();
+/// Embedding size of the simulation backend.
+const SIMULATED_N_EMBD: usize = 8;
+
+impl LlamaBackend for SimulatedBackend {
+    fn backend_init() -> c_int {
+        println!("🔧 Simulating llama_backend_init()...");
+        0 // Success
     }
 
-    // SAFETY: `path` was checked for null and is expected to be a
-    // NUL-terminated C string supplied by the caller.
-    let path_str = unsafe { CStr::from_ptr(path).to_str().unwrap_or("invalid_path") };
+    fn backend_free() {
+        println!("🧹 Simulating llama_backend_free()...");
+    }
 
-    println!(
-        "🔧 Simulating llama_load_model_from_file(<redacted>, {} bytes)",
-        path_str.len()
-    );
-    std::ptr::NonNull::dangling().as_ptr()
-}
+    fn model_default_params() -> llama_model_params {
+        llama_model_params {
+            devices: std::ptr::null_mut(),
+            tensor_buft_overrides: std::ptr::null(),
+            n_gpu_layers: 0,
+            split_mode: 0,
+            main_gpu: 0,
+            tensor_split: std::ptr::null(),
+            progress_callback: None,
+            progress_callback_user_data: std::ptr::null_mut(),
+            kv_overrides: std::ptr::null(),
+            vocab_only: false,
+            use_mmap: true,
+            use_mlock: false,
+            check_tensors: false,
+            use_extra_bufts: false,
+            no_host: false,
+        }
+    }
 
-#[allow(dead_code)]
-fn simulate_llama_model_free(model: *mut llama_model) {
-    if !model.is_null() {
-        println!("🧹 Simulating llama_model_free()");
+    fn context_default_params() -> llama_context_params {
+        llama_context_params {
+            n_ctx: 2048,
+            n_batch: 512,
+            n_ubatch: 512,
+            n_seq_max: 1,
+            n_threads: DEFAULT_LLAMA_THREADS,
+            n_threads_batch: DEFAULT_LLAMA_THREADS,
+            rope_scaling_type: 0,
+            pooling_type: 0,
+            attention_type: 0,
+            flash_attn_type: 0,
+            rope_freq_base: 0.0,
+            rope_freq_scale: 0.0,
+            yarn_ext_factor: 0.0,
+            yarn_attn_factor: 0.0,
+            yarn_beta_fast: 0.0,
+            yarn_beta_slow: 1.0,
+            yarn_orig_ctx: 0,
+            defrag_thold: 0.0,
+            cb_eval: std::ptr::null_mut(),
+            cb_eval_user_data: std::ptr::null_mut(),
+            type_k: 0,
+            type_v: 0,
+            abort_callback: std::ptr::null_mut(),
+            abort_callback_data: std::ptr::null_mut(),
+            embeddings: false,
+            offload_kqv: false,
+            no_perf: false,
+            op_offload: false,
+            swa_full: false,
+            kv_unified: false,
+            samplers: std::ptr::null_mut(),
+        }
     }
-}
 
-fn simulate_llama_init_from_model(
-    model: *const llama_model,
-    _params: llama_context_params,
-) -> *mut llama_context {
-    if model.is_null() {
-        return std::ptr::null_mut();
+    fn model_load_from_file(path: *const c_char, params: llama_model_params) -> *mut llama_model {
+        if path.is_null() {
+            return std::ptr::null_mut();
+        }
+
+        // SAFETY: `path` was checked for null and is expected to be a
+        // NUL-terminated C string supplied by the caller.
+        let path_str = unsafe { CStr::from_ptr(path).to_str().unwrap_or("invalid_path") };
+
+        println!(
+            "🔧 Simulating llama_load_model_from_file(<redacted>, {} bytes)",
+            path_str.len()
+        );
+
+        // Report tensor loading in steps like llama.cpp does; returning false aborts
+        if let Some(callback) = params.progress_callback {
+            for step in 0..=4 {
+                if !callback(step as f32 / 4.0, params.progress_callback_user_data) {
+                    return std::ptr::null_mut();
+                }
+            }
+        }
+        std::ptr::NonNull::dangling().as_ptr()
     }
 
-    println!("🔧 Simulating llama_init_from_model()");
-    std::ptr::NonNull::dangling().as_ptr()
-}
+    fn model_free(model: *mut llama_model) {
+        if !model.is_null() {
+            println!("🧹 Simulating llama_model_free()");
+        }
+    }
 
-#[allow(dead_code)]
-fn simulate_llama_free(ctx: *mut llama_context) {
-    if !ctx.is_null() {
-        println!("🧹 Simulating llama_free()");
+    fn init_from_model(
+        model: *const llama_model,
+        _params: llama_context_params,
+    ) -> *mut llama_context {
+        if model.is_null() {
+            return std::ptr::null_mut();
+        }
+
+        println!("🔧 Simulating llama_init_from_model()");
+        std::ptr::NonNull::dangling().as_ptr()
     }
-}
 
-fn simulate_llama_tokenize(
-    ctx: *mut llama_context,
-    text: *const c_char,
-    tokens: *mut LlamaToken,
-    n_max_tokens: c_int,
-    _add_bos: bool,
-) -> c_int {
-    if ctx.is_null() || text.is_null() || tokens.is_null() || n_max_tokens <= 0 {
-        return 0;
+    fn free_context(ctx: *mut llama_context) {
+        if !ctx.is_null() {
+            println!("🧹 Simulating llama_free()");
+        }
     }
 
-    // SAFETY: `text` was checked for null above and must be NUL-terminated.
-    let text_str = unsafe { CStr::from_ptr(text).to_str().unwrap_or("") };
+    fn n_ctx(ctx: *const llama_context) -> c_int {
+        if ctx.is_null() {
+            return 0;
+        }
+        2048
+    }
 
-    println!(
-        "🔧 Simulating llama_tokenize(<redacted>, {} bytes)",
-        text_str.len()
-    );
+    fn n_batch(ctx: *mut llama_context) -> c_int {
+        if ctx.is_null() {
+            return 0;
+        }
+        512
+    }
 
-    // Return fake token count
-    let token_count = text_str.len().min(n_max_tokens as usize);
-    // SAFETY: `tokens` was checked for null and `token_count` is capped by
-    // `n_max_tokens`, the caller-provided writable buffer length.
-    unsafe {
-        for i in 0..token_count {
-            *tokens.add(i) = i as LlamaToken;
+    fn memory_clear(ctx: *mut llama_context, _data: bool) {
+        if !ctx.is_null() {
+            SIMULATED_KV_CELLS.store(0, Ordering::SeqCst);
         }
     }
 
-    token_count as c_int
-}
+    // Only sequence 0 is simulated; removing a suffix truncates it to `p0`.
+    fn memory_seq_rm(ctx: *mut llama_context, seq_id: c_int, p0: LlamaPos, p1: LlamaPos) -> bool {
+        if ctx.is_null() {
+            return false;
+        }
+        if seq_id > 0 {
+            return true;
+        }
+        let cells = SIMULATED_KV_CELLS.load(Ordering::SeqCst);
+        if p1 >= 0 && p1 < cells {
+            return false;
+        }
+        SIMULATED_KV_CELLS.store(p0.clamp(0, cells), Ordering::SeqCst);
+        true
+    }
 
-fn simulate_llama_n_ctx(ctx: *const llama_context) -> c_int {
-    if ctx.is_null() {
-        return 0;
+    fn memory_seq_pos_max(ctx: *mut llama_context, seq_id: c_int) -> LlamaPos {
+        if ctx.is_null() || seq_id > 0 {
+            return -1;
+        }
+        SIMULATED_KV_CELLS.load(Ordering::SeqCst) - 1
+    }
+
+    // Mirrors llama_tokenize: one token per byte, and the negated token count when
+    // `tokens` is too small.
+    fn tokenize(
+        ctx: *mut llama_context,
+        text: &str,
+        tokens: &mut [LlamaToken],
+        add_bos: bool,
+    ) -> anyhow::Result<c_int> {
+        if ctx.is_null() {
+            return Ok(0);
+        }
+
+        println!(
+            "🔧 Simulating llama_tokenize(<redacted>, {} bytes)",
+            text.len()
+        );
+
+        let bos = add_bos.then_some(SIMULATED_BOS_TOKEN);
+        let needed = text.len() + bos.is_some() as usize;
+        if needed > tokens.len() {
+            return Ok(-(needed.min(c_int::MAX as usize) as c_int));
+        }
+        for (slot, token) in tokens
+            .iter_mut()
+            .zip(bos.into_iter().chain(text.bytes().map(LlamaToken::from)))
+        {
+            *slot = token;
+        }
+
+        Ok(needed as c_int)
+    }
+
+    // Inverse of the simulated tokenize: one byte per token, a leading BOS
+    // dropped like llama_detokenize's remove_special.
+    fn detokenize(
+        model: *const llama_model,
+        tokens: &[LlamaToken],
+        text: &mut [u8],
+    ) -> anyhow::Result<c_int> {
+        if model.is_null() {
+            return Ok(0);
+        }
+
+        let tokens = match tokens.split_first() {
+            Some((&SIMULATED_BOS_TOKEN, rest)) => rest,
+            _ => tokens,
+        };
+        if tokens.len() > text.len() {
+            return Ok(-(tokens.len().min(c_int::MAX as usize) as c_int));
+        }
+        for (slot, &token) in text.iter_mut().zip(tokens) {
+            *slot = token as u8;
+        }
+
+        Ok(tokens.len() as c_int)
+    }
+
+    // The simulated model carries no GGUF metadata
+    fn model_meta_str(_model: *const llama_model, _key: &str) -> Option<String> {
+        None
+    }
+
+    fn model_chat_template(model: *const llama_model) -> *const c_char {
+        if model.is_null() {
+            return std::ptr::null();
+        }
+        SIMULATED_CHAT_TEMPLATE.as_ptr() as *const c_char
+    }
+
+    // Formats ChatML whatever the template says
+    fn chat_apply_template(
+        tmpl: *const c_char,
+        chat: &[llama_chat_message],
+        add_assistant: bool,
+        buf: &mut [u8],
+    ) -> c_int {
+        if tmpl.is_null() {
+            return -1;
+        }
+        let mut out = String::new();
+        for message in chat {
+            // SAFETY: callers pass role/content pointers from live CStrings.
+            let (role, content) = unsafe {
+                (
+                    CStr::from_ptr(message.role).to_string_lossy(),
+                    CStr::from_ptr(message.content).to_string_lossy(),
+                )
+            };
+            out.push_str(&format!("<|im_start|>{}\n{}<|im_end|>\n", role, content));
+        }
+        if add_assistant {
+            out.push_str("<|im_start|>assistant\n");
+        }
+        let n = out.len().min(buf.len());
+        buf[..n].copy_from_slice(&out.as_bytes()[..n]);
+        out.len() as c_int
+    }
+
+    // Stands in for a pooled embedding: a fixed-size vector that depends on the tokens.
+    fn embedding(ctx: *mut llama_context, tokens: &mut [LlamaToken]) -> anyhow::Result<Vec<f32>> {
+        if ctx.is_null() {
+            return Ok(Vec::new());
+        }
+        let mut embedding = vec![0.0f32; SIMULATED_N_EMBD];
+        for (i, token) in tokens.iter().enumerate() {
+            embedding[(i + *token as usize) % SIMULATED_N_EMBD] += 1.0;
+        }
+        Ok(embedding)
     }
-    2048
-}
-
-fn simulate_llama_model_default_params() -> llama_model_params {
-    llama_model_params {
-        devices: std::ptr::null_mut(),
-        tensor_buft_overrides: std::ptr::null(),
-        n_gpu_layers: 0,
-        split_mode: 0,
-        main_gpu: 0,
-        tensor_split: std::ptr::null(),
-        progress_callback: None,
-        progress_callback_user_data: std::ptr::null_mut(),
-        kv_overrides: std::ptr::null(),
-        vocab_only: false,
-        use_mmap: true,
-        use_mlock: false,
-        check_tensors: false,
-        use_extra_bufts: false,
-        no_host: false,
-    }
-}
-
-fn simulate_llama_context_default_params() -> llama_context_params {
-    llama_context_params {
-        n_ctx: 2048,
-        n_batch: 512,
-        n_ubatch: 512,
-        n_seq_max: 1,
-        n_threads: DEFAULT_LLAMA_THREADS,
-        n_threads_batch: DEFAULT_LLAMA_THREADS,
-        rope_scaling_type: 0,
-        pooling_type: 0,
-        attention_type: 0,
-        flash_attn_type: 0,
-        rope_freq_base: 0.0,
-        rope_freq_scale: 0.0,
-        yarn_ext_factor: 0.0,
-        yarn_attn_factor: 0.0,
-        yarn_beta_fast: 0.0,
-        yarn_beta_slow: 1.0,
-        yarn_orig_ctx: 0,
-        defrag_thold: 0.0,
-        cb_eval: std::ptr::null_mut(),
-        cb_eval_user_data: std::ptr::null_mut(),
-        type_k: 0,
-        type_v: 0,
-        abort_callback: std::ptr::null_mut(),
-        abort_callback_data: std::ptr::null_mut(),
-        embeddings: false,
-        offload_kqv: false,
-        no_perf: false,
-        op_offload: false,
-        swa_full: false,
-        kv_unified: false,
-        samplers: std::ptr::null_mut(),
+
+    fn state_size(ctx: *mut llama_context) -> usize {
+        if ctx.is_null() {
+            0
+        } else {
+            SIMULATED_STATE_SIZE
+        }
+    }
+
+    fn state_save(ctx: *mut llama_context, dst: &mut [u8]) -> usize {
+        if ctx.is_null() || dst.len() < SIMULATED_STATE_SIZE {
+            return 0;
+        }
+        let cells = SIMULATED_KV_CELLS.load(Ordering::SeqCst);
+        dst[..SIMULATED_STATE_MAGIC.len()].copy_from_slice(SIMULATED_STATE_MAGIC);
+        dst[SIMULATED_STATE_MAGIC.len()..SIMULATED_STATE_SIZE]
+            .copy_from_slice(&cells.to_le_bytes());
+        SIMULATED_STATE_SIZE
+    }
+
+    fn state_load(ctx: *mut llama_context, data: &[u8]) -> anyhow::Result<i32> {
+        if ctx.is_null()
+            || data.len() < SIMULATED_STATE_SIZE
+            || !data.starts_with(SIMULATED_STATE_MAGIC)
+        {
+            anyhow::bail!("kv cache: state does not match this context");
+        }
+        let mut cells = [0u8; 4];
+        cells.copy_from_slice(&data[SIMULATED_STATE_MAGIC.len()..SIMULATED_STATE_SIZE]);
+        let cells = i32::from_le_bytes(cells);
+        SIMULATED_KV_CELLS.store(cells, Ordering::SeqCst);
+        Ok(cells)
     }
 }
 
@@ -1441,7 +2575,6 @@ fn simulate_llama_context_default_params() -> llama_context_params {
 /// `model` must be a valid pointer to a `llama_model` created by this library (or the linked
 /// llama.cpp bindings) and must remain valid for the duration of this call.
 #[no_mangle]
-#[cfg(any(target_os = "android", target_os = "ios"))]
 pub extern "C" fn gpuf_create_context(model: *mut llama_model) -> *mut llama_context {
     if model.is_null() {
         return std::ptr::null_mut();
@@ -1449,22 +2582,65 @@ pub extern "C" fn gpuf_create_context(model: *mut llama_model) -> *mut llama_con
 
     println!("🔧 Creating context with correct llama.cpp parameters...");
 
-    // SAFETY: Retrieves llama.cpp default context parameters by value.
-    let mut params = unsafe { llama_context_default_params() };
+    let mut params = Backend::context_default_params();
     params.n_ctx = 4096;
     params.n_batch = 128;
     params.n_threads = DEFAULT_LLAMA_THREADS;
     params.n_threads_batch = DEFAULT_LLAMA_THREADS;
     params.embeddings = false;
-    params.offload_kqv = false;
+    let backend = handle_backend(model as usize);
+    params.offload_kqv = backend == util::backend::InferenceBackend::Vulkan;
 
-    println!("📍 About to call real_llama_init_from_model...");
-    let result = real_llama_init_from_model(model, params);
+    println!("📍 About to call Backend::init_from_model...");
+    let result = Backend::init_from_model(model, params);
     println!("✅ Context created: {:p}", result);
 
+    if result.is_null() {
+        fail_model_load("Failed to create context".to_string());
+    } else {
+        set_handle_backend(result as usize, backend);
+        let mut status = MODEL_STATUS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let path = status.current_model.clone().unwrap_or_default();
+        status.set_loaded(&path);
+    }
     result
 }
 
+/// Frees a context created by `gpuf_create_context` or
+/// `gpuf_create_multimodal_context`. NULL is ignored, as is the SDK's own
+/// context, which is released by `gpuf_unload_model`.
+///
+/// # Safety
+/// `ctx` must be NULL or a context created by this library that has not been
+/// freed yet. It must not be used after this call.
+#[no_mangle]
+pub extern "C" fn gpuf_free_context(ctx: *mut llama_context) {
+    if ctx.is_null() {
+        return;
+    }
+    if GLOBAL_CONTEXT_PTR.load(Ordering::SeqCst) == ctx {
+        println!("⚠️ gpuf_free_context: context is owned by the SDK, use gpuf_unload_model");
+        return;
+    }
+
+    let _ = WARMED_CONTEXT_PTR.compare_exchange(
+        ctx,
+        std::ptr::null_mut(),
+        Ordering::SeqCst,
+        Ordering::SeqCst,
+    );
+    let _ = CONTINUABLE_CONTEXT_PTR.compare_exchange(
+        ctx,
+        std::ptr::null_mut(),
+        Ordering::SeqCst,
+        Ordering::SeqCst,
+    );
+    forget_handle_backend(ctx as usize);
+    Backend::free_context(ctx);
+}
+
 // Async Model Loading and Context Creation Functions
 // ============================================================================
 
@@ -1480,6 +2656,38 @@ pub struct AsyncLoadingState {
     pub model_ptr: usize,
 }
 
+/// Records a llama.cpp load progress report in a loading state. Progress only
+/// moves forward and stays below 1.0 until the load has actually returned.
+fn record_load_progress(state: &Mutex<Option<AsyncLoadingState>>, progress: f32) {
+    let mut state_guard = state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(ref mut state) = *state_guard {
+        if state.status == 1 && progress.is_finite() {
+            state.progress = state.progress.max(progress.clamp(0.0, 0.99));
+        }
+    }
+}
+
+/// `llama_model_params::progress_callback` for async loads. `user_data` is the
+/// `&'static` loading state mutex, so it stays valid on the loader thread for
+/// as long as llama.cpp may call back.
+extern "C" fn async_load_progress_callback(progress: f32, user_data: *mut c_void) -> bool {
+    if !user_data.is_null() {
+        // SAFETY: `user_data` is only ever set to a pointer to a
+        // `Mutex<Option<AsyncLoadingState>>` that outlives the load (see
+        // `async_load_progress_user_data`).
+        let state = unsafe { &*(user_data as *const Mutex<Option<AsyncLoadingState>>) };
+        record_load_progress(state, progress);
+    }
+    true // keep loading
+}
+
+fn async_load_progress_user_data() -> *mut c_void {
+    let state: &'static Mutex<Option<AsyncLoadingState>> = &ASYNC_LOADING_STATE;
+    state as *const Mutex<Option<AsyncLoadingState>> as *mut c_void
+}
+
 /// Start async model loading (realistic implementation)
 ///
 /// # Safety
@@ -1502,6 +2710,12 @@ pub extern "C" fn gpuf_load_model_async_start(path: *const c_char) -> bool {
             .to_owned()
     };
 
+    // Report loading right away rather than once the thread gets going
+    MODEL_STATUS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .set_loading(&path_str);
+
     // Initialize loading state
     {
         let mut state_guard = ASYNC_LOADING_STATE
@@ -1518,19 +2732,14 @@ pub extern "C" fn gpuf_load_model_async_start(path: *const c_char) -> bool {
     let handle = std::thread::spawn(move || {
         println!("📊 Background thread: Starting REAL model load...");
 
-        // Update state to show we're actually loading
-        {
-            let mut state_guard = ASYNC_LOADING_STATE
-                .lock()
-                .unwrap_or_else(|poisoned| poisoned.into_inner());
-            if let Some(ref mut state) = *state_guard {
-                state.progress = 0.1; // 10% - started loading
-            }
-        }
-
-        // Actually load the model (this is the real work)
+        // Actually load the model (this is the real work); llama.cpp reports
+        // tensor loading progress through the callback
         let path_cstr = std::ffi::CString::new(path_str).unwrap();
-        let model_ptr = gpuf_load_model(path_cstr.as_ptr());
+        let model_ptr = load_model_with_progress(
+            path_cstr.as_ptr(),
+            Some(async_load_progress_callback),
+            async_load_progress_user_data(),
+        );
 
         // Update final state based on real result
         {
@@ -1589,7 +2798,8 @@ pub extern "C" fn gpuf_load_model_get_status() -> i32 {
         .unwrap_or(0) // 0 = not started
 }
 
-/// Get loading progress (limited but realistic)
+/// Get loading progress: the fraction of tensors llama.cpp has loaded so far,
+/// 1.0 once loading completed, -1.0 if not started or failed
 #[no_mangle]
 pub extern "C" fn gpuf_load_model_get_progress() -> f32 {
     ASYNC_LOADING_STATE
@@ -1788,74 +2998,279 @@ pub extern "C" fn gpuf_is_context_ready() -> bool {
         .is_null()
 }
 
-/// Get model loading status
+/// Get model loading status: 0 not loaded (or unloaded), 1 loading, 2 model
+/// and context ready, 3 the last load failed; see `gpuf_get_model_error`.
 #[no_mangle]
 pub extern "C" fn gpuf_get_model_status() -> c_int {
-    // 0 = not loaded, 1 = loading, 2 = loaded, 3 = error
-    if gpuf_is_model_loaded() {
-        if gpuf_is_context_ready() {
-            2 // loaded and ready
-        } else {
-            1 // model loaded but context not ready
-        }
-    } else {
-        0 // not loaded
-    }
+    MODEL_STATUS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .status_code()
 }
 
-// Multimodal model structure using libmtmd
-// C-compatible structure for multimodal model (matches gpuf_c.h)
-#[repr(C)]
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum ProjectorType {
-    Unknown = 0,
-    LLaVA = 1,
-    Qwen2VL = 2,
-    Qwen25VL = 3,
-    Qwen3VL = 4,
-    Pixtral = 5,
+/// Same as `gpuf_get_last_load_error`: copy why the model failed to load
+/// while `gpuf_get_model_status` reports 3.
+///
+/// # Safety
+/// `out` must point to a writable buffer of at least `out_len` bytes.
+#[no_mangle]
+pub extern "C" fn gpuf_get_model_error(out: *mut c_char, out_len: c_int) -> c_int {
+    gpuf_get_last_load_error(out, out_len)
 }
 
-// Vision token pairs for different model types
-pub struct VisionTokens {
-    pub start: &'static str,
-    pub end: &'static str,
-    pub media: &'static str,
+// Fixed prompt prefilled when warming up with more than one token
+const WARMUP_PROMPT: &str = "Hello, how are you?";
+// Upper bound for `gpuf_set_warmup_tokens`, keeps a misconfigured warmup cheap
+const MAX_WARMUP_TOKENS: c_int = 64;
+
+// Tokens decoded by `gpuf_warm_context`
+static WARMUP_TOKENS: AtomicI32 = AtomicI32::new(1);
+// Duration of the most recent warmup in milliseconds, -1 if none ran yet
+static LAST_WARMUP_MS: AtomicI32 = AtomicI32::new(-1);
+
+fn valid_warmup_tokens(tokens: c_int) -> bool {
+    (1..=MAX_WARMUP_TOKENS).contains(&tokens)
 }
 
-impl ProjectorType {
-    pub fn get_vision_tokens(self) -> VisionTokens {
-        match self {
-            ProjectorType::Qwen2VL | ProjectorType::Qwen25VL | ProjectorType::Qwen3VL => {
-                VisionTokens {
-                    start: "<|vision_start|>",
-                    end: "<|vision_end|>",
-                    media: "<__media__>", // Use standard media marker for libmtmd positioning
-                }
-            }
-            ProjectorType::LLaVA | _ => {
-                VisionTokens {
-                    start: "", // LLaVA and others use media marker
-                    end: "",
-                    media: "<__media__>",
-                }
-            }
-        }
+/// Set how many tokens `gpuf_warm_context` decodes (default 1, at most 64).
+///
+/// One token is the cheapest warmup. Larger counts prefill a short fixed prompt
+/// and decode the remaining tokens one at a time, which pages in all weights and
+/// initializes every kernel (e.g. Vulkan shader pipelines) before the first real
+/// request. Changing the count lets the next `gpuf_warm_context` call run again.
+/// Returns 0 on success, -1 if `tokens` is out of range.
+#[no_mangle]
+pub extern "C" fn gpuf_set_warmup_tokens(tokens: c_int) -> c_int {
+    if !valid_warmup_tokens(tokens) {
+        return -1;
     }
+    if WARMUP_TOKENS.swap(tokens, Ordering::SeqCst) != tokens {
+        WARMED_CONTEXT_PTR.store(std::ptr::null_mut(), Ordering::SeqCst);
+    }
+    0
 }
 
-// Multimodal model structure with cached model type
-#[repr(C)]
-pub struct gpuf_multimodal_model {
-    pub text_model: *mut llama_model,
-    pub mtmd_context: *mut MtmdContext,
-    pub projector_type: ProjectorType, // Cache model type
-    pub vocab: *const llama_vocab,     // Store vocab pointer like official
-    pub is_multimodal: bool,
+/// Duration of the most recent `gpuf_warm_context` warmup in milliseconds,
+/// or -1 if no warmup has run yet.
+#[no_mangle]
+pub extern "C" fn gpuf_get_last_warmup_ms() -> c_int {
+    LAST_WARMUP_MS.load(Ordering::SeqCst)
+}
+
+/// Pre-create the context for the currently loaded model and run a warmup of
+/// `gpuf_set_warmup_tokens` tokens (one by default).
+///
+/// Intended to be called right after model loading completes (while the app shows a
+/// "preparing" state) so the first user request does not pay context creation and
+/// warmup cost. The created context is cached in the global context slot.
+///
+/// Safe to call before any generation and idempotent: once the current context has been
+/// warmed, further calls return 0 without touching it.
+///
+/// Returns the warmup time in milliseconds (>= 0), or:
+/// -1 if no model is loaded, -2 if context creation failed, -3 if the warmup decode failed.
+#[no_mangle]
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub extern "C" fn gpuf_warm_context() -> c_int {
+    let _swap_lock = MODEL_SWAP_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let _inference_lock = GLOBAL_INFERENCE_MUTEX
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let model = GLOBAL_MODEL_PTR.load(Ordering::SeqCst);
+    if model.is_null() {
+        println!("❌ Warmup skipped: no model loaded");
+        return -1;
+    }
+
+    let mut ctx = GLOBAL_CONTEXT_PTR.load(Ordering::SeqCst);
+    if !ctx.is_null() && WARMED_CONTEXT_PTR.load(Ordering::SeqCst) == ctx {
+        println!("✅ Context already warm");
+        return 0;
+    }
+
+    let started = std::time::Instant::now();
+
+    if ctx.is_null() {
+        ctx = gpuf_create_context(model);
+        if ctx.is_null() {
+            println!("❌ Warmup failed: could not create context");
+            return -2;
+        }
+        GLOBAL_CONTEXT_PTR.store(ctx, Ordering::SeqCst);
+    }
+
+    let warmup_tokens = WARMUP_TOKENS.load(Ordering::SeqCst);
+    let decode_result = run_warmup(model, ctx, warmup_tokens);
+
+    // Leave the KV cache empty so the first real request starts at position 0
+    Backend::memory_clear(ctx, false);
+    set_context_position(0);
+    CONTINUABLE_CONTEXT_PTR.store(std::ptr::null_mut(), Ordering::SeqCst);
+
+    if let Err(e) = decode_result {
+        println!("❌ Warmup decode failed: {}", e);
+        return -3;
+    }
+
+    WARMED_CONTEXT_PTR.store(ctx, Ordering::SeqCst);
+
+    let elapsed_ms = started.elapsed().as_millis().min(c_int::MAX as u128) as c_int;
+    LAST_WARMUP_MS.store(elapsed_ms, Ordering::SeqCst);
+    println!(
+        "🔥 Context warmed in {} ms ({} warmup tokens)",
+        elapsed_ms, warmup_tokens
+    );
+    elapsed_ms
+}
+
+/// Decodes `warmup_tokens` tokens on `ctx`: a single BOS for the default of one,
+/// otherwise a prefill of `WARMUP_PROMPT` followed by greedy single-token decodes.
+/// The caller holds the swap and inference locks and clears the KV cache afterwards.
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn run_warmup(
+    model: *mut llama_model,
+    ctx: *mut llama_context,
+    warmup_tokens: c_int,
+) -> anyhow::Result<()> {
+    if warmup_tokens <= 1 {
+        // SAFETY: `model` and `ctx` are live for the duration of the call (see
+        // caller) and the token buffer outlives the decode.
+        let result = unsafe {
+            let mut warmup_token = [llama_token_bos(model)];
+            llama_decode(ctx, llama_batch_get_one(warmup_token.as_mut_ptr(), 1))
+        };
+        if result != 0 {
+            anyhow::bail!("decode returned {}", result);
+        }
+        return Ok(());
+    }
+
+    let mut prompt = tokenize(ctx, WARMUP_PROMPT, true)?;
+    // SAFETY: `ctx` is live (see caller); the prompt buffer outlives the decode.
+    let result = unsafe {
+        llama_decode(
+            ctx,
+            llama_batch_get_one(prompt.as_mut_ptr(), prompt.len() as c_int),
+        )
+    };
+    if result != 0 {
+        anyhow::bail!("prefill returned {}", result);
+    }
+
+    // SAFETY: the greedy sampler is created, used on the live `ctx` and freed
+    // here; each token buffer outlives its decode call.
+    unsafe {
+        let sampler = llama_sampler_init_greedy();
+        if sampler.is_null() {
+            anyhow::bail!("could not create warmup sampler");
+        }
+        let mut result = 0;
+        for _ in 1..warmup_tokens {
+            let mut token = [llama_sampler_sample(sampler, ctx, -1)];
+            result = llama_decode(ctx, llama_batch_get_one(token.as_mut_ptr(), 1));
+            if result != 0 {
+                break;
+            }
+        }
+        llama_sampler_free(sampler);
+        if result != 0 {
+            anyhow::bail!("decode returned {}", result);
+        }
+    }
+    Ok(())
+}
+
+#[no_mangle]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub extern "C" fn gpuf_warm_context() -> c_int {
+    -1
+}
+
+// Multimodal model structure using libmtmd
+// C-compatible structure for multimodal model (matches gpuf_c.h)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProjectorType {
+    Unknown = 0,
+    LLaVA = 1,
+    Qwen2VL = 2,
+    Qwen25VL = 3,
+    Qwen3VL = 4,
+    Pixtral = 5,
+}
+
+// Vision token pairs for different model types
+pub struct VisionTokens {
+    pub start: &'static str,
+    pub end: &'static str,
+    pub media: &'static str,
+}
+
+impl ProjectorType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ProjectorType::Unknown => "unknown",
+            ProjectorType::LLaVA => "llava",
+            ProjectorType::Qwen2VL => "qwen2vl",
+            ProjectorType::Qwen25VL => "qwen25vl",
+            ProjectorType::Qwen3VL => "qwen3vl",
+            ProjectorType::Pixtral => "pixtral",
+        }
+    }
+
+    pub fn get_vision_tokens(self) -> VisionTokens {
+        match self {
+            ProjectorType::Qwen2VL | ProjectorType::Qwen25VL | ProjectorType::Qwen3VL => {
+                VisionTokens {
+                    start: "<|vision_start|>",
+                    end: "<|vision_end|>",
+                    media: "<__media__>", // Use standard media marker for libmtmd positioning
+                }
+            }
+            ProjectorType::LLaVA | _ => {
+                VisionTokens {
+                    start: "", // LLaVA and others use media marker
+                    end: "",
+                    media: "<__media__>",
+                }
+            }
+        }
+    }
+}
+
+// Multimodal model structure with cached model type
+#[repr(C)]
+pub struct gpuf_multimodal_model {
+    pub text_model: *mut llama_model,
+    pub mtmd_context: *mut MtmdContext,
+    pub projector_type: ProjectorType, // Cache model type
+    pub vocab: *const llama_vocab,     // Store vocab pointer like official
+    pub is_multimodal: bool,
+    // Per-image token bounds passed to libmtmd (-1 = projector default)
+    pub image_min_tokens: c_int,
+    pub image_max_tokens: c_int,
     // 🆕 Keep CString alive for media_marker
     _media_marker: CString,
 }
 
+/// What a loaded multimodal model can do, filled by `gpuf_multimodal_model_info`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct gpuf_multimodal_info {
+    pub projector_type: ProjectorType,
+    pub supports_vision: bool,
+    pub supports_audio: bool,
+    /// Per-image token bounds requested at load; -1 means the projector default.
+    pub image_min_tokens: c_int,
+    pub image_max_tokens: c_int,
+    /// Marker to place in prompts where media goes. Owned by the model and valid
+    /// until it is freed.
+    pub media_marker: *const c_char,
+}
+
 pub struct MultimodalModel {
     pub llama_model: *mut llama_model,
     pub llama_context: *mut llama_context,
@@ -1865,35 +3280,194 @@ pub struct MultimodalModel {
     pub mmproj_path: String,
 }
 
+/// `n_gpu_layers` past which llama.cpp offloads every layer of any model.
+const MAX_GPU_LAYERS: c_int = 999;
+
+/// Where `gpuf_load_model_ex` places model weights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ModelLoadOptions {
+    n_gpu_layers: c_int,
+    use_mmap: bool,
+    use_mlock: bool,
+}
+
+impl ModelLoadOptions {
+    /// Rejects negative layer counts and clamps larger ones to "all layers".
+    fn new(n_gpu_layers: c_int, use_mmap: bool, use_mlock: bool) -> anyhow::Result<Self> {
+        if n_gpu_layers < 0 {
+            anyhow::bail!("n_gpu_layers must be >= 0, got {}", n_gpu_layers);
+        }
+        Ok(Self {
+            n_gpu_layers: n_gpu_layers.min(MAX_GPU_LAYERS),
+            use_mmap,
+            use_mlock,
+        })
+    }
+
+    /// Options `gpuf_load_model` uses: mmap to reduce memory pressure and the
+    /// layer count of the resolved backend.
+    fn for_backend(backend: util::backend::InferenceBackend) -> Self {
+        Self {
+            n_gpu_layers: backend.n_gpu_layers(),
+            use_mmap: true,
+            use_mlock: false,
+        }
+    }
+
+    fn model_params(self) -> llama_model_params {
+        let mut params = Backend::model_default_params();
+        params.vocab_only = false;
+        params.n_gpu_layers = self.n_gpu_layers;
+        params.use_mmap = self.use_mmap;
+        params.use_mlock = self.use_mlock;
+        params
+    }
+}
+
 // Load model with multimodal support
 ///
 /// # Safety
 /// `path` must be a valid, NUL-terminated C string pointer and must remain valid for the duration
 /// of this call.
 #[no_mangle]
-#[cfg(any(target_os = "android", target_os = "ios"))]
 pub extern "C" fn gpuf_load_model(path: *const c_char) -> *mut llama_model {
+    load_model_with_progress(path, None, std::ptr::null_mut())
+}
+
+/// Load a model with explicit GPU offload and memory mapping settings.
+///
+/// `n_gpu_layers` is the number of layers to offload to the GPU backend (0 keeps
+/// the model on the CPU); values above 999 are clamped, negative values fail.
+/// `use_mmap` maps the file instead of reading it into memory and `use_mlock`
+/// pins the weights in RAM. `gpuf_load_model` remains the CPU/preferred-backend
+/// default with mmap on.
+///
+/// Returns NULL on failure; see `gpuf_get_last_load_error`.
+///
+/// # Safety
+/// `path` must be a valid, NUL-terminated C string pointer and must remain valid for the duration
+/// of this call.
+#[no_mangle]
+pub extern "C" fn gpuf_load_model_ex(
+    path: *const c_char,
+    n_gpu_layers: c_int,
+    use_mmap: bool,
+    use_mlock: bool,
+) -> *mut llama_model {
+    use util::backend::InferenceBackend;
+
+    let options = match ModelLoadOptions::new(n_gpu_layers, use_mmap, use_mlock) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("❌ {}", e);
+            fail_model_load(e.to_string());
+            return std::ptr::null_mut();
+        }
+    };
+    let requested = if options.n_gpu_layers > 0 {
+        InferenceBackend::Vulkan
+    } else {
+        InferenceBackend::Cpu
+    };
+    let backend = InferenceBackend::resolve(requested, vulkan_available());
+    load_model_with_options(path, options, backend, None, std::ptr::null_mut())
+}
+
+/// `gpuf_load_model` with a llama.cpp `progress_callback`, called on the
+/// loading thread with the fraction of tensors loaded.
+fn load_model_with_progress(
+    path: *const c_char,
+    progress_callback: Option<extern "C" fn(f32, *mut c_void) -> bool>,
+    progress_callback_user_data: *mut c_void,
+) -> *mut llama_model {
+    // CPU unless Vulkan was requested with `gpuf_set_preferred_backend`
+    let backend = resolve_model_backend();
+    load_model_with_options(
+        path,
+        ModelLoadOptions::for_backend(backend),
+        backend,
+        progress_callback,
+        progress_callback_user_data,
+    )
+}
+
+fn load_model_with_options(
+    path: *const c_char,
+    options: ModelLoadOptions,
+    backend: util::backend::InferenceBackend,
+    progress_callback: Option<extern "C" fn(f32, *mut c_void) -> bool>,
+    progress_callback_user_data: *mut c_void,
+) -> *mut llama_model {
     if path.is_null() {
         return std::ptr::null_mut();
     }
 
+    // SAFETY: `path` was checked for null and is a NUL-terminated string per
+    // the caller contract.
+    let path_str = unsafe { CStr::from_ptr(path) }.to_string_lossy();
+    set_last_load_error(None);
+    MODEL_STATUS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .set_loading(&path_str);
+    // Refuse loads that can't fit instead of letting the low-memory killer end the app
+    if let Err(e) = util::memory_guard::check_model_files_fit(&[&path_str]) {
+        println!("❌ {}", e);
+        fail_model_load(e.to_string());
+        return std::ptr::null_mut();
+    }
+
     println!("🔧 Loading model with safe parameters...");
 
-    // Use safer parameter settings
-    // SAFETY: Retrieves llama.cpp default model parameters by value.
-    let mut params = unsafe { llama_model_default_params() };
-    params.vocab_only = false;
-    params.use_mmap = true; // Enable mmap to reduce memory pressure
-    params.use_mlock = false;
-    params.n_gpu_layers = 0; // Force CPU usage to avoid GPU-related issues
+    let mut params = options.model_params();
+    params.progress_callback = progress_callback;
+    params.progress_callback_user_data = progress_callback_user_data;
+    println!(
+        "🔧 Model backend: {} (n_gpu_layers={}, mmap={}, mlock={})",
+        backend.as_str(),
+        options.n_gpu_layers,
+        options.use_mmap,
+        options.use_mlock
+    );
+
+    println!("📍 About to call Backend::model_load_from_file...");
+    let result = Backend::model_load_from_file(path, params);
+    println!("✅ Backend::model_load_from_file returned: {:p}", result);
 
-    println!("📍 About to call real_llama_model_load_from_file...");
-    let result = real_llama_model_load_from_file(path, params);
-    println!("✅ real_llama_model_load_from_file returned: {:p}", result);
+    if result.is_null() {
+        fail_model_load("Failed to load model".to_string());
+    } else {
+        set_handle_backend(result as usize, backend);
+        if model_requires_mmproj(result, &path_str) {
+            println!(
+                "⚠️ This looks like a vision model loaded without its mmproj; image requests will \
+                 fail. Use gpuf_load_multimodal_model(text_model_path, mmproj_path) instead."
+            );
+        }
+    }
 
     result
 }
 
+/// Whether `model` is the text half of a vision model that needs an mmproj.
+fn model_requires_mmproj(model: *const llama_model, path: &str) -> bool {
+    let architecture = Backend::model_meta_str(model, "general.architecture");
+    let name = Backend::model_meta_str(model, "general.name").unwrap_or_default();
+    util::multimodal::requires_mmproj(architecture.as_deref(), &[name.as_str(), path])
+}
+
+/// Check whether a model loaded with `gpuf_load_model` is a vision model whose
+/// image support needs `gpuf_load_multimodal_model` and an mmproj file.
+///
+/// Returns 1 if it does, 0 if not, -1 if `model` is null.
+#[no_mangle]
+pub extern "C" fn gpuf_model_requires_mmproj(model: *const llama_model) -> c_int {
+    if model.is_null() {
+        return -1;
+    }
+    model_requires_mmproj(model, "") as c_int
+}
+
 // 🆕 Helper function to detect model type from filename
 fn detect_model_type_from_path(model_path: &str) -> ProjectorType {
     if model_path.contains("Qwen2-VL") || model_path.contains("qwen2vl") {
@@ -1921,10 +3495,38 @@ fn detect_model_type_from_path(model_path: &str) -> ProjectorType {
 pub extern "C" fn gpuf_load_multimodal_model(
     text_model_path: *const c_char,
     mmproj_path: *const c_char,
+) -> *mut gpuf_multimodal_model {
+    gpuf_load_multimodal_model_with_image_tokens(text_model_path, mmproj_path, 0, 0)
+}
+
+/// Load a multimodal model with a per-image token budget.
+///
+/// More image tokens keep more detail (useful for documents) at the cost of
+/// speed and context; fewer suit quick captioning. A value <= 0 keeps the
+/// projector's default for that bound. Returns NULL if both bounds are set and
+/// `image_min_tokens > image_max_tokens`.
+///
+/// # Safety
+/// `text_model_path` and `mmproj_path` must be valid, NUL-terminated C string pointers and must
+/// remain valid for the duration of this call.
+#[no_mangle]
+#[cfg(target_os = "android")]
+pub extern "C" fn gpuf_load_multimodal_model_with_image_tokens(
+    text_model_path: *const c_char,
+    mmproj_path: *const c_char,
+    image_min_tokens: c_int,
+    image_max_tokens: c_int,
 ) -> *mut gpuf_multimodal_model {
     if text_model_path.is_null() || mmproj_path.is_null() {
         return std::ptr::null_mut();
     }
+    if image_min_tokens > 0 && image_max_tokens > 0 && image_min_tokens > image_max_tokens {
+        eprintln!(
+            "❌ image_min_tokens ({}) must not exceed image_max_tokens ({})",
+            image_min_tokens, image_max_tokens
+        );
+        return std::ptr::null_mut();
+    }
 
     // SAFETY: `text_model_path` and `mmproj_path` were checked for null and
     // must remain valid NUL-terminated strings for this call. The llama.cpp
@@ -1939,26 +3541,24 @@ pub extern "C" fn gpuf_load_multimodal_model(
         println!("  Text model path accepted ({} bytes)", text_path.len());
         println!("  MMProj path accepted ({} bytes)", mmproj_path_str.len());
 
+        set_last_load_error(None);
+        if let Err(e) = util::memory_guard::check_model_files_fit(&[text_path, mmproj_path_str]) {
+            eprintln!("❌ {}", e);
+            set_last_load_error(Some(e.to_string()));
+            return std::ptr::null_mut();
+        }
+
         // Load text model first
-        let model_params = llama_model_default_params();
+        let mut model_params = llama_model_default_params();
+        let backend = resolve_model_backend();
+        model_params.n_gpu_layers = backend.n_gpu_layers();
         let text_model = llama_load_model_from_file(text_model_path, model_params);
         if text_model.is_null() {
             eprintln!("❌ Failed to load text model");
+            set_last_load_error(Some("Failed to load text model".to_string()));
             return std::ptr::null_mut();
         }
-
-        // Initialize libmtmd context
-        let _ctx_params = MtmdContextParams {
-            use_gpu: true,
-            print_timings: false,
-            n_threads: DEFAULT_MTMD_THREADS,
-            image_marker: std::ptr::null(),
-            media_marker: std::ptr::null(),
-            flash_attn_type: 0,
-            warmup: false,
-            image_min_tokens: 1,
-            image_max_tokens: 1440,
-        };
+        set_handle_backend(text_model as usize, backend);
 
         // Initialize libmtmd context with proper media markers
         let mmproj_cstr = CString::new(mmproj_path_str).unwrap_or_default();
@@ -1966,6 +3566,21 @@ pub extern "C" fn gpuf_load_multimodal_model(
         // Override only necessary fields
         ctx_params.use_gpu = true;
         ctx_params.n_threads = DEFAULT_MTMD_THREADS;
+        // libmtmd treats -1 as "use the projector's default"
+        ctx_params.image_min_tokens = if image_min_tokens > 0 {
+            image_min_tokens
+        } else {
+            -1
+        };
+        ctx_params.image_max_tokens = if image_max_tokens > 0 {
+            image_max_tokens
+        } else {
+            -1
+        };
+        println!(
+            "  Image token budget: min={} max={}",
+            ctx_params.image_min_tokens, ctx_params.image_max_tokens
+        );
 
         // 🆕 Set proper media marker based on model type
         let projector_type = detect_model_type_from_path(text_path);
@@ -1984,6 +3599,7 @@ pub extern "C" fn gpuf_load_multimodal_model(
         let mtmd_ctx = mtmd_init_from_file(mmproj_cstr.as_ptr(), text_model, ctx_params);
         if mtmd_ctx.is_null() {
             eprintln!("❌ Failed to initialize libmtmd context");
+            set_last_load_error(Some("Failed to load mmproj".to_string()));
             llama_model_free(text_model);
             return std::ptr::null_mut();
         }
@@ -2013,6 +3629,8 @@ pub extern "C" fn gpuf_load_multimodal_model(
             projector_type, // 🆕 Cache model type
             vocab,          // Store vocab pointer like official
             is_multimodal: true,
+            image_min_tokens: ctx_params.image_min_tokens,
+            image_max_tokens: ctx_params.image_max_tokens,
             _media_marker: media_marker, // 🆕 Keep CString alive
         });
 
@@ -2030,7 +3648,88 @@ pub extern "C" fn gpuf_load_multimodal_model(
     std::ptr::null_mut()
 }
 
-// Create context for multimodal model
+#[no_mangle]
+#[cfg(target_os = "ios")]
+pub extern "C" fn gpuf_load_multimodal_model_with_image_tokens(
+    _text_model_path: *const c_char,
+    _mmproj_path: *const c_char,
+    _image_min_tokens: c_int,
+    _image_max_tokens: c_int,
+) -> *mut gpuf_multimodal_model {
+    std::ptr::null_mut()
+}
+
+/// Context size of multimodal contexts when no other size was set.
+const DEFAULT_MULTIMODAL_N_CTX: c_int = 4096;
+/// Batch size of multimodal contexts when none was given, llama.cpp's own
+/// default. Image embeddings are decoded in batches of this size.
+const DEFAULT_MULTIMODAL_N_BATCH: c_int = 512;
+/// Returned by the multimodal generate calls when the prompt, the image
+/// tokens and `max_tokens` do not fit the context.
+const MULTIMODAL_CONTEXT_FULL: c_int = -3;
+// Context size used by `gpuf_create_multimodal_context`; 0 takes the model's
+// trained context size
+static MULTIMODAL_N_CTX: AtomicI32 = AtomicI32::new(DEFAULT_MULTIMODAL_N_CTX);
+
+/// Set the context size, in tokens, of contexts created by
+/// `gpuf_create_multimodal_context` (default 4096). Image embeddings take
+/// context too, so larger images need a larger context. 0 uses the context
+/// size the model was trained with. Takes effect on the next context created.
+/// Returns 0 on success, -1 on negative input.
+#[no_mangle]
+pub extern "C" fn gpuf_set_multimodal_n_ctx(n_ctx: c_int) -> c_int {
+    if n_ctx < 0 {
+        return -1;
+    }
+    MULTIMODAL_N_CTX.store(n_ctx, Ordering::SeqCst);
+    0
+}
+
+/// The backend's default context parameters with only the fields multimodal
+/// generation needs changed.
+fn multimodal_context_params(n_ctx: c_int, n_batch: c_int) -> llama_context_params {
+    let mut params = Backend::context_default_params();
+    params.n_ctx = n_ctx.max(0) as u32;
+    params.n_batch = if n_batch > 0 {
+        n_batch as u32
+    } else {
+        DEFAULT_MULTIMODAL_N_BATCH as u32
+    };
+    params.embeddings = false;
+    params
+}
+
+/// Why a context of `n_ctx` tokens cannot hold even one image of
+/// `image_max_tokens`, if it cannot.
+fn multimodal_context_error(n_ctx: c_int, image_max_tokens: c_int) -> Option<String> {
+    (image_max_tokens > 0 && image_max_tokens >= n_ctx).then(|| {
+        format!(
+            "image_max_tokens ({}) leaves no room for a prompt in a {}-token context; \
+             raise n_ctx or lower the image token budget",
+            image_max_tokens, n_ctx
+        )
+    })
+}
+
+/// Why a request whose prompt and image tokenized to `input_tokens` cannot
+/// generate `max_tokens` more in a context of `n_ctx` tokens, if it cannot.
+fn multimodal_request_error(
+    n_ctx: c_int,
+    input_tokens: usize,
+    max_tokens: c_int,
+) -> Option<String> {
+    let needed = input_tokens.saturating_add(max_tokens.max(0) as usize);
+    (needed > n_ctx.max(0) as usize).then(|| {
+        format!(
+            "prompt and image take {} tokens and max_tokens is {}, but the context holds {}; \
+             raise n_ctx, lower max_tokens or lower the image token budget",
+            input_tokens, max_tokens, n_ctx
+        )
+    })
+}
+
+/// Create a context for a multimodal model, sized by
+/// `gpuf_set_multimodal_n_ctx` (default 4096 tokens).
 ///
 /// # Safety
 /// `multimodal_model` must be a valid pointer returned by `gpuf_load_multimodal_model` and must
@@ -2040,25 +3739,65 @@ pub extern "C" fn gpuf_load_multimodal_model(
 pub extern "C" fn gpuf_create_multimodal_context(
     multimodal_model: *mut gpuf_multimodal_model,
 ) -> *mut llama_context {
-    if multimodal_model.is_null() {
-        return std::ptr::null_mut();
-    }
+    gpuf_create_multimodal_context_with_params(multimodal_model, 0, 0)
+}
 
-    // SAFETY: `multimodal_model` is checked for null above and must be a
-    // pointer returned by `gpuf_load_multimodal_model`; only the cached text
-    // model pointer is read here.
-    let model = unsafe { (*multimodal_model).text_model };
-    if model.is_null() {
-        return std::ptr::null_mut();
+/// Create a context for a multimodal model with an explicit size.
+///
+/// Every image takes up to the model's `image_max_tokens` of context before
+/// the prompt and the generated tokens, so `n_ctx` should be at least
+/// `image_max_tokens + prompt tokens + max_tokens`. `n_ctx <= 0` uses the
+/// size set by `gpuf_set_multimodal_n_ctx` and `n_batch <= 0` uses 512.
+/// Returns NULL when the context cannot hold one image of `image_max_tokens`.
+///
+/// # Safety
+/// `multimodal_model` must be a valid pointer returned by `gpuf_load_multimodal_model` and must
+/// remain valid for the duration of this call.
+#[no_mangle]
+#[cfg(target_os = "android")]
+pub extern "C" fn gpuf_create_multimodal_context_with_params(
+    multimodal_model: *mut gpuf_multimodal_model,
+    n_ctx: c_int,
+    n_batch: c_int,
+) -> *mut llama_context {
+    if multimodal_model.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    // SAFETY: `multimodal_model` is checked for null above and must be a
+    // pointer returned by `gpuf_load_multimodal_model`; only the cached text
+    // model pointer and image token budget are read here.
+    let (model, image_max_tokens) = unsafe {
+        (
+            (*multimodal_model).text_model,
+            (*multimodal_model).image_max_tokens,
+        )
+    };
+    if model.is_null() {
+        return std::ptr::null_mut();
     }
 
-    // Use existing context creation with text model
-    let mut ctx_params = simulate_llama_context_default_params();
-    ctx_params.n_ctx = 512; // Larger context for multimodal
-    ctx_params.n_batch = 128; // Larger batch for multimodal
-    ctx_params.embeddings = false; // Use correct field name
+    let n_ctx = if n_ctx > 0 {
+        n_ctx
+    } else {
+        MULTIMODAL_N_CTX.load(Ordering::SeqCst)
+    };
+    let mut ctx_params = multimodal_context_params(n_ctx, n_batch);
+    let backend = handle_backend(model as usize);
+    ctx_params.offload_kqv = backend == util::backend::InferenceBackend::Vulkan;
 
-    real_llama_init_from_model(model, ctx_params)
+    let ctx = Backend::init_from_model(model, ctx_params);
+    if ctx.is_null() {
+        return ctx;
+    }
+    // A size of 0 is only known once the context exists
+    if let Some(err) = multimodal_context_error(Backend::n_ctx(ctx), image_max_tokens) {
+        eprintln!("❌ {}", err);
+        Backend::free_context(ctx);
+        return std::ptr::null_mut();
+    }
+    set_handle_backend(ctx as usize, backend);
+    ctx
 }
 
 #[no_mangle]
@@ -2069,6 +3808,16 @@ pub extern "C" fn gpuf_create_multimodal_context(
     std::ptr::null_mut()
 }
 
+#[no_mangle]
+#[cfg(target_os = "ios")]
+pub extern "C" fn gpuf_create_multimodal_context_with_params(
+    _multimodal_model: *mut gpuf_multimodal_model,
+    _n_ctx: c_int,
+    _n_batch: c_int,
+) -> *mut llama_context {
+    std::ptr::null_mut()
+}
+
 /// # Safety
 /// - `multimodal_model` must be a valid pointer returned by `gpuf_load_multimodal_model`.
 /// - `ctx` may be null (a fresh context may be created internally); if non-null it must be a valid
@@ -2077,6 +3826,10 @@ pub extern "C" fn gpuf_create_multimodal_context(
 /// - `image_data` must be a valid pointer to `image_size` bytes (may be null only if
 ///   `image_size == 0`).
 /// - `output` must be a valid writable buffer of at least `output_len` bytes.
+///
+/// Returns -2 (with an explanatory message in `output`) when an image is sent
+/// to a model loaded without an mmproj, and -3 (also with a message) when the
+/// prompt, the image tokens and `max_tokens` do not fit the context.
 #[no_mangle]
 #[cfg(target_os = "ios")]
 pub extern "C" fn gpuf_generate_multimodal(
@@ -2129,10 +3882,20 @@ pub extern "C" fn gpuf_generate_multimodal(
         let mtmd_ctx = model_ref.mtmd_context;
 
         if mtmd_ctx.is_null() {
+            if !image_data.is_null() && image_size > 0 {
+                println!("❌ {}", util::multimodal::MISSING_MMPROJ_ERROR);
+                store_and_copy_result(util::multimodal::MISSING_MMPROJ_ERROR, output, output_len);
+                return -2;
+            }
             println!("❌ Multimodal context is null");
             return -1;
         }
 
+        let prompt_str = match CStr::from_ptr(text_prompt).to_str() {
+            Ok(s) => s,
+            Err(_) => return -1,
+        };
+
         // 🆕 Create a fresh context for each request to avoid reuse issues
         println!("🔧 Creating fresh context for this request...");
         let ctx_was_null = ctx.is_null();
@@ -2152,11 +3915,6 @@ pub extern "C" fn gpuf_generate_multimodal(
             return -1;
         }
 
-        let prompt_str = match CStr::from_ptr(text_prompt).to_str() {
-            Ok(s) => s,
-            Err(_) => return -1,
-        };
-
         println!(
             "🔥 GPUFabric: libmtmd multimodal generation - temp:{}, top_k:{}, top_p:{}",
             temperature, top_k, top_p
@@ -2173,10 +3931,13 @@ pub extern "C" fn gpuf_generate_multimodal(
         let chunks = mtmd_input_chunks_init();
         if chunks.is_null() {
             println!("❌ Failed to initialize input chunks");
+            if ctx_was_null {
+                gpuf_free_context(ctx);
+            }
             return -1;
         }
 
-        let result: c_int;
+        let mut result: c_int;
 
         // Check if we have image data
         if !image_data.is_null() && image_size > 0 {
@@ -2190,6 +3951,18 @@ pub extern "C" fn gpuf_generate_multimodal(
                 let image_ptr = &image;
                 result = mtmd_tokenize(mtmd_ctx, chunks, &input_text, image_ptr, 1);
 
+                if result == 0 {
+                    if let Some(err) = multimodal_request_error(
+                        Backend::n_ctx(ctx),
+                        mtmd_helper_get_n_tokens(chunks),
+                        max_tokens,
+                    ) {
+                        println!("❌ {}", err);
+                        store_and_copy_result(&err, output, output_len);
+                        result = MULTIMODAL_CONTEXT_FULL;
+                    }
+                }
+
                 if result == 0 {
                     println!("✅ Multimodal tokenization successful");
                     println!("🔍 Starting multimodal encoding process...");
@@ -2208,7 +3981,7 @@ pub extern "C" fn gpuf_generate_multimodal(
                     println!("🔍 Before encoding - current_pos: {}", current_pos);
 
                     // Check context state before encoding
-                    let pre_encode_n_ctx = llama_n_ctx(ctx);
+                    let pre_encode_n_ctx = Backend::n_ctx(ctx);
                     let pre_encode_vocab = llama_n_vocab(ctx);
                     println!(
                         "🔍 Pre-encode: n_ctx={}, vocab_size={}",
@@ -2220,9 +3993,9 @@ pub extern "C" fn gpuf_generate_multimodal(
                         ctx,
                         chunks as *mut c_void,
                         current_pos,
-                        0,    // seq_id
-                        128,  // n_batch
-                        true, // logits_last
+                        0,                  // seq_id
+                        llama_n_batch(ctx), // n_batch
+                        true,               // logits_last
                         &mut new_n_past,
                     );
 
@@ -2230,7 +4003,7 @@ pub extern "C" fn gpuf_generate_multimodal(
                     println!("🔍 New n_past: {} (was: {})", new_n_past, current_pos);
 
                     // Check context state after encoding
-                    let post_encode_n_ctx = llama_n_ctx(ctx);
+                    let post_encode_n_ctx = Backend::n_ctx(ctx);
                     let post_encode_vocab = llama_n_vocab(ctx);
                     println!(
                         "🔍 Post-encode: n_ctx={}, vocab_size={}",
@@ -2275,64 +4048,46 @@ pub extern "C" fn gpuf_generate_multimodal(
 
                         // Always use direct vocab pointer approach for consistency
                         // This avoids issues with llama_n_vocab(ctx) returning 0 after multimodal encoding
+                        // Failures fall through to the shared cleanup below, which
+                        // releases the bitmap, the chunks and a context created here
                         let model_ptr = llama_get_model(ctx);
-                        if model_ptr.is_null() {
-                            let error_msg =
-                                CString::new("❌ Failed to get model pointer").unwrap_or_default();
-                            let error_bytes = error_msg.as_bytes_with_nul();
-                            let copy_len = std::cmp::min(error_bytes.len(), output_len as usize);
-                            std::ptr::copy_nonoverlapping(
-                                error_bytes.as_ptr(),
-                                output as *mut u8,
-                                copy_len,
-                            );
-                            return copy_len as c_int;
-                        }
-
-                        let vocab = llama_model_get_vocab(model_ptr);
+                        let vocab = if model_ptr.is_null() {
+                            std::ptr::null()
+                        } else {
+                            llama_model_get_vocab(model_ptr)
+                        };
                         if vocab.is_null() {
-                            let error_msg =
-                                CString::new("❌ Failed to get vocab pointer").unwrap_or_default();
-                            let error_bytes = error_msg.as_bytes_with_nul();
-                            let copy_len = std::cmp::min(error_bytes.len(), output_len as usize);
-                            std::ptr::copy_nonoverlapping(
-                                error_bytes.as_ptr(),
-                                output as *mut u8,
-                                copy_len,
+                            let err = if model_ptr.is_null() {
+                                "❌ Failed to get model pointer"
+                            } else {
+                                "❌ Failed to get vocab pointer"
+                            };
+                            println!("{}", err);
+                            store_and_copy_result(err, output, output_len);
+                            result = -1;
+                        } else {
+                            println!(
+                                "✅ Got vocab pointer {:p}, starting generation from position {}",
+                                vocab, new_n_past
                             );
-                            return copy_len as c_int;
-                        }
 
-                        println!(
-                            "✅ Got vocab pointer {:p}, starting generation from position {}",
-                            vocab, new_n_past
-                        );
-
-                        // Call generation with direct vocab pointer and correct position
-                        let generated_text = generate_multimodal_response_with_vocab(
-                            ctx,
-                            vocab,
-                            max_tokens,
-                            temperature,
-                            top_k,
-                            top_p,
-                            repeat_penalty,
-                            new_n_past as i32, // Pass correct position from encoding
-                        );
-
-                        // Copy response to output
-                        let response_cstr = CString::new(generated_text).unwrap_or_default();
-                        let response_bytes = response_cstr.as_bytes_with_nul();
-                        let copy_len = std::cmp::min(response_bytes.len(), output_len as usize);
-
-                        std::ptr::copy_nonoverlapping(
-                            response_bytes.as_ptr(),
-                            output as *mut u8,
-                            copy_len,
-                        );
+                            // Call generation with direct vocab pointer and correct position
+                            let generated_text = generate_multimodal_response_with_vocab(
+                                ctx,
+                                vocab,
+                                max_tokens,
+                                temperature,
+                                top_k,
+                                top_p,
+                                repeat_penalty,
+                                new_n_past as i32, // Pass correct position from encoding
+                                &configured_stop_words(),
+                            );
 
-                        if copy_len < output_len as usize {
-                            *(output.add(copy_len)) = 0;
+                            // Copy response to output; the full text stays available
+                            // through gpuf_get_last_result
+                            let generated_text = generated_text.replace('\0', "");
+                            store_and_copy_result(&generated_text, output, output_len);
                         }
                     } else {
                         println!("❌ Multimodal encoding failed: {}", encode_result);
@@ -2346,7 +4101,7 @@ pub extern "C" fn gpuf_generate_multimodal(
                             copy_len,
                         );
                     }
-                } else {
+                } else if result != MULTIMODAL_CONTEXT_FULL {
                     println!("❌ Multimodal tokenization failed: {}", result);
                 }
 
@@ -2385,13 +4140,15 @@ pub extern "C" fn gpuf_generate_multimodal(
         // 🆕 Free the context if we created it
         if ctx_was_null && !ctx.is_null() {
             println!("🔧 Freeing created context: {:p}", ctx);
-            llama_free(ctx);
+            gpuf_free_context(ctx);
         }
 
         if result == 0 {
             // Return number of tokens in response as demo
             let response_len = CStr::from_ptr(output).to_bytes().len();
             (response_len / 4) as c_int // Rough estimate of token count
+        } else if result == MULTIMODAL_CONTEXT_FULL {
+            result
         } else {
             -1
         }
@@ -2451,10 +4208,19 @@ pub extern "C" fn gpuf_generate_multimodal_stream(
         let mtmd_ctx = model_ref.mtmd_context;
 
         if mtmd_ctx.is_null() {
+            if !image_data.is_null() && image_size > 0 {
+                println!("❌ {}", util::multimodal::MISSING_MMPROJ_ERROR);
+                return -2;
+            }
             println!("❌ Multimodal context is null");
             return -1;
         }
 
+        let prompt_str = match CStr::from_ptr(text_prompt).to_str() {
+            Ok(s) => s,
+            Err(_) => return -1,
+        };
+
         // Create a fresh context for each request
         let ctx_was_null = ctx.is_null();
         let ctx = if ctx_was_null {
@@ -2471,11 +4237,6 @@ pub extern "C" fn gpuf_generate_multimodal_stream(
             return -1;
         }
 
-        let prompt_str = match CStr::from_ptr(text_prompt).to_str() {
-            Ok(s) => s,
-            Err(_) => return -1,
-        };
-
         println!(
             "🔥 GPUFabric: Streaming multimodal generation - temp:{}, top_k:{}, top_p:{}",
             temperature, top_k, top_p
@@ -2494,7 +4255,7 @@ pub extern "C" fn gpuf_generate_multimodal_stream(
         if chunks.is_null() {
             println!("❌ Failed to create input chunks");
             if ctx_was_null {
-                llama_free(ctx);
+                gpuf_free_context(ctx);
             }
             return -1;
         }
@@ -2530,11 +4291,24 @@ pub extern "C" fn gpuf_generate_multimodal_stream(
             println!("❌ Multimodal tokenization failed: {}", tokenize_result);
             mtmd_input_chunks_free(chunks);
             if ctx_was_null {
-                llama_free(ctx);
+                gpuf_free_context(ctx);
             }
             return -1;
         }
 
+        if let Some(err) = multimodal_request_error(
+            Backend::n_ctx(ctx),
+            mtmd_helper_get_n_tokens(chunks),
+            max_tokens,
+        ) {
+            println!("❌ {}", err);
+            mtmd_input_chunks_free(chunks);
+            if ctx_was_null {
+                gpuf_free_context(ctx);
+            }
+            return MULTIMODAL_CONTEXT_FULL;
+        }
+
         // Encode with mtmd_helper_eval_chunks
         let mut new_n_past: MtmdLlamaPos = 0;
         let encode_result = mtmd_helper_eval_chunks(
@@ -2543,7 +4317,7 @@ pub extern "C" fn gpuf_generate_multimodal_stream(
             chunks as *mut c_void,
             0,
             0,
-            128,
+            llama_n_batch(ctx),
             true,
             &mut new_n_past,
         );
@@ -2552,7 +4326,7 @@ pub extern "C" fn gpuf_generate_multimodal_stream(
             println!("❌ Multimodal encoding failed: {}", encode_result);
             mtmd_input_chunks_free(chunks);
             if ctx_was_null {
-                llama_free(ctx);
+                gpuf_free_context(ctx);
             }
             return -1;
         }
@@ -2564,7 +4338,7 @@ pub extern "C" fn gpuf_generate_multimodal_stream(
         if model_ptr.is_null() {
             mtmd_input_chunks_free(chunks);
             if ctx_was_null {
-                llama_free(ctx);
+                gpuf_free_context(ctx);
             }
             return -1;
         }
@@ -2573,7 +4347,7 @@ pub extern "C" fn gpuf_generate_multimodal_stream(
         if vocab.is_null() {
             mtmd_input_chunks_free(chunks);
             if ctx_was_null {
-                llama_free(ctx);
+                gpuf_free_context(ctx);
             }
             return -1;
         }
@@ -2582,32 +4356,31 @@ pub extern "C" fn gpuf_generate_multimodal_stream(
         println!("🔍 Starting inline streaming generation...");
 
         let generated_text = {
-            // Initialize samplers
-            let temp_sampler = llama_sampler_init_temp(temperature);
-            let top_k_sampler = llama_sampler_init_top_k(top_k);
-            let top_p_sampler = llama_sampler_init_top_p(top_p, 1);
-            let repeat_sampler = llama_sampler_init_penalties(-1, repeat_penalty, 0.0, 0.0);
-            let dist_sampler = llama_sampler_init_dist(1234);
-
-            // Chain samplers
-            let chain_params = llama_sampler_chain_params { no_perf: false };
-            let sampler = llama_sampler_chain_init(chain_params);
-
-            llama_sampler_chain_add(sampler, temp_sampler);
-            llama_sampler_chain_add(sampler, top_k_sampler);
-            llama_sampler_chain_add(sampler, top_p_sampler);
-            llama_sampler_chain_add(sampler, repeat_sampler);
-            llama_sampler_chain_add(sampler, dist_sampler);
-
-            let n_ctx = llama_n_ctx(ctx);
+            let sampler = build_sampler_chain(&mobile_sampling_params(
+                temperature,
+                top_k,
+                top_p,
+                repeat_penalty,
+            ));
+
+            let n_ctx = Backend::n_ctx(ctx);
             let _vocab_size = llama_vocab_n_tokens(vocab);
 
             let mut n_past = new_n_past;
             let mut generated_text = String::new();
             let mut generated_count = 0;
+            let mut utf8_buf = Utf8EmitBuffer::new();
+            let mut rate_limiter = util::generation::TokenRateLimiter::from_config();
 
             // Generation loop
-            while generated_count < max_tokens && n_past < n_ctx {
+            let generation_limit = context_generation_limit(max_tokens, n_ctx, n_past);
+            init_generation_control();
+            while generated_count < generation_limit {
+                if should_stop_generation() {
+                    println!("⏹️ Generation stopped by user");
+                    break;
+                }
+
                 let logits = llama_get_logits(ctx);
                 if logits.is_null() {
                     break;
@@ -2620,24 +4393,15 @@ pub extern "C" fn gpuf_generate_multimodal_stream(
                     break;
                 }
 
-                // Convert token to text
-                let mut token_buf = [0u8; 32];
-                let token_len = llama_token_to_piece(
-                    vocab,
-                    new_token_id,
-                    token_buf.as_mut_ptr() as *mut c_char,
-                    token_buf.len() as c_int,
-                    0,
-                    false,
-                );
-
-                if token_len > 0 {
-                    let token_str = std::str::from_utf8_unchecked(&token_buf[..token_len as usize]);
-                    generated_text.push_str(token_str);
+                // Pieces may end mid-glyph; only whole UTF-8 sequences are emitted
+                let piece = token_to_piece_bytes(vocab, new_token_id, false);
+                let emitted = utf8_buf.push_and_take_valid(&piece);
+                if !emitted.is_empty() {
+                    generated_text.push_str(&emitted);
 
                     // 🔑 Call token callback
                     if let Some(callback) = on_token {
-                        match CString::new(token_str) {
+                        match CString::new(emitted.as_str()) {
                             Ok(token_cstr) => {
                                 callback(user_data, token_cstr.as_ptr(), new_token_id);
                             }
@@ -2666,10 +4430,14 @@ pub extern "C" fn gpuf_generate_multimodal_stream(
 
                 n_past += 1;
                 generated_count += 1;
+
+                pace_generation(&mut rate_limiter);
             }
+            report_throttling(&rate_limiter);
 
             llama_sampler_free(sampler);
             println!("✅ Generated {} tokens", generated_count);
+            generated_text.push_str(&utf8_buf.flush_lossy());
 
             generated_text
         };
@@ -2696,7 +4464,7 @@ pub extern "C" fn gpuf_generate_multimodal_stream(
 
         if ctx_was_null && !ctx.is_null() {
             println!("🔧 Freeing created context: {:p}", ctx);
-            llama_free(ctx);
+            gpuf_free_context(ctx);
         }
 
         token_count
@@ -2726,6 +4494,49 @@ pub extern "C" fn gpuf_free_multimodal_model(multimodal_model: *mut gpuf_multimo
     }
 }
 
+/// Fill `info` with the projector type, vision/audio support, image token
+/// bounds and media marker of a loaded multimodal model.
+///
+/// Returns 0 on success, -1 if a pointer is null or the model has no mtmd context.
+#[no_mangle]
+#[cfg(target_os = "android")]
+pub extern "C" fn gpuf_multimodal_model_info(
+    multimodal_model: *mut gpuf_multimodal_model,
+    info: *mut gpuf_multimodal_info,
+) -> c_int {
+    if multimodal_model.is_null() || info.is_null() {
+        return -1;
+    }
+
+    // SAFETY: Both pointers were checked for null. The model must be a live
+    // pointer from `gpuf_load_multimodal_model` and `info` must be writable.
+    unsafe {
+        let model_ref = &*multimodal_model;
+        if model_ref.mtmd_context.is_null() {
+            return -1;
+        }
+
+        *info = gpuf_multimodal_info {
+            projector_type: model_ref.projector_type,
+            supports_vision: mtmd_support_vision(model_ref.mtmd_context),
+            supports_audio: mtmd_support_audio(model_ref.mtmd_context),
+            image_min_tokens: model_ref.image_min_tokens,
+            image_max_tokens: model_ref.image_max_tokens,
+            media_marker: model_ref._media_marker.as_ptr(),
+        };
+        0
+    }
+}
+
+#[no_mangle]
+#[cfg(target_os = "ios")]
+pub extern "C" fn gpuf_multimodal_model_info(
+    _multimodal_model: *mut gpuf_multimodal_model,
+    _info: *mut gpuf_multimodal_info,
+) -> c_int {
+    -1
+}
+
 // Check if multimodal model supports vision
 #[no_mangle]
 #[cfg(target_os = "android")]
@@ -2882,6 +4693,7 @@ fn generate_multimodal_response(
         top_p,
         repeat_penalty,
         0,
+        &configured_stop_words(),
     ) // 🆕 Start from position 0 for text-only generation
 }
 
@@ -2895,31 +4707,21 @@ fn generate_multimodal_response_with_vocab(
     top_p: f32,
     repeat_penalty: f32,
     initial_n_past: c_int, // 🆕 Accept correct initial position from encoding
+    stop_words: &[String],
 ) -> String {
     if ctx.is_null() {
         return "❌ Invalid context".to_string();
     }
+    record_generation_backend(ctx);
 
-    // SAFETY: `ctx` was checked for null above and must be a live llama.cpp
-    // context. Sampler pointers are checked before use where ownership matters.
-    let temp_sampler = unsafe { llama_sampler_init_temp(temperature) };
-    let top_k_sampler = unsafe { llama_sampler_init_top_k(top_k) };
-    let top_p_sampler = unsafe { llama_sampler_init_top_p(top_p, 1) };
-    let repeat_sampler = unsafe { llama_sampler_init_penalties(-1, repeat_penalty, 0.0, 0.0) };
-    let dist_sampler = unsafe { llama_sampler_init_dist(1234) }; // Fixed seed for reproducibility
-
-    // Chain samplers together
-    let chain_params = llama_sampler_chain_params { no_perf: false };
-    let sampler = unsafe { llama_sampler_chain_init(chain_params) };
-
-    // SAFETY: `sampler` is a newly created sampler chain; sampler components are
-    // handed to llama.cpp chain ownership exactly once.
-    unsafe {
-        llama_sampler_chain_add(sampler, temp_sampler);
-        llama_sampler_chain_add(sampler, top_k_sampler);
-        llama_sampler_chain_add(sampler, top_p_sampler);
-        llama_sampler_chain_add(sampler, repeat_sampler);
-        llama_sampler_chain_add(sampler, dist_sampler);
+    let sampler = build_sampler_chain(&mobile_sampling_params(
+        temperature,
+        top_k,
+        top_p,
+        repeat_penalty,
+    ));
+    if sampler.is_null() {
+        return "❌ Failed to create sampler chain".to_string();
     }
 
     // Get model and vocab at function start (only once, like llama.rn)
@@ -2946,7 +4748,7 @@ fn generate_multimodal_response_with_vocab(
 
     // SAFETY: `vocab` and `ctx` were checked above and remain valid for this call.
     let vocab_size = unsafe { llama_vocab_n_tokens(vocab) };
-    let n_ctx = unsafe { llama_n_ctx(ctx as *const llama_context) };
+    let n_ctx = Backend::n_ctx(ctx);
 
     println!(
         "🔢 Context size: {}, Vocab size: {}, Using direct vocab: {}",
@@ -2977,6 +4779,10 @@ fn generate_multimodal_response_with_vocab(
     // Generate tokens one by one
     let mut generated_text = String::new();
     let mut generated_count = 0;
+    let mut text_stream = TokenTextStream::new(stop_words);
+    let mut repetition = repetition_detector();
+    let mut rate_limiter = util::generation::TokenRateLimiter::from_config();
+    let mut finish_reason = util::generation::FinishReason::Length;
 
     // 🔍 Debug: Check context state before generation loop
     println!("🔍 === Generation Loop Starting ===");
@@ -3008,9 +4814,16 @@ fn generate_multimodal_response_with_vocab(
         }
     }
 
-    for i in 0..max_tokens {
+    init_generation_control();
+    for i in 0..context_generation_limit(max_tokens, n_ctx, n_past) {
         println!("🔍 === Token {} === (n_past: {})", i, n_past);
 
+        if should_stop_generation() {
+            println!("⏹️ Generation stopped by user");
+            finish_reason = util::generation::FinishReason::Stop;
+            break;
+        }
+
         // Check sampler validity before sampling
         if sampler.is_null() {
             println!("❌ Sampler is null!");
@@ -3029,6 +4842,7 @@ fn generate_multimodal_response_with_vocab(
         // SAFETY: `vocab` is a live llama.cpp vocab pointer checked above.
         if unsafe { llama_vocab_is_eog(vocab, token) } {
             println!("✅ EOS token detected: {} (0x{:x})", token, token);
+            finish_reason = util::generation::FinishReason::Stop;
             break;
         }
 
@@ -3063,33 +4877,19 @@ fn generate_multimodal_response_with_vocab(
         }
 
         // Convert token to string (use vocab from function start)
-        let mut token_str = [0u8; 64];
-        // SAFETY: `token_str` is a writable local buffer and `vocab` is live.
-        let token_len = unsafe {
-            llama_token_to_piece(
-                vocab, // Use vocab obtained at function start
-                token,
-                token_str.as_mut_ptr(),
-                token_str.len() as c_int,
-                0,
-                false,
-            )
-        };
-
-        if token_len > 0 {
-            let token_len = (token_len as usize).min(token_str.len());
-            match std::str::from_utf8(&token_str[..token_len]) {
-                Ok(token_text) => {
-                    generated_text.push_str(token_text);
-                    generated_count += 1;
-                    println!(
-                        " Generated token text redacted ({} bytes)",
-                        token_text.len()
-                    );
-                }
-                Err(_) => {
-                    println!(" Skipping non-UTF8 token piece ({} bytes)", token_len);
-                }
+        let piece = token_to_piece_bytes(vocab, token, false);
+        if !piece.is_empty() {
+            let (token_text, stopped) = text_stream.push(&piece);
+            generated_text.push_str(&token_text);
+            generated_count += 1;
+            println!(
+                " Generated token text redacted ({} bytes)",
+                token_text.len()
+            );
+            if stopped {
+                println!("🛑 Stop word matched, stopping generation");
+                finish_reason = util::generation::FinishReason::Stop;
+                break;
             }
         }
 
@@ -3113,15 +4913,21 @@ fn generate_multimodal_response_with_vocab(
             break;
         }
 
-        // Safety limit
-        if generated_count >= max_tokens || generated_text.len() > 1000 {
-            println!("🛑 Generation limit reached");
+        if repetition.push(token) {
+            println!("🛑 Repetition loop detected, stopping generation");
+            finish_reason = util::generation::FinishReason::Repetition;
             break;
         }
+
+        pace_generation(&mut rate_limiter);
     }
+    report_throttling(&rate_limiter);
 
     // SAFETY: `sampler` is owned by this function and has not been freed yet.
     unsafe { llama_sampler_free(sampler) };
+    set_last_finish_reason(finish_reason);
+
+    generated_text.push_str(&text_stream.finish());
 
     println!("\n✅ Real generation completed: {} tokens", generated_count);
 
@@ -3147,6 +4953,7 @@ fn generate_multimodal_response_with_callbacks(
     user_data: *mut c_void,
 ) -> String {
     println!("🔍 generate_multimodal_response_with_callbacks: ENTRY");
+    record_generation_backend(ctx);
 
     // SAFETY: The caller supplies live llama.cpp context/vocab pointers and
     // callback/user_data storage for the duration of this synchronous helper.
@@ -3154,39 +4961,19 @@ fn generate_multimodal_response_with_callbacks(
     // sampler ownership is released before returning.
     unsafe {
         println!("🔍 Initializing samplers...");
-
-        // Initialize samplers (same as original function)
-        let temp_sampler = llama_sampler_init_temp(temperature);
-        println!("🔍 temp_sampler: {:p}", temp_sampler);
-
-        let top_k_sampler = llama_sampler_init_top_k(top_k);
-        println!("🔍 top_k_sampler: {:p}", top_k_sampler);
-
-        let top_p_sampler = llama_sampler_init_top_p(top_p, 1);
-        println!("🔍 top_p_sampler: {:p}", top_p_sampler);
-
-        let repeat_sampler = llama_sampler_init_penalties(-1, repeat_penalty, 0.0, 0.0);
-        println!("🔍 repeat_sampler: {:p}", repeat_sampler);
-
-        let dist_sampler = llama_sampler_init_dist(1234);
-        println!("🔍 dist_sampler: {:p}", dist_sampler);
-
-        // Chain samplers together
-        let chain_params = llama_sampler_chain_params { no_perf: false };
-        let sampler = llama_sampler_chain_init(chain_params);
+        let sampler = build_sampler_chain(&mobile_sampling_params(
+            temperature,
+            top_k,
+            top_p,
+            repeat_penalty,
+        ));
         println!("🔍 sampler chain: {:p}", sampler);
 
         if sampler.is_null() {
             return "❌ Failed to create sampler chain".to_string();
         }
 
-        llama_sampler_chain_add(sampler, temp_sampler);
-        llama_sampler_chain_add(sampler, top_k_sampler);
-        llama_sampler_chain_add(sampler, top_p_sampler);
-        llama_sampler_chain_add(sampler, repeat_sampler);
-        llama_sampler_chain_add(sampler, dist_sampler);
-
-        let n_ctx = llama_n_ctx(ctx);
+        let n_ctx = Backend::n_ctx(ctx);
         let vocab_size = llama_vocab_n_tokens(direct_vocab);
         println!("🔍 n_ctx: {}, vocab_size: {}", n_ctx, vocab_size);
 
@@ -3204,9 +4991,20 @@ fn generate_multimodal_response_with_callbacks(
         let mut generated_text = String::new();
         let mut generated_count = 0;
         let mut utf8_buf = Utf8EmitBuffer::new();
+        let mut repetition = repetition_detector();
+        let mut rate_limiter = util::generation::TokenRateLimiter::from_config();
+        let mut finish_reason = util::generation::FinishReason::Length;
 
         // Generation loop with callbacks
-        while generated_count < max_tokens && n_past < n_ctx {
+        let generation_limit = context_generation_limit(max_tokens, n_ctx, n_past);
+        init_generation_control();
+        while generated_count < generation_limit {
+            if should_stop_generation() {
+                println!("⏹️ Generation stopped by user");
+                finish_reason = util::generation::FinishReason::Stop;
+                break;
+            }
+
             let logits = llama_get_logits(ctx);
             if logits.is_null() {
                 break;
@@ -3220,6 +5018,7 @@ fn generate_multimodal_response_with_callbacks(
             let eos_token = llama_token_eos(model);
             if new_token_id == eos_token {
                 println!("🛑 EOS token reached");
+                finish_reason = util::generation::FinishReason::Stop;
                 break;
             }
 
@@ -3274,9 +5073,19 @@ fn generate_multimodal_response_with_callbacks(
 
             n_past += 1;
             generated_count += 1;
+
+            if repetition.push(new_token_id) {
+                println!("🛑 Repetition loop detected, stopping generation");
+                finish_reason = util::generation::FinishReason::Repetition;
+                break;
+            }
+
+            pace_generation(&mut rate_limiter);
         }
+        report_throttling(&rate_limiter);
 
         llama_sampler_free(sampler);
+        set_last_finish_reason(finish_reason);
         println!(
             "✅ Streaming generation completed: {} tokens",
             generated_count
@@ -3296,15 +5105,204 @@ pub extern "C" fn gpuf_tokenize_text(
     ctx: *mut llama_context,
     text: *const c_char,
     tokens: *mut LlamaToken,
-    _max_tokens: c_int,
+    max_tokens: c_int,
+) -> c_int {
+    if ctx.is_null() || text.is_null() || tokens.is_null() || max_tokens < 0 {
+        return -1;
+    }
+
+    // SAFETY: `text` was checked for null and must be NUL-terminated.
+    let text_str = match unsafe { CStr::from_ptr(text) }.to_str() {
+        Ok(s) => s,
+        Err(_) => return -1,
+    };
+    let result = match tokenize(ctx, text_str, true) {
+        Ok(result) => result,
+        Err(e) => {
+            println!("❌ Tokenization failed: {}", e);
+            return -1;
+        }
+    };
+
+    // Same convention as llama_tokenize: negative count when it doesn't fit
+    if result.len() > max_tokens as usize {
+        return -(result.len().min(c_int::MAX as usize) as c_int);
+    }
+    // SAFETY: `tokens` is non-null and the caller provides room for
+    // `max_tokens` elements, which bounds `result.len()`.
+    unsafe {
+        std::ptr::copy_nonoverlapping(result.as_ptr(), tokens, result.len());
+    }
+    result.len() as c_int
+}
+
+/// Compute an L2-normalized embedding of `text` with the loaded model and copy
+/// it into `out`.
+///
+/// The context is switched to embeddings mode for the call and its KV cache is
+/// cleared, so any conversation state in `ctx` is lost. The text must fit in
+/// one batch of the context.
+///
+/// Returns the embedding dimension, or:
+/// the negated dimension if `out_len` is too small (nothing is written),
+/// -1 on invalid arguments or if the model could not produce an embedding.
+///
+/// # Safety
+/// `text` must be a valid NUL-terminated C string and `out` must point to at
+/// least `out_len` writable floats.
+#[no_mangle]
+pub extern "C" fn gpuf_embed_text(
+    ctx: *mut llama_context,
+    text: *const c_char,
+    out: *mut f32,
+    out_len: c_int,
+) -> c_int {
+    if ctx.is_null() || text.is_null() || out.is_null() || out_len < 0 {
+        return -1;
+    }
+    // SAFETY: `text` was checked for null and must be NUL-terminated.
+    let text_str = match unsafe { CStr::from_ptr(text) }.to_str() {
+        Ok(s) => s,
+        Err(_) => return -1,
+    };
+
+    let _inference_lock = GLOBAL_INFERENCE_MUTEX
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let embedding = tokenize(ctx, text_str, true).and_then(|mut tokens| {
+        if tokens.is_empty() {
+            anyhow::bail!("embed: text produced no tokens");
+        }
+        Backend::embedding(ctx, &mut tokens)
+    });
+    // The KV cache was cleared; the next generation on this context starts over
+    if GLOBAL_CONTEXT_PTR.load(Ordering::SeqCst) == ctx {
+        set_context_position(0);
+    }
+    let _ = CONTINUABLE_CONTEXT_PTR.compare_exchange(
+        ctx,
+        std::ptr::null_mut(),
+        Ordering::SeqCst,
+        Ordering::SeqCst,
+    );
+
+    let mut embedding = match embedding {
+        Ok(embedding) if !embedding.is_empty() => embedding,
+        Ok(_) => return -1,
+        Err(e) => {
+            println!("❌ Embedding failed: {}", e);
+            return -1;
+        }
+    };
+    let dim = embedding.len().min(c_int::MAX as usize) as c_int;
+    if embedding.len() > out_len as usize {
+        return -dim;
+    }
+
+    let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        embedding.iter_mut().for_each(|v| *v /= norm);
+    }
+    // SAFETY: `out` is non-null and holds `out_len` floats per the caller
+    // contract, which bounds `embedding.len()`.
+    unsafe {
+        std::ptr::copy_nonoverlapping(embedding.as_ptr(), out, embedding.len());
+    }
+    dim
+}
+
+/// Snapshot the KV cache (and the rest of the context state) of `ctx` into
+/// `out`, e.g. before `set_remote_worker_model` swaps the context out.
+///
+/// Returns the bytes written, or:
+/// the negated required size if `out` is null or `out_len` is too small
+/// (nothing is written; call with a null `out` to query the size),
+/// -1 if `ctx` is null or the state could not be serialized.
+///
+/// # Safety
+/// `out` must be null or point to at least `out_len` writable bytes.
+#[no_mangle]
+pub extern "C" fn gpuf_kv_cache_save(
+    ctx: *mut llama_context,
+    out: *mut u8,
+    out_len: usize,
+) -> isize {
+    if ctx.is_null() {
+        return -1;
+    }
+    let _inference_lock = GLOBAL_INFERENCE_MUTEX
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let size = Backend::state_size(ctx);
+    if size == 0 || size > isize::MAX as usize {
+        return -1;
+    }
+    if out.is_null() || out_len < size {
+        return -(size as isize);
+    }
+
+    // SAFETY: `out` is non-null and writable for `out_len >= size` bytes per
+    // the caller contract.
+    let out = unsafe { std::slice::from_raw_parts_mut(out, size) };
+    match Backend::state_save(ctx, out) {
+        0 => {
+            println!("❌ Failed to serialize context state");
+            -1
+        }
+        written => {
+            println!("💾 Saved {} bytes of context state", written);
+            written as isize
+        }
+    }
+}
+
+/// Restore a snapshot from `gpuf_kv_cache_save` into `ctx`.
+///
+/// The context must belong to the same model the snapshot was taken with.
+/// On success generation can continue from the restored conversation, e.g.
+/// with `gpuf_continue_generation`. Restoring into the SDK's own context waits
+/// for any generation running on it to finish first.
+///
+/// Returns the number of tokens in the restored cache, or -1 on invalid
+/// arguments or if the data does not match the context.
+///
+/// # Safety
+/// `data` must point to at least `len` readable bytes.
+#[no_mangle]
+pub extern "C" fn gpuf_kv_cache_load(
+    ctx: *mut llama_context,
+    data: *const u8,
+    len: usize,
 ) -> c_int {
-    if ctx.is_null() || text.is_null() || tokens.is_null() {
+    if ctx.is_null() || data.is_null() || len == 0 {
         return -1;
     }
-    /*
-    real_llama_tokenize(ctx, text, tokens, max_tokens, true)
-    */
-    0 // Placeholder
+    let _inference_lock = GLOBAL_INFERENCE_MUTEX
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    // SAFETY: `data` is non-null and readable for `len` bytes per the caller
+    // contract.
+    let data = unsafe { std::slice::from_raw_parts(data, len) };
+    match Backend::state_load(ctx, data) {
+        Ok(n_tokens) => {
+            // The position and continuation state track the SDK's context only
+            if GLOBAL_CONTEXT_PTR.load(Ordering::SeqCst) == ctx {
+                set_context_position(n_tokens);
+                let continuable = if n_tokens > 0 {
+                    ctx
+                } else {
+                    std::ptr::null_mut()
+                };
+                CONTINUABLE_CONTEXT_PTR.store(continuable, Ordering::SeqCst);
+            }
+            println!("📂 Restored context state ({} tokens)", n_tokens);
+            n_tokens
+        }
+        Err(e) => {
+            println!("❌ {}", e);
+            -1
+        }
+    }
 }
 
 #[no_mangle]
@@ -3336,7 +5334,7 @@ pub extern "C" fn gpuf_generate_final_solution_text(
         */
         let token_count = 1; // Placeholder
 
-        let n_ctx = real_llama_n_ctx(ctx);
+        let n_ctx = Backend::n_ctx(ctx);
 
         // For now, return a simple response showing tokenization worked without echoing prompt text.
         let output_text = format!(
@@ -3406,18 +5404,251 @@ pub extern "C" fn gpuf_generate_with_sampling(
     )
 }
 
+/// Continue the previous `gpuf_generate_with_sampling` completion for up to
+/// `additional_tokens` more tokens, decoding from the current KV position
+/// without re-running prefill.
+///
+/// Preconditions: `ctx` must be the context used by that completion and no
+/// other generation, warmup or model swap may have touched sequence 0 since.
+/// The KV cache must still end right before `GLOBAL_CONTEXT_POSITION`. Meant
+/// for resuming after finish reason "length"; sampler history (repeat penalty)
+/// is not carried over from the previous call. Output stops at the
+/// `gpuf_set_stop_words` list like the streaming generation loops.
+///
+/// Returns the number of bytes written to `output` (NUL terminated), -1 on
+/// invalid arguments, -2 if the context state can no longer be continued
+/// (start a fresh generation instead) and -3 if the context window is full.
 #[no_mangle]
-pub extern "C" fn gpuf_system_info() -> *const c_char {
-    let info = CString::new("GPUFabric Android LLaMA.cpp Engine").unwrap();
-    info.into_raw()
-}
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub extern "C" fn gpuf_continue_generation(
+    ctx: *mut llama_context,
+    additional_tokens: c_int,
+    temperature: f32,
+    top_k: c_int,
+    top_p: f32,
+    repeat_penalty: f32,
+    output: *mut c_char,
+    output_len: c_int,
+) -> c_int {
+    if ctx.is_null() || output.is_null() || output_len <= 0 || additional_tokens <= 0 {
+        return -1;
+    }
+
+    let _inference_lock = GLOBAL_INFERENCE_MUTEX
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    if CONTINUABLE_CONTEXT_PTR.load(Ordering::SeqCst) != ctx {
+        println!("❌ Continue rejected: context was reset or swapped since the last completion");
+        return -2;
+    }
+    record_generation_backend(ctx);
+
+    // SAFETY: `ctx` is the live context of the previous completion (checked above) and
+    // GLOBAL_INFERENCE_MUTEX keeps model swaps out while it is used. Batch pointers
+    // reference locals that outlive each decode call; output writes are bounded by
+    // `output_len` before NUL termination.
+    unsafe {
+        let start_pos = get_context_position();
+        if start_pos <= 0 || Backend::memory_seq_pos_max(ctx, 0) != start_pos - 1 {
+            println!(
+                "❌ Continue rejected: KV cache does not end at position {}",
+                start_pos
+            );
+            CONTINUABLE_CONTEXT_PTR.store(std::ptr::null_mut(), Ordering::SeqCst);
+            return -2;
+        }
+
+        let generation_limit =
+            context_generation_limit(additional_tokens, Backend::n_ctx(ctx), start_pos);
+        if generation_limit <= 0 {
+            println!("❌ Continue rejected: context window is full");
+            return -3;
+        }
+
+        let vocab = llama_model_get_vocab(llama_get_model(ctx));
+
+        let sampler = build_sampler_chain(&mobile_sampling_params(
+            temperature,
+            top_k,
+            top_p,
+            repeat_penalty,
+        ));
+        if sampler.is_null() {
+            return -1;
+        }
+
+        let mut next_pos = start_pos;
+        let mut result_text = String::new();
+        let mut text_stream = TokenTextStream::new(&configured_stop_words());
+        let mut repetition = repetition_detector();
+        // Every decoded token extends sequence 0, like the completion it continues
+        let mut seq_id: LlamaSeqId = 0;
+        let mut seq_id_ptr: *mut LlamaSeqId = &mut seq_id;
+        let mut n_seq_id: c_int = 1;
+        let mut rate_limiter = util::generation::TokenRateLimiter::from_config();
+        let mut finish_reason = util::generation::FinishReason::Length;
+
+        for _ in 0..generation_limit {
+            // Logits of the last decoded token are still in the context
+            let sampled_token = llama_sampler_sample(sampler, ctx, -1);
+            if llama_vocab_is_eog(vocab, sampled_token) {
+                finish_reason = util::generation::FinishReason::Stop;
+                break;
+            }
+
+            let piece = token_to_piece_bytes(vocab, sampled_token, true);
+            let (token_text, stopped) = text_stream.push(&piece);
+            result_text.push_str(&token_text);
+            if stopped {
+                println!("🛑 Stop word matched, stopping generation");
+                finish_reason = util::generation::FinishReason::Stop;
+                break;
+            }
+
+            let mut pos = next_pos as LlamaPos;
+            let mut logits = 1i8;
+            let batch = llama_batch {
+                n_tokens: 1,
+                token: (&sampled_token as *const LlamaToken) as *mut LlamaToken,
+                embd: std::ptr::null_mut(),
+                pos: &mut pos as *mut LlamaPos,
+                n_seq_id: &mut n_seq_id as *mut c_int,
+                seq_id: &mut seq_id_ptr as *mut *mut LlamaSeqId,
+                logits: &mut logits as *mut i8,
+            };
+            if llama_decode(ctx, batch) != 0 {
+                println!("❌ Continue decode failed at position {}", next_pos);
+                break;
+            }
+            next_pos += 1;
+
+            if repetition.push(sampled_token) {
+                println!("🛑 Repetition loop detected, stopping generation");
+                finish_reason = util::generation::FinishReason::Repetition;
+                break;
+            }
+
+            pace_generation(&mut rate_limiter);
+        }
+        report_throttling(&rate_limiter);
+
+        llama_sampler_free(sampler);
+        set_last_finish_reason(finish_reason);
+        result_text.push_str(&text_stream.finish());
+
+        set_context_position(next_pos);
+        println!(
+            "✅ Continued generation: {} tokens (position {} -> {})",
+            next_pos - start_pos,
+            start_pos,
+            next_pos
+        );
+
+        store_and_copy_result(&result_text, output, output_len)
+    }
+}
+
+#[no_mangle]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub extern "C" fn gpuf_continue_generation(
+    _ctx: *mut llama_context,
+    _additional_tokens: c_int,
+    _temperature: f32,
+    _top_k: c_int,
+    _top_p: f32,
+    _repeat_penalty: f32,
+    _output: *mut c_char,
+    _output_len: c_int,
+) -> c_int {
+    -1
+}
+
+#[no_mangle]
+pub extern "C" fn gpuf_system_info() -> *const c_char {
+    let info = CString::new("GPUFabric Android LLaMA.cpp Engine").unwrap();
+    info.into_raw()
+}
+
+// Build version reported by `gpuf_version` and `gpuf_describe_worker`
+pub(crate) const GPUF_VERSION: &str = "9.0.0-x86_64-android-FINAL-LLAMA-SOLUTION";
 
 #[no_mangle]
 pub extern "C" fn gpuf_version() -> *const c_char {
-    let version = CString::new("9.0.0-x86_64-android-FINAL-LLAMA-SOLUTION").unwrap();
+    let version = CString::new(GPUF_VERSION).unwrap();
     version.into_raw()
 }
 
+/// What the FFI runtime currently runs: the model and context loaded through
+/// `gpuf_load_model`/`gpuf_create_context`, or zeros before that.
+pub(crate) fn describe_worker() -> common::WorkerDescription {
+    let model = {
+        let status = MODEL_STATUS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if status.unloaded {
+            None
+        } else {
+            status.current_model.clone()
+        }
+    };
+    let ctx = GLOBAL_CONTEXT_PTR.load(Ordering::SeqCst);
+    let (n_ctx, n_batch) = if ctx.is_null() {
+        (0, 0)
+    } else {
+        (
+            Backend::n_ctx(ctx).max(0) as u32,
+            Backend::n_batch(ctx).max(0) as u32,
+        )
+    };
+    common::WorkerDescription {
+        version: GPUF_VERSION.to_string(),
+        protocol_revision: common::PROTOCOL_REVISION,
+        os: std::env::consts::OS.to_string(),
+        engine: common::EngineType::Llama.to_string(),
+        model,
+        n_ctx,
+        n_batch,
+        n_gpu_layers: None,
+        device_summary: util::system_info::device_summary(&[]),
+    }
+}
+
+/// Write this worker's description as JSON into `output`: build version,
+/// protocol revision, OS, engine, loaded model, context size, batch size and
+/// a short device summary. The same data answers the server's describe
+/// requests.
+///
+/// Returns `GpufError::InvalidArgument` for a null or empty buffer and
+/// `GpufError::BufferTooSmall` if the JSON (plus NUL) does not fit in
+/// `output_len` bytes.
+///
+/// # Safety
+/// `output` must be writable for `output_len` bytes.
+#[no_mangle]
+pub extern "C" fn gpuf_describe_worker(output: *mut c_char, output_len: c_int) -> GpufError {
+    if output.is_null() || output_len <= 0 {
+        return GpufError::InvalidArgument;
+    }
+    let json = match serde_json::to_string(&describe_worker()) {
+        Ok(json) => json,
+        Err(e) => {
+            println!("❌ Failed to serialize worker description: {}", e);
+            return GpufError::Internal;
+        }
+    };
+    if json.len() >= output_len as usize {
+        return GpufError::BufferTooSmall;
+    }
+    // SAFETY: `output` is non-null and writable for `output_len` bytes, which
+    // holds the JSON and its NUL terminator.
+    unsafe {
+        std::ptr::copy_nonoverlapping(json.as_ptr(), output as *mut u8, json.len());
+        *output.add(json.len()) = 0;
+    }
+    GpufError::Ok
+}
+
 #[no_mangle]
 pub extern "C" fn gpuf_init() -> c_int {
     println!("🔥 GPUFabric Android LLaMA.cpp solution initialized");
@@ -3465,7 +5696,7 @@ pub extern "C" fn gpuf_init() -> c_int {
         }
 
         // Step 3: Initialize llama.cpp backend
-        real_llama_backend_init();
+        Backend::backend_init();
 
         // Force reference to GGML backend symbols to ensure they are linked.
         let _ggml_backend_dev_by_type_ptr = ggml_backend_dev_by_type as *const ();
@@ -3481,7 +5712,7 @@ pub extern "C" fn gpuf_init() -> c_int {
 
     #[cfg(not(target_os = "android"))]
     {
-        real_llama_backend_init();
+        Backend::backend_init();
     }
 
     1 // Success
@@ -3498,10 +5729,395 @@ pub extern "C" fn gpuf_cleanup() -> c_int {
         println!("✅ Memory pool cleaned up");
     }
 
-    real_llama_backend_free();
+    Backend::backend_free();
     0
 }
 
+// ============================================================================
+// Inference backend selection (CPU / Vulkan)
+// ============================================================================
+
+/// Run models on the CPU (the default).
+pub const GPUF_BACKEND_CPU: c_int = 0;
+/// Offload models to a Vulkan GPU when one is available.
+pub const GPUF_BACKEND_VULKAN: c_int = 1;
+
+static PREFERRED_BACKEND: AtomicI32 = AtomicI32::new(GPUF_BACKEND_CPU);
+
+// Backend of each loaded model and created context, keyed by handle address
+static HANDLE_BACKENDS: Lazy<
+    Mutex<std::collections::HashMap<usize, util::backend::InferenceBackend>>,
+> = Lazy::new(|| Mutex::new(std::collections::HashMap::new()));
+
+// Backend code of the context that ran the most recent generation, -1 if none
+#[cfg(any(target_os = "android", target_os = "ios"))]
+static LAST_GENERATION_BACKEND: AtomicI32 = AtomicI32::new(-1);
+
+/// Vulkan device as reported by `gpuf_vulkan_device_info`.
+#[repr(C)]
+#[derive(Clone)]
+pub struct gpuf_vulkan_device {
+    /// NUL-terminated device name.
+    pub name: [c_char; 256],
+    pub vendor_id: u32,
+    pub device_id: u32,
+    /// Packed `VK_MAKE_API_VERSION` value supported by the device.
+    pub api_version: u32,
+    /// Total size of device-local memory heaps in bytes.
+    pub memory_bytes: u64,
+    /// 1 for software/CPU implementations (e.g. SwiftShader), 0 for GPUs.
+    pub is_software: c_int,
+}
+
+#[cfg(target_os = "android")]
+mod vulkan_probe {
+    //! Minimal Vulkan 1.0 bindings to list physical devices. libvulkan is
+    //! opened at runtime so devices without a Vulkan loader still work.
+
+    use super::gpuf_vulkan_device;
+    use std::ffi::{c_char, c_void, CStr};
+
+    const VK_SUCCESS: i32 = 0;
+    const VK_STRUCTURE_TYPE_APPLICATION_INFO: u32 = 0;
+    const VK_STRUCTURE_TYPE_INSTANCE_CREATE_INFO: u32 = 1;
+    const VK_API_VERSION_1_0: u32 = 1 << 22;
+    const VK_PHYSICAL_DEVICE_TYPE_CPU: u32 = 4;
+    const VK_MEMORY_HEAP_DEVICE_LOCAL_BIT: u32 = 1;
+
+    #[repr(C)]
+    struct VkApplicationInfo {
+        s_type: u32,
+        p_next: *const c_void,
+        p_application_name: *const c_char,
+        application_version: u32,
+        p_engine_name: *const c_char,
+        engine_version: u32,
+        api_version: u32,
+    }
+
+    #[repr(C)]
+    struct VkInstanceCreateInfo {
+        s_type: u32,
+        p_next: *const c_void,
+        flags: u32,
+        p_application_info: *const VkApplicationInfo,
+        enabled_layer_count: u32,
+        pp_enabled_layer_names: *const *const c_char,
+        enabled_extension_count: u32,
+        pp_enabled_extension_names: *const *const c_char,
+    }
+
+    // Leading fields of VkPhysicalDeviceProperties; `_limits` is large enough
+    // for VkPhysicalDeviceLimits and VkPhysicalDeviceSparseProperties.
+    #[repr(C, align(8))]
+    struct VkPhysicalDeviceProperties {
+        api_version: u32,
+        driver_version: u32,
+        vendor_id: u32,
+        device_id: u32,
+        device_type: u32,
+        device_name: [c_char; 256],
+        pipeline_cache_uuid: [u8; 16],
+        _limits: [u8; 1024],
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct VkMemoryType {
+        property_flags: u32,
+        heap_index: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct VkMemoryHeap {
+        size: u64,
+        flags: u32,
+    }
+
+    #[repr(C)]
+    struct VkPhysicalDeviceMemoryProperties {
+        memory_type_count: u32,
+        memory_types: [VkMemoryType; 32],
+        memory_heap_count: u32,
+        memory_heaps: [VkMemoryHeap; 16],
+    }
+
+    type CreateInstance =
+        unsafe extern "C" fn(*const VkInstanceCreateInfo, *const c_void, *mut *mut c_void) -> i32;
+    type DestroyInstance = unsafe extern "C" fn(*mut c_void, *const c_void);
+    type EnumeratePhysicalDevices =
+        unsafe extern "C" fn(*mut c_void, *mut u32, *mut *mut c_void) -> i32;
+    type GetPhysicalDeviceProperties =
+        unsafe extern "C" fn(*mut c_void, *mut VkPhysicalDeviceProperties);
+    type GetPhysicalDeviceMemoryProperties =
+        unsafe extern "C" fn(*mut c_void, *mut VkPhysicalDeviceMemoryProperties);
+
+    /// Looks up `name` in `lib`, or returns `None`.
+    ///
+    /// # Safety
+    /// `lib` must be a live dlopen handle and `T` the symbol's function type.
+    unsafe fn symbol<T>(lib: *mut c_void, name: &CStr) -> Option<T> {
+        let ptr = libc::dlsym(lib, name.as_ptr());
+        (!ptr.is_null()).then(|| std::mem::transmute_copy(&ptr))
+    }
+
+    /// Enumerates the Vulkan physical devices, empty if Vulkan is unavailable.
+    pub fn probe() -> Vec<gpuf_vulkan_device> {
+        // SAFETY: Every Vulkan call goes through symbols resolved from the
+        // system loader with their Vulkan 1.0 signatures. Create-info structs
+        // outlive the calls that read them, output structs are at least as
+        // large as the Vulkan definitions, and the instance is destroyed before
+        // the library handle is closed.
+        unsafe {
+            let lib = libc::dlopen(c"libvulkan.so".as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if lib.is_null() {
+                println!("⚠️ libvulkan.so not available");
+                return Vec::new();
+            }
+
+            let devices = (|| {
+                let create_instance: CreateInstance = symbol(lib, c"vkCreateInstance")?;
+                let destroy_instance: DestroyInstance = symbol(lib, c"vkDestroyInstance")?;
+                let enumerate: EnumeratePhysicalDevices =
+                    symbol(lib, c"vkEnumeratePhysicalDevices")?;
+                let get_properties: GetPhysicalDeviceProperties =
+                    symbol(lib, c"vkGetPhysicalDeviceProperties")?;
+                let get_memory: GetPhysicalDeviceMemoryProperties =
+                    symbol(lib, c"vkGetPhysicalDeviceMemoryProperties")?;
+
+                let app_info = VkApplicationInfo {
+                    s_type: VK_STRUCTURE_TYPE_APPLICATION_INFO,
+                    p_next: std::ptr::null(),
+                    p_application_name: c"gpuf-c".as_ptr(),
+                    application_version: 0,
+                    p_engine_name: std::ptr::null(),
+                    engine_version: 0,
+                    api_version: VK_API_VERSION_1_0,
+                };
+                let create_info = VkInstanceCreateInfo {
+                    s_type: VK_STRUCTURE_TYPE_INSTANCE_CREATE_INFO,
+                    p_next: std::ptr::null(),
+                    flags: 0,
+                    p_application_info: &app_info,
+                    enabled_layer_count: 0,
+                    pp_enabled_layer_names: std::ptr::null(),
+                    enabled_extension_count: 0,
+                    pp_enabled_extension_names: std::ptr::null(),
+                };
+                let mut instance = std::ptr::null_mut();
+                if create_instance(&create_info, std::ptr::null(), &mut instance) != VK_SUCCESS {
+                    return None;
+                }
+
+                let mut count = 0u32;
+                let mut physical_devices = Vec::new();
+                if enumerate(instance, &mut count, std::ptr::null_mut()) == VK_SUCCESS {
+                    physical_devices = vec![std::ptr::null_mut(); count as usize];
+                    if enumerate(instance, &mut count, physical_devices.as_mut_ptr()) < VK_SUCCESS {
+                        count = 0;
+                    }
+                    physical_devices.truncate(count as usize);
+                }
+
+                let devices = physical_devices
+                    .into_iter()
+                    .map(|physical_device| {
+                        let mut props: VkPhysicalDeviceProperties = std::mem::zeroed();
+                        get_properties(physical_device, &mut props);
+                        let mut memory: VkPhysicalDeviceMemoryProperties = std::mem::zeroed();
+                        get_memory(physical_device, &mut memory);
+
+                        let heaps =
+                            &memory.memory_heaps[..(memory.memory_heap_count as usize).min(16)];
+                        let mut name = props.device_name;
+                        name[255] = 0;
+                        gpuf_vulkan_device {
+                            name,
+                            vendor_id: props.vendor_id,
+                            device_id: props.device_id,
+                            api_version: props.api_version,
+                            memory_bytes: heaps
+                                .iter()
+                                .filter(|heap| heap.flags & VK_MEMORY_HEAP_DEVICE_LOCAL_BIT != 0)
+                                .map(|heap| heap.size)
+                                .sum(),
+                            is_software: (props.device_type == VK_PHYSICAL_DEVICE_TYPE_CPU) as i32,
+                        }
+                    })
+                    .collect();
+
+                destroy_instance(instance, std::ptr::null());
+                Some(devices)
+            })()
+            .unwrap_or_default();
+
+            libc::dlclose(lib);
+            devices
+        }
+    }
+}
+
+// Vulkan devices, probed once on first use
+#[cfg(target_os = "android")]
+static VULKAN_DEVICES: Lazy<Vec<gpuf_vulkan_device>> = Lazy::new(|| {
+    let devices = vulkan_probe::probe();
+    println!("🔍 Found {} Vulkan device(s)", devices.len());
+    devices
+});
+
+/// Whether models can be offloaded to Vulkan: a hardware Vulkan device exists
+/// and llama.cpp has a GPU backend registered for it.
+fn vulkan_available() -> bool {
+    #[cfg(target_os = "android")]
+    {
+        let has_hardware_device = VULKAN_DEVICES.iter().any(|d| d.is_software == 0);
+        // SAFETY: The ggml backend registry is initialized by
+        // `ggml_backend_load_all` and device handles are owned by it.
+        let has_gpu_backend = unsafe {
+            (0..ggml_backend_dev_count()).any(|i| {
+                let device = ggml_backend_dev_get(i);
+                !device.is_null() && matches!(ggml_backend_dev_type(device), 1 | 2)
+            })
+        };
+        has_hardware_device && has_gpu_backend
+    }
+
+    #[cfg(not(target_os = "android"))]
+    {
+        false
+    }
+}
+
+/// Backend a new model should be loaded on, given the current preference.
+fn resolve_model_backend() -> util::backend::InferenceBackend {
+    use util::backend::InferenceBackend;
+
+    let preferred = InferenceBackend::from_code(PREFERRED_BACKEND.load(Ordering::Relaxed))
+        .unwrap_or(InferenceBackend::Cpu);
+    let backend = InferenceBackend::resolve(preferred, vulkan_available());
+    if backend != preferred {
+        println!(
+            "⚠️ Preferred backend {} unavailable, using {}",
+            preferred.as_str(),
+            backend.as_str()
+        );
+    }
+    backend
+}
+
+fn set_handle_backend(handle: usize, backend: util::backend::InferenceBackend) {
+    HANDLE_BACKENDS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(handle, backend);
+}
+
+fn forget_handle_backend(handle: usize) {
+    HANDLE_BACKENDS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .remove(&handle);
+}
+
+fn handle_backend(handle: usize) -> util::backend::InferenceBackend {
+    HANDLE_BACKENDS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(&handle)
+        .copied()
+        .unwrap_or(util::backend::InferenceBackend::Cpu)
+}
+
+/// Records the backend of `ctx` as the one serving the current generation.
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn record_generation_backend(ctx: *mut llama_context) {
+    LAST_GENERATION_BACKEND.store(handle_backend(ctx as usize).code(), Ordering::SeqCst);
+}
+
+/// Pins the generating thread to the cores set with gpuf_set_thread_affinity,
+/// if any, so llama.cpp's compute threads started from it stay there too.
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn pin_inference_thread() {
+    if let Err(e) = util::affinity::pin_current_thread() {
+        println!("⚠️ Failed to pin inference thread: {}", e);
+    }
+}
+
+/// Number of Vulkan devices found on this device (0 if Vulkan is unavailable).
+#[no_mangle]
+#[cfg(target_os = "android")]
+pub extern "C" fn gpuf_vulkan_device_count() -> c_int {
+    VULKAN_DEVICES.len() as c_int
+}
+
+#[no_mangle]
+#[cfg(not(target_os = "android"))]
+pub extern "C" fn gpuf_vulkan_device_count() -> c_int {
+    0
+}
+
+/// Fill `out` with the Vulkan device at `index`.
+/// Returns 0 on success, -1 if `out` is null or `index` is out of range.
+///
+/// # Safety
+/// `out` must be null or point to writable memory for one `gpuf_vulkan_device`.
+#[no_mangle]
+#[cfg(target_os = "android")]
+pub extern "C" fn gpuf_vulkan_device_info(index: c_int, out: *mut gpuf_vulkan_device) -> c_int {
+    if out.is_null() || index < 0 {
+        return -1;
+    }
+    match VULKAN_DEVICES.get(index as usize) {
+        Some(device) => {
+            // SAFETY: `out` is non-null and writable per the caller contract.
+            unsafe { out.write(device.clone()) };
+            0
+        }
+        None => -1,
+    }
+}
+
+#[no_mangle]
+#[cfg(not(target_os = "android"))]
+pub extern "C" fn gpuf_vulkan_device_info(_index: c_int, _out: *mut gpuf_vulkan_device) -> c_int {
+    -1
+}
+
+/// Select the backend used by subsequent `gpuf_load_model` / `gpuf_create_context`
+/// calls: `GPUF_BACKEND_CPU` (default) or `GPUF_BACKEND_VULKAN`. Vulkan falls
+/// back to the CPU when no usable device is found; check
+/// `gpuf_get_last_backend` for what actually ran.
+/// Returns 0 on success, -1 for an unknown backend.
+#[no_mangle]
+pub extern "C" fn gpuf_set_preferred_backend(backend: c_int) -> c_int {
+    if util::backend::InferenceBackend::from_code(backend).is_none() {
+        return -1;
+    }
+    PREFERRED_BACKEND.store(backend, Ordering::Relaxed);
+    0
+}
+
+/// Backend that served the most recent generation: "cpu" or "vulkan".
+/// Returns NULL if nothing has been generated yet. The returned string is
+/// static and must not be freed.
+#[no_mangle]
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub extern "C" fn gpuf_get_last_backend() -> *const c_char {
+    let backend: &'static [u8] = match LAST_GENERATION_BACKEND.load(Ordering::SeqCst) {
+        GPUF_BACKEND_CPU => b"cpu\0",
+        GPUF_BACKEND_VULKAN => b"vulkan\0",
+        _ => return std::ptr::null(),
+    };
+    backend.as_ptr() as *const c_char
+}
+
+#[no_mangle]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub extern "C" fn gpuf_get_last_backend() -> *const c_char {
+    std::ptr::null()
+}
+
 // ============================================================================
 // Android memory pool for llama.cpp allocations
 // ============================================================================
@@ -3511,14 +6127,41 @@ pub struct MemoryPool {
     buffer: usize,
     size: usize,
     used: usize,
+    /// Highest `used` seen since the pool was mapped; survives `reset_pool`.
+    peak: usize,
     initialized: bool,
 }
 
+impl MemoryPool {
+    /// Reserves `size` bytes aligned to `alignment` and returns their offset
+    /// into the buffer, or `None` when the request is invalid or does not fit.
+    fn bump(&mut self, size: usize, alignment: usize) -> Option<usize> {
+        if size == 0 || alignment == 0 || !alignment.is_power_of_two() {
+            return None;
+        }
+
+        let aligned_offset = self.used.checked_add(alignment - 1)? & !(alignment - 1);
+        let new_used = aligned_offset.checked_add(size)?;
+        if new_used > self.size {
+            return None;
+        }
+
+        self.used = new_used;
+        self.peak = self.peak.max(new_used);
+        Some(aligned_offset)
+    }
+
+    fn reset(&mut self) {
+        self.used = 0;
+    }
+}
+
 static MEMORY_POOL: Lazy<Mutex<MemoryPool>> = Lazy::new(|| {
     Mutex::new(MemoryPool {
         buffer: 0,
         size: 0,
         used: 0,
+        peak: 0,
         initialized: false,
     })
 });
@@ -3551,114 +6194,702 @@ pub fn init_memory_pool() -> bool {
         )
     };
 
-    if buffer == libc::MAP_FAILED {
-        return false;
+    if buffer == libc::MAP_FAILED {
+        return false;
+    }
+
+    *pool = MemoryPool {
+        buffer: buffer as usize,
+        size: MEMORY_POOL_SIZE,
+        used: 0,
+        peak: 0,
+        initialized: true,
+    };
+
+    true
+}
+
+#[cfg(target_os = "android")]
+pub fn allocate_from_pool(size: usize, alignment: usize) -> anyhow::Result<std::ptr::NonNull<u8>> {
+    let mut pool = MEMORY_POOL
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if !pool.initialized || pool.buffer == 0 {
+        return Err(anyhow::anyhow!("Memory pool is not initialized"));
+    }
+    if size == 0 || alignment == 0 || !alignment.is_power_of_two() {
+        return Err(anyhow::anyhow!(
+            "Invalid pool allocation: {} bytes aligned to {}",
+            size,
+            alignment
+        ));
+    }
+
+    let Some(aligned_offset) = pool.bump(size, alignment) else {
+        return Err(anyhow::anyhow!(
+            "Insufficient memory: pool cannot fit {} bytes ({} of {} bytes used)",
+            size,
+            pool.used,
+            pool.size
+        ));
+    };
+    // SAFETY: `pool.buffer` is a live, non-null mmap allocation while
+    // `initialized` is true. `bump` only returns offsets whose allocation ends
+    // within `pool.size`, aligned to a power-of-two alignment.
+    let ptr = unsafe { (pool.buffer as *mut u8).add(aligned_offset) };
+    std::ptr::NonNull::new(ptr).ok_or_else(|| anyhow::anyhow!("Memory pool returned null"))
+}
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub fn reset_pool() {
+    #[cfg(target_os = "android")]
+    {
+        let mut pool = MEMORY_POOL
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        pool.reset();
+    }
+
+    #[cfg(target_os = "ios")]
+    {
+        let _ = ();
+    }
+}
+
+#[cfg(target_os = "android")]
+pub fn cleanup_memory_pool() {
+    let mut pool = MEMORY_POOL
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if pool.initialized && pool.buffer != 0 {
+        // SAFETY: The buffer/size pair was created by `init_memory_pool` with
+        // mmap and is still marked initialized under the same mutex. State is
+        // cleared immediately after munmap to prevent double unmapping.
+        unsafe {
+            libc::munmap(pool.buffer as *mut libc::c_void, pool.size);
+        }
+        pool.initialized = false;
+        pool.buffer = 0;
+        pool.size = 0;
+        pool.used = 0;
+        pool.peak = 0;
+    }
+}
+
+/// Report memory pool usage so callers can back off before `allocate_from_pool`
+/// starts failing. Any of the out pointers may be null.
+/// Returns 0 when the pool is initialized, -1 otherwise (values are then 0).
+#[no_mangle]
+pub extern "C" fn gpuf_memory_pool_stats(
+    used: *mut usize,
+    peak: *mut usize,
+    size: *mut usize,
+) -> c_int {
+    let pool = MEMORY_POOL
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    for (out, value) in [(used, pool.used), (peak, pool.peak), (size, pool.size)] {
+        if !out.is_null() {
+            // SAFETY: Non-null out pointers are caller-provided `size_t`
+            // locations that must be valid for writes.
+            unsafe { *out = value };
+        }
+    }
+
+    if pool.initialized {
+        0
+    } else {
+        -1
+    }
+}
+
+// ============================================================================
+// Async Generation Control Functions
+// ============================================================================
+
+/// Stop ongoing generation
+#[no_mangle]
+pub extern "C" fn gpuf_stop_generation(_ctx: *mut llama_context) -> c_int {
+    println!("🛑 Stopping generation...");
+    set_generation_stop(true);
+
+    // Wait a bit for generation to stop
+    std::thread::sleep(std::time::Duration::from_millis(100));
+
+    println!("✅ Generation stop signal sent");
+    0
+}
+
+/// Stop only the generation started as request `handle` by
+/// `gpuf_start_generation_async_cancellable`; other requests keep running.
+/// Returns 0 if the request was signalled, -1 if it is unknown or already
+/// finished.
+#[no_mangle]
+pub extern "C" fn gpuf_cancel_request(handle: u64) -> c_int {
+    if cancel_generation_request(handle) {
+        println!("🛑 Cancelling generation request {}", handle);
+        0
+    } else {
+        -1
+    }
+}
+
+/// Configure the generation loop detector.
+///
+/// Generation stops with finish reason "repetition" once any pattern of 1 to
+/// `ngram_size` tokens repeats back-to-back more than `max_repeats` times.
+/// Passing 0 for either value disables the check. Returns 0 on success, -1 on
+/// negative input.
+#[no_mangle]
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub extern "C" fn gpuf_set_repetition_guard(ngram_size: c_int, max_repeats: c_int) -> c_int {
+    if ngram_size < 0 || max_repeats < 0 {
+        return -1;
+    }
+    REPETITION_NGRAM_SIZE.store(ngram_size as usize, Ordering::Relaxed);
+    REPETITION_MAX_REPEATS.store(max_repeats as usize, Ordering::Relaxed);
+    0
+}
+
+#[no_mangle]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub extern "C" fn gpuf_set_repetition_guard(_ngram_size: c_int, _max_repeats: c_int) -> c_int {
+    -1
+}
+
+/// Configure the frequency and presence penalties of mobile generation and
+/// how many recent tokens the penalties (including the per-call repeat
+/// penalty) look back over.
+///
+/// Both penalties default to 0 (off); `penalty_last_n` defaults to -1, the
+/// whole context, and 0 disables the penalties. Returns 0 on success, -1 on a
+/// non-finite penalty or `penalty_last_n` below -1.
+#[no_mangle]
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub extern "C" fn gpuf_set_penalties(
+    frequency_penalty: f32,
+    presence_penalty: f32,
+    penalty_last_n: c_int,
+) -> c_int {
+    if !frequency_penalty.is_finite() || !presence_penalty.is_finite() || penalty_last_n < -1 {
+        return -1;
+    }
+    FREQUENCY_PENALTY_BITS.store(frequency_penalty.to_bits(), Ordering::Relaxed);
+    PRESENCE_PENALTY_BITS.store(presence_penalty.to_bits(), Ordering::Relaxed);
+    PENALTY_LAST_N.store(penalty_last_n, Ordering::Relaxed);
+    0
+}
+
+#[no_mangle]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub extern "C" fn gpuf_set_penalties(
+    _frequency_penalty: f32,
+    _presence_penalty: f32,
+    _penalty_last_n: c_int,
+) -> c_int {
+    -1
+}
+
+/// Cap streaming generation at `max_tokens_per_sec` tokens/s by sleeping
+/// between tokens, keeping passively cooled devices under a thermal ceiling at
+/// the cost of latency. 0 removes the cap. Returns 0 on success, -1 on negative
+/// input.
+#[no_mangle]
+pub extern "C" fn gpuf_set_max_tokens_per_sec(max_tokens_per_sec: c_int) -> c_int {
+    if max_tokens_per_sec < 0 {
+        return -1;
+    }
+    util::generation::set_max_tokens_per_sec(max_tokens_per_sec as u32);
+    0
+}
+
+/// Set which `<|...|>` control tokens are stripped from streamed output.
+/// `tokens` is a comma-separated list: with `keep_only` 0 exactly these are
+/// stripped, otherwise every control token except these is. NULL restores the
+/// built-in list. Returns 0 on success, -1 if `tokens` is not valid UTF-8.
+#[no_mangle]
+pub extern "C" fn gpuf_set_control_tokens(tokens: *const c_char, keep_only: c_int) -> c_int {
+    if tokens.is_null() {
+        util::generation::set_control_tokens(util::generation::ControlTokens::default());
+        return 0;
+    }
+    // SAFETY: `tokens` was checked for null; the caller passes a
+    // NUL-terminated string that stays valid for the duration of the call.
+    let Ok(list) = unsafe { CStr::from_ptr(tokens) }.to_str() else {
+        return -1;
+    };
+    let list: Vec<String> = list
+        .split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect();
+    util::generation::set_control_tokens(if keep_only != 0 {
+        util::generation::ControlTokens::Allowed(list)
+    } else {
+        util::generation::ControlTokens::Blocked(list)
+    });
+    0
+}
+
+/// Pin inference threads to the CPUs set in `core_mask` (bit N = CPU N), e.g.
+/// to keep them off efficiency cores. 0 removes the pinning. Takes effect on
+/// the next generation; a no-op on platforms without `sched_setaffinity`.
+/// Returns 0.
+#[no_mangle]
+pub extern "C" fn gpuf_set_thread_affinity(core_mask: u64) -> c_int {
+    util::affinity::set_thread_affinity_mask(core_mask);
+    0
+}
+
+/// Pin inference threads to the cores clocked above the slowest cluster
+/// (the big/prime cores of a big.LITTLE SoC), or remove the pinning when
+/// `enabled` is false. Returns the number of cores selected; 0 when disabled
+/// or when the CPU has no distinct performance cores, leaving threads unpinned.
+#[no_mangle]
+pub extern "C" fn gpuf_set_performance_cores_only(enabled: bool) -> c_int {
+    let mask = if enabled {
+        util::affinity::performance_core_mask(&util::affinity::cpu_max_freqs())
+    } else {
+        0
+    };
+    util::affinity::set_thread_affinity_mask(mask);
+    mask.count_ones() as c_int
+}
+
+/// Set the stop words used by the streaming and multimodal generation loops.
+///
+/// Generation halts once the output contains any of them, and the matched stop
+/// word is left out of the emitted text. Passing `n_stop_words == 0` clears the
+/// list. Returns 0 on success, -1 on a null or non-UTF-8 entry.
+///
+/// # Safety
+/// `stop_words` must point to `n_stop_words` NUL-terminated C strings.
+#[no_mangle]
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub extern "C" fn gpuf_set_stop_words(
+    stop_words: *const *const c_char,
+    n_stop_words: c_int,
+) -> c_int {
+    if n_stop_words < 0 || (n_stop_words > 0 && stop_words.is_null()) {
+        return -1;
+    }
+
+    let mut words = Vec::with_capacity(n_stop_words as usize);
+    for i in 0..n_stop_words as usize {
+        // SAFETY: `stop_words` is non-null and holds `n_stop_words` entries per
+        // the caller contract; each entry is checked for null before reading.
+        let word = unsafe { *stop_words.add(i) };
+        if word.is_null() {
+            return -1;
+        }
+        // SAFETY: `word` is non-null and NUL-terminated per the caller contract.
+        match unsafe { CStr::from_ptr(word) }.to_str() {
+            Ok(word) => words.push(word.to_string()),
+            Err(_) => return -1,
+        }
     }
 
-    *pool = MemoryPool {
-        buffer: buffer as usize,
-        size: MEMORY_POOL_SIZE,
-        used: 0,
-        initialized: true,
+    *STOP_WORDS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = words;
+    0
+}
+
+/// Set the seed used by the final sampling step of mobile generation.
+///
+/// A fixed seed makes output reproducible for the same prompt and parameters
+/// (the default is 1234); `0xFFFFFFFF` draws a random seed for every generation.
+#[no_mangle]
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub extern "C" fn gpuf_set_seed(seed: u32) {
+    SAMPLER_SEED.store(seed, Ordering::Relaxed);
+}
+
+#[no_mangle]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub extern "C" fn gpuf_set_seed(_seed: u32) {}
+
+#[no_mangle]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub extern "C" fn gpuf_set_stop_words(
+    _stop_words: *const *const c_char,
+    _n_stop_words: c_int,
+) -> c_int {
+    -1
+}
+
+/// Finish reason of the most recent generation: "stop", "length" or
+/// "repetition". Returns NULL if nothing has been generated yet. The returned
+/// string is static and must not be freed.
+#[no_mangle]
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub extern "C" fn gpuf_get_last_finish_reason() -> *const c_char {
+    let reason: &'static [u8] = match LAST_FINISH_REASON.load(Ordering::SeqCst) {
+        1 => b"stop\0",
+        2 => b"length\0",
+        3 => b"repetition\0",
+        _ => return std::ptr::null(),
     };
+    reason.as_ptr() as *const c_char
+}
 
-    true
+#[no_mangle]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub extern "C" fn gpuf_get_last_finish_reason() -> *const c_char {
+    std::ptr::null()
 }
 
-#[cfg(target_os = "android")]
-pub fn allocate_from_pool(size: usize, alignment: usize) -> *mut u8 {
-    if size == 0 || alignment == 0 || !alignment.is_power_of_two() {
-        return std::ptr::null_mut();
+/// Copy why the last `gpuf_load_model` / `gpuf_load_multimodal_model` call
+/// failed into `out` (NUL terminated, truncated to fit), e.g. "Insufficient
+/// memory: model needs about 4352 MB but only 2911 MB is available".
+/// Returns the bytes copied, 0 if the last load succeeded, -1 on invalid arguments.
+///
+/// # Safety
+/// `out` must point to a writable buffer of at least `out_len` bytes.
+#[no_mangle]
+pub extern "C" fn gpuf_get_last_load_error(out: *mut c_char, out_len: c_int) -> c_int {
+    if out.is_null() || out_len <= 0 {
+        return -1;
+    }
+    let error = LAST_LOAD_ERROR
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let message = utf8_prefix(error.as_deref().unwrap_or(""), out_len as usize - 1);
+    // SAFETY: `out` is non-null and holds `out_len` bytes per the caller
+    // contract; at most `out_len - 1` bytes plus the NUL are written.
+    unsafe {
+        std::ptr::copy_nonoverlapping(message.as_ptr(), out as *mut u8, message.len());
+        *out.add(message.len()) = 0;
     }
+    message.len() as c_int
+}
 
-    let mut pool = MEMORY_POOL
+/// Total length in bytes of the last buffered generation result.
+///
+/// `gpuf_generate_with_sampling`, `gpuf_continue_generation` and
+/// `gpuf_generate_multimodal` keep their full output even when it doesn't fit
+/// the caller's buffer. The output was truncated when this is larger than the
+/// length they returned; `gpuf_get_last_result` then returns the rest.
+#[no_mangle]
+pub extern "C" fn gpuf_get_last_result_len() -> c_int {
+    let last = LAST_RESULT
         .lock()
         .unwrap_or_else(|poisoned| poisoned.into_inner());
-    if !pool.initialized || pool.buffer == 0 {
-        return std::ptr::null_mut();
+    last.text.len().min(c_int::MAX as usize) as c_int
+}
+
+/// Copy the next chunk of the last buffered generation result into `out`.
+///
+/// Each call continues where the previous one stopped. After a new generation
+/// it starts right after the part already copied into the generation's own
+/// output buffer, so it returns 0 straight away unless that output was
+/// truncated. Chunks never split a UTF-8 character and are NUL terminated.
+/// Returns the bytes written, 0 once the whole result has been read, or -1 on
+/// invalid arguments (`out_len` must be at least 5 so a full character always
+/// fits).
+#[no_mangle]
+pub extern "C" fn gpuf_get_last_result(out: *mut c_char, out_len: c_int) -> c_int {
+    if out.is_null() || out_len < 5 {
+        return -1;
     }
 
-    // Calculate aligned offset
-    let current_offset = pool.used;
-    let aligned_offset = (current_offset + alignment - 1) & !(alignment - 1);
-    let new_used = aligned_offset.saturating_add(size);
+    let mut last = LAST_RESULT
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let LastResult { text, cursor } = &mut *last;
+    let chunk = utf8_prefix(&text[*cursor..], out_len as usize - 1);
 
-    // Check if we have enough space
-    if new_used > pool.size {
-        return std::ptr::null_mut();
+    // SAFETY: `out` is non-null and the caller guarantees it is writable for
+    // `out_len` bytes; `chunk` is at most `out_len - 1` bytes.
+    unsafe {
+        std::ptr::copy_nonoverlapping(chunk.as_ptr(), out as *mut u8, chunk.len());
+        *out.add(chunk.len()) = 0;
     }
+    *cursor += chunk.len();
+    chunk.len() as c_int
+}
 
-    // Update pool state and return pointer
-    pool.used = new_used;
-    // SAFETY: `pool.buffer` is a live mmap allocation while `initialized` is
-    // true. Bounds were checked with `new_used <= pool.size`, and
-    // `aligned_offset` was derived from a power-of-two alignment.
-    unsafe { (pool.buffer as *mut u8).add(aligned_offset) }
+/// Copy the timings of the most recent generation into `out`.
+///
+/// Covers `gpuf_start_generation_async` and the buffered completion path:
+/// prompt evaluation, time spent sampling and decoding generated tokens, and
+/// the wall-clock total. Returns 0 on success, -1 if `out` is null or nothing
+/// has been generated yet.
+#[no_mangle]
+pub extern "C" fn gpuf_get_last_timings(out: *mut llama_timings) -> c_int {
+    if out.is_null() {
+        return -1;
+    }
+    let Some(timings) = LAST_TIMINGS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+    else {
+        return -1;
+    };
+    // SAFETY: `out` is non-null and the caller guarantees it points to a
+    // writable `llama_timings`.
+    unsafe { std::ptr::copy_nonoverlapping(&timings, out, 1) };
+    0
 }
 
+/// Start async generation with streaming callback (simplified version)
+///
+/// Uses sequence 0; see `gpuf_start_generation_async_seq` to run on another sequence.
+#[no_mangle]
 #[cfg(any(target_os = "android", target_os = "ios"))]
-pub fn reset_pool() {
-    #[cfg(target_os = "android")]
-    {
-        let mut pool = MEMORY_POOL
-            .lock()
-            .unwrap_or_else(|poisoned| poisoned.into_inner());
-        pool.used = 0;
-    }
+pub extern "C" fn gpuf_start_generation_async(
+    ctx: *mut llama_context,
+    prompt: *const c_char,
+    max_tokens: c_int,
+    temperature: f32,
+    top_k: c_int,
+    top_p: f32,
+    repeat_penalty: f32,
+    on_token_callback: Option<extern "C" fn(*const c_char, *mut c_void)>,
+    user_data: *mut c_void,
+) -> c_int {
+    gpuf_start_generation_async_seq(
+        ctx,
+        0,
+        prompt,
+        max_tokens,
+        temperature,
+        top_k,
+        top_p,
+        repeat_penalty,
+        on_token_callback,
+        user_data,
+    )
+}
 
-    #[cfg(target_os = "ios")]
-    {
-        let _ = ();
-    }
+/// Start async generation on a specific KV sequence.
+///
+/// Only the KV cells of `seq_id` are cleared before prefill, so other sequences sharing
+/// the same context (parallel chats) keep their state. The context must have been
+/// created with `n_seq_max > seq_id`.
+#[no_mangle]
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub extern "C" fn gpuf_start_generation_async_seq(
+    ctx: *mut llama_context,
+    seq_id: c_int,
+    prompt: *const c_char,
+    max_tokens: c_int,
+    temperature: f32,
+    top_k: c_int,
+    top_p: f32,
+    repeat_penalty: f32,
+    on_token_callback: Option<extern "C" fn(*const c_char, *mut c_void)>,
+    user_data: *mut c_void,
+) -> c_int {
+    stream_generation_seq(
+        ctx,
+        seq_id,
+        prompt,
+        max_tokens,
+        temperature,
+        top_k,
+        top_p,
+        repeat_penalty,
+        &configured_stop_words(),
+        on_token_callback,
+        user_data,
+    )
 }
 
-#[cfg(target_os = "android")]
-pub fn cleanup_memory_pool() {
-    let mut pool = MEMORY_POOL
-        .lock()
-        .unwrap_or_else(|poisoned| poisoned.into_inner());
-    if pool.initialized && pool.buffer != 0 {
-        // SAFETY: The buffer/size pair was created by `init_memory_pool` with
-        // mmap and is still marked initialized under the same mutex. State is
-        // cleared immediately after munmap to prevent double unmapping.
-        unsafe {
-            libc::munmap(pool.buffer as *mut libc::c_void, pool.size);
-        }
-        pool.initialized = false;
-        pool.buffer = 0;
-        pool.size = 0;
-        pool.used = 0;
+/// Stream a completion described by `params` on sequence 0.
+///
+/// Like `gpuf_start_generation_async` with the prompt and sampling settings
+/// taken from `params`. Generation halts at any of `params.stop_words`; when
+/// the array holds no usable entries, the `gpuf_set_stop_words` list applies.
+/// Returns the number of generated tokens, or a negative value on error.
+///
+/// # Safety
+/// `params` must point to a valid `llama_completion_params` whose `prompt` is
+/// a NUL-terminated C string and whose `stop_words` is null or points to
+/// `n_stop_words` entries, each null or a NUL-terminated C string.
+#[no_mangle]
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub extern "C" fn gpuf_start_completion(
+    ctx: *mut llama_context,
+    params: *const llama_completion_params,
+    on_token_callback: Option<extern "C" fn(*const c_char, *mut c_void)>,
+    user_data: *mut c_void,
+) -> c_int {
+    if params.is_null() {
+        println!("❌ Invalid completion params");
+        return -1;
     }
+    // SAFETY: `params` was checked for null and points to valid params per
+    // the caller contract.
+    let params = unsafe { &*params };
+    let mut stop_words = collect_stop_words(params);
+    if stop_words.is_empty() {
+        stop_words = configured_stop_words();
+    }
+    stream_generation_seq(
+        ctx,
+        0,
+        params.prompt,
+        params.n_predict,
+        params.temperature,
+        params.top_k,
+        params.top_p,
+        params.repeat_penalty,
+        &stop_words,
+        on_token_callback,
+        user_data,
+    )
 }
 
-// ============================================================================
-// Async Generation Control Functions
-// ============================================================================
+#[no_mangle]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub extern "C" fn gpuf_start_completion(
+    _ctx: *mut llama_context,
+    _params: *const llama_completion_params,
+    _on_token_callback: Option<extern "C" fn(*const c_char, *mut c_void)>,
+    _user_data: *mut c_void,
+) -> c_int {
+    -1
+}
 
-/// Stop ongoing generation
+/// Start generation on a specific KV sequence in the background.
+///
+/// Same as `gpuf_start_generation_async_seq`, but returns immediately with a
+/// request handle for `gpuf_cancel_request`. The prompt is copied before
+/// returning; `ctx` and `user_data` must stay valid until `on_done` is called
+/// with the handle and the generated token count (negative on error).
+/// Requests run one at a time. Returns 0 if the request could not be started.
 #[no_mangle]
-pub extern "C" fn gpuf_stop_generation(_ctx: *mut llama_context) -> c_int {
-    println!("🛑 Stopping generation...");
-    set_generation_stop(true);
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub extern "C" fn gpuf_start_generation_async_cancellable(
+    ctx: *mut llama_context,
+    seq_id: c_int,
+    prompt: *const c_char,
+    max_tokens: c_int,
+    temperature: f32,
+    top_k: c_int,
+    top_p: f32,
+    repeat_penalty: f32,
+    on_token_callback: Option<extern "C" fn(*const c_char, *mut c_void)>,
+    on_done: Option<extern "C" fn(u64, c_int, *mut c_void)>,
+    user_data: *mut c_void,
+) -> u64 {
+    if ctx.is_null() || prompt.is_null() {
+        println!("❌ Invalid context or prompt for async generation");
+        return 0;
+    }
+    // SAFETY: `prompt` was checked for null and must be NUL-terminated.
+    let prompt = unsafe { CStr::from_ptr(prompt) }.to_owned();
+    let stop_words = configured_stop_words();
+    // Raw pointers are not Send; the caller keeps them alive until on_done
+    let (ctx, user_data) = (ctx as usize, user_data as usize);
+
+    let started = spawn_generation_request(move |handle| {
+        let result = {
+            let _inference_lock = GLOBAL_INFERENCE_MUTEX
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            stream_generation_seq(
+                ctx as *mut llama_context,
+                seq_id,
+                prompt.as_ptr(),
+                max_tokens,
+                temperature,
+                top_k,
+                top_p,
+                repeat_penalty,
+                &stop_words,
+                on_token_callback,
+                user_data as *mut c_void,
+            )
+        };
+        if let Some(on_done) = on_done {
+            on_done(handle, result, user_data as *mut c_void);
+        }
+        result
+    });
+    match started {
+        Ok((handle, _)) => handle,
+        Err(e) => {
+            println!("❌ Failed to start generation thread: {}", e);
+            0
+        }
+    }
+}
 
-    // Wait a bit for generation to stop
-    std::thread::sleep(std::time::Duration::from_millis(100));
+/// Runs a generation on the loaded model as a cancellable request and returns
+/// its pieces as an async stream, so Rust callers consume mobile generation
+/// the same way as `LlamaEngine::stream_with_cached_model_sampling`. Dropping
+/// the stream cancels the request.
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub(crate) fn stream_loaded_model(
+    prompt: &str,
+    max_tokens: c_int,
+    temperature: f32,
+    top_k: c_int,
+    top_p: f32,
+    repeat_penalty: f32,
+) -> anyhow::Result<util::token_stream::TokenStream> {
+    use util::token_stream::{token_channel, TokenSink};
 
-    println!("✅ Generation stop signal sent");
-    0
+    let prompt = CString::new(prompt).map_err(|e| anyhow::anyhow!("Invalid prompt: {}", e))?;
+    let stop_words = configured_stop_words();
+    let (mut sink, stream) = token_channel();
+
+    spawn_generation_request(move |_| {
+        if let Some(cancel) = CURRENT_REQUEST_CANCEL.with(|current| current.borrow().clone()) {
+            sink.cancel_on_close(cancel);
+        }
+        let result = {
+            // Held across the whole generation so the model cannot be swapped
+            // or unloaded underneath it
+            let _inference_lock = GLOBAL_INFERENCE_MUTEX
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let ctx = GLOBAL_CONTEXT_PTR.load(Ordering::SeqCst);
+            if ctx.is_null() {
+                println!("❌ No model loaded for streaming generation");
+                -1
+            } else {
+                stream_generation_seq(
+                    ctx,
+                    0,
+                    prompt.as_ptr(),
+                    max_tokens,
+                    temperature,
+                    top_k,
+                    top_p,
+                    repeat_penalty,
+                    &stop_words,
+                    Some(TokenSink::callback()),
+                    sink.as_user_data(),
+                )
+            }
+        };
+        sink.finish(result);
+        result
+    })
+    .map_err(|e| anyhow::anyhow!("Failed to start generation thread: {}", e))?;
+    Ok(stream)
 }
 
-/// Start async generation with streaming callback (simplified version)
-#[no_mangle]
+// Streaming generation loop behind the async entry points. Generation halts
+// once the decoded text reaches any of `stop_words`; the stop word itself is
+// never passed to the callback.
 #[cfg(any(target_os = "android", target_os = "ios"))]
-pub extern "C" fn gpuf_start_generation_async(
+fn stream_generation_seq(
     ctx: *mut llama_context,
+    seq_id: c_int,
     prompt: *const c_char,
     max_tokens: c_int,
     temperature: f32,
     top_k: c_int,
     top_p: f32,
     repeat_penalty: f32,
+    stop_words: &[String],
     on_token_callback: Option<extern "C" fn(*const c_char, *mut c_void)>,
     user_data: *mut c_void,
 ) -> c_int {
@@ -3666,6 +6897,12 @@ pub extern "C" fn gpuf_start_generation_async(
         println!("❌ Invalid context or prompt for async generation");
         return -1;
     }
+    if seq_id < 0 {
+        println!("❌ Invalid sequence id {}", seq_id);
+        return -1;
+    }
+    record_generation_backend(ctx);
+    pin_inference_thread();
 
     // Initialize generation control
     init_generation_control();
@@ -3685,15 +6922,17 @@ pub extern "C" fn gpuf_start_generation_async(
 
         // Reset memory pool
         reset_pool();
+        let mut timer = GenerationTimer::start();
 
-        // Clear KV cache for sequence 0 (remove all positions)
-        let kv = llama_get_memory(ctx);
-        let clear_result = llama_memory_seq_rm(kv, 0, -1, -1);
-        if !clear_result {
-            println!("⚠️ llama_memory_seq_rm failed, trying full clear...");
-            llama_memory_clear(kv, false);
+        // Clear KV cache for this sequence only (remove all positions)
+        if seq_id == 0 {
+            CONTINUABLE_CONTEXT_PTR.store(std::ptr::null_mut(), Ordering::SeqCst);
+        }
+        if !Backend::memory_seq_rm(ctx, seq_id, -1, -1) {
+            println!("❌ llama_memory_seq_rm failed for sequence {}", seq_id);
+            return -1;
         }
-        println!("✅ KV cache cleared for clean generation");
+        println!("✅ KV cache cleared for sequence {}", seq_id);
 
         // Tokenize prompt using real llama.cpp tokenizer
         let model = llama_get_model(ctx);
@@ -3756,6 +6995,11 @@ pub extern "C" fn gpuf_start_generation_async(
 
         let mut batch_pos_array = [0i32; 512];
         let mut logits_array = [0i8; 512];
+        // Every token in these batches belongs to the single sequence `seq_id`
+        let mut seq_id_value: LlamaSeqId = seq_id;
+        let seq_id_ptr: *mut LlamaSeqId = &mut seq_id_value;
+        let mut n_seq_id_array = [1 as c_int; 512];
+        let mut seq_id_array = [seq_id_ptr; 512];
 
         let mut n_past: i32 = 0;
         let mut start: i32 = 0;
@@ -3778,8 +7022,8 @@ pub extern "C" fn gpuf_start_generation_async(
                 token: tokens.as_ptr().add(start as usize) as *mut LlamaToken,
                 embd: std::ptr::null_mut(),
                 pos: batch_pos_array.as_ptr() as *mut LlamaPos,
-                n_seq_id: std::ptr::null_mut(),
-                seq_id: std::ptr::null_mut(),
+                n_seq_id: n_seq_id_array.as_mut_ptr(),
+                seq_id: seq_id_array.as_mut_ptr(),
                 logits: logits_array.as_ptr() as *mut i8,
             };
 
@@ -3797,39 +7041,39 @@ pub extern "C" fn gpuf_start_generation_async(
         }
 
         println!("🔍 Model and vocab ready, starting generation loop...");
+        timer.prompt_evaluated();
 
         // Initialize sampler
-        let temp_sampler = llama_sampler_init_temp(temperature);
-        let top_k_sampler = llama_sampler_init_top_k(top_k);
-        let top_p_sampler = llama_sampler_init_top_p(top_p, 1);
-        let repeat_sampler = llama_sampler_init_penalties(-1, repeat_penalty, 0.0, 0.0);
-        let dist_sampler = llama_sampler_init_dist(1234);
-
-        let chain_params = llama_sampler_chain_params { no_perf: false };
-        let sampler = llama_sampler_chain_init(chain_params);
-
-        llama_sampler_chain_add(sampler, temp_sampler);
-        llama_sampler_chain_add(sampler, top_k_sampler);
-        llama_sampler_chain_add(sampler, top_p_sampler);
-        llama_sampler_chain_add(sampler, repeat_sampler);
-        llama_sampler_chain_add(sampler, dist_sampler);
+        let sampler = build_sampler_chain(&mobile_sampling_params(
+            temperature,
+            top_k,
+            top_p,
+            repeat_penalty,
+        ));
+        if sampler.is_null() {
+            return -1;
+        }
 
         // Generate tokens with streaming callbacks
-        let n_ctx = llama_n_ctx(ctx) as i32;
-        let context_available = n_ctx - n_past;
-        let safe_generation_limit = std::cmp::min(max_tokens, context_available);
+        let n_ctx = Backend::n_ctx(ctx) as i32;
+        let safe_generation_limit = context_generation_limit(max_tokens, n_ctx, n_past);
         let mut next_pos = n_past;
-        let mut utf8_buf = Utf8EmitBuffer::new();
+        let mut text_stream = TokenTextStream::new(stop_words);
 
         let mut completion_tokens: c_int = 0;
+        let mut repetition = repetition_detector();
+        let mut rate_limiter = util::generation::TokenRateLimiter::from_config();
+        let mut finish_reason = util::generation::FinishReason::Length;
         for _i in 0..safe_generation_limit {
             // Check for stop signal
             if should_stop_generation() {
                 println!("⏹️ Generation stopped by user");
+                finish_reason = util::generation::FinishReason::Stop;
                 break;
             }
 
             // Sample next token using llama.cpp sampler
+            let token_started = Instant::now();
             let sampled_token = llama_sampler_sample(sampler, ctx, -1);
 
             println!(
@@ -3841,6 +7085,7 @@ pub extern "C" fn gpuf_start_generation_async(
             // Check EOS
             if llama_vocab_is_eog(vocab, sampled_token) {
                 println!("🔍 EOS token detected, stopping generation");
+                finish_reason = util::generation::FinishReason::Stop;
                 break;
             }
 
@@ -3873,32 +7118,18 @@ pub extern "C" fn gpuf_start_generation_async(
                     );
                 }
 
-                let emitted = utf8_buf.push_and_take_valid(&token_buf[..piece_len]);
+                let (emitted, stopped) = text_stream.push(&token_buf[..piece_len]);
                 println!(
                     "🔍 Token content redacted (emitted {} bytes, raw {} bytes)",
                     emitted.len(),
                     raw_len
                 );
 
-                // Call callback only if it's not None
-                if !emitted.is_empty() {
-                    if let Some(callback) = on_token_callback {
-                        println!("🔍 Calling callback with token...");
-                        match std::ffi::CString::new(emitted.as_str()) {
-                            Ok(token_cstr) => {
-                                callback(token_cstr.as_ptr(), user_data);
-                                println!("🔍 Callback completed");
-                            }
-                            Err(_) => {
-                                println!("⚠️ Token callback skipped - CString conversion failed");
-                            }
-                        }
-                    } else {
-                        println!(
-                            "🔍 No callback - token text redacted ({} bytes)",
-                            emitted.len()
-                        );
-                    }
+                emit_token_text(on_token_callback, &emitted, user_data);
+                if stopped {
+                    println!("🛑 Stop word matched, stopping generation");
+                    finish_reason = util::generation::FinishReason::Stop;
+                    break;
                 }
             } else if token_len < 0 {
                 println!(
@@ -3915,8 +7146,8 @@ pub extern "C" fn gpuf_start_generation_async(
                 token: (&sampled_token as *const LlamaToken) as *mut LlamaToken,
                 embd: std::ptr::null_mut(),
                 pos: (&next_pos as *const LlamaPos) as *mut LlamaPos,
-                n_seq_id: std::ptr::null_mut(),
-                seq_id: std::ptr::null_mut(),
+                n_seq_id: n_seq_id_array.as_mut_ptr(),
+                seq_id: seq_id_array.as_mut_ptr(),
                 logits: std::ptr::null_mut(),
             };
 
@@ -3924,23 +7155,28 @@ pub extern "C" fn gpuf_start_generation_async(
             if llama_decode(ctx, single_token_batch) != 0 {
                 break;
             }
+            timer.token_evaluated(token_started);
 
             next_pos += 1;
+
+            if repetition.push(sampled_token) {
+                println!("🛑 Repetition loop detected, stopping generation");
+                finish_reason = util::generation::FinishReason::Repetition;
+                break;
+            }
+
+            pace_generation(&mut rate_limiter);
         }
+        report_throttling(&rate_limiter);
 
         // Cleanup sampler
         llama_sampler_free(sampler);
+        set_last_finish_reason(finish_reason);
+        timer.finish();
 
         // Flush any remaining buffered bytes (best-effort)
-        let tail = utf8_buf.flush_lossy();
-
-        if !tail.is_empty() {
-            if let Some(callback) = on_token_callback {
-                if let Ok(token_cstr) = std::ffi::CString::new(tail.as_str()) {
-                    callback(token_cstr.as_ptr(), user_data);
-                }
-            }
-        }
+        let tail = text_stream.finish();
+        emit_token_text(on_token_callback, &tail, user_data);
 
         // Cleanup
         cleanup_generation_control();
@@ -4134,6 +7370,9 @@ pub extern "C" fn gpuf_validate_mobile_tls_policy(
 // ============================================================================
 
 /// Start remote worker and initialize global worker (C API)
+///
+/// Returns `GpufError::InvalidArgument` for a missing or malformed argument
+/// and `GpufError::WorkerStart` when the login fails.
 #[cfg(any(target_os = "android", target_os = "ios"))]
 #[no_mangle]
 pub extern "C" fn start_remote_worker(
@@ -4142,7 +7381,7 @@ pub extern "C" fn start_remote_worker(
     proxy_port: c_int,
     worker_type: *const c_char,
     client_id: *const c_char,
-) -> c_int {
+) -> GpufError {
     use crate::util::cmd::{Args, EngineType, LlamaSplitModeArg, WorkerType};
 
     println!("🔥 GPUFabric C API: Starting remote worker");
@@ -4150,7 +7389,7 @@ pub extern "C" fn start_remote_worker(
     // Convert C strings to Rust strings
     let server_addr_str = if server_addr.is_null() {
         eprintln!("❌ Error: server_addr is null");
-        return -1;
+        return GpufError::InvalidArgument;
     } else {
         // SAFETY: `server_addr` was checked for null and must remain a valid
         // NUL-terminated C string for the duration of this call.
@@ -4158,14 +7397,14 @@ pub extern "C" fn start_remote_worker(
             Ok(s) => s,
             Err(e) => {
                 eprintln!("❌ Error: Invalid server_addr UTF-8: {}", e);
-                return -1;
+                return GpufError::InvalidArgument;
             }
         }
     };
 
     let worker_type_str = if worker_type.is_null() {
         eprintln!("❌ Error: worker_type is null");
-        return -1;
+        return GpufError::InvalidArgument;
     } else {
         // SAFETY: `worker_type` was checked for null and must remain a valid
         // NUL-terminated C string for the duration of this call.
@@ -4173,14 +7412,14 @@ pub extern "C" fn start_remote_worker(
             Ok(s) => s,
             Err(e) => {
                 eprintln!("❌ Error: Invalid worker_type UTF-8: {}", e);
-                return -1;
+                return GpufError::InvalidArgument;
             }
         }
     };
 
     let client_id_str = if client_id.is_null() {
         eprintln!("❌ Error: client_id is null");
-        return -1;
+        return GpufError::InvalidArgument;
     } else {
         // SAFETY: `client_id` was checked for null and must remain a valid
         // NUL-terminated C string for the duration of this call.
@@ -4188,7 +7427,7 @@ pub extern "C" fn start_remote_worker(
             Ok(s) => s,
             Err(e) => {
                 eprintln!("❌ Error: Invalid client_id UTF-8: {}", e);
-                return -1;
+                return GpufError::InvalidArgument;
             }
         }
     };
@@ -4208,7 +7447,7 @@ pub extern "C" fn start_remote_worker(
         "WS" => WorkerType::WS,
         _ => {
             eprintln!("❌ Error: Unknown worker type: {}", worker_type_str);
-            return -1;
+            return GpufError::InvalidArgument;
         }
     };
 
@@ -4233,6 +7472,9 @@ pub extern "C" fn start_remote_worker(
         p2p_udp_port: 40000,
         p2p_bind_addr: "127.0.0.1".to_string(),
         p2p_public_listen: false,
+        p2p_send_window: 8,
+        turn_op_timeout_secs: 3,
+        turn_setup_timeout_secs: 10,
         cert_chain_path: "".to_string(),
         control_tls: false,
         control_tls_server_name: None,
@@ -4241,6 +7483,7 @@ pub extern "C" fn start_remote_worker(
         chat_template_path: None,
         standalone_llama: false,
         api_key: None,
+        max_concurrent_generations: 2,
         llama_model_path: None,
         n_gpu_layers: 99,
         n_ctx: 2048,  // Reduced for Android memory constraints
@@ -4249,6 +7492,21 @@ pub extern "C" fn start_remote_worker(
         llama_main_gpu: 0,
         llama_devices: None,
         stream_chunk_bytes: 256,
+        lite_heartbeat: false,
+        full_heartbeat_every: 10,
+        // Surface an error to the app instead of retrying in the background forever
+        connect_max_retries: 8,
+        log_prompts: false,
+        // Set with gpuf_set_max_tokens_per_sec
+        max_tokens_per_sec: 0,
+        quant_types: Vec::new(),
+        max_resident_models: 1,
+        model_load_timeout_secs: 120,
+        // Set with gpuf_set_thread_affinity
+        cpu_affinity: None,
+        // Set with gpuf_set_control_tokens
+        strip_control_tokens: Vec::new(),
+        keep_control_tokens: Vec::new(),
     };
 
     #[cfg(target_os = "android")]
@@ -4271,10 +7529,10 @@ pub extern "C" fn start_remote_worker(
             )
             .await
         }) {
-            Ok(_) => 0,
+            Ok(_) => GpufError::Ok,
             Err(e) => {
                 eprintln!("❌ C API: Failed to start and login Android worker: {}", e);
-                -1
+                GpufError::WorkerStart
             }
         }
     }
@@ -4296,10 +7554,10 @@ pub extern "C" fn start_remote_worker(
             )
             .await
         }) {
-            Ok(_) => 0,
+            Ok(_) => GpufError::Ok,
             Err(e) => {
                 eprintln!("❌ C API: Failed to login iOS worker: {}", e);
-                -1
+                GpufError::WorkerStart
             }
         }
     }
@@ -4308,7 +7566,8 @@ pub extern "C" fn start_remote_worker(
 /// Start remote worker over TLS and initialize global worker (C API)
 ///
 /// This is additive: `start_remote_worker` keeps the legacy plaintext behavior.
-/// Returns -2 when the TLS CA/SNI/SHA256 pin policy is invalid.
+/// Returns `GpufError::InvalidTlsPolicy` when the TLS CA/SNI/SHA256 pin policy
+/// is invalid.
 #[cfg(any(target_os = "android", target_os = "ios"))]
 #[no_mangle]
 pub extern "C" fn start_remote_worker_with_tls(
@@ -4320,41 +7579,41 @@ pub extern "C" fn start_remote_worker_with_tls(
     ca_cert_path: *const c_char,
     control_tls_server_name: *const c_char,
     cert_sha256_pin: *const c_char,
-) -> c_int {
+) -> GpufError {
     use crate::util::mobile_control_stream::MobileControlTlsConfig;
 
     println!("🔥 GPUFabric C API: Starting TLS remote worker");
 
     let server_addr_str = match required_c_string(server_addr) {
         Ok(s) => s,
-        Err(code) => return code,
+        Err(_) => return GpufError::InvalidArgument,
     };
     let worker_type_str = match required_c_string(worker_type) {
         Ok(s) => s,
-        Err(code) => return code,
+        Err(_) => return GpufError::InvalidArgument,
     };
     let client_id_str = match required_c_string(client_id) {
         Ok(s) => s,
-        Err(code) => return code,
+        Err(_) => return GpufError::InvalidArgument,
     };
     let ca_cert_path = match optional_c_string(ca_cert_path) {
         Ok(s) => s,
-        Err(code) => return code,
+        Err(_) => return GpufError::InvalidArgument,
     };
     let control_tls_server_name = match optional_c_string(control_tls_server_name) {
         Ok(s) => s.unwrap_or_else(|| server_addr_str.clone()),
-        Err(code) => return code,
+        Err(_) => return GpufError::InvalidArgument,
     };
     let cert_sha256_pin = match optional_c_string(cert_sha256_pin) {
         Ok(s) => s,
-        Err(code) => return code,
+        Err(_) => return GpufError::InvalidArgument,
     };
 
     match worker_type_str.as_str() {
         "TCP" | "WS" => {}
         _ => {
             eprintln!("❌ Error: Unknown worker type: {}", worker_type_str);
-            return -1;
+            return GpufError::InvalidArgument;
         }
     }
 
@@ -4367,7 +7626,7 @@ pub extern "C" fn start_remote_worker_with_tls(
         Ok(config) => config,
         Err(e) => {
             eprintln!("❌ Error: Invalid mobile control TLS policy: {}", e);
-            return -2;
+            return GpufError::InvalidTlsPolicy;
         }
     };
 
@@ -4398,13 +7657,13 @@ pub extern "C" fn start_remote_worker_with_tls(
             )
             .await
         }) {
-            Ok(_) => 0,
+            Ok(_) => GpufError::Ok,
             Err(e) => {
                 eprintln!(
                     "❌ C API: Failed to start and login Android TLS worker: {}",
                     e
                 );
-                -1
+                GpufError::WorkerStart
             }
         }
     }
@@ -4426,10 +7685,10 @@ pub extern "C" fn start_remote_worker_with_tls(
             )
             .await
         }) {
-            Ok(_) => 0,
+            Ok(_) => GpufError::Ok,
             Err(e) => {
                 eprintln!("❌ C API: Failed to login iOS TLS worker: {}", e);
-                -1
+                GpufError::WorkerStart
             }
         }
     }
@@ -4446,8 +7705,8 @@ pub extern "C" fn start_remote_worker_with_tls(
     _ca_cert_path: *const c_char,
     _control_tls_server_name: *const c_char,
     _cert_sha256_pin: *const c_char,
-) -> c_int {
-    -1
+) -> GpufError {
+    GpufError::Unsupported
 }
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -4458,8 +7717,80 @@ pub extern "C" fn start_remote_worker(
     _proxy_port: c_int,
     _worker_type: *const c_char,
     _client_id: *const c_char,
-) -> c_int {
-    -1
+) -> GpufError {
+    GpufError::Unsupported
+}
+
+/// Result codes returned by the remote worker C API. The values are part of
+/// the ABI and never change; `gpuf_error_string` describes each of them.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpufError {
+    Ok = 0,
+    BackendInit = -1,
+    PathConversion = -2,
+    ModelLoad = -3,
+    ContextCreate = -4,
+    InvalidArgument = -5,
+    InvalidTlsPolicy = -6,
+    WorkerStart = -7,
+    NoModelLoaded = -8,
+    BufferTooSmall = -9,
+    Internal = -10,
+    Unsupported = -11,
+    /// The call succeeded but the server could not be told; it learns on the
+    /// next model status report.
+    ServerNotNotified = 1,
+}
+
+impl GpufError {
+    pub const ALL: [GpufError; 13] = [
+        GpufError::Ok,
+        GpufError::BackendInit,
+        GpufError::PathConversion,
+        GpufError::ModelLoad,
+        GpufError::ContextCreate,
+        GpufError::InvalidArgument,
+        GpufError::InvalidTlsPolicy,
+        GpufError::WorkerStart,
+        GpufError::NoModelLoaded,
+        GpufError::BufferTooSmall,
+        GpufError::Internal,
+        GpufError::Unsupported,
+        GpufError::ServerNotNotified,
+    ];
+
+    pub fn from_code(code: c_int) -> Option<Self> {
+        Self::ALL.into_iter().find(|error| *error as c_int == code)
+    }
+
+    pub fn message(self) -> &'static CStr {
+        match self {
+            GpufError::Ok => c"Success",
+            GpufError::BackendInit => c"Backend initialization failed",
+            GpufError::PathConversion => c"Model path is null or not valid UTF-8",
+            GpufError::ModelLoad => c"Model loading failed",
+            GpufError::ContextCreate => c"Context creation failed",
+            GpufError::InvalidArgument => c"Argument is null, empty or not valid UTF-8",
+            GpufError::InvalidTlsPolicy => c"TLS CA bundle, server name or SHA256 pin is invalid",
+            GpufError::WorkerStart => c"Remote worker failed to start",
+            GpufError::NoModelLoaded => c"No model is loaded",
+            GpufError::BufferTooSmall => c"Output buffer is too small",
+            GpufError::Internal => c"Internal error",
+            GpufError::Unsupported => c"Not supported on this platform",
+            GpufError::ServerNotNotified => c"Done, but the server could not be notified",
+        }
+    }
+}
+
+/// Human-readable text for a code returned by the C API, e.g. by
+/// `set_remote_worker_model` or `start_remote_worker`. The string is static
+/// and must not be freed.
+#[no_mangle]
+pub extern "C" fn gpuf_error_string(code: c_int) -> *const c_char {
+    GpufError::from_code(code)
+        .map_or(c"Unknown error", GpufError::message)
+        .as_ptr()
 }
 
 // Global backend initialization flag
@@ -4479,7 +7810,7 @@ fn ensure_backend_initialized() -> c_int {
     }
 
     // Try to initialize
-    if real_llama_backend_init() != 0 {
+    if Backend::backend_init() != 0 {
         return -1;
     }
 
@@ -4497,11 +7828,11 @@ fn ensure_backend_initialized() -> c_int {
 /// - `model_path`: Path to the model file (.gguf)
 ///
 /// # Returns
-/// - `0`: Success (model loaded and context created)
-/// - `-1`: Backend initialization failed
-/// - `-2`: Path conversion failed
-/// - `-3`: Model loading failed
-/// - `-4`: Context creation failed
+/// - `GpufError::Ok` (0): Success (model loaded and context created)
+/// - `GpufError::BackendInit` (-1): Backend initialization failed
+/// - `GpufError::PathConversion` (-2): Path conversion failed
+/// - `GpufError::ModelLoad` (-3): Model loading failed
+/// - `GpufError::ContextCreate` (-4): Context creation failed
 ///
 /// # Safety
 /// Caller must ensure `model_path` is a valid null-terminated C string
@@ -4512,7 +7843,7 @@ fn ensure_backend_initialized() -> c_int {
 /// remains connected and continues processing afterward.
 #[cfg(any(target_os = "android", target_os = "ios"))]
 #[no_mangle]
-pub extern "C" fn set_remote_worker_model(model_path: *const c_char) -> c_int {
+pub extern "C" fn set_remote_worker_model(model_path: *const c_char) -> GpufError {
     use std::sync::atomic::Ordering;
 
     println!("🔥 GPUFabric C API: Setting remote worker model (hot swap enabled)");
@@ -4520,14 +7851,14 @@ pub extern "C" fn set_remote_worker_model(model_path: *const c_char) -> c_int {
     // 1. Ensure backend is initialized (only once per process)
     if ensure_backend_initialized() != 0 {
         eprintln!("❌ C API: Backend initialization failed");
-        return -1;
+        return GpufError::BackendInit;
     }
     println!("✅ C API: Backend ready");
 
     // 2. Convert C string to Rust string
     let path_str = if model_path.is_null() {
         eprintln!("❌ C API: Model path is null");
-        return -2;
+        return GpufError::PathConversion;
     } else {
         // SAFETY: `model_path` was checked for null and must point to a
         // NUL-terminated string owned by the caller for this call.
@@ -4536,7 +7867,7 @@ pub extern "C" fn set_remote_worker_model(model_path: *const c_char) -> c_int {
                 Ok(s) => s,
                 Err(e) => {
                     eprintln!("❌ C API: Failed to convert model path: {}", e);
-                    return -2;
+                    return GpufError::PathConversion;
                 }
             }
         }
@@ -4553,8 +7884,8 @@ pub extern "C" fn set_remote_worker_model(model_path: *const c_char) -> c_int {
     if model_ptr.is_null() {
         eprintln!("❌ C API: Failed to load model");
         let mut status = MODEL_STATUS.lock().unwrap();
-        status.set_error("Failed to load model");
-        return -3;
+        status.set_error(&last_load_error());
+        return GpufError::ModelLoad;
     }
     println!("✅ C API: Model loaded (path {} bytes)", path_str.len());
 
@@ -4565,12 +7896,21 @@ pub extern "C" fn set_remote_worker_model(model_path: *const c_char) -> c_int {
         status.set_error("Failed to create context");
         // SAFETY: `model_ptr` was returned by `gpuf_load_model` above.
         unsafe { llama_model_free(model_ptr) };
-        return -4;
+        return GpufError::ContextCreate;
     }
     println!("✅ C API: Context created");
 
     // 5. Atomically swap model/context using inference mutex
-    // This blocks both other swaps AND inference requests briefly
+    // This blocks both other swaps AND inference requests briefly. Running
+    // generations are cancelled first so the swap waits for them to stop
+    // rather than for them to finish.
+    let cancelled = cancel_all_generation_requests();
+    if cancelled > 0 {
+        println!(
+            "🛑 C API: Cancelled {} running request(s) for the swap",
+            cancelled
+        );
+    }
     println!("🔄 C API: Swapping model (blocking inference briefly)...");
     {
         let _swap_lock = MODEL_SWAP_LOCK.lock().unwrap();
@@ -4583,6 +7923,8 @@ pub extern "C" fn set_remote_worker_model(model_path: *const c_char) -> c_int {
         // Update to new model/context atomically
         GLOBAL_MODEL_PTR.store(model_ptr, Ordering::SeqCst);
         GLOBAL_CONTEXT_PTR.store(context_ptr, Ordering::SeqCst);
+        WARMED_CONTEXT_PTR.store(std::ptr::null_mut(), Ordering::SeqCst);
+        CONTINUABLE_CONTEXT_PTR.store(std::ptr::null_mut(), Ordering::SeqCst);
 
         println!("✅ C API: Global pointers updated");
 
@@ -4611,20 +7953,100 @@ pub extern "C" fn set_remote_worker_model(model_path: *const c_char) -> c_int {
         status.set_loaded(path_str);
     }
 
+    // The server routes by model and context window; a worker not connected
+    // yet reports both at login
+    #[cfg(target_os = "android")]
+    let reported = crate::handle::android_sdk::send_model_status();
+    #[cfg(target_os = "ios")]
+    let reported = crate::worker_sdk::send_model_status();
+    if let Err(e) = reported {
+        println!("ℹ️ C API: Model change not reported to the server: {}", e);
+    }
+
     println!("🎉 C API: Remote worker model set successfully (hot swap)");
-    0 // Success
+    GpufError::Ok
 }
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 #[no_mangle]
-pub extern "C" fn set_remote_worker_model(_model_path: *const c_char) -> c_int {
-    -1
+pub extern "C" fn set_remote_worker_model(_model_path: *const c_char) -> GpufError {
+    GpufError::BackendInit
+}
+
+/// Free the remote worker's model and context, e.g. when the OS reports memory
+/// pressure, and tell the server so it stops routing requests for that model
+/// here.
+///
+/// Running generations are cancelled first and the model is only freed once
+/// they have released it. `set_remote_worker_model` loads a model again.
+/// Returns `GpufError::Ok` when the model was freed and the server notified,
+/// `GpufError::ServerNotNotified` when it was freed but the server could not
+/// be reached and `GpufError::NoModelLoaded` if no model was loaded.
+#[cfg(any(target_os = "android", target_os = "ios"))]
+#[no_mangle]
+pub extern "C" fn gpuf_unload_model() -> GpufError {
+    println!("🧹 C API: Unloading remote worker model");
+
+    // Stop whatever is using the model, then wait for it under the locks
+    let cancelled = cancel_all_generation_requests();
+    set_generation_stop(true);
+    let freed = {
+        let _swap_lock = MODEL_SWAP_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _inference_lock = GLOBAL_INFERENCE_MUTEX
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        cleanup_generation_control();
+
+        let old_model = GLOBAL_MODEL_PTR.swap(std::ptr::null_mut(), Ordering::SeqCst);
+        let old_context = GLOBAL_CONTEXT_PTR.swap(std::ptr::null_mut(), Ordering::SeqCst);
+        WARMED_CONTEXT_PTR.store(std::ptr::null_mut(), Ordering::SeqCst);
+        CONTINUABLE_CONTEXT_PTR.store(std::ptr::null_mut(), Ordering::SeqCst);
+        set_context_position(0);
+
+        if !old_context.is_null() {
+            // SAFETY: The context came from this SDK global state and no
+            // generation can use it while GLOBAL_INFERENCE_MUTEX is held.
+            unsafe { llama_free(old_context) };
+        }
+        if !old_model.is_null() {
+            // SAFETY: Same as above; the context using the model is freed first.
+            unsafe { llama_model_free(old_model) };
+        }
+        !old_model.is_null()
+    };
+
+    if !freed {
+        println!("ℹ️ C API: No model loaded, nothing to unload");
+        return GpufError::NoModelLoaded;
+    }
+    MODEL_STATUS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .set_unloaded();
+    println!(
+        "✅ C API: Model unloaded ({} cancelled requests)",
+        cancelled
+    );
+
+    #[cfg(target_os = "android")]
+    let reported = crate::handle::android_sdk::send_model_status();
+    #[cfg(target_os = "ios")]
+    let reported = crate::worker_sdk::send_model_status();
+    match reported {
+        Ok(()) => GpufError::Ok,
+        Err(e) => {
+            eprintln!("⚠️ C API: Could not report the unload to the server: {}", e);
+            GpufError::ServerNotNotified
+        }
+    }
 }
 
 /// Start remote worker background tasks (C API)
 #[cfg(any(target_os = "android", target_os = "ios"))]
 #[no_mangle]
-pub extern "C" fn start_remote_worker_tasks() -> c_int {
+pub extern "C" fn start_remote_worker_tasks() -> GpufError {
     println!("🔥 GPUFabric C API: Starting remote worker background tasks");
 
     #[cfg(target_os = "android")]
@@ -4632,10 +8054,10 @@ pub extern "C" fn start_remote_worker_tasks() -> c_int {
         match TOKIO_RUNTIME
             .block_on(async { crate::handle::android_sdk::start_worker_tasks().await })
         {
-            Ok(_) => 0 as c_int,
+            Ok(_) => GpufError::Ok,
             Err(e) => {
                 eprintln!("❌ C API: Failed to start background tasks: {}", e);
-                -1 as c_int
+                GpufError::WorkerStart
             }
         }
     }
@@ -4650,10 +8072,10 @@ pub extern "C" fn start_remote_worker_tasks() -> c_int {
         match local_runtime
             .block_on(async { crate::worker_sdk::start_worker_tasks_with_callback_ptr(None).await })
         {
-            Ok(_) => 0 as c_int,
+            Ok(_) => GpufError::Ok,
             Err(e) => {
                 eprintln!("❌ C API: Failed to start background tasks: {}", e);
-                -1 as c_int
+                GpufError::WorkerStart
             }
         }
     }
@@ -4661,8 +8083,8 @@ pub extern "C" fn start_remote_worker_tasks() -> c_int {
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 #[no_mangle]
-pub extern "C" fn start_remote_worker_tasks() -> c_int {
-    -1
+pub extern "C" fn start_remote_worker_tasks() -> GpufError {
+    GpufError::Unsupported
 }
 
 /// Start remote worker background tasks with callback support (C API)
@@ -4670,7 +8092,7 @@ pub extern "C" fn start_remote_worker_tasks() -> c_int {
 #[no_mangle]
 pub extern "C" fn start_remote_worker_tasks_with_callback_ptr(
     callback: Option<extern "C" fn(*const c_char, *mut c_void)>,
-) -> c_int {
+) -> GpufError {
     println!("🔥 GPUFabric C API: Starting remote worker background tasks with callback");
 
     #[cfg(target_os = "android")]
@@ -4678,13 +8100,13 @@ pub extern "C" fn start_remote_worker_tasks_with_callback_ptr(
         match TOKIO_RUNTIME.block_on(async {
             crate::handle::android_sdk::start_worker_tasks_with_callback_ptr(callback).await
         }) {
-            Ok(_) => 0 as c_int,
+            Ok(_) => GpufError::Ok,
             Err(e) => {
                 eprintln!(
                     "❌ C API: Failed to start background tasks with callback: {}",
                     e
                 );
-                -1 as c_int
+                GpufError::WorkerStart
             }
         }
     }
@@ -4699,13 +8121,13 @@ pub extern "C" fn start_remote_worker_tasks_with_callback_ptr(
         match local_runtime.block_on(async {
             crate::worker_sdk::start_worker_tasks_with_callback_ptr(callback).await
         }) {
-            Ok(_) => 0 as c_int,
+            Ok(_) => GpufError::Ok,
             Err(e) => {
                 eprintln!(
                     "❌ C API: Failed to start background tasks with callback: {}",
                     e
                 );
-                -1 as c_int
+                GpufError::WorkerStart
             }
         }
     }
@@ -4715,8 +8137,8 @@ pub extern "C" fn start_remote_worker_tasks_with_callback_ptr(
 #[no_mangle]
 pub extern "C" fn start_remote_worker_tasks_with_callback_ptr(
     _callback: Option<extern "C" fn(*const c_char, *mut c_void)>,
-) -> c_int {
-    -1
+) -> GpufError {
+    GpufError::Unsupported
 }
 
 /// Register a status callback for remote worker background tasks (C API).
@@ -4728,8 +8150,12 @@ pub extern "C" fn start_remote_worker_tasks_with_callback_ptr(
 pub extern "C" fn gpuf_register_remote_worker_callback(
     callback: Option<extern "C" fn(*const c_char, *mut c_void)>,
     user_data: *mut c_void,
-) -> c_int {
-    crate::worker_sdk::register_remote_worker_callback(callback, user_data)
+) -> GpufError {
+    if crate::worker_sdk::register_remote_worker_callback(callback, user_data) == 0 {
+        GpufError::Ok
+    } else {
+        GpufError::Internal
+    }
 }
 
 #[cfg(not(target_os = "ios"))]
@@ -4737,20 +8163,20 @@ pub extern "C" fn gpuf_register_remote_worker_callback(
 pub extern "C" fn gpuf_register_remote_worker_callback(
     _callback: Option<extern "C" fn(*const c_char, *mut c_void)>,
     _user_data: *mut c_void,
-) -> c_int {
-    -1
+) -> GpufError {
+    GpufError::Unsupported
 }
 
 /// Stop remote worker and cleanup (C API)
 #[cfg(any(target_os = "android", target_os = "ios"))]
 #[no_mangle]
-pub extern "C" fn stop_remote_worker() -> c_int {
+pub extern "C" fn stop_remote_worker() -> GpufError {
     println!("🔥 GPUFabric C API: Stopping remote worker");
 
     #[cfg(target_os = "android")]
     {
         TOKIO_RUNTIME.block_on(async { crate::handle::android_sdk::stop_global_worker().await });
-        0
+        GpufError::Ok
     }
 
     #[cfg(target_os = "ios")]
@@ -4760,14 +8186,14 @@ pub extern "C" fn stop_remote_worker() -> c_int {
             .build()
             .expect("Failed to create local tokio runtime");
         local_runtime.block_on(async { crate::worker_sdk::stop_global_worker().await });
-        0
+        GpufError::Ok
     }
 }
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 #[no_mangle]
-pub extern "C" fn stop_remote_worker() -> c_int {
-    -1
+pub extern "C" fn stop_remote_worker() -> GpufError {
+    GpufError::Unsupported
 }
 
 /// Get remote worker status (C API)
@@ -4777,24 +8203,26 @@ pub extern "C" fn stop_remote_worker() -> c_int {
 /// - `buffer_size`: Size of the output buffer
 ///
 /// # Returns
-/// - `0`: Success (status written to buffer)
-/// - `-1`: Error (buffer too small or other error)
+/// - `GpufError::Ok`: Success (status written to buffer)
+/// - `GpufError::InvalidArgument`: `buffer` is null or `buffer_size` is zero
+/// - `GpufError::BufferTooSmall`: The status does not fit in `buffer`
+/// - `GpufError::Internal`: The status could not be converted to a C string
 ///
 /// # Safety
 /// Caller must ensure `buffer` is valid and can hold `buffer_size` bytes
 #[cfg(any(target_os = "android", target_os = "ios"))]
 #[no_mangle]
-pub extern "C" fn get_remote_worker_status(buffer: *mut c_char, buffer_size: size_t) -> c_int {
+pub extern "C" fn get_remote_worker_status(buffer: *mut c_char, buffer_size: size_t) -> GpufError {
     println!("🔥 GPUFabric C API: Getting remote worker status");
 
     if buffer.is_null() {
         eprintln!("❌ C API: Buffer is null");
-        return -1;
+        return GpufError::InvalidArgument;
     }
 
     if buffer_size == 0 {
         eprintln!("❌ C API: Buffer size is zero");
-        return -1;
+        return GpufError::InvalidArgument;
     }
 
     // Get status from async function
@@ -4829,7 +8257,7 @@ pub extern "C" fn get_remote_worker_status(buffer: *mut c_char, buffer_size: siz
         Ok(s) => s,
         Err(e) => {
             eprintln!("❌ C API: Failed to convert status to C string: {}", e);
-            return -1;
+            return GpufError::Internal;
         }
     };
 
@@ -4841,7 +8269,7 @@ pub extern "C" fn get_remote_worker_status(buffer: *mut c_char, buffer_size: siz
             status_bytes.len(),
             buffer_size
         );
-        return -1;
+        return GpufError::BufferTooSmall;
     }
 
     // SAFETY: `buffer` is non-null, `buffer_size` was checked above, and the
@@ -4851,14 +8279,14 @@ pub extern "C" fn get_remote_worker_status(buffer: *mut c_char, buffer_size: siz
     }
 
     println!("✅ C API: Status written to buffer");
-    0 as c_int
+    GpufError::Ok
 }
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 #[no_mangle]
-pub extern "C" fn get_remote_worker_status(buffer: *mut c_char, buffer_size: size_t) -> c_int {
+pub extern "C" fn get_remote_worker_status(buffer: *mut c_char, buffer_size: size_t) -> GpufError {
     if buffer.is_null() || buffer_size == 0 {
-        return -1;
+        return GpufError::InvalidArgument;
     }
 
     // SAFETY: `buffer` is non-null and at least one byte is writable because
@@ -4866,5 +8294,736 @@ pub extern "C" fn get_remote_worker_status(buffer: *mut c_char, buffer_size: siz
     unsafe {
         *buffer = 0;
     }
-    -1
+    GpufError::Unsupported
+}
+
+#[cfg(all(test, not(any(target_os = "android", target_os = "ios"))))]
+mod tests {
+    use super::*;
+
+    fn simulated_context() -> *mut llama_context {
+        std::ptr::NonNull::dangling().as_ptr()
+    }
+
+    fn completion_params(
+        stop_words: &[*const c_char],
+        n_stop_words: c_int,
+    ) -> llama_completion_params {
+        llama_completion_params {
+            prompt: std::ptr::null(),
+            n_predict: 16,
+            temperature: 0.7,
+            top_k: 40,
+            top_p: 0.9,
+            repeat_penalty: 1.1,
+            stop_words: if stop_words.is_empty() {
+                std::ptr::null()
+            } else {
+                stop_words.as_ptr()
+            },
+            n_stop_words,
+        }
+    }
+
+    #[test]
+    fn collects_stop_words_from_completion_params() {
+        let end = CString::new("</s>").unwrap();
+        let user = CString::new("\nUser:").unwrap();
+        let words = [end.as_ptr(), user.as_ptr()];
+        assert_eq!(
+            collect_stop_words(&completion_params(&words, 2)),
+            ["</s>", "\nUser:"]
+        );
+        // A shorter count only reads that many entries
+        assert_eq!(collect_stop_words(&completion_params(&words, 1)), ["</s>"]);
+    }
+
+    #[test]
+    fn collect_stop_words_skips_null_and_invalid_entries() {
+        let end = CString::new("</s>").unwrap();
+        let empty = CString::new("").unwrap();
+        let latin1 = CString::new(vec![0xE9u8]).unwrap();
+        let words = [
+            std::ptr::null(),
+            end.as_ptr(),
+            empty.as_ptr(),
+            latin1.as_ptr(),
+        ];
+        assert_eq!(collect_stop_words(&completion_params(&words, 4)), ["</s>"]);
+
+        // Counts that do not match the pointer yield nothing
+        assert!(collect_stop_words(&completion_params(&words, -1)).is_empty());
+        assert!(collect_stop_words(&completion_params(&words, 0)).is_empty());
+        assert!(collect_stop_words(&completion_params(&[], 2)).is_empty());
+    }
+
+    #[test]
+    fn every_error_code_has_a_message() {
+        for error in GpufError::ALL {
+            let code = error as c_int;
+            assert_eq!(GpufError::from_code(code), Some(error));
+            // SAFETY: `gpuf_error_string` returns a static NUL-terminated string.
+            let text = unsafe { CStr::from_ptr(gpuf_error_string(code)) };
+            assert_eq!(text, error.message());
+            assert!(!text.is_empty(), "{:?} has no message", error);
+        }
+        assert_eq!(GpufError::from_code(42), None);
+        // SAFETY: as above.
+        let unknown = unsafe { CStr::from_ptr(gpuf_error_string(42)) };
+        assert_eq!(unknown, c"Unknown error");
+    }
+
+    #[test]
+    fn model_status_codes_follow_load_transitions() {
+        let mut status = ModelStatusInfo::new();
+        assert_eq!(status.status_code(), 0);
+        status.set_loading("/models/a.gguf");
+        assert_eq!(status.status_code(), 1);
+        status.set_loaded("/models/a.gguf");
+        assert_eq!(status.status_code(), 2);
+        status.set_loading("/models/b.gguf");
+        assert_eq!(status.status_code(), 1);
+        status.set_error("Failed to create context");
+        assert_eq!(status.status_code(), 3);
+        // A new attempt clears the error
+        status.set_loading("/models/b.gguf");
+        assert_eq!(status.status_code(), 1);
+        status.set_loaded("/models/b.gguf");
+        status.set_unloaded();
+        assert_eq!(status.status_code(), 0);
+        status.set_error("Failed to load model");
+        status.clear();
+        assert_eq!(status.status_code(), 0);
+    }
+
+    #[test]
+    fn model_status_follows_load_and_context_creation() {
+        let model_file = tempfile::NamedTempFile::new().unwrap();
+        let path = CString::new(model_file.path().to_str().unwrap()).unwrap();
+
+        let model = gpuf_load_model(path.as_ptr());
+        assert!(!model.is_null());
+        // The model is loaded but there is no context to run it yet
+        assert_eq!(gpuf_get_model_status(), 1);
+        let ctx = gpuf_create_context(model);
+        assert!(!ctx.is_null());
+        assert_eq!(gpuf_get_model_status(), 2);
+        gpuf_free_context(ctx);
+
+        let missing = CString::new("/nonexistent/model.gguf").unwrap();
+        assert!(gpuf_load_model(missing.as_ptr()).is_null());
+        assert_eq!(gpuf_get_model_status(), 3);
+
+        let mut buf = vec![0 as c_char; 128];
+        let len = gpuf_get_model_error(buf.as_mut_ptr(), buf.len() as c_int);
+        // SAFETY: `gpuf_get_model_error` NUL-terminated the message in `buf`.
+        let message = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap();
+        assert!(message.starts_with("Cannot read model file"), "{}", message);
+        assert_eq!(len as usize, message.len());
+        let mut small = vec![0 as c_char; 5];
+        assert_eq!(gpuf_get_model_error(small.as_mut_ptr(), 5), 4);
+        assert_eq!(gpuf_get_model_error(std::ptr::null_mut(), 8), -1);
+
+        // A new attempt clears the error
+        assert!(!gpuf_load_model(path.as_ptr()).is_null());
+        assert_eq!(gpuf_get_model_status(), 1);
+        assert_eq!(
+            gpuf_get_model_error(buf.as_mut_ptr(), buf.len() as c_int),
+            0
+        );
+        MODEL_STATUS.lock().unwrap().clear();
+    }
+
+    #[test]
+    fn truncated_result_is_finished_by_get_last_result() {
+        let text = "hello wörld";
+        let mut output = vec![0 as c_char; 6];
+        // SAFETY: `output` is writable for its length.
+        let copied =
+            unsafe { store_and_copy_result(text, output.as_mut_ptr(), output.len() as c_int) };
+        assert_eq!(copied, 5);
+        assert!(gpuf_get_last_result_len() > copied);
+
+        // The rest, without repeating what the generation call already returned
+        let mut rest = vec![0 as c_char; 16];
+        let len = gpuf_get_last_result(rest.as_mut_ptr(), rest.len() as c_int);
+        // SAFETY: both buffers were NUL terminated by the calls above.
+        let (head, tail) = unsafe {
+            (
+                CStr::from_ptr(output.as_ptr()).to_str().unwrap(),
+                CStr::from_ptr(rest.as_ptr()).to_str().unwrap(),
+            )
+        };
+        assert_eq!(len as usize, tail.len());
+        assert_eq!(format!("{}{}", head, tail), text);
+        assert_eq!(
+            gpuf_get_last_result(rest.as_mut_ptr(), rest.len() as c_int),
+            0
+        );
+        assert_eq!(gpuf_get_last_result(std::ptr::null_mut(), 16), -1);
+    }
+
+    #[test]
+    fn describe_worker_writes_json_that_fits() {
+        let mut buf = vec![0 as c_char; 1024];
+        assert_eq!(
+            gpuf_describe_worker(buf.as_mut_ptr(), buf.len() as c_int),
+            GpufError::Ok
+        );
+        // SAFETY: `gpuf_describe_worker` NUL-terminated the JSON in `buf`.
+        let json = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap();
+        let description: common::WorkerDescription = serde_json::from_str(json).unwrap();
+        assert_eq!(description.version, GPUF_VERSION);
+        assert_eq!(description.protocol_revision, common::PROTOCOL_REVISION);
+        assert_eq!(description.engine, "Llama");
+
+        let mut small = vec![0 as c_char; 8];
+        assert_eq!(
+            gpuf_describe_worker(small.as_mut_ptr(), 8),
+            GpufError::BufferTooSmall
+        );
+        assert_eq!(
+            gpuf_describe_worker(std::ptr::null_mut(), 8),
+            GpufError::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn utf8_emit_buffer_holds_split_glyphs_and_drops_nuls() {
+        let mut buf = Utf8EmitBuffer::new();
+        // "é" split across two token pieces
+        assert_eq!(buf.push_and_take_valid(b"caf\xC3"), "caf");
+        assert_eq!(buf.push_and_take_valid(b"\xA9!"), "é!");
+        // An embedded NUL must not make the piece unemittable as a C string
+        let text = buf.push_and_take_valid(b"a\0b");
+        assert_eq!(text, "ab");
+        assert!(CString::new(text).is_ok());
+        // A dangling partial glyph is released lossily at the end
+        assert_eq!(buf.push_and_take_valid(b"\xE2\x82"), "");
+        assert_eq!(buf.flush_lossy(), "\u{FFFD}");
+        assert_eq!(buf.flush_lossy(), "");
+    }
+
+    #[test]
+    fn utf8_emit_buffer_assembles_a_glyph_byte_by_byte() {
+        let mut buf = Utf8EmitBuffer::new();
+        // "€" is E2 82 AC
+        assert_eq!(buf.push_and_take_valid(&[0xE2]), "");
+        assert_eq!(buf.pending_len(), 1);
+        assert_eq!(buf.push_and_take_valid(&[0x82]), "");
+        assert_eq!(buf.pending_len(), 2);
+        assert_eq!(buf.push_and_take_valid(&[0xAC]), "€");
+        assert_eq!(buf.pending_len(), 0);
+    }
+
+    #[test]
+    fn utf8_emit_buffer_flushes_lossily_past_its_limit() {
+        let mut buf = Utf8EmitBuffer::with_limit(4);
+        for _ in 0..4 {
+            assert_eq!(buf.push_and_take_valid(&[0xFF]), "");
+        }
+        // Crossing the limit flushes everything held so far, once
+        assert_eq!(buf.push_and_take_valid(&[0xFF]), "\u{FFFD}".repeat(5));
+        assert_eq!(buf.pending_len(), 0);
+        assert_eq!(buf.push_and_take_valid(&[0xFF]), "");
+        assert_eq!(buf.pending_len(), 1);
+    }
+
+    #[test]
+    fn cancelling_one_request_leaves_the_other_running() {
+        // Stands in for a generation loop: one "token" per millisecond
+        fn fake_generation(_handle: u64) -> c_int {
+            let mut tokens = 0;
+            while tokens < 200 && !should_stop_generation() {
+                tokens += 1;
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            tokens
+        }
+
+        let (first, first_thread) = spawn_generation_request(fake_generation).unwrap();
+        let (second, second_thread) = spawn_generation_request(fake_generation).unwrap();
+        assert_ne!(first, second);
+
+        assert_eq!(gpuf_cancel_request(first), 0);
+        assert!(first_thread.join().unwrap() < 200);
+        assert_eq!(second_thread.join().unwrap(), 200);
+
+        // Finished requests can no longer be cancelled
+        assert_eq!(gpuf_cancel_request(first), -1);
+        assert_eq!(gpuf_cancel_request(second), -1);
+    }
+
+    #[test]
+    fn tokenize_empty_text_yields_only_bos() {
+        let tokens = tokenize(simulated_context(), "", true).unwrap();
+        assert_eq!(tokens, vec![SIMULATED_BOS_TOKEN]);
+        assert!(tokenize(simulated_context(), "", false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tokenize_grows_buffer_for_long_text() {
+        let text = "x".repeat(10_000);
+        let tokens = tokenize(simulated_context(), &text, true).unwrap();
+        assert_eq!(tokens.len(), text.len() + 1);
+        assert_eq!(tokens[0], SIMULATED_BOS_TOKEN);
+        assert!(tokens[1..].iter().all(|&t| t == b'x' as LlamaToken));
+    }
+
+    #[test]
+    fn detokenize_inverts_tokenize() {
+        let model = std::ptr::NonNull::<llama_model>::dangling().as_ptr();
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(20);
+        let tokens = tokenize(simulated_context(), &text, true).unwrap();
+        assert_eq!(detokenize(model, &tokens).unwrap(), text);
+        assert_eq!(detokenize(model, &[]).unwrap(), "");
+        assert!(detokenize(std::ptr::null(), &tokens).is_err());
+    }
+
+    #[test]
+    fn multimodal_context_overrides_only_its_own_fields() {
+        let defaults = Backend::context_default_params();
+        let params = multimodal_context_params(8192, 0);
+        assert_eq!(params.n_ctx, 8192);
+        assert_eq!(params.n_batch, DEFAULT_MULTIMODAL_N_BATCH as u32);
+        assert!(!params.embeddings);
+        assert_eq!(params.n_ubatch, defaults.n_ubatch);
+        assert_eq!(params.n_threads, defaults.n_threads);
+        assert_eq!(params.n_threads_batch, defaults.n_threads_batch);
+        assert_eq!(params.offload_kqv, defaults.offload_kqv);
+
+        assert_eq!(gpuf_set_multimodal_n_ctx(-1), -1);
+        assert_eq!(
+            MULTIMODAL_N_CTX.load(Ordering::SeqCst),
+            DEFAULT_MULTIMODAL_N_CTX
+        );
+        assert_eq!(multimodal_context_params(0, 0).n_ctx, 0);
+        assert_eq!(multimodal_context_params(4096, 256).n_batch, 256);
+    }
+
+    #[test]
+    fn multimodal_budget_counts_image_prompt_and_generation() {
+        assert!(multimodal_context_error(4096, 1024).is_none());
+        // The projector default leaves the budget unknown
+        assert!(multimodal_context_error(512, -1).is_none());
+        let err = multimodal_context_error(512, 1024).unwrap();
+        assert!(err.contains("1024") && err.contains("512"), "{}", err);
+        assert!(multimodal_context_error(1024, 1024).is_some());
+
+        assert!(multimodal_request_error(4096, 1100, 256).is_none());
+        assert!(multimodal_request_error(1356, 1100, 256).is_none());
+        let err = multimodal_request_error(1355, 1100, 256).unwrap();
+        assert!(err.contains("1100") && err.contains("1355"), "{}", err);
+        assert!(multimodal_request_error(512, 600, -1).is_some());
+    }
+
+    #[test]
+    fn tokenize_retry_keeps_prompts_longer_than_the_first_buffer() {
+        // Sized like the buffer manual_llama_completion starts with
+        let text = "long prompt ".repeat(100);
+        let mut calls = Vec::new();
+        let tokens = tokenize_with_retry(512, |buf| {
+            calls.push(buf.len());
+            SimulatedBackend::tokenize(simulated_context(), &text, buf, true)
+        })
+        .unwrap();
+
+        assert_eq!(calls, vec![512, text.len() + 1]);
+        assert_eq!(tokens.len(), text.len() + 1);
+        assert_eq!(tokens[0], SIMULATED_BOS_TOKEN);
+        assert_eq!(*tokens.last().unwrap(), b' ' as LlamaToken);
+    }
+
+    #[test]
+    fn context_position_helpers_are_race_free() {
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    for n in 0..1_000 {
+                        set_context_position(i * 1_000 + n);
+                        let pos = get_context_position();
+                        assert!((0..8_000).contains(&pos));
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        set_context_position(0);
+        assert_eq!(get_context_position(), 0);
+    }
+
+    extern "C" fn collect_tokens(token: *const c_char, user_data: *mut c_void) {
+        // SAFETY: the test passes a `String` as user data and a live C string.
+        let out = unsafe { &mut *(user_data as *mut String) };
+        out.push_str(unsafe { CStr::from_ptr(token) }.to_str().unwrap());
+    }
+
+    #[test]
+    fn stop_word_ends_stream_and_is_not_emitted() {
+        let mut out = String::new();
+        let user_data = &mut out as *mut String as *mut c_void;
+        let mut stream = TokenTextStream::new(&["\n\n".to_string()]);
+
+        let mut stopped = false;
+        for piece in ["Hello", " world", "\n", "\n", "ignored"] {
+            let (text, hit) = stream.push(piece.as_bytes());
+            emit_token_text(Some(collect_tokens), &text, user_data);
+            if hit {
+                stopped = true;
+                break;
+            }
+        }
+        emit_token_text(Some(collect_tokens), &stream.finish(), user_data);
+
+        assert!(stopped);
+        assert_eq!(out, "Hello world");
+    }
+
+    #[test]
+    fn stream_without_stop_word_flushes_held_back_text() {
+        let mut stream = TokenTextStream::new(&["\n\n".to_string()]);
+        let (text, hit) = stream.push(b"done\n");
+        assert_eq!(text, "done");
+        assert!(!hit);
+        assert_eq!(stream.finish(), "\n");
+    }
+
+    #[test]
+    fn tokenize_rejects_null_context() {
+        assert!(tokenize(std::ptr::null_mut(), "hi", true).is_err());
+    }
+
+    fn test_pool(size: usize) -> MemoryPool {
+        MemoryPool {
+            buffer: 0,
+            size,
+            used: 0,
+            peak: 0,
+            initialized: true,
+        }
+    }
+
+    #[test]
+    fn memory_pool_aligns_allocations() {
+        let mut pool = test_pool(1024);
+        assert_eq!(pool.bump(3, 1), Some(0));
+        assert_eq!(pool.bump(8, 16), Some(16));
+        assert_eq!(pool.bump(1, 8), Some(24));
+        assert_eq!(pool.used, 25);
+
+        assert_eq!(pool.bump(0, 8), None);
+        assert_eq!(pool.bump(8, 3), None);
+        assert_eq!(pool.used, 25);
+    }
+
+    #[test]
+    fn memory_pool_exhaustion_returns_none() {
+        let mut pool = test_pool(64);
+        assert_eq!(pool.bump(48, 16), Some(0));
+        assert_eq!(pool.bump(32, 16), None);
+        assert_eq!(pool.bump(usize::MAX, 1), None);
+        assert_eq!(pool.used, 48);
+        assert_eq!(pool.bump(16, 16), Some(48));
+        assert_eq!(pool.bump(1, 1), None);
+    }
+
+    #[test]
+    fn memory_pool_reset_keeps_peak() {
+        let mut pool = test_pool(1024);
+        pool.bump(600, 8).unwrap();
+        pool.reset();
+        assert_eq!((pool.used, pool.peak), (0, 600));
+
+        pool.bump(100, 8).unwrap();
+        assert_eq!((pool.used, pool.peak), (100, 600));
+    }
+
+    #[test]
+    fn memory_pool_stats_accepts_null_out_pointers() {
+        let mut size = usize::MAX;
+        let status = gpuf_memory_pool_stats(std::ptr::null_mut(), std::ptr::null_mut(), &mut size);
+        // The pool is only mapped on Android.
+        assert_eq!(status, -1);
+        assert_eq!(size, 0);
+    }
+
+    #[test]
+    fn warmup_token_count_is_bounded() {
+        assert_eq!(WARMUP_TOKENS.load(Ordering::SeqCst), 1);
+        assert!(!valid_warmup_tokens(0));
+        assert!(valid_warmup_tokens(1));
+        assert!(valid_warmup_tokens(MAX_WARMUP_TOKENS));
+        assert!(!valid_warmup_tokens(MAX_WARMUP_TOKENS + 1));
+        assert_eq!(gpuf_set_warmup_tokens(-3), -1);
+    }
+
+    extern "C" fn record_simulated_progress(progress: f32, user_data: *mut c_void) -> bool {
+        // SAFETY: the test passes a pointer to its own `Vec<f32>`, which
+        // outlives the simulated load.
+        let seen = unsafe { &mut *(user_data as *mut Vec<f32>) };
+        seen.push(progress);
+        true
+    }
+
+    #[test]
+    fn async_load_progress_is_fractional_and_monotonic() {
+        // The simulation backend drives the progress callback like llama.cpp
+        let mut seen: Vec<f32> = Vec::new();
+        let mut params = SimulatedBackend::model_default_params();
+        params.progress_callback = Some(record_simulated_progress);
+        params.progress_callback_user_data = &mut seen as *mut Vec<f32> as *mut c_void;
+        let path = CString::new("model.gguf").unwrap();
+        assert!(!SimulatedBackend::model_load_from_file(path.as_ptr(), params).is_null());
+        assert_eq!(seen, vec![0.0, 0.25, 0.5, 0.75, 1.0]);
+
+        let state = Mutex::new(Some(AsyncLoadingState {
+            status: 1,
+            progress: 0.0,
+            model_ptr: 0,
+        }));
+        let user_data = &state as *const Mutex<Option<AsyncLoadingState>> as *mut c_void;
+        let mut reported = Vec::new();
+        for progress in [0.25, 0.5, 0.4, f32::NAN, 0.75, 1.0] {
+            assert!(async_load_progress_callback(progress, user_data));
+            reported.push(state.lock().unwrap().unwrap().progress);
+        }
+        assert_eq!(reported, vec![0.25, 0.5, 0.5, 0.5, 0.75, 0.99]);
+        assert!(reported.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn embed_text_checks_buffer_bounds_and_returns_dimension() {
+        let ctx = simulated_context();
+        let text = CString::new("retrieval augmented generation").unwrap();
+        let dim = SIMULATED_N_EMBD as c_int;
+
+        let mut small = [0.0f32; SIMULATED_N_EMBD - 1];
+        assert_eq!(
+            gpuf_embed_text(ctx, text.as_ptr(), small.as_mut_ptr(), small.len() as c_int),
+            -dim
+        );
+        assert!(small.iter().all(|v| *v == 0.0));
+
+        let mut out = [0.0f32; SIMULATED_N_EMBD + 4];
+        assert_eq!(
+            gpuf_embed_text(ctx, text.as_ptr(), out.as_mut_ptr(), out.len() as c_int),
+            dim
+        );
+        let norm = out[..SIMULATED_N_EMBD]
+            .iter()
+            .map(|v| v * v)
+            .sum::<f32>()
+            .sqrt();
+        assert!((norm - 1.0).abs() < 1e-5, "{}", norm);
+        assert!(out[SIMULATED_N_EMBD..].iter().all(|v| *v == 0.0));
+
+        let mut again = [0.0f32; SIMULATED_N_EMBD];
+        gpuf_embed_text(ctx, text.as_ptr(), again.as_mut_ptr(), again.len() as c_int);
+        assert_eq!(again[..], out[..SIMULATED_N_EMBD]);
+
+        assert_eq!(
+            gpuf_embed_text(std::ptr::null_mut(), text.as_ptr(), out.as_mut_ptr(), 12),
+            -1
+        );
+        assert_eq!(
+            gpuf_embed_text(ctx, text.as_ptr(), out.as_mut_ptr(), -1),
+            -1
+        );
+    }
+
+    #[test]
+    fn model_load_options_populate_params() {
+        let params = ModelLoadOptions::new(32, false, true)
+            .unwrap()
+            .model_params();
+        assert_eq!(params.n_gpu_layers, 32);
+        assert!(!params.use_mmap);
+        assert!(params.use_mlock);
+        assert!(!params.vocab_only);
+
+        let clamped = ModelLoadOptions::new(c_int::MAX, true, false).unwrap();
+        assert_eq!(clamped.model_params().n_gpu_layers, MAX_GPU_LAYERS);
+        assert!(ModelLoadOptions::new(-1, true, false).is_err());
+
+        let cpu = ModelLoadOptions::for_backend(util::backend::InferenceBackend::Cpu);
+        assert_eq!(cpu, ModelLoadOptions::new(0, true, false).unwrap());
+    }
+
+    fn chat(turns: &[(&str, &str)]) -> Vec<common::ChatMessage> {
+        turns
+            .iter()
+            .map(|(role, content)| common::ChatMessage {
+                role: role.to_string(),
+                content: content.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chat_template_formats_empty_conversation() {
+        let model = std::ptr::NonNull::<llama_model>::dangling().as_ptr();
+        assert!(model_has_chat_template(model));
+        assert_eq!(
+            apply_chat_template(model, &[], true).unwrap(),
+            "<|im_start|>assistant\n"
+        );
+        assert_eq!(apply_chat_template(model, &[], false).unwrap(), "");
+
+        assert!(!model_has_chat_template(std::ptr::null()));
+        assert!(apply_chat_template(std::ptr::null(), &[], true).is_err());
+    }
+
+    #[test]
+    fn chat_template_formats_multi_turn_conversation() {
+        let model = std::ptr::NonNull::<llama_model>::dangling().as_ptr();
+        let messages = chat(&[
+            ("system", "You are terse."),
+            ("user", "Hi"),
+            ("assistant", "Hello."),
+            ("user", "Name a prime."),
+        ]);
+
+        // Longer than the initial buffer estimate, so this goes through the retry
+        let prompt = apply_chat_template(model, &messages, true).unwrap();
+        assert_eq!(
+            prompt,
+            "<|im_start|>system\nYou are terse.<|im_end|>\n\
+             <|im_start|>user\nHi<|im_end|>\n\
+             <|im_start|>assistant\nHello.<|im_end|>\n\
+             <|im_start|>user\nName a prime.<|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+
+        let bad = chat(&[("user", "a\0b")]);
+        assert!(apply_chat_template(model, &bad, true).is_err());
+    }
+
+    #[test]
+    fn generation_timings_cover_prompt_and_token_eval() {
+        let mut timer = GenerationTimer::start();
+        std::thread::sleep(Duration::from_millis(5));
+        timer.prompt_evaluated();
+        for _ in 0..3 {
+            let token_started = Instant::now();
+            std::thread::sleep(Duration::from_millis(2));
+            timer.token_evaluated(token_started);
+            // Callback work between tokens counts toward the total only
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        timer.finish();
+
+        let mut timings = llama_timings {
+            prompt_eval_time_ms: 0.0,
+            eval_time_ms: 0.0,
+            total_time_ms: 0.0,
+        };
+        assert_eq!(gpuf_get_last_timings(&mut timings), 0);
+        assert!(timings.prompt_eval_time_ms >= 5.0);
+        assert!(timings.eval_time_ms >= 6.0);
+        assert!(
+            timings.total_time_ms + 0.001
+                >= timings.prompt_eval_time_ms + timings.eval_time_ms + 3.0,
+            "total {} prompt {} eval {}",
+            timings.total_time_ms,
+            timings.prompt_eval_time_ms,
+            timings.eval_time_ms
+        );
+
+        assert_eq!(gpuf_get_last_timings(std::ptr::null_mut()), -1);
+    }
+
+    #[test]
+    fn kv_cache_round_trips_with_bounds_checks() {
+        let ctx = simulated_context();
+        let size = SIMULATED_STATE_SIZE as isize;
+        SIMULATED_KV_CELLS.store(42, Ordering::SeqCst);
+
+        assert_eq!(
+            gpuf_kv_cache_save(std::ptr::null_mut(), std::ptr::null_mut(), 0),
+            -1
+        );
+        assert_eq!(gpuf_kv_cache_save(ctx, std::ptr::null_mut(), 0), -size);
+        let mut small = vec![0u8; SIMULATED_STATE_SIZE - 1];
+        assert_eq!(
+            gpuf_kv_cache_save(ctx, small.as_mut_ptr(), small.len()),
+            -size
+        );
+        assert!(small.iter().all(|b| *b == 0));
+
+        let mut saved = vec![0u8; SIMULATED_STATE_SIZE + 8];
+        assert_eq!(
+            gpuf_kv_cache_save(ctx, saved.as_mut_ptr(), saved.len()),
+            size
+        );
+        saved.truncate(SIMULATED_STATE_SIZE);
+
+        // The swap replaces the context; the snapshot brings the conversation back
+        SIMULATED_KV_CELLS.store(0, Ordering::SeqCst);
+        assert_eq!(gpuf_kv_cache_load(ctx, saved.as_ptr(), saved.len()), 42);
+        assert_eq!(SIMULATED_KV_CELLS.load(Ordering::SeqCst), 42);
+        // Not the SDK's context, so the continuation state is left alone
+        assert_ne!(CONTINUABLE_CONTEXT_PTR.load(Ordering::SeqCst), ctx);
+
+        GLOBAL_CONTEXT_PTR.store(ctx, Ordering::SeqCst);
+        SIMULATED_KV_CELLS.store(0, Ordering::SeqCst);
+        assert_eq!(gpuf_kv_cache_load(ctx, saved.as_ptr(), saved.len()), 42);
+        assert_eq!(CONTINUABLE_CONTEXT_PTR.load(Ordering::SeqCst), ctx);
+        GLOBAL_CONTEXT_PTR.store(std::ptr::null_mut(), Ordering::SeqCst);
+        CONTINUABLE_CONTEXT_PTR.store(std::ptr::null_mut(), Ordering::SeqCst);
+        set_context_position(0);
+
+        assert_eq!(gpuf_kv_cache_load(ctx, saved.as_ptr(), saved.len() - 1), -1);
+        assert_eq!(gpuf_kv_cache_load(ctx, b"garbage!!!".as_ptr(), 10), -1);
+        assert_eq!(gpuf_kv_cache_load(ctx, std::ptr::null(), 0), -1);
+        assert_eq!(
+            gpuf_kv_cache_load(std::ptr::null_mut(), saved.as_ptr(), saved.len()),
+            -1
+        );
+    }
+}
+
+// Sampler chains need the llama.cpp library, which only mobile builds link
+#[cfg(all(test, any(target_os = "android", target_os = "ios")))]
+mod mobile_tests {
+    use super::*;
+
+    #[test]
+    fn sampler_chain_with_min_p_builds_and_frees() {
+        let params = util::generation::SamplingParams {
+            min_p: 0.05,
+            ..mobile_sampling_params(0.7, 40, 0.9, 1.1)
+        };
+        let chain = build_sampler_chain(&params);
+        assert!(!chain.is_null());
+        // SAFETY: `chain` was just built and is freed exactly once.
+        unsafe {
+            assert_eq!(
+                llama_sampler_chain_n(chain) as usize,
+                params.sampler_stages().len()
+            );
+            llama_sampler_free(chain);
+        }
+    }
+
+    #[test]
+    fn sampler_chain_with_every_sampler_builds_and_frees() {
+        let params = util::generation::SamplingParams {
+            min_p: 0.05,
+            typical_p: 0.9,
+            xtc_probability: 0.5,
+            ..mobile_sampling_params(0.7, 40, 0.9, 1.1)
+        };
+        let chain = build_sampler_chain(&params);
+        assert!(!chain.is_null());
+        // SAFETY: `chain` was just built and is freed exactly once.
+        unsafe {
+            assert_eq!(
+                llama_sampler_chain_n(chain) as usize,
+                params.sampler_stages().len()
+            );
+            llama_sampler_free(chain);
+        }
+    }
 }
@@ -1,8 +1,9 @@
 // Anthropic API compatible server routes for LlamaEngine
+use super::llama_engine::FinishReason;
 use super::llama_engine::SamplingParams;
 use super::llama_server::{
-    build_chat_prompt, validate_prompt_and_tokens, ApiServerState, AppError,
-    ChatMessage as LlamaChatMessage,
+    build_chat_prompt, validate_prompt_and_tokens, validate_stop_sequences, ApiServerState,
+    AppError, ChatMessage as LlamaChatMessage,
 };
 use axum::{
     extract::State,
@@ -33,6 +34,8 @@ pub struct MessagesRequest {
     pub thinking: Option<ThinkingConfig>,
     #[serde(default)]
     pub temperature: Option<f32>,
+    #[serde(default)]
+    pub stop_sequences: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -57,6 +60,7 @@ pub struct MessagesResponse {
     pub content: Vec<ContentBlock>,
     pub model: String,
     pub stop_reason: String,
+    pub stop_sequence: Option<String>,
     pub usage: AnthropicUsage,
 }
 
@@ -107,10 +111,21 @@ async fn messages_non_stream(
     if let Some(t) = req.temperature {
         sampling.temperature = t;
     }
+    if let Some(stop) = req.stop_sequences.clone() {
+        validate_stop_sequences(&stop)?;
+        sampling.stop = stop;
+    }
 
-    let (text, prompt_tokens, completion_tokens) = engine
+    let output = engine
         .generate_with_cached_model_sampling(&prompt, max_tokens, &sampling)
         .await?;
+    let (text, prompt_tokens, completion_tokens) =
+        (output.text, output.prompt_tokens, output.completion_tokens);
+    let stop_reason = match (output.finish_reason, &output.stop_sequence) {
+        (_, Some(_)) => "stop_sequence",
+        (FinishReason::Length, None) => "max_tokens",
+        _ => "end_turn",
+    };
 
     let content = if req.thinking.is_some() {
         split_thinking_text(&text)
@@ -124,7 +139,8 @@ async fn messages_non_stream(
         role: "assistant".to_string(),
         content,
         model: req.model.unwrap_or_else(|| "llama.cpp".to_string()),
-        stop_reason: "end_turn".to_string(),
+        stop_reason: stop_reason.to_string(),
+        stop_sequence: output.stop_sequence,
         usage: AnthropicUsage {
             input_tokens: prompt_tokens,
             output_tokens: completion_tokens,
@@ -148,6 +164,10 @@ async fn messages_stream(
     if let Some(t) = req.temperature {
         sampling.temperature = t;
     }
+    if let Some(stop) = req.stop_sequences.clone() {
+        validate_stop_sequences(&stop)?;
+        sampling.stop = stop;
+    }
     if let Some(ref thinking) = req.thinking {
         if let Some(budget) = thinking.budget_tokens {
             sampling.thinking_budget_tokens = Some(budget);
@@ -14,7 +14,16 @@ use tokio::sync::mpsc;
 #[cfg(not(target_os = "android"))]
 use tokio_stream::wrappers::ReceiverStream;
 
+#[cfg(not(target_os = "android"))]
+use crate::util::batch::{pack_sequences, BatchScheduler};
 use crate::util::cmd::LlamaSplitModeArg;
+#[cfg(not(target_os = "android"))]
+use crate::util::generation::{
+    generation_limit, RepetitionDetector, SamplerStage, StopSequenceMatcher, TokenRateLimiter,
+};
+pub use crate::util::generation::{FinishReason, SamplingParams};
+#[cfg(not(target_os = "android"))]
+use crate::util::lru::LruCache;
 
 // llama-cpp-2 imports (only for non-Android platforms)
 #[cfg(not(target_os = "android"))]
@@ -55,38 +64,120 @@ pub struct LlamaEngine {
     pub cached_model: Option<Arc<Mutex<LlamaModel>>>,
     #[cfg(not(target_os = "android"))]
     pub cached_model_path: Option<String>, // Track which model is currently cached
+    // Loaded models by resolved path, including the cached one, so switching
+    // back to a recently used model skips the load
+    #[cfg(not(target_os = "android"))]
+    pub resident_models: LruCache<String, Arc<Mutex<LlamaModel>>>,
 }
 
-#[derive(Clone, Debug)]
-pub struct SamplingParams {
-    pub temperature: f32,
-    pub top_k: i32,
-    pub top_p: f32,
-    pub repeat_penalty: f32,
-    pub repeat_last_n: i32,
-    pub seed: u32,
-    pub min_keep: usize,
-    /// Hint for max tokens to spend on thinking content (Anthropic extended thinking).
-    /// The model uses this as guidance; actual thinking token count depends on model output.
-    #[allow(dead_code)]
-    pub thinking_budget_tokens: Option<usize>,
+/// End-of-turn markers of common chat templates (ChatML, Llama3, etc.).
+#[cfg(not(target_os = "android"))]
+const BUILTIN_STOP_MARKERS: &[&str] = &["<|im_end|>", "<|eot_id|>", "<|end_of_text|>", "</s>"];
+
+#[cfg(not(target_os = "android"))]
+impl SamplingParams {
+    fn repetition_detector(&self) -> RepetitionDetector {
+        RepetitionDetector::new(self.repetition_ngram_size, self.repetition_max_repeats)
+    }
+
+    fn samplers(&self) -> Vec<llama_cpp_2::sampling::LlamaSampler> {
+        use llama_cpp_2::sampling::LlamaSampler;
+
+        self.sampler_stages()
+            .into_iter()
+            .map(|stage| match stage {
+                SamplerStage::Penalties(penalties) => {
+                    let (last_n, repeat, frequency, presence) = penalties.args();
+                    LlamaSampler::penalties(last_n, repeat, frequency, presence)
+                }
+                SamplerStage::TopK(k) => LlamaSampler::top_k(k),
+                SamplerStage::Typical { p, min_keep } => LlamaSampler::typical(p, min_keep),
+                SamplerStage::MinP { p, min_keep } => LlamaSampler::min_p(p, min_keep),
+                SamplerStage::TopP { p, min_keep } => LlamaSampler::top_p(p, min_keep),
+                SamplerStage::Xtc {
+                    probability,
+                    threshold,
+                    min_keep,
+                    seed,
+                } => LlamaSampler::xtc(probability, threshold, min_keep, seed),
+                SamplerStage::Temperature(t) => LlamaSampler::temp(t),
+                SamplerStage::Greedy => LlamaSampler::greedy(),
+                SamplerStage::Dist(seed) => LlamaSampler::dist(seed),
+            })
+            .collect()
+    }
+
+    fn stop_matcher(&self) -> StopSequenceMatcher {
+        StopSequenceMatcher::new(&self.stop).with_end_markers(BUILTIN_STOP_MARKERS.iter().copied())
+    }
 }
 
-impl Default for SamplingParams {
-    fn default() -> Self {
-        Self {
-            temperature: 0.8,
-            top_k: 40,
-            top_p: 0.95,
-            repeat_penalty: 1.1,
-            repeat_last_n: 64,
-            seed: 0,
-            min_keep: 1,
-            thinking_budget_tokens: None,
-        }
+#[cfg(not(target_os = "android"))]
+fn log_throttling(rate_limiter: &TokenRateLimiter) {
+    if !rate_limiter.throttled().is_zero() {
+        info!(
+            "Generation throttled for {} ms to stay under {} tokens/s",
+            rate_limiter.throttled().as_millis(),
+            rate_limiter.max_per_sec()
+        );
     }
 }
 
+#[cfg(not(target_os = "android"))]
+fn pin_inference_thread() {
+    if let Err(e) = crate::util::affinity::pin_current_thread() {
+        warn!("Failed to pin inference thread: {}", e);
+    }
+}
+
+// Sequences embedded together in one context
+#[cfg(not(target_os = "android"))]
+const MAX_EMBEDDING_SEQUENCES: usize = 32;
+
+#[cfg(not(target_os = "android"))]
+fn l2_normalize(embedding: &[f32]) -> Vec<f32> {
+    let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        embedding.iter().map(|v| v / norm).collect()
+    } else {
+        embedding.to_vec()
+    }
+}
+
+/// Result of a non-streaming generation.
+#[derive(Clone, Debug)]
+pub struct GenerationOutput {
+    pub text: String,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub finish_reason: FinishReason,
+    /// Stop sequence that ended generation; `None` with `FinishReason::Stop`
+    /// means the model emitted EOS.
+    pub stop_sequence: Option<String>,
+}
+
+/// One request of `LlamaEngine::generate_batch`.
+#[derive(Clone, Debug)]
+pub struct GenRequest {
+    pub prompt: String,
+    pub max_tokens: usize,
+    pub sampling: SamplingParams,
+}
+
+/// Outcome of one `GenRequest`, in request order.
+pub type GenResult = Result<GenerationOutput>;
+
+/// Per-request sampling state of a batched generation.
+#[cfg(not(target_os = "android"))]
+struct BatchSequence {
+    sampler: llama_cpp_2::sampling::LlamaSampler,
+    repetition: RepetitionDetector,
+    stops: StopSequenceMatcher,
+    text: String,
+    prompt_tokens: usize,
+    finish_reason: FinishReason,
+}
+
 // llama-cpp-2 state wrapper (no longer stored, used for single inference)
 #[cfg(not(target_os = "android"))]
 pub struct LlamaCppState<'a> {
@@ -139,14 +230,30 @@ impl LlamaEngine {
                     );
                     return Ok(());
                 } else if cached_path != &resolved_model_path_str {
-                    // Model path changed, clear old cache
-                    warn!(
-                        "Model path changed from {} to {}, clearing cache",
+                    info!(
+                        "Model path changed from {} to {}",
                         cached_path, resolved_model_path_str
                     );
-                    self.clear_cache();
                 }
             }
+
+            // Switch to a model that is still resident without reloading it
+            if let Some(model) = self.resident_models.get(&resolved_model_path_str) {
+                self.cached_model = Some(model.clone());
+                self.cached_backend = LLAMA_BACKEND.get().cloned();
+                self.cached_model_path = Some(resolved_model_path_str.clone());
+                self.is_initialized = true;
+                info!("Using resident model: {}", resolved_model_path_str);
+                return Ok(());
+            }
+
+            // Free the least recently used models before loading so memory
+            // never holds more than the resident budget
+            self.release_cached_model().await;
+            self.cached_model_path = None;
+            for (path, _) in self.resident_models.make_room() {
+                info!("Evicting resident model: {}", path);
+            }
             let n_gpu_layers = self.n_gpu_layers;
             let llama_split_mode = self.llama_split_mode.clone();
             let llama_main_gpu = self.llama_main_gpu;
@@ -233,8 +340,11 @@ impl LlamaEngine {
             .await??;
 
             // Cache the components and store the model path
+            let model = Arc::new(Mutex::new(model));
+            self.resident_models
+                .insert(model_path_for_cache.clone(), model.clone());
             self.cached_backend = Some(backend);
-            self.cached_model = Some(Arc::new(Mutex::new(model)));
+            self.cached_model = Some(model);
             self.cached_model_path = Some(model_path_for_cache.clone());
             self.is_initialized = true;
 
@@ -246,6 +356,22 @@ impl LlamaEngine {
         }
     }
 
+    /// Drops the current model once no generation holds it. Generations keep
+    /// the model locked until they end, so this waits them out instead of
+    /// evicting (or loading beside) a model that is still in use.
+    #[cfg(not(target_os = "android"))]
+    async fn release_cached_model(&mut self) {
+        let Some(model) = self.cached_model.take() else {
+            return;
+        };
+        if let Err(e) = tokio::task::spawn_blocking(move || drop(model.lock())).await {
+            warn!(
+                "Waiting for the previous model to be released failed: {:?}",
+                e
+            );
+        }
+    }
+
     /// Clear cached model to free memory
     #[cfg(not(target_os = "android"))]
     pub fn clear_cache(&mut self) {
@@ -257,6 +383,7 @@ impl LlamaEngine {
             self.is_initialized = false;
             info!("Model cache cleared");
         }
+        self.resident_models.clear();
     }
 
     /// Generate text using cached model (inference only)
@@ -267,8 +394,10 @@ impl LlamaEngine {
         max_tokens: usize,
     ) -> Result<(String, usize, usize)> {
         let params = SamplingParams::default();
-        self.generate_with_cached_model_sampling(prompt, max_tokens, &params)
-            .await
+        let output = self
+            .generate_with_cached_model_sampling(prompt, max_tokens, &params)
+            .await?;
+        Ok((output.text, output.prompt_tokens, output.completion_tokens))
     }
 
     pub async fn generate_with_cached_model_sampling(
@@ -276,7 +405,7 @@ impl LlamaEngine {
         prompt: &str,
         max_tokens: usize,
         sampling: &SamplingParams,
-    ) -> Result<(String, usize, usize)> {
+    ) -> Result<GenerationOutput> {
         if !self.is_initialized {
             return Err(anyhow!("Engine not initialized - call load_model() first"));
         }
@@ -293,7 +422,13 @@ impl LlamaEngine {
                 &prompt[..prompt.len().min(30)],
                 max_tokens
             );
-            Ok((text, 10, 20)) // Simulated token counts
+            Ok(GenerationOutput {
+                text,
+                prompt_tokens: 10, // Simulated token counts
+                completion_tokens: 20,
+                finish_reason: FinishReason::Stop,
+                stop_sequence: None,
+            })
         }
 
         #[cfg(not(target_os = "android"))]
@@ -323,6 +458,8 @@ impl LlamaEngine {
                 use llama_cpp_2::model::AddBos;
                 use llama_cpp_2::sampling::LlamaSampler;
 
+                pin_inference_thread();
+
                 let context_params = LlamaContextParams::default()
                     .with_n_ctx(NonZeroU32::new(n_ctx))
                     .with_n_batch(n_batch);
@@ -360,33 +497,15 @@ impl LlamaEngine {
                 let mut output_text = String::new();
                 let mut n_cur = tokens.len(); // Current position in sequence
 
-                let mut samplers = Vec::new();
-
-                if sampling.repeat_penalty != 1.0 {
-                    samplers.push(LlamaSampler::penalties(
-                        sampling.repeat_last_n,
-                        sampling.repeat_penalty,
-                        0.0,
-                        0.0,
-                    ));
-                }
-                if sampling.top_k > 0 {
-                    samplers.push(LlamaSampler::top_k(sampling.top_k));
-                }
-                if sampling.top_p > 0.0 && sampling.top_p < 1.0 {
-                    samplers.push(LlamaSampler::top_p(sampling.top_p, sampling.min_keep));
-                }
-                samplers.push(LlamaSampler::temp(sampling.temperature));
-                if sampling.temperature <= 0.0 {
-                    samplers.push(LlamaSampler::greedy());
-                } else {
-                    samplers.push(LlamaSampler::dist(sampling.seed));
-                }
-
-                let mut sampler = LlamaSampler::chain_simple(samplers);
+                let mut sampler = LlamaSampler::chain_simple(sampling.samplers());
                 sampler.accept_many(tokens.iter());
 
-                for i in 0..max_tokens {
+                let mut repetition = sampling.repetition_detector();
+                let mut stops = sampling.stop_matcher();
+                let mut rate_limiter = TokenRateLimiter::from_config();
+                let mut finish_reason = FinishReason::Length;
+
+                for i in 0..generation_limit(max_tokens, n_ctx as usize, n_cur) {
                     // Sample using the sampler chain
                     let new_token = sampler.sample(&context, -1);
                     sampler.accept(new_token);
@@ -399,23 +518,31 @@ impl LlamaEngine {
 
                     // Check for EOS token
                     if new_token == model_guard.token_eos() {
+                        finish_reason = FinishReason::Stop;
                         break;
                     }
                     // Convert token to string and append
                     if let Some(piece) = piece {
-                        // Check for stop sequences (ChatML, Llama3, etc.)
-                        if piece.contains("<|im_end|>")
-                            || piece.contains("<|eot_id|>")
-                            || piece.contains("<|end_of_text|>")
-                            || piece.contains("</s>")
-                        {
+                        // Stop sequences may span several tokens, so match the running output
+                        let (text, stopped) = stops.push(&piece);
+                        output_text.push_str(&text);
+                        if stopped {
+                            finish_reason = FinishReason::Stop;
                             break;
                         }
-                        output_text.push_str(&piece);
                     }
 
                     output_tokens.push(new_token);
 
+                    if repetition.push(new_token.0) {
+                        warn!(
+                            "Aborting generation: repetition loop detected after {} tokens",
+                            output_tokens.len()
+                        );
+                        finish_reason = FinishReason::Repetition;
+                        break;
+                    }
+
                     // Prepare next batch with single token at correct position
                     let mut next_batch = LlamaBatch::new(1, 1);
                     next_batch
@@ -429,12 +556,26 @@ impl LlamaEngine {
 
                     // Increment position for next token
                     n_cur += 1;
+
+                    if rate_limiter.pace() {
+                        info!(
+                            "Throttling generation to {} tokens/s",
+                            rate_limiter.max_per_sec()
+                        );
+                    }
                 }
+                log_throttling(&rate_limiter);
+
+                output_text.push_str(&stops.finish());
 
                 // Return text with token counts
-                let prompt_token_count = tokens.len();
-                let completion_token_count = output_tokens.len();
-                Ok((output_text, prompt_token_count, completion_token_count))
+                Ok(GenerationOutput {
+                    text: output_text,
+                    prompt_tokens: tokens.len(),
+                    completion_tokens: output_tokens.len(),
+                    finish_reason,
+                    stop_sequence: stops.matched().map(str::to_string),
+                })
             })
             .await?
         }
@@ -488,6 +629,8 @@ impl LlamaEngine {
                 use llama_cpp_2::model::AddBos;
                 use llama_cpp_2::sampling::LlamaSampler;
 
+                pin_inference_thread();
+
                 let context_params = LlamaContextParams::default()
                     .with_n_ctx(NonZeroU32::new(n_ctx))
                     .with_n_batch(n_batch);
@@ -515,33 +658,20 @@ impl LlamaEngine {
                     .decode(&mut batch)
                     .map_err(|e| anyhow!("Failed to decode batch: {:?}", e))?;
 
-                let mut samplers = Vec::new();
-                if sampling.repeat_penalty != 1.0 {
-                    samplers.push(LlamaSampler::penalties(
-                        sampling.repeat_last_n,
-                        sampling.repeat_penalty,
-                        0.0,
-                        0.0,
-                    ));
-                }
-                if sampling.top_k > 0 {
-                    samplers.push(LlamaSampler::top_k(sampling.top_k));
-                }
-                if sampling.top_p > 0.0 && sampling.top_p < 1.0 {
-                    samplers.push(LlamaSampler::top_p(sampling.top_p, sampling.min_keep));
-                }
-                samplers.push(LlamaSampler::temp(sampling.temperature));
-                if sampling.temperature <= 0.0 {
-                    samplers.push(LlamaSampler::greedy());
-                } else {
-                    samplers.push(LlamaSampler::dist(sampling.seed));
-                }
-
-                let mut sampler = LlamaSampler::chain_simple(samplers);
+                let mut sampler = LlamaSampler::chain_simple(sampling.samplers());
                 sampler.accept_many(tokens.iter());
 
+                let mut repetition = sampling.repetition_detector();
+                let mut stops = sampling.stop_matcher();
+                let mut rate_limiter = TokenRateLimiter::from_config();
                 let mut n_cur = tokens.len();
-                for _i in 0..max_tokens {
+                for _i in 0..generation_limit(max_tokens, n_ctx as usize, n_cur) {
+                    // The consumer went away, e.g. an SSE client disconnected;
+                    // checked every step since held-back text sends nothing
+                    if tx.is_closed() {
+                        info!("Stream receiver dropped, aborting generation");
+                        break;
+                    }
                     let new_token = sampler.sample(&context, -1);
                     sampler.accept(new_token);
 
@@ -552,19 +682,23 @@ impl LlamaEngine {
                     if let Ok(piece) =
                         model_guard.token_to_piece(new_token, &mut token_decoder, true, None)
                     {
-                        if piece.contains("<|im_end|>")
-                            || piece.contains("<|eot_id|>")
-                            || piece.contains("<|end_of_text|>")
-                            || piece.contains("</s>")
-                        {
+                        let (text, stopped) = stops.push(&piece);
+                        if !text.is_empty() && tx.blocking_send(Ok(text)).is_err() {
                             break;
                         }
-
-                        if tx.blocking_send(Ok(piece)).is_err() {
+                        if stopped {
                             break;
                         }
                     }
 
+                    if repetition.push(new_token.0) {
+                        warn!(
+                            "Aborting stream: repetition loop detected after {} tokens",
+                            n_cur + 1 - tokens.len()
+                        );
+                        break;
+                    }
+
                     let mut next_batch = LlamaBatch::new(1, 1);
                     next_batch
                         .add(new_token, n_cur as i32, &[0], true)
@@ -573,6 +707,19 @@ impl LlamaEngine {
                         .decode(&mut next_batch)
                         .map_err(|e| anyhow!("Failed to decode token: {:?}", e))?;
                     n_cur += 1;
+
+                    if rate_limiter.pace() {
+                        info!(
+                            "Throttling stream to {} tokens/s",
+                            rate_limiter.max_per_sec()
+                        );
+                    }
+                }
+                log_throttling(&rate_limiter);
+
+                let tail = stops.finish();
+                if !tail.is_empty() {
+                    let _ = tx.blocking_send(Ok(tail));
                 }
 
                 Ok::<(), anyhow::Error>(())
@@ -582,6 +729,355 @@ impl LlamaEngine {
         }
     }
 
+    /// Runs several requests together: each gets its own sequence in one
+    /// shared context, so a step decodes the next token of every request in a
+    /// single `llama_decode` instead of running them one after another.
+    /// Sampling, stop sequences and limits stay per request; the sequences
+    /// split the context window evenly.
+    pub async fn generate_batch(&self, requests: Vec<GenRequest>) -> Vec<GenResult> {
+        let count = requests.len();
+        match self.run_batch(requests).await {
+            Ok(results) => results,
+            Err(e) => (0..count).map(|_| Err(anyhow!("{:#}", e))).collect(),
+        }
+    }
+
+    async fn run_batch(&self, requests: Vec<GenRequest>) -> Result<Vec<GenResult>> {
+        if requests.is_empty() {
+            return Ok(Vec::new());
+        }
+        if !self.is_initialized {
+            return Err(anyhow!("Engine not initialized - call load_model() first"));
+        }
+
+        #[cfg(target_os = "android")]
+        {
+            warn!("Android SDK: Batched generation is not supported");
+            Err(anyhow!("Batched generation is not supported on Android"))
+        }
+
+        #[cfg(not(target_os = "android"))]
+        {
+            let backend = self
+                .cached_backend
+                .as_ref()
+                .ok_or_else(|| anyhow!("Model not loaded - call load_model() first"))?
+                .clone();
+            let model = self
+                .cached_model
+                .as_ref()
+                .ok_or_else(|| anyhow!("Model not loaded - call load_model() first"))?
+                .clone();
+
+            let n_ctx = self.n_ctx;
+            let n_batch = self.n_batch;
+
+            tokio::task::spawn_blocking(move || {
+                use llama_cpp_2::llama_batch::LlamaBatch;
+                use llama_cpp_2::model::AddBos;
+                use llama_cpp_2::sampling::LlamaSampler;
+                use llama_cpp_2::token::LlamaToken;
+
+                pin_inference_thread();
+
+                let n_seq = requests.len();
+                let context_params = LlamaContextParams::default()
+                    .with_n_ctx(NonZeroU32::new(n_ctx))
+                    .with_n_batch(n_batch)
+                    .with_n_seq_max(n_seq as u32);
+
+                let model_guard = model
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock model: {:?}", e))?;
+                let mut context = model_guard
+                    .new_context(&*backend, context_params)
+                    .map_err(|e| anyhow!("Failed to create context: {:?}", e))?;
+
+                // Each sequence gets an even share of the KV cache
+                let seq_ctx = n_ctx as usize / n_seq;
+                let mut errors: Vec<Option<anyhow::Error>> = Vec::with_capacity(n_seq);
+                let mut sequences = Vec::with_capacity(n_seq);
+                let mut plan = Vec::with_capacity(n_seq);
+                for request in &requests {
+                    let tokens = match model_guard.str_to_token(&request.prompt, AddBos::Always) {
+                        Ok(tokens) => {
+                            errors.push(None);
+                            tokens
+                        }
+                        Err(e) => {
+                            // Runs as an empty sequence that finishes right away
+                            errors.push(Some(anyhow!("Failed to tokenize prompt: {:?}", e)));
+                            Vec::new()
+                        }
+                    };
+                    let mut sampler = LlamaSampler::chain_simple(request.sampling.samplers());
+                    sampler.accept_many(tokens.iter());
+                    sequences.push(BatchSequence {
+                        sampler,
+                        repetition: request.sampling.repetition_detector(),
+                        stops: request.sampling.stop_matcher(),
+                        text: String::new(),
+                        prompt_tokens: tokens.len(),
+                        finish_reason: FinishReason::Length,
+                    });
+                    let limit = generation_limit(request.max_tokens, seq_ctx, tokens.len());
+                    plan.push((tokens.iter().map(|token| token.0).collect(), limit));
+                }
+
+                let mut scheduler = BatchScheduler::new(plan, n_batch as usize);
+                let mut batch = LlamaBatch::new((n_batch as usize).max(1), n_seq as i32);
+                let mut rate_limiter = TokenRateLimiter::from_config();
+                let eos = model_guard.token_eos();
+                loop {
+                    let entries = scheduler.next_batch();
+                    if entries.is_empty() {
+                        break;
+                    }
+                    batch.clear();
+                    for entry in &entries {
+                        batch
+                            .add(
+                                LlamaToken(entry.token),
+                                entry.pos,
+                                &[entry.seq_id],
+                                entry.logits,
+                            )
+                            .map_err(|e| anyhow!("Failed to add token to batch: {:?}", e))?;
+                    }
+                    context
+                        .decode(&mut batch)
+                        .map_err(|e| anyhow!("Failed to decode batch: {:?}", e))?;
+
+                    for (index, entry) in entries.iter().enumerate().filter(|(_, e)| e.logits) {
+                        let seq_id = entry.seq_id;
+                        let sequence = &mut sequences[seq_id as usize];
+                        let new_token = sequence.sampler.sample(&context, index as i32);
+                        sequence.sampler.accept(new_token);
+
+                        if new_token == eos {
+                            sequence.finish_reason = FinishReason::Stop;
+                            scheduler.finish(seq_id);
+                            continue;
+                        }
+                        let mut token_decoder = encoding_rs::UTF_8.new_decoder();
+                        if let Ok(piece) =
+                            model_guard.token_to_piece(new_token, &mut token_decoder, true, None)
+                        {
+                            let (text, stopped) = sequence.stops.push(&piece);
+                            sequence.text.push_str(&text);
+                            if stopped {
+                                sequence.finish_reason = FinishReason::Stop;
+                                scheduler.finish(seq_id);
+                                continue;
+                            }
+                        }
+
+                        scheduler.accept(seq_id, new_token.0);
+
+                        if sequence.repetition.push(new_token.0) {
+                            warn!(
+                                "Aborting sequence {}: repetition loop detected after {} tokens",
+                                seq_id,
+                                scheduler.generated(seq_id).len()
+                            );
+                            sequence.finish_reason = FinishReason::Repetition;
+                            scheduler.finish(seq_id);
+                        }
+
+                        if rate_limiter.pace() {
+                            info!(
+                                "Throttling generation to {} tokens/s",
+                                rate_limiter.max_per_sec()
+                            );
+                        }
+                    }
+                }
+                log_throttling(&rate_limiter);
+
+                Ok(sequences
+                    .into_iter()
+                    .zip(errors)
+                    .enumerate()
+                    .map(|(seq_id, (mut sequence, error))| match error {
+                        Some(e) => Err(e),
+                        None => {
+                            sequence.text.push_str(&sequence.stops.finish());
+                            Ok(GenerationOutput {
+                                text: sequence.text,
+                                prompt_tokens: sequence.prompt_tokens,
+                                completion_tokens: scheduler.generated(seq_id as i32).len(),
+                                finish_reason: sequence.finish_reason,
+                                stop_sequence: sequence.stops.matched().map(str::to_string),
+                            })
+                        }
+                    })
+                    .collect())
+            })
+            .await?
+        }
+    }
+
+    /// Embeds each input with the loaded model and returns one L2-normalized
+    /// vector per input, in order, plus the prompt tokens used. Inputs are
+    /// decoded together as separate sequences of shared batches in an
+    /// embeddings-mode context with mean pooling.
+    pub async fn embed(&self, inputs: Vec<String>) -> Result<(Vec<Vec<f32>>, usize)> {
+        if inputs.is_empty() {
+            return Ok((Vec::new(), 0));
+        }
+        if !self.is_initialized {
+            return Err(anyhow!("Engine not initialized - call load_model() first"));
+        }
+
+        #[cfg(target_os = "android")]
+        {
+            warn!("Android SDK: Embeddings are not supported");
+            Err(anyhow!("Embeddings are not supported on Android"))
+        }
+
+        #[cfg(not(target_os = "android"))]
+        {
+            let backend = self
+                .cached_backend
+                .as_ref()
+                .ok_or_else(|| anyhow!("Model not loaded - call load_model() first"))?
+                .clone();
+            let model = self
+                .cached_model
+                .as_ref()
+                .ok_or_else(|| anyhow!("Model not loaded - call load_model() first"))?
+                .clone();
+
+            let n_ctx = self.n_ctx;
+            let n_batch = self.n_batch;
+
+            tokio::task::spawn_blocking(move || {
+                use llama_cpp_2::context::params::LlamaPoolingType;
+                use llama_cpp_2::llama_batch::LlamaBatch;
+                use llama_cpp_2::model::AddBos;
+
+                pin_inference_thread();
+
+                let model_guard = model
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock model: {:?}", e))?;
+                let tokens = inputs
+                    .iter()
+                    .map(|input| {
+                        model_guard
+                            .str_to_token(input, AddBos::Always)
+                            .map_err(|e| anyhow!("Failed to tokenize input: {:?}", e))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                // Pooling needs a whole sequence in one batch, and each
+                // sequence only gets its share of the KV cache
+                let n_seq = inputs.len().min(MAX_EMBEDDING_SEQUENCES);
+                let max_len = (n_batch as usize).min(n_ctx as usize / n_seq);
+                if let Some((index, input)) = tokens
+                    .iter()
+                    .enumerate()
+                    .find(|(_, input)| input.len() > max_len)
+                {
+                    return Err(anyhow!(
+                        "Input {} is {} tokens, the limit is {}",
+                        index,
+                        input.len(),
+                        max_len
+                    ));
+                }
+
+                let context_params = LlamaContextParams::default()
+                    .with_n_ctx(NonZeroU32::new(n_ctx))
+                    .with_n_batch(n_batch)
+                    .with_n_ubatch(n_batch)
+                    .with_n_seq_max(n_seq as u32)
+                    .with_embeddings(true)
+                    .with_pooling_type(LlamaPoolingType::Mean);
+                let mut context = model_guard
+                    .new_context(&*backend, context_params)
+                    .map_err(|e| anyhow!("Failed to create context: {:?}", e))?;
+
+                let lengths: Vec<usize> = tokens.iter().map(Vec::len).collect();
+                let mut embeddings = Vec::with_capacity(tokens.len());
+                for group in pack_sequences(&lengths, n_batch as usize, n_seq) {
+                    context.clear_kv_cache();
+                    let mut batch = LlamaBatch::new(n_batch as usize, n_seq as i32);
+                    for (seq_id, input) in tokens[group.clone()].iter().enumerate() {
+                        batch
+                            .add_sequence(input, seq_id as i32, false)
+                            .map_err(|e| anyhow!("Failed to add input to batch: {:?}", e))?;
+                    }
+                    context
+                        .decode(&mut batch)
+                        .map_err(|e| anyhow!("Failed to decode batch: {:?}", e))?;
+
+                    for seq_id in 0..group.len() {
+                        let embedding = context
+                            .embeddings_seq_ith(seq_id as i32)
+                            .map_err(|e| anyhow!("Failed to read embedding: {:?}", e))?;
+                        embeddings.push(l2_normalize(embedding));
+                    }
+                }
+
+                Ok((embeddings, lengths.iter().sum()))
+            })
+            .await?
+        }
+    }
+
+    /// Decodes a lone BOS token so llama.cpp allocates its compute graph (and
+    /// GPU backends compile their kernels) before the first real request.
+    /// Does nothing on Android or before a model is loaded, so calling it
+    /// again is harmless.
+    pub async fn warmup(&self) -> Result<()> {
+        #[cfg(target_os = "android")]
+        {
+            debug!("Android SDK: Skipping warmup");
+            Ok(())
+        }
+
+        #[cfg(not(target_os = "android"))]
+        {
+            let (Some(backend), Some(model)) =
+                (self.cached_backend.clone(), self.cached_model.clone())
+            else {
+                debug!("No model loaded, skipping warmup");
+                return Ok(());
+            };
+            let n_ctx = self.n_ctx;
+            let n_batch = self.n_batch;
+
+            let started = std::time::Instant::now();
+            tokio::task::spawn_blocking(move || {
+                use llama_cpp_2::llama_batch::LlamaBatch;
+
+                pin_inference_thread();
+
+                let model_guard = model
+                    .lock()
+                    .map_err(|e| anyhow!("Failed to lock model: {:?}", e))?;
+                let context_params = LlamaContextParams::default()
+                    .with_n_ctx(NonZeroU32::new(n_ctx))
+                    .with_n_batch(n_batch);
+                let mut context = model_guard
+                    .new_context(&*backend, context_params)
+                    .map_err(|e| anyhow!("Failed to create context: {:?}", e))?;
+
+                let mut batch = LlamaBatch::new(1, 1);
+                batch
+                    .add(model_guard.token_bos(), 0, &[0], true)
+                    .map_err(|e| anyhow!("Failed to add token to batch: {:?}", e))?;
+                context
+                    .decode(&mut batch)
+                    .map_err(|e| anyhow!("Failed to decode warmup batch: {:?}", e))
+            })
+            .await??;
+            info!("Warmup finished in {:?}", started.elapsed());
+            Ok(())
+        }
+    }
+
     pub fn new() -> Self {
         let models_dir = dirs::home_dir()
             .unwrap_or_else(|| PathBuf::from("."))
@@ -609,6 +1105,8 @@ impl LlamaEngine {
             cached_model: None,
             #[cfg(not(target_os = "android"))]
             cached_model_path: None,
+            #[cfg(not(target_os = "android"))]
+            resident_models: LruCache::new(1),
         }
     }
 
@@ -646,6 +1144,8 @@ impl LlamaEngine {
             cached_model: None,
             #[cfg(not(target_os = "android"))]
             cached_model_path: None,
+            #[cfg(not(target_os = "android"))]
+            resident_models: LruCache::new(1),
         }
     }
 
@@ -684,7 +1184,22 @@ impl LlamaEngine {
             cached_model: None,
             #[cfg(not(target_os = "android"))]
             cached_model_path: None,
+            #[cfg(not(target_os = "android"))]
+            resident_models: LruCache::new(1),
+        }
+    }
+
+    /// Keep up to `max` recently used models loaded so requests can switch
+    /// between them without reloading (default 1). No effect on Android,
+    /// where the SDK owns the model.
+    pub fn with_max_resident_models(mut self, max: usize) -> Self {
+        #[cfg(not(target_os = "android"))]
+        {
+            self.resident_models = LruCache::new(max);
         }
+        #[cfg(target_os = "android")]
+        let _ = max;
+        self
     }
 
     async fn ensure_initialized(&mut self) -> Result<()> {
@@ -796,7 +1311,8 @@ impl LlamaEngine {
 
         debug!(
             "Generating response with prompt: {}, max_tokens: {}",
-            prompt, max_tokens
+            common::prompt_log_label(prompt),
+            max_tokens
         );
 
         #[cfg(target_os = "android")]
@@ -1007,8 +1523,9 @@ impl Engine for LlamaEngine {
                     if Some(model_path.clone()) != self.model_path {
                         info!("Unloading previous model before loading new one");
 
-                        // Clear cached model and backend to free memory
-                        self.cached_model = None;
+                        // Resident models stay loaded; the rest is evicted
+                        // by initialize_model before the new one is loaded
+                        self.release_cached_model().await;
                         self.cached_backend = None;
                         info!("Previous model cache cleared");
 
@@ -1302,7 +1819,8 @@ impl LlamaEngine {
 
         debug!(
             "Generating text with prompt: {}, max_tokens: {}",
-            prompt, max_tokens
+            common::prompt_log_label(prompt),
+            max_tokens
         );
 
         // Use the real inference method with cached model
@@ -1377,3 +1895,16 @@ impl LlamaEngine {
         Ok(metadata.len())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn warmup_without_a_model_is_a_repeatable_no_op() {
+        let engine = LlamaEngine::new();
+        engine.warmup().await.unwrap();
+        engine.warmup().await.unwrap();
+        assert!(!engine.is_ready().await);
+    }
+}
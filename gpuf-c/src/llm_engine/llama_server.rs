@@ -175,12 +175,14 @@ fn read_csv_env(name: &str) -> Vec<String> {
         .unwrap_or_default()
 }
 
-/// OpenAI compatible chat completion request
+/// OpenAI compatible chat completion request. Fields this server does not
+/// implement (`n`, `user`, `logprobs`, ...) are ignored rather than rejected so
+/// stock OpenAI clients work unchanged.
 #[derive(Debug, Deserialize)]
 pub struct ChatCompletionRequest {
     pub model: Option<String>,
     pub messages: Vec<ChatMessage>,
-    #[serde(default)]
+    #[serde(default, alias = "max_completion_tokens")]
     pub max_tokens: Option<usize>,
     #[serde(default)]
     pub temperature: Option<f32>,
@@ -189,17 +191,92 @@ pub struct ChatCompletionRequest {
     #[serde(default)]
     pub top_p: Option<f32>,
     #[serde(default)]
+    pub min_p: Option<f32>,
+    #[serde(default)]
+    pub typical_p: Option<f32>,
+    #[serde(default)]
+    pub xtc_probability: Option<f32>,
+    #[serde(default)]
+    pub xtc_threshold: Option<f32>,
+    #[serde(default)]
     pub repeat_penalty: Option<f32>,
     #[serde(default)]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default)]
+    pub presence_penalty: Option<f32>,
+    #[serde(default)]
     pub repeat_last_n: Option<i32>,
     #[serde(default)]
     pub seed: Option<u32>,
     #[serde(default)]
     pub min_keep: Option<usize>,
     #[serde(default)]
+    pub repetition_ngram_size: Option<usize>,
+    #[serde(default)]
+    pub repetition_max_repeats: Option<usize>,
+    #[serde(default)]
+    pub stop: Option<StopSequences>,
+    #[serde(default)]
     pub stream: bool,
 }
 
+impl ChatCompletionRequest {
+    /// Sampling parameters for this request; omitted fields keep the
+    /// `SamplingParams` defaults.
+    pub fn sampling_params(&self) -> Result<SamplingParams, AppError> {
+        let defaults = SamplingParams::default();
+        let sampling = SamplingParams {
+            temperature: self.temperature.unwrap_or(defaults.temperature),
+            top_k: self.top_k.unwrap_or(defaults.top_k),
+            top_p: self.top_p.unwrap_or(defaults.top_p),
+            min_p: self.min_p.unwrap_or(defaults.min_p),
+            typical_p: self.typical_p.unwrap_or(defaults.typical_p),
+            xtc_probability: self.xtc_probability.unwrap_or(defaults.xtc_probability),
+            xtc_threshold: self.xtc_threshold.unwrap_or(defaults.xtc_threshold),
+            repeat_penalty: self.repeat_penalty.unwrap_or(defaults.repeat_penalty),
+            frequency_penalty: self.frequency_penalty.unwrap_or(defaults.frequency_penalty),
+            presence_penalty: self.presence_penalty.unwrap_or(defaults.presence_penalty),
+            repeat_last_n: self.repeat_last_n.unwrap_or(defaults.repeat_last_n),
+            seed: self.seed.unwrap_or(defaults.seed),
+            min_keep: self.min_keep.unwrap_or(defaults.min_keep),
+            repetition_ngram_size: self
+                .repetition_ngram_size
+                .unwrap_or(defaults.repetition_ngram_size),
+            repetition_max_repeats: self
+                .repetition_max_repeats
+                .unwrap_or(defaults.repetition_max_repeats),
+            stop: self
+                .stop
+                .clone()
+                .map(StopSequences::into_vec)
+                .unwrap_or(defaults.stop),
+            ..defaults
+        };
+        validate_stop_sequences(&sampling.stop)?;
+        Ok(sampling)
+    }
+}
+
+/// OpenAI `stop` parameter: a single string or a list of strings.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum StopSequences {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl StopSequences {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            StopSequences::One(stop) => vec![stop],
+            StopSequences::Many(stops) => stops,
+        }
+    }
+}
+
+const MAX_STOP_SEQUENCES: usize = 16;
+const MAX_STOP_SEQUENCE_BYTES: usize = 256;
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ChatMessage {
     pub role: String,
@@ -222,6 +299,9 @@ pub struct ChatChoice {
     pub index: usize,
     pub message: ChatMessage,
     pub finish_reason: String,
+    /// Stop sequence that ended generation (absent when stopped by EOS or length)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
 }
 
 /// Streaming chunk for chat completion
@@ -241,10 +321,14 @@ pub struct ChatChoiceChunk {
     pub finish_reason: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+/// OpenAI sends the role only in the first chunk and the content in the
+/// following ones; the final chunk has an empty delta.
+#[derive(Debug, Default, Serialize)]
 pub struct ChatMessageDelta {
-    pub role: String,
-    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -268,13 +352,31 @@ pub struct CompletionRequest {
     #[serde(default)]
     pub top_p: Option<f32>,
     #[serde(default)]
+    pub min_p: Option<f32>,
+    #[serde(default)]
+    pub typical_p: Option<f32>,
+    #[serde(default)]
+    pub xtc_probability: Option<f32>,
+    #[serde(default)]
+    pub xtc_threshold: Option<f32>,
+    #[serde(default)]
     pub repeat_penalty: Option<f32>,
     #[serde(default)]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default)]
+    pub presence_penalty: Option<f32>,
+    #[serde(default)]
     pub repeat_last_n: Option<i32>,
     #[serde(default)]
     pub seed: Option<u32>,
     #[serde(default)]
     pub min_keep: Option<usize>,
+    #[serde(default)]
+    pub repetition_ngram_size: Option<usize>,
+    #[serde(default)]
+    pub repetition_max_repeats: Option<usize>,
+    #[serde(default)]
+    pub stop: Option<StopSequences>,
 }
 
 /// Text completion response
@@ -293,6 +395,57 @@ pub struct CompletionChoice {
     pub index: usize,
     pub text: String,
     pub finish_reason: String,
+    /// Stop sequence that ended generation (absent when stopped by EOS or length)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
+}
+
+/// OpenAI compatible embeddings request
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingRequest {
+    pub model: Option<String>,
+    pub input: EmbeddingInput,
+}
+
+/// OpenAI `input` parameter: one text or a list of texts embedded together.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl EmbeddingInput {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            EmbeddingInput::One(input) => vec![input],
+            EmbeddingInput::Many(inputs) => inputs,
+        }
+    }
+}
+
+const MAX_EMBEDDING_INPUTS: usize = 256;
+
+/// OpenAI compatible embeddings response
+#[derive(Debug, Serialize)]
+pub struct EmbeddingResponse {
+    pub object: String,
+    pub data: Vec<EmbeddingData>,
+    pub model: String,
+    pub usage: EmbeddingUsage,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbeddingData {
+    pub object: String,
+    pub embedding: Vec<f32>,
+    pub index: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbeddingUsage {
+    pub prompt_tokens: usize,
+    pub total_tokens: usize,
 }
 
 /// Model list response
@@ -345,6 +498,7 @@ pub fn create_router_with_security(
         .route("/v1/models", get(list_models))
         .route("/v1/chat/completions", post(chat_completions))
         .route("/v1/completions", post(completions))
+        .route("/v1/embeddings", post(embeddings))
         .route("/v1/security/metrics", get(security_metrics_handler))
         .route(
             "/v1/messages",
@@ -420,28 +574,7 @@ async fn chat_completions(
     validate_content_safety(&state.security.content_safety, &prompt, "prompt")?;
 
     let max_tokens = req.max_tokens.unwrap_or(100);
-    let mut sampling = SamplingParams::default();
-    if let Some(v) = req.temperature {
-        sampling.temperature = v;
-    }
-    if let Some(v) = req.top_k {
-        sampling.top_k = v;
-    }
-    if let Some(v) = req.top_p {
-        sampling.top_p = v;
-    }
-    if let Some(v) = req.repeat_penalty {
-        sampling.repeat_penalty = v;
-    }
-    if let Some(v) = req.repeat_last_n {
-        sampling.repeat_last_n = v;
-    }
-    if let Some(v) = req.seed {
-        sampling.seed = v;
-    }
-    if let Some(v) = req.min_keep {
-        sampling.min_keep = v;
-    }
+    let sampling = req.sampling_params()?;
 
     let model_name = req.model.unwrap_or_else(|| "llama.cpp".to_string());
     let id = format!("chatcmpl-{}", uuid::Uuid::new_v4());
@@ -455,95 +588,24 @@ async fn chat_completions(
         let sse_permit = state.try_sse_permit()?;
         let engine = state.engine.read().await;
 
-        // True streaming: use stream_with_cached_model_sampling
-        // When SSE disconnects, the channel send fails and inference stops
+        // True streaming: use stream_with_cached_model_sampling. When the
+        // client disconnects the SSE stream and its token receiver are
+        // dropped, which stops generation
         let token_stream = engine
             .stream_with_cached_model_sampling(&prompt, max_tokens, &sampling)
             .await?;
 
-        // Convert token stream to SSE stream with proper OpenAI format.
-        let id = id.clone();
-        let created = created;
-        let model_name = model_name.clone();
-        let content_safety = state.security.content_safety.clone();
-        let output_filter_state = Arc::new(Mutex::new((false, String::new())));
-        let token_events = token_stream.filter_map(move |result| {
-            let id = id.clone();
-            let model_name = model_name.clone();
-            let content_safety = content_safety.clone();
-            let output_filter_state = Arc::clone(&output_filter_state);
-
-            async move {
-                if output_filter_state
-                    .lock()
-                    .map(|state| state.0)
-                    .unwrap_or(true)
-                {
-                    return None;
-                }
-
-                let event = match result {
-                    Ok(token) => {
-                        {
-                            let mut state = output_filter_state
-                                .lock()
-                                .unwrap_or_else(|poisoned| poisoned.into_inner());
-                            state.1.push_str(&token);
-                            if state.1.len() > 65_536 {
-                                let trim_to = state.1.len() - 65_536;
-                                state.1.drain(..trim_to);
-                            }
-
-                            if let Err(err) =
-                                validate_content_safety(&content_safety, &state.1, "output")
-                            {
-                                state.0 = true;
-                                return Some(Ok::<_, std::convert::Infallible>(
-                                    sse::Event::default()
-                                        .event("error")
-                                        .data(err.public_message),
-                                ));
-                            }
-                        }
-
-                        let chunk = ChatCompletionChunk {
-                            id,
-                            object: "chat.completion.chunk".to_string(),
-                            created,
-                            model: model_name,
-                            choices: vec![ChatChoiceChunk {
-                                index: 0,
-                                delta: ChatMessageDelta {
-                                    role: "assistant".to_string(),
-                                    content: token,
-                                },
-                                finish_reason: None,
-                            }],
-                        };
-                        Ok::<_, std::convert::Infallible>(
-                            sse::Event::default().json_data(chunk).unwrap_or_else(|_| {
-                                sse::Event::default()
-                                    .event("error")
-                                    .data("json serialization failed")
-                            }),
-                        )
-                    }
-                    Err(e) => {
-                        error!("OpenAI stream token error: {}", e);
-                        Ok::<_, std::convert::Infallible>(
-                            sse::Event::default().event("error").data("stream error"),
-                        )
-                    }
-                };
-
-                Some(event)
-            }
-        });
-        let done = stream::once(async {
-            Ok::<_, std::convert::Infallible>(sse::Event::default().data("[DONE]"))
-        });
+        let events = chat_completion_events(
+            token_stream,
+            ChatChunkTemplate {
+                id,
+                created,
+                model: model_name,
+            },
+            state.security.content_safety.clone(),
+        );
         let permits = Arc::new((generation_permit, sse_permit));
-        let stream = token_events.chain(done).map(move |event| {
+        let stream = events.map(move |event| {
             let _keep_permits_alive = &permits;
             event
         });
@@ -554,9 +616,11 @@ async fn chat_completions(
         let engine = state.engine.read().await;
 
         // Non-streaming response
-        let (response_text, prompt_tokens, completion_tokens) = engine
+        let output = engine
             .generate_with_cached_model_sampling(&prompt, max_tokens, &sampling)
             .await?;
+        let (response_text, prompt_tokens, completion_tokens) =
+            (output.text, output.prompt_tokens, output.completion_tokens);
         validate_content_safety(&state.security.content_safety, &response_text, "output")?;
 
         let response = ChatCompletionResponse {
@@ -570,7 +634,8 @@ async fn chat_completions(
                     role: "assistant".to_string(),
                     content: response_text,
                 },
-                finish_reason: "stop".to_string(),
+                finish_reason: output.finish_reason.as_str().to_string(),
+                stop_reason: output.stop_sequence,
             }],
             usage: Usage {
                 prompt_tokens,
@@ -583,6 +648,128 @@ async fn chat_completions(
     }
 }
 
+/// Fields shared by every chunk of one streamed chat completion.
+#[derive(Clone)]
+struct ChatChunkTemplate {
+    id: String,
+    created: u64,
+    model: String,
+}
+
+impl ChatChunkTemplate {
+    fn event(&self, delta: ChatMessageDelta, finish_reason: Option<&str>) -> sse::Event {
+        let chunk = ChatCompletionChunk {
+            id: self.id.clone(),
+            object: "chat.completion.chunk".to_string(),
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![ChatChoiceChunk {
+                index: 0,
+                delta,
+                finish_reason: finish_reason.map(str::to_string),
+            }],
+        };
+        sse::Event::default().json_data(chunk).unwrap_or_else(|_| {
+            sse::Event::default()
+                .event("error")
+                .data("json serialization failed")
+        })
+    }
+}
+
+/// Converts generated pieces into OpenAI chat completion SSE events: a chunk
+/// with the assistant role, one content delta per piece, a final chunk with
+/// `finish_reason`, then `data: [DONE]`. Output failing the content-safety
+/// check ends the deltas with an error event.
+fn chat_completion_events<S>(
+    token_stream: S,
+    template: ChatChunkTemplate,
+    content_safety: ContentSafetyConfig,
+) -> impl futures_util::Stream<Item = Result<sse::Event, std::convert::Infallible>>
+where
+    S: futures_util::Stream<Item = Result<String>>,
+{
+    let role = template.event(
+        ChatMessageDelta {
+            role: Some("assistant".to_string()),
+            content: None,
+        },
+        None,
+    );
+    let output_filter_state = Arc::new(Mutex::new((false, String::new())));
+
+    let token_events = {
+        let template = template.clone();
+        let output_filter_state = Arc::clone(&output_filter_state);
+        token_stream.filter_map(move |result| {
+            let template = template.clone();
+            let content_safety = content_safety.clone();
+            let output_filter_state = Arc::clone(&output_filter_state);
+
+            async move {
+                if output_filter_state
+                    .lock()
+                    .map(|state| state.0)
+                    .unwrap_or(true)
+                {
+                    return None;
+                }
+
+                let event = match result {
+                    Ok(token) => {
+                        let mut state = output_filter_state
+                            .lock()
+                            .unwrap_or_else(|poisoned| poisoned.into_inner());
+                        state.1.push_str(&token);
+                        if state.1.len() > 65_536 {
+                            let trim_to = state.1.len() - 65_536;
+                            state.1.drain(..trim_to);
+                        }
+
+                        if let Err(err) =
+                            validate_content_safety(&content_safety, &state.1, "output")
+                        {
+                            state.0 = true;
+                            sse::Event::default()
+                                .event("error")
+                                .data(err.public_message)
+                        } else {
+                            template.event(
+                                ChatMessageDelta {
+                                    role: None,
+                                    content: Some(token),
+                                },
+                                None,
+                            )
+                        }
+                    }
+                    Err(e) => {
+                        error!("OpenAI stream token error: {}", e);
+                        sse::Event::default().event("error").data("stream error")
+                    }
+                };
+
+                Some(Ok(event))
+            }
+        })
+    };
+
+    let finish = stream::once(async move {
+        let filtered = output_filter_state
+            .lock()
+            .map(|state| state.0)
+            .unwrap_or(true);
+        (!filtered).then(|| Ok(template.event(ChatMessageDelta::default(), Some("stop"))))
+    })
+    .filter_map(std::future::ready);
+    let done = stream::once(async { Ok(sse::Event::default().data("[DONE]")) });
+
+    stream::once(async { Ok(role) })
+        .chain(token_events)
+        .chain(finish)
+        .chain(done)
+}
+
 /// Text completion
 async fn completions(
     State(state): State<ApiServerState>,
@@ -608,9 +795,27 @@ async fn completions(
     if let Some(v) = req.top_p {
         sampling.top_p = v;
     }
+    if let Some(v) = req.min_p {
+        sampling.min_p = v;
+    }
+    if let Some(v) = req.typical_p {
+        sampling.typical_p = v;
+    }
+    if let Some(v) = req.xtc_probability {
+        sampling.xtc_probability = v;
+    }
+    if let Some(v) = req.xtc_threshold {
+        sampling.xtc_threshold = v;
+    }
     if let Some(v) = req.repeat_penalty {
         sampling.repeat_penalty = v;
     }
+    if let Some(v) = req.frequency_penalty {
+        sampling.frequency_penalty = v;
+    }
+    if let Some(v) = req.presence_penalty {
+        sampling.presence_penalty = v;
+    }
     if let Some(v) = req.repeat_last_n {
         sampling.repeat_last_n = v;
     }
@@ -620,10 +825,22 @@ async fn completions(
     if let Some(v) = req.min_keep {
         sampling.min_keep = v;
     }
+    if let Some(v) = req.repetition_ngram_size {
+        sampling.repetition_ngram_size = v;
+    }
+    if let Some(v) = req.repetition_max_repeats {
+        sampling.repetition_max_repeats = v;
+    }
+    if let Some(v) = req.stop {
+        sampling.stop = v.into_vec();
+        validate_stop_sequences(&sampling.stop)?;
+    }
 
-    let (response_text, prompt_tokens, completion_tokens) = engine
+    let output = engine
         .generate_with_cached_model_sampling(&req.prompt, max_tokens, &sampling)
         .await?;
+    let (response_text, prompt_tokens, completion_tokens) =
+        (output.text, output.prompt_tokens, output.completion_tokens);
     validate_content_safety(&state.security.content_safety, &response_text, "output")?;
 
     let response = CompletionResponse {
@@ -637,7 +854,8 @@ async fn completions(
         choices: vec![CompletionChoice {
             index: 0,
             text: response_text,
-            finish_reason: "stop".to_string(),
+            finish_reason: output.finish_reason.as_str().to_string(),
+            stop_reason: output.stop_sequence,
         }],
         usage: Usage {
             prompt_tokens,
@@ -649,6 +867,70 @@ async fn completions(
     Ok(Json(response))
 }
 
+/// Embeddings
+async fn embeddings(
+    State(state): State<ApiServerState>,
+    Json(req): Json<EmbeddingRequest>,
+) -> Result<Json<EmbeddingResponse>, AppError> {
+    let inputs = validate_embedding_inputs(&state.security.limits, req.input)?;
+    info!("Embeddings request: {} inputs", inputs.len());
+
+    let _generation_permit = state.try_generation_permit()?;
+    let engine = state.engine.read().await;
+    let (embeddings, prompt_tokens) = engine.embed(inputs).await?;
+
+    Ok(Json(embedding_response(
+        req.model.unwrap_or_else(|| "llama.cpp".to_string()),
+        embeddings,
+        prompt_tokens,
+    )))
+}
+
+fn validate_embedding_inputs(
+    limits: &SecurityLimits,
+    input: EmbeddingInput,
+) -> Result<Vec<String>, AppError> {
+    let inputs = input.into_vec();
+    if inputs.is_empty() {
+        return Err(AppError::bad_request("input must not be empty"));
+    }
+    if inputs.len() > MAX_EMBEDDING_INPUTS {
+        return Err(AppError::bad_request(format!(
+            "too many inputs: {} exceeds limit {}",
+            inputs.len(),
+            MAX_EMBEDDING_INPUTS
+        )));
+    }
+    for input in &inputs {
+        validate_prompt_and_tokens(limits, input, None)?;
+    }
+    Ok(inputs)
+}
+
+fn embedding_response(
+    model: String,
+    embeddings: Vec<Vec<f32>>,
+    prompt_tokens: usize,
+) -> EmbeddingResponse {
+    EmbeddingResponse {
+        object: "list".to_string(),
+        data: embeddings
+            .into_iter()
+            .enumerate()
+            .map(|(index, embedding)| EmbeddingData {
+                object: "embedding".to_string(),
+                embedding,
+                index,
+            })
+            .collect(),
+        model,
+        usage: EmbeddingUsage {
+            prompt_tokens,
+            total_tokens: prompt_tokens,
+        },
+    }
+}
+
 /// Build chat prompt using various popular formats
 /// You can set CHAT_TEMPLATE env var to: chatml, llama3, alpaca, or simple (default)
 pub(crate) fn build_chat_prompt(messages: &[ChatMessage]) -> String {
@@ -749,6 +1031,23 @@ pub(crate) fn validate_prompt_and_tokens(
     Ok(())
 }
 
+pub(crate) fn validate_stop_sequences(stop: &[String]) -> Result<(), AppError> {
+    if stop.len() > MAX_STOP_SEQUENCES {
+        return Err(AppError::bad_request(format!(
+            "too many stop sequences: {} exceeds limit {}",
+            stop.len(),
+            MAX_STOP_SEQUENCES
+        )));
+    }
+    if stop.iter().any(|s| s.len() > MAX_STOP_SEQUENCE_BYTES) {
+        return Err(AppError::bad_request(format!(
+            "stop sequence exceeds {} bytes",
+            MAX_STOP_SEQUENCE_BYTES
+        )));
+    }
+    Ok(())
+}
+
 async fn require_api_key(
     State(state): State<ApiServerState>,
     req: Request<Body>,
@@ -991,6 +1290,7 @@ pub async fn start_server_with_security(
 mod tests {
     use super::*;
     use axum::http::HeaderValue;
+    use tokio_stream::wrappers::ReceiverStream;
 
     #[test]
     fn bearer_and_x_api_key_authorize() {
@@ -1025,6 +1325,88 @@ mod tests {
         assert!(after.max_token_rejections >= before.max_token_rejections + 1);
     }
 
+    #[test]
+    fn stop_accepts_string_or_list_and_is_bounded() {
+        let one: StopSequences = serde_json::from_str(r#""\n\n""#).unwrap();
+        assert_eq!(one.into_vec(), vec!["\n\n".to_string()]);
+        let many: StopSequences = serde_json::from_str(r#"["</tool_call>", "END"]"#).unwrap();
+        assert_eq!(many.into_vec().len(), 2);
+
+        assert!(validate_stop_sequences(&["END".to_string()]).is_ok());
+        let too_many: Vec<String> = (0..=MAX_STOP_SEQUENCES).map(|i| i.to_string()).collect();
+        assert!(validate_stop_sequences(&too_many).is_err());
+        assert!(validate_stop_sequences(&["x".repeat(MAX_STOP_SEQUENCE_BYTES + 1)]).is_err());
+    }
+
+    #[test]
+    fn openai_chat_payload_maps_to_sampling_params() {
+        let req: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o-mini",
+            "messages": [
+                {"role": "system", "content": "You are a helpful assistant."},
+                {"role": "user", "content": "Write a haiku about GPUs."}
+            ],
+            "temperature": 0.2,
+            "top_p": 0.9,
+            "top_k": 20,
+            "max_completion_tokens": 64,
+            "repeat_penalty": 1.3,
+            "stop": ["\n\n", "END"],
+            "seed": 42,
+            "stream": true,
+            "n": 1,
+            "user": "user-1234",
+            "frequency_penalty": 0.5,
+            "presence_penalty": 0.25,
+            "logprobs": false
+        }))
+        .unwrap();
+        assert_eq!(req.messages.len(), 2);
+        assert_eq!(req.messages[1].role, "user");
+        assert_eq!(req.max_tokens, Some(64));
+        assert!(req.stream);
+
+        let sampling = req.sampling_params().unwrap();
+        assert_eq!(sampling.temperature, 0.2);
+        assert_eq!(sampling.top_p, 0.9);
+        assert_eq!(sampling.top_k, 20);
+        assert_eq!(sampling.repeat_penalty, 1.3);
+        assert_eq!(sampling.frequency_penalty, 0.5);
+        assert_eq!(sampling.presence_penalty, 0.25);
+        assert_eq!(sampling.seed, 42);
+        assert_eq!(sampling.stop, vec!["\n\n".to_string(), "END".to_string()]);
+        let defaults = SamplingParams::default();
+        assert_eq!(sampling.repeat_last_n, defaults.repeat_last_n);
+        assert_eq!(sampling.min_keep, defaults.min_keep);
+    }
+
+    #[test]
+    fn omitted_chat_sampling_fields_use_defaults() {
+        let req: ChatCompletionRequest = serde_json::from_str(
+            r#"{"messages": [{"role": "user", "content": "Hi"}], "stop": "\n"}"#,
+        )
+        .unwrap();
+        assert!(req.model.is_none());
+        assert!(req.max_tokens.is_none());
+        assert!(!req.stream);
+
+        let sampling = req.sampling_params().unwrap();
+        let defaults = SamplingParams::default();
+        assert_eq!(sampling.temperature, defaults.temperature);
+        assert_eq!(sampling.top_p, defaults.top_p);
+        assert_eq!(sampling.top_k, defaults.top_k);
+        assert_eq!(sampling.repeat_penalty, defaults.repeat_penalty);
+        assert_eq!(sampling.seed, defaults.seed);
+        assert_eq!(sampling.stop, vec!["\n".to_string()]);
+
+        let too_many: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "messages": [],
+            "stop": (0..=MAX_STOP_SEQUENCES).map(|i| i.to_string()).collect::<Vec<_>>()
+        }))
+        .unwrap();
+        assert!(too_many.sampling_params().is_err());
+    }
+
     #[test]
     fn content_safety_is_opt_in_and_records_rejections() {
         let before = security_metrics::snapshot();
@@ -1048,4 +1430,234 @@ mod tests {
         assert!(is_loopback_host("localhost"));
         assert!(!is_loopback_host("0.0.0.0"));
     }
+
+    type FakeTokens = Arc<Mutex<Option<ReceiverStream<Result<String>>>>>;
+
+    // Chat endpoint fed from a channel instead of a model, so the SSE output
+    // can be checked over HTTP
+    async fn fake_chat_completions(
+        State(tokens): State<FakeTokens>,
+        Json(req): Json<ChatCompletionRequest>,
+    ) -> Response {
+        assert!(req.stream);
+        let tokens = tokens.lock().unwrap().take().expect("one request per test");
+        let template = ChatChunkTemplate {
+            id: "chatcmpl-test".to_string(),
+            created: 0,
+            model: "test".to_string(),
+        };
+        sse::Sse::new(chat_completion_events(
+            tokens,
+            template,
+            ContentSafetyConfig::default(),
+        ))
+        .into_response()
+    }
+
+    async fn serve(app: Router) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        addr
+    }
+
+    async fn post_streaming_chat(tokens: ReceiverStream<Result<String>>) -> reqwest::Response {
+        let app = Router::new()
+            .route("/v1/chat/completions", post(fake_chat_completions))
+            .with_state(Arc::new(Mutex::new(Some(tokens))));
+        let addr = serve(app).await;
+
+        reqwest::Client::new()
+            .post(format!("http://{}/v1/chat/completions", addr))
+            .json(&serde_json::json!({
+                "messages": [{"role": "user", "content": "Hi"}],
+                "stream": true,
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn streaming_chat_sends_openai_deltas_then_done() {
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        for piece in ["Hel", "lo", "!"] {
+            tx.send(Ok(piece.to_string())).await.unwrap();
+        }
+        drop(tx);
+
+        let response = post_streaming_chat(ReceiverStream::new(rx)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let content_type = response.headers()[header::CONTENT_TYPE].to_str().unwrap();
+        assert!(content_type.starts_with("text/event-stream"));
+
+        let body = response.text().await.unwrap();
+        let data: Vec<&str> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .collect();
+        assert_eq!(data.last(), Some(&"[DONE]"));
+
+        let chunks: Vec<serde_json::Value> = data[..data.len() - 1]
+            .iter()
+            .map(|chunk| serde_json::from_str(chunk).unwrap())
+            .collect();
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk["object"] == "chat.completion.chunk"));
+        let deltas: Vec<&serde_json::Value> =
+            chunks.iter().map(|chunk| &chunk["choices"][0]).collect();
+        assert_eq!(deltas[0]["delta"]["role"], "assistant");
+        let content: Vec<&str> = deltas
+            .iter()
+            .filter_map(|choice| choice["delta"]["content"].as_str())
+            .collect();
+        assert_eq!(content, ["Hel", "lo", "!"]);
+        assert_eq!(deltas.last().unwrap()["finish_reason"], "stop");
+    }
+
+    #[tokio::test]
+    async fn client_disconnect_drops_the_token_stream() {
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        tx.send(Ok("first".to_string())).await.unwrap();
+
+        let response = post_streaming_chat(ReceiverStream::new(rx)).await;
+        let mut body = response.bytes_stream();
+        assert!(body.next().await.unwrap().is_ok());
+        drop(body);
+
+        // The generation side sees the receiver go away and stops
+        tokio::time::timeout(std::time::Duration::from_secs(5), tx.closed())
+            .await
+            .expect("token stream still open after the client disconnected");
+    }
+
+    // Embeddings endpoint with a stand-in for `LlamaEngine::embed`: a fixed
+    // dimension vector derived from each input's bytes
+    async fn fake_embeddings(Json(req): Json<EmbeddingRequest>) -> Response {
+        let limits = SecurityLimits::from_env();
+        let inputs = match validate_embedding_inputs(&limits, req.input) {
+            Ok(inputs) => inputs,
+            Err(err) => return err.into_response(),
+        };
+        let embeddings = inputs
+            .iter()
+            .map(|input| {
+                let mut embedding = vec![0.0f32; 8];
+                for (i, byte) in input.bytes().enumerate() {
+                    embedding[i % 8] += byte as f32;
+                }
+                embedding
+            })
+            .collect();
+        let prompt_tokens = inputs.iter().map(String::len).sum();
+        Json(embedding_response(
+            req.model.unwrap_or_default(),
+            embeddings,
+            prompt_tokens,
+        ))
+        .into_response()
+    }
+
+    #[tokio::test]
+    async fn embeddings_return_one_vector_per_input() {
+        let addr = serve(Router::new().route("/v1/embeddings", post(fake_embeddings))).await;
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/v1/embeddings", addr);
+
+        let response: serde_json::Value = client
+            .post(&url)
+            .json(&serde_json::json!({
+                "model": "test",
+                "input": ["first text", "second"],
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(response["object"], "list");
+        assert_eq!(response["model"], "test");
+        let data = response["data"].as_array().unwrap();
+        assert_eq!(data.len(), 2);
+        for (index, item) in data.iter().enumerate() {
+            assert_eq!(item["object"], "embedding");
+            assert_eq!(item["index"], index);
+        }
+        let first = data[0]["embedding"].as_array().unwrap();
+        let second = data[1]["embedding"].as_array().unwrap();
+        assert!(!first.is_empty());
+        assert_eq!(first.len(), second.len());
+        assert_ne!(first, second);
+        assert_eq!(response["usage"]["total_tokens"], 16);
+
+        // A single string is one input; an empty list is rejected
+        let single: serde_json::Value = client
+            .post(&url)
+            .json(&serde_json::json!({"input": "only"}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(single["data"].as_array().unwrap().len(), 1);
+        let empty = client
+            .post(&url)
+            .json(&serde_json::json!({"input": []}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(empty.status(), StatusCode::BAD_REQUEST);
+    }
+
+    type GatedState = (ApiServerState, tokio::sync::watch::Receiver<bool>);
+
+    // Holds a generation permit until the test opens the gate, standing in
+    // for a long generation
+    async fn gated_generation(
+        State((state, mut gate)): State<GatedState>,
+    ) -> Result<&'static str, AppError> {
+        let _permit = state.try_generation_permit()?;
+        gate.wait_for(|open| *open).await.unwrap();
+        Ok("done")
+    }
+
+    #[tokio::test]
+    async fn generations_beyond_the_limit_get_429() {
+        let security = ServerSecurityConfig {
+            api_key: None,
+            limits: SecurityLimits {
+                max_concurrent_generations: 2,
+                ..SecurityLimits::from_env()
+            },
+            content_safety: ContentSafetyConfig::default(),
+        };
+        let state = ApiServerState::new(Arc::new(RwLock::new(LlamaEngine::new())), security);
+        let (open_gate, gate) = tokio::sync::watch::channel(false);
+        let app = Router::new()
+            .route("/generate", post(gated_generation))
+            .with_state((state, gate));
+        let url = format!("http://{}/generate", serve(app).await);
+
+        let (done_tx, mut done_rx) = tokio::sync::mpsc::unbounded_channel();
+        for _ in 0..5 {
+            let (url, done_tx) = (url.clone(), done_tx.clone());
+            tokio::spawn(async move {
+                let response = reqwest::Client::new().post(url).send().await.unwrap();
+                done_tx.send(response.status()).unwrap();
+            });
+        }
+
+        // The two permit holders are parked on the gate, so the first three
+        // responses are the rejections
+        for _ in 0..3 {
+            assert_eq!(done_rx.recv().await.unwrap(), StatusCode::TOO_MANY_REQUESTS);
+        }
+        open_gate.send(true).unwrap();
+        for _ in 0..2 {
+            assert_eq!(done_rx.recv().await.unwrap(), StatusCode::OK);
+        }
+    }
 }
@@ -2,7 +2,9 @@ use anyhow::{anyhow, Result};
 use clap::Parser;
 use gpuf_c::{
     handle::{new_worker, WorkerHandle},
+    util::affinity::{parse_cpu_affinity, set_thread_affinity_mask},
     util::cmd::Args,
+    util::generation::{set_control_tokens, set_max_tokens_per_sec},
     util::init_logging,
 };
 
@@ -26,6 +28,16 @@ async fn main() -> Result<()> {
     }));
 
     let args = Args::parse().load_config()?;
+    common::set_log_prompts(args.log_prompts);
+    set_max_tokens_per_sec(args.max_tokens_per_sec);
+    set_control_tokens(args.control_tokens());
+    if let Some(spec) = &args.cpu_affinity {
+        let mask = parse_cpu_affinity(spec)?;
+        if mask == 0 {
+            tracing::warn!("No distinct performance cores found; inference threads are not pinned");
+        }
+        set_thread_affinity_mask(mask);
+    }
 
     // Check if running in standalone LLAMA mode
     #[cfg(not(target_os = "android"))]
@@ -35,7 +47,7 @@ async fn main() -> Result<()> {
 
     // Normal GPUFabric worker mode
     loop {
-        let worker = new_worker(args.clone()).await;
+        let worker = new_worker(args.clone()).await?;
 
         if let Err(e) = worker.login().await {
             tracing::error!(error = %e, "gpuf-c login failed");
@@ -119,6 +131,7 @@ async fn run_standalone_llama(mut args: Args) -> Result<()> {
 
     engine.init().await?;
     engine.start_worker().await?;
+    engine.warmup().await?;
 
     info!("Model loaded successfully!");
     info!("Engine ready: {}", engine.is_ready().await);
@@ -131,6 +144,7 @@ async fn run_standalone_llama(mut args: Args) -> Result<()> {
     info!("OpenAI compatible endpoints:");
     info!("  - POST http://{}:{}/v1/chat/completions", host, port);
     info!("  - POST http://{}:{}/v1/completions", host, port);
+    info!("  - POST http://{}:{}/v1/embeddings", host, port);
     info!("  - GET  http://{}:{}/v1/models", host, port);
     info!("  - GET  http://{}:{}/health", host, port);
 
@@ -143,6 +157,7 @@ async fn run_standalone_llama(mut args: Args) -> Result<()> {
     {
         security.api_key = Some(api_key);
     }
+    security.limits.max_concurrent_generations = args.max_concurrent_generations;
     start_server_with_security(engine, &host, port, security).await?;
 
     Ok(())
@@ -0,0 +1,173 @@
+use anyhow::{anyhow, Result};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Cores inference threads are pinned to, one bit per CPU (0 = not pinned).
+/// Only CPUs 0-63 can be selected.
+static THREAD_AFFINITY_MASK: AtomicU64 = AtomicU64::new(0);
+
+pub const MAX_CPUS: usize = 64;
+
+thread_local! {
+    // Mask this thread was last pinned to, so clearing the setting unpins it
+    static PINNED_MASK: Cell<u64> = const { Cell::new(0) };
+}
+
+pub fn set_thread_affinity_mask(mask: u64) {
+    THREAD_AFFINITY_MASK.store(mask, Ordering::Relaxed);
+}
+
+pub fn thread_affinity_mask() -> u64 {
+    THREAD_AFFINITY_MASK.load(Ordering::Relaxed)
+}
+
+/// Parses `--cpu-affinity`: "performance" for the fast cores, or a core
+/// list such as "4-7" or "0,2,4-5". Returns the mask (0 when the CPU has no
+/// distinct performance cores).
+pub fn parse_cpu_affinity(spec: &str) -> Result<u64> {
+    let spec = spec.trim();
+    if spec.eq_ignore_ascii_case("performance") {
+        return Ok(performance_core_mask(&cpu_max_freqs()));
+    }
+
+    let mut mask = 0u64;
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (first.trim(), last.trim()),
+            None => (part, part),
+        };
+        let first: usize = first
+            .parse()
+            .map_err(|_| anyhow!("Invalid CPU core '{}' in '{}'", first, spec))?;
+        let last: usize = last
+            .parse()
+            .map_err(|_| anyhow!("Invalid CPU core '{}' in '{}'", last, spec))?;
+        if first > last || last >= MAX_CPUS {
+            return Err(anyhow!(
+                "Invalid CPU core range '{}' (cores 0-{} are supported)",
+                part,
+                MAX_CPUS - 1
+            ));
+        }
+        for cpu in first..=last {
+            mask |= 1 << cpu;
+        }
+    }
+    if mask == 0 {
+        return Err(anyhow!("CPU affinity '{}' selects no cores", spec));
+    }
+    Ok(mask)
+}
+
+/// Cores clocked above the slowest cluster, e.g. the big and prime cores of a
+/// big.LITTLE SoC. `max_freqs[cpu]` is the core's maximum frequency, `None`
+/// if unknown. Returns 0 when all known cores run at the same speed.
+pub fn performance_core_mask(max_freqs: &[Option<u64>]) -> u64 {
+    let known = || max_freqs.iter().take(MAX_CPUS).flatten();
+    let Some(slowest) = known().min().copied() else {
+        return 0;
+    };
+    max_freqs
+        .iter()
+        .take(MAX_CPUS)
+        .enumerate()
+        .filter(|(_, freq)| freq.is_some_and(|freq| freq > slowest))
+        .fold(0, |mask, (cpu, _)| mask | 1 << cpu)
+}
+
+/// Maximum frequency of each CPU from sysfs; empty where cpufreq is not
+/// exposed.
+pub fn cpu_max_freqs() -> Vec<Option<u64>> {
+    (0..MAX_CPUS)
+        .map_while(|cpu| {
+            let dir = format!("/sys/devices/system/cpu/cpu{}", cpu);
+            std::path::Path::new(&dir).exists().then(|| {
+                std::fs::read_to_string(format!("{}/cpufreq/cpuinfo_max_freq", dir))
+                    .ok()
+                    .and_then(|freq| freq.trim().parse().ok())
+            })
+        })
+        .collect()
+}
+
+/// Pins the calling thread to the configured cores. Threads it starts
+/// afterwards, like llama.cpp's compute threads, inherit the mask. A thread
+/// pinned earlier is released to all cores once the setting is cleared.
+/// Returns whether the thread is pinned; a no-op on platforms without
+/// `sched_setaffinity`.
+pub fn pin_current_thread() -> Result<bool> {
+    let mask = thread_affinity_mask();
+    let pinned = PINNED_MASK.with(Cell::get);
+    if mask == pinned {
+        return Ok(mask != 0);
+    }
+    // u64::MAX lets the thread run anywhere again
+    let applied = pin_current_thread_to(if mask == 0 { u64::MAX } else { mask })?;
+    if applied {
+        PINNED_MASK.with(|cell| cell.set(mask));
+    }
+    Ok(applied && mask != 0)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn pin_current_thread_to(mask: u64) -> Result<bool> {
+    // SAFETY: `set` is a zeroed, properly sized cpu_set_t on the stack and only
+    // CPUs below MAX_CPUS (well under CPU_SETSIZE) are added; pid 0 targets the
+    // calling thread.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for cpu in (0..MAX_CPUS).filter(|cpu| mask & (1 << cpu) != 0) {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(anyhow!(
+                "sched_setaffinity({:#x}) failed: {}",
+                mask,
+                std::io::Error::last_os_error()
+            ));
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn pin_current_thread_to(_mask: u64) -> Result<bool> {
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_core_lists() {
+        assert_eq!(parse_cpu_affinity("4-7").unwrap(), 0xF0);
+        assert_eq!(parse_cpu_affinity("0, 2,4-5").unwrap(), 0b110101);
+        assert_eq!(parse_cpu_affinity("63").unwrap(), 1 << 63);
+        assert!(parse_cpu_affinity("64").is_err());
+        assert!(parse_cpu_affinity("7-4").is_err());
+        assert!(parse_cpu_affinity("big").is_err());
+        assert!(parse_cpu_affinity("").is_err());
+    }
+
+    #[test]
+    fn performance_cores_exclude_the_slowest_cluster() {
+        // 4 little, 3 big, 1 prime
+        let phone = [
+            Some(1_800_000),
+            Some(1_800_000),
+            Some(1_800_000),
+            Some(1_800_000),
+            Some(2_400_000),
+            Some(2_400_000),
+            Some(2_400_000),
+            Some(3_000_000),
+        ];
+        assert_eq!(performance_core_mask(&phone), 0xF0);
+
+        // Homogeneous or unknown: nothing to pin
+        assert_eq!(performance_core_mask(&[Some(3_000_000); 8]), 0);
+        assert_eq!(performance_core_mask(&[None, None]), 0);
+        assert_eq!(performance_core_mask(&[None, Some(1), Some(2)]), 0b100);
+    }
+}
@@ -0,0 +1,94 @@
+/// Inference backend a mobile model is loaded on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InferenceBackend {
+    Cpu,
+    Vulkan,
+}
+
+impl InferenceBackend {
+    /// Parses the `GPUF_BACKEND_*` code used over FFI.
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(InferenceBackend::Cpu),
+            1 => Some(InferenceBackend::Vulkan),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> i32 {
+        match self {
+            InferenceBackend::Cpu => 0,
+            InferenceBackend::Vulkan => 1,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            InferenceBackend::Cpu => "cpu",
+            InferenceBackend::Vulkan => "vulkan",
+        }
+    }
+
+    /// Backend a model actually lands on: Vulkan falls back to the CPU when no
+    /// hardware Vulkan device was found.
+    pub fn resolve(preferred: Self, vulkan_available: bool) -> Self {
+        match preferred {
+            InferenceBackend::Vulkan if vulkan_available => InferenceBackend::Vulkan,
+            _ => InferenceBackend::Cpu,
+        }
+    }
+
+    /// `n_gpu_layers` for llama.cpp model loading.
+    pub fn n_gpu_layers(self) -> i32 {
+        match self {
+            InferenceBackend::Cpu => 0,
+            InferenceBackend::Vulkan => 99,
+        }
+    }
+}
+
+/// Formats a packed `VK_MAKE_API_VERSION` value as "major.minor.patch".
+pub fn format_vulkan_version(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        (version >> 22) & 0x7f,
+        (version >> 12) & 0x3ff,
+        version & 0xfff
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vulkan_preference_falls_back_to_cpu() {
+        assert_eq!(
+            InferenceBackend::resolve(InferenceBackend::Vulkan, true),
+            InferenceBackend::Vulkan
+        );
+        assert_eq!(
+            InferenceBackend::resolve(InferenceBackend::Vulkan, false),
+            InferenceBackend::Cpu
+        );
+        assert_eq!(
+            InferenceBackend::resolve(InferenceBackend::Cpu, true),
+            InferenceBackend::Cpu
+        );
+
+        for backend in [InferenceBackend::Cpu, InferenceBackend::Vulkan] {
+            assert_eq!(InferenceBackend::from_code(backend.code()), Some(backend));
+        }
+        assert_eq!(InferenceBackend::from_code(7), None);
+    }
+
+    #[test]
+    fn formats_packed_vulkan_versions() {
+        // VK_MAKE_API_VERSION(0, 1, 3, 255)
+        assert_eq!(
+            format_vulkan_version((1 << 22) | (3 << 12) | 255),
+            "1.3.255"
+        );
+        assert_eq!(format_vulkan_version(1 << 22), "1.0.0");
+    }
+}
@@ -0,0 +1,281 @@
+use std::ops::Range;
+
+/// One token of a multi-sequence decode batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchEntry {
+    pub token: i32,
+    pub pos: i32,
+    pub seq_id: i32,
+    /// Set on the entry the sequence samples its next token from.
+    pub logits: bool,
+}
+
+#[derive(Debug)]
+struct Sequence {
+    prompt: Vec<i32>,
+    // Prompt tokens already handed out for decoding
+    prefilled: usize,
+    generated: Vec<i32>,
+    // The last generated token still has to be decoded
+    pending: bool,
+    limit: usize,
+    finished: bool,
+}
+
+/// Packs several generations into shared decode batches so one `llama_decode`
+/// advances all of them. Request `i` runs as sequence `i`; prompts are
+/// prefilled in chunks of at most `n_batch` tokens, then every live sequence
+/// contributes its last sampled token each step.
+///
+/// After decoding a batch the caller samples one token for each entry with
+/// `logits` set and reports it through `accept` or `finish` before asking for
+/// the next batch.
+#[derive(Debug)]
+pub struct BatchScheduler {
+    sequences: Vec<Sequence>,
+    n_batch: usize,
+}
+
+impl BatchScheduler {
+    /// `requests` holds each sequence's prompt tokens and how many tokens it
+    /// may generate. A sequence with a limit of 0 finishes without decoding.
+    pub fn new(requests: Vec<(Vec<i32>, usize)>, n_batch: usize) -> Self {
+        let sequences = requests
+            .into_iter()
+            .map(|(prompt, limit)| Sequence {
+                finished: limit == 0 || prompt.is_empty(),
+                prompt,
+                prefilled: 0,
+                generated: Vec::new(),
+                pending: false,
+                limit,
+            })
+            .collect();
+        Self {
+            sequences,
+            n_batch: n_batch.max(1),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sequences.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
+
+    /// Entries to decode next, at most `n_batch` of them. Empty once every
+    /// sequence has finished.
+    pub fn next_batch(&mut self) -> Vec<BatchEntry> {
+        let mut entries = Vec::new();
+        for (seq_id, seq) in self.sequences.iter_mut().enumerate() {
+            let room = self.n_batch - entries.len();
+            if room == 0 {
+                break;
+            }
+            if seq.finished {
+                continue;
+            }
+            if seq.prefilled < seq.prompt.len() {
+                let end = (seq.prefilled + room).min(seq.prompt.len());
+                entries.extend((seq.prefilled..end).map(|pos| BatchEntry {
+                    token: seq.prompt[pos],
+                    pos: pos as i32,
+                    seq_id: seq_id as i32,
+                    logits: pos + 1 == seq.prompt.len(),
+                }));
+                seq.prefilled = end;
+            } else if seq.pending {
+                let pos = seq.prompt.len() + seq.generated.len() - 1;
+                entries.push(BatchEntry {
+                    token: seq.generated[seq.generated.len() - 1],
+                    pos: pos as i32,
+                    seq_id: seq_id as i32,
+                    logits: true,
+                });
+                seq.pending = false;
+            }
+        }
+        entries
+    }
+
+    /// Records the token sampled for `seq_id`. The sequence finishes once it
+    /// reaches its limit.
+    pub fn accept(&mut self, seq_id: i32, token: i32) {
+        let seq = &mut self.sequences[seq_id as usize];
+        seq.generated.push(token);
+        seq.pending = true;
+        if seq.generated.len() >= seq.limit {
+            seq.finished = true;
+            seq.pending = false;
+        }
+    }
+
+    /// Ends `seq_id` early, e.g. on EOS or a stop sequence.
+    pub fn finish(&mut self, seq_id: i32) {
+        let seq = &mut self.sequences[seq_id as usize];
+        seq.finished = true;
+        seq.pending = false;
+    }
+
+    /// Tokens accepted for `seq_id` so far.
+    pub fn generated(&self, seq_id: i32) -> &[i32] {
+        &self.sequences[seq_id as usize].generated
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.sequences.iter().all(|seq| seq.finished)
+    }
+}
+
+/// Splits sequences of the given token counts, in order, into groups decoded
+/// together in one batch: each group holds at most `max_seqs` sequences and
+/// `max_tokens` tokens. A sequence longer than `max_tokens` gets a group of
+/// its own.
+pub fn pack_sequences(lengths: &[usize], max_tokens: usize, max_seqs: usize) -> Vec<Range<usize>> {
+    let max_seqs = max_seqs.max(1);
+    let mut groups = Vec::new();
+    let mut start = 0;
+    let mut tokens = 0;
+    for (i, &len) in lengths.iter().enumerate() {
+        if i > start && (i - start == max_seqs || tokens + len > max_tokens) {
+            groups.push(start..i);
+            start = i;
+            tokens = 0;
+        }
+        tokens += len;
+    }
+    if start < lengths.len() {
+        groups.push(start..lengths.len());
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Stand-in for a model with a per-sequence KV cache. Its next token
+    /// depends on everything decoded for that sequence, so any leak between
+    /// sequences or wrong position changes the output.
+    #[derive(Default)]
+    struct FakeModel {
+        kv: HashMap<i32, Vec<i32>>,
+    }
+
+    impl FakeModel {
+        fn decode(&mut self, entries: &[BatchEntry]) {
+            for entry in entries {
+                let cache = self.kv.entry(entry.seq_id).or_default();
+                assert_eq!(
+                    entry.pos as usize,
+                    cache.len(),
+                    "gap in sequence {}",
+                    entry.seq_id
+                );
+                cache.push(entry.token);
+            }
+        }
+
+        fn sample(&self, seq_id: i32) -> i32 {
+            let cache = &self.kv[&seq_id];
+            cache
+                .iter()
+                .enumerate()
+                .map(|(i, token)| (i as i32 + 1) * token)
+                .sum::<i32>()
+                % 1000
+        }
+    }
+
+    fn run(requests: Vec<(Vec<i32>, usize)>, n_batch: usize) -> (Vec<Vec<i32>>, usize) {
+        let mut model = FakeModel::default();
+        let mut scheduler = BatchScheduler::new(requests, n_batch);
+        let mut decodes = 0;
+        loop {
+            let entries = scheduler.next_batch();
+            if entries.is_empty() {
+                break;
+            }
+            assert!(entries.len() <= n_batch);
+            model.decode(&entries);
+            decodes += 1;
+            for entry in entries.iter().filter(|entry| entry.logits) {
+                let token = model.sample(entry.seq_id);
+                scheduler.accept(entry.seq_id, token);
+            }
+        }
+        assert!(scheduler.is_done());
+        let outputs = (0..scheduler.len() as i32)
+            .map(|seq_id| scheduler.generated(seq_id).to_vec())
+            .collect();
+        (outputs, decodes)
+    }
+
+    #[test]
+    fn batched_sequences_match_running_alone() {
+        let first = (vec![1, 2, 3], 4);
+        let second = (vec![7, 8], 6);
+
+        let (alone_first, _) = run(vec![first.clone()], 8);
+        let (alone_second, _) = run(vec![second.clone()], 8);
+        let (batched, decodes) = run(vec![first, second], 8);
+
+        assert_eq!(
+            batched,
+            vec![alone_first[0].clone(), alone_second[0].clone()]
+        );
+        assert_eq!(batched[0].len(), 4);
+        assert_eq!(batched[1].len(), 6);
+        assert_ne!(batched[0], batched[1]);
+        // One prefill for both prompts, then one decode per step until the
+        // longer sequence is done
+        assert_eq!(decodes, 6);
+    }
+
+    #[test]
+    fn prompts_longer_than_the_batch_are_prefilled_in_chunks() {
+        let requests = vec![((1..=5).collect(), 2), (vec![9, 9, 9], 3), (vec![4], 0)];
+        let (small_batches, _) = run(requests.clone(), 3);
+        let (one_batch, _) = run(requests, 16);
+        assert_eq!(small_batches, one_batch);
+        assert!(small_batches[2].is_empty());
+    }
+
+    #[test]
+    fn finished_sequences_leave_the_batch() {
+        let mut scheduler = BatchScheduler::new(vec![(vec![1], 5), (vec![2], 5)], 8);
+        assert_eq!(scheduler.next_batch().len(), 2);
+        scheduler.finish(0);
+        scheduler.accept(1, 42);
+
+        let entries = scheduler.next_batch();
+        assert_eq!(
+            entries,
+            vec![BatchEntry {
+                token: 42,
+                pos: 1,
+                seq_id: 1,
+                logits: true
+            }]
+        );
+        assert!(!scheduler.is_done());
+        scheduler.finish(1);
+        assert!(scheduler.next_batch().is_empty());
+        assert!(scheduler.is_done());
+    }
+
+    #[test]
+    fn packs_sequences_by_tokens_and_count() {
+        assert_eq!(pack_sequences(&[3, 4, 2, 6], 8, 4), vec![0..2, 2..4]);
+        assert_eq!(pack_sequences(&[3, 4, 2, 7], 8, 4), vec![0..2, 2..3, 3..4]);
+        assert_eq!(
+            pack_sequences(&[1, 1, 1, 1, 1], 8, 2),
+            vec![0..2, 2..4, 4..5]
+        );
+        assert_eq!(pack_sequences(&[10, 1], 8, 4), vec![0..1, 1..2]);
+        assert!(pack_sequences(&[], 8, 4).is_empty());
+    }
+}
@@ -2,7 +2,12 @@ use anyhow::{Context, Result};
 use clap::{Parser, ValueEnum};
 
 use crate::util::config::Config;
-use tracing::info;
+use crate::util::generation::ControlTokens;
+use tracing::{info, warn};
+
+/// Bounds `stream_chunk_bytes` is clamped to: smaller chunks flood the
+/// server with a frame per byte, larger ones stall streaming output.
+pub const STREAM_CHUNK_BYTES_RANGE: std::ops::RangeInclusive<usize> = 16..=65536;
 
 #[derive(ValueEnum, Debug, Clone, PartialEq, Eq, serde::Serialize)]
 pub enum LlamaSplitModeArg {
@@ -118,6 +123,19 @@ pub struct Args {
     #[arg(long, default_value_t = false)]
     pub p2p_public_listen: bool,
 
+    /// P2P UDP fragments of one message that may await an ACK at once.
+    #[arg(long, default_value_t = 8)]
+    pub p2p_send_window: u16,
+
+    /// Seconds each TURN step may take: the TLS connect or one request/response.
+    #[arg(long, default_value_t = 3)]
+    pub turn_op_timeout_secs: u64,
+
+    /// Seconds a relay setup through one TURN server may take before failing
+    /// over to the next configured server.
+    #[arg(long, default_value_t = 10)]
+    pub turn_setup_timeout_secs: u64,
+
     /// Certificate chain for TLS
     #[arg(long, default_value = "ca-cert.pem")]
     pub cert_chain_path: String,
@@ -161,6 +179,15 @@ pub struct Args {
     #[arg(long, env = "GPUF_API_KEY", default_value = None)]
     pub api_key: Option<String>,
 
+    /// Generations the standalone server runs at once; requests beyond this get 429
+    #[arg(
+        long,
+        env = "GPUF_MAX_CONCURRENT_GENERATIONS",
+        default_value_t = 2,
+        help = "Reject standalone API generations with 429 once N are in flight"
+    )]
+    pub max_concurrent_generations: usize,
+
     /// Model path for standalone LLAMA server
     #[arg(long, help = "Path to GGUF model file for standalone mode")]
     pub llama_model_path: Option<String>,
@@ -208,14 +235,97 @@ pub struct Args {
 
     #[arg(
         long,
-        default_value_t = 1,
-        help = "Max bytes per streamed delta chunk sent to server"
+        default_value_t = 256,
+        help = "Max bytes per streamed delta chunk sent to server (16-65536)"
     )]
     pub stream_chunk_bytes: usize,
+
+    /// Send heartbeats with only the usage readings that changed, for metered links
+    #[arg(long, help = "Send trimmed heartbeats that skip static device fields")]
+    pub lite_heartbeat: bool,
+
+    #[arg(
+        long,
+        default_value_t = 10,
+        help = "With --lite-heartbeat, send a full heartbeat every N heartbeats to resync"
+    )]
+    pub full_heartbeat_every: u32,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Give up connecting to the server after N retries (0 retries forever)"
+    )]
+    pub connect_max_retries: u32,
+
+    /// Log full prompt text instead of only its length and hash (privacy sensitive)
+    #[arg(long, help = "Log full prompt text (off: only length and hash)")]
+    pub log_prompts: bool,
+
+    /// Keep generation under a thermal/power ceiling on passively cooled devices
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Cap generation speed at N tokens/s by sleeping between tokens (0 = unlimited)"
+    )]
+    pub max_tokens_per_sec: u32,
+
+    /// Quantizations this worker should be preferred for; empty means all supported
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Comma-separated quantizations this worker runs well (e.g. 'Q4_0,Q8_0'); empty = all"
+    )]
+    pub quant_types: Vec<common::QuantType>,
+
+    /// Models kept loaded at once; requests for other on-disk models load them on demand
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "Keep up to N recently used models loaded (LLAMA engine)"
+    )]
+    pub max_resident_models: usize,
+
+    #[arg(
+        long,
+        default_value_t = 120,
+        help = "Fail a request if loading its model on demand takes longer than N seconds"
+    )]
+    pub model_load_timeout_secs: u64,
+
+    /// Cores to pin inference threads to, e.g. to keep them off efficiency cores
+    #[arg(
+        long,
+        help = "Pin inference threads to 'performance' cores or a core list like '4-7' (Linux/Android)"
+    )]
+    pub cpu_affinity: Option<String>,
+
+    /// Special tokens stripped from streamed output instead of the built-in list
+    #[arg(
+        long,
+        value_delimiter = ',',
+        conflicts_with = "keep_control_tokens",
+        help = "Comma-separated control tokens to strip from output (e.g. '<|im_end|>'); empty = built-in list"
+    )]
+    pub strip_control_tokens: Vec<String>,
+
+    /// Strip every `<|...|>` token from streamed output except these
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Strip all control tokens from output except these comma-separated ones"
+    )]
+    pub keep_control_tokens: Vec<String>,
 }
 
 impl Args {
     pub fn load_config(&self) -> Result<Args> {
+        let mut args = self.load_config_unchecked()?;
+        args.stream_chunk_bytes = validate_stream_chunk_bytes(args.stream_chunk_bytes)?;
+        Ok(args)
+    }
+
+    fn load_config_unchecked(&self) -> Result<Args> {
         if let Some(config_path) = &self.config {
             // Try to load from config file
             let config_data = Config::from_file(config_path)
@@ -247,7 +357,7 @@ impl Args {
             let client_id = parse_client_id(&config_data.client.client_id)
                 .map_err(|e| anyhow::anyhow!("Invalid client_id format in config: {}", e))?;
 
-            info!("client_id: {:?}", client_id);
+            info!("client_id: {}", common::client_id_log_label(&client_id));
 
             let llama_split_mode = match config_data
                 .client
@@ -272,6 +382,9 @@ impl Args {
                 p2p_udp_port: self.p2p_udp_port,
                 p2p_bind_addr: self.p2p_bind_addr.clone(),
                 p2p_public_listen: self.p2p_public_listen,
+                p2p_send_window: self.p2p_send_window,
+                turn_op_timeout_secs: self.turn_op_timeout_secs,
+                turn_setup_timeout_secs: self.turn_setup_timeout_secs,
                 cert_chain_path: config_data.client.cert_chain_path,
                 control_tls: config_data.client.control_tls.unwrap_or(self.control_tls),
                 control_tls_server_name: config_data
@@ -286,6 +399,7 @@ impl Args {
                 chat_template_path: config_data.client.chat_template_path,
                 standalone_llama: false, // Config file doesn't support standalone mode
                 api_key: self.api_key.clone(),
+                max_concurrent_generations: self.max_concurrent_generations,
                 llama_model_path: None,
                 n_ctx: config_data.client.n_ctx,
                 n_batch: self.n_batch,
@@ -301,6 +415,17 @@ impl Args {
                     .clone()
                     .or_else(|| self.llama_devices.clone()),
                 stream_chunk_bytes: self.stream_chunk_bytes,
+                lite_heartbeat: self.lite_heartbeat,
+                full_heartbeat_every: self.full_heartbeat_every,
+                connect_max_retries: self.connect_max_retries,
+                log_prompts: self.log_prompts,
+                max_tokens_per_sec: self.max_tokens_per_sec,
+                quant_types: self.quant_types.clone(),
+                max_resident_models: self.max_resident_models,
+                model_load_timeout_secs: self.model_load_timeout_secs,
+                cpu_affinity: self.cpu_affinity.clone(),
+                strip_control_tokens: self.strip_control_tokens.clone(),
+                keep_control_tokens: self.keep_control_tokens.clone(),
             })
         } else {
             // In standalone_llama mode, client_id is optional
@@ -323,6 +448,37 @@ impl Args {
     pub fn security_config(&self) -> SecurityConfig {
         SecurityConfig::from_args(self)
     }
+
+    /// Control tokens streamed output strips, from `--keep-control-tokens`
+    /// or `--strip-control-tokens`, else the built-in list.
+    pub fn control_tokens(&self) -> ControlTokens {
+        if !self.keep_control_tokens.is_empty() {
+            ControlTokens::Allowed(self.keep_control_tokens.clone())
+        } else if !self.strip_control_tokens.is_empty() {
+            ControlTokens::Blocked(self.strip_control_tokens.clone())
+        } else {
+            ControlTokens::default()
+        }
+    }
+}
+
+/// Rejects a `stream_chunk_bytes` of 0 and clamps other values into
+/// `STREAM_CHUNK_BYTES_RANGE`.
+fn validate_stream_chunk_bytes(bytes: usize) -> Result<usize> {
+    if bytes == 0 {
+        return Err(anyhow::anyhow!("stream_chunk_bytes must be greater than 0"));
+    }
+    let clamped = bytes.clamp(
+        *STREAM_CHUNK_BYTES_RANGE.start(),
+        *STREAM_CHUNK_BYTES_RANGE.end(),
+    );
+    if clamped != bytes {
+        warn!(
+            "stream_chunk_bytes {} is out of range, using {}",
+            bytes, clamped
+        );
+    }
+    Ok(clamped)
 }
 
 fn parse_client_id(s: &str) -> Result<[u8; 16], String> {
@@ -337,7 +493,8 @@ fn parse_client_id(s: &str) -> Result<[u8; 16], String> {
 pub enum WorkerType {
     #[clap(name = "tcp")]
     TCP,
-    #[clap(name = "ws")]
+    /// Not served by gpuf-s yet; `new_worker` rejects it
+    #[clap(name = "ws", hide = true)]
     WS,
 }
 
@@ -381,4 +538,71 @@ mod tests {
             Some("gpuf.example.internal")
         );
     }
+
+    #[test]
+    fn stream_chunk_bytes_is_validated_on_load() {
+        let load = |bytes: &str| {
+            Args::try_parse_from([
+                "gpuf-c",
+                "--standalone-llama",
+                "--stream-chunk-bytes",
+                bytes,
+            ])
+            .unwrap()
+            .load_config()
+        };
+        assert!(load("0").is_err());
+        assert_eq!(load("1").unwrap().stream_chunk_bytes, 16);
+        assert_eq!(load("256").unwrap().stream_chunk_bytes, 256);
+        assert_eq!(load("1000000").unwrap().stream_chunk_bytes, 65536);
+    }
+
+    #[test]
+    fn parses_quant_types() {
+        let args = Args::try_parse_from(["gpuf-c", "--standalone-llama"]).unwrap();
+        assert!(args.quant_types.is_empty());
+
+        let args =
+            Args::try_parse_from(["gpuf-c", "--standalone-llama", "--quant-types", "q4_0,Q8_0"])
+                .unwrap();
+        assert_eq!(
+            args.quant_types,
+            vec![common::QuantType::Q4_0, common::QuantType::Q8_0]
+        );
+        assert!(
+            Args::try_parse_from(["gpuf-c", "--standalone-llama", "--quant-types", "Q9"]).is_err()
+        );
+    }
+
+    #[test]
+    fn builds_control_tokens_from_args() {
+        let parse = |extra: &[&str]| {
+            let mut argv = vec!["gpuf-c", "--standalone-llama"];
+            argv.extend_from_slice(extra);
+            Args::try_parse_from(argv)
+        };
+        assert_eq!(
+            parse(&[]).unwrap().control_tokens(),
+            ControlTokens::default()
+        );
+        assert_eq!(
+            parse(&["--strip-control-tokens", "<|im_end|>,<|eot_id|>"])
+                .unwrap()
+                .control_tokens(),
+            ControlTokens::Blocked(vec!["<|im_end|>".into(), "<|eot_id|>".into()])
+        );
+        assert_eq!(
+            parse(&["--keep-control-tokens", "<|tool|>"])
+                .unwrap()
+                .control_tokens(),
+            ControlTokens::Allowed(vec!["<|tool|>".into()])
+        );
+        assert!(parse(&[
+            "--strip-control-tokens",
+            "<|im_end|>",
+            "--keep-control-tokens",
+            "<|tool|>"
+        ])
+        .is_err());
+    }
 }
@@ -0,0 +1,999 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// Longest repeating pattern (in tokens) checked by default.
+pub const DEFAULT_REPETITION_NGRAM_SIZE: usize = 6;
+/// Back-to-back occurrences of a pattern tolerated by default before aborting.
+pub const DEFAULT_REPETITION_MAX_REPEATS: usize = 10;
+
+/// Detects a model stuck in a generation loop.
+///
+/// Tracks the most recent tokens and reports a loop once any pattern of 1 to
+/// `ngram_size` tokens has been emitted back-to-back more than `max_repeats`
+/// times (e.g. "the the the ..." or the same sentence over and over).
+/// Setting either limit to 0 disables the check.
+#[derive(Debug, Clone)]
+pub struct RepetitionDetector {
+    ngram_size: usize,
+    max_repeats: usize,
+    recent: VecDeque<i32>,
+}
+
+impl RepetitionDetector {
+    pub fn new(ngram_size: usize, max_repeats: usize) -> Self {
+        let capacity = ngram_size.saturating_mul(max_repeats.saturating_add(1));
+        Self {
+            ngram_size,
+            max_repeats,
+            recent: VecDeque::with_capacity(capacity.min(4096)),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.ngram_size > 0 && self.max_repeats > 0
+    }
+
+    /// Records a generated token. Returns true when generation should stop.
+    pub fn push(&mut self, token: i32) -> bool {
+        if !self.enabled() {
+            return false;
+        }
+
+        let window = self.ngram_size.saturating_mul(self.max_repeats + 1);
+        if self.recent.len() == window {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(token);
+
+        (1..=self.ngram_size).any(|n| self.tail_repeats(n))
+    }
+
+    /// Whether the last `n * (max_repeats + 1)` tokens are one n-gram repeated.
+    fn tail_repeats(&self, n: usize) -> bool {
+        let span = n * (self.max_repeats + 1);
+        let len = self.recent.len();
+        if len < span {
+            return false;
+        }
+        let start = len - span;
+        (start..len - n).all(|i| self.recent[i] == self.recent[i + n])
+    }
+}
+
+impl Default for RepetitionDetector {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_REPETITION_NGRAM_SIZE,
+            DEFAULT_REPETITION_MAX_REPEATS,
+        )
+    }
+}
+
+/// Why a generation finished.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FinishReason {
+    /// EOS or an end-of-turn marker was produced.
+    Stop,
+    /// `max_tokens` was reached.
+    Length,
+    /// The loop detector aborted a repeating output.
+    Repetition,
+}
+
+impl FinishReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FinishReason::Stop => "stop",
+            FinishReason::Length => "length",
+            FinishReason::Repetition => "repetition",
+        }
+    }
+}
+
+/// Matches stop sequences against the running output instead of single tokens,
+/// so stops that span several tokens are still found.
+///
+/// Text that could be the beginning of a stop sequence is held back until more
+/// output disambiguates it. When several stops match, the one starting earliest
+/// wins; ties go to the one that ends first.
+#[derive(Debug, Clone, Default)]
+pub struct StopSequenceMatcher {
+    stops: Vec<String>,
+    // Stops added by `with_end_markers` that the caller did not ask for
+    end_markers: Vec<String>,
+    pending: String,
+    matched: Option<String>,
+}
+
+impl StopSequenceMatcher {
+    pub fn new<I, S>(stops: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut unique: Vec<String> = Vec::new();
+        for stop in stops.into_iter().map(Into::into) {
+            if !stop.is_empty() && !unique.contains(&stop) {
+                unique.push(stop);
+            }
+        }
+        Self {
+            stops: unique,
+            end_markers: Vec::new(),
+            pending: String::new(),
+            matched: None,
+        }
+    }
+
+    /// Also stops at the template's end-of-turn markers. A marker ends
+    /// generation like the model's EOS token does: `matched` stays `None`
+    /// unless the marker was requested as a stop sequence too.
+    pub fn with_end_markers<I, S>(mut self, markers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        for marker in markers.into_iter().map(Into::into) {
+            if !marker.is_empty() && !self.stops.contains(&marker) {
+                self.stops.push(marker.clone());
+                self.end_markers.push(marker);
+            }
+        }
+        self
+    }
+
+    /// Appends generated text. Returns the text that is safe to emit and whether
+    /// a stop sequence was hit; output after the stop is discarded.
+    pub fn push(&mut self, text: &str) -> (String, bool) {
+        if self.matched.is_some() {
+            return (String::new(), true);
+        }
+        self.pending.push_str(text);
+
+        let earliest = self
+            .stops
+            .iter()
+            .filter_map(|stop| self.pending.find(stop.as_str()).map(|idx| (idx, stop)))
+            .min_by_key(|(idx, stop)| (*idx, idx + stop.len()));
+        if let Some((idx, stop)) = earliest {
+            self.matched = Some(stop.clone());
+            let emit = self.pending[..idx].to_string();
+            self.pending.clear();
+            return (emit, true);
+        }
+
+        let hold = self.partial_match_len();
+        let emit_to = self.pending.len() - hold;
+        let emit = self.pending[..emit_to].to_string();
+        self.pending.drain(..emit_to);
+        (emit, false)
+    }
+
+    /// Length of the longest pending suffix that is a prefix of some stop.
+    fn partial_match_len(&self) -> usize {
+        self.pending
+            .char_indices()
+            .map(|(idx, _)| &self.pending[idx..])
+            .find(|suffix| self.stops.iter().any(|stop| stop.starts_with(suffix)))
+            .map_or(0, str::len)
+    }
+
+    /// Releases held-back text once generation ends without a stop match.
+    pub fn finish(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// The stop sequence that ended generation, if any. End markers are not
+    /// reported.
+    pub fn matched(&self) -> Option<&str> {
+        self.matched
+            .as_deref()
+            .filter(|stop| !self.end_markers.iter().any(|marker| marker == stop))
+    }
+}
+
+/// Special tokens stripped from streamed output unless a model family
+/// configures its own set: the gpt-oss harmony markers and the Llama 3
+/// turn/header markers.
+pub const DEFAULT_CONTROL_TOKENS: &[&str] = &[
+    "<|start|>",
+    "<|end|>",
+    "<|channel|>",
+    "<|message|>",
+    "<|eot_id|>",
+    "<|start_header_id|>",
+    "<|end_header_id|>",
+];
+
+/// Longest `<|...|>` run treated as a special token; anything longer is text.
+/// This also bounds how much output a `ControlTokenFilter` holds back.
+pub const MAX_CONTROL_TOKEN_BYTES: usize = 64;
+
+/// Which well-formed `<|...|>` spans a `ControlTokenFilter` strips.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlTokens {
+    /// Strip only these tokens and keep every other span.
+    Blocked(Vec<String>),
+    /// Strip every span except these tokens.
+    Allowed(Vec<String>),
+}
+
+impl ControlTokens {
+    fn strips(&self, span: &str) -> bool {
+        match self {
+            ControlTokens::Blocked(tokens) => tokens.iter().any(|t| t == span),
+            ControlTokens::Allowed(tokens) => !tokens.iter().any(|t| t == span),
+        }
+    }
+}
+
+impl Default for ControlTokens {
+    fn default() -> Self {
+        ControlTokens::Blocked(
+            DEFAULT_CONTROL_TOKENS
+                .iter()
+                .map(|t| t.to_string())
+                .collect(),
+        )
+    }
+}
+
+// Worker-wide control token set; None keeps the built-in default
+static CONTROL_TOKENS: RwLock<Option<ControlTokens>> = RwLock::new(None);
+
+/// Sets which control tokens streamed output strips on this worker.
+pub fn set_control_tokens(tokens: ControlTokens) {
+    *CONTROL_TOKENS
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(tokens);
+}
+
+pub fn control_tokens() -> ControlTokens {
+    CONTROL_TOKENS
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+        .unwrap_or_default()
+}
+
+/// Strips special-token spans such as `<|channel|>` from streamed output.
+///
+/// Only complete `<|name|>` spans are considered, where `name` is non-empty and
+/// has no whitespace, `<`, `>` or `|`. Everything else, including ordinary
+/// prose and spans outside the configured set, passes through unchanged. A
+/// trailing `<` or unfinished `<|name` is held back until the next piece shows
+/// whether it is a span, so tokens split across chunks are still caught.
+#[derive(Debug, Clone, Default)]
+pub struct ControlTokenFilter {
+    tokens: ControlTokens,
+    pending: String,
+}
+
+enum Span {
+    /// A complete span of this many bytes.
+    Complete(usize),
+    /// Could still become a span once more text arrives.
+    Partial,
+    /// Not a span; the leading `<` is plain text.
+    Text,
+}
+
+impl ControlTokenFilter {
+    pub fn new(tokens: ControlTokens) -> Self {
+        Self {
+            tokens,
+            pending: String::new(),
+        }
+    }
+
+    /// A filter for the worker-wide set from `set_control_tokens`.
+    pub fn from_config() -> Self {
+        Self::new(control_tokens())
+    }
+
+    /// Appends generated text and returns the part that is safe to emit.
+    pub fn push(&mut self, text: &str) -> String {
+        self.pending.push_str(text);
+        let mut out = String::with_capacity(self.pending.len());
+        let mut rest = self.pending.as_str();
+
+        while let Some(start) = rest.find('<') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            match Self::scan_span(rest) {
+                Span::Complete(len) => {
+                    if !self.tokens.strips(&rest[..len]) {
+                        out.push_str(&rest[..len]);
+                    }
+                    rest = &rest[len..];
+                }
+                Span::Partial => break,
+                Span::Text => {
+                    out.push('<');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        if !rest.starts_with('<') {
+            out.push_str(rest);
+            rest = "";
+        }
+
+        self.pending = rest.to_string();
+        out
+    }
+
+    /// Releases held-back text once generation ends.
+    pub fn finish(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Classifies the span starting at `text`, which begins with `<`.
+    fn scan_span(text: &str) -> Span {
+        let Some(body) = text.strip_prefix("<|") else {
+            return if text.len() == 1 {
+                Span::Partial
+            } else {
+                Span::Text
+            };
+        };
+        for (idx, ch) in body.char_indices() {
+            let len = idx + 2;
+            if len >= MAX_CONTROL_TOKEN_BYTES {
+                return Span::Text;
+            }
+            match ch {
+                '|' if idx > 0 => {
+                    return match body[idx + 1..].chars().next() {
+                        Some('>') if len + 2 <= MAX_CONTROL_TOKEN_BYTES => Span::Complete(len + 2),
+                        None => Span::Partial,
+                        _ => Span::Text,
+                    };
+                }
+                '|' | '<' | '>' => return Span::Text,
+                ch if ch.is_whitespace() => return Span::Text,
+                _ => {}
+            }
+        }
+        Span::Partial
+    }
+}
+
+/// Seed value that asks llama.cpp to pick a random seed (`LLAMA_DEFAULT_SEED`).
+pub const RANDOM_SEED: u32 = u32::MAX;
+
+#[derive(Clone, Debug)]
+pub struct SamplingParams {
+    pub temperature: f32,
+    pub top_k: i32,
+    pub top_p: f32,
+    /// Drops tokens less likely than this fraction of the most likely one
+    /// (0 disables min-p).
+    pub min_p: f32,
+    /// Locally typical sampling mass (1 disables it).
+    pub typical_p: f32,
+    /// Chance per token that XTC removes the top choices (0 disables XTC).
+    pub xtc_probability: f32,
+    /// Tokens at least this likely count as top choices for XTC; above 0.5
+    /// XTC never triggers.
+    pub xtc_threshold: f32,
+    /// Penalty applied to tokens repeated within the last `repeat_last_n`
+    /// (1 disables it).
+    pub repeat_penalty: f32,
+    /// Subtracted from a token's logit once per earlier occurrence (0
+    /// disables it).
+    pub frequency_penalty: f32,
+    /// Subtracted from a token's logit if it occurred at all (0 disables it).
+    pub presence_penalty: f32,
+    /// Tokens the penalties look back over, llama.cpp's `penalty_last_n`
+    /// (-1 is the whole context, 0 disables the penalties).
+    pub repeat_last_n: i32,
+    /// Seed of the final sampling step. A fixed value makes output
+    /// reproducible; `RANDOM_SEED` draws a fresh one per chain.
+    pub seed: u32,
+    pub min_keep: usize,
+    /// Hint for max tokens to spend on thinking content (Anthropic extended thinking).
+    /// The model uses this as guidance; actual thinking token count depends on model output.
+    #[allow(dead_code)]
+    pub thinking_budget_tokens: Option<usize>,
+    /// Longest token pattern checked by the loop detector (0 disables it).
+    pub repetition_ngram_size: usize,
+    /// Back-to-back repeats of a pattern allowed before generation is aborted.
+    pub repetition_max_repeats: usize,
+    /// Extra stop sequences, matched against the running output in addition to
+    /// the built-in end-of-turn markers.
+    pub stop: Vec<String>,
+}
+
+impl Default for SamplingParams {
+    fn default() -> Self {
+        Self {
+            temperature: 0.8,
+            top_k: 40,
+            top_p: 0.95,
+            min_p: 0.0,
+            typical_p: 1.0,
+            xtc_probability: 0.0,
+            xtc_threshold: 0.1,
+            repeat_penalty: 1.1,
+            frequency_penalty: 0.0,
+            presence_penalty: 0.0,
+            repeat_last_n: 64,
+            seed: 0,
+            min_keep: 1,
+            thinking_budget_tokens: None,
+            repetition_ngram_size: DEFAULT_REPETITION_NGRAM_SIZE,
+            repetition_max_repeats: DEFAULT_REPETITION_MAX_REPEATS,
+            stop: Vec::new(),
+        }
+    }
+}
+
+/// Settings of llama.cpp's penalties sampler.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Penalties {
+    pub last_n: i32,
+    pub repeat: f32,
+    pub frequency: f32,
+    pub presence: f32,
+}
+
+impl Penalties {
+    /// `(penalty_last_n, penalty_repeat, penalty_freq, penalty_present)`, the
+    /// parameter order of `llama_sampler_init_penalties`.
+    pub fn args(&self) -> (i32, f32, f32, f32) {
+        (self.last_n, self.repeat, self.frequency, self.presence)
+    }
+}
+
+/// One step of a llama.cpp sampler chain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerStage {
+    Penalties(Penalties),
+    TopK(i32),
+    Typical {
+        p: f32,
+        min_keep: usize,
+    },
+    MinP {
+        p: f32,
+        min_keep: usize,
+    },
+    TopP {
+        p: f32,
+        min_keep: usize,
+    },
+    Xtc {
+        probability: f32,
+        threshold: f32,
+        min_keep: usize,
+        seed: u32,
+    },
+    Temperature(f32),
+    Greedy,
+    Dist(u32),
+}
+
+impl SamplingParams {
+    /// The sampler chain for these parameters, in llama.cpp's usual order:
+    /// penalties, top-k, typical, min-p, top-p, XTC, temperature, then the
+    /// final pick. Stages whose parameter is disabled (repeat penalty 1.0 with
+    /// no frequency or presence penalty, top_k <= 0, typical_p or top_p
+    /// outside (0, 1), min_p outside (0, 1],
+    /// xtc_probability <= 0 or xtc_threshold > 0.5) are left out, and
+    /// temperature <= 0 picks greedily. Tail-free sampling is not offered:
+    /// llama.cpp removed it.
+    pub fn sampler_stages(&self) -> Vec<SamplerStage> {
+        let mut stages = Vec::with_capacity(8);
+        if self.repeat_penalty != 1.0
+            || self.frequency_penalty != 0.0
+            || self.presence_penalty != 0.0
+        {
+            stages.push(SamplerStage::Penalties(Penalties {
+                last_n: self.repeat_last_n,
+                repeat: self.repeat_penalty,
+                frequency: self.frequency_penalty,
+                presence: self.presence_penalty,
+            }));
+        }
+        if self.top_k > 0 {
+            stages.push(SamplerStage::TopK(self.top_k));
+        }
+        if self.typical_p > 0.0 && self.typical_p < 1.0 {
+            stages.push(SamplerStage::Typical {
+                p: self.typical_p,
+                min_keep: self.min_keep,
+            });
+        }
+        if self.min_p > 0.0 && self.min_p <= 1.0 {
+            stages.push(SamplerStage::MinP {
+                p: self.min_p,
+                min_keep: self.min_keep,
+            });
+        }
+        if self.top_p > 0.0 && self.top_p < 1.0 {
+            stages.push(SamplerStage::TopP {
+                p: self.top_p,
+                min_keep: self.min_keep,
+            });
+        }
+        if self.xtc_probability > 0.0 && self.xtc_threshold <= 0.5 {
+            stages.push(SamplerStage::Xtc {
+                probability: self.xtc_probability,
+                threshold: self.xtc_threshold,
+                min_keep: self.min_keep,
+                seed: self.seed,
+            });
+        }
+        if self.temperature > 0.0 {
+            stages.push(SamplerStage::Temperature(self.temperature));
+            stages.push(SamplerStage::Dist(self.seed));
+        } else {
+            stages.push(SamplerStage::Greedy);
+        }
+        stages
+    }
+}
+
+/// Tokens a generation may produce: the requested `max_tokens`, cut short so
+/// the output still fits a context window of `n_ctx` that already holds
+/// `n_past` tokens. Every generation path derives its limit here.
+pub fn generation_limit(max_tokens: usize, n_ctx: usize, n_past: usize) -> usize {
+    max_tokens.min(n_ctx.saturating_sub(n_past))
+}
+
+// Worker-wide generation speed cap in tokens/s; 0 means unlimited
+static MAX_TOKENS_PER_SEC: AtomicU32 = AtomicU32::new(0);
+
+/// Caps generation speed on this worker; 0 removes the cap.
+pub fn set_max_tokens_per_sec(limit: u32) {
+    MAX_TOKENS_PER_SEC.store(limit, Ordering::Relaxed);
+}
+
+pub fn max_tokens_per_sec() -> u32 {
+    MAX_TOKENS_PER_SEC.load(Ordering::Relaxed)
+}
+
+/// Keeps a generation loop under a tokens/second ceiling by sleeping between
+/// tokens, trading latency for sustained thermals on passively cooled devices.
+///
+/// The rate is averaged from the first generated token, so prompt processing
+/// does not build up credit for a burst afterwards.
+#[derive(Debug, Clone)]
+pub struct TokenRateLimiter {
+    max_per_sec: u32,
+    first_token: Option<Instant>,
+    tokens: u32,
+    throttled: Duration,
+}
+
+impl TokenRateLimiter {
+    /// A limiter for `max_per_sec` tokens/s; 0 never sleeps.
+    pub fn new(max_per_sec: u32) -> Self {
+        Self {
+            max_per_sec,
+            first_token: None,
+            tokens: 0,
+            throttled: Duration::ZERO,
+        }
+    }
+
+    /// A limiter for the worker-wide cap from `set_max_tokens_per_sec`.
+    pub fn from_config() -> Self {
+        Self::new(max_tokens_per_sec())
+    }
+
+    pub fn max_per_sec(&self) -> u32 {
+        self.max_per_sec
+    }
+
+    /// Total time spent sleeping so far.
+    pub fn throttled(&self) -> Duration {
+        self.throttled
+    }
+
+    /// Records a token generated at `now` and returns how long to wait before
+    /// generating the next one.
+    pub fn delay_after_token(&mut self, now: Instant) -> Duration {
+        if self.max_per_sec == 0 {
+            return Duration::ZERO;
+        }
+        let first = *self.first_token.get_or_insert(now);
+        self.tokens = self.tokens.saturating_add(1);
+        let next_allowed =
+            first + Duration::from_secs_f64(self.tokens as f64 / self.max_per_sec as f64);
+        next_allowed.saturating_duration_since(now)
+    }
+
+    /// Records a generated token and sleeps if generation is ahead of the cap.
+    /// Returns true on the first sleep, so callers can report that throttling
+    /// kicked in.
+    pub fn pace(&mut self) -> bool {
+        let delay = self.delay_after_token(Instant::now());
+        if delay.is_zero() {
+            return false;
+        }
+        let first_throttle = self.throttled.is_zero();
+        self.throttled += delay;
+        std::thread::sleep(delay);
+        first_throttle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_trip(detector: &mut RepetitionDetector, tokens: &[i32]) -> Option<usize> {
+        tokens.iter().position(|t| detector.push(*t))
+    }
+
+    #[test]
+    fn trips_on_single_token_loop_after_threshold() {
+        let mut detector = RepetitionDetector::new(4, 3);
+        // Four back-to-back occurrences is one more than allowed
+        assert_eq!(first_trip(&mut detector, &[7; 10]), Some(3));
+    }
+
+    #[test]
+    fn trips_on_phrase_loop_up_to_ngram_size() {
+        let mut detector = RepetitionDetector::new(3, 2);
+        let tokens: Vec<i32> = [1, 2, 3].repeat(4);
+        assert_eq!(first_trip(&mut detector, &tokens), Some(8));
+
+        // Patterns longer than the configured n-gram size are not checked
+        let mut detector = RepetitionDetector::new(2, 2);
+        assert_eq!(first_trip(&mut detector, &tokens), None);
+    }
+
+    #[test]
+    fn varied_output_and_disabled_detector_never_trip() {
+        let tokens: Vec<i32> = (0..200).map(|i| (i * 7) % 13).collect();
+        assert_eq!(
+            first_trip(&mut RepetitionDetector::new(4, 3), &tokens),
+            None
+        );
+        assert_eq!(
+            first_trip(&mut RepetitionDetector::new(0, 3), &[5; 50]),
+            None
+        );
+        assert_eq!(
+            first_trip(&mut RepetitionDetector::new(4, 0), &[5; 50]),
+            None
+        );
+    }
+
+    fn run_matcher(stops: &[&str], pieces: &[&str]) -> (String, Option<String>) {
+        let mut matcher = StopSequenceMatcher::new(stops.iter().copied());
+        let mut out = String::new();
+        for piece in pieces {
+            let (emit, stopped) = matcher.push(piece);
+            out.push_str(&emit);
+            if stopped {
+                return (out, matcher.matched().map(str::to_string));
+            }
+        }
+        out.push_str(&matcher.finish());
+        (out, None)
+    }
+
+    #[test]
+    fn stop_sequence_split_across_tokens_is_matched() {
+        let (out, matched) = run_matcher(
+            &["</tool_call>", "\n\nUser:"],
+            &["call(", "x)", "</", "tool", "_call", ">", "ignored"],
+        );
+        assert_eq!(out, "call(x)");
+        assert_eq!(matched.as_deref(), Some("</tool_call>"));
+    }
+
+    #[test]
+    fn earliest_of_overlapping_stops_wins() {
+        // Both stops arrive in one piece; "END" starts first
+        let (out, matched) = run_matcher(&["DONE", "END"], &["a", "bENDONE"]);
+        assert_eq!(out, "ab");
+        assert_eq!(matched.as_deref(), Some("END"));
+
+        // Same start: the shorter stop completes first
+        let (out, matched) = run_matcher(&["###", "##"], &["x##", "#"]);
+        assert_eq!(out, "x");
+        assert_eq!(matched.as_deref(), Some("##"));
+    }
+
+    #[test]
+    fn held_back_prefix_is_released_when_no_stop_follows() {
+        let mut matcher = StopSequenceMatcher::new(["<|im_end|>"]);
+        assert_eq!(matcher.push("hello <|im"), ("hello ".to_string(), false));
+        assert_eq!(matcher.push("age|>"), ("<|image|>".to_string(), false));
+        assert_eq!(matcher.push(" é <"), (" é ".to_string(), false));
+        assert_eq!(matcher.finish(), "<");
+        assert_eq!(matcher.matched(), None);
+    }
+
+    #[test]
+    fn end_markers_stop_without_being_reported() {
+        let mut matcher = StopSequenceMatcher::new(["STOP"]).with_end_markers(["<|im_end|>"]);
+        assert_eq!(matcher.push("done<|im"), ("done".to_string(), false));
+        assert_eq!(matcher.push("_end|>more"), (String::new(), true));
+        assert_eq!(matcher.matched(), None);
+
+        // Requested explicitly, the same marker is reported as the stop
+        let mut matcher = StopSequenceMatcher::new(["<|im_end|>"]).with_end_markers(["<|im_end|>"]);
+        assert_eq!(matcher.push("x<|im_end|>"), ("x".to_string(), true));
+        assert_eq!(matcher.matched(), Some("<|im_end|>"));
+    }
+
+    #[test]
+    fn sampler_stages_skip_disabled_parameters() {
+        let disabled = SamplingParams {
+            temperature: 0.0,
+            top_k: 0,
+            top_p: 1.0,
+            repeat_penalty: 1.0,
+            ..SamplingParams::default()
+        };
+        assert_eq!(disabled.sampler_stages(), vec![SamplerStage::Greedy]);
+
+        let seeded = SamplingParams {
+            temperature: 0.7,
+            seed: 42,
+            ..disabled.clone()
+        };
+        assert_eq!(
+            seeded.sampler_stages(),
+            vec![SamplerStage::Temperature(0.7), SamplerStage::Dist(42)]
+        );
+
+        let all = SamplingParams::default().sampler_stages();
+        assert!(matches!(all[0], SamplerStage::Penalties { .. }));
+        assert_eq!(all[1], SamplerStage::TopK(40));
+        assert!(matches!(all.last(), Some(SamplerStage::Dist(0))));
+        assert!(!all.iter().any(|stage| matches!(
+            stage,
+            SamplerStage::MinP { .. } | SamplerStage::Typical { .. } | SamplerStage::Xtc { .. }
+        )));
+    }
+
+    #[test]
+    fn min_p_runs_between_top_k_and_top_p() {
+        let params = SamplingParams {
+            min_p: 0.05,
+            repeat_penalty: 1.0,
+            ..SamplingParams::default()
+        };
+        assert_eq!(
+            params.sampler_stages(),
+            vec![
+                SamplerStage::TopK(40),
+                SamplerStage::MinP {
+                    p: 0.05,
+                    min_keep: 1
+                },
+                SamplerStage::TopP {
+                    p: 0.95,
+                    min_keep: 1
+                },
+                SamplerStage::Temperature(0.8),
+                SamplerStage::Dist(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn penalties_map_each_field_to_its_argument() {
+        let params = SamplingParams {
+            repeat_penalty: 1.3,
+            frequency_penalty: 0.4,
+            presence_penalty: 0.6,
+            repeat_last_n: 256,
+            ..SamplingParams::default()
+        };
+        let SamplerStage::Penalties(penalties) = params.sampler_stages()[0] else {
+            panic!("penalties stage missing");
+        };
+        assert_eq!(penalties.args(), (256, 1.3, 0.4, 0.6));
+
+        // Frequency or presence alone still needs the stage
+        for (frequency_penalty, presence_penalty) in [(0.5, 0.0), (0.0, 0.5)] {
+            let params = SamplingParams {
+                repeat_penalty: 1.0,
+                frequency_penalty,
+                presence_penalty,
+                repeat_last_n: -1,
+                ..SamplingParams::default()
+            };
+            assert_eq!(
+                params.sampler_stages()[0],
+                SamplerStage::Penalties(Penalties {
+                    last_n: -1,
+                    repeat: 1.0,
+                    frequency: frequency_penalty,
+                    presence: presence_penalty,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn typical_and_xtc_each_add_one_stage() {
+        let base = SamplingParams {
+            top_k: 0,
+            top_p: 1.0,
+            repeat_penalty: 1.0,
+            ..SamplingParams::default()
+        };
+        let final_pick = [SamplerStage::Temperature(0.8), SamplerStage::Dist(0)];
+
+        let typical = SamplingParams {
+            typical_p: 0.9,
+            ..base.clone()
+        };
+        let typical_stage = SamplerStage::Typical {
+            p: 0.9,
+            min_keep: 1,
+        };
+        assert_eq!(
+            typical.sampler_stages(),
+            [&[typical_stage][..], &final_pick].concat()
+        );
+
+        let xtc = SamplingParams {
+            xtc_probability: 0.5,
+            seed: 7,
+            ..base.clone()
+        };
+        let xtc_stage = SamplerStage::Xtc {
+            probability: 0.5,
+            threshold: 0.1,
+            min_keep: 1,
+            seed: 7,
+        };
+        assert_eq!(
+            xtc.sampler_stages(),
+            [
+                &[xtc_stage][..],
+                &[SamplerStage::Temperature(0.8), SamplerStage::Dist(7)]
+            ]
+            .concat()
+        );
+        // XTC never triggers above a 0.5 threshold, so it is left out
+        let inert = SamplingParams {
+            xtc_threshold: 0.6,
+            ..xtc
+        };
+        assert_eq!(inert.sampler_stages().len(), 2);
+    }
+
+    #[test]
+    fn all_samplers_combine_in_documented_order() {
+        let params = SamplingParams {
+            min_p: 0.05,
+            typical_p: 0.9,
+            xtc_probability: 0.5,
+            ..SamplingParams::default()
+        };
+        let stages = params.sampler_stages();
+        assert_eq!(stages.len(), 8);
+        assert!(matches!(stages[0], SamplerStage::Penalties { .. }));
+        assert_eq!(stages[1], SamplerStage::TopK(40));
+        assert!(matches!(stages[2], SamplerStage::Typical { .. }));
+        assert!(matches!(stages[3], SamplerStage::MinP { .. }));
+        assert!(matches!(stages[4], SamplerStage::TopP { .. }));
+        assert!(matches!(stages[5], SamplerStage::Xtc { .. }));
+        assert_eq!(stages[6], SamplerStage::Temperature(0.8));
+        assert_eq!(stages[7], SamplerStage::Dist(0));
+    }
+
+    #[test]
+    fn rate_limiter_spaces_tokens_to_the_cap() {
+        let start = Instant::now();
+        let mut limiter = TokenRateLimiter::new(10);
+
+        // Generating instantly is held back to one token per 100ms
+        assert_eq!(limiter.delay_after_token(start), Duration::from_millis(100));
+        assert_eq!(
+            limiter.delay_after_token(start + Duration::from_millis(100)),
+            Duration::from_millis(100)
+        );
+
+        // Falling behind the cap never sleeps
+        assert_eq!(
+            limiter.delay_after_token(start + Duration::from_secs(2)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn unlimited_rate_limiter_never_sleeps() {
+        let start = Instant::now();
+        let mut limiter = TokenRateLimiter::new(0);
+        for _ in 0..100 {
+            assert_eq!(limiter.delay_after_token(start), Duration::ZERO);
+        }
+        assert!(!limiter.pace());
+        assert_eq!(limiter.throttled(), Duration::ZERO);
+    }
+
+    fn filter_all(filter: &mut ControlTokenFilter, pieces: &[&str]) -> String {
+        let mut out: String = pieces.iter().map(|p| filter.push(p)).collect();
+        out.push_str(&filter.finish());
+        out
+    }
+
+    #[test]
+    fn control_filter_keeps_prose_about_analysis() {
+        let prose = "We need to run the analysis first. The analysis shows x < y and a|b > c.";
+        let mut filter = ControlTokenFilter::default();
+        assert_eq!(filter.push(prose), prose);
+        assert_eq!(filter.finish(), "");
+
+        let mut filter = ControlTokenFilter::default();
+        let harmony = "<|start|>assistant<|channel|>analysis<|message|>We need to check.<|end|>";
+        assert_eq!(
+            filter_all(&mut filter, &[harmony]),
+            "assistantanalysisWe need to check."
+        );
+    }
+
+    #[test]
+    fn control_filter_strips_tokens_split_across_chunks() {
+        let mut filter = ControlTokenFilter::default();
+        assert_eq!(filter.push("Hello <|chan"), "Hello ");
+        assert_eq!(filter.push("nel|>world"), "world");
+
+        let mut filter = ControlTokenFilter::default();
+        assert_eq!(filter.push("done<"), "done");
+        assert_eq!(filter.push("|end|"), "");
+        assert_eq!(filter.push(">!"), "!");
+
+        // A held-back `<` that turns out to be text is released
+        let mut filter = ControlTokenFilter::default();
+        assert_eq!(filter.push("a <"), "a ");
+        assert_eq!(filter.push(" b"), "< b");
+        assert_eq!(filter_all(&mut filter, &["tail <|end"]), "tail <|end");
+    }
+
+    #[test]
+    fn control_filter_only_strips_configured_well_formed_spans() {
+        let mut filter = ControlTokenFilter::default();
+        assert_eq!(
+            filter_all(&mut filter, &["<|unknown|> <| spaced |> <||> <|end|>"]),
+            "<|unknown|> <| spaced |> <||> "
+        );
+
+        let mut filter = ControlTokenFilter::new(ControlTokens::Blocked(vec!["<|im_end|>".into()]));
+        assert_eq!(
+            filter_all(&mut filter, &["hi<|im_end|><|end|>"]),
+            "hi<|end|>"
+        );
+
+        let mut filter = ControlTokenFilter::new(ControlTokens::Allowed(vec!["<|tool|>".into()]));
+        assert_eq!(
+            filter_all(&mut filter, &["<|a|>x<|tool|>y<|b|>"]),
+            "x<|tool|>y"
+        );
+    }
+
+    #[test]
+    fn control_filter_holds_back_at_most_one_token() {
+        let mut filter = ControlTokenFilter::default();
+        let long = format!("<|{}", "x".repeat(MAX_CONTROL_TOKEN_BYTES));
+        assert_eq!(filter.push(&long), long);
+        assert_eq!(filter.finish(), "");
+    }
+
+    #[test]
+    fn generation_limit_respects_max_tokens_and_context() {
+        // Room to spare: max_tokens wins
+        assert_eq!(generation_limit(128, 4096, 100), 128);
+        // Near the end of the window: only what still fits
+        assert_eq!(generation_limit(512, 2048, 2000), 48);
+        // Full or overfull window: nothing
+        assert_eq!(generation_limit(512, 2048, 2048), 0);
+        assert_eq!(generation_limit(512, 2048, 3000), 0);
+        // Not capped by any fixed size, only by the context
+        assert_eq!(generation_limit(10_000, 32_768, 0), 10_000);
+    }
+}
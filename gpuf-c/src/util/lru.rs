@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+
+/// Least-recently-used map for a handful of large values such as resident
+/// models. Lookups scan linearly, which is fine at this size.
+#[derive(Clone)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    // Most recently used first
+    entries: VecDeque<(K, V)>,
+}
+
+impl<K: PartialEq, V> LruCache<K, V> {
+    /// A cache holding at most `capacity` entries (at least one).
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.iter().any(|(k, _)| k == key)
+    }
+
+    /// Looks up `key` and marks it most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let pos = self.entries.iter().position(|(k, _)| k == key)?;
+        let entry = self.entries.remove(pos)?;
+        self.entries.push_front(entry);
+        self.entries.front().map(|(_, v)| v)
+    }
+
+    /// Inserts `key` as most recently used and returns the entries evicted to
+    /// stay within capacity.
+    pub fn insert(&mut self, key: K, value: V) -> Vec<(K, V)> {
+        if let Some(pos) = self.entries.iter().position(|(k, _)| *k == key) {
+            self.entries.remove(pos);
+        }
+        self.entries.push_front((key, value));
+        self.evict_to(self.capacity)
+    }
+
+    /// Evicts least recently used entries so one more fits without going over
+    /// capacity. Lets callers free memory before building the next value.
+    pub fn make_room(&mut self) -> Vec<(K, V)> {
+        self.evict_to(self.capacity - 1)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn evict_to(&mut self, len: usize) -> Vec<(K, V)> {
+        let mut evicted = Vec::new();
+        while self.entries.len() > len {
+            if let Some(entry) = self.entries.pop_back() {
+                evicted.push(entry);
+            }
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = LruCache::new(2);
+        assert!(cache.insert("a", 1).is_empty());
+        assert!(cache.insert("b", 2).is_empty());
+
+        // Touching "a" leaves "b" as the eviction candidate
+        assert_eq!(cache.get(&"a"), Some(&1));
+        assert_eq!(cache.insert("c", 3), vec![("b", 2)]);
+        assert!(cache.contains(&"a"));
+        assert!(!cache.contains(&"b"));
+
+        // Re-inserting an existing key replaces it without evicting
+        assert!(cache.insert("a", 10).is_empty());
+        assert_eq!(cache.get(&"a"), Some(&10));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn make_room_frees_a_slot_before_loading() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        assert!(cache.make_room().is_empty());
+        cache.insert("b", 2);
+        assert_eq!(cache.make_room(), vec![("a", 1)]);
+        assert_eq!(cache.len(), 1);
+
+        let mut single = LruCache::new(0);
+        assert_eq!(single.capacity(), 1);
+        single.insert("a", 1);
+        assert_eq!(single.make_room(), vec![("a", 1)]);
+        assert!(single.is_empty());
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::{anyhow, Result};
+
+/// Memory kept free on top of the model weights for the KV cache, compute
+/// buffers and the rest of the app.
+pub const LOAD_HEADROOM_BYTES: u64 = 256 * 1024 * 1024;
+
+/// `MemAvailable` in bytes from the contents of `/proc/meminfo`.
+pub fn parse_mem_available(meminfo: &str) -> Option<u64> {
+    meminfo.lines().find_map(|line| {
+        let rest = line.strip_prefix("MemAvailable:")?;
+        let kb = rest.split_whitespace().next()?.parse::<u64>().ok()?;
+        Some(kb * 1024)
+    })
+}
+
+/// Memory the kernel considers available without swapping, if known.
+pub fn available_memory_bytes() -> Option<u64> {
+    std::fs::read_to_string("/proc/meminfo")
+        .ok()
+        .and_then(|meminfo| parse_mem_available(&meminfo))
+}
+
+/// Refuses a load of `model_bytes` that obviously won't fit in `available`
+/// bytes. Unknown availability is let through.
+pub fn check_model_fits(model_bytes: u64, available: Option<u64>) -> Result<()> {
+    let Some(available) = available else {
+        return Ok(());
+    };
+    let required = model_bytes.saturating_add(LOAD_HEADROOM_BYTES);
+    if required > available {
+        return Err(anyhow!(
+            "Insufficient memory: model needs about {} MB but only {} MB is available",
+            required / (1024 * 1024),
+            available / (1024 * 1024)
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that the files at `paths` fit in currently available memory.
+pub fn check_model_files_fit(paths: &[&str]) -> Result<()> {
+    let mut model_bytes = 0u64;
+    for path in paths {
+        let metadata = std::fs::metadata(path)
+            .map_err(|e| anyhow!("Cannot read model file {}: {}", path, e))?;
+        model_bytes = model_bytes.saturating_add(metadata.len());
+    }
+    check_model_fits(model_bytes, available_memory_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn parses_mem_available() {
+        let meminfo = "MemTotal:        7812340 kB\n\
+                       MemFree:          301244 kB\n\
+                       MemAvailable:    2097152 kB\n\
+                       Buffers:            1024 kB\n";
+        assert_eq!(parse_mem_available(meminfo), Some(2048 * MB));
+        assert_eq!(parse_mem_available("MemTotal: 1 kB\n"), None);
+    }
+
+    #[test]
+    fn refuses_models_that_do_not_fit() {
+        assert!(check_model_fits(1024 * MB, Some(2048 * MB)).is_ok());
+        assert!(check_model_fits(1024 * MB, None).is_ok());
+
+        let err = check_model_fits(2000 * MB, Some(2048 * MB)).unwrap_err();
+        assert!(
+            err.to_string().starts_with("Insufficient memory"),
+            "{}",
+            err
+        );
+    }
+}
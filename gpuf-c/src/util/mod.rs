@@ -1,18 +1,26 @@
+pub mod affinity;
 pub mod asm;
+pub mod backend;
+pub mod batch;
 pub mod cmd;
 pub mod config;
 pub mod device_info;
+pub mod generation;
+pub mod lru;
+pub mod memory_guard;
 pub mod mobile_control_stream;
 pub mod mobile_tls_policy;
 pub mod model_downloader;
 #[cfg(not(target_os = "ios"))]
 pub mod model_downloader_example;
+pub mod multimodal;
 pub mod network_info;
 pub mod nvswitch_check;
 pub mod safe_command;
 pub mod security_metrics;
 pub mod system_info;
 pub mod system_info_vulkan;
+pub mod token_stream;
 
 use std::sync::OnceLock;
 use tracing::{debug, Level};
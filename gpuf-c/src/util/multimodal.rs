@@ -0,0 +1,69 @@
+/// GGUF `general.architecture` values whose text weights are only half of a
+/// vision model; images need the matching mmproj projector.
+const VISION_ARCHITECTURES: &[&str] = &[
+    "qwen2vl",
+    "qwen25vl",
+    "qwen3vl",
+    "qwen3vlmoe",
+    "mllama",
+    "llava",
+    "minicpmv",
+];
+
+/// Name fragments (model name or file name, lowercased) used when the
+/// architecture is shared with text-only models, e.g. LLaVA on `llama`.
+const VISION_NAME_HINTS: &[&str] = &[
+    "-vl",
+    "_vl",
+    "vl-",
+    "qwen2vl",
+    "qwen25vl",
+    "qwen3vl",
+    "llava",
+    "pixtral",
+    "smolvlm",
+    "minicpm-v",
+    "vision",
+];
+
+/// Error reported when an image is sent to a model that has no mmproj loaded.
+pub const MISSING_MMPROJ_ERROR: &str = "model loaded without mmproj: load it with \
+     gpuf_load_multimodal_model(text_model_path, mmproj_path) to send images";
+
+/// Whether a model is the text half of a vision model, judged from its GGUF
+/// `general.architecture`, `general.name` and file name.
+pub fn requires_mmproj(architecture: Option<&str>, names: &[&str]) -> bool {
+    if let Some(arch) = architecture {
+        if VISION_ARCHITECTURES.contains(&arch.to_ascii_lowercase().as_str()) {
+            return true;
+        }
+    }
+
+    names.iter().any(|name| {
+        let name = name.to_ascii_lowercase();
+        VISION_NAME_HINTS.iter().any(|hint| name.contains(hint))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_vision_architectures_and_names() {
+        assert!(requires_mmproj(Some("qwen2vl"), &[]));
+        assert!(requires_mmproj(Some("Qwen3VL"), &["model.gguf"]));
+        assert!(requires_mmproj(
+            Some("llama"),
+            &["llava-v1.6-mistral-7b.Q4_K_M.gguf"]
+        ));
+        assert!(requires_mmproj(None, &["SmolVLM-500M-Instruct"]));
+
+        assert!(!requires_mmproj(Some("llama"), &["Llama-3.2-1B-Instruct"]));
+        assert!(!requires_mmproj(
+            Some("qwen2"),
+            &["qwen2.5-0.5b-instruct-q4_0.gguf"]
+        ));
+        assert!(!requires_mmproj(None, &[]));
+    }
+}
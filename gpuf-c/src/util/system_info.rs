@@ -1051,6 +1051,26 @@ pub fn pct_to_u8(pct: f32) -> u8 {
     pct.round().clamp(0.0, 100.0) as u8
 }
 
+/// One-line hardware summary for `WorkerDescription`, e.g.
+/// "linux x86_64, 16 CPUs, 2 GPUs, 48 GB".
+pub fn device_summary(devices: &[DevicesInfo]) -> String {
+    let cpus = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let mut summary = format!(
+        "{} {}, {} CPUs",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        cpus
+    );
+    let gpus: u32 = devices.iter().map(|d| d.num as u32).sum();
+    if gpus > 0 {
+        let memory_gb: u32 = devices.iter().map(|d| d.memtotal_gb as u32).sum();
+        summary.push_str(&format!(", {} GPUs, {} GB", gpus, memory_gb));
+    }
+    summary
+}
+
 #[inline]
 #[allow(dead_code)]
 pub fn is_power_of_two_divide(n: i32) -> bool {
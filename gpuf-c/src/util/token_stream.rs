@@ -0,0 +1,134 @@
+use anyhow::{anyhow, Result};
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+// Pieces buffered ahead of a slow consumer before the generation thread waits
+const TOKEN_CHANNEL_CAPACITY: usize = 64;
+
+/// Pieces of a callback-based generation as an async stream, shaped like
+/// `LlamaEngine::stream_with_cached_model_sampling` so both can be relayed the
+/// same way. Ends when the generation finishes; a failed generation ends with
+/// an error item.
+pub type TokenStream = ReceiverStream<Result<String>>;
+
+/// Generation-thread end of a `TokenStream`. Pass `TokenSink::callback()` as
+/// the FFI token callback and `as_user_data()` as its user data, then call
+/// `finish` with the generation's result. The sink must stay in place until
+/// the generation returns.
+pub struct TokenSink {
+    tx: mpsc::Sender<Result<String>>,
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+pub fn token_channel() -> (TokenSink, TokenStream) {
+    let (tx, rx) = mpsc::channel(TOKEN_CHANNEL_CAPACITY);
+    (TokenSink { tx, cancel: None }, ReceiverStream::new(rx))
+}
+
+impl TokenSink {
+    pub fn callback() -> extern "C" fn(*const c_char, *mut c_void) {
+        on_token
+    }
+
+    pub fn as_user_data(&self) -> *mut c_void {
+        self as *const Self as *mut c_void
+    }
+
+    /// Sets `cancel` once the stream is dropped, so the generation stops
+    /// instead of decoding tokens nobody reads.
+    pub fn cancel_on_close(&mut self, cancel: Arc<AtomicBool>) {
+        self.cancel = Some(cancel);
+    }
+
+    /// Sends one piece, waiting while the consumer is behind. Must not be
+    /// called from an async task. Returns false once the stream was dropped.
+    pub fn send(&self, piece: String) -> bool {
+        if self.tx.blocking_send(Ok(piece)).is_ok() {
+            return true;
+        }
+        if let Some(cancel) = &self.cancel {
+            cancel.store(true, Ordering::SeqCst);
+        }
+        false
+    }
+
+    /// Ends the stream with the generation's result code; negative codes are
+    /// passed on as an error.
+    pub fn finish(self, code: c_int) {
+        if code < 0 {
+            let _ = self
+                .tx
+                .blocking_send(Err(anyhow!("Generation failed with code: {}", code)));
+        }
+    }
+}
+
+extern "C" fn on_token(token: *const c_char, user_data: *mut c_void) {
+    if token.is_null() || user_data.is_null() {
+        return;
+    }
+    // SAFETY: `user_data` comes from `TokenSink::as_user_data` and the sink
+    // outlives the generation invoking this callback; `token` is a
+    // NUL-terminated string valid for the duration of the call.
+    let (sink, piece) = unsafe {
+        (
+            &*(user_data as *const TokenSink),
+            CStr::from_ptr(token).to_string_lossy().into_owned(),
+        )
+    };
+    sink.send(piece);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use std::ffi::CString;
+
+    // Drives the sink the way the FFI generation loop does
+    fn generate(sink: TokenSink, pieces: &[&str], code: c_int) {
+        let callback = TokenSink::callback();
+        for piece in pieces {
+            let piece = CString::new(*piece).unwrap();
+            callback(piece.as_ptr(), sink.as_user_data());
+        }
+        sink.finish(code);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn callback_pieces_arrive_in_order() {
+        let (sink, stream) = token_channel();
+        let generation = std::thread::spawn(move || generate(sink, &["Hel", "lo", " wörld"], 3));
+
+        let pieces: Vec<String> = stream.map(|piece| piece.unwrap()).collect().await;
+        assert_eq!(pieces, ["Hel", "lo", " wörld"]);
+        generation.join().unwrap();
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn failed_generation_ends_with_an_error() {
+        let (sink, mut stream) = token_channel();
+        let generation = std::thread::spawn(move || generate(sink, &["partial"], -1));
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), "partial");
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+        generation.join().unwrap();
+    }
+
+    #[test]
+    fn dropping_the_stream_cancels_the_generation() {
+        let (mut sink, stream) = token_channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        sink.cancel_on_close(cancel.clone());
+
+        assert!(sink.send("kept".to_string()));
+        assert!(!cancel.load(Ordering::SeqCst));
+        drop(stream);
+        assert!(!sink.send("dropped".to_string()));
+        assert!(cancel.load(Ordering::SeqCst));
+    }
+}
@@ -15,7 +15,7 @@ use crate::api_server::ApiServer;
 use crate::api_server::ClientInfoResponse;
 use crate::db::stats::{ClientHeartbeatInfo, ClientMonitorInfo};
 use crate::db::{
-    client::{self, ClientDeviceDetailResponse, ClientDeviceInfo},
+    client::{self, ClientDeviceDetailResponse, ClientDeviceInfo, ClientListFilter, ListPage},
     stats::{self, EditClientRequest},
 };
 
@@ -39,6 +39,28 @@ pub struct ClientListResponse {
     pub devices: Vec<ClientDeviceInfo>,
 }
 
+/// Largest page `client_list` returns, also used when no `limit` is given.
+const CLIENT_LIST_MAX_LIMIT: usize = 500;
+
+/// One page of a listing; `total` counts every item matching the filters.
+#[derive(Debug, serde::Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: usize,
+    pub limit: usize,
+    pub offset: usize,
+}
+
+/// The requested page, clamping `limit` to `CLIENT_LIST_MAX_LIMIT`.
+fn list_page(limit: Option<usize>, offset: Option<usize>) -> ListPage {
+    ListPage {
+        limit: limit
+            .unwrap_or(CLIENT_LIST_MAX_LIMIT)
+            .min(CLIENT_LIST_MAX_LIMIT),
+        offset: offset.unwrap_or(0),
+    }
+}
+
 //#@ get_user_clients api
 #[derive(Debug, Deserialize)]
 pub struct ClientListQuery {
@@ -47,6 +69,24 @@ pub struct ClientListQuery {
     pub status: Option<String>,
     pub name: Option<String>,
     pub valid_status: Option<String>,
+    /// Page size for `client_list`, at most `CLIENT_LIST_MAX_LIMIT`.
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    /// Only clients whose status is `online`.
+    #[serde(default, alias = "online_only")]
+    pub authed_only: bool,
+}
+
+impl ClientListQuery {
+    fn filter(&self) -> ClientListFilter<'_> {
+        ClientListFilter {
+            client_id: self.client_id.as_ref(),
+            status: self.status.as_ref(),
+            name: self.name.as_ref(),
+            valid_status: self.valid_status.as_ref(),
+            online_only: self.authed_only,
+        }
+    }
 }
 
 // API Handlers
@@ -88,15 +128,13 @@ pub async fn insert_client(
 pub async fn get_user_clients(
     State(app_state): State<Arc<ApiServer>>,
     Query(query): Query<ClientListQuery>,
-) -> Result<Json<ApiResponse<ClientListResponse>>, StatusCode> {
-    // Get database connection
-    let mut devices = client::get_user_client_status_list(
+) -> Result<Json<ApiResponse<Page<ClientDeviceInfo>>>, StatusCode> {
+    let list_page = list_page(query.limit, query.offset);
+    let (items, total) = client::get_user_client_status_list(
         &app_state.db_pool,
         &query.user_id,
-        query.client_id.as_ref(),
-        query.status.as_ref(),
-        query.name.as_ref(),
-        query.valid_status.as_ref(),
+        &query.filter(),
+        Some(list_page),
     )
     .await
     .map_err(|e| {
@@ -104,34 +142,35 @@ pub async fn get_user_clients(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    let client_ids: Vec<String> = devices.iter().map(|d| d.client_id.clone()).collect();
+    // Only the returned page needs its loaded models
+    let mut page = Page {
+        items,
+        total,
+        limit: list_page.limit,
+        offset: list_page.offset,
+    };
+    let client_ids: Vec<String> = page.items.iter().map(|d| d.client_id.clone()).collect();
     let models_map =
         client::get_loaded_models_batch_from_redis(&app_state.redis_client, &client_ids)
             .await
             .unwrap_or_default();
-    for d in &mut devices {
+    for d in &mut page.items {
         if let Some(models) = models_map.get(&d.client_id) {
             d.loaded_models = models.clone();
         }
     }
-    let response = ClientListResponse {
-        total: devices.len(),
-        devices,
-    };
-    Ok(Json(ApiResponse::success(response)))
+    Ok(Json(ApiResponse::success(page)))
 }
 
 pub async fn get_user_client_status_list(
     State(app_state): State<Arc<ApiServer>>,
     Query(query): Query<ClientListQuery>,
 ) -> Result<Json<ApiResponse<ClientListResponse>>, StatusCode> {
-    let mut devices = client::get_user_client_status_list(
+    let (mut devices, total) = client::get_user_client_status_list(
         &app_state.db_pool,
         &query.user_id,
-        query.client_id.as_ref(),
-        query.status.as_ref(),
-        query.name.as_ref(),
-        query.valid_status.as_ref(),
+        &query.filter(),
+        None,
     )
     .await
     .map_err(|e| {
@@ -149,10 +188,7 @@ pub async fn get_user_client_status_list(
             d.loaded_models = models.clone();
         }
     }
-    let response = ClientListResponse {
-        total: devices.len(),
-        devices,
-    };
+    let response = ClientListResponse { total, devices };
     Ok(Json(ApiResponse::success(response)))
 }
 
@@ -398,3 +434,104 @@ pub async fn get_model_download_progress(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_limit_is_clamped_to_the_maximum() {
+        let page = list_page(Some(10_000), None);
+        assert_eq!(page.limit, CLIENT_LIST_MAX_LIMIT);
+        assert_eq!(page.offset, 0);
+
+        let page = list_page(None, Some(20));
+        assert_eq!((page.limit, page.offset), (CLIENT_LIST_MAX_LIMIT, 20));
+        assert_eq!(list_page(Some(10), None).limit, 10);
+    }
+
+    #[test]
+    fn online_filter_accepts_both_parameter_names() {
+        let parse = |query: &str| {
+            Query::<ClientListQuery>::try_from_uri(&format!("/clients?{query}").parse().unwrap())
+                .unwrap()
+                .0
+        };
+        assert!(!parse("user_id=u1").authed_only);
+        assert!(parse("user_id=u1&authed_only=true").authed_only);
+        assert!(parse("user_id=u1&online_only=true").filter().online_only);
+    }
+
+    /// Pages through five clients with `get_user_clients`. Needs Postgres
+    /// (`GPUF_TEST_DATABASE_URL`) and Redis (`GPUF_TEST_REDIS_URL`); run with
+    /// `--features db-tests`.
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_client_list_pages_through_the_handler() {
+        let database_url = std::env::var("GPUF_TEST_DATABASE_URL")
+            .unwrap_or_else(|_| "postgres://postgres@localhost:5432/postgres".to_string());
+        let redis_url = std::env::var("GPUF_TEST_REDIS_URL")
+            .unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+        let app_state = Arc::new(ApiServer {
+            db_pool: sqlx::PgPool::connect(&database_url).await.unwrap(),
+            redis_client: Arc::new(redis::Client::open(redis_url).unwrap()),
+        });
+        let user_id = hex::encode(rand::random::<[u8; 8]>());
+        let mut client_ids: Vec<[u8; 16]> = Vec::new();
+        for _ in 0..5 {
+            let client_id: [u8; 16] = rand::random();
+            sqlx::query(
+                "INSERT INTO gpu_assets (user_id, client_id, client_name, client_status) VALUES ($1, $2, 'test', 'online')",
+            )
+            .bind(&user_id)
+            .bind(client_id)
+            .execute(&app_state.db_pool)
+            .await
+            .unwrap();
+            client_ids.push(client_id);
+        }
+
+        let mut pages = Vec::new();
+        for (limit, offset) in [(2, 0), (2, 2), (2, 4), (2, 10)] {
+            let query = ClientListQuery {
+                user_id: user_id.clone(),
+                client_id: None,
+                status: None,
+                name: None,
+                valid_status: None,
+                limit: Some(limit),
+                offset: Some(offset),
+                authed_only: false,
+            };
+            pages.push(get_user_clients(State(app_state.clone()), Query(query)).await);
+        }
+        for client_id in &client_ids {
+            sqlx::query("DELETE FROM gpu_assets WHERE client_id = $1")
+                .bind(client_id)
+                .execute(&app_state.db_pool)
+                .await
+                .unwrap();
+        }
+
+        let pages: Vec<Page<ClientDeviceInfo>> = pages
+            .into_iter()
+            .map(|page| page.unwrap().0.data.unwrap())
+            .collect();
+        let sizes: Vec<usize> = pages.iter().map(|page| page.items.len()).collect();
+        assert_eq!(sizes, [2, 2, 1, 0]);
+        for page in &pages {
+            assert_eq!(page.total, 5);
+            assert_eq!(page.limit, 2);
+        }
+        assert_eq!(pages[3].offset, 10);
+
+        // The pages cover every client exactly once
+        let mut seen: Vec<String> = pages
+            .iter()
+            .flat_map(|page| page.items.iter().map(|d| d.client_id.clone()))
+            .collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 5);
+    }
+}
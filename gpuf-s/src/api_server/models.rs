@@ -40,6 +40,9 @@ pub struct ModelResponse {
     pub download_url: Option<String>,
     pub checksum: Option<String>,
     pub expected_size: Option<i64>,
+    /// Quantization detected from the model name, e.g. "Q4_K_M".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quant: Option<String>,
 }
 
 // Create or update a model
@@ -89,6 +92,7 @@ pub async fn get_models(
             let models = models
                 .into_iter()
                 .map(|model| ModelResponse {
+                    quant: common::QuantType::detect(&model.name).map(|q| q.to_string()),
                     id: model.id,
                     name: model.name,
                     version: model.version,
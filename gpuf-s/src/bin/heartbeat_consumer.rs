@@ -4,7 +4,10 @@ use gpuf_s::{consumer, points_sync};
 use tracing::{error, info, warn};
 use tracing_subscriber::{fmt, EnvFilter};
 
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -28,6 +31,10 @@ pub struct Args {
     #[arg(long, default_value = "300")]
     pub offline_after_secs: i64,
 
+    /// Port serving consumer lag metrics on `/metrics`; 0 disables it
+    #[arg(long, env = "GPUF_CONSUMER_METRICS_PORT", default_value = "9101")]
+    pub metrics_port: u16,
+
     #[arg(long, default_value = "30")]
     pub sweep_interval_secs: u64,
 
@@ -186,6 +193,43 @@ async fn main() -> Result<()> {
         }
     });
 
+    let lag = Arc::new(consumer::ConsumerLag::new()?);
+    if args.metrics_port != 0 {
+        let metrics_addr = SocketAddr::from(([0, 0, 0, 0], args.metrics_port));
+        let metrics_lag = lag.clone();
+        tokio::spawn(async move {
+            if let Err(e) = consumer::serve_metrics(metrics_addr, metrics_lag).await {
+                error!("Consumer metrics endpoint failed: {}", e);
+            }
+        });
+    }
+
+    // Stop consuming on SIGTERM/SIGINT; the buffered heartbeats are still written
+    let shutdown = CancellationToken::new();
+    let signal_shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut sigterm =
+                signal(SignalKind::terminate()).expect("Failed to create SIGTERM listener");
+            tokio::select! {
+                _ = sigterm.recv() => info!("Received SIGTERM, shutting down consumer..."),
+                _ = tokio::signal::ctrl_c() => info!("Received Ctrl-C, shutting down consumer..."),
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                error!("Failed to listen for Ctrl-C shutdown signal: {}", e);
+            }
+        }
+
+        signal_shutdown.cancel();
+    });
+
     // Start the consumer service
     consumer::start_consumer_services(
         &args.bootstrap_server, // From your command line args
@@ -194,6 +238,8 @@ async fn main() -> Result<()> {
         db_pool,
         args.batch_size,    // Batch size
         args.batch_timeout, // Batch timeout in seconds
+        lag,
+        shutdown,
     )
     .await?;
 
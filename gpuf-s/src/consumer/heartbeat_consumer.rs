@@ -1,64 +1,285 @@
+use super::ConsumerLag;
 use anyhow::Result;
 use rdkafka::consumer::stream_consumer::StreamConsumer;
+use rdkafka::consumer::Consumer;
 use rdkafka::message::OwnedMessage;
+use std::future::Future;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc;
-use tracing::{debug, error, info};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+/// The Kafka side of the heartbeat consumer; tests substitute a fake.
+pub trait HeartbeatSource {
+    fn recv(&self) -> impl Future<Output = Result<OwnedMessage>> + Send;
+    /// Stops fetching from the assigned partitions while still polling, so
+    /// the group does not see the consumer as stalled.
+    fn pause(&self) -> Result<()>;
+    fn resume(&self) -> Result<()>;
+}
+
+impl HeartbeatSource for StreamConsumer {
+    async fn recv(&self) -> Result<OwnedMessage> {
+        Ok(StreamConsumer::recv(self).await?.detach())
+    }
+
+    fn pause(&self) -> Result<()> {
+        Ok(Consumer::pause(self, &self.assignment()?)?)
+    }
+
+    fn resume(&self) -> Result<()> {
+        Ok(Consumer::resume(self, &self.assignment()?)?)
+    }
+}
 
 #[allow(dead_code)] // Heartbeat consumer service
-pub async fn start_consumer(
-    consumer: Arc<StreamConsumer>,
+pub async fn start_consumer<C: HeartbeatSource>(
+    consumer: Arc<C>,
     tx: mpsc::Sender<Vec<OwnedMessage>>,
     batch_size: usize,
+    lag: Arc<ConsumerLag>,
+    shutdown: CancellationToken,
 ) -> Result<()> {
     info!(
         "Starting heartbeat consumer with batch size: {}",
         batch_size
     );
+    consume(
+        consumer.as_ref(),
+        tx,
+        batch_size,
+        Duration::from_secs(1),
+        &lag,
+        &shutdown,
+    )
+    .await;
+    info!("Heartbeat consumer shutting down");
+    Ok(())
+}
+
+/// Polls `consumer` at least every `poll_interval` and hands batches to the
+/// processor without ever waiting on the channel. Consumption is paused
+/// while three quarters of the channel is taken and resumed once half of it
+/// is free again. On `shutdown` the partial batch is handed over and `tx`
+/// dropped, so the processor writes everything and stops.
+async fn consume<C: HeartbeatSource>(
+    consumer: &C,
+    tx: mpsc::Sender<Vec<OwnedMessage>>,
+    batch_size: usize,
+    poll_interval: Duration,
+    lag: &ConsumerLag,
+    shutdown: &CancellationToken,
+) {
     let mut message_buffer = Vec::with_capacity(batch_size);
     let mut last_flush = tokio::time::Instant::now();
-    let flush_interval = Duration::from_secs(1);
+    let mut paused = false;
 
-    'consumer_loop: loop {
-        match tokio::time::timeout(flush_interval, consumer.as_ref().recv()).await {
-            Ok(Ok(borrowed_message)) => {
-                // Convert BorrowedMessage to OwnedMessage using detach()
-                let message = borrowed_message.detach();
-                message_buffer.push(message);
+    loop {
+        let free = tx.capacity();
+        if !paused && free <= tx.max_capacity() / 4 {
+            match consumer.pause() {
+                Ok(()) => {
+                    paused = true;
+                    lag.record_pause();
+                    warn!(
+                        "Heartbeat processor is behind ({} heartbeats pending), pausing consumption (pause #{})",
+                        lag.pending(),
+                        lag.pauses()
+                    );
+                }
+                Err(e) => error!("Failed to pause heartbeat consumption: {}", e),
+            }
+        } else if paused && free >= tx.max_capacity() / 2 {
+            match consumer.resume() {
+                Ok(()) => {
+                    paused = false;
+                    info!(
+                        "Heartbeat processor caught up ({} heartbeats pending), resuming consumption",
+                        lag.pending()
+                    );
+                }
+                Err(e) => error!("Failed to resume heartbeat consumption: {}", e),
+            }
+        }
 
-                if message_buffer.len() >= batch_size {
-                    if let Err(e) = tx.send(message_buffer.drain(..).collect()).await {
-                        error!("Failed to send batch to processor: {}", e);
-                        break 'consumer_loop;
-                    }
-                    last_flush = tokio::time::Instant::now();
+        let received = tokio::select! {
+            _ = shutdown.cancelled() => {
+                if !message_buffer.is_empty() && tx.send(message_buffer).await.is_err() {
+                    error!("Failed to send final batch to processor: channel closed");
                 }
+                return;
+            }
+            received = tokio::time::timeout(poll_interval, consumer.recv()) => received,
+        };
+        match received {
+            Ok(Ok(message)) => {
+                message_buffer.push(message);
+                lag.consumed(1);
             }
             Ok(Err(e)) => {
                 error!("Error receiving message: {}", e);
                 continue;
             }
-
             Err(_) => {
                 debug!("Heartbeat consumer timeout");
-                if !message_buffer.is_empty() && last_flush.elapsed() >= flush_interval {
-                    if let Err(e) = tx.send(message_buffer.drain(..).collect()).await {
-                        error!("Failed to send batch to processor: {}", e);
-                        break 'consumer_loop;
-                    }
-                    last_flush = tokio::time::Instant::now();
+            }
+        }
+
+        let flush_due = message_buffer.len() >= batch_size
+            || (!message_buffer.is_empty() && last_flush.elapsed() >= poll_interval);
+        if flush_due {
+            match tx.try_send(std::mem::take(&mut message_buffer)) {
+                Ok(()) => last_flush = tokio::time::Instant::now(),
+                // Kept until the processor makes room; consumption is paused by then
+                Err(TrySendError::Full(batch)) => message_buffer = batch,
+                Err(TrySendError::Closed(_)) => {
+                    error!("Failed to send batch to processor: channel closed");
+                    return;
                 }
             }
         }
     }
+}
 
-    if !message_buffer.is_empty() {
-        if let Err(e) = tx.send(message_buffer).await {
-            error!("Failed to send final batch to processor: {}", e);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdkafka::message::Timestamp;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::Mutex;
+    use tokio::time::Instant;
+
+    /// Stream of heartbeats that records when it was polled. Endless unless
+    /// `stop_after` is set, in which case it goes quiet after that many.
+    #[derive(Default)]
+    struct FakeSource {
+        stop_after: Option<usize>,
+        paused: AtomicBool,
+        pauses: AtomicU32,
+        resumes: AtomicU32,
+        polls: Mutex<Vec<Instant>>,
+    }
+
+    impl HeartbeatSource for FakeSource {
+        async fn recv(&self) -> Result<OwnedMessage> {
+            let polled = {
+                let mut polls = self.polls.lock().unwrap();
+                polls.push(Instant::now());
+                polls.len()
+            };
+            if self.paused.load(Ordering::SeqCst)
+                || self.stop_after.is_some_and(|limit| polled > limit)
+            {
+                std::future::pending::<()>().await;
+            }
+            tokio::task::yield_now().await;
+            Ok(OwnedMessage::new(
+                Some(vec![0; 8]),
+                Some(b"client".to_vec()),
+                "client-heartbeats".to_string(),
+                Timestamp::NotAvailable,
+                0,
+                0,
+                None,
+            ))
+        }
+
+        fn pause(&self) -> Result<()> {
+            self.paused.store(true, Ordering::SeqCst);
+            self.pauses.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn resume(&self) -> Result<()> {
+            self.paused.store(false, Ordering::SeqCst);
+            self.resumes.fetch_add(1, Ordering::SeqCst);
+            Ok(())
         }
     }
 
-    info!("Heartbeat consumer shutting down");
-    Ok(())
+    #[tokio::test]
+    async fn slow_processor_pauses_consumption_instead_of_stalling_polls() {
+        // Stand-in for max.poll.interval.ms, scaled down with the poll interval
+        let max_poll_interval = Duration::from_millis(200);
+        let poll_interval = Duration::from_millis(20);
+        let source = Arc::new(FakeSource::default());
+        let lag = Arc::new(ConsumerLag::new().unwrap());
+        let (tx, mut rx) = mpsc::channel::<Vec<OwnedMessage>>(8);
+
+        let consumer = tokio::spawn({
+            let source = source.clone();
+            let lag = lag.clone();
+            async move {
+                let shutdown = CancellationToken::new();
+                consume(source.as_ref(), tx, 4, poll_interval, &lag, &shutdown).await
+            }
+        });
+
+        // A DB insert that stalls for well over the poll limit
+        let first = rx.recv().await.unwrap();
+        lag.processed(first.len() as u64);
+        tokio::time::sleep(max_poll_interval * 3).await;
+        assert!(source.paused.load(Ordering::SeqCst));
+        assert!(lag.pending() > 0);
+
+        while source.resumes.load(Ordering::SeqCst) == 0 {
+            let batch = rx.recv().await.unwrap();
+            lag.processed(batch.len() as u64);
+        }
+        consumer.abort();
+
+        assert_eq!(lag.pauses(), source.pauses.load(Ordering::SeqCst) as u64);
+        assert!(lag.pauses() >= 1);
+        let polls = source.polls.lock().unwrap();
+        let longest_gap = polls
+            .windows(2)
+            .map(|w| w[1] - w[0])
+            .max()
+            .unwrap_or_default();
+        assert!(
+            longest_gap < max_poll_interval,
+            "consumer went {:?} without polling",
+            longest_gap
+        );
+    }
+
+    #[tokio::test]
+    async fn shutdown_hands_the_partial_batch_to_the_processor() {
+        let source = Arc::new(FakeSource {
+            stop_after: Some(3),
+            ..FakeSource::default()
+        });
+        let lag = Arc::new(ConsumerLag::new().unwrap());
+        let shutdown = CancellationToken::new();
+        let (tx, mut rx) = mpsc::channel::<Vec<OwnedMessage>>(8);
+
+        // Neither the batch size nor the poll interval would flush on their own
+        let consumer = tokio::spawn({
+            let source = source.clone();
+            let lag = lag.clone();
+            let shutdown = shutdown.clone();
+            async move {
+                consume(
+                    source.as_ref(),
+                    tx,
+                    100,
+                    Duration::from_secs(3600),
+                    &lag,
+                    &shutdown,
+                )
+                .await
+            }
+        });
+        while lag.pending() < 3 {
+            tokio::task::yield_now().await;
+        }
+
+        shutdown.cancel();
+        assert_eq!(rx.recv().await.unwrap().len(), 3);
+        // The sender is dropped, so the processor's loop ends after this batch
+        assert!(rx.recv().await.is_none());
+        consumer.await.unwrap();
+    }
 }
@@ -3,45 +3,100 @@ use chrono::{TimeZone, Utc};
 use rdkafka::message::Timestamp;
 use rdkafka::message::{Message, OwnedMessage};
 use sqlx::{Pool, Postgres};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
 use tracing::{debug, error, info};
 
-use crate::db::stats::{insert_heartbeat, ClientDailyStats, DeviceDailyStats};
+use super::ConsumerLag;
+use crate::db::stats::{insert_heartbeat, upsert_daily_stats};
 use crate::util::protoc;
 use common::format_bytes;
 
 #[allow(dead_code)]
 pub async fn start_processor(
-    mut rx: mpsc::Receiver<Vec<OwnedMessage>>,
+    rx: mpsc::Receiver<Vec<OwnedMessage>>,
     db_pool: Pool<Postgres>,
     batch_size: usize,
     batch_timeout_secs: u64,
+    lag: Arc<ConsumerLag>,
 ) -> Result<()> {
     info!(
         "Starting heartbeat processor with batch size: {}, timeout: {}s",
         batch_size, batch_timeout_secs
     );
 
-    loop {
-        match rx.recv().await {
-            Some(messages) => {
-                let pool = db_pool.clone();
-                let message_count = messages.len();
+    write_in_batches(
+        rx,
+        batch_size,
+        Duration::from_secs(batch_timeout_secs.max(1)),
+        &lag,
+        |messages| process_batch(messages, db_pool.clone()),
+    )
+    .await;
+    Ok(())
+}
 
-                if let Err(e) = process_batch(messages, pool).await {
-                    error!("Error processing batch: {}", e);
-                }
+/// Buffers incoming messages and hands them to `write` once `batch_size`
+/// are buffered or `batch_timeout` passes with a partial batch. Whatever is
+/// still buffered when the channel closes is written before returning.
+async fn write_in_batches<F, Fut>(
+    mut rx: mpsc::Receiver<Vec<OwnedMessage>>,
+    batch_size: usize,
+    batch_timeout: Duration,
+    lag: &ConsumerLag,
+    mut write: F,
+) where
+    F: FnMut(Vec<OwnedMessage>) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut buffer = Vec::with_capacity(batch_size);
+    let mut ticker = tokio::time::interval(batch_timeout);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker.reset();
 
-                debug!("Processed batch of {} messages", message_count);
-            }
-            None => {
-                info!("No more messages to process, shutting down processor");
-                break;
+    loop {
+        tokio::select! {
+            received = rx.recv() => match received {
+                Some(messages) => {
+                    buffer.extend(messages);
+                    if buffer.len() < batch_size {
+                        continue;
+                    }
+                }
+                None => {
+                    info!("No more messages to process, shutting down processor");
+                    if !buffer.is_empty() {
+                        flush(&mut buffer, lag, &mut write).await;
+                    }
+                    return;
+                }
+            },
+            _ = ticker.tick() => {
+                if buffer.is_empty() {
+                    continue;
+                }
             }
         }
+        flush(&mut buffer, lag, &mut write).await;
+        ticker.reset();
     }
+}
 
-    Ok(())
+async fn flush<F, Fut>(buffer: &mut Vec<OwnedMessage>, lag: &ConsumerLag, write: &mut F)
+where
+    F: FnMut(Vec<OwnedMessage>) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let messages = std::mem::take(buffer);
+    let message_count = messages.len();
+    if let Err(e) = write(messages).await {
+        error!("Error processing batch: {}", e);
+    }
+    lag.processed(message_count as u64);
+    debug!("Processed batch of {} messages", message_count);
 }
 
 #[allow(dead_code)]
@@ -111,36 +166,17 @@ async fn process_batch(messages: Vec<OwnedMessage>, db_pool: Pool<Postgres>) ->
                     continue;
                 }
 
-                if let Err(e) = ClientDailyStats::upsert(
-                    &mut transaction,
-                    &heartbeat.client_id,
-                    Some(heartbeat.system_info.cpu_usage as f64),
-                    Some(heartbeat.system_info.memory_usage as f64),
-                    Some(heartbeat.system_info.disk_usage as f64),
-                    Some(heartbeat.system_info.network_rx.try_into().unwrap_or(0)),
-                    Some(heartbeat.system_info.network_tx.try_into().unwrap_or(0)),
-                    event_ts,
-                )
-                .await
-                {
-                    error!(
-                        "Failed to update client heartbeat for client {}: {}",
-                        heartbeat.client_id.log_label(),
-                        e
-                    );
-                    let _ = transaction.rollback().await;
-                    continue;
-                }
-                if let Err(e) = DeviceDailyStats::upsert_batch(
+                if let Err(e) = upsert_daily_stats(
                     &mut transaction,
                     &heartbeat.client_id,
+                    &heartbeat.system_info,
                     &heartbeat.devices_info,
                     event_ts,
                 )
                 .await
                 {
                     error!(
-                        "Failed to update device heartbeat for client {}: {}",
+                        "Failed to update daily stats for client {}: {}",
                         heartbeat.client_id.log_label(),
                         e
                     );
@@ -171,3 +207,70 @@ async fn process_batch(messages: Vec<OwnedMessage>, db_pool: Pool<Postgres>) ->
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn heartbeats(count: usize) -> Vec<OwnedMessage> {
+        (0..count)
+            .map(|offset| {
+                OwnedMessage::new(
+                    Some(vec![0; 8]),
+                    Some(b"client".to_vec()),
+                    "client-heartbeats".to_string(),
+                    Timestamp::NotAvailable,
+                    0,
+                    offset as i64,
+                    None,
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn partial_batch_is_written_when_the_channel_closes() {
+        let (tx, rx) = mpsc::channel(4);
+        let lag = ConsumerLag::new().unwrap();
+        lag.consumed(3);
+        tx.send(heartbeats(2)).await.unwrap();
+        tx.send(heartbeats(1)).await.unwrap();
+        drop(tx);
+
+        let written = Mutex::new(Vec::new());
+        write_in_batches(rx, 10, Duration::from_secs(60), &lag, |messages| {
+            written.lock().unwrap().push(messages.len());
+            std::future::ready(Ok(()))
+        })
+        .await;
+
+        assert_eq!(written.into_inner().unwrap(), [3]);
+        assert_eq!(lag.pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn partial_batch_is_written_on_the_timeout_tick() {
+        let (tx, rx) = mpsc::channel(4);
+        let lag = ConsumerLag::new().unwrap();
+        lag.consumed(2);
+        let (written_tx, mut written_rx) = mpsc::unbounded_channel();
+        let processor = tokio::spawn(async move {
+            write_in_batches(rx, 10, Duration::from_millis(50), &lag, |messages| {
+                let _ = written_tx.send(messages.len());
+                std::future::ready(Ok(()))
+            })
+            .await
+        });
+
+        tx.send(heartbeats(2)).await.unwrap();
+        let written = tokio::time::timeout(Duration::from_secs(5), written_rx.recv())
+            .await
+            .expect("partial batch was not flushed on the tick");
+        assert_eq!(written, Some(2));
+
+        drop(tx);
+        processor.await.unwrap();
+        assert_eq!(written_rx.recv().await, None, "nothing left to flush");
+    }
+}
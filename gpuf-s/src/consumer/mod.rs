@@ -2,16 +2,106 @@ pub mod heartbeat_consumer;
 pub mod heartbeat_processor;
 
 use anyhow::Result;
+use axum::{http::header, response::IntoResponse, routing::get, Router};
+use prometheus::{Encoder, IntCounter, IntGauge, Opts, Registry, TextEncoder};
 use rdkafka::config::ClientConfig;
 use rdkafka::consumer::stream_consumer::StreamConsumer;
 use rdkafka::consumer::Consumer;
 use rdkafka::message::OwnedMessage;
 use sqlx::{Pool, Postgres};
+use std::net::SocketAddr;
 use std::sync::Arc;
+use tokio::net::TcpListener;
 use tokio::sync::mpsc;
-use tracing::error;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
 
+/// Heartbeats taken off Kafka that the processor has not written yet, and
+/// how often the consumer paused because of them. Served in Prometheus text
+/// format on the consumer's `/metrics`.
+#[allow(dead_code)] // Only the heartbeat-consumer binary builds one
+pub struct ConsumerLag {
+    registry: Registry,
+    pending: IntGauge,
+    pauses: IntCounter,
+}
+
+#[allow(dead_code)]
+impl ConsumerLag {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+        let pending = IntGauge::with_opts(Opts::new(
+            "gpuf_heartbeat_consumer_pending",
+            "Heartbeats taken off Kafka that are not written to the database yet",
+        ))?;
+        let pauses = IntCounter::with_opts(Opts::new(
+            "gpuf_heartbeat_consumer_pauses_total",
+            "Times consumption paused because the processor fell behind",
+        ))?;
+        registry.register(Box::new(pending.clone()))?;
+        registry.register(Box::new(pauses.clone()))?;
+        Ok(Self {
+            registry,
+            pending,
+            pauses,
+        })
+    }
+
+    pub fn pending(&self) -> u64 {
+        self.pending.get().max(0) as u64
+    }
+
+    pub fn pauses(&self) -> u64 {
+        self.pauses.get()
+    }
+
+    fn consumed(&self, count: u64) {
+        self.pending.add(count as i64);
+    }
+
+    fn processed(&self, count: u64) {
+        self.pending.sub(count as i64);
+    }
+
+    fn record_pause(&self) {
+        self.pauses.inc();
+    }
+
+    /// All metrics in Prometheus text exposition format.
+    pub fn encode(&self) -> Result<String> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+}
+
+/// Serves `lag` on `/metrics` at `addr` until the listener fails.
+#[allow(dead_code)]
+pub async fn serve_metrics(addr: SocketAddr, lag: Arc<ConsumerLag>) -> Result<()> {
+    let app = Router::new().route(
+        "/metrics",
+        get(move || async move {
+            match lag.encode() {
+                Ok(body) => {
+                    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response()
+                }
+                Err(e) => {
+                    error!("Failed to encode consumer metrics: {}", e);
+                    axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+        }),
+    );
+    let listener = TcpListener::bind(addr).await?;
+    info!("Serving heartbeat consumer metrics on {}/metrics", addr);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// Runs the heartbeat consumer and processor until `shutdown` is cancelled,
+/// then waits for the processor to write the heartbeats already consumed.
 #[allow(dead_code)] // Consumer service management
+#[allow(clippy::too_many_arguments)]
 pub async fn start_consumer_services(
     bootstrap_servers: &str,
     group_id: &str,
@@ -19,6 +109,8 @@ pub async fn start_consumer_services(
     db_pool: Pool<Postgres>,
     batch_size: usize,
     batch_timeout_secs: u64,
+    lag: Arc<ConsumerLag>,
+    shutdown: CancellationToken,
 ) -> Result<()> {
     // Create Kafka consumer with Arc for shared ownership
     let consumer: Arc<StreamConsumer> = Arc::new(
@@ -37,15 +129,16 @@ pub async fn start_consumer_services(
     // Subscribe to the topic
     consumer.subscribe(&[topic])?;
 
-    // Create channel for batching
+    // Create channel for batching; the consumer pauses before it fills up
     let (tx, rx) = mpsc::channel::<Vec<OwnedMessage>>(32);
 
     // Start the processor
-    let processor_handle = tokio::spawn(heartbeat_processor::start_processor(
+    let mut processor_handle = tokio::spawn(heartbeat_processor::start_processor(
         rx,
         db_pool.clone(),
         batch_size,
         batch_timeout_secs,
+        lag.clone(),
     ));
 
     // Clone the Arc for the consumer task
@@ -56,6 +149,8 @@ pub async fn start_consumer_services(
         consumer_clone,
         tx,
         batch_size,
+        lag,
+        shutdown,
     ));
 
     // Wait for either task to complete
@@ -64,8 +159,12 @@ pub async fn start_consumer_services(
             if let Err(e) = res {
                 error!("Consumer task failed: {}", e);
             }
+            // The consumer's sender is gone; let the processor write what it buffered
+            if let Err(e) = processor_handle.await {
+                error!("Processor task failed: {}", e);
+            }
         }
-        res = processor_handle => {
+        res = &mut processor_handle => {
             if let Err(e) = res {
                 error!("Processor task failed: {}", e);
             }
@@ -74,3 +173,20 @@ pub async fn start_consumer_services(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lag_is_exported_as_prometheus_metrics() {
+        let lag = ConsumerLag::new().unwrap();
+        lag.consumed(5);
+        lag.processed(2);
+        lag.record_pause();
+
+        let text = lag.encode().unwrap();
+        assert!(text.contains("gpuf_heartbeat_consumer_pending 3"));
+        assert!(text.contains("gpuf_heartbeat_consumer_pauses_total 1"));
+    }
+}
@@ -4,7 +4,7 @@ use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
 use common::Model;
 use redis::{AsyncCommands, Client as RedisClient, Commands};
-use sqlx::{postgres::Postgres, FromRow, Pool};
+use sqlx::{postgres::Postgres, FromRow, Pool, QueryBuilder};
 use std::time::{SystemTime, UNIX_EPOCH};
 use tracing::{debug, info, warn};
 
@@ -92,8 +92,9 @@ pub async fn update_client_db(
     os_type: &str,
 ) -> Result<()> {
     debug!(
-        "update model for client {:?}: os_type: {}",
-        client_id, os_type
+        "update model for client {}: os_type: {}",
+        client_id.log_label(),
+        os_type
     );
 
     let _row  = sqlx::query(
@@ -185,118 +186,98 @@ pub async fn get_loaded_models_batch_from_redis(
 
     Ok(out)
 }
-#[allow(dead_code)]
-pub async fn get_user_client_status_list(
-    pool: &Pool<Postgres>,
+/// Which of a user's valid clients `get_user_client_status_list` returns.
+#[derive(Debug, Default)]
+pub struct ClientListFilter<'a> {
+    pub client_id: Option<&'a String>,
+    pub status: Option<&'a String>,
+    /// Case-insensitive substring of the client name.
+    pub name: Option<&'a String>,
+    pub valid_status: Option<&'a String>,
+    /// Only clients whose `client_status` is `online`.
+    pub online_only: bool,
+}
+
+/// Rows of a listing to fetch: `limit` of them after skipping `offset`.
+#[derive(Debug, Clone, Copy)]
+pub struct ListPage {
+    pub limit: usize,
+    pub offset: usize,
+}
+
+impl ListPage {
+    /// `LIMIT` and `OFFSET` values, saturated to Postgres' `BIGINT`.
+    fn sql_bounds(self) -> (i64, i64) {
+        let bound = |n: usize| i64::try_from(n).unwrap_or(i64::MAX);
+        (bound(self.limit), bound(self.offset))
+    }
+}
+
+/// Appends the `WHERE` clause shared by the client list and its count.
+fn push_client_list_filter(
+    query: &mut QueryBuilder<'_, Postgres>,
     user_id: &str,
-    client_id: Option<&String>,
-    status: Option<&String>,
-    name: Option<&String>,
-    valid_status: Option<&String>,
-) -> Result<Vec<ClientDeviceInfo>> {
-    // Build the base query
-    let mut query = r#"
-    SELECT 
-        ga.client_id as client_id,
-        ga.client_name as client_name,
-        ga.os_type as os_type,
-        ga.client_status as client_status,
-        ga.created_at::TIMESTAMPTZ as created_at,
-        ga.updated_at::TIMESTAMPTZ as last_online,
-        di.device_name as device_name,
-        si.cpu_usage as cpu_usage,
-        si.mem_usage as memory_usage,
-        si.disk_usage as storage_usage,
-        si.total_tflops as total_tflops,
-        COALESCE((
-            SELECT (ROUND((AVG(LEAST(1.0, total_heartbeats::FLOAT / 720) * 100.0))::numeric, 2))::FLOAT8
-            FROM client_daily_stats 
-            WHERE client_id = ga.client_id
-            GROUP BY client_id
-        ), 0) as health_rate,
-        (
-            SELECT COUNT(*)::INTEGER
-            FROM client_daily_stats
-            WHERE client_id = ga.client_id
-        ) as uptime_days
-    FROM gpu_assets ga
-    LEFT JOIN device_info di ON ga.client_id = di.client_id AND di.device_index = 0
-    LEFT JOIN (
-        SELECT 
-            client_id,
-            cpu_usage,
-            mem_usage,
-            disk_usage,
-            total_tflops,
-            ROW_NUMBER() OVER (PARTITION BY client_id ORDER BY created_at DESC) as rn
-        FROM system_info
-    ) si ON ga.client_id = si.client_id AND si.rn = 1
-    WHERE ga.user_id = $1 AND ga.valid_status = 'valid'
-    "#
-    .to_string();
-
-    // 2. Collect all parameters in a vector
-    let mut param_count = 1;
-    let mut query_builder = sqlx::query_as::<_, ClientStatusRow>(&query).bind(user_id);
-
-    // 3. Add optional conditions
-    if let Some(client_id) = client_id {
-        param_count += 1;
-        query.push_str(&format!(" AND ga.client_id = ${}", param_count));
-        // Create a new query builder with the updated query
-        query_builder = sqlx::query_as::<_, ClientStatusRow>(&query)
-            .bind(user_id)
-            .bind(client_id.parse::<ClientId>()?);
+    filter: &ClientListFilter<'_>,
+) -> Result<()> {
+    query
+        .push(" WHERE ga.user_id = ")
+        .push_bind(user_id.to_string())
+        .push(" AND ga.valid_status = 'valid'");
+    if let Some(client_id) = filter.client_id {
+        query
+            .push(" AND ga.client_id = ")
+            .push_bind(client_id.parse::<ClientId>()?);
     }
-    if let Some(status) = status {
-        param_count += 1;
-        query.push_str(&format!(" AND ga.client_status = ${}", param_count));
-        query_builder = sqlx::query_as::<_, ClientStatusRow>(&query).bind(user_id);
-        if let Some(cid) = client_id {
-            query_builder = query_builder.bind(cid.parse::<ClientId>()?);
-        }
-        query_builder = query_builder.bind(status);
+    if let Some(status) = filter.status {
+        query
+            .push(" AND ga.client_status = ")
+            .push_bind(status.clone());
     }
-    if let Some(valid_status) = valid_status {
-        param_count += 1;
-        query.push_str(&format!(" AND ga.valid_status = ${}", param_count));
-
-        query_builder = sqlx::query_as::<_, ClientStatusRow>(&query).bind(user_id);
-        if let Some(cid) = client_id {
-            query_builder = query_builder.bind(cid.parse::<ClientId>()?);
-        }
-        if let Some(s) = status {
-            query_builder = query_builder.bind(s);
-        }
-        query_builder = query_builder.bind(valid_status);
+    if filter.online_only {
+        query.push(" AND ga.client_status = 'online'");
     }
-    if let Some(name) = name {
-        param_count += 1;
-        query.push_str(&format!(" AND ga.client_name ILIKE ${}", param_count));
-        let mut temp_builder = sqlx::query_as::<_, ClientStatusRow>(&query).bind(user_id);
-
-        if let Some(cid) = client_id {
-            temp_builder = temp_builder.bind(cid.parse::<ClientId>()?);
-        }
-        if let Some(s) = status {
-            temp_builder = temp_builder.bind(s);
-        }
-        if let Some(vs) = valid_status {
-            temp_builder = temp_builder.bind(vs);
-        }
-        query_builder = temp_builder.bind(format!("%{}%", name));
+    if let Some(valid_status) = filter.valid_status {
+        query
+            .push(" AND ga.valid_status = ")
+            .push_bind(valid_status.clone());
+    }
+    if let Some(name) = filter.name {
+        query
+            .push(" AND ga.client_name ILIKE ")
+            .push_bind(format!("%{}%", name));
     }
-    // 4. Execute the query
+    Ok(())
+}
+
+/// A user's clients matching `filter` with their latest system info, and
+/// how many match in all. `page` limits the rows fetched; the count does
+/// not depend on it.
+pub async fn get_user_client_status_list(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+    filter: &ClientListFilter<'_>,
+    page: Option<ListPage>,
+) -> Result<(Vec<ClientDeviceInfo>, usize)> {
+    let mut query = client_list_query(user_id, filter, page)?;
+    let mut count = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM gpu_assets ga");
+    push_client_list_filter(&mut count, user_id, filter)?;
+
     let mut conn = pool
         .acquire()
         .await
         .map_err(|_| anyhow!("Failed to acquire database connection"))?;
 
     // get online info in DB
-    let devices = query_builder
+    let devices = query
+        .build_query_as::<ClientStatusRow>()
         .fetch_all(&mut *conn)
         .await
         .map_err(|e| anyhow!("Failed to fetch user client list: {}", e))?;
+    let total: i64 = count
+        .build_query_scalar()
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| anyhow!("Failed to count user clients: {}", e))?;
 
     let devices: Vec<ClientDeviceInfo> = devices
         .into_iter()
@@ -321,7 +302,65 @@ pub async fn get_user_client_status_list(
         })
         .collect();
 
-    Ok(devices)
+    Ok((devices, total as usize))
+}
+
+/// The client list query of `get_user_client_status_list`, without the count.
+fn client_list_query<'a>(
+    user_id: &str,
+    filter: &ClientListFilter<'_>,
+    page: Option<ListPage>,
+) -> Result<QueryBuilder<'a, Postgres>> {
+    let mut query = QueryBuilder::<Postgres>::new(
+        r#"
+    SELECT 
+        ga.client_id as client_id,
+        ga.client_name as client_name,
+        ga.os_type as os_type,
+        ga.client_status as client_status,
+        ga.created_at::TIMESTAMPTZ as created_at,
+        ga.updated_at::TIMESTAMPTZ as last_online,
+        di.device_name as device_name,
+        si.cpu_usage as cpu_usage,
+        si.mem_usage as memory_usage,
+        si.disk_usage as storage_usage,
+        si.total_tflops as total_tflops,
+        COALESCE((
+            SELECT (ROUND((AVG(LEAST(1.0, total_heartbeats::FLOAT / 720) * 100.0))::numeric, 2))::FLOAT8
+            FROM client_daily_stats 
+            WHERE client_id = ga.client_id
+            GROUP BY client_id
+        ), 0) as health_rate,
+        (
+            SELECT COUNT(*)::INTEGER
+            FROM client_daily_stats
+            WHERE client_id = ga.client_id
+        ) as uptime_days
+    FROM gpu_assets ga
+    LEFT JOIN device_info di ON ga.client_id = di.client_id AND di.device_index = 0
+    LEFT JOIN (
+        SELECT 
+            client_id,
+            cpu_usage,
+            mem_usage,
+            disk_usage,
+            total_tflops,
+            ROW_NUMBER() OVER (PARTITION BY client_id ORDER BY created_at DESC) as rn
+        FROM system_info
+    ) si ON ga.client_id = si.client_id AND si.rn = 1"#,
+    );
+    push_client_list_filter(&mut query, user_id, filter)?;
+    // A stable order keeps pages from overlapping
+    query.push(" ORDER BY ga.created_at, ga.client_id");
+    if let Some(page) = page {
+        let (limit, offset) = page.sql_bounds();
+        query
+            .push(" LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+    }
+    Ok(query)
 }
 
 #[derive(serde::Serialize)]
@@ -635,10 +674,17 @@ pub async fn validate_client(
     let cached_result: Option<String> = redis_conn.get(&cache_key).unwrap_or(None);
     if let Some(model) = cached_result {
         if model == "invalid" {
-            warn!("Client ID  {}  found  invalid in cache.", client_id);
+            warn!(
+                "Client ID  {}  found  invalid in cache.",
+                client_id.log_label()
+            );
             return Ok(false);
         } else {
-            debug!("Client ID  {} found  model {} in cache.", client_id, model);
+            debug!(
+                "Client ID  {} found  model {} in cache.",
+                client_id.log_label(),
+                model
+            );
             return Ok(true);
         }
     }
@@ -668,10 +714,93 @@ pub async fn validate_client(
 
     if let Err(e) = redis_conn.set_ex::<_, _, ()>(&cache_key, valid, 300) {
         warn!(
-            "first pull model is empty Failed to cache result for client {:?}: {}",
-            client_id, e
+            "first pull model is empty Failed to cache result for client {}: {}",
+            client_id.log_label(),
+            e
         );
     }
 
     Ok(is_valid)
 }
+
+#[cfg(feature = "db-tests")]
+#[tokio::test]
+async fn test_client_list_pages_and_counts_in_the_database() {
+    let database_url = std::env::var("GPUF_TEST_DATABASE_URL")
+        .unwrap_or_else(|_| "postgres://postgres@localhost:5432/postgres".to_string());
+    let pool = sqlx::PgPool::connect(&database_url).await.unwrap();
+    let user_id = hex::encode(rand::random::<[u8; 8]>());
+    let clients: Vec<([u8; 16], &str)> = vec![
+        (rand::random(), "online"),
+        (rand::random(), "offline"),
+        (rand::random(), "online"),
+    ];
+    for (client_id, status) in &clients {
+        sqlx::query(
+            "INSERT INTO gpu_assets (user_id, client_id, client_name, client_status) VALUES ($1, $2, 'test', $3)",
+        )
+        .bind(&user_id)
+        .bind(client_id)
+        .bind(status)
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+
+    let page = ListPage {
+        limit: 2,
+        offset: 1,
+    };
+    let all = get_user_client_status_list(&pool, &user_id, &Default::default(), Some(page)).await;
+    let online = ClientListFilter {
+        online_only: true,
+        ..Default::default()
+    };
+    let online = get_user_client_status_list(&pool, &user_id, &online, None).await;
+    for (client_id, _) in &clients {
+        sqlx::query("DELETE FROM gpu_assets WHERE client_id = $1")
+            .bind(client_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+
+    let (devices, total) = all.unwrap();
+    assert_eq!(total, 3);
+    assert_eq!(devices.len(), 2);
+    let (devices, total) = online.unwrap();
+    assert_eq!(total, 2);
+    assert!(devices.iter().all(|d| d.client_status == "online"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_list_query_pages_with_bound_limit_and_offset() {
+        let page = ListPage {
+            limit: 50,
+            offset: 100,
+        };
+        assert_eq!(page.sql_bounds(), (50, 100));
+        let huge = ListPage {
+            limit: usize::MAX,
+            offset: usize::MAX,
+        };
+        assert_eq!(huge.sql_bounds(), (i64::MAX, i64::MAX));
+
+        let filter = ClientListFilter::default();
+        let query = client_list_query("u1", &filter, Some(page)).unwrap();
+        assert!(
+            query
+                .sql()
+                .ends_with(" ORDER BY ga.created_at, ga.client_id LIMIT $2 OFFSET $3"),
+            "{}",
+            query.sql()
+        );
+
+        let query = client_list_query("u1", &filter, None).unwrap();
+        assert!(!query.sql().contains("LIMIT"));
+    }
+}
@@ -155,7 +155,11 @@ async fn get_client_model_impl(pool: &Pool<Postgres>, client_id: &ClientId) -> R
         Some(record) => match (record.model, record.model_version) {
             (Some(model), Some(version)) if !model.is_empty() && !version.is_empty() => {
                 let model_str = format!("{}:{}", model, version);
-                info!("Found model for client {}: {}", client_id, model_str);
+                info!(
+                    "Found model for client {}: {}",
+                    client_id.log_label(),
+                    model_str
+                );
                 Ok(model_str)
             }
             _ => Err(anyhow::anyhow!("Client has no valid model information")),
@@ -56,10 +56,10 @@ impl ClientDailyStats {
         network_in: Option<i64>,
         network_out: Option<i64>,
         timestamp: DateTime<Utc>,
-    ) -> Result<Self, sqlx::Error> {
+    ) -> Result<u64, sqlx::Error> {
         let day = timestamp.date_naive();
 
-        let record = sqlx::query_as(
+        let result = sqlx::query(
             r#"
             INSERT INTO client_daily_stats (
                 date, client_id, 
@@ -116,7 +116,6 @@ impl ClientDailyStats {
                 last_heartbeat = GREATEST(client_daily_stats.last_heartbeat, EXCLUDED.last_heartbeat),
                 last_heartbeat_bucket = GREATEST(client_daily_stats.last_heartbeat_bucket, EXCLUDED.last_heartbeat_bucket),
                 updated_at = NOW()
-            RETURNING *
             "#,
         )
         .bind(day)
@@ -127,10 +126,10 @@ impl ClientDailyStats {
         .bind(network_in)
         .bind(network_out)
         .bind(timestamp)
-        .fetch_one(&mut **tx)
+        .execute(&mut **tx)
         .await?;
 
-        Ok(record)
+        Ok(result.rows_affected())
     }
 
     #[allow(dead_code)] // Get client statistics for date range
@@ -329,6 +328,33 @@ impl DeviceDailyStats {
     }
 }
 
+/// Folds one heartbeat into `client_daily_stats` and `device_daily_stats`.
+/// Rows are keyed by client (and device) and day, and a heartbeat whose
+/// interval bucket was already counted leaves the counters unchanged, so a
+/// redelivered heartbeat is not counted twice. Returns the rows written.
+pub async fn upsert_daily_stats(
+    tx: &mut Transaction<'_, Postgres>,
+    client_id: &ClientId,
+    system_info: &SystemInfo,
+    devices_info: &Vec<DevicesInfo>,
+    timestamp: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    let client_rows = ClientDailyStats::upsert(
+        tx,
+        client_id,
+        Some(system_info.cpu_usage as f64),
+        Some(system_info.memory_usage as f64),
+        Some(system_info.disk_usage as f64),
+        Some(system_info.network_rx.try_into().unwrap_or(0)),
+        Some(system_info.network_tx.try_into().unwrap_or(0)),
+        timestamp,
+    )
+    .await?;
+    let device_rows =
+        DeviceDailyStats::upsert_batch(tx, client_id, devices_info, timestamp).await?;
+    Ok(client_rows + device_rows as u64)
+}
+
 pub async fn insert_heartbeat(
     tx: &mut Transaction<'_, Postgres>,
     client_id: &ClientId,
@@ -510,14 +536,60 @@ pub struct ClientStatResponse {
     pub uptime_rate: i32,
 }
 
-#[derive(sqlx::FromRow)]
-pub struct ClientStatsDb {
+/// A user's clients by state, counted by `online_counts`. Everything but
+/// `warning` counts valid clients only.
+#[derive(Debug, sqlx::FromRow)]
+pub struct OnlineCounts {
     pub total: i64,
-    pub maintenance_count: i64,
-    pub warning_count: i64,
+    /// Clients with a heartbeat within the window, its edge included.
+    pub online: i64,
+    pub maintenance: i64,
+    /// Clients whose `valid_status` is `warning` or `invalid`.
+    pub warning: i64,
     pub total_tflops: i64,
 }
 
+/// Counts `user_id`'s clients, treating those with a heartbeat in the last
+/// `window_secs` as online.
+pub async fn online_counts(
+    pool: &sqlx::PgPool,
+    user_id: &str,
+    window_secs: i64,
+) -> Result<OnlineCounts, sqlx::Error> {
+    online_counts_at(pool, user_id, window_secs, Utc::now()).await
+}
+
+async fn online_counts_at(
+    pool: &sqlx::PgPool,
+    user_id: &str,
+    window_secs: i64,
+    now: DateTime<Utc>,
+) -> Result<OnlineCounts, sqlx::Error> {
+    sqlx::query_as::<_, OnlineCounts>(&format!(
+        "
+        SELECT
+            COUNT(*) FILTER (WHERE ga.valid_status = 'valid') AS total,
+            COUNT(*) FILTER (WHERE ga.valid_status = 'valid' AND EXISTS (
+                SELECT 1 FROM {} h
+                WHERE h.client_id = ga.client_id
+                AND h.timestamp >= $3 - $2 * INTERVAL '1 second'
+            )) AS online,
+            COUNT(*) FILTER (WHERE ga.valid_status = 'valid' AND ga.client_status = 'maintenance') AS maintenance,
+            COUNT(*) FILTER (WHERE ga.valid_status = 'warning' OR ga.valid_status = 'invalid') AS warning,
+            COALESCE(SUM(si.total_tflops) FILTER (WHERE ga.valid_status = 'valid'), 0)::BIGINT AS total_tflops
+        FROM {} ga
+        LEFT JOIN {} si ON ga.client_id = si.client_id
+        WHERE ga.user_id = $1
+        ",
+        HEARTBEAT_TABLE, GPU_ASSETS_TABLE, SYSTEM_INFO_TABLE
+    ))
+    .bind(user_id)
+    .bind(window_secs)
+    .bind(now)
+    .fetch_one(pool)
+    .await
+}
+
 pub async fn get_client_stats(
     pool: &sqlx::PgPool,
     user_id: &str,
@@ -527,43 +599,7 @@ pub async fn get_client_stats(
     let recent_interval = recent_interval.unwrap_or_else(|| time::Duration::minutes(5));
     //let analysis_window = analysis_window.unwrap_or_else(|| time::Duration::hours(24));
 
-    let stats = sqlx::query_as::<_, ClientStatsDb>(
-        &format!("
-        WITH client_stats AS (
-            SELECT 
-                COUNT(*) as total,
-                COUNT(CASE WHEN ga.client_status = 'maintenance' THEN 1 END) as maintenance_count,
-                COUNT(CASE WHEN ga.valid_status = 'warning' OR ga.valid_status = 'invalid' THEN 1 END) as warning_count,
-                COALESCE(SUM(si.total_tflops), 0) as total_tflops
-            FROM {} ga
-            LEFT JOIN {} si ON ga.client_id = si.client_id
-            WHERE ga.user_id = $1 and ga.valid_status = 'valid'
-        )
-        SELECT 
-            total,
-            maintenance_count,
-            warning_count,
-            total_tflops
-        FROM client_stats
-        ", GPU_ASSETS_TABLE, SYSTEM_INFO_TABLE)
-    )
-    .bind(user_id)
-    .fetch_one(pool)
-    .await?;
-
-    let online_count = sqlx::query_scalar(&format!(
-        "
-        SELECT COUNT(DISTINCT ga.client_id)
-        FROM {} ga
-        JOIN {} h ON ga.client_id = h.client_id
-        WHERE ga.user_id = $1 and ga.valid_status = 'valid'
-        AND h.timestamp > NOW() - $2::interval",
-        GPU_ASSETS_TABLE, HEARTBEAT_TABLE
-    ))
-    .bind(user_id)
-    .bind(format!("{} seconds", recent_interval.whole_seconds()))
-    .fetch_one(pool)
-    .await?;
+    let counts = online_counts(pool, user_id, recent_interval.whole_seconds()).await?;
 
     let avg_uptime: f64 = sqlx::query_scalar(&format!(
         "
@@ -581,11 +617,11 @@ pub async fn get_client_stats(
     .await?;
 
     Ok(ClientStatResponse {
-        systems_total_number: stats.total,
-        systems_online_number: online_count,
-        systems_maintenance_number: stats.maintenance_count,
-        systems_warnings_number: stats.warning_count,
-        total_tflops: stats.total_tflops,
+        systems_total_number: counts.total,
+        systems_online_number: counts.online,
+        systems_maintenance_number: counts.maintenance,
+        systems_warnings_number: counts.warning,
+        total_tflops: counts.total_tflops,
         uptime_rate: avg_uptime.round() as i32,
     })
 }
@@ -645,6 +681,141 @@ async fn test_device_daily_stats() {
     assert_eq!(stats[0].avg_memory_usage, Some(1.0));
 }
 
+#[cfg(feature = "db-tests")]
+#[tokio::test]
+async fn test_upsert_daily_stats_accumulates_into_one_row() {
+    use chrono::TimeZone;
+
+    let database_url = std::env::var("GPUF_TEST_DATABASE_URL")
+        .unwrap_or_else(|_| "postgres://postgres@localhost:5432/postgres".to_string());
+    let pool = PgPool::connect(&database_url).await.unwrap();
+    let client_id = ClientId(rand::random());
+    let system_info = SystemInfo {
+        cpu_usage: 40,
+        memory_usage: 50,
+        disk_usage: 60,
+        network_rx: 100,
+        network_tx: 200,
+    };
+    let first = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+    // Two hours on lands in a later bucket whatever the configured interval
+    let later = first + chrono::Duration::hours(2);
+
+    for timestamp in [first, first, later] {
+        let mut tx = pool.begin().await.unwrap();
+        let rows = upsert_daily_stats(&mut tx, &client_id, &system_info, &vec![], timestamp)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!(rows, 1);
+    }
+
+    let day = first.date_naive();
+    let stats = ClientDailyStats::get_stats(&pool, &client_id.0, day, day)
+        .await
+        .unwrap();
+    ClientDailyStats::delete(&pool, &client_id.0, day)
+        .await
+        .unwrap();
+    assert_eq!(stats.len(), 1);
+    // The redelivered first heartbeat was not counted again
+    assert_eq!(stats[0].total_heartbeats, 2);
+    assert_eq!(stats[0].total_network_in_bytes, Some(200));
+    assert_eq!(stats[0].total_network_out_bytes, Some(400));
+    assert_eq!(stats[0].avg_cpu_usage, Some(40.0));
+}
+
+#[cfg(feature = "db-tests")]
+#[tokio::test]
+async fn test_online_counts_include_heartbeat_at_window_edge() {
+    let database_url = std::env::var("GPUF_TEST_DATABASE_URL")
+        .unwrap_or_else(|_| "postgres://postgres@localhost:5432/postgres".to_string());
+    let pool = PgPool::connect(&database_url).await.unwrap();
+    let user_id = hex::encode(rand::random::<[u8; 8]>());
+    let at_edge: [u8; 16] = rand::random();
+    let just_outside: [u8; 16] = rand::random();
+    let now = Utc::now();
+    let window_secs = 120;
+
+    for (client_id, age) in [(at_edge, window_secs), (just_outside, window_secs + 1)] {
+        sqlx::query(&format!(
+            "INSERT INTO {} (user_id, client_id) VALUES ($1, $2)",
+            GPU_ASSETS_TABLE
+        ))
+        .bind(&user_id)
+        .bind(client_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(&format!(
+            "INSERT INTO {} (client_id, timestamp) VALUES ($1, $2)",
+            HEARTBEAT_TABLE
+        ))
+        .bind(client_id)
+        .bind(now - chrono::Duration::seconds(age))
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+
+    let counts = online_counts_at(&pool, &user_id, window_secs, now).await;
+    for client_id in [at_edge, just_outside] {
+        for table in [HEARTBEAT_TABLE, GPU_ASSETS_TABLE] {
+            sqlx::query(&format!("DELETE FROM {} WHERE client_id = $1", table))
+                .bind(client_id)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+    }
+    let counts = counts.unwrap();
+    assert_eq!(counts.total, 2);
+    assert_eq!(counts.online, 1);
+    assert_eq!(counts.maintenance, 0);
+}
+
+#[cfg(feature = "db-tests")]
+#[tokio::test]
+async fn test_online_counts_report_warning_clients() {
+    let database_url = std::env::var("GPUF_TEST_DATABASE_URL")
+        .unwrap_or_else(|_| "postgres://postgres@localhost:5432/postgres".to_string());
+    let pool = PgPool::connect(&database_url).await.unwrap();
+    let user_id = hex::encode(rand::random::<[u8; 8]>());
+    let clients: [([u8; 16], &str); 3] = [
+        (rand::random(), "valid"),
+        (rand::random(), "warning"),
+        (rand::random(), "invalid"),
+    ];
+
+    for (client_id, valid_status) in clients {
+        sqlx::query(&format!(
+            "INSERT INTO {} (user_id, client_id, valid_status) VALUES ($1, $2, $3)",
+            GPU_ASSETS_TABLE
+        ))
+        .bind(&user_id)
+        .bind(client_id)
+        .bind(valid_status)
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+
+    let counts = online_counts_at(&pool, &user_id, 120, Utc::now()).await;
+    for (client_id, _) in clients {
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE client_id = $1",
+            GPU_ASSETS_TABLE
+        ))
+        .bind(client_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+    let counts = counts.unwrap();
+    assert_eq!(counts.total, 1);
+    assert_eq!(counts.warning, 2);
+}
+
 #[derive(Debug, Validate, Serialize, Deserialize)]
 pub struct EditClientRequest {
     #[validate(length(min = 1, max = 255))]
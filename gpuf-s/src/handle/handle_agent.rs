@@ -51,7 +51,10 @@ impl ServerState {
 
         loop {
             let (proxy_stream, addr) = listener.accept().await?;
-            info!("New proxy connection from: {}", addr);
+            info!(
+                "New proxy connection from: {}",
+                common::addr_log_label(&addr)
+            );
             let _ = proxy_stream.set_nodelay(true);
             let acceptor = acceptor.clone();
             let pending_clone = self.pending_connections.clone();
@@ -115,7 +118,10 @@ impl ServerState {
                         );
                     }
                 } else {
-                    error!("Failed to read NewProxyConn command from {}", addr);
+                    error!(
+                        "Failed to read NewProxyConn command from {}",
+                        common::addr_log_label(&addr)
+                    );
                 }
             });
         }
@@ -124,7 +130,10 @@ impl ServerState {
     pub async fn handle_public_connections(self: Arc<Self>, listener: TcpListener) -> Result<()> {
         loop {
             let (user_stream, addr) = listener.accept().await?;
-            info!("New public connection from: {}", addr);
+            info!(
+                "New public connection from: {}",
+                common::addr_log_label(&addr)
+            );
             let active_clients_clone = self.active_clients.clone();
             let pending_connections_clone = self.pending_connections.clone();
             let total_connections_clone = self.total_connections.clone();
@@ -152,7 +161,11 @@ impl ServerState {
                 .await
                 {
                     //send_http_error_response(user_stream, 401, "Invalid API key").await;
-                    error!("Failed to route public connection from {} : {}", addr, e);
+                    error!(
+                        "Failed to route public connection from {} : {}",
+                        common::addr_log_label(&addr),
+                        e
+                    );
                 }
             });
         }
@@ -718,6 +731,47 @@ fn try_parse_chat_info(data: &[u8]) -> io::Result<Option<String>> {
     Ok(model)
 }
 
+/// Path prefix that pins a public request to one worker:
+/// `/workers/<client_id>/v1/chat/completions` is sent to that worker's local
+/// server as `/v1/chat/completions`.
+pub const WORKER_ROUTE_PREFIX: &str = "/workers/";
+
+/// Strips a `/workers/<client_id>` prefix from the request line in `buffer`
+/// and returns the pinned worker. Requests without the prefix are left alone.
+fn take_worker_route(buffer: &mut BytesMut) -> Result<Option<ClientId>> {
+    let line_end =
+        twoway::find_bytes(buffer, b"\r\n").ok_or_else(|| anyhow!("Missing HTTP request line"))?;
+    let line = std::str::from_utf8(&buffer[..line_end])?;
+    let mut parts = line.splitn(3, ' ');
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(anyhow!("Malformed HTTP request line"));
+    };
+    let Some(rest) = target.strip_prefix(WORKER_ROUTE_PREFIX) else {
+        return Ok(None);
+    };
+
+    let (client_id, path) = match rest.find(['/', '?']) {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, ""),
+    };
+    let client_id = client_id
+        .parse::<ClientId>()
+        .map_err(|_| anyhow!("Invalid worker id in request path"))?;
+    let path = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{}", path)
+    };
+
+    let request_line = format!("{} {} {}", method, path, version);
+    let mut rewritten = BytesMut::with_capacity(buffer.len());
+    rewritten.extend_from_slice(request_line.as_bytes());
+    rewritten.extend_from_slice(&buffer[line_end..]);
+    *buffer = rewritten;
+    Ok(Some(client_id))
+}
+
 pub async fn send_http_error_response(
     mut stream: TcpStream,
     status_code: u16,
@@ -731,7 +785,9 @@ pub async fn send_http_error_response(
         400 => "Bad Request",
         401 => "Unauthorized",
         403 => "Forbidden",
+        404 => "Not Found",
         500 => "Internal Server Error",
+        503 => "Service Unavailable",
         _ => "Error",
     };
 
@@ -765,6 +821,15 @@ async fn route_public_connection_new(
         }
     };
 
+    let pinned_worker = match take_worker_route(&mut buffer) {
+        Ok(worker) => worker,
+        Err(e) => {
+            buffer_pool.put(buffer).await;
+            send_http_error_response(user_stream, 400, &e.to_string()).await?;
+            return Err(e);
+        }
+    };
+
     // debug!("Request Parsing Module - Handle HTTP request parsing and validation chat_info {:?}", chat_info);
     // Validate model and request_id
     if chat_info.model.is_none() || chat_info.api_key.is_none() {
@@ -802,6 +867,34 @@ async fn route_public_connection_new(
         return Err(anyhow::anyhow!("No available clients"));
     }
 
+    // A pinned worker must be one the API key could have been routed to anyway
+    let client_ids = match pinned_worker {
+        Some(worker) if !client_ids.contains(&worker) => {
+            buffer_pool.put(buffer).await;
+            send_http_error_response(user_stream, 404, "Worker not available for this API key")
+                .await?;
+            return Err(anyhow::anyhow!(
+                "Worker {} not available for this API key",
+                worker.log_label()
+            ));
+        }
+        Some(worker) => vec![worker],
+        None => client_ids,
+    };
+
+    // Park the public connection before asking a worker for its proxy
+    // connection, so a worker that connects back at once finds it
+    let proxy_conn_id = ProxyConnId(*Uuid::new_v4().as_bytes());
+    let inserted = pending_connections
+        .lock()
+        .await
+        .insert(proxy_conn_id, user_stream, buffer);
+    if let Err((user_stream, buffer)) = inserted {
+        buffer_pool.put(buffer).await;
+        send_http_error_response(user_stream, 503, "Too many pending connections").await?;
+        return Err(anyhow::anyhow!("Pending connection pool is full"));
+    }
+
     // Route public connection to chosen client
     debug!("Route public connection to chosen client");
     let mut active_clients = active_clients.lock().await;
@@ -810,19 +903,20 @@ async fn route_public_connection_new(
         chat_info.model.as_ref().unwrap(),
         client_ids,
         &mut active_clients,
+        proxy_conn_id,
     )
     .await
     {
-        Ok((chosen_client_id, chosen_client_proxy_conn_id)) => {
-            pending_connections
-                .lock()
-                .await
-                .insert(chosen_client_proxy_conn_id, (user_stream, buffer));
-            chosen_client_id
-        }
+        Ok(chosen_client_id) => chosen_client_id,
         Err(e) => {
-            buffer_pool.put(buffer).await;
-            send_http_error_response(user_stream, 400, "No available clients").await?;
+            drop(active_clients);
+            // Gone already if the reaper evicted it meanwhile
+            if let Some((user_stream, buffer)) =
+                pending_connections.lock().await.remove(&proxy_conn_id)
+            {
+                buffer_pool.put(buffer).await;
+                send_http_error_response(user_stream, 400, "No available clients").await?;
+            }
             return Err(anyhow::anyhow!("No available clients {}", e));
         }
     };
@@ -842,11 +936,14 @@ async fn route_public_connection_new(
     }
 }
 
+/// Asks the first of `client_ids` serving `model_name` to open proxy
+/// connection `proxy_conn_id`, and returns that client.
 pub async fn connect_client_filter_model_and_client(
     model_name: &str,
     client_ids: Vec<ClientId>,
     clients: &mut HashMap<ClientId, ClientInfo>,
-) -> Result<(ClientId, ProxyConnId)> {
+    proxy_conn_id: ProxyConnId,
+) -> Result<ClientId> {
     let chosen_client: Option<(&ClientInfo, ClientId)> =
         client_ids.into_iter().find_map(|client_id| {
             if let Some(client_info) = clients.get(&client_id) {
@@ -863,8 +960,9 @@ pub async fn connect_client_filter_model_and_client(
             if !client_info.authed {
                 return Err(anyhow!("Chosen client not authenticated"));
             }
-            let proxy_conn_id = Uuid::new_v4().as_bytes().clone();
-            let command = Command::V1(CommandV1::RequestNewProxyConn { proxy_conn_id });
+            let command = Command::V1(CommandV1::RequestNewProxyConn {
+                proxy_conn_id: proxy_conn_id.0,
+            });
 
             info!(
                 "Requesting new proxy connection with id: {:?}",
@@ -875,7 +973,7 @@ pub async fn connect_client_filter_model_and_client(
             if let Err(e) = write_command(&mut *writer, &command).await {
                 error!(
                 "Failed to send RequestNewProxyConn to client {}: {}. Removing from active list.",
-                client_id, e
+                client_id.log_label(), e
                 );
                 drop(writer);
                 clients.remove(&client_id);
@@ -883,9 +981,9 @@ pub async fn connect_client_filter_model_and_client(
             }
             info!(
                 "Successfully sent RequestNewProxyConn to client {}",
-                client_id
+                client_id.log_label()
             );
-            Ok((client_id, ProxyConnId(proxy_conn_id)))
+            Ok(client_id)
         }
         None => {
             error!("Chosen client disappeared");
@@ -923,3 +1021,45 @@ async fn request_to_kafka(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORKER: &str = "0123456789abcdef0123456789abcdef";
+
+    fn request(line: &str) -> BytesMut {
+        BytesMut::from(
+            format!(
+                "{}\r\nHost: gpuf\r\nContent-Type: application/json\r\n\r\n{{\"model\":\"m\"}}",
+                line
+            )
+            .as_bytes(),
+        )
+    }
+
+    #[test]
+    fn worker_route_is_stripped_before_forwarding() {
+        let mut buffer = request(&format!(
+            "POST /workers/{}/v1/chat/completions HTTP/1.1",
+            WORKER
+        ));
+        let worker = take_worker_route(&mut buffer).unwrap();
+        assert_eq!(worker, Some(WORKER.parse().unwrap()));
+        assert_eq!(buffer, request("POST /v1/chat/completions HTTP/1.1"));
+
+        let mut buffer = request(&format!("GET /workers/{}?x=1 HTTP/1.1", WORKER));
+        assert!(take_worker_route(&mut buffer).unwrap().is_some());
+        assert_eq!(buffer, request("GET /?x=1 HTTP/1.1"));
+    }
+
+    #[test]
+    fn unpinned_requests_are_untouched() {
+        let mut buffer = request("POST /v1/completions HTTP/1.1");
+        assert_eq!(take_worker_route(&mut buffer).unwrap(), None);
+        assert_eq!(buffer, request("POST /v1/completions HTTP/1.1"));
+
+        let mut buffer = request("POST /workers/not-hex/v1/completions HTTP/1.1");
+        assert!(take_worker_route(&mut buffer).is_err());
+    }
+}
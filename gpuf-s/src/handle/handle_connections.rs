@@ -56,7 +56,7 @@ impl ServerState {
             let (stream, addr) = listener.accept().await?;
             info!(
                 "New control connection from: {} (tls={})",
-                addr,
+                common::addr_log_label(&addr),
                 acceptor.is_some()
             );
             if let Err(_e) = set_keepalive(&stream) {
@@ -64,13 +64,7 @@ impl ServerState {
                 continue;
             }
 
-            let active_clients_clone = self.active_clients.clone();
-            let db_pool_clone = self.db_pool.clone();
-            let redis_client_clone = self.redis_client.clone();
-            let client_models = self.client_model.clone();
-            let hot_models = self.hot_models.clone();
-            let producer: Arc<FutureProducer> = self.producer.clone();
-            let server_state_clone = self.clone();
+            let server_state = self.clone();
             let acceptor = acceptor.clone();
             tokio::spawn(async move {
                 let streams: Result<(
@@ -92,30 +86,51 @@ impl ServerState {
                 let (reader, writer) = match streams {
                     Ok(streams) => streams,
                     Err(e) => {
-                        error!("Error preparing control stream {}: {}", addr, e);
+                        error!(
+                            "Error preparing control stream {}: {}",
+                            common::addr_log_label(&addr),
+                            e
+                        );
                         return;
                     }
                 };
 
-                if let Err(e) = handle_single_client(
-                    reader,
-                    writer,
-                    addr,
-                    active_clients_clone,
-                    client_models,
-                    hot_models,
-                    db_pool_clone,
-                    producer,
-                    redis_client_clone,
-                    server_state_clone,
-                )
-                .await
+                if let Err(e) = server_state
+                    .serve_control_stream(reader, writer, addr)
+                    .await
                 {
-                    error!("Error handling client {}: {}", addr, e);
+                    error!(
+                        "Error handling client {}: {}",
+                        common::addr_log_label(&addr),
+                        e
+                    );
                 }
             });
         }
     }
+
+    /// Runs the control protocol on an accepted worker connection until the
+    /// worker disconnects.
+    pub(crate) async fn serve_control_stream(
+        self: Arc<Self>,
+        reader: Box<dyn AsyncRead + Send + Unpin>,
+        writer: Box<dyn AsyncWrite + Send + Unpin>,
+        addr: std::net::SocketAddr,
+    ) -> Result<()> {
+        handle_single_client(
+            reader,
+            writer,
+            addr,
+            self.active_clients.clone(),
+            self.client_model.clone(),
+            self.hot_models.clone(),
+            self.db_pool.clone(),
+            self.producer.clone(),
+            self.redis_client.clone(),
+            self,
+        )
+        .await
+    }
 }
 
 #[cfg(unix)]
@@ -160,6 +175,7 @@ async fn handle_single_client(
 
     let mut authed = false;
     let mut session_client_id = ClientId([0; 16]);
+    let mut last_heartbeat: Option<HeartbeatBaseline> = None;
     let mut buf = BytesMut::with_capacity(1024 * 1024);
 
     loop {
@@ -219,7 +235,19 @@ async fn handle_single_client(
                 };
                 session_client_id = ClientId(id);
 
-                write_command(&mut *writer.lock().await, &Command::V1(validate_result)).await?;
+                let logged_in = matches!(
+                    validate_result,
+                    CommandV1::LoginResult { success: true, .. }
+                );
+                let mut writer = writer.lock().await;
+                write_command(&mut *writer, &Command::V1(validate_result)).await?;
+                // Tells the worker which commands it may send us
+                let server_revision = Command::V1(CommandV1::ServerRevision {
+                    protocol_revision: common::PROTOCOL_REVISION,
+                });
+                if logged_in && server_revision.supported_by(version) {
+                    write_command(&mut *writer, &server_revision).await?;
+                }
             }
             // Device system status from client to server 120s
             Ok(Command::V1(CommandV1::Heartbeat {
@@ -234,6 +262,13 @@ async fn handle_single_client(
                     "Heartbeat received from client {}",
                     ClientId(id).log_label()
                 );
+                record_heartbeat(&active_clients, &session_client_id).await;
+                last_heartbeat = Some(HeartbeatBaseline {
+                    device_memtotal_gb,
+                    device_total_tflops,
+                    device_count,
+                    devices_info: devices_info.clone(),
+                });
                 handle_heartbeat(
                     &producer,
                     &ClientId(id),
@@ -245,6 +280,57 @@ async fn handle_single_client(
                 )
                 .await;
             }
+            Ok(Command::V1(CommandV1::HeartbeatLite {
+                client_id: id,
+                system_info,
+                devices_usage,
+            })) => {
+                record_heartbeat(&active_clients, &session_client_id).await;
+                let Some(baseline) = last_heartbeat.as_mut() else {
+                    warn!(
+                        "Lite heartbeat from client {} before any full heartbeat, ignoring",
+                        ClientId(id).log_label()
+                    );
+                    continue;
+                };
+                debug!(
+                    "Lite heartbeat received from client {} ({} changed devices)",
+                    ClientId(id).log_label(),
+                    devices_usage.len()
+                );
+                common::apply_device_usage(&mut baseline.devices_info, &devices_usage);
+                handle_heartbeat(
+                    &producer,
+                    &ClientId(id),
+                    system_info,
+                    baseline.devices_info.clone(),
+                    baseline.device_memtotal_gb,
+                    baseline.device_count as u32,
+                    baseline.device_total_tflops,
+                )
+                .await;
+            }
+            // Both apply to the logged-in session, whatever client id they carry
+            Ok(Command::V1(CommandV1::Capabilities { quant_types, .. })) => {
+                info!(
+                    "Client {} supports quantizations {:?}",
+                    session_client_id.log_label(),
+                    quant_types
+                );
+                if let Some(client) = active_clients.lock().await.get_mut(&session_client_id) {
+                    client.quant_types = Some(quant_types);
+                }
+            }
+            Ok(Command::V1(CommandV1::ContextWindow { n_ctx, .. })) => {
+                info!(
+                    "Client {} has a context window of {} tokens",
+                    session_client_id.log_label(),
+                    n_ctx
+                );
+                if let Some(client) = active_clients.lock().await.get_mut(&session_client_id) {
+                    client.n_ctx = Some(n_ctx);
+                }
+            }
             // Device model status from client to server 300s
             Ok(Command::V1(CommandV1::ModelStatus {
                 client_id: id,
@@ -283,8 +369,12 @@ async fn handle_single_client(
                 write_command(&mut *writer.lock().await, &Command::V1(pods_model)).await?;
             }
             Err(e) => {
-                info!("addr {} disconnected: {}", addr, e);
+                info!("addr {} disconnected: {}", common::addr_log_label(&addr), e);
                 active_clients.lock().await.remove(&session_client_id);
+                server_state
+                    .inference_scheduler
+                    .worker_disconnected(&session_client_id)
+                    .await;
                 client::upsert_client_status(&db_pool, &session_client_id, "offline").await?;
                 return Ok(());
             }
@@ -345,6 +435,16 @@ async fn handle_single_client(
                     .await;
             }
 
+            Ok(Command::V1(CommandV1::WorkerDescription {
+                request_id,
+                description,
+            })) => {
+                server_state
+                    .inference_scheduler
+                    .handle_worker_description(&session_client_id, request_id, description)
+                    .await;
+            }
+
             Ok(Command::V1(CommandV1::ModelDownloadProgress {
                 client_id: id,
                 model_name,
@@ -556,7 +656,10 @@ async fn handle_single_client(
                 write_command(&mut *target_writer.lock().await, &forward).await?;
             }
             _ => {
-                warn!("Received unexpected command from client addr {}", addr);
+                warn!(
+                    "Received unexpected command from client addr {}",
+                    common::addr_log_label(&addr)
+                );
             }
         }
     }
@@ -649,6 +752,8 @@ async fn handle_login(
             connected_at: Utc::now(),
             models: None,
             devices_info,
+            quant_types: None,
+            n_ctx: None,
         },
     );
     Ok(validate_result)
@@ -664,6 +769,12 @@ async fn handle_models_status(
     //TODO: push msg-> api filter
     let mut clients = active_clients.lock().await;
     if let Some(client) = clients.get_mut(client_id) {
+        if models.is_empty() && client.models.as_ref().is_some_and(|m| !m.is_empty()) {
+            info!(
+                "Client {} unloaded its models, no longer routing to it",
+                client_id.log_label()
+            );
+        }
         client.models = Some(models);
     }
 
@@ -726,6 +837,15 @@ async fn upsert_client_models_in_redis(
     let _: std::result::Result<(), _> = conn.expire(&key, 300).await;
 }
 
+/// Static part of the last full heartbeat on a connection, used to fill in
+/// the fields a `HeartbeatLite` leaves out.
+struct HeartbeatBaseline {
+    device_memtotal_gb: u32,
+    device_total_tflops: u32,
+    device_count: u16,
+    devices_info: Vec<DevicesInfo>,
+}
+
 async fn handle_heartbeat(
     producer: &Arc<FutureProducer>,
     client_id: &ClientId,
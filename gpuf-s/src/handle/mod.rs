@@ -2,7 +2,7 @@ pub mod handle_agent;
 pub mod handle_connections;
 
 use crate::db::{models::ClientModelClass, models::HotModelClass};
-use crate::inference::InferenceScheduler;
+use crate::inference::{circuit_breaker::BreakerConfig, InferenceScheduler};
 use crate::util::pack::BufferPool;
 use crate::util::{
     cmd, db,
@@ -12,7 +12,9 @@ use crate::util::{
 use anyhow::{anyhow, Result};
 use bytes::BytesMut;
 use chrono::{DateTime, Utc};
-use common::{join_streams, read_command, write_command, Command, CommandV1, DevicesInfo, Model};
+use common::{
+    join_streams, read_command, write_command, Command, CommandV1, DevicesInfo, Model, QuantType,
+};
 use rdkafka::producer::FutureProducer;
 use rdkafka::producer::Producer;
 use redis::Client as RedisClient;
@@ -20,16 +22,18 @@ use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
 use std::collections::HashMap;
 use std::sync::{Arc, Once};
-use tokio::io::AsyncWrite;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use tracing::{error, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
 pub type UserDb = Arc<Mutex<HashMap<String, User>>>;
 pub type TokenDb = Arc<Mutex<HashMap<String, String>>>;
 pub type ActiveClients = Arc<Mutex<HashMap<ClientId, ClientInfo>>>;
-pub type PendingConnections = Arc<Mutex<HashMap<ProxyConnId, (TcpStream, BytesMut)>>>;
+pub type PendingConnections = Arc<Mutex<PendingPool>>;
 pub type ControlWriter = Box<dyn AsyncWrite + Send + Unpin>;
 
 pub fn install_rustls_crypto_provider_once() {
@@ -49,7 +53,7 @@ pub fn install_rustls_crypto_provider_once() {
 pub struct ClientInfo {
     pub writer: Arc<Mutex<ControlWriter>>,
     pub authed: bool,
-    #[allow(dead_code)] // Client protocol version
+    /// `common::PROTOCOL_REVISION` the client reported at login.
     pub version: u32,
     pub system_info: Option<SystemInfo>,
     #[allow(dead_code)] // Connected devices information
@@ -57,6 +61,24 @@ pub struct ClientInfo {
     #[allow(dead_code)] // Connection timestamp
     pub connected_at: DateTime<Utc>,
     pub models: Option<Vec<Model>>,
+    /// Quantizations reported via `Capabilities`; `None` for clients that
+    /// never sent it, which are assumed to handle every quantization.
+    pub quant_types: Option<Vec<QuantType>>,
+    /// Context window in tokens reported via `ContextWindow`; `None` until
+    /// the client sent one, in which case no request is refused for size.
+    pub n_ctx: Option<u32>,
+}
+
+impl ClientInfo {
+    pub fn supports_quant(&self, quant: QuantType) -> bool {
+        self.quant_types
+            .as_ref()
+            .is_none_or(|quant_types| quant_types.contains(&quant))
+    }
+
+    pub fn fits_context(&self, tokens: u32) -> bool {
+        self.n_ctx.is_none_or(|n_ctx| tokens <= n_ctx)
+    }
 }
 
 pub struct User {
@@ -75,13 +97,115 @@ pub struct SystemInfo {
     pub memsize_gb: u32,
 }
 
-#[allow(dead_code)]
-#[derive(Serialize)]
-struct ServerStats {
-    active_clients: usize,
-    pending_connections: usize,
-    total_connections: u64,
-    uptime_seconds: u64,
+/// Connection statistics of the server, exported on the gateway's
+/// `/metrics`.
+#[derive(Debug, Serialize)]
+pub struct ServerStats {
+    pub active_clients: usize,
+    /// Public connections waiting in `PendingPool` for a proxy connection.
+    pub pending_connections: usize,
+    pub total_connections: u64,
+    pub uptime_seconds: u64,
+}
+
+struct PendingEntry {
+    stream: TcpStream,
+    buf: BytesMut,
+    inserted_at: Instant,
+}
+
+/// Public connections waiting for the chosen worker to open its proxy
+/// connection, keyed by the id sent in `RequestNewProxyConn`. Entries are
+/// taken once; ones the worker never claims are evicted after `ttl`.
+pub struct PendingPool {
+    entries: HashMap<ProxyConnId, PendingEntry>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl PendingPool {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            capacity,
+            ttl,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.entries.len() >= self.capacity
+    }
+
+    /// Parks a public connection; hands it back when the pool is full.
+    pub fn insert(
+        &mut self,
+        proxy_conn_id: ProxyConnId,
+        stream: TcpStream,
+        buf: BytesMut,
+    ) -> Result<(), (TcpStream, BytesMut)> {
+        self.insert_at(proxy_conn_id, stream, buf, Instant::now())
+    }
+
+    fn insert_at(
+        &mut self,
+        proxy_conn_id: ProxyConnId,
+        stream: TcpStream,
+        buf: BytesMut,
+        now: Instant,
+    ) -> Result<(), (TcpStream, BytesMut)> {
+        if self.is_full() {
+            return Err((stream, buf));
+        }
+        self.entries.insert(
+            proxy_conn_id,
+            PendingEntry {
+                stream,
+                buf,
+                inserted_at: now,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn remove(&mut self, proxy_conn_id: &ProxyConnId) -> Option<(TcpStream, BytesMut)> {
+        self.entries
+            .remove(proxy_conn_id)
+            .map(|entry| (entry.stream, entry.buf))
+    }
+
+    /// Drops entries older than the pool's TTL, closing their streams.
+    /// Returns the evicted ids with their buffers, which belong back in the
+    /// `BufferPool` they came from.
+    pub fn evict_stale(&mut self) -> Vec<(ProxyConnId, BytesMut)> {
+        self.evict_stale_at(Instant::now())
+    }
+
+    fn evict_stale_at(&mut self, now: Instant) -> Vec<(ProxyConnId, BytesMut)> {
+        let ttl = self.ttl;
+        let stale: Vec<ProxyConnId> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| now.saturating_duration_since(entry.inserted_at) > ttl)
+            .map(|(proxy_conn_id, _)| *proxy_conn_id)
+            .collect();
+        stale
+            .into_iter()
+            .filter_map(|proxy_conn_id| {
+                self.entries
+                    .remove(&proxy_conn_id)
+                    .map(|entry| (proxy_conn_id, entry.buf))
+            })
+            .collect()
+    }
 }
 
 #[derive(Serialize, Clone)]
@@ -120,6 +244,17 @@ pub struct ServerState {
     pub buffer_pool: Arc<BufferPool>,
 }
 
+impl ServerState {
+    pub async fn stats(&self) -> ServerStats {
+        ServerStats {
+            active_clients: self.active_clients.lock().await.len(),
+            pending_connections: self.pending_connections.lock().await.len(),
+            total_connections: *self.total_connections.lock().await,
+            uptime_seconds: (Utc::now() - self.server_start_time).num_seconds().max(0) as u64,
+        }
+    }
+}
+
 impl Drop for ServerState {
     fn drop(&mut self) {
         if let Err(e) = self.producer.flush(std::time::Duration::from_secs(1)) {
@@ -136,7 +271,10 @@ struct ChatCompletionRequest {
     model: String,
 }
 
-pub async fn new_server_state(args: &cmd::Args) -> Result<ServerState, anyhow::Error> {
+pub async fn new_server_state(
+    args: &cmd::Args,
+    shutdown: CancellationToken,
+) -> Result<ServerState, anyhow::Error> {
     // check cert chain path
     let cert_chain_path = args.proxy_cert_chain_path.clone();
     if std::path::Path::new(&cert_chain_path).exists() {
@@ -166,16 +304,32 @@ pub async fn new_server_state(args: &cmd::Args) -> Result<ServerState, anyhow::E
     ) = db::init_db(&args.bootstrap_server, &args.database_url, &args.redis_url).await?;
 
     let active_clients = Arc::new(Mutex::new(HashMap::new()));
-    let pending_connections = Arc::new(Mutex::new(HashMap::new()));
+    let pending_ttl = Duration::from_secs(args.pending_conn_ttl_secs.max(1));
+    let pending_connections = Arc::new(Mutex::new(PendingPool::new(
+        args.max_pending_connections,
+        pending_ttl,
+    )));
     let user_db = Arc::new(Mutex::new(HashMap::<String, User>::new()));
     let token_db = Arc::new(Mutex::new(HashMap::new()));
     let total_connections = Arc::new(Mutex::new(0u64));
     let server_start_time = Utc::now();
+    let buffer_pool = Arc::new(BufferPool::new(8 * 1024, 16));
     let cert_chain = crate::util::load_certs(&args.proxy_cert_chain_path)?;
     let priv_key = crate::util::load_private_key(&args.proxy_private_key_path)?;
 
     // Initialize inference scheduler
-    let inference_scheduler = Arc::new(InferenceScheduler::new(active_clients.clone()));
+    let inference_scheduler = Arc::new(
+        InferenceScheduler::new(
+            active_clients.clone(),
+            BreakerConfig {
+                failure_threshold: args.breaker_failure_threshold.max(1),
+                window: std::time::Duration::from_secs(args.breaker_window_secs),
+                cooldown: std::time::Duration::from_secs(args.breaker_cooldown_secs),
+            },
+        )
+        .with_latency_tie_break(!args.no_latency_tie_break)
+        .with_shutdown(shutdown),
+    );
 
     let app_state = ServerState {
         active_clients: active_clients.clone(),
@@ -191,7 +345,7 @@ pub async fn new_server_state(args: &cmd::Args) -> Result<ServerState, anyhow::E
             api_port: args.api_port,
             control_tls: args.control_tls,
         },
-        buffer_pool: Arc::new(BufferPool::new(8 * 1024, 16)),
+        buffer_pool: buffer_pool.clone(),
         db_pool: db_pool.clone(),
         redis_client: redis_client.clone(),
         producer: producer.clone(),
@@ -199,8 +353,18 @@ pub async fn new_server_state(args: &cmd::Args) -> Result<ServerState, anyhow::E
         priv_key: Arc::new(priv_key),
         hot_models: Arc::new(HotModelClass::new(db_pool.clone())),
         client_model: Arc::new(ClientModelClass::new(db_pool.clone())),
-        inference_scheduler,
+        inference_scheduler: inference_scheduler.clone(),
     };
+    if args.client_timeout_secs > 0 {
+        spawn_client_reaper(
+            active_clients.clone(),
+            inference_scheduler,
+            Duration::from_secs(args.client_timeout_secs),
+        );
+    }
+
+    spawn_pending_reaper(pending_connections.clone(), buffer_pool, pending_ttl);
+
     // If monitor flag is set, just print monitoring data and exit
     if args.monitor {
         print_monitoring_data(active_clients.clone()).await;
@@ -208,6 +372,116 @@ pub async fn new_server_state(args: &cmd::Args) -> Result<ServerState, anyhow::E
     Ok(app_state)
 }
 
+/// Periodically drops clients that have not sent a heartbeat for `timeout`;
+/// see `reap_stale_clients`. Tasks running on a dropped client are failed in
+/// `scheduler` so their requests stop waiting on it.
+pub fn spawn_client_reaper(
+    active_clients: ActiveClients,
+    scheduler: Arc<InferenceScheduler>,
+    timeout: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval((timeout / 4).max(Duration::from_secs(1)));
+        loop {
+            interval.tick().await;
+            for client_id in reap_stale_clients(&active_clients, timeout).await {
+                scheduler.worker_disconnected(&client_id).await;
+            }
+        }
+    })
+}
+
+/// Periodically evicts public connections no worker claimed within `ttl`,
+/// returning their buffers to `buffer_pool`.
+pub fn spawn_pending_reaper(
+    pending_connections: PendingConnections,
+    buffer_pool: Arc<BufferPool>,
+    ttl: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval((ttl / 4).max(Duration::from_secs(1)));
+        loop {
+            interval.tick().await;
+            let evicted = pending_connections.lock().await.evict_stale();
+            for (proxy_conn_id, buf) in evicted {
+                warn!(
+                    "Closing public connection {:?}: no proxy connection within {}s",
+                    proxy_conn_id,
+                    ttl.as_secs()
+                );
+                buffer_pool.put(buf).await;
+            }
+        }
+    })
+}
+
+/// Marks `client_id` as alive now so `reap_stale_clients` keeps it.
+pub async fn record_heartbeat(active_clients: &ActiveClients, client_id: &ClientId) {
+    if let Some(system_info) = active_clients
+        .lock()
+        .await
+        .get_mut(client_id)
+        .and_then(|client| client.system_info.as_mut())
+    {
+        system_info.last_heartbeat = std::time::SystemTime::now();
+    }
+}
+
+/// Removes clients whose last heartbeat, or connection time if they never sent
+/// one, is older than `timeout`, and shuts down their control writer so the
+/// peer sees the connection close. Returns the evicted client ids.
+pub async fn reap_stale_clients(
+    active_clients: &ActiveClients,
+    timeout: Duration,
+) -> Vec<ClientId> {
+    let now = std::time::SystemTime::now();
+    let evicted: Vec<(ClientId, ClientInfo)> = {
+        let mut clients = active_clients.lock().await;
+        let stale: Vec<ClientId> = clients
+            .iter()
+            .filter(|(_, info)| {
+                let last_seen = info
+                    .system_info
+                    .as_ref()
+                    .map(|s| s.last_heartbeat)
+                    .unwrap_or_else(|| info.connected_at.into());
+                now.duration_since(last_seen).unwrap_or_default() > timeout
+            })
+            .map(|(client_id, _)| *client_id)
+            .collect();
+        stale
+            .into_iter()
+            .filter_map(|client_id| clients.remove(&client_id).map(|info| (client_id, info)))
+            .collect()
+    };
+
+    // Shut writers down outside the map lock; a dead peer may stall the flush.
+    for (client_id, info) in &evicted {
+        warn!(
+            "Evicting client {}: no heartbeat for over {}s",
+            client_id.log_label(),
+            timeout.as_secs()
+        );
+        let shutdown = async { info.writer.lock().await.shutdown().await };
+        match tokio::time::timeout(Duration::from_secs(5), shutdown).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!(
+                "Failed to close writer of client {}: {}",
+                client_id.log_label(),
+                e
+            ),
+            Err(_) => warn!(
+                "Timed out closing writer of client {}",
+                client_id.log_label()
+            ),
+        }
+    }
+    evicted
+        .into_iter()
+        .map(|(client_id, _)| client_id)
+        .collect()
+}
+
 pub async fn print_monitoring_data(active_clients: ActiveClients) {
     let clients = active_clients.lock().await;
     if clients.is_empty() {
@@ -231,7 +505,7 @@ pub async fn print_monitoring_data(active_clients: ActiveClients) {
             let seconds = duration.as_secs();
             println!(
                 "{:<20} {:<10.2} {:<10.2} {:<10.2} {:<20}",
-                client_id,
+                client_id.log_label(),
                 sys_info.cpu_usage,
                 sys_info.memory_usage,
                 sys_info.disk_usage,
@@ -240,7 +514,11 @@ pub async fn print_monitoring_data(active_clients: ActiveClients) {
         } else {
             println!(
                 "{:<20} {:<10} {:<10} {:<10} {:<20}",
-                client_id, "N/A", "N/A", "N/A", "No data"
+                client_id.log_label(),
+                "N/A",
+                "N/A",
+                "N/A",
+                "No data"
             );
         }
     }
@@ -257,3 +535,176 @@ pub struct ClientStatResponse {
     pub total_tflops: i64,
     pub uptime_rate: i32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    fn client(writer: ControlWriter, heartbeat_age: Duration) -> ClientInfo {
+        ClientInfo {
+            writer: Arc::new(Mutex::new(writer)),
+            authed: true,
+            version: 1,
+            system_info: Some(SystemInfo {
+                cpu_usage: 0,
+                memory_usage: 0,
+                disk_usage: 0,
+                device_memsize: 0,
+                total_tflops: 0,
+                memsize_gb: 0,
+                last_heartbeat: std::time::SystemTime::now() - heartbeat_age,
+            }),
+            devices_info: Vec::new(),
+            connected_at: Utc::now(),
+            models: None,
+            quant_types: None,
+            n_ctx: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn reaper_evicts_only_stale_clients_and_closes_their_writer() {
+        let (stale_writer, mut stale_peer) = tokio::io::duplex(64);
+        let stale = ClientId([1; 16]);
+        let fresh = ClientId([2; 16]);
+        let active_clients: ActiveClients = Arc::new(Mutex::new(HashMap::from([
+            (
+                stale,
+                client(Box::new(stale_writer), Duration::from_secs(400)),
+            ),
+            (
+                fresh,
+                client(Box::new(tokio::io::sink()), Duration::from_secs(10)),
+            ),
+        ])));
+
+        let evicted = reap_stale_clients(&active_clients, Duration::from_secs(360)).await;
+        assert_eq!(evicted, vec![stale]);
+        let clients = active_clients.lock().await;
+        assert!(!clients.contains_key(&stale));
+        assert!(clients.contains_key(&fresh));
+
+        // The evicted client's connection reads as closed
+        let mut buf = [0u8; 8];
+        assert_eq!(stale_peer.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn heartbeating_client_survives_reap_after_timeout() {
+        let worker = ClientId([3; 16]);
+        // Logged in long ago, well past the timeout
+        let active_clients: ActiveClients = Arc::new(Mutex::new(HashMap::from([(
+            worker,
+            client(Box::new(tokio::io::sink()), Duration::from_secs(400)),
+        )])));
+
+        record_heartbeat(&active_clients, &worker).await;
+        let evicted = reap_stale_clients(&active_clients, Duration::from_secs(360)).await;
+        assert!(evicted.is_empty());
+        assert!(active_clients.lock().await.contains_key(&worker));
+    }
+
+    #[tokio::test]
+    async fn reaper_fails_tasks_of_the_workers_it_evicts() {
+        let worker = ClientId([4; 16]);
+        let active_clients: ActiveClients = Arc::new(Mutex::new(HashMap::from([(
+            worker,
+            client(Box::new(tokio::io::sink()), Duration::from_secs(400)),
+        )])));
+        let scheduler = Arc::new(InferenceScheduler::new(
+            active_clients.clone(),
+            BreakerConfig::default(),
+        ));
+        let request = crate::inference::scheduler::CompletionRequest {
+            prompt: "Hello".to_string(),
+            max_tokens: Some(16),
+            temperature: None,
+            top_k: None,
+            top_p: None,
+            repeat_penalty: None,
+            repeat_last_n: None,
+            min_keep: None,
+            model: None,
+            stream: Some(true),
+        };
+        let (_, device_id, mut rx) = scheduler
+            .execute_inference_stream(request, None)
+            .await
+            .unwrap();
+        assert_eq!(device_id, worker);
+
+        let reaper = spawn_client_reaper(
+            active_clients.clone(),
+            scheduler.clone(),
+            Duration::from_secs(360),
+        );
+        let closed = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+        reaper.abort();
+        assert!(
+            matches!(closed, Ok(None)),
+            "task kept waiting on the evicted worker"
+        );
+    }
+
+    /// Connected loopback pair: the stream to park and the peer observing it.
+    async fn tcp_pair() -> (TcpStream, TcpStream) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (peer, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        (accepted.unwrap().0, peer.unwrap())
+    }
+
+    #[tokio::test]
+    async fn pending_pool_evicts_stale_entries_and_closes_their_streams() {
+        let mut pool = PendingPool::new(8, Duration::from_secs(30));
+        let t0 = Instant::now();
+        let (stale_stream, mut stale_peer) = tcp_pair().await;
+        let (fresh_stream, _fresh_peer) = tcp_pair().await;
+        let stale = ProxyConnId([1; 16]);
+        let fresh = ProxyConnId([2; 16]);
+        assert!(pool
+            .insert_at(stale, stale_stream, BytesMut::from(&b"GET /"[..]), t0)
+            .is_ok());
+        assert!(pool
+            .insert_at(
+                fresh,
+                fresh_stream,
+                BytesMut::new(),
+                t0 + Duration::from_secs(20)
+            )
+            .is_ok());
+
+        assert!(pool.evict_stale_at(t0 + Duration::from_secs(30)).is_empty());
+        // The evicted entry hands back its buffer for the buffer pool
+        assert_eq!(
+            pool.evict_stale_at(t0 + Duration::from_secs(31)),
+            vec![(stale, BytesMut::from(&b"GET /"[..]))]
+        );
+        assert_eq!(pool.len(), 1);
+        assert!(pool.remove(&stale).is_none());
+        assert!(pool.remove(&fresh).is_some());
+
+        let mut buf = [0u8; 1];
+        let n = tokio::time::timeout(Duration::from_secs(1), stale_peer.read(&mut buf))
+            .await
+            .expect("evicted stream was not closed")
+            .unwrap();
+        assert_eq!(n, 0);
+    }
+
+    #[tokio::test]
+    async fn pending_pool_rejects_inserts_once_full() {
+        let mut pool = PendingPool::new(1, Duration::from_secs(30));
+        let (first, _first_peer) = tcp_pair().await;
+        let (second, _second_peer) = tcp_pair().await;
+        assert!(pool
+            .insert(ProxyConnId([1; 16]), first, BytesMut::new())
+            .is_ok());
+        assert!(pool.is_full());
+        assert!(pool
+            .insert(ProxyConnId([2; 16]), second, BytesMut::new())
+            .is_err());
+        assert_eq!(pool.len(), 1);
+    }
+}
@@ -0,0 +1,379 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::util::protoc::ClientId;
+
+/// Circuit breaker tuning shared by all workers.
+#[derive(Debug, Clone, Copy)]
+pub struct BreakerConfig {
+    /// Consecutive dispatch failures (within `window`) that open the breaker.
+    pub failure_threshold: u32,
+    /// Failures older than this no longer count towards the threshold.
+    pub window: Duration,
+    /// How long an open breaker keeps the worker out of scheduling before a probe.
+    pub cooldown: Duration,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// Worker is healthy and schedulable.
+    Closed,
+    /// Worker is skipped until the cooldown elapses.
+    Open,
+    /// Cooldown elapsed; a single probe request is allowed through.
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BreakerSnapshot {
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    pub trips: u64,
+}
+
+#[derive(Debug)]
+struct WorkerBreaker {
+    state: BreakerState,
+    consecutive_failures: u32,
+    first_failure_at: Option<Instant>,
+    opened_at: Option<Instant>,
+    probe_in_flight: bool,
+    trips: u64,
+}
+
+impl WorkerBreaker {
+    fn new() -> Self {
+        Self {
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            first_failure_at: None,
+            opened_at: None,
+            probe_in_flight: false,
+            trips: 0,
+        }
+    }
+
+    fn effective_state(&self, config: &BreakerConfig, now: Instant) -> BreakerState {
+        match (self.state, self.opened_at) {
+            (BreakerState::Open, Some(opened_at))
+                if now.duration_since(opened_at) >= config.cooldown =>
+            {
+                BreakerState::HalfOpen
+            }
+            (state, _) => state,
+        }
+    }
+
+    fn open(&mut self, now: Instant) {
+        self.state = BreakerState::Open;
+        self.opened_at = Some(now);
+        self.probe_in_flight = false;
+        self.trips += 1;
+    }
+}
+
+/// Per-worker circuit breakers used by the scheduler to stop routing requests
+/// to a worker that keeps failing.
+#[derive(Debug)]
+pub struct CircuitBreakers {
+    config: BreakerConfig,
+    workers: Mutex<HashMap<ClientId, WorkerBreaker>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(config: BreakerConfig) -> Self {
+        Self {
+            config,
+            workers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<ClientId, WorkerBreaker>> {
+        self.workers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Whether the worker may be considered for scheduling. Does not claim the
+    /// half-open probe; call `begin_dispatch` once the worker is chosen.
+    pub fn is_available(&self, client_id: &ClientId) -> bool {
+        self.is_available_at(client_id, Instant::now())
+    }
+
+    fn is_available_at(&self, client_id: &ClientId, now: Instant) -> bool {
+        let workers = self.lock();
+        let Some(breaker) = workers.get(client_id) else {
+            return true;
+        };
+        match breaker.effective_state(&self.config, now) {
+            BreakerState::Closed => true,
+            BreakerState::Open => false,
+            BreakerState::HalfOpen => !breaker.probe_in_flight,
+        }
+    }
+
+    /// Marks a dispatch to the chosen worker. In half-open state this claims the
+    /// single probe slot so concurrent requests keep skipping the worker, and
+    /// returns the guard holding it: the slot is freed when the guard drops
+    /// before the probe's outcome was recorded, e.g. because it was cancelled.
+    pub fn begin_dispatch(self: &Arc<Self>, client_id: &ClientId) -> Option<ProbeGuard> {
+        self.begin_dispatch_at(client_id, Instant::now())
+    }
+
+    fn begin_dispatch_at(
+        self: &Arc<Self>,
+        client_id: &ClientId,
+        now: Instant,
+    ) -> Option<ProbeGuard> {
+        let mut workers = self.lock();
+        let breaker = workers.get_mut(client_id)?;
+        if breaker.effective_state(&self.config, now) != BreakerState::HalfOpen {
+            return None;
+        }
+        breaker.state = BreakerState::HalfOpen;
+        breaker.probe_in_flight = true;
+        info!(
+            "Circuit breaker for {} half-open, sending probe request",
+            client_id.log_label()
+        );
+        Some(ProbeGuard {
+            breakers: self.clone(),
+            client_id: *client_id,
+        })
+    }
+
+    fn release_probe(&self, client_id: &ClientId) {
+        let mut workers = self.lock();
+        let Some(breaker) = workers.get_mut(client_id) else {
+            return;
+        };
+        if breaker.state == BreakerState::HalfOpen && breaker.probe_in_flight {
+            breaker.probe_in_flight = false;
+            info!(
+                "Circuit breaker probe for {} ended without an outcome, next request probes",
+                client_id.log_label()
+            );
+        }
+    }
+
+    pub fn record_success(&self, client_id: &ClientId) {
+        let mut workers = self.lock();
+        let Some(breaker) = workers.get_mut(client_id) else {
+            return;
+        };
+        if breaker.state != BreakerState::Closed {
+            info!(
+                "Circuit breaker for {} closed after successful probe",
+                client_id.log_label()
+            );
+        }
+        breaker.state = BreakerState::Closed;
+        breaker.consecutive_failures = 0;
+        breaker.first_failure_at = None;
+        breaker.opened_at = None;
+        breaker.probe_in_flight = false;
+    }
+
+    pub fn record_failure(&self, client_id: &ClientId) {
+        self.record_failure_at(client_id, Instant::now());
+    }
+
+    fn record_failure_at(&self, client_id: &ClientId, now: Instant) {
+        let mut workers = self.lock();
+        let breaker = workers.entry(*client_id).or_insert_with(WorkerBreaker::new);
+
+        match breaker.state {
+            BreakerState::HalfOpen => {
+                warn!(
+                    "Circuit breaker for {} re-opened: probe request failed",
+                    client_id.log_label()
+                );
+                breaker.open(now);
+            }
+            BreakerState::Open => {}
+            BreakerState::Closed => {
+                let window_expired = breaker
+                    .first_failure_at
+                    .is_some_and(|first| now.duration_since(first) > self.config.window);
+                if window_expired || breaker.first_failure_at.is_none() {
+                    breaker.first_failure_at = Some(now);
+                    breaker.consecutive_failures = 0;
+                }
+                breaker.consecutive_failures += 1;
+
+                if breaker.consecutive_failures >= self.config.failure_threshold {
+                    warn!(
+                        "Circuit breaker for {} opened after {} consecutive failures (cooldown {}s)",
+                        client_id.log_label(),
+                        breaker.consecutive_failures,
+                        self.config.cooldown.as_secs()
+                    );
+                    breaker.open(now);
+                }
+            }
+        }
+    }
+
+    pub fn snapshot(&self, client_id: &ClientId) -> BreakerSnapshot {
+        let now = Instant::now();
+        let workers = self.lock();
+        match workers.get(client_id) {
+            Some(breaker) => self.snapshot_of(breaker, now),
+            None => BreakerSnapshot {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                trips: 0,
+            },
+        }
+    }
+
+    /// Snapshots of every worker that has failed a dispatch at least once;
+    /// all other workers are closed and never tripped.
+    pub fn snapshots(&self) -> Vec<(ClientId, BreakerSnapshot)> {
+        let now = Instant::now();
+        self.lock()
+            .iter()
+            .map(|(client_id, breaker)| (*client_id, self.snapshot_of(breaker, now)))
+            .collect()
+    }
+
+    fn snapshot_of(&self, breaker: &WorkerBreaker, now: Instant) -> BreakerSnapshot {
+        BreakerSnapshot {
+            state: breaker.effective_state(&self.config, now),
+            consecutive_failures: breaker.consecutive_failures,
+            trips: breaker.trips,
+        }
+    }
+}
+
+/// Half-open probe slot claimed by `CircuitBreakers::begin_dispatch`.
+#[derive(Debug)]
+pub struct ProbeGuard {
+    breakers: Arc<CircuitBreakers>,
+    client_id: ClientId,
+}
+
+impl Drop for ProbeGuard {
+    fn drop(&mut self) {
+        self.breakers.release_probe(&self.client_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breakers() -> Arc<CircuitBreakers> {
+        Arc::new(CircuitBreakers::new(BreakerConfig {
+            failure_threshold: 3,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(30),
+        }))
+    }
+
+    #[test]
+    fn opens_after_threshold_and_half_opens_after_cooldown() {
+        let cb = breakers();
+        let id = ClientId([1; 16]);
+        let t0 = Instant::now();
+
+        cb.record_failure_at(&id, t0);
+        cb.record_failure_at(&id, t0);
+        assert!(cb.is_available_at(&id, t0));
+        cb.record_failure_at(&id, t0);
+        assert!(!cb.is_available_at(&id, t0));
+
+        let after_cooldown = t0 + Duration::from_secs(31);
+        assert!(cb.is_available_at(&id, after_cooldown));
+        let probe = cb.begin_dispatch_at(&id, after_cooldown);
+        assert!(probe.is_some());
+        // Only one probe at a time
+        assert!(!cb.is_available_at(&id, after_cooldown));
+
+        cb.record_success(&id);
+        drop(probe);
+        assert!(cb.is_available_at(&id, after_cooldown));
+        assert_eq!(cb.snapshot(&id).state, BreakerState::Closed);
+        assert_eq!(cb.snapshot(&id).trips, 1);
+    }
+
+    #[test]
+    fn failed_probe_reopens() {
+        let cb = breakers();
+        let id = ClientId([2; 16]);
+        let t0 = Instant::now();
+        for _ in 0..3 {
+            cb.record_failure_at(&id, t0);
+        }
+        let probe_at = t0 + Duration::from_secs(31);
+        let probe = cb.begin_dispatch_at(&id, probe_at);
+        cb.record_failure_at(&id, probe_at);
+        drop(probe);
+        assert!(!cb.is_available_at(&id, probe_at + Duration::from_secs(1)));
+        assert_eq!(cb.snapshot(&id).trips, 2);
+    }
+
+    #[test]
+    fn dropped_probe_frees_the_slot() {
+        let cb = breakers();
+        let id = ClientId([4; 16]);
+        let t0 = Instant::now();
+        for _ in 0..3 {
+            cb.record_failure_at(&id, t0);
+        }
+        let probe_at = t0 + Duration::from_secs(31);
+        let probe = cb.begin_dispatch_at(&id, probe_at);
+        assert!(!cb.is_available_at(&id, probe_at));
+
+        // Cancelled before any outcome: the next request becomes the probe
+        drop(probe);
+        assert!(cb.is_available_at(&id, probe_at));
+        assert_eq!(cb.snapshot(&id).state, BreakerState::HalfOpen);
+        assert!(cb.begin_dispatch_at(&id, probe_at).is_some());
+    }
+
+    #[test]
+    fn snapshots_cover_every_tracked_worker() {
+        let cb = breakers();
+        let tripped = ClientId([5; 16]);
+        let failing = ClientId([6; 16]);
+        let t0 = Instant::now();
+        for _ in 0..3 {
+            cb.record_failure_at(&tripped, t0);
+        }
+        cb.record_failure_at(&failing, t0);
+
+        let mut snapshots = cb.snapshots();
+        snapshots.sort_by_key(|(client_id, _)| client_id.0);
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].1.state, BreakerState::Open);
+        assert_eq!(snapshots[0].1.trips, 1);
+        assert_eq!(snapshots[1].1.state, BreakerState::Closed);
+        assert_eq!(snapshots[1].1.consecutive_failures, 1);
+    }
+
+    #[test]
+    fn failures_outside_window_do_not_accumulate() {
+        let cb = breakers();
+        let id = ClientId([3; 16]);
+        let t0 = Instant::now();
+        cb.record_failure_at(&id, t0);
+        cb.record_failure_at(&id, t0);
+        cb.record_failure_at(&id, t0 + Duration::from_secs(61));
+        assert!(cb.is_available_at(&id, t0 + Duration::from_secs(61)));
+    }
+}
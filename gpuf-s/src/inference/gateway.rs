@@ -8,14 +8,22 @@ use axum::{
 };
 use rdkafka::producer::FutureProducer;
 use sqlx::{Pool, Postgres};
+use std::fmt;
+use std::future::Future;
 use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 use tower_http::cors::CorsLayer;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 use crate::db::client::get_user_client_by_token;
 #[cfg(feature = "experimental")]
 use crate::handle::ActiveClients;
-use crate::inference::{handlers, InferenceScheduler};
+use crate::handle::ServerState;
+use crate::inference::{
+    handlers, metrics::GatewayMetrics, scheduler::StreamEvent, InferenceScheduler,
+};
 use crate::util::policy::{AccessLevel, REQUEST_MESSAGE_TOPIC};
 use crate::util::protoc::{ClientId, RequestIDAndClientIDMessage};
 use anyhow::anyhow;
@@ -28,11 +36,68 @@ pub struct AuthContext {
     pub access_level: AccessLevel,
 }
 
+/// How long a request waits on its worker for the next chunk by default.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long in-flight requests may keep running after shutdown by default.
+pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+
+/// Every worker a request was dispatched to stayed silent for the request
+/// timeout or disconnected before emitting anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerTimedOut {
+    pub timeout: Duration,
+}
+
+impl fmt::Display for WorkerTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "No worker replied within {}s",
+            self.timeout.as_secs_f32()
+        )
+    }
+}
+
+impl std::error::Error for WorkerTimedOut {}
+
+type StartedStream = (String, ClientId, mpsc::Receiver<StreamEvent>);
+
+/// Cancels a dispatched task on its worker when dropped while still armed,
+/// as when the client disconnects before the task produced anything.
+struct FirstEventGuard {
+    scheduler: Arc<InferenceScheduler>,
+    task_id: String,
+    device_id: ClientId,
+    armed: bool,
+}
+
+impl Drop for FirstEventGuard {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let scheduler = self.scheduler.clone();
+        let task_id = std::mem::take(&mut self.task_id);
+        let device_id = self.device_id;
+        tokio::spawn(async move {
+            let _ = scheduler.cancel_inference(&task_id, &device_id).await;
+        });
+    }
+}
+
 /// Inference Gateway - Handles external API requests and routes them to Android devices
 pub struct InferenceGateway {
     pub scheduler: Arc<InferenceScheduler>,
     pub db_pool: Arc<Pool<Postgres>>,
     pub producer: Arc<FutureProducer>,
+    pub metrics: Arc<GatewayMetrics>,
+    /// Connection-level server state reported on `/metrics`, when the
+    /// gateway runs inside the server.
+    pub server: Option<Arc<ServerState>>,
+    request_timeout: Duration,
+    shutdown: CancellationToken,
+    shutdown_grace: Duration,
 }
 
 impl InferenceGateway {
@@ -40,25 +105,52 @@ impl InferenceGateway {
         scheduler: Arc<InferenceScheduler>,
         db_pool: Arc<Pool<Postgres>>,
         producer: Arc<FutureProducer>,
-    ) -> Self {
-        Self {
+    ) -> Result<Self> {
+        Ok(Self {
             scheduler,
             db_pool,
             producer,
-        }
+            metrics: Arc::new(GatewayMetrics::new()?),
+            server: None,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            shutdown: CancellationToken::new(),
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+        })
+    }
+
+    /// How long a request waits on its worker for the next chunk before the
+    /// worker is treated as dead.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Once `token` is cancelled the server stops accepting connections and
+    /// `run` returns as soon as in-flight requests finish, or after `grace`
+    /// with their worker tasks cancelled.
+    pub fn with_shutdown(mut self, token: CancellationToken, grace: Duration) -> Self {
+        self.shutdown = token;
+        self.shutdown_grace = grace;
+        self
+    }
+
+    /// Reports `server`'s connection statistics alongside the gateway's own
+    /// metrics.
+    pub fn with_server_state(mut self, server: Arc<ServerState>) -> Self {
+        self.server = Some(server);
+        self
     }
     #[cfg(feature = "experimental")]
     pub fn with_active_clients(
         active_clients: ActiveClients,
         db_pool: Arc<Pool<Postgres>>,
         producer: Arc<FutureProducer>,
-    ) -> Self {
-        let scheduler = Arc::new(InferenceScheduler::new(active_clients));
-        Self {
-            scheduler,
-            db_pool,
-            producer,
-        }
+    ) -> Result<Self> {
+        let scheduler = Arc::new(InferenceScheduler::new(
+            active_clients,
+            crate::inference::circuit_breaker::BreakerConfig::default(),
+        ));
+        Self::new(scheduler, db_pool, producer)
     }
 
     async fn auth_middleware(
@@ -138,18 +230,165 @@ impl InferenceGateway {
         Ok(())
     }
 
+    /// Dispatches a streaming task through `dispatch`, which is handed the
+    /// workers it may pick from, and waits for the task's first event. While
+    /// nothing has reached the client, a worker that stays silent for the
+    /// request timeout or disconnects is failed and the task goes to another
+    /// of the `allowed` workers; fails with `WorkerTimedOut` once none is left.
+    pub async fn start_stream<F, Fut>(
+        &self,
+        allowed: &[ClientId],
+        mut dispatch: F,
+    ) -> Result<StartedStream>
+    where
+        F: FnMut(Vec<ClientId>) -> Fut,
+        Fut: Future<Output = Result<StartedStream>>,
+    {
+        let mut failed: Vec<ClientId> = Vec::new();
+        loop {
+            let remaining = allowed
+                .iter()
+                .filter(|id| !failed.contains(id))
+                .copied()
+                .collect();
+            let (task_id, device_id, mut rx) = match dispatch(remaining).await {
+                Ok(started) => started,
+                Err(e) if failed.is_empty() => return Err(e),
+                Err(_) => {
+                    return Err(WorkerTimedOut {
+                        timeout: self.request_timeout,
+                    }
+                    .into())
+                }
+            };
+
+            let mut waiting = FirstEventGuard {
+                scheduler: self.scheduler.clone(),
+                task_id: task_id.clone(),
+                device_id,
+                armed: true,
+            };
+            let first = tokio::time::timeout(self.request_timeout, rx.recv()).await;
+            waiting.armed = false;
+            match first {
+                Ok(Some(first)) => {
+                    let rx = self.relay_stream(task_id.clone(), device_id, first, rx);
+                    return Ok((task_id, device_id, rx));
+                }
+                Ok(None) => warn!(
+                    "Worker {} disconnected before task {} produced output, failing over",
+                    device_id.log_label(),
+                    task_id
+                ),
+                Err(_) => warn!(
+                    "Worker {} sent nothing for task {} within {}s, failing over",
+                    device_id.log_label(),
+                    task_id,
+                    self.request_timeout.as_secs_f32()
+                ),
+            }
+            self.scheduler.fail_task(&task_id, &device_id).await;
+            failed.push(device_id);
+        }
+    }
+
+    /// Forwards a started task's events, beginning with the `first` one
+    /// already received. Output has reached the client by now, so a worker
+    /// that then goes quiet for the request timeout or disconnects ends the
+    /// stream with an error rather than being retried.
+    fn relay_stream(
+        &self,
+        task_id: String,
+        device_id: ClientId,
+        first: StreamEvent,
+        mut rx: mpsc::Receiver<StreamEvent>,
+    ) -> mpsc::Receiver<StreamEvent> {
+        let (tx, out) = mpsc::channel(128);
+        let scheduler = self.scheduler.clone();
+        let timeout = self.request_timeout;
+        tokio::spawn(async move {
+            let mut next = Some(first);
+            loop {
+                let event = match next.take() {
+                    Some(event) => event,
+                    None => match tokio::time::timeout(timeout, rx.recv()).await {
+                        Ok(Some(event)) => event,
+                        // The client went away and its guard cancels the task
+                        _ if tx.is_closed() => return,
+                        _ => {
+                            warn!(
+                                "Worker {} stopped responding mid-stream for task {}",
+                                device_id.log_label(),
+                                task_id
+                            );
+                            scheduler.fail_task(&task_id, &device_id).await;
+                            let _ = tx
+                                .send(StreamEvent::Error("Worker stopped responding".to_string()))
+                                .await;
+                            let _ = tx.send(StreamEvent::Done).await;
+                            return;
+                        }
+                    },
+                };
+                let done = matches!(event, StreamEvent::Done);
+                if tx.send(event).await.is_err() || done {
+                    return;
+                }
+            }
+        });
+        out
+    }
+
     /// Run the inference gateway server
     pub async fn run(self: Arc<Self>, port: u16) -> Result<()> {
-        let app = self.create_router().await;
-        let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+        let app = Arc::clone(&self).create_router().await;
+        let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
 
         info!("Inference Gateway listening on port {}", port);
-        axum::serve(listener, app).await.map_err(Into::into)
+        self.serve_until_drained(listener, app).await
+    }
+
+    /// Serves `app` until shutdown, then drains: no new connections are
+    /// accepted and in-flight requests get the grace period to finish.
+    async fn serve_until_drained(&self, listener: TcpListener, app: Router) -> Result<()> {
+        let server = axum::serve(listener, app)
+            .with_graceful_shutdown(self.shutdown.clone().cancelled_owned());
+        let grace_over = async {
+            self.shutdown.cancelled().await;
+            info!(
+                "Draining in-flight inference requests for up to {}s",
+                self.shutdown_grace.as_secs_f32()
+            );
+            tokio::time::sleep(self.shutdown_grace).await;
+        };
+        tokio::select! {
+            res = server => res.map_err(Into::into),
+            _ = grace_over => {
+                let cancelled = self.scheduler.cancel_in_flight().await;
+                warn!(
+                    "Shutdown grace period ran out, cancelled {} in-flight inference tasks",
+                    cancelled
+                );
+                Ok(())
+            }
+        }
     }
 
     /// Create API router for inference endpoints
     pub async fn create_router(self: Arc<Self>) -> Router {
         let state = Arc::clone(&self);
+        Self::routes()
+            .route_layer(middleware::from_fn_with_state(
+                self.db_pool.clone(),
+                Self::auth_middleware,
+            ))
+            .merge(Self::metrics_routes())
+            .layer(CorsLayer::permissive())
+            .with_state(state)
+    }
+
+    /// Inference and device routes, without the authentication layer.
+    fn routes() -> Router<Arc<Self>> {
         Router::new()
             // OpenAI Compatible Inference APIs
             .route("/v1/completions", post(handlers::handle_completion))
@@ -164,11 +403,988 @@ impl InferenceGateway {
                 "/api/v1/devices/:id/status",
                 get(handlers::get_device_status),
             )
-            .route_layer(middleware::from_fn_with_state(
-                self.db_pool.clone(),
-                Self::auth_middleware,
+            .route(
+                "/api/v1/devices/:id/describe",
+                get(handlers::describe_device),
+            )
+    }
+
+    /// Prometheus scrape endpoint, served without authentication.
+    fn metrics_routes() -> Router<Arc<Self>> {
+        Router::new().route("/metrics", get(handlers::metrics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handle::{ActiveClients, ClientInfo, ControlWriter, SystemInfo};
+    use crate::inference::scheduler::{CompletionRequest, ShuttingDown};
+    use axum::Extension;
+    use bytes::BytesMut;
+    use common::{read_command, write_command, Command, CommandV1, OsType, OutputPhase};
+    use rdkafka::ClientConfig;
+    use sqlx::postgres::PgPoolOptions;
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::Mutex;
+
+    const WORKER_ID: ClientId = ClientId([7; 16]);
+    const WORKER_REPLY: [&str; 3] = ["Hello", ", ", "world"];
+
+    /// Fake worker: connects to the control port, logs in, answers every
+    /// inference task by streaming `WORKER_REPLY` back one token per chunk and
+    /// describe requests with `worker_description()`.
+    async fn run_fake_worker(control_addr: SocketAddr) -> Result<()> {
+        let stream = TcpStream::connect(control_addr).await?;
+        let (mut reader, mut writer) = stream.into_split();
+        let mut buf = BytesMut::new();
+
+        let login = CommandV1::Login {
+            client_id: WORKER_ID.0,
+            version: common::PROTOCOL_REVISION,
+            os_type: OsType::ANDROID,
+            auto_models: false,
+            system_info: common::SystemInfo {
+                cpu_usage: 10,
+                memory_usage: 20,
+                ..Default::default()
+            },
+            device_memtotal_gb: 8,
+            device_total_tflops: 2,
+            devices_info: Vec::new(),
+        };
+        write_command(&mut writer, &Command::V1(login)).await?;
+
+        loop {
+            let (task_id, prompt) = match read_command(&mut reader, &mut buf).await? {
+                Command::V1(CommandV1::InferenceTask {
+                    task_id, prompt, ..
+                })
+                | Command::V1(CommandV1::ModelInferenceTask {
+                    task_id, prompt, ..
+                }) => (task_id, prompt),
+                Command::V1(CommandV1::DescribeWorker { request_id }) => {
+                    let reply = CommandV1::WorkerDescription {
+                        request_id,
+                        description: worker_description(),
+                    };
+                    write_command(&mut writer, &Command::V1(reply)).await?;
+                    continue;
+                }
+                _ => continue,
+            };
+
+            let prompt_tokens = prompt.split_whitespace().count() as u32;
+            let chunks = WORKER_REPLY
+                .iter()
+                .map(|delta| (delta.to_string(), false))
+                .chain(std::iter::once((String::new(), true)));
+            for (seq, (delta, done)) in chunks.enumerate() {
+                let chunk = CommandV1::InferenceResultChunk {
+                    task_id: task_id.clone(),
+                    seq: seq as u32,
+                    delta,
+                    phase: OutputPhase::Final,
+                    done,
+                    error: None,
+                    prompt_tokens,
+                    completion_tokens: WORKER_REPLY.len() as u32,
+                    analysis_tokens: 0,
+                    final_tokens: WORKER_REPLY.len() as u32,
+                };
+                write_command(&mut writer, &Command::V1(chunk)).await?;
+            }
+        }
+    }
+
+    fn worker_description() -> common::WorkerDescription {
+        common::WorkerDescription {
+            version: "test-worker".to_string(),
+            protocol_revision: common::PROTOCOL_REVISION,
+            os: "android".to_string(),
+            engine: "Llama".to_string(),
+            model: Some("tiny-llama.gguf".to_string()),
+            n_ctx: 2048,
+            n_batch: 512,
+            n_gpu_layers: None,
+            device_summary: "android aarch64, 8 CPUs".to_string(),
+        }
+    }
+
+    /// Gateway end of the control connection. Mirrors `handle_single_client`
+    /// without the database-backed login check: registers the worker once it
+    /// logs in and feeds its result chunks and descriptions to the scheduler.
+    /// `test_login_through_the_control_handler_persists` runs the real
+    /// handler to check what its login persists.
+    async fn serve_control_connection(
+        listener: TcpListener,
+        active_clients: ActiveClients,
+        scheduler: Arc<InferenceScheduler>,
+    ) -> Result<()> {
+        let (stream, _) = listener.accept().await?;
+        let (mut reader, writer) = stream.into_split();
+        let mut writer = Some(writer);
+        let mut buf = BytesMut::new();
+
+        loop {
+            match read_command(&mut reader, &mut buf).await? {
+                Command::V1(CommandV1::Login {
+                    client_id,
+                    version,
+                    system_info,
+                    devices_info,
+                    ..
+                }) => {
+                    let writer = writer.take().ok_or_else(|| anyhow!("Duplicate login"))?;
+                    let info = ClientInfo {
+                        writer: Arc::new(Mutex::new(Box::new(writer))),
+                        authed: true,
+                        version,
+                        system_info: Some(SystemInfo {
+                            cpu_usage: system_info.cpu_usage,
+                            memory_usage: system_info.memory_usage,
+                            disk_usage: system_info.disk_usage,
+                            device_memsize: 0,
+                            total_tflops: 0,
+                            last_heartbeat: std::time::SystemTime::now(),
+                            memsize_gb: 0,
+                        }),
+                        devices_info,
+                        connected_at: chrono::Utc::now(),
+                        models: None,
+                        quant_types: None,
+                        n_ctx: None,
+                    };
+                    active_clients
+                        .lock()
+                        .await
+                        .insert(ClientId(client_id), info);
+                }
+                Command::V1(CommandV1::InferenceResultChunk {
+                    task_id,
+                    seq,
+                    delta,
+                    phase,
+                    done,
+                    error,
+                    prompt_tokens,
+                    completion_tokens,
+                    analysis_tokens,
+                    final_tokens,
+                }) => {
+                    scheduler
+                        .handle_inference_result_chunk(
+                            task_id,
+                            seq,
+                            delta,
+                            phase,
+                            done,
+                            error,
+                            prompt_tokens,
+                            completion_tokens,
+                            analysis_tokens,
+                            final_tokens,
+                        )
+                        .await;
+                }
+                Command::V1(CommandV1::WorkerDescription {
+                    request_id,
+                    description,
+                }) => {
+                    scheduler
+                        .handle_worker_description(&WORKER_ID, request_id, description)
+                        .await;
+                }
+                other => return Err(anyhow!("Unexpected command {}", other.variant_name())),
+            }
+        }
+    }
+
+    /// Starts the gateway HTTP API with every request authorized for the fake
+    /// worker. Postgres and Kafka are configured but never contacted: auth is
+    /// injected directly and the token is not metered.
+    async fn start_gateway(scheduler: Arc<InferenceScheduler>) -> Result<SocketAddr> {
+        start_gateway_with(scheduler, vec![WORKER_ID], DEFAULT_REQUEST_TIMEOUT).await
+    }
+
+    async fn start_gateway_with(
+        scheduler: Arc<InferenceScheduler>,
+        client_ids: Vec<ClientId>,
+        request_timeout: Duration,
+    ) -> Result<SocketAddr> {
+        let gateway = test_gateway(scheduler)?.with_request_timeout(request_timeout);
+        let app = authorized_routes(Arc::new(gateway), client_ids);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Ok(addr)
+    }
+
+    fn test_gateway(scheduler: Arc<InferenceScheduler>) -> Result<InferenceGateway> {
+        let db_pool = PgPoolOptions::new().connect_lazy("postgres://127.0.0.1:1/gpuf")?;
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", "127.0.0.1:1")
+            .set_log_level(rdkafka::config::RDKafkaLogLevel::Emerg)
+            .create()?;
+        InferenceGateway::new(scheduler, Arc::new(db_pool), Arc::new(producer))
+    }
+
+    fn authorized_routes(gateway: Arc<InferenceGateway>, client_ids: Vec<ClientId>) -> Router {
+        InferenceGateway::routes()
+            .layer(Extension(AuthContext {
+                client_ids,
+                access_level: AccessLevel(0),
+            }))
+            .merge(InferenceGateway::metrics_routes())
+            .with_state(gateway)
+    }
+
+    /// Streams a completion through the gateway at `http_addr` and checks it
+    /// returns `WORKER_REPLY` with the worker's usage.
+    async fn assert_streams_worker_reply(http_addr: SocketAddr) -> Result<()> {
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/v1/completions", http_addr))
+            .json(&serde_json::json!({
+                "prompt": "say hello world",
+                "max_tokens": 16,
+                "stream": true
+            }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let body = response.text().await?;
+
+        let events: Vec<&str> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .collect();
+        assert_eq!(events.last(), Some(&"[DONE]"), "{}", body);
+
+        let payloads = events[..events.len() - 1]
+            .iter()
+            .map(|event| serde_json::from_str::<serde_json::Value>(event))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (finish, deltas) = payloads.split_last().expect("finish event");
+
+        let texts: Vec<&str> = deltas
+            .iter()
+            .map(|delta| delta["choices"][0]["text"].as_str().unwrap_or_default())
+            .collect();
+        assert_eq!(texts, WORKER_REPLY);
+        assert!(deltas
+            .iter()
+            .all(|delta| delta["choices"][0]["finish_reason"].is_null()));
+
+        assert_eq!(finish["choices"][0]["finish_reason"], "stop");
+        assert_eq!(finish["usage"]["prompt_tokens"], 3);
+        assert_eq!(finish["usage"]["completion_tokens"], WORKER_REPLY.len());
+        assert_eq!(finish["usage"]["total_tokens"], 3 + WORKER_REPLY.len());
+        Ok(())
+    }
+
+    async fn stream_completion_round_trip() -> Result<()> {
+        let active_clients: ActiveClients = Arc::new(Mutex::new(HashMap::new()));
+        let scheduler = Arc::new(InferenceScheduler::new(
+            active_clients.clone(),
+            crate::inference::circuit_breaker::BreakerConfig::default(),
+        ));
+
+        let control = TcpListener::bind("127.0.0.1:0").await?;
+        let control_addr = control.local_addr()?;
+        let control_task = tokio::spawn(serve_control_connection(
+            control,
+            active_clients.clone(),
+            scheduler.clone(),
+        ));
+        let worker_task = tokio::spawn(run_fake_worker(control_addr));
+        while !active_clients.lock().await.contains_key(&WORKER_ID) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let http_addr = start_gateway(scheduler).await?;
+        assert_streams_worker_reply(http_addr).await?;
+
+        worker_task.abort();
+        control_task.abort();
+        Ok(())
+    }
+
+    async fn describe_round_trip() -> Result<()> {
+        let active_clients: ActiveClients = Arc::new(Mutex::new(HashMap::new()));
+        let scheduler = Arc::new(InferenceScheduler::new(
+            active_clients.clone(),
+            crate::inference::circuit_breaker::BreakerConfig::default(),
+        ));
+
+        let control = TcpListener::bind("127.0.0.1:0").await?;
+        let control_addr = control.local_addr()?;
+        let control_task = tokio::spawn(serve_control_connection(
+            control,
+            active_clients.clone(),
+            scheduler.clone(),
+        ));
+        let worker_task = tokio::spawn(run_fake_worker(control_addr));
+        while !active_clients.lock().await.contains_key(&WORKER_ID) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let http_addr = start_gateway(scheduler).await?;
+        let client = reqwest::Client::new();
+        let response = client
+            .get(format!(
+                "http://{}/api/v1/devices/{}/describe",
+                http_addr,
+                hex::encode(WORKER_ID.0)
             ))
-            .layer(CorsLayer::permissive())
-            .with_state(state)
+            .send()
+            .await?;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let description: common::WorkerDescription = response.json().await?;
+        assert_eq!(description, worker_description());
+
+        let response = client
+            .get(format!(
+                "http://{}/api/v1/devices/{}/describe",
+                http_addr,
+                hex::encode([8u8; 16])
+            ))
+            .send()
+            .await?;
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        worker_task.abort();
+        control_task.abort();
+        Ok(())
+    }
+
+    async fn metrics_after_failed_request() -> Result<()> {
+        let active_clients: ActiveClients = Arc::new(Mutex::new(HashMap::new()));
+        let scheduler = Arc::new(InferenceScheduler::new(
+            active_clients,
+            crate::inference::circuit_breaker::BreakerConfig::default(),
+        ));
+        let http_addr = start_gateway(scheduler).await?;
+        let client = reqwest::Client::new();
+
+        // No worker is connected, so the request fails but is still counted,
+        // under `other` since no worker advertises the model
+        let response = client
+            .post(format!("http://{}/v1/completions", http_addr))
+            .json(&serde_json::json!({"prompt": "hi", "model": "tiny-llama"}))
+            .send()
+            .await?;
+        assert!(!response.status().is_success());
+
+        let metrics = client
+            .get(format!("http://{}/metrics", http_addr))
+            .send()
+            .await?
+            .text()
+            .await?;
+        for expected in [
+            "gpuf_gateway_requests_total 1",
+            "gpuf_gateway_requests_in_flight 0",
+            "gpuf_gateway_generated_tokens_total 0",
+            "gpuf_gateway_model_requests_total{model=\"other\"} 1",
+            "gpuf_gateway_request_duration_seconds_count 1",
+        ] {
+            assert!(
+                metrics.lines().any(|line| line == expected),
+                "missing {:?} in\n{}",
+                expected,
+                metrics
+            );
+        }
+        Ok(())
+    }
+
+    async fn unserved_model_rejected() -> Result<()> {
+        let active_clients: ActiveClients = Arc::new(Mutex::new(HashMap::new()));
+        let scheduler = Arc::new(InferenceScheduler::new(
+            active_clients,
+            crate::inference::circuit_breaker::BreakerConfig::default(),
+        ));
+        let http_addr = start_gateway(scheduler).await?;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/v1/chat/completions", http_addr))
+            .json(&serde_json::json!({
+                "model": "tiny-llama",
+                "messages": [{"role": "user", "content": "hi"}]
+            }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = response.json().await?;
+        assert_eq!(
+            body,
+            serde_json::json!({"error": "no_worker_for_model", "model": "tiny-llama"})
+        );
+        Ok(())
+    }
+
+    async fn served_model_routed() -> Result<()> {
+        let active_clients: ActiveClients = Arc::new(Mutex::new(HashMap::new()));
+        let scheduler = Arc::new(InferenceScheduler::new(
+            active_clients.clone(),
+            crate::inference::circuit_breaker::BreakerConfig::default(),
+        ));
+
+        let control = TcpListener::bind("127.0.0.1:0").await?;
+        let control_addr = control.local_addr()?;
+        let control_task = tokio::spawn(serve_control_connection(
+            control,
+            active_clients.clone(),
+            scheduler.clone(),
+        ));
+        let worker_task = tokio::spawn(run_fake_worker(control_addr));
+        while !active_clients.lock().await.contains_key(&WORKER_ID) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        if let Some(info) = active_clients.lock().await.get_mut(&WORKER_ID) {
+            info.models = Some(vec![common::Model {
+                id: "tiny-llama".to_string(),
+                object: "model".to_string(),
+                created: 0,
+                owned_by: "gpuf".to_string(),
+            }]);
+        }
+
+        let http_addr = start_gateway(scheduler).await?;
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/v1/completions", http_addr))
+            .json(&serde_json::json!({
+                "model": "tiny-llama",
+                "prompt": "say hello world",
+                "max_tokens": 16
+            }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let body: serde_json::Value = response.json().await?;
+        assert_eq!(body["choices"][0]["text"], WORKER_REPLY.concat());
+
+        worker_task.abort();
+        control_task.abort();
+        Ok(())
+    }
+
+    /// Authenticated, idle worker whose tasks go to `writer`; nothing answers
+    /// them unless the test does.
+    fn idle_worker(writer: ControlWriter) -> ClientInfo {
+        ClientInfo {
+            writer: Arc::new(Mutex::new(writer)),
+            authed: true,
+            version: common::PROTOCOL_REVISION,
+            system_info: Some(SystemInfo {
+                cpu_usage: 0,
+                memory_usage: 0,
+                disk_usage: 0,
+                device_memsize: 0,
+                total_tflops: 0,
+                last_heartbeat: std::time::SystemTime::now(),
+                memsize_gb: 0,
+            }),
+            devices_info: Vec::new(),
+            connected_at: chrono::Utc::now(),
+            models: None,
+            quant_types: None,
+            n_ctx: None,
+        }
+    }
+
+    async fn silent_worker_failed_over() -> Result<()> {
+        const SILENT_ID: ClientId = ClientId([9; 16]);
+        let active_clients: ActiveClients = Arc::new(Mutex::new(HashMap::new()));
+        let scheduler = Arc::new(InferenceScheduler::new(
+            active_clients.clone(),
+            crate::inference::circuit_breaker::BreakerConfig::default(),
+        ));
+
+        let control = TcpListener::bind("127.0.0.1:0").await?;
+        let control_addr = control.local_addr()?;
+        let control_task = tokio::spawn(serve_control_connection(
+            control,
+            active_clients.clone(),
+            scheduler.clone(),
+        ));
+        let worker_task = tokio::spawn(run_fake_worker(control_addr));
+        while !active_clients.lock().await.contains_key(&WORKER_ID) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // Idle, so the scheduler picks it over the fake worker first
+        active_clients
+            .lock()
+            .await
+            .insert(SILENT_ID, idle_worker(Box::new(tokio::io::sink())));
+
+        let http_addr = start_gateway_with(
+            scheduler,
+            vec![SILENT_ID, WORKER_ID],
+            Duration::from_millis(300),
+        )
+        .await?;
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!("http://{}/v1/completions", http_addr))
+            .json(&serde_json::json!({
+                "prompt": "say hello world",
+                "max_tokens": 16,
+                "stream": true
+            }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let body = response.text().await?;
+        let texts: Vec<String> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter_map(|event| serde_json::from_str::<serde_json::Value>(event).ok())
+            .filter_map(|event| event["choices"][0]["text"].as_str().map(str::to_string))
+            .filter(|text| !text.is_empty())
+            .collect();
+        assert_eq!(texts, WORKER_REPLY);
+
+        // With only the silent worker left to try, the request times out
+        worker_task.abort();
+        control_task.abort();
+        active_clients.lock().await.remove(&WORKER_ID);
+        let response = client
+            .post(format!("http://{}/v1/completions", http_addr))
+            .json(&serde_json::json!({"prompt": "hi", "stream": true}))
+            .send()
+            .await?;
+        assert_eq!(response.status(), reqwest::StatusCode::GATEWAY_TIMEOUT);
+        Ok(())
+    }
+
+    async fn disconnect_cancels_task() -> Result<()> {
+        let active_clients: ActiveClients = Arc::new(Mutex::new(HashMap::new()));
+        let scheduler = Arc::new(InferenceScheduler::new(
+            active_clients.clone(),
+            crate::inference::circuit_breaker::BreakerConfig::default(),
+        ));
+        let (writer, mut worker_end) = tokio::io::duplex(64 * 1024);
+        active_clients
+            .lock()
+            .await
+            .insert(WORKER_ID, idle_worker(Box::new(writer)));
+
+        let http_addr = start_gateway(scheduler.clone()).await?;
+        let request = tokio::spawn(async move {
+            let mut response = reqwest::Client::new()
+                .post(format!("http://{}/v1/completions", http_addr))
+                .json(&serde_json::json!({"prompt": "hi", "stream": true}))
+                .send()
+                .await?;
+            // Read the first token, then hang up mid-stream
+            response.chunk().await?;
+            Ok::<_, reqwest::Error>(())
+        });
+
+        let mut buf = BytesMut::new();
+        let task_id = match read_command(&mut worker_end, &mut buf).await? {
+            Command::V1(CommandV1::InferenceTask { task_id, .. }) => task_id,
+            other => return Err(anyhow!("Unexpected command {}", other.variant_name())),
+        };
+        scheduler
+            .handle_inference_result_chunk(
+                task_id.clone(),
+                0,
+                "Hel".to_string(),
+                OutputPhase::Final,
+                false,
+                None,
+                0,
+                1,
+                0,
+                1,
+            )
+            .await;
+        request.await??;
+
+        match read_command(&mut worker_end, &mut buf).await? {
+            Command::V1(CommandV1::CancelInference { task_id: cancelled }) => {
+                assert_eq!(cancelled, task_id);
+            }
+            other => return Err(anyhow!("Unexpected command {}", other.variant_name())),
+        }
+        Ok(())
+    }
+
+    /// Sends a completion, hangs up once the worker got the task (and, with
+    /// `after_first_token`, once a first token reached the gateway) and
+    /// expects the worker to be told to cancel it.
+    async fn hang_up_cancels_task(stream: bool, after_first_token: bool) -> Result<()> {
+        let active_clients: ActiveClients = Arc::new(Mutex::new(HashMap::new()));
+        let scheduler = Arc::new(InferenceScheduler::new(
+            active_clients.clone(),
+            crate::inference::circuit_breaker::BreakerConfig::default(),
+        ));
+        let (writer, mut worker_end) = tokio::io::duplex(64 * 1024);
+        active_clients
+            .lock()
+            .await
+            .insert(WORKER_ID, idle_worker(Box::new(writer)));
+
+        let http_addr = start_gateway(scheduler.clone()).await?;
+        let request = tokio::spawn(async move {
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/completions", http_addr))
+                .json(&serde_json::json!({"prompt": "hi", "stream": stream}))
+                .send()
+                .await?
+                .text()
+                .await
+        });
+
+        let mut buf = BytesMut::new();
+        let task_id = match read_command(&mut worker_end, &mut buf).await? {
+            Command::V1(CommandV1::InferenceTask { task_id, .. }) => task_id,
+            other => return Err(anyhow!("Unexpected command {}", other.variant_name())),
+        };
+        if after_first_token {
+            scheduler
+                .handle_inference_result_chunk(
+                    task_id.clone(),
+                    0,
+                    "Hel".to_string(),
+                    OutputPhase::Final,
+                    false,
+                    None,
+                    0,
+                    1,
+                    0,
+                    1,
+                )
+                .await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        request.abort();
+        let _ = request.await;
+
+        match read_command(&mut worker_end, &mut buf).await? {
+            Command::V1(CommandV1::CancelInference { task_id: cancelled }) => {
+                assert_eq!(cancelled, task_id);
+            }
+            other => return Err(anyhow!("Unexpected command {}", other.variant_name())),
+        }
+        Ok(())
+    }
+
+    async fn shutdown_drains_in_flight_request() -> Result<()> {
+        let active_clients: ActiveClients = Arc::new(Mutex::new(HashMap::new()));
+        let shutdown = CancellationToken::new();
+        let scheduler = Arc::new(
+            InferenceScheduler::new(
+                active_clients.clone(),
+                crate::inference::circuit_breaker::BreakerConfig::default(),
+            )
+            .with_shutdown(shutdown.clone()),
+        );
+        let (writer, mut worker_end) = tokio::io::duplex(64 * 1024);
+        active_clients
+            .lock()
+            .await
+            .insert(WORKER_ID, idle_worker(Box::new(writer)));
+
+        let grace = Duration::from_secs(5);
+        let gateway =
+            Arc::new(test_gateway(scheduler.clone())?.with_shutdown(shutdown.clone(), grace));
+        let app = authorized_routes(gateway.clone(), vec![WORKER_ID]);
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let http_addr = listener.local_addr()?;
+        let server = tokio::spawn(async move { gateway.serve_until_drained(listener, app).await });
+
+        let request = tokio::spawn(async move {
+            reqwest::Client::new()
+                .post(format!("http://{}/v1/completions", http_addr))
+                .json(&serde_json::json!({"prompt": "hi", "stream": true}))
+                .send()
+                .await?
+                .text()
+                .await
+        });
+        let mut buf = BytesMut::new();
+        let task_id = match read_command(&mut worker_end, &mut buf).await? {
+            Command::V1(CommandV1::InferenceTask { task_id, .. }) => task_id,
+            other => return Err(anyhow!("Unexpected command {}", other.variant_name())),
+        };
+
+        shutdown.cancel();
+        let drain_started = std::time::Instant::now();
+        let late: CompletionRequest = serde_json::from_value(serde_json::json!({"prompt": "hi"}))?;
+        let refused = scheduler
+            .execute_inference_stream(late, None)
+            .await
+            .expect_err("dispatched a task while draining");
+        assert!(refused.downcast_ref::<ShuttingDown>().is_some());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!server.is_finished(), "exited with a request in flight");
+
+        // The worker finishes the slow request after shutdown began
+        for (seq, (delta, done)) in [("Hel", false), ("lo", false), ("", true)]
+            .into_iter()
+            .enumerate()
+        {
+            scheduler
+                .handle_inference_result_chunk(
+                    task_id.clone(),
+                    seq as u32,
+                    delta.to_string(),
+                    OutputPhase::Final,
+                    done,
+                    None,
+                    1,
+                    2,
+                    0,
+                    2,
+                )
+                .await;
+        }
+        let body = request.await??;
+        let texts: Vec<String> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter_map(|event| serde_json::from_str::<serde_json::Value>(event).ok())
+            .filter_map(|event| event["choices"][0]["text"].as_str().map(str::to_string))
+            .filter(|text| !text.is_empty())
+            .collect();
+        assert_eq!(texts, ["Hel", "lo"]);
+        assert!(body.contains("data: [DONE]"), "{}", body);
+
+        tokio::time::timeout(grace, server)
+            .await
+            .expect("server kept running after draining")??;
+        assert!(drain_started.elapsed() < grace);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_in_flight_requests() {
+        tokio::time::timeout(Duration::from_secs(10), shutdown_drains_in_flight_request())
+            .await
+            .expect("drain timed out")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_cancels_the_worker_task() {
+        tokio::time::timeout(Duration::from_secs(10), disconnect_cancels_task())
+            .await
+            .expect("no cancel reached the worker")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_hang_up_before_first_token_cancels_the_worker_task() {
+        for stream in [true, false] {
+            tokio::time::timeout(Duration::from_secs(10), hang_up_cancels_task(stream, false))
+                .await
+                .expect("no cancel reached the worker")
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hang_up_on_non_stream_completion_cancels_the_worker_task() {
+        tokio::time::timeout(Duration::from_secs(10), hang_up_cancels_task(false, true))
+            .await
+            .expect("no cancel reached the worker")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_silent_worker_fails_over_before_first_token() {
+        tokio::time::timeout(Duration::from_secs(10), silent_worker_failed_over())
+            .await
+            .expect("failover timed out")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_request_for_unserved_model_gets_503() {
+        tokio::time::timeout(Duration::from_secs(10), unserved_model_rejected())
+            .await
+            .expect("request timed out")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_request_for_served_model_reaches_worker() {
+        tokio::time::timeout(Duration::from_secs(10), served_model_routed())
+            .await
+            .expect("request timed out")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_gateway_requests() {
+        tokio::time::timeout(Duration::from_secs(10), metrics_after_failed_request())
+            .await
+            .expect("metrics request timed out")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_worker_gateway_stream_round_trip() {
+        tokio::time::timeout(Duration::from_secs(10), stream_completion_round_trip())
+            .await
+            .expect("round trip timed out")
+            .unwrap();
+    }
+
+    /// Server state around `scheduler` backed by the test Postgres and Redis;
+    /// Kafka is configured but never reached since the worker sends no
+    /// heartbeats. `WORKER_ID` is registered as a valid client.
+    #[cfg(feature = "db-tests")]
+    async fn db_server_state(
+        active_clients: ActiveClients,
+        scheduler: Arc<InferenceScheduler>,
+    ) -> Result<Arc<ServerState>> {
+        use crate::db::models::{ClientModelClass, HotModelClass};
+        use crate::handle::{PendingPool, ServerConfig};
+        use crate::util::pack::BufferPool;
+        use redis::Commands;
+        use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+
+        let database_url = std::env::var("GPUF_TEST_DATABASE_URL")
+            .unwrap_or_else(|_| "postgres://postgres@localhost:5432/postgres".to_string());
+        let redis_url = std::env::var("GPUF_TEST_REDIS_URL")
+            .unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+        let db_pool = Arc::new(PgPoolOptions::new().connect(&database_url).await?);
+        let redis_client = redis::Client::open(redis_url)?;
+
+        sqlx::query("DELETE FROM gpu_assets WHERE client_id = $1")
+            .bind(WORKER_ID)
+            .execute(&*db_pool)
+            .await?;
+        sqlx::query(
+            "INSERT INTO gpu_assets (user_id, client_id, client_name, client_status) VALUES ('gateway-test', $1, 'test', 'offline')",
+        )
+        .bind(WORKER_ID)
+        .execute(&*db_pool)
+        .await?;
+        redis_client
+            .get_connection()?
+            .del::<_, ()>(format!("client_status:{}", WORKER_ID))?;
+
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", "127.0.0.1:1")
+            .set_log_level(rdkafka::config::RDKafkaLogLevel::Emerg)
+            .create()?;
+        Ok(Arc::new(ServerState {
+            active_clients,
+            pending_connections: Arc::new(Mutex::new(PendingPool::new(
+                16,
+                Duration::from_secs(30),
+            ))),
+            user_db: Default::default(),
+            token_db: Default::default(),
+            server_start_time: chrono::Utc::now(),
+            total_connections: Default::default(),
+            config: ServerConfig {
+                control_port: 0,
+                proxy_port: 0,
+                public_port: 0,
+                api_port: 0,
+                control_tls: false,
+            },
+            db_pool: db_pool.clone(),
+            redis_client: Arc::new(redis_client),
+            producer: Arc::new(producer),
+            inference_scheduler: scheduler,
+            client_model: Arc::new(ClientModelClass::new(db_pool.clone())),
+            hot_models: Arc::new(HotModelClass::new(db_pool)),
+            cert_chain: Arc::new(Vec::new()),
+            priv_key: Arc::new(PrivateKeyDer::Pkcs8(Vec::new().into())),
+            buffer_pool: Arc::new(BufferPool::new(8 * 1024, 16)),
+        }))
+    }
+
+    /// Logs the fake worker in through the server's own control handler and
+    /// checks what the login check persisted: the worker's OS type on its
+    /// `gpu_assets` row and the cached `client_status` entry in Redis. The
+    /// streamed round trip itself is covered by
+    /// `test_worker_gateway_stream_round_trip` without a database.
+    #[cfg(feature = "db-tests")]
+    async fn login_through_the_control_handler_persists() -> Result<()> {
+        use redis::Commands;
+
+        let active_clients: ActiveClients = Arc::new(Mutex::new(HashMap::new()));
+        let scheduler = Arc::new(InferenceScheduler::new(
+            active_clients.clone(),
+            crate::inference::circuit_breaker::BreakerConfig::default(),
+        ));
+        let state = db_server_state(active_clients.clone(), scheduler).await?;
+        let db_pool = state.db_pool.clone();
+        let redis_client = state.redis_client.clone();
+
+        let control = TcpListener::bind("127.0.0.1:0").await?;
+        let control_addr = control.local_addr()?;
+        let control_task = tokio::spawn(async move {
+            let (stream, addr) = control.accept().await?;
+            let (reader, writer) = stream.into_split();
+            state
+                .serve_control_stream(Box::new(reader), Box::new(writer), addr)
+                .await
+        });
+        let worker_task = tokio::spawn(run_fake_worker(control_addr));
+        loop {
+            let authed = active_clients
+                .lock()
+                .await
+                .get(&WORKER_ID)
+                .map(|client| client.authed);
+            match authed {
+                Some(true) => break,
+                Some(false) => return Err(anyhow!("Worker login was rejected")),
+                None => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+
+        let os_type: Option<String> =
+            sqlx::query_scalar("SELECT os_type FROM gpu_assets WHERE client_id = $1")
+                .bind(WORKER_ID)
+                .fetch_one(&*db_pool)
+                .await?;
+        assert_eq!(os_type.as_deref(), common::os_type_str(&OsType::ANDROID));
+        let cached: Option<String> = redis_client
+            .get_connection()?
+            .get(format!("client_status:{}", WORKER_ID))?;
+        assert_eq!(cached.as_deref(), Some("valid"));
+
+        worker_task.abort();
+        control_task.abort();
+        sqlx::query("DELETE FROM gpu_assets WHERE client_id = $1")
+            .bind(WORKER_ID)
+            .execute(&*db_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Needs Postgres (`GPUF_TEST_DATABASE_URL`) and Redis
+    /// (`GPUF_TEST_REDIS_URL`); run with `--features db-tests`.
+    #[cfg(feature = "db-tests")]
+    #[tokio::test]
+    async fn test_login_through_the_control_handler_persists() {
+        tokio::time::timeout(
+            Duration::from_secs(30),
+            login_through_the_control_handler_persists(),
+        )
+        .await
+        .expect("login timed out")
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_describe_device_asks_the_live_worker() {
+        tokio::time::timeout(Duration::from_secs(10), describe_round_trip())
+            .await
+            .expect("describe timed out")
+            .unwrap();
     }
 }
@@ -1,6 +1,6 @@
 use axum::{
     extract::{Extension, Path, State},
-    http::{HeaderMap, StatusCode},
+    http::{header, HeaderMap, StatusCode},
     response::{sse::Event, sse::Sse, IntoResponse, Response},
     Json,
 };
@@ -14,9 +14,12 @@ use tokio_stream::wrappers::ReceiverStream;
 use tracing::{debug, error, info};
 
 use crate::inference::{
-    gateway::{AuthContext, InferenceGateway},
+    circuit_breaker::BreakerSnapshot,
+    gateway::{AuthContext, InferenceGateway, WorkerTimedOut},
+    metrics::InFlightRequest,
     scheduler::{
-        ChatCompletionRequest, ChatCompletionResponse, CompletionRequest, DeviceInfo, ModelInfo,
+        ChatCompletionRequest, ChatCompletionResponse, CompletionChoice, CompletionRequest,
+        CompletionResponse, CompletionUsage, ContextExceeded, DeviceInfo, ModelInfo, ShuttingDown,
         StreamEvent,
     },
 };
@@ -87,11 +90,15 @@ fn role_to_chatml(role: &str) -> &str {
     }
 }
 
+/// Cancels the task on its worker when the response is dropped before the
+/// worker finished, as when the HTTP client disconnects.
 struct StreamCancelGuard {
     scheduler: Arc<crate::inference::InferenceScheduler>,
     task_id: String,
     device_id: ClientId,
     finished: Arc<AtomicBool>,
+    // Stays in flight for the gateway metrics until the stream is dropped
+    _request: InFlightRequest,
 }
 
 struct StopMarkerState {
@@ -159,6 +166,71 @@ impl StopMarkerState {
     }
 }
 
+/// 400 for a request whose prompt plus `max_tokens` fits no worker's context
+/// window, naming the limit, instead of a generic server error.
+fn context_exceeded_response(e: &anyhow::Error) -> Option<Response> {
+    let exceeded = e.downcast_ref::<ContextExceeded>()?;
+    let error_response = json!({
+        "error": {
+            "message": exceeded.to_string(),
+            "type": "invalid_request_error",
+            "param": "max_tokens",
+            "code": StatusCode::BAD_REQUEST.as_u16(),
+            "context_limit": exceeded.limit
+        }
+    });
+    Some((StatusCode::BAD_REQUEST, Json(error_response)).into_response())
+}
+
+/// 504 for a request whose workers all stopped responding before it
+/// produced any output.
+fn worker_timed_out_response(e: &anyhow::Error) -> Option<Response> {
+    let timed_out = e.downcast_ref::<WorkerTimedOut>()?;
+    let error_response = json!({
+        "error": {
+            "message": timed_out.to_string(),
+            "type": "api_error",
+            "code": StatusCode::GATEWAY_TIMEOUT.as_u16()
+        }
+    });
+    Some((StatusCode::GATEWAY_TIMEOUT, Json(error_response)).into_response())
+}
+
+/// 503 for a request that arrived while the server drains for shutdown; a
+/// client should retry it against another instance.
+fn shutting_down_response(e: &anyhow::Error) -> Option<Response> {
+    let shutting_down = e.downcast_ref::<ShuttingDown>()?;
+    let error_response = json!({
+        "error": {
+            "message": shutting_down.to_string(),
+            "type": "api_error",
+            "code": StatusCode::SERVICE_UNAVAILABLE.as_u16()
+        }
+    });
+    Some((StatusCode::SERVICE_UNAVAILABLE, Json(error_response)).into_response())
+}
+
+/// 503 for a request naming a model that no healthy worker it may be routed
+/// to advertises, so the client can tell it apart from a capacity problem.
+fn no_worker_for_model_response(model: &str) -> Response {
+    let error_response = json!({
+        "error": "no_worker_for_model",
+        "model": model
+    });
+    (StatusCode::SERVICE_UNAVAILABLE, Json(error_response)).into_response()
+}
+
+/// Label of a request for `model` in the per-model metrics: the model when
+/// a worker advertises it, `other` otherwise, so arbitrary client-supplied
+/// names cannot grow the label set.
+async fn model_metric_label<'a>(gateway: &InferenceGateway, model: Option<&'a str>) -> &'a str {
+    match model {
+        None => "gpuf",
+        Some(model) if gateway.scheduler.has_worker_for_model(model, None).await => model,
+        Some(_) => "other",
+    }
+}
+
 impl Drop for StreamCancelGuard {
     fn drop(&mut self) {
         if self.finished.load(Ordering::SeqCst) {
@@ -183,9 +255,12 @@ pub async fn handle_completion(
     Json(request): Json<CompletionRequest>,
 ) -> Response {
     info!(
-        "Received completion request: {} chars",
-        request.prompt.len()
+        "Received completion request: {}",
+        common::prompt_log_label(&request.prompt)
     );
+    let in_flight = gateway
+        .metrics
+        .start_request(model_metric_label(&gateway, request.model.as_deref()).await);
 
     // Extract Request-ID header
     let request_id = headers
@@ -241,6 +316,21 @@ pub async fn handle_completion(
         }
     }
 
+    let allowed_ids = target_client_id
+        .as_ref()
+        .map(std::slice::from_ref)
+        .unwrap_or(auth.client_ids.as_slice());
+
+    if let Some(model) = request.model.as_deref() {
+        if !gateway
+            .scheduler
+            .has_worker_for_model(model, Some(allowed_ids))
+            .await
+        {
+            return no_worker_for_model_response(model);
+        }
+    }
+
     if request.stream.unwrap_or(false) {
         let max_tokens_effective: u32 = request.max_tokens.unwrap_or(4090);
         let model_name = request.model.clone().unwrap_or_else(|| "gpuf".to_string());
@@ -249,14 +339,16 @@ pub async fn handle_completion(
             .unwrap()
             .as_secs();
 
-        let allowed_ids = target_client_id
-            .as_ref()
-            .map(std::slice::from_ref)
-            .unwrap_or(auth.client_ids.as_slice());
-
         let stream_res = gateway
-            .scheduler
-            .execute_inference_stream(request, Some(allowed_ids))
+            .start_stream(allowed_ids, |candidates| {
+                let scheduler = gateway.scheduler.clone();
+                let request = request.clone();
+                async move {
+                    scheduler
+                        .execute_inference_stream(request, Some(&candidates))
+                        .await
+                }
+            })
             .await;
 
         match stream_res {
@@ -281,12 +373,15 @@ pub async fn handle_completion(
                     task_id: task_id.clone(),
                     device_id,
                     finished: finished.clone(),
+                    _request: in_flight,
                 });
+                let metrics = gateway.metrics.clone();
                 let stop_state: Arc<Mutex<StopMarkerState>> =
                     Arc::new(Mutex::new(StopMarkerState::new(&[])));
                 let s = ReceiverStream::new(rx)
                     .then(move |ev| {
                         let guard = guard.clone();
+                        let metrics = metrics.clone();
                         let stop_state = stop_state.clone();
                         let task_id = task_id.clone();
                         let model_name = model_name.clone();
@@ -318,6 +413,9 @@ pub async fn handle_completion(
                                     payload.to_string()
                                 }
                                 StreamEvent::Finish(usage) => {
+                                    if let Some(usage) = &usage {
+                                        metrics.add_generated_tokens(usage.completion_tokens);
+                                    }
                                     let tail = {
                                         let mut st = stop_state.lock().await;
                                         if st.stopped {
@@ -367,6 +465,15 @@ pub async fn handle_completion(
             }
             Err(e) => {
                 error!("Completion request failed: {}", e);
+                if let Some(response) = context_exceeded_response(&e) {
+                    return response;
+                }
+                if let Some(response) = worker_timed_out_response(&e) {
+                    return response;
+                }
+                if let Some(response) = shutting_down_response(&e) {
+                    return response;
+                }
                 let error_response = json!({
                     "error": {"message": e.to_string(), "type": "api_error", "code": 500}
                 });
@@ -376,47 +483,92 @@ pub async fn handle_completion(
     }
 
     let max_tokens_effective: u32 = request.max_tokens.unwrap_or(1024);
+    let model_name = request.model.clone().unwrap_or_else(|| "gpuf".to_string());
+    let created = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let mut request = request;
+    request.max_tokens = Some(max_tokens_effective);
 
-    let allowed_ids = target_client_id
-        .as_ref()
-        .map(std::slice::from_ref)
-        .unwrap_or(auth.client_ids.as_slice());
+    let stream_res = gateway
+        .start_stream(allowed_ids, |candidates| {
+            let scheduler = gateway.scheduler.clone();
+            let request = request.clone();
+            async move {
+                scheduler
+                    .execute_inference_stream(request, Some(&candidates))
+                    .await
+            }
+        })
+        .await;
 
-    match gateway
-        .scheduler
-        .execute_inference(request, Some(allowed_ids))
-        .await
-    {
-        Ok(response) => {
-            // Send metrics to Kafka if needed
+    match stream_res {
+        Ok((task_id, device_id, mut rx)) => {
             if auth.access_level.is_metered() {
-                if let Some(chosen_client_id) = auth.client_ids.first() {
+                let gateway = gateway.clone();
+                let request_id = request_id.clone();
+                let access_level = auth.access_level;
+                tokio::spawn(async move {
                     if let Err(e) = gateway
-                        .send_request_metrics(request_id, *chosen_client_id, auth.access_level)
+                        .send_request_metrics(request_id, device_id, access_level)
                         .await
                     {
                         error!("Failed to send request metrics: {}", e);
-                        // Don't fail the request, just log the error
                     }
-                }
+                });
             }
 
-            let mut response = response;
-            let finish_reason = if response.usage.completion_tokens >= max_tokens_effective {
+            // The handler is dropped along with the client's connection
+            let finished = Arc::new(AtomicBool::new(false));
+            let _guard = StreamCancelGuard {
+                scheduler: gateway.scheduler.clone(),
+                task_id: task_id.clone(),
+                device_id,
+                finished: finished.clone(),
+                _request: in_flight,
+            };
+            let (text, usage) = match collect_stream(&mut rx, &finished).await {
+                Ok(collected) => collected,
+                Err(response) => return response,
+            };
+            gateway
+                .metrics
+                .add_generated_tokens(usage.completion_tokens);
+            let finish_reason = if usage.completion_tokens >= max_tokens_effective {
                 "length"
             } else {
                 "stop"
             };
 
-            if let Some(choice) = response.choices.get_mut(0) {
-                choice.finish_reason = finish_reason.to_string();
-            }
+            let response = CompletionResponse {
+                id: task_id,
+                object: "text_completion".to_string(),
+                created,
+                model: model_name,
+                choices: vec![CompletionChoice {
+                    text,
+                    index: 0,
+                    logprobs: None,
+                    finish_reason: finish_reason.to_string(),
+                }],
+                usage,
+            };
 
             info!("Completion request completed successfully");
             Json(response).into_response()
         }
         Err(e) => {
             error!("Completion request failed: {}", e);
+            if let Some(response) = context_exceeded_response(&e) {
+                return response;
+            }
+            if let Some(response) = worker_timed_out_response(&e) {
+                return response;
+            }
+            if let Some(response) = shutting_down_response(&e) {
+                return response;
+            }
             // Return appropriate HTTP status code with JSON error message
             let (status, error_message) = if e
                 .to_string()
@@ -446,6 +598,50 @@ pub async fn handle_completion(
     }
 }
 
+/// Gathers a started task's output for a non-streaming response: the text
+/// and the usage the worker reported. A worker error becomes the 500
+/// response to return instead.
+async fn collect_stream(
+    rx: &mut tokio::sync::mpsc::Receiver<StreamEvent>,
+    finished: &AtomicBool,
+) -> Result<(String, CompletionUsage), Response> {
+    let mut text = String::new();
+    let mut usage_final = None;
+
+    while let Some(ev) = rx.recv().await {
+        match ev {
+            StreamEvent::Delta(d, _phase) => {
+                text.push_str(&d);
+            }
+            StreamEvent::Finish(usage) => {
+                usage_final = usage;
+            }
+            StreamEvent::Error(msg) => {
+                finished.store(true, Ordering::SeqCst);
+                let error_response = json!({
+                    "error": {"message": msg, "type": "api_error", "code": 500}
+                });
+                return Err(
+                    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+                );
+            }
+            StreamEvent::Done => {
+                finished.store(true, Ordering::SeqCst);
+                break;
+            }
+        }
+    }
+
+    let usage = usage_final.unwrap_or(CompletionUsage {
+        prompt_tokens: 0,
+        completion_tokens: 0,
+        total_tokens: 0,
+        analysis_tokens: None,
+        final_tokens: None,
+    });
+    Ok((text, usage))
+}
+
 /// Handle chat completion requests
 pub async fn handle_chat_completion(
     State(gateway): State<Arc<InferenceGateway>>,
@@ -457,6 +653,9 @@ pub async fn handle_chat_completion(
         "Received chat completion request with {} messages",
         request.messages.len()
     );
+    let in_flight = gateway
+        .metrics
+        .start_request(model_metric_label(&gateway, request.model.as_deref()).await);
 
     // Extract Request-ID header
     let request_id = headers
@@ -512,6 +711,21 @@ pub async fn handle_chat_completion(
         }
     }
 
+    let allowed_ids = target_client_id
+        .as_ref()
+        .map(std::slice::from_ref)
+        .unwrap_or(auth.client_ids.as_slice());
+
+    if let Some(model) = request.model.as_deref() {
+        if !gateway
+            .scheduler
+            .has_worker_for_model(model, Some(allowed_ids))
+            .await
+        {
+            return no_worker_for_model_response(model);
+        }
+    }
+
     if request.stream.unwrap_or(false) {
         let max_tokens_effective: u32 = request.max_tokens.unwrap_or(4090);
         let model_name = request.model.clone().unwrap_or_else(|| "gpuf".to_string());
@@ -520,25 +734,29 @@ pub async fn handle_chat_completion(
             .unwrap()
             .as_secs();
 
-        let allowed_ids = target_client_id
-            .as_ref()
-            .map(std::slice::from_ref)
-            .unwrap_or(auth.client_ids.as_slice());
         debug!("Allowed client count: {}", allowed_ids.len());
         let stream_res = gateway
-            .scheduler
-            .execute_chat_inference_stream(
-                model_name.clone(),
-                request.messages.clone(),
-                request.max_tokens.unwrap_or(4090),
-                request.temperature.unwrap_or(0.7),
-                request.top_k.unwrap_or(40),
-                request.top_p.unwrap_or(0.9),
-                request.repeat_penalty.unwrap_or(1.1),
-                request.repeat_last_n.unwrap_or(64),
-                request.min_keep.unwrap_or(1),
-                Some(allowed_ids),
-            )
+            .start_stream(allowed_ids, |candidates| {
+                let scheduler = gateway.scheduler.clone();
+                let model_name = model_name.clone();
+                let messages = request.messages.clone();
+                async move {
+                    scheduler
+                        .execute_chat_inference_stream(
+                            model_name,
+                            messages,
+                            request.max_tokens,
+                            request.temperature.unwrap_or(0.7),
+                            request.top_k.unwrap_or(40),
+                            request.top_p.unwrap_or(0.9),
+                            request.repeat_penalty.unwrap_or(1.1),
+                            request.repeat_last_n.unwrap_or(64),
+                            request.min_keep.unwrap_or(1),
+                            Some(&candidates),
+                        )
+                        .await
+                }
+            })
             .await;
 
         match stream_res {
@@ -563,12 +781,15 @@ pub async fn handle_chat_completion(
                     task_id: task_id.clone(),
                     device_id,
                     finished: finished.clone(),
+                    _request: in_flight,
                 });
+                let metrics = gateway.metrics.clone();
                 let stop_state: Arc<Mutex<StopMarkerState>> =
                     Arc::new(Mutex::new(StopMarkerState::new(&[])));
                 let s = ReceiverStream::new(rx)
                     .then(move |ev| {
                         let guard = guard.clone();
+                        let metrics = metrics.clone();
                         let stop_state = stop_state.clone();
                         let task_id = task_id.clone();
                         let model_name = model_name.clone();
@@ -607,6 +828,9 @@ pub async fn handle_chat_completion(
                                     payload.to_string()
                                 }
                                 StreamEvent::Finish(usage) => {
+                                    if let Some(usage) = &usage {
+                                        metrics.add_generated_tokens(usage.completion_tokens);
+                                    }
                                     let tail = {
                                         let mut st = stop_state.lock().await;
                                         if st.stopped {
@@ -662,6 +886,15 @@ pub async fn handle_chat_completion(
             }
             Err(e) => {
                 error!("Chat completion request failed: {}", e);
+                if let Some(response) = context_exceeded_response(&e) {
+                    return response;
+                }
+                if let Some(response) = worker_timed_out_response(&e) {
+                    return response;
+                }
+                if let Some(response) = shutting_down_response(&e) {
+                    return response;
+                }
                 let error_response = json!({
                     "error": {"message": e.to_string(), "type": "api_error", "code": 500}
                 });
@@ -676,25 +909,28 @@ pub async fn handle_chat_completion(
         .unwrap()
         .as_secs();
 
-    let allowed_ids = target_client_id
-        .as_ref()
-        .map(std::slice::from_ref)
-        .unwrap_or(auth.client_ids.as_slice());
-
     let stream_res = gateway
-        .scheduler
-        .execute_chat_inference_stream(
-            model_name.clone(),
-            request.messages.clone(),
-            request.max_tokens.unwrap_or(4090),
-            request.temperature.unwrap_or(0.7),
-            request.top_k.unwrap_or(40),
-            request.top_p.unwrap_or(0.9),
-            request.repeat_penalty.unwrap_or(1.1),
-            request.repeat_last_n.unwrap_or(64),
-            request.min_keep.unwrap_or(1),
-            Some(allowed_ids),
-        )
+        .start_stream(allowed_ids, |candidates| {
+            let scheduler = gateway.scheduler.clone();
+            let model_name = model_name.clone();
+            let messages = request.messages.clone();
+            async move {
+                scheduler
+                    .execute_chat_inference_stream(
+                        model_name,
+                        messages,
+                        request.max_tokens,
+                        request.temperature.unwrap_or(0.7),
+                        request.top_k.unwrap_or(40),
+                        request.top_p.unwrap_or(0.9),
+                        request.repeat_penalty.unwrap_or(1.1),
+                        request.repeat_last_n.unwrap_or(64),
+                        request.min_keep.unwrap_or(1),
+                        Some(&candidates),
+                    )
+                    .await
+            }
+        })
         .await;
 
     match stream_res {
@@ -713,37 +949,22 @@ pub async fn handle_chat_completion(
                 });
             }
 
-            let mut text = String::new();
-            let mut usage_final = None;
-
-            while let Some(ev) = rx.recv().await {
-                match ev {
-                    StreamEvent::Delta(d, _phase) => {
-                        text.push_str(&d);
-                    }
-                    StreamEvent::Finish(usage) => {
-                        usage_final = usage;
-                    }
-                    StreamEvent::Error(msg) => {
-                        let error_response = json!({
-                            "error": {"message": msg, "type": "api_error", "code": 500}
-                        });
-                        return (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-                            .into_response();
-                    }
-                    StreamEvent::Done => {
-                        break;
-                    }
-                }
-            }
-
-            let usage = usage_final.unwrap_or(crate::inference::scheduler::CompletionUsage {
-                prompt_tokens: 0,
-                completion_tokens: 0,
-                total_tokens: 0,
-                analysis_tokens: None,
-                final_tokens: None,
-            });
+            // The handler is dropped along with the client's connection
+            let finished = Arc::new(AtomicBool::new(false));
+            let _guard = StreamCancelGuard {
+                scheduler: gateway.scheduler.clone(),
+                task_id: task_id.clone(),
+                device_id,
+                finished: finished.clone(),
+                _request: in_flight,
+            };
+            let (text, usage) = match collect_stream(&mut rx, &finished).await {
+                Ok(collected) => collected,
+                Err(response) => return response,
+            };
+            gateway
+                .metrics
+                .add_generated_tokens(usage.completion_tokens);
             let max_tokens_effective: u32 = request.max_tokens.unwrap_or(1024);
             let finish_reason = if usage.completion_tokens >= max_tokens_effective {
                 "length"
@@ -771,6 +992,15 @@ pub async fn handle_chat_completion(
         }
         Err(e) => {
             error!("Chat completion request failed: {}", e);
+            if let Some(response) = context_exceeded_response(&e) {
+                return response;
+            }
+            if let Some(response) = worker_timed_out_response(&e) {
+                return response;
+            }
+            if let Some(response) = shutting_down_response(&e) {
+                return response;
+            }
             let error_response = json!({
                 "error": {"message": e.to_string(), "type": "api_error", "code": 500}
             });
@@ -779,6 +1009,34 @@ pub async fn handle_chat_completion(
     }
 }
 
+/// Gateway metrics for Prometheus scrapes
+pub async fn metrics(State(gateway): State<Arc<InferenceGateway>>) -> Response {
+    let latencies: Vec<(String, u64)> = gateway
+        .scheduler
+        .worker_latencies()
+        .into_iter()
+        .map(|(client_id, latency_ms)| (client_id.log_label(), latency_ms))
+        .collect();
+    gateway.metrics.set_worker_latencies(&latencies);
+    let breakers: Vec<(String, BreakerSnapshot)> = gateway
+        .scheduler
+        .worker_breakers()
+        .into_iter()
+        .map(|(client_id, snapshot)| (client_id.log_label(), snapshot))
+        .collect();
+    gateway.metrics.set_worker_breakers(&breakers);
+    if let Some(server) = &gateway.server {
+        gateway.metrics.set_server_stats(&server.stats().await);
+    }
+    match gateway.metrics.encode() {
+        Ok(body) => ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response(),
+        Err(e) => {
+            error!("Failed to encode metrics: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
 /// List available models
 pub async fn list_models() -> Json<Vec<ModelInfo>> {
     let models = vec![ModelInfo {
@@ -826,6 +1084,8 @@ pub async fn get_device_status(
             "cpu_usage": device.cpu_usage,
             "memory_usage": device.memory_usage,
             "device_count": device.device_count,
+            "circuit_breaker": device.circuit_breaker,
+            "avg_latency_ms": device.avg_latency_ms,
             "last_updated": chrono::Utc::now().to_rfc3339()
         });
         Ok(Json(status))
@@ -833,3 +1093,41 @@ pub async fn get_device_status(
         Err(StatusCode::NOT_FOUND)
     }
 }
+
+/// Ask a connected device to describe itself: build version, engine, loaded
+/// model, context settings and a hardware summary.
+pub async fn describe_device(
+    State(gateway): State<Arc<InferenceGateway>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(device_id): Path<String>,
+) -> Response {
+    let devices = gateway
+        .scheduler
+        .get_available_devices(Some(auth.client_ids.as_slice()))
+        .await;
+    let client_id = match devices
+        .iter()
+        .find(|d| d.client_id == device_id)
+        .and_then(|d| ClientId::from_str(&d.client_id).ok())
+    {
+        Some(client_id) => client_id,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    match gateway.scheduler.describe_worker(&client_id).await {
+        Ok(description) => Json(description).into_response(),
+        Err(e) => {
+            let (status, error_type) = if e.downcast_ref::<tokio::time::error::Elapsed>().is_some()
+            {
+                (StatusCode::GATEWAY_TIMEOUT, "timeout_error")
+            } else {
+                (StatusCode::BAD_GATEWAY, "api_error")
+            };
+            error!("Describe device {} failed: {:#}", client_id.log_label(), e);
+            let error_response = json!({
+                "error": {"message": format!("{:#}", e), "type": error_type, "code": status.as_u16()}
+            });
+            (status, Json(error_response)).into_response()
+        }
+    }
+}
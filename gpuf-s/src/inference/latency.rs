@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::util::protoc::ClientId;
+
+// Weight of the newest sample in the rolling average, so a worker that got
+// slower moves within a few requests without one outlier dominating
+const SMOOTHING: f64 = 0.2;
+
+/// Rolling average latency of completed requests per worker, used by the
+/// scheduler to pick the snappiest of otherwise equivalent workers.
+#[derive(Default)]
+pub struct WorkerLatencies {
+    workers: Mutex<HashMap<ClientId, f64>>,
+}
+
+impl WorkerLatencies {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<ClientId, f64>> {
+        self.workers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Adds the latency of a request the worker completed successfully.
+    pub fn record(&self, client_id: &ClientId, elapsed: Duration) {
+        let sample = elapsed.as_secs_f64() * 1000.0;
+        let mut workers = self.lock();
+        workers
+            .entry(*client_id)
+            .and_modify(|average| *average += (sample - *average) * SMOOTHING)
+            .or_insert(sample);
+    }
+
+    /// Rolling average in milliseconds, `None` until the worker completed a
+    /// request.
+    pub fn average_ms(&self, client_id: &ClientId) -> Option<u64> {
+        self.lock()
+            .get(client_id)
+            .map(|average| average.round() as u64)
+    }
+
+    /// Every measured worker with its rolling average in milliseconds.
+    pub fn snapshot(&self) -> Vec<(ClientId, u64)> {
+        self.lock()
+            .iter()
+            .map(|(client_id, average)| (*client_id, average.round() as u64))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_recent_requests() {
+        let latencies = WorkerLatencies::default();
+        let id = ClientId([1; 16]);
+        assert_eq!(latencies.average_ms(&id), None);
+
+        latencies.record(&id, Duration::from_millis(100));
+        assert_eq!(latencies.average_ms(&id), Some(100));
+        latencies.record(&id, Duration::from_millis(600));
+        assert_eq!(latencies.average_ms(&id), Some(200));
+
+        // Enough fast requests pull the average back down
+        for _ in 0..30 {
+            latencies.record(&id, Duration::from_millis(50));
+        }
+        assert_eq!(latencies.average_ms(&id), Some(50));
+        assert_eq!(latencies.snapshot(), vec![(id, 50)]);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::handle::ServerStats;
+use crate::inference::circuit_breaker::{BreakerSnapshot, BreakerState};
+use anyhow::Result;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramTimer, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+// End-to-end latency buckets in seconds, from cached short answers up to long
+// generations on slow devices
+const LATENCY_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Request metrics of the inference gateway, served in Prometheus text format
+/// on `/metrics`.
+pub struct GatewayMetrics {
+    registry: Registry,
+    requests: IntCounter,
+    in_flight: IntGauge,
+    generated_tokens: IntCounter,
+    model_requests: IntCounterVec,
+    latency: Histogram,
+    worker_latency: IntGaugeVec,
+    worker_breaker_state: IntGaugeVec,
+    worker_breaker_trips: IntGaugeVec,
+    active_clients: IntGauge,
+    pending_connections: IntGauge,
+}
+
+/// An inference request being served; leaves the in-flight gauge and records
+/// the request's latency when dropped.
+#[must_use]
+pub struct InFlightRequest {
+    in_flight: IntGauge,
+    _latency: HistogramTimer,
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        self.in_flight.dec();
+    }
+}
+
+impl GatewayMetrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+        let requests = IntCounter::with_opts(Opts::new(
+            "gpuf_gateway_requests_total",
+            "Inference requests received",
+        ))?;
+        let in_flight = IntGauge::with_opts(Opts::new(
+            "gpuf_gateway_requests_in_flight",
+            "Inference requests currently being served",
+        ))?;
+        let generated_tokens = IntCounter::with_opts(Opts::new(
+            "gpuf_gateway_generated_tokens_total",
+            "Completion tokens returned to clients",
+        ))?;
+        let model_requests = IntCounterVec::new(
+            Opts::new(
+                "gpuf_gateway_model_requests_total",
+                "Inference requests received per requested model",
+            ),
+            &["model"],
+        )?;
+        let latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "gpuf_gateway_request_duration_seconds",
+                "End-to-end inference request latency, until the last streamed event",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+        )?;
+
+        let worker_latency = IntGaugeVec::new(
+            Opts::new(
+                "gpuf_worker_latency_ms",
+                "Rolling average latency of completed requests per worker",
+            ),
+            &["worker"],
+        )?;
+        let worker_breaker_state = IntGaugeVec::new(
+            Opts::new(
+                "gpuf_worker_circuit_breaker_state",
+                "Circuit breaker state per worker: 0 closed, 1 half-open, 2 open",
+            ),
+            &["worker"],
+        )?;
+        let worker_breaker_trips = IntGaugeVec::new(
+            Opts::new(
+                "gpuf_worker_circuit_breaker_trips",
+                "Times the worker's circuit breaker opened",
+            ),
+            &["worker"],
+        )?;
+
+        let active_clients = IntGauge::with_opts(Opts::new(
+            "gpuf_server_active_clients",
+            "Workers connected on the control port",
+        ))?;
+        let pending_connections = IntGauge::with_opts(Opts::new(
+            "gpuf_server_pending_connections",
+            "Public connections waiting for a worker's proxy connection",
+        ))?;
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(in_flight.clone()))?;
+        registry.register(Box::new(generated_tokens.clone()))?;
+        registry.register(Box::new(model_requests.clone()))?;
+        registry.register(Box::new(latency.clone()))?;
+        registry.register(Box::new(worker_latency.clone()))?;
+        registry.register(Box::new(worker_breaker_state.clone()))?;
+        registry.register(Box::new(worker_breaker_trips.clone()))?;
+        registry.register(Box::new(active_clients.clone()))?;
+        registry.register(Box::new(pending_connections.clone()))?;
+
+        Ok(Self {
+            registry,
+            requests,
+            in_flight,
+            generated_tokens,
+            model_requests,
+            latency,
+            worker_latency,
+            worker_breaker_state,
+            worker_breaker_trips,
+            active_clients,
+            pending_connections,
+        })
+    }
+
+    /// Counts a request under the `model` label and tracks it until the
+    /// returned guard is dropped. Callers keep the label set bounded.
+    pub fn start_request(&self, model: &str) -> InFlightRequest {
+        self.requests.inc();
+        self.model_requests.with_label_values(&[model]).inc();
+        self.in_flight.inc();
+        InFlightRequest {
+            in_flight: self.in_flight.clone(),
+            _latency: self.latency.start_timer(),
+        }
+    }
+
+    pub fn add_generated_tokens(&self, tokens: u32) {
+        self.generated_tokens.inc_by(tokens as u64);
+    }
+
+    /// Replaces the per-worker latency gauges with `latencies`, pairs of
+    /// worker log label and rolling average in milliseconds.
+    pub fn set_worker_latencies(&self, latencies: &[(String, u64)]) {
+        self.worker_latency.reset();
+        for (worker, latency_ms) in latencies {
+            self.worker_latency
+                .with_label_values(&[worker])
+                .set(*latency_ms as i64);
+        }
+    }
+
+    /// Replaces the per-worker circuit breaker gauges with `breakers`, pairs
+    /// of worker log label and breaker snapshot.
+    pub fn set_worker_breakers(&self, breakers: &[(String, BreakerSnapshot)]) {
+        self.worker_breaker_state.reset();
+        self.worker_breaker_trips.reset();
+        for (worker, snapshot) in breakers {
+            let state = match snapshot.state {
+                BreakerState::Closed => 0,
+                BreakerState::HalfOpen => 1,
+                BreakerState::Open => 2,
+            };
+            self.worker_breaker_state
+                .with_label_values(&[worker])
+                .set(state);
+            self.worker_breaker_trips
+                .with_label_values(&[worker])
+                .set(snapshot.trips as i64);
+        }
+    }
+
+    /// Sets the server connection gauges from a `ServerState::stats`
+    /// snapshot.
+    pub fn set_server_stats(&self, stats: &ServerStats) {
+        self.active_clients.set(stats.active_clients as i64);
+        self.pending_connections
+            .set(stats.pending_connections as i64);
+    }
+
+    /// All metrics in Prometheus text exposition format.
+    pub fn encode(&self) -> Result<String> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+}
@@ -1,5 +1,8 @@
+pub mod circuit_breaker;
 pub mod gateway;
 pub mod handlers;
+pub mod latency;
+pub mod metrics;
 pub mod scheduler;
 
 // Re-export main components
@@ -1,22 +1,30 @@
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc;
 use tokio::sync::{oneshot, Mutex};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 use crate::handle::ActiveClients;
+use crate::inference::circuit_breaker::{
+    BreakerConfig, BreakerSnapshot, CircuitBreakers, ProbeGuard,
+};
+use crate::inference::latency::WorkerLatencies;
 use crate::util::protoc::ClientId;
-use common::{Command, CommandV1, OutputPhase};
+use common::{Command, CommandV1, OutputPhase, QuantType, WorkerDescription};
 
 // Type aliases for easier function signatures
 // Note: Can't create type alias for enum variants in Rust
 
 // OpenAI Compatible Request/Response Types
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct CompletionRequest {
     pub prompt: String,
     pub max_tokens: Option<u32>,
@@ -115,23 +123,202 @@ pub enum StreamEvent {
     Error(String),
 }
 
+/// Workers whose last heartbeat is older than this are not picked.
+const WORKER_STALE_AFTER: Duration = Duration::from_secs(120);
+
+/// How long `describe_worker` waits for the worker's reply.
+const DESCRIBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Device an in-flight task was dispatched to and when, for circuit breaker
+// and latency accounting
+struct DispatchedTask {
+    device_id: ClientId,
+    dispatched_at: Instant,
+    // Frees the half-open probe slot if the task ends without an outcome
+    #[allow(dead_code)] // Held only to be dropped with the task
+    probe: Option<ProbeGuard>,
+}
+
+// Describe request waiting for its reply, with the worker that was asked
+type PendingDescription = (ClientId, oneshot::Sender<WorkerDescription>);
+
+// The gateway does not tokenize, so prompt sizes are estimated at four bytes
+// per token: close for English, low for multi-byte scripts, so a request is
+// only refused when it clearly cannot fit
+const BYTES_PER_TOKEN: usize = 4;
+
+// Role markers and separators a chat template adds around each message
+const CHAT_TOKENS_PER_MESSAGE: u32 = 4;
+
+/// Estimated token count of `text`.
+pub fn estimate_tokens(text: &str) -> u32 {
+    text.len().div_ceil(BYTES_PER_TOKEN) as u32
+}
+
+/// Estimated prompt tokens of a chat once the worker applied its template.
+pub fn estimate_chat_tokens(messages: &[ChatMessage]) -> u32 {
+    messages.iter().fold(0u32, |total, message| {
+        total
+            .saturating_add(estimate_tokens(&message.content))
+            .saturating_add(CHAT_TOKENS_PER_MESSAGE)
+    })
+}
+
+/// The request's prompt plus `max_tokens` do not fit the context window of
+/// any worker that could otherwise take it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContextExceeded {
+    /// Estimated prompt tokens plus the requested `max_tokens`.
+    pub needed: u32,
+    /// Largest context window among the eligible workers.
+    pub limit: u32,
+}
+
+impl fmt::Display for ContextExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Context exceeded: the prompt plus max_tokens need about {} tokens but the largest available context window is {} tokens",
+            self.needed, self.limit
+        )
+    }
+}
+
+impl std::error::Error for ContextExceeded {}
+
+/// The server is draining for shutdown and takes no new inference tasks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShuttingDown;
+
+impl fmt::Display for ShuttingDown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Server is shutting down")
+    }
+}
+
+impl std::error::Error for ShuttingDown {}
+
+// Largest context window among workers skipped because the request does not fit
+fn note_too_small(too_small: &mut Option<u32>, client_info: &crate::handle::ClientInfo) {
+    if let Some(n_ctx) = client_info.n_ctx {
+        *too_small = Some(too_small.map_or(n_ctx, |limit| limit.max(n_ctx)));
+    }
+}
+
 // Inference Scheduler
 pub struct InferenceScheduler {
     pending_tasks: Arc<Mutex<HashMap<String, PendingTask>>>,
     partial_results: Arc<Mutex<HashMap<String, String>>>,
     pending_streams: Arc<Mutex<HashMap<String, mpsc::Sender<StreamEvent>>>>,
     stream_usages: Arc<Mutex<HashMap<String, CompletionUsage>>>,
+    task_devices: Arc<Mutex<HashMap<String, DispatchedTask>>>,
+    pending_descriptions: Arc<Mutex<HashMap<String, PendingDescription>>>,
+    breakers: Arc<CircuitBreakers>,
+    latencies: Arc<WorkerLatencies>,
+    // Break ties between equally ranked workers by their rolling latency
+    latency_tie_break: bool,
     active_clients: ActiveClients,
+    // Cancelled when the server starts draining; no task is dispatched after
+    shutdown: CancellationToken,
 }
 
 impl InferenceScheduler {
-    pub fn new(active_clients: ActiveClients) -> Self {
+    pub fn new(active_clients: ActiveClients, breaker_config: BreakerConfig) -> Self {
         Self {
             pending_tasks: Arc::new(Mutex::new(HashMap::new())),
             partial_results: Arc::new(Mutex::new(HashMap::new())),
             pending_streams: Arc::new(Mutex::new(HashMap::new())),
             stream_usages: Arc::new(Mutex::new(HashMap::new())),
+            task_devices: Arc::new(Mutex::new(HashMap::new())),
+            pending_descriptions: Arc::new(Mutex::new(HashMap::new())),
+            breakers: Arc::new(CircuitBreakers::new(breaker_config)),
+            latencies: Arc::new(WorkerLatencies::default()),
+            latency_tie_break: true,
             active_clients,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    /// Stops dispatching new tasks, failing them with `ShuttingDown`, once
+    /// `token` is cancelled. Tasks already dispatched run to completion.
+    pub fn with_shutdown(mut self, token: CancellationToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+
+    fn ensure_accepting(&self) -> Result<()> {
+        if self.shutdown.is_cancelled() {
+            return Err(ShuttingDown.into());
+        }
+        Ok(())
+    }
+
+    /// Whether workers with equal load prefer the one with the lowest rolling
+    /// latency of completed requests (the default).
+    pub fn with_latency_tie_break(mut self, enabled: bool) -> Self {
+        self.latency_tie_break = enabled;
+        self
+    }
+
+    /// Tie-breaker between otherwise equal workers, lower is better. Workers
+    /// without a completed request yet go first so they get measured.
+    fn latency_rank(&self, client_id: &ClientId) -> u64 {
+        if !self.latency_tie_break {
+            return 0;
+        }
+        self.latencies.average_ms(client_id).unwrap_or(0)
+    }
+
+    /// Rolling average latency in milliseconds of every worker that completed
+    /// a request.
+    pub fn worker_latencies(&self) -> Vec<(ClientId, u64)> {
+        self.latencies.snapshot()
+    }
+
+    /// Circuit breaker state of every worker that failed a dispatch.
+    pub fn worker_breakers(&self) -> Vec<(ClientId, BreakerSnapshot)> {
+        self.breakers.snapshots()
+    }
+
+    async fn track_task_device(&self, task_id: &str, device_id: ClientId) {
+        let mut task_devices = self.task_devices.lock().await;
+        task_devices.insert(
+            task_id.to_string(),
+            DispatchedTask {
+                device_id,
+                dispatched_at: Instant::now(),
+                probe: None,
+            },
+        );
+    }
+
+    /// Keeps a half-open probe claimed for as long as the task sent as the
+    /// probe is tracked. A task that already ended drops it right away.
+    async fn hold_probe(&self, task_id: &str, probe: Option<ProbeGuard>) {
+        let Some(probe) = probe else {
+            return;
+        };
+        if let Some(task) = self.task_devices.lock().await.get_mut(task_id) {
+            task.probe = Some(probe);
+        }
+    }
+
+    /// Records the outcome of a dispatched task against its device's circuit
+    /// breaker and, on success, its latency. Tasks that are no longer tracked
+    /// (already finished or cancelled) are ignored.
+    async fn finish_task_device(&self, task_id: &str, success: bool) {
+        let dispatched = {
+            let mut task_devices = self.task_devices.lock().await;
+            task_devices.remove(task_id)
+        };
+        if let Some(task) = dispatched {
+            if success {
+                self.breakers.record_success(&task.device_id);
+                self.latencies
+                    .record(&task.device_id, task.dispatched_at.elapsed());
+            } else {
+                self.breakers.record_failure(&task.device_id);
+            }
         }
     }
 
@@ -140,95 +327,155 @@ impl InferenceScheduler {
         request: CompletionRequest,
         allowed_client_ids: Option<&[ClientId]>,
     ) -> Result<(String, ClientId, mpsc::Receiver<StreamEvent>)> {
+        self.ensure_accepting()?;
         let task_id = Uuid::new_v4().to_string();
         let (tx, rx) = mpsc::channel::<StreamEvent>(128);
 
+        // Defaulted max_tokens only cap generation, the worker stops at its context
+        let needed_tokens =
+            estimate_tokens(&request.prompt).saturating_add(request.max_tokens.unwrap_or(0));
+        let selected = match request.model.as_deref() {
+            Some(model) => match self
+                .pick_worker(model, allowed_client_ids, needed_tokens)
+                .await
+            {
+                Ok(device_id) => Ok((device_id, self.breakers.begin_dispatch(&device_id))),
+                Err(e) if e.is::<ContextExceeded>() => Err(e),
+                Err(e) => {
+                    warn!(
+                        "No model-compatible device found for model '{}': {}. Falling back to generic device selection.",
+                        model, e
+                    );
+                    self.select_best_device(allowed_client_ids, needed_tokens)
+                        .await
+                }
+            },
+            None => {
+                self.select_best_device(allowed_client_ids, needed_tokens)
+                    .await
+            }
+        };
+        let (device_id, probe) = selected?;
         {
             let mut streams = self.pending_streams.lock().await;
             streams.insert(task_id.clone(), tx);
         }
 
-        let device_id = self.select_best_device(allowed_client_ids).await?;
         if let Err(e) = self
-            .send_task_to_device(
-                &device_id,
-                task_id.clone(),
-                request.prompt,
-                request.max_tokens.unwrap_or(4090),
-                request.temperature.unwrap_or(0.7),
-                request.top_k.unwrap_or(40),
-                request.top_p.unwrap_or(0.9),
-                request.repeat_penalty.unwrap_or(1.1),
-                request.repeat_last_n.unwrap_or(64),
-                request.min_keep.unwrap_or(1),
-            )
+            .send_task_to_device(&device_id, task_id.clone(), request)
             .await
         {
             let mut streams = self.pending_streams.lock().await;
             streams.remove(&task_id);
             return Err(e);
         }
+        self.hold_probe(&task_id, probe).await;
 
         Ok((task_id, device_id, rx))
     }
 
-    async fn select_best_device_for_model(
+    /// Picks the worker to run `model` on: among authenticated workers in
+    /// `allowed_client_ids` (when given) that advertise the model, heartbeated
+    /// within `WORKER_STALE_AFTER`, have a closed circuit and a context window
+    /// fitting `needed_tokens`, the least loaded one. Workers that reported
+    /// support for the model's quantization win over the rest, which stay
+    /// eligible so a request is never refused for it; equal load prefers more
+    /// TFLOPS and then the lowest rolling latency. Fails with
+    /// `ContextExceeded` when only too-small workers have the model.
+    pub async fn pick_worker(
         &self,
-        model_name: &str,
+        model: &str,
         allowed_client_ids: Option<&[ClientId]>,
+        needed_tokens: u32,
     ) -> Result<ClientId> {
         let clients = self.active_clients.lock().await;
-
-        let mut best_device: Option<(ClientId, u16)> = None;
+        let quant = QuantType::detect(model);
+        let mut too_small = None;
 
         debug!("online Clients: {}", clients.len());
-        for (client_id, client_info) in clients.iter() {
-            if let Some(allowed) = allowed_client_ids {
-                if !allowed.iter().any(|id| id == client_id) {
-                    debug!("Client {} is not allowed", client_id.log_label());
-                    continue;
+        let best = clients
+            .iter()
+            .filter(|(client_id, _)| {
+                allowed_client_ids.is_none_or(|allowed| allowed.contains(client_id))
+            })
+            .filter(|(client_id, info)| info.authed && self.breakers.is_available(client_id))
+            .filter(|(_, info)| {
+                info.models
+                    .as_ref()
+                    .is_some_and(|models| models.iter().any(|m| m.id == model))
+            })
+            .filter_map(|(client_id, info)| {
+                let system_info = info.system_info.as_ref()?;
+                let age = system_info
+                    .last_heartbeat
+                    .elapsed()
+                    .unwrap_or(Duration::ZERO);
+                if age > WORKER_STALE_AFTER {
+                    debug!(
+                        "Client {} skipped: last heartbeat {}s ago",
+                        client_id.log_label(),
+                        age.as_secs()
+                    );
+                    return None;
                 }
-            }
-            debug!(
-                "Client {} is authed {} model {}",
-                client_id.log_label(),
-                client_info.authed,
-                model_name
-            );
-            if !client_info.authed {
-                continue;
-            }
-            let Some(models) = &client_info.models else {
-                continue;
-            };
-            if !models.iter().any(|m| m.id == model_name) {
-                continue;
-            }
-
-            let Some(system_info) = &client_info.system_info else {
-                continue;
-            };
-            let total_load: u16 = (system_info.cpu_usage + system_info.memory_usage) as u16;
-
-            match best_device {
-                None => best_device = Some((*client_id, total_load)),
-                Some((_best_id, best_load)) if total_load < best_load => {
-                    best_device = Some((*client_id, total_load))
+                if !info.fits_context(needed_tokens) {
+                    debug!(
+                        "Client {} skipped: context window too small",
+                        client_id.log_label()
+                    );
+                    note_too_small(&mut too_small, info);
+                    return None;
                 }
-                _ => {}
+                let unsupported = quant.is_some_and(|quant| !info.supports_quant(quant));
+                let load = system_info.cpu_usage as u16 + system_info.memory_usage as u16;
+                // The id only breaks full ties, so the pick doesn't depend on map order
+                let rank = (
+                    unsupported,
+                    load,
+                    Reverse(system_info.total_tflops),
+                    self.latency_rank(client_id),
+                    client_id.0,
+                );
+                Some((rank, *client_id))
+            })
+            .min_by_key(|(rank, _)| *rank)
+            .map(|(_, client_id)| client_id);
+
+        match (best, too_small) {
+            (Some(client_id), _) => Ok(client_id),
+            (None, Some(limit)) => Err(ContextExceeded {
+                needed: needed_tokens,
+                limit,
             }
+            .into()),
+            (None, None) => Err(anyhow!("No compatible client found for model '{model}'")),
         }
+    }
 
-        best_device
-            .map(|(id, _)| id)
-            .ok_or_else(|| anyhow!("No compatible client found for model '{model_name}'"))
+    /// Whether any authenticated worker with a closed circuit, among
+    /// `allowed_client_ids` when given, advertises `model`.
+    pub async fn has_worker_for_model(
+        &self,
+        model: &str,
+        allowed_client_ids: Option<&[ClientId]>,
+    ) -> bool {
+        let clients = self.active_clients.lock().await;
+        clients.iter().any(|(client_id, info)| {
+            allowed_client_ids.is_none_or(|allowed| allowed.contains(client_id))
+                && info.authed
+                && self.breakers.is_available(client_id)
+                && info
+                    .models
+                    .as_ref()
+                    .is_some_and(|models| models.iter().any(|m| m.id == model))
+        })
     }
 
     pub async fn execute_chat_inference_stream(
         &self,
         model: String,
         messages: Vec<ChatMessage>,
-        max_tokens: u32,
+        max_tokens: Option<u32>,
         temperature: f32,
         top_k: u32,
         top_p: f32,
@@ -237,27 +484,32 @@ impl InferenceScheduler {
         min_keep: u32,
         allowed_client_ids: Option<&[ClientId]>,
     ) -> Result<(String, ClientId, mpsc::Receiver<StreamEvent>)> {
+        self.ensure_accepting()?;
         let task_id = Uuid::new_v4().to_string();
         let (tx, rx) = mpsc::channel::<StreamEvent>(128);
 
-        {
-            let mut streams = self.pending_streams.lock().await;
-            streams.insert(task_id.clone(), tx);
-        }
-
-        let device_id = match self
-            .select_best_device_for_model(&model, allowed_client_ids)
+        let needed_tokens = estimate_chat_tokens(&messages).saturating_add(max_tokens.unwrap_or(0));
+        let max_tokens = max_tokens.unwrap_or(4090);
+        let (device_id, probe) = match self
+            .pick_worker(&model, allowed_client_ids, needed_tokens)
             .await
         {
-            Ok(d) => d,
+            Ok(device_id) => (device_id, self.breakers.begin_dispatch(&device_id)),
+            // Workers serving the model exist but none fits; others cannot take it
+            Err(e) if e.is::<ContextExceeded>() => return Err(e),
             Err(e) => {
                 warn!(
                     "No model-compatible device found for model '{}': {}. Falling back to generic device selection.",
                     model, e
                 );
-                self.select_best_device(allowed_client_ids).await?
+                self.select_best_device(allowed_client_ids, needed_tokens)
+                    .await?
             }
         };
+        {
+            let mut streams = self.pending_streams.lock().await;
+            streams.insert(task_id.clone(), tx);
+        }
         debug!(
             "Selected device {} for model {}",
             device_id.log_label(),
@@ -291,6 +543,7 @@ impl InferenceScheduler {
             streams.remove(&task_id);
             return Err(e);
         }
+        self.hold_probe(&task_id, probe).await;
 
         Ok((task_id, device_id, rx))
     }
@@ -305,6 +558,14 @@ impl InferenceScheduler {
             let mut streams = self.pending_streams.lock().await;
             streams.remove(task_id);
         }
+        self.stream_usages.lock().await.remove(task_id);
+        self.pending_tasks.lock().await.remove(task_id);
+        self.partial_results.lock().await.remove(task_id);
+        {
+            // A cancelled task says nothing about the device's health
+            let mut task_devices = self.task_devices.lock().await;
+            task_devices.remove(task_id);
+        }
 
         use common::write_command;
 
@@ -328,6 +589,135 @@ impl InferenceScheduler {
         Ok(())
     }
 
+    /// Gives up on a task whose worker stopped responding: counts it as a
+    /// failure against the worker's circuit breaker, then cancels it.
+    pub async fn fail_task(&self, task_id: &str, device_id: &ClientId) {
+        self.finish_task_device(task_id, false).await;
+        if let Err(e) = self.cancel_inference(task_id, device_id).await {
+            debug!(
+                "Could not cancel task {} on device {}: {}",
+                task_id,
+                device_id.log_label(),
+                e
+            );
+        }
+    }
+
+    /// Cancels every task still running on a worker, as when the shutdown
+    /// grace period ran out. Returns how many were cancelled.
+    pub async fn cancel_in_flight(&self) -> usize {
+        let in_flight: Vec<(String, ClientId)> = {
+            let task_devices = self.task_devices.lock().await;
+            task_devices
+                .iter()
+                .map(|(task_id, task)| (task_id.clone(), task.device_id))
+                .collect()
+        };
+        for (task_id, device_id) in &in_flight {
+            if let Err(e) = self.cancel_inference(task_id, device_id).await {
+                debug!(
+                    "Could not cancel task {} on device {}: {}",
+                    task_id,
+                    device_id.log_label(),
+                    e
+                );
+            }
+            self.pending_tasks.lock().await.remove(task_id);
+            self.partial_results.lock().await.remove(task_id);
+        }
+        in_flight.len()
+    }
+
+    /// Closes the result channels of every task dispatched to a worker whose
+    /// control connection went away, so their requests stop waiting on it.
+    pub async fn worker_disconnected(&self, device_id: &ClientId) {
+        let orphaned: Vec<String> = {
+            let task_devices = self.task_devices.lock().await;
+            task_devices
+                .iter()
+                .filter(|(_, task)| task.device_id == *device_id)
+                .map(|(task_id, _)| task_id.clone())
+                .collect()
+        };
+        for task_id in orphaned {
+            warn!("Task {} lost its worker {}", task_id, device_id.log_label());
+            self.finish_task_device(&task_id, false).await;
+            self.pending_streams.lock().await.remove(&task_id);
+            self.stream_usages.lock().await.remove(&task_id);
+            self.pending_tasks.lock().await.remove(&task_id);
+            self.partial_results.lock().await.remove(&task_id);
+        }
+    }
+
+    /// Asks a connected worker to describe itself and waits for the reply.
+    /// Fails with a `tokio::time::error::Elapsed` source if the worker does
+    /// not answer within `DESCRIBE_TIMEOUT`.
+    pub async fn describe_worker(&self, device_id: &ClientId) -> Result<WorkerDescription> {
+        let request_id = Uuid::new_v4().to_string();
+        let (sender, receiver) = oneshot::channel();
+        self.pending_descriptions
+            .lock()
+            .await
+            .insert(request_id.clone(), (*device_id, sender));
+
+        if let Err(e) = self.send_describe_request(device_id, &request_id).await {
+            self.pending_descriptions.lock().await.remove(&request_id);
+            return Err(e);
+        }
+        let reply = tokio::time::timeout(DESCRIBE_TIMEOUT, receiver).await;
+        self.pending_descriptions.lock().await.remove(&request_id);
+
+        match reply {
+            Ok(Ok(description)) => Ok(description),
+            Ok(Err(_)) => Err(anyhow!("Worker description channel closed")),
+            Err(elapsed) => Err(anyhow::Error::new(elapsed).context(format!(
+                "Worker did not describe itself within {}s",
+                DESCRIBE_TIMEOUT.as_secs()
+            ))),
+        }
+    }
+
+    async fn send_describe_request(&self, device_id: &ClientId, request_id: &str) -> Result<()> {
+        let clients = self.active_clients.lock().await;
+        let client_info = clients
+            .get(device_id)
+            .ok_or_else(|| anyhow!("Device not found or not connected"))?;
+
+        if !client_info.authed {
+            return Err(anyhow!("Device not authenticated"));
+        }
+
+        let command = Command::V1(CommandV1::DescribeWorker {
+            request_id: request_id.to_string(),
+        });
+        let mut writer = client_info.writer.lock().await;
+        write_dispatch(&mut *writer, client_info.version, &command).await
+    }
+
+    /// Completes the `describe_worker` call waiting for `request_id`. Replies
+    /// from any other worker than the one asked are dropped.
+    pub async fn handle_worker_description(
+        &self,
+        from: &ClientId,
+        request_id: String,
+        description: WorkerDescription,
+    ) {
+        let mut pending = self.pending_descriptions.lock().await;
+        match pending.get(&request_id) {
+            Some((device_id, _)) if device_id == from => {
+                if let Some((_, sender)) = pending.remove(&request_id) {
+                    let _ = sender.send(description);
+                }
+            }
+            Some(_) => warn!(
+                "Ignoring worker description {} from device {} that was not asked",
+                request_id,
+                from.log_label()
+            ),
+            None => debug!("No pending describe request {}", request_id),
+        }
+    }
+
     async fn send_chat_task_to_device(
         &self,
         device_id: &ClientId,
@@ -342,8 +732,6 @@ impl InferenceScheduler {
         repeat_last_n: i32,
         min_keep: u32,
     ) -> Result<()> {
-        use common::write_command;
-
         let mut clients = self.active_clients.lock().await;
         let client_info = clients
             .get_mut(device_id)
@@ -383,8 +771,12 @@ impl InferenceScheduler {
             },
             max_tokens
         );
-        write_command(&mut *writer, &command).await?;
-        writer.flush().await?;
+        self.track_task_device(&task_id, *device_id).await;
+        if let Err(e) = write_dispatch(&mut *writer, client_info.version, &command).await {
+            self.task_devices.lock().await.remove(&task_id);
+            self.breakers.record_failure(device_id);
+            return Err(e);
+        }
         Ok(())
     }
 
@@ -401,6 +793,10 @@ impl InferenceScheduler {
         analysis_tokens: u32,
         final_tokens: u32,
     ) {
+        if error.is_some() || done {
+            self.finish_task_device(&task_id, error.is_none()).await;
+        }
+
         let stream_sender = {
             let streams = self.pending_streams.lock().await;
             streams.get(&task_id).cloned()
@@ -494,6 +890,7 @@ impl InferenceScheduler {
             "Handling inference result for task {} (success: {})",
             task_id, success
         );
+        self.finish_task_device(&task_id, success).await;
 
         let mut tasks = self.pending_tasks.lock().await;
         let pending_count_before = tasks.len();
@@ -557,11 +954,13 @@ impl InferenceScheduler {
     async fn select_best_device(
         &self,
         allowed_client_ids: Option<&[ClientId]>,
-    ) -> Result<ClientId> {
+        needed_tokens: u32,
+    ) -> Result<(ClientId, Option<ProbeGuard>)> {
         let clients = self.active_clients.lock().await;
 
-        let mut best_device: Option<(ClientId, u16)> = None;
+        let mut best_device: Option<(ClientId, (u16, u64))> = None;
         let mut device_count = 0;
+        let mut too_small = None;
 
         let mut consider_device =
             |client_id: &ClientId, client_info: &crate::handle::ClientInfo| {
@@ -570,17 +969,30 @@ impl InferenceScheduler {
                     return;
                 }
 
+                // Skip devices whose circuit breaker is open
+                if !self.breakers.is_available(client_id) {
+                    return;
+                }
+
                 // Check if device has system info (Android devices should have this)
                 let Some(system_info) = &client_info.system_info else {
                     return;
                 };
 
-                // Simple load balancing: choose device with lowest CPU + Memory usage
+                // Leave workers whose context cannot hold the request to others
+                if !client_info.fits_context(needed_tokens) {
+                    note_too_small(&mut too_small, client_info);
+                    return;
+                }
+
+                // Simple load balancing: choose device with lowest CPU + Memory usage,
+                // the snappiest one on equal load
                 let total_load: u16 = (system_info.cpu_usage + system_info.memory_usage) as u16;
+                let rank = (total_load, self.latency_rank(client_id));
                 device_count += 1;
 
-                if best_device.is_none() || total_load < best_device.as_ref().unwrap().1 {
-                    best_device = Some((*client_id, total_load));
+                if best_device.is_none() || rank < best_device.as_ref().unwrap().1 {
+                    best_device = Some((*client_id, rank));
                 }
             };
 
@@ -601,35 +1013,35 @@ impl InferenceScheduler {
             }
         }
 
-        if let Some((client_id, _load)) = best_device {
+        if let Some((client_id, (_load, _))) = best_device {
+            let probe = self.breakers.begin_dispatch(&client_id);
             info!(
                 "Selected device {} for inference (load: {}%, available devices: {})",
                 client_id.log_label(),
                 _load,
                 device_count
             );
-            Ok(client_id)
+            Ok((client_id, probe))
+        } else if let Some(limit) = too_small {
+            Err(ContextExceeded {
+                needed: needed_tokens,
+                limit,
+            }
+            .into())
         } else {
             Err(anyhow!("No available Android devices found"))
         }
     }
 
-    /// Send inference task to device
+    /// Send inference task to device. Requests naming a model go out as
+    /// `ModelInferenceTask` so the worker can load it; workers too old for that
+    /// get a plain `InferenceTask`.
     async fn send_task_to_device(
         &self,
         device_id: &ClientId,
         task_id: String,
-        prompt: String,
-        max_tokens: u32,
-        temperature: f32,
-        top_k: u32,
-        top_p: f32,
-        repeat_penalty: f32,
-        repeat_last_n: i32,
-        min_keep: u32,
+        request: CompletionRequest,
     ) -> Result<()> {
-        use common::write_command;
-
         // Find active client connection
         let mut clients = self.active_clients.lock().await;
         let client_info = clients
@@ -649,31 +1061,37 @@ impl InferenceScheduler {
             .map_err(|_| anyhow!("Device is busy, please try again"))?;
 
         // Create and send inference task command
-        let inference_task = CommandV1::InferenceTask {
-            task_id: task_id.clone(),
-            prompt,
-            max_tokens,
-            temperature,
-            top_k,
-            top_p,
-            repeat_penalty,
-            repeat_last_n,
-            min_keep,
-        };
-
-        let command = Command::V1(inference_task);
+        let max_tokens = request.max_tokens.unwrap_or(4090);
         info!(
-            "sent inference task {} to device {} (prompt_bytes={}, max_tokens={})",
+            "sent inference task {} to device {} (prompt={}, max_tokens={})",
             task_id,
             device_id.log_label(),
-            match &command {
-                Command::V1(CommandV1::InferenceTask { prompt, .. }) => prompt.len(),
-                _ => 0,
-            },
+            common::prompt_log_label(&request.prompt),
             max_tokens
         );
-        write_command(&mut *writer, &command).await?;
-        writer.flush().await?;
+        let model = request.model.unwrap_or_default();
+        let has_model = !model.is_empty();
+        let mut command = Command::V1(CommandV1::ModelInferenceTask {
+            task_id: task_id.clone(),
+            model,
+            prompt: request.prompt,
+            max_tokens,
+            temperature: request.temperature.unwrap_or(0.7),
+            top_k: request.top_k.unwrap_or(40),
+            top_p: request.top_p.unwrap_or(0.9),
+            repeat_penalty: request.repeat_penalty.unwrap_or(1.1),
+            repeat_last_n: request.repeat_last_n.unwrap_or(64),
+            min_keep: request.min_keep.unwrap_or(1),
+        });
+        if !has_model || command.min_revision() > client_info.version {
+            command = without_model(command);
+        }
+        self.track_task_device(&task_id, *device_id).await;
+        if let Err(e) = write_dispatch(&mut *writer, client_info.version, &command).await {
+            self.task_devices.lock().await.remove(&task_id);
+            self.breakers.record_failure(device_id);
+            return Err(e);
+        }
 
         info!(
             "Successfully sent inference task {} to device {}",
@@ -683,118 +1101,6 @@ impl InferenceScheduler {
         Ok(())
     }
 
-    /// Execute inference task
-    pub async fn execute_inference(
-        &self,
-        request: CompletionRequest,
-        allowed_client_ids: Option<&[ClientId]>,
-    ) -> Result<CompletionResponse> {
-        let task_id = Uuid::new_v4().to_string();
-
-        // Create response channel
-        let (sender, receiver) = oneshot::channel();
-        {
-            let mut tasks = self.pending_tasks.lock().await;
-            let existing_task_count = tasks.len();
-            debug!(
-                "Existing pending task count before insert: {}",
-                existing_task_count
-            );
-            tasks.insert(task_id.clone(), sender);
-            info!(
-                "Stored task {} in pending_tasks (total: {})",
-                task_id,
-                tasks.len()
-            );
-            debug!("All pending task count after insert: {}", tasks.len());
-        }
-
-        // Select best available device
-        let device_id = self.select_best_device(allowed_client_ids).await?;
-
-        // Send task to device
-        info!(
-            "About to send task {} to device {}",
-            task_id,
-            device_id.log_label()
-        );
-        if let Err(e) = self
-            .send_task_to_device(
-                &device_id,
-                task_id.clone(),
-                request.prompt,
-                request.max_tokens.unwrap_or(1024),
-                request.temperature.unwrap_or(0.7),
-                request.top_k.unwrap_or(40),
-                request.top_p.unwrap_or(0.9),
-                request.repeat_penalty.unwrap_or(1.1),
-                request.repeat_last_n.unwrap_or(64),
-                request.min_keep.unwrap_or(1),
-            )
-            .await
-        {
-            // Clean up pending task on failure
-            let mut tasks = self.pending_tasks.lock().await;
-            tasks.remove(&task_id);
-            error!(
-                "Failed to send inference task to device {}: {}",
-                device_id.log_label(),
-                e
-            );
-            return Err(e);
-        }
-
-        info!(
-            "Task {} sent successfully, now waiting for result...",
-            task_id
-        );
-
-        // Check if task is still in pending_tasks before waiting
-        {
-            let tasks = self.pending_tasks.lock().await;
-            info!("Pending tasks count before timeout wait: {}", tasks.len());
-            if !tasks.contains_key(&task_id) {
-                error!("Task {} missing from pending_tasks before wait!", task_id);
-                return Err(anyhow!(
-                    "Task {} was removed from pending_tasks unexpectedly",
-                    task_id
-                ));
-            }
-        }
-
-        // Wait for result with timeout
-        let timeout_secs: u64 = std::env::var("GPUF_INFERENCE_TIMEOUT_SECS")
-            .ok()
-            .and_then(|s| s.parse::<u64>().ok())
-            .filter(|&v| v > 0)
-            .unwrap_or(300);
-
-        info!(
-            "Waiting for result of task {} with {}s timeout...",
-            task_id, timeout_secs
-        );
-        match tokio::time::timeout(std::time::Duration::from_secs(timeout_secs), receiver).await {
-            Ok(Ok(response)) => {
-                info!("Task {} completed successfully", task_id);
-                response
-            }
-            Ok(Err(_)) => {
-                warn!("Task {} response channel closed", task_id);
-                Err(anyhow!("Task response channel closed"))
-            }
-            Err(_) => {
-                // Clean up pending task on timeout
-                let mut tasks = self.pending_tasks.lock().await;
-                tasks.remove(&task_id);
-                warn!("Task {} timed out after {} seconds", task_id, timeout_secs);
-                Err(anyhow!(
-                    "Inference task timed out after {} seconds",
-                    timeout_secs
-                ))
-            }
-        }
-    }
-
     /// Get list of available devices
     pub async fn get_available_devices(
         &self,
@@ -826,6 +1132,8 @@ impl InferenceScheduler {
                         .map(|s| s.memory_usage)
                         .unwrap_or(0),
                     device_count: client_info.devices_info.len() as u32,
+                    circuit_breaker: self.breakers.snapshot(client_id),
+                    avg_latency_ms: self.latencies.average_ms(client_id),
                 };
                 devices.push(device);
             };
@@ -856,4 +1164,426 @@ pub struct DeviceInfo {
     pub cpu_usage: u8,
     pub memory_usage: u8,
     pub device_count: u32,
+    pub circuit_breaker: BreakerSnapshot,
+    /// Rolling average latency of completed requests, `None` until one completed.
+    pub avg_latency_ms: Option<u64>,
+}
+
+/// Sends a task, unless the worker's protocol revision predates the command.
+/// `ModelInferenceTask` as the plain `InferenceTask` older workers know.
+fn without_model(command: Command) -> Command {
+    match command {
+        Command::V1(CommandV1::ModelInferenceTask {
+            task_id,
+            model: _,
+            prompt,
+            max_tokens,
+            temperature,
+            top_k,
+            top_p,
+            repeat_penalty,
+            repeat_last_n,
+            min_keep,
+        }) => Command::V1(CommandV1::InferenceTask {
+            task_id,
+            prompt,
+            max_tokens,
+            temperature,
+            top_k,
+            top_p,
+            repeat_penalty,
+            repeat_last_n,
+            min_keep,
+        }),
+        other => other,
+    }
+}
+
+async fn write_dispatch<W: tokio::io::AsyncWrite + Unpin>(
+    writer: &mut W,
+    peer_revision: u32,
+    command: &Command,
+) -> Result<()> {
+    if command.min_revision() > peer_revision {
+        return Err(anyhow!(
+            "Worker protocol revision {} does not support {}; upgrade the worker",
+            peer_revision,
+            command.variant_name()
+        ));
+    }
+    common::write_command(writer, command).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handle::{ClientInfo, SystemInfo};
+    use crate::inference::circuit_breaker::BreakerConfig;
+    use common::Model;
+
+    fn model(id: &str) -> Model {
+        Model {
+            id: id.to_string(),
+            object: "model".to_string(),
+            created: 0,
+            owned_by: "gpuf".to_string(),
+        }
+    }
+
+    fn worker(load: u8, quant_types: Option<Vec<QuantType>>) -> ClientInfo {
+        ClientInfo {
+            writer: Arc::new(Mutex::new(Box::new(tokio::io::sink()))),
+            authed: true,
+            version: 1,
+            system_info: Some(SystemInfo {
+                cpu_usage: load,
+                memory_usage: 0,
+                disk_usage: 0,
+                device_memsize: 0,
+                total_tflops: 0,
+                memsize_gb: 0,
+                last_heartbeat: std::time::SystemTime::now(),
+            }),
+            devices_info: Vec::new(),
+            connected_at: chrono::Utc::now(),
+            models: Some(vec![model("llama-3.2-1b-Q8_0"), model("llama-3.2-1b")]),
+            quant_types,
+            n_ctx: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn prefers_workers_supporting_the_model_quantization() {
+        let busy_any = ClientId([1; 16]);
+        let idle_q4 = ClientId([2; 16]);
+        let clients = HashMap::from([
+            (busy_any, worker(80, None)),
+            (idle_q4, worker(10, Some(vec![QuantType::Q4_0]))),
+        ]);
+        let scheduler =
+            InferenceScheduler::new(Arc::new(Mutex::new(clients)), BreakerConfig::default());
+
+        let picked = scheduler
+            .pick_worker("llama-3.2-1b-Q8_0", None, 0)
+            .await
+            .unwrap();
+        assert_eq!(picked, busy_any);
+
+        let picked = scheduler
+            .pick_worker("llama-3.2-1b", None, 0)
+            .await
+            .unwrap();
+        assert_eq!(picked, idle_q4);
+
+        // Capability is a preference, not a filter.
+        let picked = scheduler
+            .pick_worker("llama-3.2-1b-Q8_0", Some(&[idle_q4]), 0)
+            .await
+            .unwrap();
+        assert_eq!(picked, idle_q4);
+    }
+
+    fn scheduler_with(clients: HashMap<ClientId, ClientInfo>) -> InferenceScheduler {
+        InferenceScheduler::new(Arc::new(Mutex::new(clients)), BreakerConfig::default())
+    }
+
+    fn rated_worker(load: u8, total_tflops: u32, heartbeat_age: Duration) -> ClientInfo {
+        let mut info = worker(load, None);
+        if let Some(system_info) = info.system_info.as_mut() {
+            system_info.total_tflops = total_tflops;
+            system_info.last_heartbeat = std::time::SystemTime::now() - heartbeat_age;
+        }
+        info
+    }
+
+    #[tokio::test]
+    async fn pick_worker_prefers_low_load_then_high_tflops() {
+        let busy_fast = ClientId([1; 16]);
+        let idle_slow = ClientId([2; 16]);
+        let idle_fast = ClientId([3; 16]);
+        let scheduler = scheduler_with(HashMap::from([
+            (busy_fast, rated_worker(70, 100, Duration::ZERO)),
+            (idle_slow, rated_worker(20, 10, Duration::ZERO)),
+            (idle_fast, rated_worker(20, 40, Duration::ZERO)),
+        ]));
+        assert_eq!(
+            scheduler.pick_worker("llama-3.2-1b", None, 0).await.ok(),
+            Some(idle_fast)
+        );
+
+        // Full ties resolve the same way every time
+        let first = ClientId([4; 16]);
+        let second = ClientId([5; 16]);
+        let scheduler = scheduler_with(HashMap::from([
+            (second, rated_worker(20, 40, Duration::ZERO)),
+            (first, rated_worker(20, 40, Duration::ZERO)),
+        ]));
+        assert_eq!(
+            scheduler.pick_worker("llama-3.2-1b", None, 0).await.ok(),
+            Some(first)
+        );
+    }
+
+    #[tokio::test]
+    async fn equal_workers_are_tie_broken_by_latency() {
+        let slow = ClientId([1; 16]);
+        let snappy = ClientId([2; 16]);
+        let clients = || {
+            HashMap::from([
+                (slow, rated_worker(20, 40, Duration::ZERO)),
+                (snappy, rated_worker(20, 40, Duration::ZERO)),
+            ])
+        };
+        let scheduler = scheduler_with(clients());
+        scheduler
+            .latencies
+            .record(&slow, Duration::from_millis(900));
+        scheduler
+            .latencies
+            .record(&snappy, Duration::from_millis(150));
+
+        assert_eq!(
+            scheduler.pick_worker("llama-3.2-1b", None, 0).await.ok(),
+            Some(snappy)
+        );
+        assert_eq!(
+            scheduler.select_best_device(None, 0).await.unwrap().0,
+            snappy
+        );
+        let devices = scheduler.get_available_devices(Some(&[snappy])).await;
+        assert_eq!(devices[0].avg_latency_ms, Some(150));
+
+        // Load still comes first
+        let mut busy = clients();
+        busy.get_mut(&snappy)
+            .unwrap()
+            .system_info
+            .as_mut()
+            .unwrap()
+            .cpu_usage = 90;
+        let scheduler = scheduler_with(busy);
+        scheduler
+            .latencies
+            .record(&slow, Duration::from_millis(900));
+        scheduler
+            .latencies
+            .record(&snappy, Duration::from_millis(150));
+        assert_eq!(
+            scheduler.pick_worker("llama-3.2-1b", None, 0).await.ok(),
+            Some(slow)
+        );
+
+        // Disabled, ties fall back to the client id
+        let scheduler = scheduler_with(clients()).with_latency_tie_break(false);
+        scheduler
+            .latencies
+            .record(&slow, Duration::from_millis(900));
+        scheduler
+            .latencies
+            .record(&snappy, Duration::from_millis(150));
+        assert_eq!(
+            scheduler.pick_worker("llama-3.2-1b", None, 0).await.ok(),
+            Some(slow)
+        );
+    }
+
+    #[test]
+    fn estimates_prompt_tokens_from_text() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("Hello"), 2);
+        assert_eq!(estimate_tokens(&"word ".repeat(100)), 125);
+        let messages = vec![
+            ChatMessage {
+                role: "system".to_string(),
+                content: "Be brief.".to_string(),
+            },
+            ChatMessage {
+                role: "user".to_string(),
+                content: "Hello".to_string(),
+            },
+        ];
+        assert_eq!(
+            estimate_chat_tokens(&messages),
+            3 + 2 + 2 * CHAT_TOKENS_PER_MESSAGE
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_go_to_workers_whose_context_fits() {
+        let idle_small = ClientId([1; 16]);
+        let busy_large = ClientId([2; 16]);
+        let mut small = worker(10, None);
+        small.n_ctx = Some(2048);
+        let mut large = worker(80, None);
+        large.n_ctx = Some(8192);
+        let scheduler = scheduler_with(HashMap::from([(idle_small, small), (busy_large, large)]));
+
+        assert_eq!(
+            scheduler.select_best_device(None, 1000).await.unwrap().0,
+            idle_small
+        );
+        assert_eq!(
+            scheduler.select_best_device(None, 4000).await.unwrap().0,
+            busy_large
+        );
+        assert_eq!(
+            scheduler
+                .pick_worker("llama-3.2-1b", None, 4000)
+                .await
+                .unwrap(),
+            busy_large
+        );
+
+        let err = scheduler.select_best_device(None, 9000).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ContextExceeded>(),
+            Some(&ContextExceeded {
+                needed: 9000,
+                limit: 8192
+            })
+        );
+        assert!(err.to_string().contains("8192"), "{}", err);
+        let err = scheduler
+            .pick_worker("llama-3.2-1b", Some(&[idle_small]), 4000)
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<ContextExceeded>().unwrap().limit, 2048);
+
+        // A chat for the model is refused rather than sent to a worker without it
+        let mut other_model = worker(0, None);
+        other_model.models = Some(vec![model("qwen-7b")]);
+        other_model.n_ctx = Some(32_768);
+        let scheduler = scheduler_with(HashMap::from([
+            (idle_small, {
+                let mut small = worker(10, None);
+                small.n_ctx = Some(2048);
+                small
+            }),
+            (busy_large, other_model),
+        ]));
+        let messages = vec![ChatMessage {
+            role: "user".to_string(),
+            content: "Hello".to_string(),
+        }];
+        let err = scheduler
+            .execute_chat_inference_stream(
+                "llama-3.2-1b".to_string(),
+                messages,
+                Some(4000),
+                0.7,
+                40,
+                0.9,
+                1.1,
+                64,
+                1,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<ContextExceeded>().unwrap().limit, 2048);
+
+        // Workers that never reported a context window are not refused
+        let unknown = ClientId([3; 16]);
+        let scheduler = scheduler_with(HashMap::from([(unknown, worker(50, None))]));
+        assert_eq!(
+            scheduler.select_best_device(None, 100_000).await.unwrap().0,
+            unknown
+        );
+    }
+
+    #[tokio::test]
+    async fn pick_worker_skips_stale_workers_and_missing_models() {
+        let stale = ClientId([1; 16]);
+        let fresh = ClientId([2; 16]);
+        let scheduler = scheduler_with(HashMap::from([
+            (stale, rated_worker(0, 100, Duration::from_secs(121))),
+            (fresh, rated_worker(90, 1, Duration::from_secs(30))),
+        ]));
+        assert_eq!(
+            scheduler.pick_worker("llama-3.2-1b", None, 0).await.ok(),
+            Some(fresh)
+        );
+        assert_eq!(scheduler.pick_worker("qwen-7b", None, 0).await.ok(), None);
+
+        let scheduler = scheduler_with(HashMap::from([(
+            stale,
+            rated_worker(0, 100, Duration::from_secs(600)),
+        )]));
+        assert_eq!(
+            scheduler.pick_worker("llama-3.2-1b", None, 0).await.ok(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_probe_lets_the_next_request_probe() {
+        let device = ClientId([1; 16]);
+        let scheduler = InferenceScheduler::new(
+            Arc::new(Mutex::new(HashMap::from([(device, worker(10, None))]))),
+            BreakerConfig {
+                failure_threshold: 1,
+                window: Duration::from_secs(60),
+                cooldown: Duration::ZERO,
+            },
+        );
+        scheduler.breakers.record_failure(&device);
+
+        let request = CompletionRequest {
+            prompt: "hi".to_string(),
+            max_tokens: Some(8),
+            temperature: None,
+            top_k: None,
+            top_p: None,
+            repeat_penalty: None,
+            repeat_last_n: None,
+            min_keep: None,
+            model: None,
+            stream: Some(true),
+        };
+        let (task_id, picked, _rx) = scheduler
+            .execute_inference_stream(request, None)
+            .await
+            .unwrap();
+        assert_eq!(picked, device);
+        assert!(!scheduler.breakers.is_available(&device));
+
+        scheduler.cancel_inference(&task_id, &device).await.unwrap();
+        assert!(scheduler.breakers.is_available(&device));
+    }
+
+    #[tokio::test]
+    async fn completions_name_their_model_to_workers_that_know_the_command() {
+        for (version, expects_model) in [(common::PROTOCOL_REVISION, true), (4, false)] {
+            let device = ClientId([1; 16]);
+            let (writer, mut reader) = tokio::io::duplex(64 * 1024);
+            let mut info = worker(10, None);
+            info.version = version;
+            info.writer = Arc::new(Mutex::new(Box::new(writer)));
+            let scheduler = scheduler_with(HashMap::from([(device, info)]));
+
+            let request: CompletionRequest = serde_json::from_value(
+                serde_json::json!({"prompt": "hi", "model": "llama-3.2-1b"}),
+            )
+            .unwrap();
+            scheduler
+                .execute_inference_stream(request, None)
+                .await
+                .unwrap();
+
+            let mut buf = bytes::BytesMut::new();
+            match common::read_command(&mut reader, &mut buf).await.unwrap() {
+                Command::V1(CommandV1::ModelInferenceTask { model, .. }) if expects_model => {
+                    assert_eq!(model, "llama-3.2-1b")
+                }
+                Command::V1(CommandV1::InferenceTask { .. }) if !expects_model => {}
+                other => panic!(
+                    "Unexpected {} for revision {}",
+                    other.variant_name(),
+                    version
+                ),
+            }
+        }
+    }
 }
@@ -13,6 +13,7 @@ use std::sync::Arc;
 use tokio::net::TcpListener;
 #[cfg(target_os = "linux")]
 use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 
 #[cfg(debug_assertions)]
@@ -28,6 +29,7 @@ async fn main() -> Result<()> {
     //parse args
     let args = util::cmd::Args::parse();
     util::init_logging();
+    common::set_log_prompts(args.log_prompts);
 
     //bind port
     let control_listener = TcpListener::bind(format!("0.0.0.0:{}", args.control_port)).await?;
@@ -47,24 +49,31 @@ async fn main() -> Result<()> {
             "SECURITY: gpuf-s control listener is plaintext TCP; use --control-tls before exposing it outside a trusted network"
         );
     }
-    // Create a channel to signal when to drop ServerState
-    let (shutdown_tx, mut shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    // Cancelled on SIGTERM/SIGINT: stops new inference and starts the drain
+    let shutdown = CancellationToken::new();
+    let shutdown_grace = std::time::Duration::from_secs(args.shutdown_grace_secs);
 
-    // Spawn a task to handle signals
-    let server_state = Arc::new(handle::new_server_state(&args).await?);
+    let server_state = Arc::new(handle::new_server_state(&args, shutdown.clone()).await?);
     let server_state1 = Arc::clone(&server_state);
     let server_state2 = Arc::clone(&server_state);
     let server_state3 = Arc::clone(&server_state);
-    let _server_state4 = Arc::clone(&server_state);
+    let server_state4 = Arc::clone(&server_state);
 
     // Start inference gateway.
     let inference_gateway_port = args.inference_gateway_port;
-    let inference_gateway = Arc::new(inference::InferenceGateway::new(
-        server_state.inference_scheduler.clone(),
-        server_state.db_pool.clone(),
-        server_state.producer.clone(),
-    ));
-    let inference_gateway_task = tokio::spawn(async move {
+    let inference_gateway = Arc::new(
+        inference::InferenceGateway::new(
+            server_state.inference_scheduler.clone(),
+            server_state.db_pool.clone(),
+            server_state.producer.clone(),
+        )?
+        .with_request_timeout(std::time::Duration::from_secs(
+            args.inference_request_timeout_secs,
+        ))
+        .with_shutdown(shutdown.clone(), shutdown_grace)
+        .with_server_state(server_state4),
+    );
+    let mut inference_gateway_task = tokio::spawn(async move {
         info!(
             "Starting Inference Gateway on port {}...",
             inference_gateway_port
@@ -78,6 +87,8 @@ async fn main() -> Result<()> {
         inference_gateway_port
     );
 
+    // Spawn a task to handle signals
+    let signal_shutdown = shutdown.clone();
     tokio::spawn(async move {
         #[cfg(target_os = "linux")]
         {
@@ -104,8 +115,7 @@ async fn main() -> Result<()> {
             }
         }
 
-        // Send shutdown signal
-        let _ = shutdown_tx.send(());
+        signal_shutdown.cancel();
     });
     //init server state
     let server_loop = async {
@@ -113,11 +123,11 @@ async fn main() -> Result<()> {
             res = server_state1.handle_client_connections(control_listener) => res,
             res = server_state2.handle_proxy_connections(proxy_listener) => res,
             res = server_state3.handle_public_connections(public_listener) => res,
-            _res = inference_gateway_task => {
+            _res = &mut inference_gateway_task => {
                 info!("Inference gateway task completed");
                 Ok(())
             }
-            _ = &mut shutdown_rx => {
+            _ = shutdown.cancelled() => {
                 info!("Shutdown signal received, stopping server...");
                 Ok(())
             }
@@ -126,6 +136,14 @@ async fn main() -> Result<()> {
 
     let result = server_loop.await;
 
+    // The gateway returns once its in-flight requests finished or the grace
+    // period ran out
+    if shutdown.is_cancelled() && !inference_gateway_task.is_finished() {
+        if let Err(e) = inference_gateway_task.await {
+            error!("Inference gateway task failed while draining: {}", e);
+        }
+    }
+
     info!("Dropping ServerState...");
     drop(server_state);
 
@@ -51,6 +51,46 @@ pub struct Args {
 
     #[arg(long, default_value = "localhost:9092")]
     pub bootstrap_server: String,
+
+    /// Consecutive dispatch failures before a worker's circuit breaker opens
+    #[arg(long, default_value_t = 5)]
+    pub breaker_failure_threshold: u32,
+
+    /// Window in seconds over which consecutive dispatch failures are counted
+    #[arg(long, default_value_t = 60)]
+    pub breaker_window_secs: u64,
+
+    /// Seconds an open circuit breaker keeps a worker out of scheduling before a probe
+    #[arg(long, default_value_t = 30)]
+    pub breaker_cooldown_secs: u64,
+
+    /// Seconds an inference request waits on its worker for the next chunk before failing over or giving up
+    #[arg(long, default_value_t = 120, value_parser = clap::value_parser!(u64).range(1..))]
+    pub inference_request_timeout_secs: u64,
+
+    /// Seconds in-flight inference requests get to finish after a shutdown signal before they are cut off
+    #[arg(long, default_value_t = 30)]
+    pub shutdown_grace_secs: u64,
+
+    /// Pick among equally loaded workers by client id instead of by lowest rolling request latency
+    #[arg(long, default_value_t = false)]
+    pub no_latency_tie_break: bool,
+
+    /// Seconds without a heartbeat before a client is dropped from the active list (0 = never)
+    #[arg(long, default_value_t = 360)]
+    pub client_timeout_secs: u64,
+
+    /// Seconds a public connection may wait for its worker's proxy connection before it is closed
+    #[arg(long, default_value_t = 30)]
+    pub pending_conn_ttl_secs: u64,
+
+    /// Public connections allowed to wait for a proxy connection at once; further requests get 503
+    #[arg(long, default_value_t = 1024)]
+    pub max_pending_connections: usize,
+
+    /// Log full prompt text instead of only its length and hash (privacy sensitive)
+    #[arg(long, default_value_t = false)]
+    pub log_prompts: bool,
 }
 
 #[cfg(test)]
@@ -65,4 +105,12 @@ mod tests {
         let args = Args::try_parse_from(["gpuf-s"]).unwrap();
         assert!(!args.control_tls);
     }
+
+    #[test]
+    fn rejects_zero_inference_request_timeout() {
+        assert!(Args::try_parse_from(["gpuf-s", "--inference-request-timeout-secs", "0"]).is_err());
+        let args =
+            Args::try_parse_from(["gpuf-s", "--inference-request-timeout-secs", "1"]).unwrap();
+        assert_eq!(args.inference_request_timeout_secs, 1);
+    }
 }
@@ -12,8 +12,7 @@ pub struct ClientId(pub [u8; 16]);
 
 impl ClientId {
     pub fn log_label(&self) -> String {
-        let encoded = hex::encode(self.0);
-        format!("{}...{}", &encoded[..6], &encoded[encoded.len() - 4..])
+        common::client_id_log_label(&self.0)
     }
 }
 